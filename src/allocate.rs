@@ -0,0 +1,132 @@
+//! Reserving the full size of a file before it is written.
+//!
+//! With `--file-allocation`, a download whose size is known gets its full
+//! length up front:
+//!
+//! * `falloc` reserves the disk blocks with `posix_fallocate`, which keeps the
+//!   file contiguous and fails at once when the disk is too small. Where that
+//!   is not supported, it falls back to `trunc`.
+//! * `trunc` only sets the length, leaving a sparse file.
+//! * `none` lets the file grow as data arrives.
+//!
+//! A preallocated file that does not complete is truncated to the bytes
+//! actually written, so that it can be resumed like any partial file.
+
+use crate::download::Control;
+use std::fs::File;
+use std::io;
+
+/// Names accepted by `--file-allocation`.
+pub const ALLOCATIONS: [&str; 3] = ["falloc", "trunc", "none"];
+
+/// How a file's space is reserved before it is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Allocation {
+    Falloc,
+    Trunc,
+    #[default]
+    None,
+}
+
+impl Allocation {
+    /// Parses one of the [`ALLOCATIONS`].
+    pub fn parse(name: &str) -> Result<Allocation, String> {
+        match name {
+            "falloc" => Ok(Allocation::Falloc),
+            "trunc" => Ok(Allocation::Trunc),
+            "none" => Ok(Allocation::None),
+            other => Err(format!(
+                "unknown file allocation '{}': expected one of {}",
+                other,
+                ALLOCATIONS.join(", ")
+            )),
+        }
+    }
+
+    /// Gives `file` a length of `len` bytes as this method does.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the space cannot be reserved, such as when the disk is full.
+    pub fn apply(self, file: &File, len: u64) -> io::Result<()> {
+        match self {
+            Allocation::Falloc => fallocate(file, len),
+            Allocation::Trunc => file.set_len(len),
+            Allocation::None => Ok(()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn fallocate(file: &File, len: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let len = libc::off_t::try_from(len)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file too large"))?;
+    // SAFETY: the descriptor belongs to `file`, which outlives the call.
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len) } {
+        0 => Ok(()),
+        libc::EOPNOTSUPP | libc::EINVAL => file.set_len(len as u64),
+        err => Err(io::Error::from_raw_os_error(err)),
+    }
+}
+
+#[cfg(not(unix))]
+fn fallocate(file: &File, len: u64) -> io::Result<()> {
+    file.set_len(len)
+}
+
+/// Truncates a preallocated file to the bytes written so far when dropped,
+/// unless the transfer was marked [`complete`](Reservation::complete).
+pub struct Reservation<'a> {
+    file: File,
+    control: &'a Control,
+    complete: bool,
+}
+
+impl<'a> Reservation<'a> {
+    /// Guards `file`, whose written length is [`Control::downloaded`].
+    pub fn new(file: File, control: &'a Control) -> Reservation<'a> {
+        Reservation {
+            file,
+            control,
+            complete: false,
+        }
+    }
+
+    /// Keeps the file as it is.
+    pub fn complete(mut self) {
+        self.complete = true;
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if !self.complete {
+            let _ = self.file.set_len(self.control.downloaded());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_allocate_and_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.iso");
+        for allocation in [Allocation::Falloc, Allocation::Trunc] {
+            let file = File::create(&path).unwrap();
+            allocation.apply(&file, 1 << 20).unwrap();
+            assert_eq!(fs::metadata(&path).unwrap().len(), 1 << 20);
+
+            let control = Control::default();
+            control.start(1000, Some(1 << 20));
+            drop(Reservation::new(file, &control));
+            assert_eq!(fs::metadata(&path).unwrap().len(), 1000);
+        }
+        assert!(Allocation::parse("prealloc").is_err());
+    }
+}
@@ -0,0 +1,722 @@
+//! Credentials sent with download requests.
+//!
+//! A bearer token can be given with `--bearer TOKEN`, read from a file with
+//! `--token-file PATH`, or taken from the `RUSTWGET_TOKEN` environment
+//! variable, in that order of precedence. The file and the variable keep the
+//! token out of process listings and shell history.
+//!
+//! Credentials for individual hosts can instead be kept in the system keyring
+//! (Secret Service, macOS Keychain, or Windows Credential Manager):
+//!
+//! ```text
+//! rustwget auth add files.example.com --user alice    # prompts for the password
+//! rustwget auth add api.example.com --token           # prompts for a bearer token
+//! rustwget auth remove files.example.com
+//! ```
+//!
+//! They are looked up by the host (and explicit port) of each request unless
+//! `--no-keyring` is given; a token given on the command line takes precedence.
+//! Tokens of OAuth providers configured in the configuration file are obtained
+//! with `rustwget auth login PROVIDER` and stored the same way (see [`oauth`]).
+//!
+//! User names and passwords are used with HTTP Basic authentication unless
+//! `--auth-type ntlm` or `--auth-type negotiate` selects Windows-integrated
+//! authentication (see [`ntlm`]), which also works against proxies given with
+//! `--proxy` for `http://` URLs. `https://` URLs are tunnelled through the
+//! proxy with `CONNECT`, which only supports Basic proxy credentials. Files on
+//! SMB shares are read with NTLMv2 whatever the type (see [`smb`](crate::smb)).
+//!
+//! `--aws-sigv4` signs requests with AWS Signature Version 4 instead (see
+//! [`sigv4`]).
+//!
+//! Services that answer with a `Bearer` challenge naming a token realm, such
+//! as container registries, are sent a token obtained from it, once per realm
+//! and scope for the whole run (see [`realm`](crate::realm)).
+
+use crate::config;
+use crate::ntlm;
+use crate::oauth::{self, Provider, Token};
+use crate::sigv4;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{
+    HeaderName, HeaderValue, AUTHORIZATION, PROXY_AUTHENTICATE, PROXY_AUTHORIZATION,
+    WWW_AUTHENTICATE,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal};
+use std::path::Path;
+use std::sync::Mutex;
+use url::Url;
+
+/// Environment variable holding a bearer token.
+pub const TOKEN_ENV: &str = "RUSTWGET_TOKEN";
+
+/// Service name under which credentials are stored in the system keyring.
+const KEYRING_SERVICE: &str = "rustwget";
+
+/// Credentials attached to requests.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Credentials {
+    /// `Authorization: Bearer <token>`.
+    Bearer(String),
+    /// HTTP Basic authentication.
+    Basic { user: String, password: String },
+    /// NTLMv2 challenge-response authentication; `user` may be `DOMAIN\\user`.
+    Ntlm { user: String, password: String },
+    /// NTLMv2 tokens sent under the `Negotiate` (SPNEGO) scheme.
+    Negotiate { user: String, password: String },
+    /// AWS Signature Version 4, computed afresh for every request.
+    AwsSigV4(sigv4::Signer),
+}
+
+/// Names accepted by `--auth-type`.
+pub const AUTH_TYPES: [&str; 3] = ["basic", "ntlm", "negotiate"];
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Credentials::Bearer(_) => f.write_str("Bearer(<redacted>)"),
+            Credentials::Basic { user, .. } => write!(f, "Basic({}, <redacted>)", user),
+            Credentials::Ntlm { user, .. } => write!(f, "Ntlm({}, <redacted>)", user),
+            Credentials::Negotiate { user, .. } => write!(f, "Negotiate({}, <redacted>)", user),
+            Credentials::AwsSigV4(signer) => write!(f, "AwsSigV4({:?})", signer),
+        }
+    }
+}
+
+impl Credentials {
+    /// Creates password credentials for one of the [`AUTH_TYPES`].
+    pub fn password(
+        auth_type: &str,
+        user: String,
+        password: String,
+    ) -> Result<Credentials, String> {
+        match auth_type {
+            "basic" => Ok(Credentials::Basic { user, password }),
+            "ntlm" => Ok(Credentials::Ntlm { user, password }),
+            "negotiate" => Ok(Credentials::Negotiate { user, password }),
+            other => Err(format!(
+                "unknown authentication type '{}': expected basic, ntlm, or negotiate",
+                other
+            )),
+        }
+    }
+
+    /// Adds the `Authorization` header to `request`.
+    ///
+    /// The header is marked sensitive so that it is never printed in debug
+    /// output. Challenge-response credentials are left to [`send`].
+    pub fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+        match self {
+            Credentials::Bearer(token) => match sensitive(format!("Bearer {}", token)) {
+                Some(value) => request.header(AUTHORIZATION, value),
+                None => request,
+            },
+            Credentials::Basic { user, password } => request.basic_auth(user, Some(password)),
+            Credentials::Ntlm { .. } | Credentials::Negotiate { .. } => request,
+            Credentials::AwsSigV4(signer) => {
+                let Some(built) = request.try_clone().and_then(|request| request.build().ok())
+                else {
+                    return request;
+                };
+                match signer.sign(
+                    built.method().as_str(),
+                    built.url(),
+                    built.headers(),
+                    chrono::Utc::now(),
+                ) {
+                    Ok(headers) => headers.into_iter().fold(request, |request, (name, value)| {
+                        request.header(name, value)
+                    }),
+                    // Sent unsigned, the request fails with the server's own error.
+                    Err(_) => request,
+                }
+            }
+        }
+    }
+
+    /// Returns the scheme, user, and password of challenge-response credentials.
+    fn handshake(&self) -> Option<(&'static str, &str, &str)> {
+        match self {
+            Credentials::Ntlm { user, password } => Some(("NTLM", user, password)),
+            Credentials::Negotiate { user, password } => Some(("Negotiate", user, password)),
+            _ => None,
+        }
+    }
+}
+
+/// One side (server or proxy) of a challenge-response handshake.
+struct Handshake<'a> {
+    scheme: &'static str,
+    user: &'a str,
+    password: &'a str,
+    header: HeaderName,
+    challenge_header: HeaderName,
+    token: String,
+    answered: bool,
+}
+
+impl<'a> Handshake<'a> {
+    fn start(
+        credentials: Option<&'a Credentials>,
+        header: HeaderName,
+        challenge_header: HeaderName,
+    ) -> Option<Handshake<'a>> {
+        let (scheme, user, password) = credentials?.handshake()?;
+        Some(Handshake {
+            scheme,
+            user,
+            password,
+            header,
+            challenge_header,
+            token: format!("{} {}", scheme, ntlm::negotiate()),
+            answered: false,
+        })
+    }
+
+    /// Computes the answer to the challenge in `response`, if it holds one.
+    fn answer(&mut self, response: &Response) -> bool {
+        if self.answered {
+            return false;
+        }
+        let challenge = response
+            .headers()
+            .get_all(&self.challenge_header)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| value.strip_prefix(self.scheme))
+            .find_map(|token| ntlm::parse_challenge(token).ok());
+        let Some(challenge) = challenge else {
+            return false;
+        };
+        self.token = format!(
+            "{} {}",
+            self.scheme,
+            ntlm::authenticate(self.user, self.password, &challenge)
+        );
+        self.answered = true;
+        true
+    }
+}
+
+/// Sends the request made by `build`, completing NTLM handshakes with the
+/// server and the proxy when their credentials call for one.
+///
+/// `build` is called again for every step of a handshake. The body of each
+/// challenge response is read in full so that the connection, which NTLM
+/// authenticates, is reused for the next step.
+///
+/// # Errors
+///
+/// Returns an error if a request fails.
+pub fn send(
+    build: impl Fn() -> RequestBuilder,
+    server: Option<&Credentials>,
+    proxy: Option<&Credentials>,
+) -> reqwest::Result<Response> {
+    let mut server_handshake = Handshake::start(server, AUTHORIZATION, WWW_AUTHENTICATE);
+    let mut proxy_handshake = Handshake::start(proxy, PROXY_AUTHORIZATION, PROXY_AUTHENTICATE);
+    loop {
+        let mut request = build();
+        if let Some(server) = server {
+            request = server.apply(request);
+        }
+        for handshake in [&server_handshake, &proxy_handshake].into_iter().flatten() {
+            if let Some(value) = sensitive(handshake.token.clone()) {
+                request = request.header(handshake.header.clone(), value);
+            }
+        }
+        let response = request.send()?;
+        let handshake = match response.status() {
+            StatusCode::UNAUTHORIZED => server_handshake.as_mut(),
+            StatusCode::PROXY_AUTHENTICATION_REQUIRED => proxy_handshake.as_mut(),
+            _ => None,
+        };
+        if !handshake.is_some_and(|handshake| handshake.answer(&response)) {
+            return Ok(response);
+        }
+        response.bytes()?;
+    }
+}
+
+/// Builds a header value that is hidden from debug output.
+fn sensitive(value: String) -> Option<HeaderValue> {
+    let mut value = HeaderValue::from_str(&value).ok()?;
+    value.set_sensitive(true);
+    Some(value)
+}
+
+/// Resolves password credentials given on the command line.
+///
+/// # Arguments
+///
+/// * `auth_type`: One of the [`AUTH_TYPES`].
+/// * `user`: The user name.
+/// * `password`: The password given as an option, if any.
+/// * `password_env`: Environment variable consulted when no password was given;
+///   without either, the password is prompted for.
+///
+/// # Errors
+///
+/// Returns an error if the type is unknown or no password can be read.
+pub fn user_credentials(
+    auth_type: &str,
+    user: &str,
+    password: Option<&str>,
+    password_env: &str,
+) -> Result<Credentials, Box<dyn std::error::Error>> {
+    let password = match password {
+        Some(password) => password.to_string(),
+        None => match std::env::var(password_env) {
+            Ok(password) => password,
+            Err(_) => read_secret(&format!("Password for {}: ", user))?,
+        },
+    };
+    Ok(Credentials::password(
+        auth_type,
+        user.to_string(),
+        password,
+    )?)
+}
+
+/// Returns the key under which credentials for `url` are stored: its host,
+/// followed by the port when one is given explicitly.
+pub fn host_key(url: &Url) -> Option<String> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    Some(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host,
+    })
+}
+
+/// Per-host credentials and OAuth tokens from the system keyring, cached for the run.
+#[derive(Debug, Default)]
+pub struct Keyring {
+    cache: Mutex<HashMap<String, Option<Credentials>>>,
+    providers: Vec<Provider>,
+    tokens: Mutex<HashMap<String, Option<Token>>>,
+    /// Whether refreshed tokens are written back to the keyring.
+    persist: bool,
+}
+
+impl Keyring {
+    /// Creates a keyring that also sends the tokens of `providers` to their hosts.
+    pub fn new(providers: Vec<Provider>) -> Keyring {
+        Keyring {
+            providers,
+            persist: true,
+            ..Keyring::default()
+        }
+    }
+
+    /// Returns the stored credentials for the host of `url`, if any.
+    ///
+    /// Credentials stored for the host take precedence over the token of an
+    /// OAuth provider serving it, which is refreshed once it has expired. The
+    /// keyring is queried once per host and provider; an unavailable keyring
+    /// counts as having no credentials.
+    pub fn lookup(&self, url: &str) -> Option<Credentials> {
+        let url = Url::parse(url).ok()?;
+        let key = host_key(&url)?;
+        let stored = {
+            let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+            cache
+                .entry(key)
+                .or_insert_with_key(|key| load(key).ok().flatten())
+                .clone()
+        };
+        stored.or_else(|| self.token(url.host_str()?))
+    }
+
+    /// Returns the token of the provider serving `host`, refreshing it if needed.
+    fn token(&self, host: &str) -> Option<Credentials> {
+        let provider = self
+            .providers
+            .iter()
+            .find(|provider| provider.matches(host))?;
+        let mut tokens = self.tokens.lock().unwrap_or_else(|e| e.into_inner());
+        let token = tokens
+            .entry(provider.name.clone())
+            .or_insert_with(|| load_token(&provider.name).ok().flatten());
+        if token.as_ref().is_some_and(Token::is_expired) {
+            let refreshed = token.as_ref().and_then(|token| {
+                match oauth::refresh(&Client::new(), provider, token) {
+                    Ok(token) => Some(token),
+                    Err(err) => {
+                        eprintln!("{}: {}", provider.name, err);
+                        None
+                    }
+                }
+            });
+            if let Some(refreshed) = refreshed.as_ref().filter(|_| self.persist) {
+                // The refreshed token still works for this run if it cannot be saved.
+                let _ = store_token(&provider.name, refreshed);
+            }
+            *token = refreshed;
+        }
+        token
+            .as_ref()
+            .map(|token| Credentials::Bearer(token.access_token.clone()))
+    }
+}
+
+/// Reads the credentials stored for `host`.
+fn load(host: &str) -> Result<Option<Credentials>, Box<dyn std::error::Error>> {
+    match keyring::Entry::new(KEYRING_SERVICE, host)?.get_password() {
+        Ok(secret) => Ok(Some(serde_json::from_str(&secret)?)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Returns the keyring account holding the token of an OAuth provider.
+fn token_account(provider: &str) -> String {
+    format!("oauth:{}", provider)
+}
+
+/// Reads the stored token of an OAuth provider.
+fn load_token(provider: &str) -> Result<Option<Token>, Box<dyn std::error::Error>> {
+    match keyring::Entry::new(KEYRING_SERVICE, &token_account(provider))?.get_password() {
+        Ok(secret) => Ok(Some(serde_json::from_str(&secret)?)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Stores the token of an OAuth provider.
+fn store_token(provider: &str, token: &Token) -> Result<(), Box<dyn std::error::Error>> {
+    let secret = serde_json::to_string(token)?;
+    keyring::Entry::new(KEYRING_SERVICE, &token_account(provider))
+        .and_then(|entry| entry.set_password(&secret))
+        .map_err(unavailable)?;
+    Ok(())
+}
+
+/// Builds the `auth` subcommand definition.
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    let host = Arg::with_name("HOST")
+        .help("Host name, with the port if it is not the default one")
+        .required(true)
+        .index(1);
+    let provider = Arg::with_name("PROVIDER")
+        .help("Name of an [[oauth]] provider in the configuration file")
+        .required(true)
+        .index(1);
+    SubCommand::with_name("auth")
+        .about("Manage credentials and OAuth tokens stored in the system keyring")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("add")
+                .about(
+                    "Store credentials for a host; the secret is read from the terminal or stdin",
+                )
+                .arg(host.clone())
+                .arg(
+                    Arg::with_name("user")
+                        .long("user")
+                        .value_name("USER")
+                        .help("User name for HTTP Basic authentication")
+                        .required_unless("token")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("auth-type")
+                        .long("auth-type")
+                        .value_name("TYPE")
+                        .help("How the user name and password are sent")
+                        .possible_values(&AUTH_TYPES)
+                        .default_value("basic")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("token")
+                        .long("token")
+                        .help("Store a bearer token instead of a user name and password")
+                        .conflicts_with("user"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("remove")
+                .about("Delete the stored credentials of a host")
+                .arg(host),
+        )
+        .subcommand(
+            SubCommand::with_name("login")
+                .about("Obtain a token from an OAuth provider of the configuration file")
+                .arg(provider.clone())
+                .arg(
+                    Arg::with_name("config")
+                        .long("config")
+                        .value_name("FILE")
+                        .help("Read the providers from FILE instead of the default configuration file")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("logout")
+                .about("Delete the stored token of an OAuth provider")
+                .arg(provider),
+        )
+}
+
+/// Runs the `auth` subcommand.
+///
+/// # Errors
+///
+/// Returns an error if the secret cannot be read or the keyring is unavailable.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    match matches.subcommand() {
+        ("add", Some(matches)) => {
+            let host = matches.value_of("HOST").unwrap().to_ascii_lowercase();
+            let credentials = match matches.value_of("user") {
+                Some(user) => Credentials::password(
+                    matches.value_of("auth-type").unwrap(),
+                    user.to_string(),
+                    read_secret(&format!("Password for {}@{}: ", user, host))?,
+                )?,
+                None => Credentials::Bearer(read_secret(&format!("Token for {}: ", host))?),
+            };
+            let secret = serde_json::to_string(&credentials)?;
+            keyring::Entry::new(KEYRING_SERVICE, &host)
+                .and_then(|entry| entry.set_password(&secret))
+                .map_err(unavailable)?;
+            println!("Stored credentials for {}", host);
+        }
+        ("remove", Some(matches)) => {
+            let host = matches.value_of("HOST").unwrap().to_ascii_lowercase();
+            match keyring::Entry::new(KEYRING_SERVICE, &host)
+                .and_then(|entry| entry.delete_credential())
+            {
+                Ok(()) => println!("Removed credentials for {}", host),
+                Err(keyring::Error::NoEntry) => {
+                    return Err(format!("no credentials stored for {}", host).into())
+                }
+                Err(err) => return Err(unavailable(err).into()),
+            }
+        }
+        ("login", Some(matches)) => {
+            let name = matches.value_of("PROVIDER").unwrap();
+            let config = config::load(matches.value_of("config").map(Path::new))?;
+            let provider = config
+                .oauth
+                .iter()
+                .find(|provider| provider.name == name)
+                .ok_or_else(|| format!("no [[oauth]] provider named '{}' is configured", name))?;
+            let token = oauth::authorize(&Client::new(), provider, |instructions| {
+                println!("{}", instructions);
+                println!("Waiting for approval...");
+            })?;
+            store_token(name, &token)?;
+            println!("Logged in to {}", name);
+        }
+        ("logout", Some(matches)) => {
+            let name = matches.value_of("PROVIDER").unwrap();
+            match keyring::Entry::new(KEYRING_SERVICE, &token_account(name))
+                .and_then(|entry| entry.delete_credential())
+            {
+                Ok(()) => println!("Logged out of {}", name),
+                Err(keyring::Error::NoEntry) => {
+                    return Err(format!("not logged in to {}", name).into())
+                }
+                Err(err) => return Err(unavailable(err).into()),
+            }
+        }
+        _ => unreachable!("clap requires a subcommand"),
+    }
+    Ok(())
+}
+
+/// Describes a keyring failure.
+fn unavailable(err: keyring::Error) -> String {
+    format!("cannot use the system keyring: {}", err)
+}
+
+/// Reads a secret without echo from the terminal, or as one line of piped input.
+pub fn read_secret(prompt: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let secret = if io::stdin().is_terminal() {
+        rpassword::prompt_password(prompt)?
+    } else {
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+    if secret.is_empty() {
+        return Err("no secret given".into());
+    }
+    Ok(secret)
+}
+
+/// Resolves the bearer token of a run.
+///
+/// # Arguments
+///
+/// * `bearer`: The `--bearer` value.
+/// * `token_file`: The `--token-file` path; surrounding whitespace in the file is ignored.
+///
+/// # Errors
+///
+/// Returns an error if the token file cannot be read, or if the token is
+/// empty or contains characters that are not allowed in a header.
+pub fn bearer(
+    bearer: Option<&str>,
+    token_file: Option<&Path>,
+) -> Result<Option<Credentials>, Box<dyn std::error::Error>> {
+    let token = match (bearer, token_file) {
+        (Some(token), _) => token.to_string(),
+        (None, Some(path)) => fs::read_to_string(path)
+            .map_err(|err| format!("cannot read token file {}: {}", path.display(), err))?,
+        (None, None) => match std::env::var(TOKEN_ENV) {
+            Ok(token) => token,
+            Err(_) => return Ok(None),
+        },
+    };
+    let token = token.trim();
+    if token.is_empty() || HeaderValue::from_str(token).is_err() {
+        return Err("invalid bearer token: must be non-empty printable ASCII".into());
+    }
+    Ok(Some(Credentials::Bearer(token.to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use reqwest::blocking::Client;
+
+    #[test]
+    fn test_token_file_is_trimmed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        fs::write(&path, "s3cret\n").unwrap();
+
+        let credentials = bearer(None, Some(&path)).unwrap();
+        assert_eq!(credentials, Some(Credentials::Bearer("s3cret".to_string())));
+        assert_eq!(format!("{:?}", credentials), "Some(Bearer(<redacted>))");
+        assert!(bearer(Some("bad\ntoken"), None).is_err());
+    }
+
+    #[test]
+    fn test_keyring_lookup_by_host() {
+        let keyring = Keyring::default();
+        let credentials = Credentials::Basic {
+            user: "alice".to_string(),
+            password: "pw".to_string(),
+        };
+        keyring.cache.lock().unwrap().extend([
+            ("files.example.com".to_string(), Some(credentials.clone())),
+            ("files.example.com:8443".to_string(), None),
+        ]);
+
+        assert_eq!(
+            keyring.lookup("https://FILES.example.com/a.iso"),
+            Some(credentials)
+        );
+        assert_eq!(keyring.lookup("https://files.example.com:8443/a.iso"), None);
+        assert_eq!(keyring.lookup("not a url"), None);
+    }
+
+    #[test]
+    fn test_keyring_refreshes_expired_oauth_token() {
+        let refresh = mock("POST", "/auth/oauth/token")
+            .match_body(Matcher::UrlEncoded("refresh_token".into(), "rt".into()))
+            .with_body(r#"{"access_token":"fresh","expires_in":3600}"#)
+            .expect(1)
+            .create();
+        let keyring = Keyring {
+            providers: vec![Provider {
+                name: "drive".to_string(),
+                device_authorization_url: format!("{}/auth/oauth/device", server_url()),
+                token_url: format!("{}/auth/oauth/token", server_url()),
+                client_id: "client".to_string(),
+                client_secret: None,
+                scope: None,
+                hosts: vec!["drive.example.com".to_string()],
+            }],
+            ..Keyring::default()
+        };
+        keyring.cache.lock().unwrap().extend([
+            ("drive.example.com".to_string(), None),
+            ("other.example.com".to_string(), None),
+        ]);
+        keyring.tokens.lock().unwrap().insert(
+            "drive".to_string(),
+            Some(Token {
+                access_token: "stale".to_string(),
+                refresh_token: Some("rt".to_string()),
+                expires_at: Some(0),
+            }),
+        );
+
+        for _ in 0..2 {
+            assert_eq!(
+                keyring.lookup("https://drive.example.com/file"),
+                Some(Credentials::Bearer("fresh".to_string()))
+            );
+        }
+        assert_eq!(keyring.lookup("https://other.example.com/file"), None);
+        refresh.assert();
+    }
+
+    #[test]
+    fn test_stored_format() {
+        let credentials = Credentials::Basic {
+            user: "alice".to_string(),
+            password: "pw".to_string(),
+        };
+        let json = serde_json::to_string(&credentials).unwrap();
+        assert_eq!(json, r#"{"basic":{"user":"alice","password":"pw"}}"#);
+        assert_eq!(
+            serde_json::from_str::<Credentials>(r#"{"bearer":"t"}"#).unwrap(),
+            Credentials::Bearer("t".to_string())
+        );
+    }
+
+    #[test]
+    fn test_send_completes_ntlm_handshake() {
+        let challenge = "TlRMTVNTUAACAAAAAAAAAAAAAAAFgomiASNFZ4mrze8AAAAAAAAAAA==";
+        let first = mock("GET", "/auth/ntlm")
+            .match_header(
+                "authorization",
+                Matcher::Regex("^NTLM TlRMTVNTUAABAAAA".to_string()),
+            )
+            .with_status(401)
+            .with_header("www-authenticate", &format!("NTLM {}", challenge))
+            .create();
+        let second = mock("GET", "/auth/ntlm")
+            .match_header(
+                "authorization",
+                Matcher::Regex("^NTLM TlRMTVNTUAADAAAA".to_string()),
+            )
+            .with_body("secret")
+            .create();
+
+        let credentials =
+            Credentials::password("ntlm", "DOMAIN\\user".to_string(), "pw".to_string()).unwrap();
+        let url = format!("{}/auth/ntlm", server_url());
+        let client = Client::new();
+        let response = send(|| client.get(&url), Some(&credentials), None).unwrap();
+
+        assert_eq!(response.text().unwrap(), "secret");
+        first.assert();
+        second.assert();
+    }
+
+    #[test]
+    fn test_apply_sets_authorization() {
+        let mock = mock("GET", "/auth/bearer")
+            .match_header("authorization", "Bearer s3cret")
+            .create();
+
+        let credentials = Credentials::Bearer("s3cret".to_string());
+        let request = Client::new().get(format!("{}/auth/bearer", server_url()));
+        credentials.apply(request).send().unwrap();
+
+        mock.assert();
+    }
+}
@@ -0,0 +1,243 @@
+//! Stacked progress bars for concurrent downloads.
+//!
+//! While several URLs are downloaded without `--tui` and standard error is a
+//! terminal, every running transfer has a line at the bottom of the terminal
+//! with its file name, a bar, and its size and speed. Status lines printed
+//! meanwhile, through [`logfile`](crate::logfile), appear above the bars
+//! instead of between them.
+//!
+//! Lines are fitted to the width of the terminal, which is read again on
+//! every redraw, so that the bars reflow when the terminal is resized
+//! (`SIGWINCH`) instead of wrapping. Only as many bars as fit in half the
+//! terminal are shown, followed by how many transfers are left out.
+
+use crate::queue::{Job, Status};
+use crate::redact;
+use crate::tui::Speeds;
+use crate::units::{format_bytes, format_rate};
+use ratatui::crossterm::terminal;
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+
+/// Narrowest bar worth drawing, in cells.
+const MIN_BAR: usize = 5;
+
+/// Widest bar drawn, in cells.
+const MAX_BAR: usize = 30;
+
+/// Widest file name shown, in cells.
+const MAX_NAME: usize = 30;
+
+/// The bars on screen, while they are shown.
+static BARS: Mutex<Option<Bars>> = Mutex::new(None);
+
+#[derive(Debug, Default)]
+struct Bars {
+    /// The lines last drawn, to erase them.
+    lines: Vec<String>,
+    speeds: Speeds,
+}
+
+/// Starts showing bars, if standard error is a terminal.
+pub fn start() {
+    if io::stderr().is_terminal() {
+        *BARS.lock().unwrap_or_else(|e| e.into_inner()) = Some(Bars::default());
+    }
+}
+
+/// Redraws the bars of the running transfers among `jobs`.
+pub fn update(jobs: &[Job]) {
+    if let Some(bars) = &mut *BARS.lock().unwrap_or_else(|e| e.into_inner()) {
+        bars.speeds.update(jobs);
+        let (width, height) = size();
+        let mut text = erase(&bars.lines, width);
+        bars.lines = lines(jobs, &bars.speeds, width, (height / 2).max(3));
+        text += &draw(&bars.lines);
+        let _ = io::stderr().write_all(text.as_bytes());
+    }
+}
+
+/// Runs `print`, which writes to the terminal, with the bars moved below
+/// what it writes.
+pub fn above(print: impl FnOnce()) {
+    let mut guard = BARS.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(bars) = &mut *guard else {
+        drop(guard);
+        return print();
+    };
+    let mut stderr = io::stderr();
+    let _ = stderr.write_all(erase(&bars.lines, size().0).as_bytes());
+    let _ = stderr.flush();
+    print();
+    let _ = io::stdout().flush();
+    let _ = stderr.write_all(draw(&bars.lines).as_bytes());
+}
+
+/// Removes the bars for good.
+pub fn finish() {
+    if let Some(bars) = BARS.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        let _ = io::stderr().write_all(erase(&bars.lines, size().0).as_bytes());
+    }
+}
+
+/// Returns the width and height of the terminal, taking 80 by 24 for what
+/// it does not report.
+fn size() -> (usize, usize) {
+    let (width, height) = terminal::size().unwrap_or((0, 0));
+    let known = |cells: u16, default| match cells {
+        0 => default,
+        cells => cells as usize,
+    };
+    (known(width, 80), known(height, 24))
+}
+
+/// Returns the escape sequence that moves the cursor back to the first of
+/// `lines` and clears the screen below it, on a terminal `width` cells wide.
+///
+/// Lines drawn on a wider terminal may have been wrapped by a resize since.
+fn erase(lines: &[String], width: usize) -> String {
+    let rows: usize = lines
+        .iter()
+        .map(|line| line.chars().count().div_ceil(width).max(1))
+        .sum();
+    match rows {
+        0 => String::new(),
+        rows => format!("\r\x1b[{}A\x1b[J", rows),
+    }
+}
+
+fn draw(lines: &[String]) -> String {
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Renders a line for each running or paused transfer of `jobs`, at most
+/// `max` of them, fitted to `width` cells.
+fn lines(jobs: &[Job], speeds: &Speeds, width: usize, max: usize) -> Vec<String> {
+    let active: Vec<&Job> = jobs
+        .iter()
+        .filter(|job| matches!(job.status, Status::Running | Status::Paused))
+        .collect();
+    // Leave the last column free, so that no terminal wraps a full line.
+    let width = width.saturating_sub(1);
+    let shown = match active.len() > max {
+        true => max.saturating_sub(1),
+        false => active.len(),
+    };
+    let mut lines: Vec<String> = active[..shown]
+        .iter()
+        .map(|job| line(job, speeds.get(job.id), width))
+        .collect();
+    if shown < active.len() {
+        lines.push(fit(&format!("… and {} more", active.len() - shown), width));
+    }
+    lines
+}
+
+/// Renders the bar of `job` in `width` cells, such as
+/// `file.iso [████████░░░░]  66.7% of 1.5 GiB  12.0 MiB/s`.
+fn line(job: &Job, speed: Option<f64>, width: usize) -> String {
+    let name = job
+        .output
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| redact::url(&job.url).into_owned());
+    let speed = match job.status {
+        Status::Paused => "paused".to_string(),
+        _ => speed.map(format_rate).unwrap_or_default(),
+    };
+    let (ratio, size) = match job.total {
+        Some(total) if total > 0 => (
+            Some((job.downloaded as f64 / total as f64).min(1.0)),
+            format!(
+                "{:>5.1}% of {}",
+                job.downloaded as f64 / total as f64 * 100.0,
+                format_bytes(total)
+            ),
+        ),
+        _ => (None, format_bytes(job.downloaded)),
+    };
+    let status = match speed.is_empty() {
+        true => format!(" {}", size),
+        false => format!(" {}  {}", size, speed),
+    };
+    let name = fit(
+        &name,
+        MAX_NAME.min(width.saturating_sub(status.chars().count())),
+    );
+    let room = width.saturating_sub(name.chars().count() + status.chars().count() + 3);
+    let bar = match ratio {
+        Some(ratio) if room >= MIN_BAR => {
+            let cells = room.min(MAX_BAR);
+            let filled = (ratio * cells as f64).round() as usize;
+            format!(" [{}{}]", "█".repeat(filled), "░".repeat(cells - filled))
+        }
+        _ => String::new(),
+    };
+    fit(&format!("{}{}{}", name, bar, status), width)
+}
+
+/// Cuts `text` to `width` cells, ending it with `…` when it is cut.
+fn fit(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut fitted: String = text.chars().take(width.saturating_sub(1)).collect();
+    if width > 0 {
+        fitted.push('…');
+    }
+    fitted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn job(id: u64, status: Status, downloaded: u64, total: Option<u64>) -> Job {
+        Job {
+            id,
+            url: format!("https://example.com/{}.iso", id),
+            output: PathBuf::from(format!("{}.iso", id)),
+            priority: 0,
+            status,
+            downloaded,
+            total,
+            attempts: 0,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_lines_fit_the_terminal() {
+        let running = job(1, Status::Running, 512, Some(1024));
+        assert_eq!(
+            line(&running, None, 40),
+            "1.iso [███████░░░░░░░]  50.0% of 1.0 KiB"
+        );
+        assert_eq!(line(&running, None, 30), "1.iso  50.0% of 1.0 KiB");
+        assert_eq!(line(&running, None, 10).chars().count(), 10);
+        assert_eq!(
+            line(&job(2, Status::Paused, 2048, None), None, 40),
+            "2.iso 2.0 KiB  paused"
+        );
+
+        let jobs = [
+            running,
+            job(2, Status::Completed, 1024, Some(1024)),
+            job(3, Status::Running, 0, None),
+            job(4, Status::Queued, 0, None),
+            job(5, Status::Running, 0, None),
+        ];
+        let speeds = Speeds::default();
+        assert_eq!(lines(&jobs, &speeds, 80, 5).len(), 3);
+        let cut = lines(&jobs, &speeds, 80, 2);
+        assert_eq!(cut.len(), 2);
+        assert_eq!(cut[1], "… and 2 more");
+
+        // A line wrapped by a narrower terminal takes two rows to erase.
+        let drawn = ["x".repeat(60), "y".repeat(10)];
+        assert_eq!(erase(&drawn, 80), "\r\x1b[2A\x1b[J");
+        assert_eq!(erase(&drawn, 40), "\r\x1b[3A\x1b[J");
+        assert_eq!(erase(&[], 40), "");
+    }
+}
@@ -0,0 +1,262 @@
+//! Concurrent downloading of several URLs through an in-memory [`Queue`].
+
+use crate::bars;
+use crate::download::Options;
+use crate::i18n;
+use crate::logfile;
+use crate::queue::{self, Job, Queue, Status};
+use crate::report::Report;
+use crate::tui;
+use reqwest::blocking::Client;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often the plain-text reporter polls the queue.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A URL to fetch, where to write it, and how urgently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Download {
+    pub url: String,
+    pub output: PathBuf,
+    pub priority: i32,
+    /// Whether to continue the existing file instead of replacing it.
+    pub resume: bool,
+}
+
+/// Downloads every entry of `downloads` using `workers` concurrent transfers.
+///
+/// # Arguments
+///
+/// * `client`: The HTTP client shared by all workers.
+/// * `downloads`: The URLs to fetch, in order of submission.
+/// * `workers`: Number of transfers to run at the same time.
+/// * `per_host`: Number of those transfers that may fetch from the same host.
+/// * `options`: Settings shared by every transfer, such as retries and rate limits.
+/// * `dashboard`: Whether to show the interactive dashboard instead of status lines and
+///   progress [`bars`].
+///
+/// # Returns
+///
+/// * `Report`: How many downloads succeeded and which ones failed.
+///
+/// # Errors
+///
+/// Returns an error if the dashboard could not drive the terminal.
+pub fn run(
+    client: &Client,
+    downloads: Vec<Download>,
+    workers: usize,
+    per_host: usize,
+    options: &Options,
+    dashboard: bool,
+) -> Result<Report, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let queue = Arc::new(Queue::open(None)?.with_host_limit(per_host));
+    for download in downloads {
+        let partial = match download.resume {
+            true => fs::metadata(&download.output).map_or(0, |meta| meta.len()),
+            false => 0,
+        };
+        queue.add_partial(download.url, download.output, download.priority, partial);
+    }
+    let workers: Vec<_> = (0..workers.max(1))
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let client = client.clone();
+            let options = options.clone();
+            thread::spawn(move || queue::run_worker(&queue, &client, &options))
+        })
+        .collect();
+
+    if dashboard {
+        let shown = tui::run(&queue);
+        // Transfers paused on leaving the dashboard end before they are reported.
+        finish(&queue, workers)?;
+        shown?;
+        queue.jobs().iter().for_each(report);
+    } else {
+        for job in queue.jobs() {
+            logfile::info(&format!("Downloading: {}", job.url));
+        }
+        let mut reported = HashSet::new();
+        bars::start();
+        loop {
+            let idle = queue.is_idle();
+            let jobs = queue.jobs();
+            for job in &jobs {
+                if job.is_finished() && reported.insert(job.id) {
+                    report(job);
+                }
+            }
+            if idle {
+                break;
+            }
+            bars::update(&jobs);
+            thread::sleep(POLL_INTERVAL);
+        }
+        bars::finish();
+        finish(&queue, workers)?;
+    }
+
+    Ok(Report::new(&queue.jobs(), started.elapsed()))
+}
+
+/// Closes `queue` and waits for its `workers` to return.
+fn finish(queue: &Queue, workers: Vec<JoinHandle<()>>) -> Result<(), String> {
+    queue.close();
+    for worker in workers {
+        worker
+            .join()
+            .map_err(|_| "a download worker panicked".to_string())?;
+    }
+    Ok(())
+}
+
+/// Describes what [`run`] would do with `downloads`, one line per URL.
+///
+/// Lines are listed in the order the transfers would start: by descending
+/// priority, then in order of submission.
+pub fn plan(downloads: &[Download]) -> String {
+    let mut ordered: Vec<&Download> = downloads.iter().collect();
+    ordered.sort_by_key(|download| Reverse(download.priority));
+    ordered
+        .iter()
+        .map(|download| match download.priority {
+            0 => format!("{} -> {}\n", download.url, download.output.display()),
+            priority => format!(
+                "{} -> {} (priority {})\n",
+                download.url,
+                download.output.display(),
+                priority
+            ),
+        })
+        .collect()
+}
+
+/// Prints the final state of one job.
+fn report(job: &Job) {
+    match job.status {
+        Status::Completed => logfile::success(&i18n::text(
+            "downloaded",
+            &[("file", &job.output.display())],
+        )),
+        Status::Failed => logfile::error(&i18n::text(
+            "failed",
+            &[
+                ("url", &job.url),
+                ("error", &job.error.as_deref().unwrap_or("unknown error")),
+            ],
+        )),
+        status => logfile::info(&format!(
+            "{}: {} ({})",
+            capitalize(&status.to_string()),
+            job.url,
+            job.output.display()
+        )),
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+    use std::fs;
+
+    #[test]
+    fn test_downloads_all_urls() {
+        let first = mock("GET", "/batch/one.txt").with_body("one").create();
+        let second = mock("GET", "/batch/two.txt").with_body("two").create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloads = vec![
+            Download {
+                url: format!("{}/batch/one.txt", server_url()),
+                output: dir.path().join("one.txt"),
+                priority: 0,
+                resume: false,
+            },
+            Download {
+                url: format!("{}/batch/two.txt", server_url()),
+                output: dir.path().join("two.txt"),
+                priority: 1,
+                resume: false,
+            },
+        ];
+
+        let report = run(&Client::new(), downloads, 2, 2, &Options::default(), false).unwrap();
+        assert_eq!((report.succeeded, report.bytes), (2, 6));
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("one.txt")).unwrap(),
+            "one"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("two.txt")).unwrap(),
+            "two"
+        );
+        first.assert();
+        second.assert();
+    }
+
+    #[test]
+    fn test_plan_lists_downloads_in_start_order() {
+        let download = |url: &str, priority| Download {
+            url: url.to_string(),
+            output: PathBuf::from(url.rsplit('/').next().unwrap()),
+            priority,
+            resume: false,
+        };
+        let downloads = vec![
+            download("https://example.com/a", 0),
+            download("https://example.com/b", 2),
+            download("https://example.com/c", 0),
+        ];
+
+        assert_eq!(
+            plan(&downloads),
+            "https://example.com/b -> b (priority 2)\n\
+             https://example.com/a -> a\n\
+             https://example.com/c -> c\n"
+        );
+    }
+
+    #[test]
+    fn test_reports_failures() {
+        let missing = mock("GET", "/batch/missing.txt")
+            .with_status(404)
+            .expect(2)
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let downloads = vec![Download {
+            url: format!("{}/batch/missing.txt", server_url()),
+            output: dir.path().join("missing.txt"),
+            priority: 0,
+            resume: false,
+        }];
+
+        let options = Options {
+            tries: 2,
+            ..Options::default()
+        };
+        let report = run(&Client::new(), downloads, 1, 1, &options, false).unwrap();
+
+        assert_eq!(report.check().unwrap_err(), "1 of 1 downloads failed");
+        assert!(report.failures[0].url.ends_with("/batch/missing.txt"));
+        missing.assert();
+    }
+}
@@ -0,0 +1,200 @@
+//! Measuring how fast a server delivers a resource.
+//!
+//! With `--benchmark N`, each URL is downloaded `N` times into a sink that
+//! discards the data, and the runs are summarized: throughput, and where the
+//! time of each run went. Every run resolves the host, opens a TCP connection
+//! to it to time the handshake, and then sends the request on a fresh
+//! connection, so that no run profits from the one before.
+
+use crate::download::Options;
+use crate::logfile;
+use crate::units;
+use reqwest::blocking::Client;
+use std::io::Read;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// How long a TCP connection may take to open.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The timings of one run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    /// Resolving the host name.
+    pub dns: Duration,
+    /// Opening a TCP connection to the first address.
+    pub connect: Duration,
+    /// From sending the request to receiving the response headers.
+    pub first_byte: Duration,
+    /// Receiving the body.
+    pub transfer: Duration,
+    pub bytes: u64,
+}
+
+impl Sample {
+    /// Bytes per second over the whole request, from sending it to the last byte.
+    fn throughput(&self) -> f64 {
+        self.bytes as f64 / (self.first_byte + self.transfer).as_secs_f64().max(1e-9)
+    }
+}
+
+/// Downloads `url` once, discarding the body, and reports how long each step took.
+///
+/// # Errors
+///
+/// Returns an error if the host cannot be resolved or reached, or if the
+/// request fails or is answered with an error status.
+pub fn measure(client: &Client, url: &str, options: &Options) -> Result<Sample, String> {
+    let parsed = Url::parse(url).map_err(|err| err.to_string())?;
+    let host = parsed.host_str().ok_or("URL has no host")?;
+    let port = parsed.port_or_known_default().ok_or("URL has no port")?;
+
+    let started = Instant::now();
+    let address = (host, port)
+        .to_socket_addrs()
+        .map_err(|err| format!("cannot resolve {}: {}", host, err))?
+        .next()
+        .ok_or_else(|| format!("{} has no addresses", host))?;
+    let dns = started.elapsed();
+
+    let started = Instant::now();
+    TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)
+        .map_err(|err| format!("cannot connect to {}: {}", address, err))?;
+    let connect = started.elapsed();
+
+    let started = Instant::now();
+    let mut response = options
+        .send(client, url, |client| client.get(url))
+        .map_err(|err| err.to_string())?
+        .error_for_status()
+        .map_err(|err| err.to_string())?;
+    let first_byte = started.elapsed();
+
+    let mut buffer = vec![0; options.buffer_size];
+    let mut bytes = 0;
+    loop {
+        let read = response.read(&mut buffer).map_err(|err| err.to_string())?;
+        if read == 0 {
+            break;
+        }
+        bytes += read as u64;
+        options.consume(url, read);
+    }
+    Ok(Sample {
+        dns,
+        connect,
+        first_byte,
+        transfer: started.elapsed() - first_byte,
+        bytes,
+    })
+}
+
+/// Measures `url` `runs` times and returns the summary to print.
+///
+/// Each run is printed as it completes; a failed run ends the benchmark.
+///
+/// # Errors
+///
+/// Returns the error of the first failed run.
+pub fn run(client: &Client, url: &str, runs: usize, options: &Options) -> Result<String, String> {
+    logfile::info(&format!("Benchmarking: {}", url));
+    let mut samples = Vec::with_capacity(runs);
+    for run in 1..=runs {
+        let sample = measure(client, url, options).map_err(|err| format!("{}: {}", url, err))?;
+        logfile::info(&format!(
+            "Run {}/{}: {} in {:.3}s ({})",
+            run,
+            runs,
+            units::format_bytes(sample.bytes),
+            (sample.first_byte + sample.transfer).as_secs_f64(),
+            units::format_rate(sample.throughput())
+        ));
+        samples.push(sample);
+    }
+    Ok(summarize(&samples))
+}
+
+/// Formats the minimum, mean, and 95th percentile of each measurement over `samples`.
+pub fn summarize(samples: &[Sample]) -> String {
+    let milliseconds = |pick: fn(&Sample) -> Duration| {
+        let values = samples.iter().map(|s| pick(s).as_secs_f64() * 1000.0);
+        statistics(values.collect()).map(|value| format!("{:.1}", value))
+    };
+    let rows = [
+        ("dns (ms)", milliseconds(|s| s.dns)),
+        ("connect (ms)", milliseconds(|s| s.connect)),
+        ("first byte (ms)", milliseconds(|s| s.first_byte)),
+        ("transfer (ms)", milliseconds(|s| s.transfer)),
+        (
+            "throughput",
+            statistics(samples.iter().map(Sample::throughput).collect()).map(units::format_rate),
+        ),
+    ];
+
+    let mut summary = format!("{:<16} {:>12} {:>12} {:>12}\n", "", "min", "avg", "p95");
+    for (name, [min, avg, p95]) in rows {
+        summary += &format!("{:<16} {:>12} {:>12} {:>12}\n", name, min, avg, p95);
+    }
+    summary
+}
+
+/// Returns the minimum, mean, and 95th percentile of `values`, or zeros if there are none.
+fn statistics(mut values: Vec<f64>) -> [f64; 3] {
+    if values.is_empty() {
+        return [0.0; 3];
+    }
+    values.sort_by(f64::total_cmp);
+    let avg = values.iter().sum::<f64>() / values.len() as f64;
+    [values[0], avg, percentile(&values, 95)]
+}
+
+/// Returns the nearest-rank `percent`th percentile of sorted, non-empty `values`.
+fn percentile(values: &[f64], percent: usize) -> f64 {
+    let rank = (values.len() * percent).div_ceil(100);
+    values[rank.clamp(1, values.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+
+    #[test]
+    fn test_percentile() {
+        let values: Vec<f64> = (1..=20).map(f64::from).collect();
+        assert_eq!(percentile(&values, 95), 19.0);
+        assert_eq!(percentile(&values[..1], 95), 1.0);
+        assert_eq!(percentile(&values[..10], 50), 5.0);
+    }
+
+    #[test]
+    fn test_measure_and_summarize() {
+        let _m = mock("GET", "/benchmark/blob.bin")
+            .with_status(200)
+            .with_body(vec![7; 100_000])
+            .expect(3)
+            .create();
+        let url = format!("{}/benchmark/blob.bin", server_url());
+        let client = Client::new();
+
+        let samples: Vec<Sample> = (0..3)
+            .map(|_| measure(&client, &url, &Options::default()).unwrap())
+            .collect();
+        assert!(samples.iter().all(|sample| sample.bytes == 100_000));
+
+        let summary = summarize(&samples);
+        assert!(summary.lines().next().unwrap().ends_with("p95"));
+        assert!(summary.contains("first byte (ms)"));
+        assert!(summary.lines().last().unwrap().starts_with("throughput"));
+    }
+
+    #[test]
+    fn test_measure_fails_on_error_status() {
+        let _m = mock("GET", "/benchmark/missing").with_status(404).create();
+        let url = format!("{}/benchmark/missing", server_url());
+
+        let err = measure(&Client::new(), &url, &Options::default()).unwrap_err();
+        assert!(err.contains("404"), "{}", err);
+    }
+}
@@ -0,0 +1,619 @@
+//! `rustwget bundle`: moving downloads across an air gap in one file.
+//!
+//! `rustwget bundle create OUT -i urls.txt` downloads the URLs and writes them
+//! to `OUT`, a tar archive holding `bundle.json` followed by the files under
+//! `files/HOST/PATH`. `bundle.json` lists the URL, path, size, SHA-256 digest,
+//! content type, and modification time of every file. `rustwget bundle
+//! extract OUT -C DIR` writes the files to `DIR/HOST/PATH` on the other side,
+//! checking each against its digest and keeping its modification time. Any
+//! `tar` can read a bundle too.
+//!
+//! Credentials come from the keyring and the `[host]` sections of the
+//! configuration file, as for downloads. A URL that cannot be downloaded is
+//! left out of the bundle, which is still written, and makes the command fail.
+
+use crate::auth::Keyring;
+use crate::cache;
+use crate::config;
+use crate::download::{self, Options};
+use crate::filename::{self, Restriction};
+use crate::hosts::Hosts;
+use crate::input;
+use crate::ledger::{self, hex};
+use crate::logfile;
+use crate::redact;
+use crate::redirect;
+use crate::units;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use reqwest::blocking::{Client, ClientBuilder};
+use reqwest::header::{CONTENT_TYPE, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use url::Url;
+
+/// The name of the description of a bundle, its first member.
+const MANIFEST: &str = "bundle.json";
+
+/// The directory of a bundle that holds the downloaded files.
+const FILES: &str = "files";
+
+/// The size of a tar block.
+const BLOCK: usize = 512;
+
+/// The version of the bundle format.
+const VERSION: u32 = 1;
+
+/// The description of a bundle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    /// When the bundle was created, in RFC 3339 form.
+    created: String,
+    files: Vec<Member>,
+}
+
+/// A downloaded file in a bundle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Member {
+    url: String,
+    /// Where the file is extracted to, relative to the target directory.
+    path: PathBuf,
+    size: u64,
+    sha256: String,
+    content_type: Option<String>,
+    /// The server's modification time, in seconds since the Unix epoch.
+    modified: Option<u64>,
+}
+
+/// Builds the `bundle` subcommand definition.
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("bundle")
+        .about("Carry downloads across an air gap in a single file")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("create")
+                .about("Download URLs into a bundle")
+                .arg(
+                    Arg::with_name("BUNDLE")
+                        .help("The bundle to write")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("URL")
+                        .help("URLs to download")
+                        .multiple(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("input-file")
+                        .short("i")
+                        .long("input-file")
+                        .value_name("FILE")
+                        .help("Read URLs from FILE ('-' for stdin), one per line")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("config")
+                        .long("config")
+                        .value_name("FILE")
+                        .help("Read host settings from this TOML file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output-file")
+                        .short("o")
+                        .long("output-file")
+                        .value_name("LOGFILE")
+                        .help("Also append all messages to LOGFILE")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("extract")
+                .about("Write the files of a bundle to a directory")
+                .arg(
+                    Arg::with_name("BUNDLE")
+                        .help("The bundle to read ('-' for stdin)")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("directory")
+                        .short("C")
+                        .long("directory")
+                        .value_name("DIR")
+                        .help("Directory to extract into")
+                        .default_value(".")
+                        .takes_value(true),
+                ),
+        )
+}
+
+/// Runs the `bundle` subcommand.
+///
+/// # Errors
+///
+/// Returns an error if the arguments or the configuration are invalid, the
+/// bundle cannot be written or read, or a URL cannot be downloaded.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    match matches.subcommand() {
+        ("create", Some(matches)) => {
+            if let Some(path) = matches.value_of("output-file") {
+                logfile::open(Path::new(path), true, None)?;
+            }
+            let mut urls: Vec<String> = matches
+                .values_of("URL")
+                .map(|urls| urls.map(str::to_string).collect())
+                .unwrap_or_default();
+            if let Some(path) = matches.value_of("input-file") {
+                urls.extend(input::read(path, 0)?.into_iter().map(|entry| entry.url));
+            }
+            if urls.is_empty() {
+                return Err("no URLs to bundle; give them as arguments or with -i".into());
+            }
+            let urls: Vec<String> = urls
+                .iter()
+                .map(|url| input::with_default_scheme(url, "https"))
+                .collect();
+            let config = config::load(matches.value_of("config").map(Path::new))?;
+            let builder = || -> Result<ClientBuilder, Box<dyn std::error::Error>> {
+                Ok(Client::builder().redirect(redirect::policy(redirect::DEFAULT_MAX, false)))
+            };
+            let options = Options {
+                keyring: Some(Arc::new(Keyring::new(config.oauth.clone()))),
+                hosts: Arc::new(Hosts::new(&config.host, builder)?),
+                ..Options::default()
+            };
+            let out = Path::new(matches.value_of("BUNDLE").unwrap());
+            create(out, &urls, &builder()?.build()?, &options)
+        }
+        ("extract", Some(matches)) => {
+            let bundle = matches.value_of("BUNDLE").unwrap();
+            let reader: Box<dyn Read> = match bundle {
+                "-" => Box::new(io::stdin().lock()),
+                path => Box::new(
+                    File::open(path).map_err(|err| format!("cannot open {}: {}", path, err))?,
+                ),
+            };
+            let dir = Path::new(matches.value_of("directory").unwrap());
+            let files = extract(BufReader::new(reader), dir)?;
+            let size = files.iter().map(|member| member.size).sum();
+            logfile::info(&format!(
+                "Extracted {} files ({}) into {}",
+                files.len(),
+                units::format_bytes(size),
+                dir.display()
+            ));
+            Ok(())
+        }
+        _ => unreachable!("clap requires a subcommand"),
+    }
+}
+
+/// Downloads `urls` into the bundle `out`. The files are first downloaded
+/// next to it, and the bundle is only put in place once it is complete.
+fn create(
+    out: &Path,
+    urls: &[String],
+    client: &Client,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let staging = PathBuf::from(format!("{}.parts", out.display()));
+    fs::create_dir_all(&staging)
+        .map_err(|err| format!("cannot create {}: {}", staging.display(), err))?;
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let mut files = Vec::new();
+        let mut paths = HashSet::new();
+        let mut failed = 0;
+        for url in urls {
+            let part = staging.join(files.len().to_string());
+            match fetch(client, url, &part, options) {
+                Ok(mut member) => {
+                    member.path = unique(member.path, &mut paths);
+                    logfile::info(&format!(
+                        "Bundled {} ({})",
+                        redact::url(url),
+                        units::format_bytes(member.size)
+                    ));
+                    files.push(member);
+                }
+                Err(err) => {
+                    logfile::warning(&format!(
+                        "Warning: cannot download {}: {}",
+                        redact::url(url),
+                        err
+                    ));
+                    failed += 1;
+                }
+            }
+        }
+
+        let manifest = Manifest {
+            version: VERSION,
+            created: chrono::Utc::now().to_rfc3339(),
+            files,
+        };
+        let tmp = PathBuf::from(format!("{}.part", out.display()));
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        let json = serde_json::to_vec_pretty(&manifest)?;
+        append(
+            &mut writer,
+            MANIFEST,
+            json.len() as u64,
+            cache::now(),
+            &json[..],
+        )?;
+        for (index, member) in manifest.files.iter().enumerate() {
+            let name = Path::new(FILES).join(&member.path);
+            let name = name.to_string_lossy().replace('\\', "/");
+            let file = File::open(staging.join(index.to_string()))?;
+            append(
+                &mut writer,
+                &name,
+                member.size,
+                member.modified.unwrap_or_else(cache::now),
+                file,
+            )?;
+        }
+        // Two empty blocks end a tar archive.
+        writer.write_all(&[0; 2 * BLOCK])?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(&tmp, out)?;
+
+        let size: u64 = manifest.files.iter().map(|member| member.size).sum();
+        logfile::info(&format!(
+            "Wrote {} files ({}) to {}",
+            manifest.files.len(),
+            units::format_bytes(size),
+            out.display()
+        ));
+        match failed {
+            0 => Ok(()),
+            failed => Err(format!("{} of {} URLs could not be bundled", failed, urls.len()).into()),
+        }
+    })();
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+/// Downloads `url` to `part`, describing it for the manifest.
+fn fetch(
+    client: &Client,
+    url: &str,
+    part: &Path,
+    options: &Options,
+) -> Result<Member, Box<dyn std::error::Error>> {
+    let parsed = Url::parse(url)?;
+    let mut response = options.send(client, url, |client| client.get(url))?;
+    if !response.status().is_success() {
+        return Err(format!("the server answered {}", response.status()).into());
+    }
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let content_type = header(CONTENT_TYPE);
+    let modified = header(LAST_MODIFIED)
+        .and_then(|value| chrono::DateTime::parse_from_rfc2822(&value).ok())
+        .and_then(|time| u64::try_from(time.timestamp()).ok());
+    let size = io::copy(&mut response, &mut File::create(part)?)?;
+    Ok(Member {
+        url: url.to_string(),
+        path: member_path(&parsed),
+        size,
+        sha256: ledger::sha256_file(part)?,
+        content_type,
+        modified,
+    })
+}
+
+/// Returns where the file of `url` is extracted to: `HOST/PATH`, with
+/// `index.html` for a path ending in `/`, and each component made an ASCII
+/// file name. Extracting makes it valid on the platform too.
+fn member_path(url: &Url) -> PathBuf {
+    let mut path = PathBuf::from(match url.port() {
+        Some(port) => format!("{}_{}", url.host_str().unwrap_or("localhost"), port),
+        None => url.host_str().unwrap_or("localhost").to_string(),
+    });
+    if let Some(segments) = url.path_segments() {
+        let segments: Vec<&str> = segments.collect();
+        for segment in &segments[..segments.len().saturating_sub(1)] {
+            let segment = percent_encoding::percent_decode_str(segment).decode_utf8_lossy();
+            if !segment.is_empty() && segment != "." && segment != ".." {
+                path.push(segment.as_ref());
+            }
+        }
+    }
+    path.push(download::default_filename(url));
+    filename::sanitize_path(&path, Restriction::Ascii)
+}
+
+/// Returns `path`, or `path.1`, `path.2`, ... when an earlier file of the
+/// bundle already has it, as URLs differing in their query do.
+fn unique(path: PathBuf, taken: &mut HashSet<PathBuf>) -> PathBuf {
+    let mut candidate = path.clone();
+    let mut number = 0;
+    while !taken.insert(candidate.clone()) {
+        number += 1;
+        candidate = PathBuf::from(format!("{}.{}", path.display(), number));
+    }
+    candidate
+}
+
+/// Writes the files of the bundle read from `reader` into `dir`.
+///
+/// # Errors
+///
+/// Returns an error if the bundle is not one, a file in it does not match its
+/// digest, or a file cannot be written. Files written before then are kept.
+fn extract<R: Read>(mut reader: R, dir: &Path) -> Result<Vec<Member>, Box<dyn std::error::Error>> {
+    let not_a_bundle = || "not a rustwget bundle".to_string();
+    let (name, size, _) = next_header(&mut reader)?.ok_or_else(not_a_bundle)?;
+    if name != MANIFEST || size > 64 * 1024 * 1024 {
+        return Err(not_a_bundle().into());
+    }
+    let mut json = Vec::new();
+    (&mut reader).take(size).read_to_end(&mut json)?;
+    skip_padding(&mut reader, size)?;
+    let manifest: Manifest =
+        serde_json::from_slice(&json).map_err(|err| format!("{}: {}", not_a_bundle(), err))?;
+    if manifest.version != VERSION {
+        return Err(format!("unsupported bundle version {}", manifest.version).into());
+    }
+
+    let mut extracted = Vec::new();
+    while let Some((name, size, mtime)) = next_header(&mut reader)? {
+        let member = Path::new(&name)
+            .strip_prefix(FILES)
+            .ok()
+            .and_then(|path| manifest.files.iter().find(|member| member.path == path));
+        let Some(member) = member else {
+            // Members not in the manifest, such as directories, are skipped.
+            io::copy(&mut (&mut reader).take(size), &mut io::sink())?;
+            skip_padding(&mut reader, size)?;
+            continue;
+        };
+        if !filename::is_contained(&member.path) {
+            return Err(format!("refusing to extract {}", member.path.display()).into());
+        }
+        let path = dir.join(filename::sanitize_path(
+            &member.path,
+            Restriction::default(),
+        ));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = PathBuf::from(format!("{}.part", path.display()));
+        let mut hasher = Sha256::new();
+        let mut file = File::create(&tmp)?;
+        let mut body = (&mut reader).take(size);
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = body.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            file.write_all(&buffer[..read])?;
+        }
+        skip_padding(&mut reader, size)?;
+        if size != member.size || hex(&hasher.finalize()) != member.sha256 {
+            drop(file);
+            let _ = fs::remove_file(&tmp);
+            return Err(format!("{} does not match its digest", member.path.display()).into());
+        }
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(member.modified.unwrap_or(mtime)))?;
+        drop(file);
+        fs::rename(&tmp, &path)?;
+        extracted.push(member.clone());
+    }
+    if let Some(missing) = manifest
+        .files
+        .iter()
+        .find(|member| !extracted.contains(member))
+    {
+        return Err(format!("the bundle is missing {}", missing.path.display()).into());
+    }
+    Ok(extracted)
+}
+
+/// Appends a regular file `name` of `size` bytes, read from `body`, to a tar
+/// archive. Names that do not fit the header are given in a PAX header.
+fn append<W: Write, R: Read>(
+    writer: &mut W,
+    name: &str,
+    size: u64,
+    mtime: u64,
+    body: R,
+) -> io::Result<()> {
+    if name.len() > 100 {
+        let record = pax_record("path", name);
+        writer.write_all(&header("././@PaxHeader", record.len() as u64, 0, b'x'))?;
+        writer.write_all(record.as_bytes())?;
+        pad(writer, record.len() as u64)?;
+    }
+    let short = &name[name.len().saturating_sub(100)..];
+    writer.write_all(&header(short, size, mtime, b'0'))?;
+    let copied = io::copy(&mut body.take(size), writer)?;
+    if copied != size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{} changed while it was bundled", name),
+        ));
+    }
+    pad(writer, size)
+}
+
+/// Builds a ustar header block.
+fn header(name: &str, size: u64, mtime: u64, kind: u8) -> [u8; BLOCK] {
+    let mut block = [0; BLOCK];
+    let field = |block: &mut [u8; BLOCK], offset: usize, value: &[u8]| {
+        block[offset..offset + value.len()].copy_from_slice(value);
+    };
+    field(&mut block, 0, &name.as_bytes()[..name.len().min(100)]);
+    field(&mut block, 100, b"0000644\0");
+    field(&mut block, 108, b"0000000\0");
+    field(&mut block, 116, b"0000000\0");
+    field(&mut block, 124, format!("{:011o}\0", size).as_bytes());
+    field(&mut block, 136, format!("{:011o}\0", mtime).as_bytes());
+    field(&mut block, 148, b"        ");
+    block[156] = kind;
+    field(&mut block, 257, b"ustar\x0000");
+    let checksum: u32 = block.iter().map(|&byte| u32::from(byte)).sum();
+    field(&mut block, 148, format!("{:06o}\0 ", checksum).as_bytes());
+    block
+}
+
+/// Formats a PAX extended header record, which starts with its own length.
+fn pax_record(key: &str, value: &str) -> String {
+    let rest = format!(" {}={}\n", key, value);
+    let mut length = rest.len() + 1;
+    while length.to_string().len() + rest.len() > length {
+        length += 1;
+    }
+    format!("{}{}", length, rest)
+}
+
+/// Writes the zeros that fill the last block of a member of `size` bytes.
+fn pad<W: Write>(writer: &mut W, size: u64) -> io::Result<()> {
+    let rest = (BLOCK - (size % BLOCK as u64) as usize) % BLOCK;
+    writer.write_all(&[0; BLOCK][..rest])
+}
+
+/// Skips the zeros that fill the last block of a member of `size` bytes.
+fn skip_padding<R: Read>(reader: &mut R, size: u64) -> io::Result<()> {
+    let rest = (BLOCK - (size % BLOCK as u64) as usize) % BLOCK;
+    reader.read_exact(&mut [0; BLOCK][..rest])
+}
+
+/// Reads the header of the next regular file or directory of a tar archive,
+/// applying any PAX header before it, and returns its name, size, and
+/// modification time, or `None` at the end of the archive.
+fn next_header<R: Read>(reader: &mut R) -> io::Result<Option<(String, u64, u64)>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut long_name = None;
+    loop {
+        let mut block = [0; BLOCK];
+        match reader.read_exact(&mut block) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        if block.iter().all(|&byte| byte == 0) {
+            return Ok(None);
+        }
+        let text = |range: std::ops::Range<usize>| {
+            let field = &block[range];
+            let end = field
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..end]).into_owned()
+        };
+        let number = |range| u64::from_str_radix(text(range).trim(), 8);
+        let size = number(124..136).map_err(|_| invalid("invalid tar header"))?;
+        let mtime = number(136..148).unwrap_or(0);
+        let mut name = text(0..100);
+        if &block[257..262] == b"ustar" && block[345] != 0 {
+            name = format!("{}/{}", text(345..500), name);
+        }
+        match block[156] {
+            b'x' => {
+                let mut records = Vec::new();
+                (&mut *reader).take(size).read_to_end(&mut records)?;
+                skip_padding(reader, size)?;
+                long_name = String::from_utf8_lossy(&records)
+                    .lines()
+                    .find_map(|record| Some(record.split_once(" path=")?.1.to_string()));
+            }
+            b'0' | 0 | b'5' => return Ok(Some((long_name.unwrap_or(name), size, mtime))),
+            _ => {
+                io::copy(&mut (&mut *reader).take(size), &mut io::sink())?;
+                skip_padding(reader, size)?;
+                long_name = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+
+    #[test]
+    fn test_member_path() {
+        let path = |url: &str| member_path(&Url::parse(url).unwrap());
+        assert_eq!(
+            path("https://example.com/docs/a%20b.pdf?x=1"),
+            Path::new("example.com/docs/a b.pdf")
+        );
+        assert_eq!(
+            path("http://example.com:8080/site/"),
+            Path::new("example.com_8080/site/index.html")
+        );
+        assert_eq!(
+            path("https://example.com/../../etc/passwd"),
+            Path::new("example.com/etc/passwd")
+        );
+        let mut taken = HashSet::new();
+        assert_eq!(unique(path("https://a/x"), &mut taken), Path::new("a/x"));
+        assert_eq!(
+            unique(path("https://a/x?2"), &mut taken),
+            Path::new("a/x.1")
+        );
+    }
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let long = format!("/bundle/{}/report.csv", "nested".repeat(20));
+        let report = mock("GET", long.as_str())
+            .with_header("content-type", "text/csv")
+            .with_header("last-modified", "Wed, 01 Jan 2025 00:00:00 GMT")
+            .with_body("a,b\n1,2\n")
+            .create();
+        let missing = mock("GET", "/bundle/missing.bin").with_status(404).create();
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("web.bundle");
+        let urls = [
+            format!("{}{}", server_url(), long),
+            format!("{}/bundle/missing.bin", server_url()),
+        ];
+        let err = create(&out, &urls, &Client::new(), &Options::default()).unwrap_err();
+        assert_eq!(err.to_string(), "1 of 2 URLs could not be bundled");
+        assert!(!dir.path().join("web.bundle.parts").exists());
+        report.assert();
+        missing.assert();
+
+        let target = dir.path().join("target");
+        let files = extract(File::open(&out).unwrap(), &target).unwrap();
+        assert_eq!(files.len(), 1);
+        let path = target.join(&files[0].path);
+        assert!(path.ends_with("report.csv"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a,b\n1,2\n");
+        assert_eq!(
+            fs::metadata(&path).unwrap().modified().unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_735_689_600)
+        );
+
+        // A corrupted file is refused.
+        let mut bytes = fs::read(&out).unwrap();
+        let at = bytes.windows(8).rposition(|w| w == b"a,b\n1,2\n").unwrap();
+        bytes[at] = b'z';
+        let err = extract(&bytes[..], &dir.path().join("other")).unwrap_err();
+        assert!(err.to_string().ends_with("does not match its digest"));
+        assert!(extract(&b"not a bundle"[..], dir.path()).is_err());
+    }
+}
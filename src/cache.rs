@@ -0,0 +1,341 @@
+//! The download cache shared by `rustwget proxy` and `rustwget prefetch`.
+//!
+//! Responses are stored by URL in `index/`, one JSON file per URL named by
+//! the SHA-256 digest of the URL, and their bodies in `objects/`, named by
+//! their own SHA-256 digest, so that a file served under several URLs is kept
+//! once. With a size limit, the entries used least recently are removed once
+//! the cache grows past it.
+
+use crate::ledger::hex;
+use crate::paths;
+use reqwest::blocking::Response;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The number of locks requests for the same URL wait on each other with.
+const LOCKS: usize = 64;
+
+/// A cached response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub url: String,
+    /// The SHA-256 digest of the body, which names the object holding it.
+    pub digest: String,
+    pub size: u64,
+    pub content_type: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// When the server last confirmed it, in seconds since the Unix epoch.
+    pub fetched: u64,
+}
+
+/// Responses stored on disk: an index file per URL, named by the SHA-256
+/// digest of the URL, and an object file per distinct body.
+#[derive(Debug)]
+pub struct Cache {
+    dir: PathBuf,
+    max_size: Option<u64>,
+    /// Requests for a URL wait on the lock its digest picks, so that it is
+    /// fetched once however many clients ask for it at the same time.
+    locks: [Mutex<()>; LOCKS],
+    /// Held while the cache is trimmed to its size.
+    eviction: Mutex<()>,
+}
+
+impl Cache {
+    /// Opens the cache in `dir`, creating it if needed.
+    pub fn open(dir: &Path, max_size: Option<u64>) -> io::Result<Cache> {
+        for sub in ["index", "objects", "tmp"] {
+            fs::create_dir_all(dir.join(sub))?;
+        }
+        Ok(Cache {
+            dir: dir.to_path_buf(),
+            max_size,
+            locks: std::array::from_fn(|_| Mutex::new(())),
+            eviction: Mutex::new(()),
+        })
+    }
+
+    fn index_path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join("index")
+            .join(format!("{}.json", hex(&Sha256::digest(url.as_bytes()))))
+    }
+
+    pub fn object_path(&self, digest: &str) -> PathBuf {
+        self.dir.join("objects").join(&digest[..2]).join(digest)
+    }
+
+    /// Returns the lock that requests for `url` take turns with.
+    pub fn lock(&self, url: &str) -> &Mutex<()> {
+        &self.locks[Sha256::digest(url.as_bytes())[0] as usize % LOCKS]
+    }
+
+    /// Returns the entry of `url`, if its body is still stored.
+    pub fn lookup(&self, url: &str) -> Option<Entry> {
+        let index = fs::read(self.index_path(url)).ok()?;
+        let entry: Entry = serde_json::from_slice(&index).ok()?;
+        (entry.url == url && self.object_path(&entry.digest).is_file()).then_some(entry)
+    }
+
+    /// Records `entry`, marking it as used now.
+    pub fn record(&self, entry: &Entry) -> io::Result<()> {
+        let tmp = self.temporary();
+        fs::write(&tmp, serde_json::to_vec(entry)?)?;
+        fs::rename(tmp, self.index_path(&entry.url))
+    }
+
+    /// Marks the entry of `url` as used now, for eviction.
+    pub fn touch(&self, url: &str) {
+        let _ = File::options()
+            .write(true)
+            .open(self.index_path(url))
+            .and_then(|file| file.set_modified(SystemTime::now()));
+    }
+
+    /// Stores `body` as the response of `entry.url`, copying it to `out` as it
+    /// is read. The copy stops if `out` fails, but the body is still stored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `body` fails or is not `expected` bytes long, or the
+    /// cache cannot be written; nothing is stored then.
+    pub fn store<R: Read, W: Write>(
+        &self,
+        mut entry: Entry,
+        mut body: R,
+        expected: Option<u64>,
+        mut out: W,
+    ) -> io::Result<Entry> {
+        let tmp = self.temporary();
+        let stored = (|| {
+            let mut file = File::create(&tmp)?;
+            let mut hasher = Sha256::new();
+            let mut size = 0;
+            let mut copying = true;
+            let mut buffer = vec![0; 64 * 1024];
+            loop {
+                let read = body.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                file.write_all(&buffer[..read])?;
+                hasher.update(&buffer[..read]);
+                size += read as u64;
+                copying = copying && out.write_all(&buffer[..read]).is_ok();
+            }
+            if expected.is_some_and(|expected| expected != size) {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the body ended early",
+                ));
+            }
+            let _ = out.flush();
+            Ok((hex(&hasher.finalize()), size))
+        })();
+        let (digest, size) = match stored {
+            Ok(stored) => stored,
+            Err(err) => {
+                let _ = fs::remove_file(&tmp);
+                return Err(err);
+            }
+        };
+        let object = self.object_path(&digest);
+        if object.is_file() {
+            // The same body is already stored for another URL.
+            fs::remove_file(&tmp)?;
+        } else {
+            fs::create_dir_all(object.parent().unwrap())?;
+            fs::rename(&tmp, &object)?;
+        }
+        entry.digest = digest;
+        entry.size = size;
+        self.record(&entry)?;
+        self.evict()?;
+        Ok(entry)
+    }
+
+    /// Removes the entries used least recently, and then the bodies no entry
+    /// refers to, until the cache fits in its size.
+    fn evict(&self) -> io::Result<()> {
+        let Some(max_size) = self.max_size else {
+            return Ok(());
+        };
+        let _eviction = self.eviction.lock().unwrap_or_else(|e| e.into_inner());
+        let mut entries = Vec::new();
+        for file in fs::read_dir(self.dir.join("index"))? {
+            let path = file?.path();
+            let used = fs::metadata(&path).and_then(|meta| meta.modified());
+            let entry = fs::read(&path)
+                .ok()
+                .and_then(|index| serde_json::from_slice::<Entry>(&index).ok());
+            if let (Ok(used), Some(entry)) = (used, entry) {
+                entries.push((used, path, entry));
+            }
+        }
+        // Each body counts once, however many entries refer to it.
+        let mut references = HashMap::new();
+        let mut sizes = HashMap::new();
+        for (_, _, entry) in &entries {
+            *references.entry(entry.digest.clone()).or_insert(0) += 1;
+            sizes.insert(entry.digest.clone(), entry.size);
+        }
+        let mut size: u64 = sizes.values().sum();
+        entries.sort_by_key(|(used, _, _)| *used);
+        for (_, path, entry) in entries {
+            if size <= max_size {
+                break;
+            }
+            fs::remove_file(path)?;
+            let count = references.get_mut(&entry.digest).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                let _ = fs::remove_file(self.object_path(&entry.digest));
+                size -= entry.size;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a fresh path in the cache's temporary directory.
+    fn temporary(&self) -> PathBuf {
+        let mut name = [0; 16];
+        let _ = getrandom::getrandom(&mut name);
+        self.dir.join("tmp").join(hex(&name))
+    }
+}
+
+/// Returns the directory of the cache when none is given.
+pub fn default_dir() -> PathBuf {
+    paths::cache_dir().join("proxy")
+}
+
+impl Entry {
+    /// Describes the `response` for `url`, whose body is yet to be stored.
+    pub fn new(url: &str, response: &Response) -> Entry {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Entry {
+            url: url.to_string(),
+            digest: String::new(),
+            size: 0,
+            content_type: header("content-type"),
+            etag: header("etag"),
+            last_modified: header("last-modified"),
+            fetched: now(),
+        }
+    }
+
+    /// Adds the headers that ask the server whether the entry is still
+    /// current to `headers`.
+    pub fn revalidate(&self, headers: &mut HeaderMap) {
+        for (name, value) in [
+            ("if-none-match", &self.etag),
+            ("if-modified-since", &self.last_modified),
+        ] {
+            if let Some(value) = value.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+/// Whether `response`, to a `GET` without credentials or cookies, may be
+/// stored and served to every client.
+pub fn storable(response: &Response) -> bool {
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let cache_control = header("cache-control")
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    response.status() == StatusCode::OK
+        && !cache_control.contains("no-store")
+        && !cache_control.contains("private")
+        && header("set-cookie").is_none()
+        && header("vary").is_none_or(|vary| {
+            vary.split(',')
+                .all(|name| name.trim().eq_ignore_ascii_case("accept-encoding"))
+        })
+}
+
+/// The current time in seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn entry(url: &str) -> Entry {
+        Entry {
+            url: url.to_string(),
+            digest: String::new(),
+            size: 0,
+            content_type: None,
+            etag: None,
+            last_modified: None,
+            fetched: now(),
+        }
+    }
+
+    #[test]
+    fn test_cache_stores_and_evicts() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::open(dir.path(), Some(10)).unwrap();
+        let mut copied = Vec::new();
+        let stored = cache
+            .store(entry("http://a/1"), &b"123456"[..], Some(6), &mut copied)
+            .unwrap();
+        assert_eq!(copied, b"123456");
+        assert_eq!(cache.lookup("http://a/1"), Some(stored.clone()));
+        assert!(cache
+            .store(entry("http://a/short"), &b"12"[..], Some(6), io::sink())
+            .is_err());
+        assert_eq!(cache.lookup("http://a/short"), None);
+
+        // The same body under another URL is stored once.
+        cache
+            .store(entry("http://a/2"), &b"123456"[..], None, io::sink())
+            .unwrap();
+        assert_eq!(fs::read_dir(dir.path().join("objects")).unwrap().count(), 1);
+
+        // Used least recently, the body of the first two goes.
+        let past = SystemTime::now() - Duration::from_secs(60);
+        for url in ["http://a/1", "http://a/2"] {
+            File::options()
+                .write(true)
+                .open(cache.index_path(url))
+                .unwrap()
+                .set_modified(past)
+                .unwrap();
+        }
+        cache
+            .store(entry("http://a/3"), &b"abcdef"[..], None, io::sink())
+            .unwrap();
+        assert_eq!(cache.lookup("http://a/1"), None);
+        assert_eq!(cache.lookup("http://a/2"), None);
+        assert!(cache.lookup("http://a/3").is_some());
+    }
+}
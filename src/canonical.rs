@@ -0,0 +1,121 @@
+//! Canonical forms of URLs.
+//!
+//! Links to the same page often differ only in tracking parameters
+//! (`?utm_source=feed`), in the order of their query parameters, or in their
+//! fragment. `--strip-query-params LIST` drops the query parameters whose
+//! names match one of the comma-separated patterns, where `*` and `?` are
+//! wildcards, and `--canonicalize` sorts the remaining parameters by name and
+//! drops fragments, so that such variants count as one URL: they are fetched
+//! once, checked once by `rustwget check`, and named alike. Host names are
+//! always compared in lowercase and without their scheme's default port.
+
+use crate::crawl;
+use url::Url;
+
+/// How URLs are brought to their canonical form.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rules {
+    /// Patterns of the names of query parameters to drop, such as `utm_*`.
+    pub strip: Vec<String>,
+    /// Whether query parameters are sorted by name and fragments dropped.
+    pub sort: bool,
+}
+
+impl Rules {
+    /// Parses a comma-separated list of parameter names, as given to
+    /// `--strip-query-params`.
+    pub fn patterns(list: &str) -> Vec<String> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(str::to_ascii_lowercase)
+            .collect()
+    }
+
+    /// Whether the rules change any URL.
+    pub fn is_active(&self) -> bool {
+        self.sort || !self.strip.is_empty()
+    }
+
+    /// Returns the canonical form of `url`.
+    ///
+    /// Parameters keep their encoding, and those with the same name their
+    /// order.
+    pub fn apply(&self, url: &Url) -> Url {
+        let mut url = url.clone();
+        if self.sort {
+            url.set_fragment(None);
+        }
+        let Some(query) = url.query() else {
+            return url;
+        };
+        let name = |pair: &str| {
+            let name = pair.split('=').next().unwrap_or_default();
+            percent_encoding::percent_decode_str(&name.replace('+', " "))
+                .decode_utf8_lossy()
+                .to_ascii_lowercase()
+        };
+        let mut pairs: Vec<&str> = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .filter(|pair| {
+                let name = name(pair);
+                !self
+                    .strip
+                    .iter()
+                    .any(|pattern| crawl::wildcard(pattern, &name))
+            })
+            .collect();
+        if self.sort {
+            pairs.sort_by_key(|pair| name(pair));
+        }
+        let query = pairs.join("&");
+        url.set_query(
+            Some(&query)
+                .filter(|query| !query.is_empty())
+                .map(String::as_str),
+        );
+        url
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let url = |text| Url::parse(text).unwrap();
+        let rules = Rules {
+            strip: Rules::patterns("utm_*, fbclid,"),
+            sort: false,
+        };
+        assert_eq!(rules.strip, ["utm_*", "fbclid"]);
+        assert_eq!(
+            rules.apply(&url(
+                "https://Example.COM:443/a?utm_source=x&b=2&FBCLID=1&a=1#top"
+            )),
+            url("https://example.com/a?b=2&a=1#top")
+        );
+        assert_eq!(
+            rules
+                .apply(&url("https://example.com/a?utm_medium=feed"))
+                .as_str(),
+            "https://example.com/a"
+        );
+
+        let rules = Rules {
+            sort: true,
+            ..rules
+        };
+        assert_eq!(
+            rules
+                .apply(&url(
+                    "https://example.com/a?q=a%20b&b=2&utm_id=3&a=1&a=0#top"
+                ))
+                .as_str(),
+            "https://example.com/a?a=1&a=0&b=2&q=a%20b"
+        );
+        assert!(!Rules::default().is_active());
+    }
+}
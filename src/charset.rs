@@ -0,0 +1,104 @@
+//! Decoding pages that are not in UTF-8.
+//!
+//! Links are searched for in text, so pages in legacy encodings such as
+//! Windows-1252 or Shift_JIS must be decoded first, or their links may be
+//! missed or corrupted. The encoding is taken, as browsers do, from a byte
+//! order mark, then the `charset` of the `Content-Type` header, then an
+//! `@charset` rule or a `<meta>` tag near the start of the document, and is
+//! UTF-8 when none says otherwise.
+
+use encoding_rs::{Encoding, UTF_8};
+use std::borrow::Cow;
+
+/// How far into a document `<meta>` tags and `@charset` rules are looked for.
+const PRESCAN: usize = 1024;
+
+/// Decodes `bytes`, served with the `Content-Type` `content_type`, to text.
+///
+/// Bytes that are invalid in the encoding become U+FFFD.
+pub fn decode<'a>(bytes: &'a [u8], content_type: Option<&str>) -> Cow<'a, str> {
+    // Strips the byte order mark, if any.
+    detect(bytes, content_type).decode(bytes).0
+}
+
+/// Returns the encoding of `bytes`, served with the `Content-Type` `content_type`.
+pub fn detect(bytes: &[u8], content_type: Option<&str>) -> &'static Encoding {
+    Encoding::for_bom(bytes)
+        .map(|(encoding, _)| encoding)
+        .or_else(|| content_type.and_then(header_charset))
+        .or_else(|| sniff(&bytes[..bytes.len().min(PRESCAN)]))
+        .unwrap_or(UTF_8)
+}
+
+/// Returns the encoding named by the `charset` parameter of a `Content-Type`.
+fn header_charset(content_type: &str) -> Option<&'static Encoding> {
+    content_type.split(';').skip(1).find_map(|parameter| {
+        let (name, value) = parameter.split_once('=')?;
+        match name.trim().eq_ignore_ascii_case("charset") {
+            true => Encoding::for_label(value.trim().trim_matches('"').as_bytes()),
+            false => None,
+        }
+    })
+}
+
+/// Returns the encoding named by `<meta charset="...">`, `<meta
+/// http-equiv="Content-Type" content="...; charset=...">`, or `@charset "...";`
+/// in the start of a document.
+fn sniff(head: &[u8]) -> Option<&'static Encoding> {
+    // Encoding declarations are in ASCII in every encoding they may name.
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+    let encoding = match head.strip_prefix("@charset \"") {
+        Some(rest) => Encoding::for_label(rest.split('"').next()?.as_bytes()),
+        None => head.match_indices("<meta").find_map(|(index, _)| {
+            let tag = head[index..].split('>').next()?;
+            let (_, value) = tag.split_once("charset")?;
+            let value = value.trim_start().strip_prefix('=')?.trim_start();
+            let label = value
+                .trim_start_matches(['"', '\''])
+                .split(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | ';' | '/'))
+                .next()?;
+            Encoding::for_label(label.as_bytes())
+        }),
+    }?;
+    // A document that declares UTF-16 in ASCII is not in UTF-16.
+    Some(encoding.output_encoding())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        // `<a href="café.html">` in Windows-1252.
+        let latin = b"<a href=\"caf\xe9.html\">";
+        assert_eq!(
+            decode(latin, Some("text/html; charset=ISO-8859-1")),
+            "<a href=\"café.html\">"
+        );
+        let meta = [b"<meta charset=\"windows-1252\">".as_slice(), latin].concat();
+        assert!(decode(&meta, Some("text/html")).ends_with("café.html\">"));
+        let http_equiv = [
+            b"<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=shift_jis\">"
+                .as_slice(),
+            b"<a href=\"\x93\xfa\x96\x7b.html\">",
+        ]
+        .concat();
+        assert!(decode(&http_equiv, None).ends_with("日本.html\">"));
+        assert_eq!(
+            decode(
+                b"@charset \"iso-8859-1\"; a { background: url(\xe9.png) }",
+                None
+            ),
+            "@charset \"iso-8859-1\"; a { background: url(é.png) }"
+        );
+        assert_eq!(
+            decode(
+                "\u{feff}<p>é</p>".as_bytes(),
+                Some("text/html; charset=latin1")
+            ),
+            "<p>é</p>"
+        );
+        assert_eq!(decode(b"plain", None), "plain");
+    }
+}
@@ -0,0 +1,741 @@
+//! Finding broken links on a site.
+//!
+//! `rustwget check URL` [`crawl`]s the site at `URL` within `--level`,
+//! `--no-parent`, and `--include-directories`/`--exclude-directories`,
+//! fetching each of its pages and stylesheets and verifying every link they
+//! hold, on the site or elsewhere, with a `HEAD` request (see [`info`]). At
+//! the end it lists the links that failed or answered with an error status,
+//! together with the pages that link to them, and exits with an error if
+//! there were any. `--json` prints the same as JSON. With `--render`, the
+//! links of pages are read after their scripts have run (see [`render`](crate::render)).
+//! `--strip-query-params` and `--canonicalize` check links that differ only
+//! in tracking parameters or fragments once (see [`canonical`]).
+//! Pages are asked for compressed, and decoded from the encoding they declare
+//! before their links are read (see [`charset`]).
+//!
+//! With `--state FILE`, the links still to check, those already seen, and
+//! the broken ones found so far are saved to `FILE` as the check goes, so
+//! that a check of a large site that is interrupted continues where it
+//! stopped when run again with the same URL and `FILE`.
+//!
+//! Requests are spaced by the `Crawl-delay` of the site's `robots.txt` (see
+//! [`robots`]), or by `--wait SECONDS`, and identify rustwget in their
+//! `User-Agent`, with `--from` adding a contact address as the `From` header.
+//! `--max-urls`, `--max-bytes`, and `--max-time` stop a run early, leaving the
+//! rest to a later run with `--state`.
+
+use crate::auth::Keyring;
+use crate::canonical;
+use crate::charset;
+use crate::config;
+use crate::crawl::{self, Frontier, Pace, Scope};
+use crate::decompress::Format;
+use crate::download::Options;
+use crate::hosts::Hosts;
+use crate::info;
+use crate::logfile;
+use crate::redirect;
+use crate::render::Browser;
+use crate::robots;
+use crate::units;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use reqwest::blocking::{Client, ClientBuilder};
+use reqwest::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, FROM,
+};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Largest part of a page that is searched for links.
+const MAX_PAGE: u64 = 16 * 1024 * 1024;
+
+/// Content encodings pages are asked for in, all of which [`Format`] decodes.
+const ENCODINGS: &str = "gzip, zstd";
+
+/// Number of links checked between saves of the state.
+pub const SAVE_INTERVAL: usize = 25;
+
+/// Defines the `check` subcommand.
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("check")
+        .about("Crawl a site and report its broken links")
+        .arg(
+            Arg::with_name("URL")
+                .help("The page to start from")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("level")
+                .long("level")
+                .short("l")
+                .value_name("N")
+                .help("Follow links at most N pages deep, 0 for no limit [default: 5]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-parent")
+                .long("no-parent")
+                .help("Only follow links below the start page's directory"),
+        )
+        .arg(
+            Arg::with_name("include-directories")
+                .long("include-directories")
+                .short("I")
+                .value_name("LIST")
+                .help("Only follow links in these comma-separated directories, where * and ? are wildcards")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("exclude-directories")
+                .long("exclude-directories")
+                .short("X")
+                .value_name("LIST")
+                .help("Do not follow links in these comma-separated directories, where * and ? are wildcards")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("strip-query-params")
+                .long("strip-query-params")
+                .value_name("LIST")
+                .help("Drop the query parameters with these comma-separated names, where * and ? are wildcards, from links")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("canonicalize")
+                .long("canonicalize")
+                .help("Sort the query parameters of links and drop their fragments, so that variants are checked once"),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Print the report as JSON"),
+        )
+        .arg(
+            Arg::with_name("render")
+                .long("render")
+                .help("Read the links of pages after their scripts have run, in a headless Chromium"),
+        )
+        .arg(
+            Arg::with_name("browser")
+                .long("browser")
+                .value_name("PATH")
+                .help("Chromium to render pages with [default: chromium, google-chrome, ... on the PATH]")
+                .requires("render")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("wait")
+                .long("wait")
+                .value_name("SECONDS")
+                .help("Wait this long between requests, instead of the Crawl-delay of robots.txt")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("from")
+                .long("from")
+                .value_name("EMAIL")
+                .help("Send this contact address in the From header")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-urls")
+                .long("max-urls")
+                .value_name("N")
+                .help("Stop after checking N links")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-bytes")
+                .long("max-bytes")
+                .value_name("SIZE")
+                .help("Stop after reading SIZE of pages (e.g. 500M)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-time")
+                .long("max-time")
+                .value_name("SECONDS")
+                .help("Stop after running this long")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("state")
+                .long("state")
+                .value_name("FILE")
+                .help("Save the progress of the check to FILE, and carry on from it if it was interrupted")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Read host settings from this TOML file")
+                .takes_value(true),
+        )
+}
+
+/// A link that does not work.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Broken {
+    pub url: String,
+    pub error: String,
+    /// The pages that link to it.
+    pub referrers: Vec<String>,
+}
+
+/// What a check found.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Report {
+    /// Number of links checked, the start page included.
+    pub checked: usize,
+    pub broken: Vec<Broken>,
+    /// Number of links left to check when the run stopped early.
+    pub unchecked: usize,
+}
+
+impl Report {
+    /// Formats the report for the terminal.
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Checked {} links: {} broken\n",
+            self.checked,
+            self.broken.len()
+        );
+        for broken in &self.broken {
+            summary += &format!("  {} ({})\n", broken.url, broken.error);
+            for referrer in &broken.referrers {
+                summary += &format!("    linked from {}\n", referrer);
+            }
+        }
+        if self.unchecked != 0 {
+            summary += &format!("{} links left unchecked\n", self.unchecked);
+        }
+        summary
+    }
+}
+
+/// The progress of a check, which `--state FILE` keeps on disk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    start: Url,
+    frontier: Frontier,
+    checked: usize,
+    /// The links that did not work so far, with what went wrong.
+    failures: Vec<(Url, String)>,
+    /// The pages that link to each link found so far.
+    referrers: HashMap<Url, Vec<String>>,
+    /// Bytes of pages read in this run, which is not saved.
+    #[serde(skip)]
+    bytes: u64,
+}
+
+impl State {
+    /// Starts a check at `start`.
+    pub fn new(start: Url) -> State {
+        State {
+            frontier: Frontier::new(start.clone()),
+            start,
+            checked: 0,
+            failures: Vec::new(),
+            referrers: HashMap::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Reads the state of an earlier check from `path`, if there is one that
+    /// started at `start`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or is not a state.
+    pub fn load(path: &Path, start: &Url) -> Result<Option<State>, Box<dyn std::error::Error>> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(format!("cannot read {}: {}", path.display(), err).into()),
+        };
+        let state: State = serde_json::from_str(&text)
+            .map_err(|err| format!("invalid check state {}: {}", path.display(), err))?;
+        Ok((state.start == *start).then_some(state))
+    }
+
+    /// Writes the state to `path`, replacing it in one step.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Checks the next link, returning `false` once there are none left.
+    fn step(
+        &mut self,
+        client: &Client,
+        scope: &Scope,
+        options: &Options,
+        browser: Option<&Browser>,
+    ) -> bool {
+        let Some(link) = self.frontier.pop() else {
+            return false;
+        };
+        self.checked += 1;
+        logfile::detail(&format!("Checking: {}", link.url));
+        let result = match scope.follows(&link.url, link.depth) {
+            true => visit(client, &link.url, scope, options, browser),
+            false => verify(client, &link.url, options).map(|()| (Vec::new(), 0)),
+        };
+        match result {
+            Ok((links, bytes)) => {
+                self.bytes += bytes;
+                for url in links {
+                    let url = scope.canonical.apply(&url);
+                    let from = self.referrers.entry(url.clone()).or_default();
+                    if !from.contains(&link.url.to_string()) {
+                        from.push(link.url.to_string());
+                    }
+                    self.frontier.push(url, link.depth + 1);
+                }
+            }
+            Err(error) => self.failures.push((link.url, error)),
+        }
+        true
+    }
+
+    /// Returns what the check found.
+    pub fn report(mut self) -> Report {
+        Report {
+            checked: self.checked,
+            broken: self
+                .failures
+                .into_iter()
+                .map(|(url, error)| Broken {
+                    referrers: self.referrers.remove(&url).unwrap_or_default(),
+                    url: url.into(),
+                    error,
+                })
+                .collect(),
+            unchecked: self.frontier.len(),
+        }
+    }
+}
+
+/// Crawls the pages within `scope`, starting from `start`, and checks every link.
+///
+/// Requests are spaced by `pace.wait`, and the run stops once it has spent
+/// any of the budget of `pace`. With `state`, the progress is saved to that
+/// file every [`SAVE_INTERVAL`] links and when the run stops early, and a
+/// check interrupted earlier carries on from it. The file is removed once the
+/// check is complete.
+///
+/// # Errors
+///
+/// Returns an error if the state cannot be read or saved.
+pub fn check(
+    client: &Client,
+    start: Url,
+    scope: &Scope,
+    options: &Options,
+    browser: Option<&Browser>,
+    pace: &Pace,
+    state: Option<&Path>,
+) -> Result<Report, Box<dyn std::error::Error>> {
+    let mut progress = match state
+        .map(|path| State::load(path, &start))
+        .transpose()?
+        .flatten()
+    {
+        Some(progress) => {
+            logfile::info(&format!(
+                "Resuming check of {}: {} links checked, {} to go",
+                start,
+                progress.checked,
+                progress.frontier.len()
+            ));
+            progress
+        }
+        None => State::new(start),
+    };
+    let started = Instant::now();
+    let mut visited = 0;
+    loop {
+        let left = progress.frontier.len();
+        if left == 0 {
+            break;
+        }
+        if let Some(limit) = pace.spent(visited, progress.bytes, started.elapsed()) {
+            logfile::warning(&format!(
+                "Stopping the check at {}: {} links left to check",
+                limit, left
+            ));
+            break;
+        }
+        if visited != 0 {
+            thread::sleep(pace.wait);
+        }
+        if !progress.step(client, scope, options, browser) {
+            break;
+        }
+        visited += 1;
+        if let Some(path) = state.filter(|_| progress.checked % SAVE_INTERVAL == 0) {
+            progress.save(path)?;
+        }
+    }
+    if let Some(path) = state {
+        match progress.frontier.len() {
+            0 => {
+                let _ = fs::remove_file(path);
+            }
+            _ => progress.save(path)?,
+        }
+    }
+    Ok(progress.report())
+}
+
+/// Fetches the page at `url` and returns its links, if it is an HTML page or
+/// a stylesheet on the site, with the number of bytes read.
+///
+/// With `browser`, the links of an HTML page are read from its rendering.
+fn visit(
+    client: &Client,
+    url: &Url,
+    scope: &Scope,
+    options: &Options,
+    browser: Option<&Browser>,
+) -> Result<(Vec<Url>, u64), String> {
+    let response = options
+        .send(client, url.as_str(), |client| {
+            client.get(url.as_str()).header(ACCEPT_ENCODING, ENCODINGS)
+        })
+        .map_err(|err| err.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status()));
+    }
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let content_type = header(CONTENT_TYPE).unwrap_or_default();
+    let encoding = header(CONTENT_ENCODING);
+    let html = content_type.starts_with("text/html");
+    let extract = match content_type.as_str() {
+        _ if html => crawl::links,
+        css if css.starts_with("text/css") => crawl::css_links,
+        _ => return Ok((Vec::new(), 0)),
+    };
+    // A redirect may lead off the site, whose pages are not followed.
+    let base = response.url().clone();
+    if !scope.is_internal(&base) {
+        return Ok((Vec::new(), 0));
+    }
+    if let Some(browser) = browser.filter(|_| html) {
+        let page = browser.render(base.as_str())?;
+        return Ok((crawl::links(&page, &base), page.len() as u64));
+    }
+    let mut body = Vec::new();
+    response
+        .take(MAX_PAGE)
+        .read_to_end(&mut body)
+        .map_err(|err| err.to_string())?;
+    let page = match encoding
+        .as_deref()
+        .map(|name| (name, Format::from_encoding(name)))
+    {
+        None | Some(("identity", _)) => Cow::Borrowed(body.as_slice()),
+        Some((_, Some(format))) => {
+            let mut page = Page(Vec::new());
+            format
+                .decode(body.as_slice(), &mut page)
+                .map_err(|err| format!("cannot decompress: {}", err))?;
+            Cow::Owned(page.0)
+        }
+        Some((name, None)) => return Err(format!("unsupported Content-Encoding '{}'", name)),
+    };
+    let links = extract(&charset::decode(&page, Some(&content_type)), &base);
+    Ok((links, body.len() as u64))
+}
+
+/// A decompressed page, of which only the first [`MAX_PAGE`] bytes are kept.
+struct Page(Vec<u8>);
+
+impl Write for Page {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = MAX_PAGE as usize - self.0.len();
+        self.0.extend_from_slice(&buf[..buf.len().min(room)]);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Checks that `url` answers with a success status.
+fn verify(client: &Client, url: &Url, options: &Options) -> Result<(), String> {
+    let info = info::fetch(client, url.as_str(), options).map_err(|err| err.to_string())?;
+    match reqwest::StatusCode::from_u16(info.status) {
+        Ok(status) if status.is_success() => Ok(()),
+        Ok(status) => Err(format!("HTTP {}", status)),
+        Err(_) => Err(format!("HTTP {}", info.status)),
+    }
+}
+
+/// Runs the `check` subcommand.
+///
+/// # Errors
+///
+/// Returns an error if the arguments or configuration are invalid, or if any
+/// link is broken.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let canonical = canonical::Rules {
+        strip: matches
+            .value_of("strip-query-params")
+            .map_or_else(Vec::new, canonical::Rules::patterns),
+        sort: matches.is_present("canonicalize"),
+    };
+    let start = canonical.apply(&Url::parse(matches.value_of("URL").unwrap())?);
+    let level = matches
+        .value_of("level")
+        .map_or(Ok(crawl::DEFAULT_LEVEL), str::parse)?;
+    let mut scope = Scope::new(start.clone(), level, matches.is_present("no-parent"));
+    scope.include = matches
+        .value_of("include-directories")
+        .map_or_else(Vec::new, crawl::directories);
+    scope.exclude = matches
+        .value_of("exclude-directories")
+        .map_or_else(Vec::new, crawl::directories);
+    scope.canonical = canonical;
+    let browser = match matches.is_present("render") {
+        true => Some(Browser::find(matches.value_of("browser").map(Path::new))?),
+        false => None,
+    };
+    let mut headers = HeaderMap::new();
+    if let Some(from) = matches.value_of("from") {
+        let from = HeaderValue::from_str(from).map_err(|_| format!("invalid --from '{}'", from))?;
+        headers.insert(FROM, from);
+    }
+    let config = config::load(matches.value_of("config").map(Path::new))?;
+    let builder = || -> Result<ClientBuilder, Box<dyn std::error::Error>> {
+        Ok(Client::builder()
+            .redirect(redirect::policy(redirect::DEFAULT_MAX, false))
+            .user_agent(concat!("rustwget/", env!("CARGO_PKG_VERSION")))
+            .default_headers(headers.clone()))
+    };
+    let options = Options {
+        keyring: Some(Arc::new(Keyring::new(config.oauth.clone()))),
+        hosts: Arc::new(Hosts::new(&config.host, builder)?),
+        ..Options::default()
+    };
+    let client = builder()?.build()?;
+
+    let wait = match matches.value_of("wait") {
+        Some(wait) => wait
+            .parse::<f64>()
+            .ok()
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .ok_or_else(|| format!("invalid --wait '{}'", wait))?,
+        None => {
+            let delay = robots::fetch(&client, &start, &options).unwrap_or_default();
+            if !delay.is_zero() {
+                logfile::detail(&format!(
+                    "Waiting {:.1}s between requests, as robots.txt asks",
+                    delay.as_secs_f64()
+                ));
+            }
+            delay
+        }
+    };
+    let pace = Pace {
+        wait,
+        max_urls: matches.value_of("max-urls").map(str::parse).transpose()?,
+        max_bytes: matches
+            .value_of("max-bytes")
+            .map(units::parse_size)
+            .transpose()?,
+        max_time: matches
+            .value_of("max-time")
+            .map(str::parse)
+            .transpose()?
+            .map(Duration::from_secs),
+    };
+
+    let report = check(
+        &client,
+        start,
+        &scope,
+        &options,
+        browser.as_ref(),
+        &pace,
+        matches.value_of("state").map(Path::new),
+    )?;
+    if matches.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        logfile::info(report.summary().trim_end());
+    }
+    match report.broken.len() {
+        0 => Ok(()),
+        broken => Err(format!("{} broken links", broken).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+
+    #[test]
+    fn test_check_reports_broken_links_with_referrers() {
+        let html = |body| mock("GET", body).with_header("content-type", "text/html; charset=utf-8");
+        let index = html("/check/index.html")
+            .with_body(r#"<a href="a.html">A</a> <a href="gone.html">Gone</a> <a href="/outside.zip">Zip</a>"#)
+            .create();
+        let a = html("/check/a.html")
+            .with_body(r#"<a href="index.html">Home</a> <img src="gone.html">"#)
+            .create();
+        let gone = mock("GET", "/check/gone.html").with_status(404).create();
+        let outside = mock("HEAD", "/outside.zip").create();
+
+        let start = Url::parse(&format!("{}/check/index.html", server_url())).unwrap();
+        let scope = Scope::new(start.clone(), crawl::DEFAULT_LEVEL, true);
+        let report = check(
+            &Client::new(),
+            start,
+            &scope,
+            &Options::default(),
+            None,
+            &Pace::default(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(report.checked, 4);
+        assert_eq!(report.broken.len(), 1);
+        let broken = &report.broken[0];
+        assert!(broken.url.ends_with("/check/gone.html"));
+        assert_eq!(broken.error, "HTTP 404 Not Found");
+        assert_eq!(broken.referrers.len(), 2);
+        assert!(report.summary().contains("linked from"));
+        for mock in [index, a, gone, outside] {
+            mock.assert();
+        }
+    }
+
+    #[test]
+    fn test_check_resumes_from_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("check.json");
+        let start = Url::parse(&format!("{}/resume/index.html", server_url())).unwrap();
+        let broken = Url::parse(&format!("{}/resume/gone.html", server_url())).unwrap();
+
+        // An earlier check found one broken link and left one to check.
+        let mut state = State::new(start.clone());
+        state.frontier.pop();
+        state.frontier.push(start.join("next.html").unwrap(), 1);
+        state.checked = 1;
+        state
+            .failures
+            .push((broken, "HTTP 404 Not Found".to_string()));
+        state.save(&path).unwrap();
+        let other = Url::parse("https://example.com/").unwrap();
+        assert!(State::load(&path, &other).unwrap().is_none());
+
+        let next = mock("GET", "/resume/next.html").create();
+        let scope = Scope::new(start.clone(), crawl::DEFAULT_LEVEL, false);
+        let report = check(
+            &Client::new(),
+            start,
+            &scope,
+            &Options::default(),
+            None,
+            &Pace::default(),
+            Some(&path),
+        )
+        .unwrap();
+        assert_eq!(report.checked, 2);
+        assert_eq!(report.broken.len(), 1);
+        assert!(!path.exists());
+        next.assert();
+    }
+
+    #[test]
+    fn test_check_decodes_compressed_legacy_pages() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        // `<a href="café.html">` in Windows-1252, gzipped.
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(b"<a href=\"caf\xe9.html\">Caf\xe9</a>")
+            .unwrap();
+        let index = mock("GET", "/legacy/index.html")
+            .match_header("accept-encoding", ENCODINGS)
+            .with_header("content-type", "text/html; charset=windows-1252")
+            .with_header("content-encoding", "gzip")
+            .with_body(gzip.finish().unwrap())
+            .create();
+        let cafe = mock("GET", "/legacy/caf%C3%A9.html").create();
+
+        let start = Url::parse(&format!("{}/legacy/index.html", server_url())).unwrap();
+        let scope = Scope::new(start.clone(), crawl::DEFAULT_LEVEL, false);
+        let report = check(
+            &Client::new(),
+            start,
+            &scope,
+            &Options::default(),
+            None,
+            &Pace::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!((report.checked, report.broken.len()), (2, 0));
+        index.assert();
+        cafe.assert();
+    }
+
+    #[test]
+    fn test_check_stops_when_budget_is_spent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("check.json");
+        let index = mock("GET", "/budget/index.html")
+            .with_header("content-type", "text/html")
+            .with_body(r#"<a href="a.html">A</a> <a href="b.html">B</a>"#)
+            .create();
+
+        let start = Url::parse(&format!("{}/budget/index.html", server_url())).unwrap();
+        let scope = Scope::new(start.clone(), crawl::DEFAULT_LEVEL, false);
+        let pace = Pace {
+            max_urls: Some(1),
+            ..Pace::default()
+        };
+        let report = check(
+            &Client::new(),
+            start.clone(),
+            &scope,
+            &Options::default(),
+            None,
+            &pace,
+            Some(&path),
+        )
+        .unwrap();
+        assert_eq!((report.checked, report.unchecked), (1, 2));
+        assert!(report.summary().contains("2 links left unchecked"));
+        let state = State::load(&path, &start).unwrap().unwrap();
+        assert_eq!(state.frontier.len(), 2);
+        index.assert();
+    }
+}
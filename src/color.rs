@@ -0,0 +1,113 @@
+//! Colors for status lines on the terminal.
+//!
+//! Successes are shown in green, warnings in yellow, and errors in red. With
+//! `--color=auto`, the default, a stream is only colored when it is a
+//! terminal, `NO_COLOR` is unset or empty, and `TERM` is not `dumb`;
+//! `--color=always` and `--color=never` override that.
+
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::OnceLock;
+
+/// Names accepted by `--color`.
+pub const CHOICES: [&str; 3] = ["auto", "always", "never"];
+
+/// When to color output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Choice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl Choice {
+    /// Parses one of the [`CHOICES`].
+    pub fn parse(name: &str) -> Result<Choice, String> {
+        match name {
+            "auto" => Ok(Choice::Auto),
+            "always" => Ok(Choice::Always),
+            "never" => Ok(Choice::Never),
+            other => Err(format!(
+                "unknown color choice '{}': expected one of {}",
+                other,
+                CHOICES.join(", ")
+            )),
+        }
+    }
+}
+
+/// A color of a status line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+}
+
+impl Color {
+    fn code(self) -> u8 {
+        match self {
+            Color::Red => 31,
+            Color::Green => 32,
+            Color::Yellow => 33,
+        }
+    }
+}
+
+/// An output stream, which is colored on its own terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Whether standard output and standard error are colored, once decided.
+static ENABLED: OnceLock<[bool; 2]> = OnceLock::new();
+
+/// Decides for the rest of the run whether each stream is colored.
+pub fn init(choice: Choice) {
+    let auto = |terminal: bool| {
+        terminal
+            && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            && env::var_os("TERM").is_none_or(|term| term != "dumb")
+    };
+    let _ = ENABLED.set(match choice {
+        Choice::Always => [true, true],
+        Choice::Never => [false, false],
+        Choice::Auto => [
+            auto(io::stdout().is_terminal()),
+            auto(io::stderr().is_terminal()),
+        ],
+    });
+}
+
+/// Returns `text` in `color` if `stream` is colored.
+pub fn paint(text: &str, color: Color, stream: Stream) -> String {
+    let enabled = ENABLED.get().is_some_and(|enabled| match stream {
+        Stream::Stdout => enabled[0],
+        Stream::Stderr => enabled[1],
+    });
+    match enabled {
+        true => wrap(text, color),
+        false => text.to_string(),
+    }
+}
+
+fn wrap(text: &str, color: Color) -> String {
+    format!("\x1b[{}m{}\x1b[0m", color.code(), text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colors() {
+        assert_eq!(wrap("Failed", Color::Red), "\x1b[31mFailed\x1b[0m");
+        assert_eq!(Choice::parse("always"), Ok(Choice::Always));
+        assert!(Choice::parse("sometimes").is_err());
+        // Nothing is colored before `init` decides.
+        assert_eq!(paint("ok", Color::Green, Stream::Stdout), "ok");
+    }
+}
@@ -0,0 +1,358 @@
+//! The TOML configuration file.
+//!
+//! By default the file is read from `<config dir>/config.toml` (see
+//! [`paths::config_dir`]) when it exists; `--config FILE` selects another one.
+//!
+//! ```toml
+//! # Fetch the nightly build every day at 02:30.
+//! [[schedule]]
+//! url = "https://example.com/nightly.tar.gz"
+//! cron = "30 2 * * *"
+//! output = "nightly.tar.gz"   # optional
+//! priority = "high"           # optional
+//!
+//! # Limit all transfers to 100 KiB/s during office hours; windows may wrap
+//! # past midnight. Outside every window `--limit-rate` (or no limit) applies.
+//! [[bandwidth]]
+//! from = "09:00"
+//! to = "18:00"
+//! rate = "100k"
+//!
+//! # An OAuth provider for `rustwget auth login` (see [`oauth`](crate::oauth)).
+//! [[oauth]]
+//! name = "onedrive"
+//! device_authorization_url = "https://login.microsoftonline.com/common/oauth2/v2.0/devicecode"
+//! token_url = "https://login.microsoftonline.com/common/oauth2/v2.0/token"
+//! client_id = "00000000-0000-0000-0000-000000000000"
+//! scope = "Files.Read offline_access"
+//! hosts = ["graph.microsoft.com"]
+//!
+//! # Headers, credentials, a rate limit, and TLS settings for one host (see
+//! # [`hosts`](crate::hosts)).
+//! [host."artifacts.corp.com"]
+//! headers = { "X-Team" = "build" }
+//! user = "deploy"
+//! password_env = "ARTIFACTS_PASSWORD"
+//! rate = "2M"
+//! ca_certificate = "/etc/ssl/corp-root.pem"
+//! ```
+
+use crate::auth::AUTH_TYPES;
+use crate::input;
+use crate::oauth::Provider;
+use crate::paths;
+use crate::schedule::Cron;
+use crate::throttle::{Throttle, Window};
+use crate::units;
+use chrono::NaiveTime;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Settings loaded from the configuration file.
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// Recurring downloads queued by the daemon.
+    #[serde(default)]
+    pub schedule: Vec<Schedule>,
+    /// Time-of-day bandwidth limits.
+    #[serde(default)]
+    pub bandwidth: Vec<Bandwidth>,
+    /// OAuth providers whose tokens are sent to their hosts.
+    #[serde(default)]
+    pub oauth: Vec<Provider>,
+    /// Settings for single hosts, by name and any explicit port.
+    #[serde(default)]
+    pub host: BTreeMap<String, HostConfig>,
+}
+
+impl Config {
+    /// Converts the `[[bandwidth]]` entries into throttle windows.
+    pub fn windows(&self) -> Result<Vec<Window>, String> {
+        self.bandwidth.iter().map(Bandwidth::window).collect()
+    }
+
+    /// Builds the bandwidth limiter for a run.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit_rate`: The `--limit-rate` value, used outside every configured window.
+    pub fn throttle(&self, limit_rate: Option<u64>) -> Result<Option<Arc<Throttle>>, String> {
+        Ok(Throttle::new(limit_rate, self.windows()?).map(Arc::new))
+    }
+}
+
+/// A recurring download.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Schedule {
+    pub url: String,
+    pub cron: String,
+    pub output: Option<PathBuf>,
+    #[serde(default)]
+    pub priority: Option<String>,
+}
+
+impl Schedule {
+    /// Parses the cron expression of this schedule.
+    pub fn cron(&self) -> Result<Cron, String> {
+        Cron::parse(&self.cron)
+    }
+
+    /// Resolves the priority of this schedule, defaulting to `normal`.
+    pub fn priority(&self) -> Result<i32, String> {
+        self.priority
+            .as_deref()
+            .map_or(Ok(0), input::parse_priority)
+    }
+}
+
+/// A rate limit that applies between two times of day.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Bandwidth {
+    pub from: String,
+    pub to: String,
+    pub rate: String,
+}
+
+impl Bandwidth {
+    /// Parses this entry into a throttle window.
+    pub fn window(&self) -> Result<Window, String> {
+        let time = |value: &str| {
+            NaiveTime::parse_from_str(value, "%H:%M")
+                .map_err(|_| format!("invalid bandwidth time '{}': expected HH:MM", value))
+        };
+        Ok(Window {
+            from: time(&self.from)?,
+            to: time(&self.to)?,
+            rate: units::parse_rate(&self.rate)?,
+        })
+    }
+}
+
+/// Settings for the URLs of one host.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HostConfig {
+    /// Headers sent with every request.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub user: Option<String>,
+    /// Environment variable holding the password of `user`.
+    pub password_env: Option<String>,
+    /// One of the authentication types, `basic` by default.
+    pub auth_type: Option<String>,
+    /// File holding a bearer token, instead of a user.
+    pub token_file: Option<PathBuf>,
+    /// Rate limit shared by the transfers from the host.
+    pub rate: Option<String>,
+    /// PEM file of a certificate authority to trust.
+    pub ca_certificate: Option<PathBuf>,
+    /// Whether invalid certificates are accepted.
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+impl HostConfig {
+    /// Parses the headers of this host.
+    pub fn headers(&self) -> Result<HeaderMap, String> {
+        self.headers
+            .iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| format!("invalid header name '{}'", name))?;
+                let value = HeaderValue::from_str(value)
+                    .map_err(|_| format!("invalid value for header '{}'", name))?;
+                Ok((name, value))
+            })
+            .collect()
+    }
+
+    /// Parses the rate limit of this host.
+    pub fn rate(&self) -> Result<Option<u64>, String> {
+        self.rate.as_deref().map_or(Ok(None), units::parse_rate)
+    }
+
+    /// Checks that the credentials of this host are complete.
+    fn check(&self, host: &str) -> Result<(), String> {
+        self.headers()?;
+        self.rate()?;
+        match (&self.user, &self.password_env, &self.token_file) {
+            (Some(_), _, Some(_)) => Err(format!(
+                "host {}: user and token_file cannot both be set",
+                host
+            )),
+            (Some(_), None, _) => Err(format!(
+                "host {}: user needs password_env to name the variable holding the password",
+                host
+            )),
+            _ => match self.auth_type.as_deref() {
+                Some(auth_type) if !AUTH_TYPES.contains(&auth_type) => Err(format!(
+                    "host {}: unknown auth_type '{}': expected one of {}",
+                    host,
+                    auth_type,
+                    AUTH_TYPES.join(", ")
+                )),
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+/// Returns the path of the configuration file used when none is given.
+pub fn default_path() -> PathBuf {
+    paths::config_dir().join("config.toml")
+}
+
+/// Loads the configuration.
+///
+/// # Arguments
+///
+/// * `path`: An explicitly requested file, which must exist; `None` reads
+///   [`default_path`] if it exists and falls back to an empty configuration.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or is not valid, including
+/// invalid cron expressions and priorities in schedules.
+pub fn load(path: Option<&Path>) -> Result<Config, Box<dyn std::error::Error>> {
+    match read(path)? {
+        Some((path, text)) => {
+            parse(&text).map_err(|err| format!("{}: {}", path.display(), err).into())
+        }
+        None => Ok(Config::default()),
+    }
+}
+
+/// Reads the configuration file [`load`] would load, returning its path and
+/// text, or `None` when there is none.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read.
+pub fn read(path: Option<&Path>) -> Result<Option<(PathBuf, String)>, Box<dyn std::error::Error>> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let path = default_path();
+            if !path.exists() {
+                return Ok(None);
+            }
+            path
+        }
+    };
+    let text = fs::read_to_string(&path)
+        .map_err(|err| format!("cannot read config file {}: {}", path.display(), err))?;
+    Ok(Some((path, text)))
+}
+
+/// Parses and validates configuration text.
+pub fn parse(text: &str) -> Result<Config, String> {
+    let config: Config = toml::from_str(text).map_err(|err| err.to_string())?;
+    for schedule in &config.schedule {
+        schedule.cron()?;
+        schedule.priority()?;
+    }
+    config.windows()?;
+    for (host, settings) in &config.host {
+        settings.check(host)?;
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_schedules() {
+        let config = parse(
+            r#"
+            [[schedule]]
+            url = "https://example.com/nightly.tar.gz"
+            cron = "30 2 * * *"
+            priority = "high"
+
+            [[schedule]]
+            url = "https://example.com/weekly.iso"
+            cron = "0 3 * * 0"
+            output = "weekly.iso"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.schedule.len(), 2);
+        assert_eq!(config.schedule[0].priority(), Ok(1));
+        assert_eq!(config.schedule[1].output, Some(PathBuf::from("weekly.iso")));
+    }
+
+    #[test]
+    fn test_parse_bandwidth_windows() {
+        let config = parse(
+            r#"
+            [[bandwidth]]
+            from = "09:00"
+            to = "18:00"
+            rate = "100k"
+
+            [[bandwidth]]
+            from = "22:00"
+            to = "06:00"
+            rate = "unlimited"
+            "#,
+        )
+        .unwrap();
+
+        let windows = config.windows().unwrap();
+        assert_eq!(windows[0].rate, Some(102_400));
+        assert_eq!(windows[1].rate, None);
+
+        let err =
+            parse("[[bandwidth]]\nfrom = \"9am\"\nto = \"18:00\"\nrate = \"1k\"\n").unwrap_err();
+        assert_eq!(err, "invalid bandwidth time '9am': expected HH:MM");
+    }
+
+    #[test]
+    fn test_parse_hosts() {
+        let config = parse(
+            r#"
+            [host."artifacts.corp.com"]
+            headers = { "X-Team" = "build" }
+            user = "deploy"
+            password_env = "ARTIFACTS_PASSWORD"
+            rate = "1M"
+            insecure = true
+
+            [host."localhost:8080"]
+            token_file = "token.txt"
+            "#,
+        )
+        .unwrap();
+
+        let artifacts = &config.host["artifacts.corp.com"];
+        assert_eq!(artifacts.headers().unwrap()["x-team"], "build");
+        assert_eq!(artifacts.rate(), Ok(Some(1_048_576)));
+        assert!(artifacts.insecure);
+        assert!(!config.host["localhost:8080"].insecure);
+
+        let err = parse("[host.\"a.example\"]\nuser = \"me\"\n").unwrap_err();
+        assert!(err.contains("user needs password_env"));
+        let err = parse("[host.\"a.example\"]\nheaders = { \"Bad Name\" = \"x\" }\n").unwrap_err();
+        assert_eq!(err, "invalid header name 'Bad Name'");
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_cron() {
+        let err =
+            parse("[[schedule]]\nurl = \"https://example.com\"\ncron = \"daily\"\n").unwrap_err();
+        assert!(err.contains("invalid cron expression 'daily'"));
+    }
+
+    #[test]
+    fn test_missing_explicit_file_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = load(Some(&dir.path().join("missing.toml"))).unwrap_err();
+        assert!(err.to_string().starts_with("cannot read config file"));
+    }
+}
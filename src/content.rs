@@ -0,0 +1,221 @@
+//! Checks of what a server actually sent.
+//!
+//! `--require-content-type` and `--reject-content-type` accept or refuse a
+//! response by its `Content-Type`, with `type/*` matching a whole family.
+//! Independently of them, a response that is an HTML page although the URL
+//! names some other kind of file is most likely an error, login, or captcha
+//! page served with status 200; it is reported with a warning, or refused with
+//! `--strict`.
+//!
+//! `--accept-mime` and `--reject-mime` take the same patterns but skip the
+//! responses they do not want instead of failing them, as the files of a
+//! recursive download whose names do not tell their type: the headers of the
+//! response are read, and the body is not.
+
+/// File extensions of URLs that are expected to return HTML.
+const PAGE_EXTENSIONS: [&str; 9] = [
+    "html", "htm", "xhtml", "shtml", "php", "asp", "aspx", "jsp", "cgi",
+];
+
+/// What to accept from servers.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    /// Patterns of which a response's type must match one, if any are given.
+    pub require: Vec<String>,
+    /// Patterns of types that are refused.
+    pub reject: Vec<String>,
+    /// Whether an unexpected HTML page is an error rather than a warning.
+    pub strict: bool,
+    /// Patterns of which a response's type must match one not to be skipped, if any are given.
+    pub accept: Vec<String>,
+    /// Patterns of types whose responses are skipped.
+    pub skip: Vec<String>,
+}
+
+impl Policy {
+    /// Checks a response before its body is saved.
+    ///
+    /// # Arguments
+    ///
+    /// * `url`: The URL that was requested.
+    /// * `content_type`: The `Content-Type` header of the response, if any.
+    /// * `head`: The first bytes of the body, used to recognize HTML sent under another type.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<String>, String>`: A warning to show, or an error if the
+    ///   response must not be saved.
+    pub fn check(
+        &self,
+        url: &str,
+        content_type: Option<&str>,
+        head: &[u8],
+    ) -> Result<Option<String>, String> {
+        let mime = content_type.map(essence).unwrap_or_default();
+        if !self.require.is_empty() && !self.require.iter().any(|pattern| matches(pattern, &mime)) {
+            return Err(format!(
+                "{}: Content-Type '{}' is not one of --require-content-type {}",
+                url,
+                mime,
+                self.require.join(",")
+            ));
+        }
+        if let Some(pattern) = self.reject.iter().find(|pattern| matches(pattern, &mime)) {
+            return Err(format!(
+                "{}: Content-Type '{}' is rejected by --reject-content-type {}",
+                url, mime, pattern
+            ));
+        }
+        if expects_file(url) && (mime == "text/html" || looks_like_html(head)) {
+            let message = format!(
+                "{} returned an HTML page instead of the expected file; it may be an error or captcha page",
+                url
+            );
+            return if self.strict {
+                Err(message)
+            } else {
+                Ok(Some(message))
+            };
+        }
+        Ok(None)
+    }
+
+    /// Returns why a response with the `Content-Type` `content_type` is
+    /// skipped, if it is.
+    pub fn skips(&self, content_type: Option<&str>) -> Option<String> {
+        let mime = content_type.map(essence).unwrap_or_default();
+        if !self.accept.is_empty() && !self.accept.iter().any(|pattern| matches(pattern, &mime)) {
+            return Some(format!(
+                "Content-Type '{}' is not one of --accept-mime {}",
+                mime,
+                self.accept.join(",")
+            ));
+        }
+        self.skip
+            .iter()
+            .find(|pattern| matches(pattern, &mime))
+            .map(|pattern| {
+                format!(
+                    "Content-Type '{}' is skipped by --reject-mime {}",
+                    mime, pattern
+                )
+            })
+    }
+}
+
+/// Returns the lowercase media type of a `Content-Type` value, without parameters.
+pub fn essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// Returns whether `mime` matches `pattern`, which may end in `/*`.
+pub fn matches(pattern: &str, mime: &str) -> bool {
+    let pattern = pattern.trim().to_ascii_lowercase();
+    match pattern.strip_suffix("/*") {
+        Some(family) => mime.split_once('/').is_some_and(|(kind, _)| kind == family),
+        None => pattern == mime,
+    }
+}
+
+/// Returns whether the last path segment of `url` names a file that is not a web page.
+fn expects_file(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let name = path.rsplit('/').next().unwrap_or_default();
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !extension.is_empty() => {
+            !PAGE_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+        }
+        _ => false,
+    }
+}
+
+/// Returns whether a body starts like an HTML document.
+pub fn looks_like_html(head: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(&head[..head.len().min(512)]);
+    let text = text
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .to_ascii_lowercase();
+    text.starts_with("<!doctype html") || text.starts_with("<html") || text.starts_with("<head")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_require_and_reject() {
+        let policy = Policy {
+            require: vec!["application/zip".to_string(), "image/*".to_string()],
+            reject: vec!["image/svg+xml".to_string()],
+            ..Policy::default()
+        };
+        let check = |content_type| policy.check("https://example.com/a", Some(content_type), b"");
+
+        assert_eq!(check("application/zip"), Ok(None));
+        assert_eq!(check("Image/PNG; charset=binary"), Ok(None));
+        assert!(check("image/svg+xml").unwrap_err().contains("rejected"));
+        assert!(check("text/plain").unwrap_err().contains("not one of"));
+    }
+
+    #[test]
+    fn test_skips() {
+        let policy = Policy {
+            accept: vec!["image/*".to_string(), "application/pdf".to_string()],
+            skip: vec!["image/gif".to_string()],
+            ..Policy::default()
+        };
+
+        assert_eq!(policy.skips(Some("image/png")), None);
+        assert_eq!(policy.skips(Some("application/PDF; qs=0.9")), None);
+        assert!(policy
+            .skips(Some("image/gif"))
+            .unwrap()
+            .contains("--reject-mime"));
+        assert!(policy
+            .skips(Some("text/html"))
+            .unwrap()
+            .contains("--accept-mime"));
+        assert!(policy.skips(None).is_some());
+        assert_eq!(Policy::default().skips(None), None);
+    }
+
+    #[test]
+    fn test_html_error_page_detection() {
+        let lenient = Policy::default();
+        let strict = Policy {
+            strict: true,
+            ..Policy::default()
+        };
+        let page = b"\n<!DOCTYPE html><html><title>Access denied</title>";
+
+        assert!(lenient
+            .check("https://example.com/a.iso", Some("text/html"), b"")
+            .unwrap()
+            .is_some());
+        assert!(strict
+            .check(
+                "https://example.com/a.iso?x=1",
+                Some("application/octet-stream"),
+                page
+            )
+            .is_err());
+        assert_eq!(
+            strict.check("https://example.com/index.php", Some("text/html"), page),
+            Ok(None)
+        );
+        assert_eq!(
+            strict.check("https://example.com/docs/", Some("text/html"), page),
+            Ok(None)
+        );
+        assert_eq!(
+            strict.check("https://example.com/a.iso", None, b"\x7fELF"),
+            Ok(None)
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Converting the links of downloaded pages for browsing them offline.
+//!
+//! With `--convert-links`, once every download of a run has ended, the HTML
+//! pages and stylesheets it saved are rewritten: links to URLs that the run
+//! downloaded lead to the local files by relative paths, and other relative
+//! links are made absolute, so that they still lead to the site. Absolute
+//! links to URLs that were not downloaded are left alone. Since this happens
+//! after all transfers, a run where some downloads failed still leaves pages
+//! that work as far as their files arrived.
+//!
+//! Pages are recognized by their extension or by how they start, and are
+//! written back in the encoding they were in. Files renamed after the
+//! download, as with `--content-disposition`, cannot be linked to, so those
+//! options do not go with `--convert-links`.
+
+use crate::charset;
+use crate::content;
+use crate::crawl::{self, Reference};
+use crate::logfile;
+use crate::share;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use url::Url;
+
+/// Characters escaped in the local paths that links are rewritten to.
+const PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'\'')
+    .add(b'(')
+    .add(b')')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\');
+
+/// A kind of document whose links are converted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Html,
+    Css,
+}
+
+impl Kind {
+    /// Recognizes a page or stylesheet by the extension of `path`, or by `head`,
+    /// the start of its content.
+    fn detect(path: &Path, head: &[u8]) -> Option<Kind> {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("html" | "htm" | "xhtml" | "shtml") => Some(Kind::Html),
+            Some("css") => Some(Kind::Css),
+            _ if content::looks_like_html(head) => Some(Kind::Html),
+            _ => None,
+        }
+    }
+}
+
+/// Converts the links of the pages and stylesheets among `downloaded`, the
+/// URLs a run saved with the files it saved them to.
+///
+/// Files that cannot be converted are reported and left as they are.
+pub fn convert(downloaded: &[(String, PathBuf)]) {
+    let files: Vec<(Url, PathBuf)> = downloaded
+        .iter()
+        .filter_map(|(url, path)| {
+            let mut url = Url::parse(url).ok()?;
+            url.set_fragment(None);
+            Some((url, std::path::absolute(path).ok()?))
+        })
+        .collect();
+    let local: HashMap<Url, PathBuf> = files.iter().cloned().collect();
+    let mut converted = 0;
+    for (url, path) in &files {
+        match convert_file(url, path, &local) {
+            Ok(true) => converted += 1,
+            Ok(false) => {}
+            Err(err) => logfile::warning(&format!(
+                "Cannot convert the links of {}: {}",
+                path.display(),
+                err
+            )),
+        }
+    }
+    if converted != 0 {
+        logfile::info(&format!("Converted the links of {} files", converted));
+    }
+}
+
+/// Converts the links of the file at `path`, downloaded from `base`, if it is
+/// a page or stylesheet, returning whether it changed.
+fn convert_file(base: &Url, path: &Path, local: &HashMap<Url, PathBuf>) -> io::Result<bool> {
+    let bytes = fs::read(path)?;
+    let Some(kind) = Kind::detect(path, &bytes[..bytes.len().min(512)]) else {
+        return Ok(false);
+    };
+    let encoding = charset::detect(&bytes, None);
+    // Pages in UTF-16 have no ASCII to rewrite in place.
+    if encoding.output_encoding() != encoding {
+        return Ok(false);
+    }
+    let (text, malformed) = encoding.decode_without_bom_handling(&bytes);
+    if malformed {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("not valid {}", encoding.name()),
+        ));
+    }
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let rewritten = rewrite(&text, kind, base, dir, local);
+    if rewritten == text {
+        return Ok(false);
+    }
+    // The file is replaced rather than rewritten, as it may be a hard link
+    // to another page saved with `--dedupe-content`.
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, encoding.encode(&rewritten).0)?;
+    fs::rename(&tmp, path)?;
+    Ok(true)
+}
+
+/// Rewrites the links of `text`, a document of `kind` at `base` that is saved
+/// in the directory `dir`.
+fn rewrite(
+    text: &str,
+    kind: Kind,
+    base: &Url,
+    dir: &Path,
+    local: &HashMap<Url, PathBuf>,
+) -> String {
+    let references = match kind {
+        Kind::Html => crawl::html_references(text),
+        Kind::Css => crawl::css_references(text),
+    };
+    let mut rewritten = String::with_capacity(text.len());
+    let mut end = 0;
+    for Reference { start, text: link } in references {
+        // A `url(...)` inside an attribute already rewritten.
+        if start < end {
+            continue;
+        }
+        if let Some(converted) = convert_link(link, kind, base, dir, local) {
+            rewritten.push_str(&text[end..start]);
+            rewritten.push_str(&converted);
+            end = start + link.len();
+        }
+    }
+    rewritten.push_str(&text[end..]);
+    rewritten
+}
+
+/// Returns what `link` becomes, or `None` if it stays as it is.
+fn convert_link(
+    link: &str,
+    kind: Kind,
+    base: &Url,
+    dir: &Path,
+    local: &HashMap<Url, PathBuf>,
+) -> Option<String> {
+    let link = match kind {
+        Kind::Html => share::unescape(link),
+        Kind::Css => link.to_string(),
+    };
+    // `#section` already works within the local page.
+    if link.starts_with('#') {
+        return None;
+    }
+    let url = crawl::resolve(base, &link)?;
+    let mut resource = url.clone();
+    resource.set_fragment(None);
+    let converted = match local.get(&resource) {
+        Some(target) => match url.fragment() {
+            Some(fragment) => format!("{}#{}", relative(dir, target), fragment),
+            None => relative(dir, target),
+        },
+        None if Url::parse(&link).is_err() => url.into(),
+        None => return None,
+    };
+    Some(match kind {
+        Kind::Html => converted.replace('&', "&amp;"),
+        Kind::Css => converted,
+    })
+}
+
+/// Returns the path of the file `target` relative to the directory `dir`,
+/// both absolute, as the path of a relative URL.
+fn relative(dir: &Path, target: &Path) -> String {
+    let dir: Vec<Component> = dir.components().collect();
+    let target: Vec<Component> = target.components().collect();
+    let common = dir
+        .iter()
+        .zip(&target)
+        .take_while(|(dir, target)| dir == target)
+        .count();
+    let up = (common..dir.len()).map(|_| "..".to_string());
+    let down = target[common..].iter().map(|component| {
+        utf8_percent_encode(&component.as_os_str().to_string_lossy(), PATH).to_string()
+    });
+    up.chain(down).collect::<Vec<_>>().join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite() {
+        let base = Url::parse("https://example.com/docs/index.html").unwrap();
+        let root = std::path::absolute("site").unwrap();
+        let local = HashMap::from([
+            (
+                Url::parse("https://example.com/docs/guide.html").unwrap(),
+                root.join("guide.html"),
+            ),
+            (
+                Url::parse("https://example.com/img/logo one.png").unwrap(),
+                root.join("img").join("logo one.png"),
+            ),
+        ]);
+        let page = r##"<a href="guide.html#intro">Guide</a> <a href="#top">Top</a>
+            <img src="/img/logo%20one.png"> <a href='faq.html?a=1&amp;b=2'>FAQ</a>
+            <a href="https://other.example/">Other</a>"##;
+        assert_eq!(
+            rewrite(page, Kind::Html, &base, &root.join("docs"), &local),
+            r##"<a href="../guide.html#intro">Guide</a> <a href="#top">Top</a>
+            <img src="../img/logo%20one.png"> <a href='https://example.com/docs/faq.html?a=1&amp;b=2'>FAQ</a>
+            <a href="https://other.example/">Other</a>"##
+        );
+
+        let css = "body { background: url('../img/logo%20one.png') } @import \"print.css\";";
+        assert_eq!(
+            rewrite(css, Kind::Css, &base, &root, &local),
+            "body { background: url('img/logo%20one.png') } @import \"https://example.com/docs/print.css\";"
+        );
+    }
+
+    #[test]
+    fn test_convert_keeps_encoding() {
+        let dir = tempfile::tempdir().unwrap();
+        let page = dir.path().join("index.html");
+        let image = dir.path().join("caf\u{e9}.png");
+        // `<meta charset=latin1><img src="café.png">` in Latin-1.
+        fs::write(
+            &page,
+            b"<meta charset=latin1><img src=\"caf\xe9.png\" alt=\"\xe9\">",
+        )
+        .unwrap();
+        fs::write(&image, b"png").unwrap();
+
+        convert(&[
+            ("https://example.com/index.html".to_string(), page.clone()),
+            ("https://example.com/caf%C3%A9.png".to_string(), image),
+        ]);
+        assert_eq!(
+            fs::read(&page).unwrap(),
+            b"<meta charset=latin1><img src=\"caf%C3%A9.png\" alt=\"\xe9\">"
+        );
+    }
+}
@@ -0,0 +1,461 @@
+//! Following the links of HTML pages.
+//!
+//! A crawl starts at one page and follows the links of the pages and
+//! stylesheets it finds, breadth first, including the images, fonts, and
+//! stylesheets they need. Only pages within its [`Scope`] are
+//! followed: those on the start page's host, at most `--level` links away
+//! from it (5 by default, `0` for no limit), and with `--no-parent` only those
+//! below the start page's directory. `--include-directories` and
+//! `--exclude-directories` take comma-separated lists of directories, where
+//! `*` and `?` are wildcards, and confine the crawl to the subtrees of the
+//! first and out of those of the second. Links leading elsewhere are still
+//! reported, so that they can be checked, but not followed. Links are visited
+//! in the canonical form the scope's [`canonical::Rules`] give them.
+//!
+//! The [`Frontier`] of links still to visit can be saved and loaded with
+//! serde, so that an interrupted crawl can carry on where it stopped. Its
+//! [`Pace`] spaces out requests and ends a run early after so many links,
+//! bytes, or seconds.
+
+use crate::canonical;
+use crate::share;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+use url::Url;
+
+/// How many links away from the start page a crawl goes by default.
+pub const DEFAULT_LEVEL: usize = 5;
+
+/// Schemes of links that lead to no resource to fetch.
+const IGNORED_SCHEMES: [&str; 4] = ["mailto", "javascript", "data", "tel"];
+
+/// Which pages a crawl follows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scope {
+    start: Url,
+    /// Largest number of links between the start page and a followed page;
+    /// zero for no limit.
+    pub level: usize,
+    /// Whether only pages below the start page's directory are followed.
+    pub no_parent: bool,
+    /// Directories whose pages are followed, such as `/docs`; empty for all.
+    pub include: Vec<String>,
+    /// Directories whose pages are not followed.
+    pub exclude: Vec<String>,
+    /// How links are brought to the form they are visited in.
+    pub canonical: canonical::Rules,
+}
+
+impl Scope {
+    pub fn new(start: Url, level: usize, no_parent: bool) -> Scope {
+        Scope {
+            start,
+            level,
+            no_parent,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            canonical: canonical::Rules::default(),
+        }
+    }
+
+    /// Whether `url` belongs to the site being crawled.
+    ///
+    /// The start page always does, whatever directories are included.
+    pub fn is_internal(&self, url: &Url) -> bool {
+        let parent = &self.start.path()[..self.start.path().rfind('/').map_or(0, |i| i + 1)];
+        let directories = *url == self.start
+            || ((self.include.is_empty() || in_directories(url.path(), &self.include))
+                && !in_directories(url.path(), &self.exclude));
+        url.scheme() == self.start.scheme()
+            && url.host_str() == self.start.host_str()
+            && url.port_or_known_default() == self.start.port_or_known_default()
+            && (!self.no_parent || url.path().starts_with(parent))
+            && directories
+    }
+
+    /// Whether the links of a page `depth` links away from the start are followed.
+    pub fn follows(&self, url: &Url, depth: usize) -> bool {
+        self.is_internal(url) && (self.level == 0 || depth < self.level)
+    }
+}
+
+/// Parses a comma-separated list of directories, as given to
+/// `--include-directories`, into absolute paths without a trailing slash.
+pub fn directories(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|dir| dir.trim().trim_matches('/'))
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| format!("/{}", dir))
+        .collect()
+}
+
+/// Whether the page at `path` is in one of `directories` or below it.
+fn in_directories(path: &str, directories: &[String]) -> bool {
+    // `/a/b/page.html` is in `/a` and `/a/b`.
+    path.match_indices('/')
+        .skip(1)
+        .map(|(index, _)| &path[..index])
+        .any(|ancestor| directories.iter().any(|dir| wildcard(dir, ancestor)))
+}
+
+/// Whether `text` matches `pattern`, where `*` stands for any characters but
+/// `/` and `?` for any one of them.
+pub fn wildcard(pattern: &str, text: &str) -> bool {
+    match pattern.chars().next() {
+        None => text.is_empty(),
+        Some('*') => {
+            let rest = &pattern[1..];
+            let end = text.find('/').unwrap_or(text.len());
+            (0..=end).any(|skip| wildcard(rest, &text[skip..]))
+        }
+        Some(c) => match text.chars().next() {
+            Some(t) if t == c || (c == '?' && t != '/') => {
+                wildcard(&pattern[c.len_utf8()..], &text[t.len_utf8()..])
+            }
+            _ => false,
+        },
+    }
+}
+
+/// How fast a crawl goes, and how much it may do in one run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pace {
+    /// Time to wait between requests.
+    pub wait: Duration,
+    /// Largest number of links to visit; `None` for no limit.
+    pub max_urls: Option<usize>,
+    /// Largest number of bytes of pages to read; `None` for no limit.
+    pub max_bytes: Option<u64>,
+    /// Longest time to run for; `None` for no limit.
+    pub max_time: Option<Duration>,
+}
+
+impl Pace {
+    /// Returns which limit a run that visited `urls` links, read `bytes`
+    /// bytes, and took `elapsed` has reached, if any.
+    pub fn spent(&self, urls: usize, bytes: u64, elapsed: Duration) -> Option<&'static str> {
+        if self.max_urls.is_some_and(|max| urls >= max) {
+            Some("--max-urls")
+        } else if self.max_bytes.is_some_and(|max| bytes >= max) {
+            Some("--max-bytes")
+        } else if self.max_time.is_some_and(|max| elapsed >= max) {
+            Some("--max-time")
+        } else {
+            None
+        }
+    }
+}
+
+/// One link found on a page.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Link {
+    pub url: Url,
+    /// Number of links between the start page and this one.
+    pub depth: usize,
+}
+
+/// The links still to visit in a crawl, each visited once.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Frontier {
+    pending: VecDeque<Link>,
+    seen: HashSet<Url>,
+}
+
+impl Frontier {
+    /// Starts a crawl at `start`.
+    pub fn new(start: Url) -> Frontier {
+        let mut frontier = Frontier {
+            pending: VecDeque::new(),
+            seen: HashSet::new(),
+        };
+        frontier.push(start, 0);
+        frontier
+    }
+
+    /// Queues `url`, `depth` links away from the start, unless it has been queued before.
+    ///
+    /// Returns whether it was queued.
+    pub fn push(&mut self, url: Url, depth: usize) -> bool {
+        if !self.seen.insert(url.clone()) {
+            return false;
+        }
+        self.pending.push_back(Link { url, depth });
+        true
+    }
+
+    /// Returns the next link to visit.
+    pub fn pop(&mut self) -> Option<Link> {
+        self.pending.pop_front()
+    }
+
+    /// Returns the number of links still to visit.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Whether no links are left to visit.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Returns the links of the HTML `page` at `base`, without their fragments,
+/// in the order they appear and each once.
+///
+/// Besides `href` and `src` attributes, these are the candidates of `srcset`
+/// attributes, as in `<img>` and the `<source>`s of `<picture>`, and the
+/// [`css_links`] of `<style>` blocks and `style` attributes.
+pub fn links(page: &str, base: &Url) -> Vec<Url> {
+    let mut found = Found::new(base);
+    for reference in html_references(page) {
+        found.add(reference.start, &share::unescape(reference.text));
+    }
+    found.into_links()
+}
+
+/// Returns the links of the stylesheet `css` at `base`: its `url(...)`
+/// references, such as images and fonts, and the stylesheets it `@import`s.
+pub fn css_links(css: &str, base: &Url) -> Vec<Url> {
+    let mut found = Found::new(base);
+    for reference in css_references(css) {
+        found.add(reference.start, reference.text);
+    }
+    found.into_links()
+}
+
+/// Where a link appears in a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reference<'a> {
+    /// Byte offset of the link in the document.
+    pub start: usize,
+    /// The link as written, with HTML character references in an HTML page.
+    pub text: &'a str,
+}
+
+/// Returns the references to links in the HTML `page`, in the order they
+/// appear, as read by [`links`].
+pub fn html_references(page: &str) -> Vec<Reference<'_>> {
+    let lower = page.to_ascii_lowercase();
+    let mut references = Vec::new();
+    for name in ["href", "src", "srcset"] {
+        for (index, _) in lower.match_indices(name) {
+            if !lower[..index].ends_with(char::is_whitespace) {
+                continue;
+            }
+            let Some(value) = value(&page[index + name.len()..]) else {
+                continue;
+            };
+            match name {
+                // `small.jpg 480w, large.jpg 2x`
+                "srcset" => references.extend(
+                    value
+                        .split(',')
+                        .filter_map(|candidate| candidate.split_whitespace().next())
+                        .map(|link| reference(page, link)),
+                ),
+                _ => references.push(reference(page, value.trim())),
+            }
+        }
+    }
+    references.extend(css_references(page));
+    references.sort_by_key(|reference| reference.start);
+    references
+}
+
+/// Returns the `url(...)` and `@import "..."` references in `css`, in the
+/// order they appear.
+pub fn css_references(css: &str) -> Vec<Reference<'_>> {
+    let lower = css.to_ascii_lowercase();
+    let mut references = Vec::new();
+    for (index, _) in lower.match_indices("url(") {
+        let rest = &css[index + 4..];
+        let Some(end) = rest.find(')') else {
+            continue;
+        };
+        let link = rest[..end].trim().trim_matches(|c| c == '"' || c == '\'');
+        references.push(reference(css, link));
+    }
+    for (index, _) in lower.match_indices("@import") {
+        let rest = css[index + 7..].trim_start();
+        if let Some(quote @ ('"' | '\'')) = rest.chars().next() {
+            if let Some(link) = rest[1..].split(quote).next() {
+                references.push(reference(css, link));
+            }
+        }
+    }
+    references.retain(|reference| !reference.text.is_empty());
+    references.sort_by_key(|reference| reference.start);
+    references
+}
+
+/// Returns the reference to `link`, a part of `document`.
+fn reference<'a>(document: &str, link: &'a str) -> Reference<'a> {
+    Reference {
+        start: link.as_ptr() as usize - document.as_ptr() as usize,
+        text: link,
+    }
+}
+
+/// Resolves `link` against `base`, unless it leads to no resource to fetch.
+///
+/// The fragment, if any, is kept.
+pub fn resolve(base: &Url, link: &str) -> Option<Url> {
+    base.join(link.trim())
+        .ok()
+        .filter(|url| !IGNORED_SCHEMES.contains(&url.scheme()))
+}
+
+/// Links found in a document, with where they appear.
+struct Found<'a> {
+    base: &'a Url,
+    links: Vec<(usize, Url)>,
+    seen: HashSet<Url>,
+}
+
+impl<'a> Found<'a> {
+    fn new(base: &'a Url) -> Found<'a> {
+        Found {
+            base,
+            links: Vec::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Adds `link`, found at `index`, unless it leads nowhere or was found before.
+    fn add(&mut self, index: usize, link: &str) {
+        let Some(mut url) = resolve(self.base, link) else {
+            return;
+        };
+        url.set_fragment(None);
+        if self.seen.insert(url.clone()) {
+            self.links.push((index, url));
+        }
+    }
+
+    /// Returns the links in the order they appear.
+    fn into_links(mut self) -> Vec<Url> {
+        self.links.sort_by_key(|(index, _)| *index);
+        self.links.into_iter().map(|(_, url)| url).collect()
+    }
+}
+
+/// Returns the value of an attribute whose name `rest` follows, quoted or not.
+fn value(rest: &str) -> Option<&str> {
+    let rest = rest.trim_start().strip_prefix('=')?.trim_start();
+    match rest.chars().next()? {
+        quote @ ('"' | '\'') => rest[1..].split(quote).next(),
+        _ => rest
+            .split(|c: char| c.is_whitespace() || c == '>')
+            .next()
+            .filter(|value| !value.is_empty()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_links() {
+        let base = Url::parse("https://example.com/docs/index.html").unwrap();
+        let page = r#"<a HREF="guide.html#intro">Guide</a>
+            <img src='/logo.png' alt="">
+            <a href=../about.html>About</a>
+            <a data-href="skipped.html" href="guide.html">Again</a>
+            <a href="mailto:team@example.com">Mail</a>
+            <a href="https://other.example/?a=1&amp;b=2">Other</a>"#;
+        let links: Vec<String> = links(page, &base).into_iter().map(String::from).collect();
+        assert_eq!(
+            links,
+            [
+                "https://example.com/docs/guide.html",
+                "https://example.com/logo.png",
+                "https://example.com/about.html",
+                "https://other.example/?a=1&b=2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_srcset_and_css_links() {
+        let base = Url::parse("https://example.com/docs/").unwrap();
+        let page = r#"<picture>
+              <source srcset="wide.webp 1200w, narrow.webp 600w" type="image/webp">
+              <img src="fallback.jpg" srcset="fallback@2x.jpg 2x">
+            </picture>
+            <div style="background: url('bg.png')"></div>
+            <style>@import "print.css"; h1 { background: url(data:image/png;base64,AAAA) }</style>"#;
+        let links: Vec<String> = links(page, &base).into_iter().map(String::from).collect();
+        assert_eq!(
+            links,
+            [
+                "https://example.com/docs/wide.webp",
+                "https://example.com/docs/narrow.webp",
+                "https://example.com/docs/fallback.jpg",
+                "https://example.com/docs/fallback@2x.jpg",
+                "https://example.com/docs/bg.png",
+                "https://example.com/docs/print.css",
+            ]
+        );
+
+        let css = r#"@import url("theme/dark.css");
+            @font-face { src: url(/fonts/a.woff2) format("woff2"), url( "/fonts/a.woff" ); }"#;
+        let base = Url::parse("https://example.com/css/main.css").unwrap();
+        let links: Vec<String> = css_links(css, &base)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            links,
+            [
+                "https://example.com/css/theme/dark.css",
+                "https://example.com/fonts/a.woff2",
+                "https://example.com/fonts/a.woff",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scope() {
+        let start = Url::parse("https://example.com/docs/index.html").unwrap();
+        let url = |text| Url::parse(text).unwrap();
+        let scope = Scope::new(start.clone(), 2, true);
+        assert!(scope.follows(&url("https://example.com/docs/a/b.html"), 1));
+        assert!(!scope.follows(&url("https://example.com/docs/a/b.html"), 2));
+        assert!(!scope.is_internal(&url("https://example.com/about.html")));
+        assert!(!scope.is_internal(&url("https://other.example/docs/")));
+        assert!(
+            Scope::new(start.clone(), 0, false).follows(&url("https://example.com/about.html"), 99)
+        );
+
+        let mut scope = Scope::new(start, 0, false);
+        scope.include = directories("docs, /blog/20*/");
+        scope.exclude = directories("/docs/old");
+        assert_eq!(scope.include, ["/docs", "/blog/20*"]);
+        assert!(scope.is_internal(&url("https://example.com/docs/new/a.html")));
+        assert!(scope.is_internal(&url("https://example.com/blog/2024/05/post.html")));
+        assert!(!scope.is_internal(&url("https://example.com/blog/archive/post.html")));
+        assert!(!scope.is_internal(&url("https://example.com/docs/old/a.html")));
+        assert!(!scope.is_internal(&url("https://example.com/docsearch/a.html")));
+        assert!(!scope.is_internal(&url("https://example.com/index.html")));
+        assert!(scope.is_internal(&url("https://example.com/docs/index.html")));
+
+        let pace = Pace {
+            max_urls: Some(10),
+            max_time: Some(Duration::from_secs(60)),
+            ..Pace::default()
+        };
+        assert_eq!(pace.spent(9, u64::MAX, Duration::ZERO), None);
+        assert_eq!(pace.spent(10, 0, Duration::ZERO), Some("--max-urls"));
+        assert_eq!(
+            pace.spent(0, 0, Duration::from_secs(60)),
+            Some("--max-time")
+        );
+
+        let mut frontier = Frontier::new(url("https://example.com/"));
+        assert!(!frontier.push(url("https://example.com/"), 1));
+        assert_eq!(frontier.pop().unwrap().depth, 0);
+        assert!(frontier.pop().is_none());
+    }
+}
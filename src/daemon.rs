@@ -0,0 +1,779 @@
+//! `rustwget daemon`: a long-running download manager with a local control API.
+//!
+//! The daemon keeps a persistent [`Queue`] of jobs, processes it with a pool
+//! of worker threads, and exposes a small JSON API over loopback TCP or, on
+//! Unix, over a domain socket:
+//!
+//! | Method | Path                  | Effect                                        |
+//! |--------|-----------------------|-----------------------------------------------|
+//! | GET    | `/status`             | Job counts per status                         |
+//! | GET    | `/jobs`               | List all jobs                                 |
+//! | POST   | `/jobs`               | Add a job: `{"url", "output", "priority"}`    |
+//! | GET    | `/jobs/{id}`          | Show one job                                  |
+//! | POST   | `/jobs/{id}/pause`    | Pause a queued or running job                 |
+//! | POST   | `/jobs/{id}/resume`   | Re-queue a paused or failed job               |
+//! | POST   | `/jobs/{id}/cancel`   | Cancel a job (also `DELETE /jobs/{id}`)       |
+//! | POST   | `/jobs/{id}/priority` | Change the priority: `{"priority": 2}`        |
+//!
+//! Requests must come from a loopback `Host` and `Origin`, and those over TCP
+//! must carry `Authorization: Bearer TOKEN` with the token the daemon writes
+//! next to its state file at every start, readable only by its user, so that
+//! web pages cannot drive the API. Bodies must be sent as `application/json`.
+//! With `--socket`, only the Unix domain socket is served unless `--listen` is
+//! also given; its file permissions are what guard it.
+//!
+//! A bearer token given with `--token-file` or `RUSTWGET_TOKEN` is only sent
+//! to the hosts configured under `[host."NAME"]`, never to other queued URLs.
+//!
+//! Recurring downloads listed under `[[schedule]]` in the configuration file
+//! are queued automatically whenever their cron expression fires.
+//!
+//! Under systemd the daemon reports when it is ready, keeps the watchdog fed,
+//! and serves the API on the sockets of its socket unit (see [`systemd`]).
+//! `rustwget daemon install` starts it at every logon (see [`service`]).
+
+use crate::auth::{self, Keyring};
+use crate::config::{self, Schedule};
+use crate::download::{self, Options};
+use crate::filename::{self, Restriction};
+use crate::hosts::Hosts;
+use crate::httpd::{self, Request, Response};
+use crate::ledger;
+use crate::paths;
+use crate::queue::{self, Queue, QueueError};
+use crate::redact;
+use crate::redirect;
+use crate::schedule::Cron;
+use crate::service;
+use crate::systemd::{self, Listener};
+use crate::units;
+use chrono::{DateTime, Local};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use reqwest::blocking::{Client, ClientBuilder};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use url::Url;
+
+/// Default address of the control API.
+const DEFAULT_LISTEN: &str = "127.0.0.1:8750";
+
+/// Body of a `POST /jobs` request.
+#[derive(Debug, Deserialize)]
+struct NewJob {
+    url: String,
+    output: Option<PathBuf>,
+    #[serde(default)]
+    priority: i32,
+}
+
+/// Body of a `POST /jobs/{id}/priority` request.
+#[derive(Debug, Deserialize)]
+struct NewPriority {
+    priority: i32,
+}
+
+/// A validated schedule with its parsed cron expression, priority, and output path.
+type ScheduleEntry<'a> = (&'a Schedule, Cron, i32, PathBuf);
+
+/// State shared by all API connections.
+#[derive(Debug)]
+struct Daemon {
+    queue: Arc<Queue>,
+    dir: PathBuf,
+    /// The bearer token that requests over TCP must carry.
+    token: String,
+}
+
+/// Builds the `daemon` subcommand definition.
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("daemon")
+        .about("Run as a download manager controlled through a local HTTP API")
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .value_name("ADDR")
+                .help("Address of the HTTP control API")
+                .default_value(DEFAULT_LISTEN)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("socket")
+                .long("socket")
+                .value_name("PATH")
+                .help("Serve the control API on a Unix domain socket instead, or as well with --listen")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("state")
+                .long("state")
+                .value_name("FILE")
+                .help("Where the job queue is persisted [default: <data dir>/daemon.json]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Configuration file with [[schedule]] and [[bandwidth]] entries")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dir")
+                .long("dir")
+                .value_name("DIR")
+                .help("Directory that relative output paths are resolved against")
+                .default_value(".")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tries")
+                .short("t")
+                .long("tries")
+                .value_name("N")
+                .help("Number of attempts per job before it is marked failed")
+                .default_value("1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("token-file")
+                .long("token-file")
+                .value_name("PATH")
+                .help("Read a bearer token sent to the [host.\"NAME\"] hosts of --config from PATH [default: $RUSTWGET_TOKEN]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("limit-rate")
+                .long("limit-rate")
+                .value_name("RATE")
+                .help("Limit the combined download rate, e.g. 500k or 2M")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .value_name("N")
+                .help("Number of downloads to run at the same time")
+                .default_value("1")
+                .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("install")
+                .about("Start the daemon, with the options given before 'install', at every logon"),
+        )
+        .subcommand(SubCommand::with_name("uninstall").about("Stop the daemon started at logon"))
+}
+
+/// Runs the daemon until the process is terminated.
+///
+/// # Arguments
+///
+/// * `matches`: The parsed arguments of the `daemon` subcommand.
+///
+/// # Errors
+///
+/// Returns an error if the arguments are invalid, the state file cannot be
+/// loaded, or a listener cannot be bound.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    match matches.subcommand_name() {
+        Some("install") => {
+            return service::install(&std::env::current_exe()?, &service::arguments(matches)?)
+        }
+        Some("uninstall") => return service::uninstall(),
+        _ => {}
+    }
+    let workers: usize = matches.value_of("jobs").unwrap().parse()?;
+    if workers == 0 {
+        return Err("--jobs must be at least 1".into());
+    }
+    let limit_rate = matches
+        .value_of("limit-rate")
+        .map(units::parse_rate)
+        .transpose()?
+        .flatten();
+    let state = match matches.value_of("state") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let dir = paths::data_dir();
+            std::fs::create_dir_all(&dir)?;
+            dir.join("daemon.json")
+        }
+    };
+    let config = config::load(matches.value_of("config").map(Path::new))?;
+    let builder = || -> Result<ClientBuilder, Box<dyn std::error::Error>> {
+        Ok(Client::builder().redirect(redirect::policy(redirect::DEFAULT_MAX, false)))
+    };
+    // Anyone able to queue a URL would otherwise be sent the token.
+    let mut hosts = Hosts::new(&config.host, builder)?;
+    if let Some(token) = auth::bearer(None, matches.value_of("token-file").map(Path::new))? {
+        if config.host.is_empty() {
+            eprintln!(
+                "Warning: the bearer token is only sent to hosts with a [host.\"NAME\"] section, and the configuration has none"
+            );
+        }
+        hosts.authorize_configured(&token);
+    }
+    let options = Options {
+        tries: matches.value_of("tries").unwrap().parse()?,
+        throttle: config.throttle(limit_rate)?,
+        keyring: Some(Arc::new(Keyring::new(config.oauth.clone()))),
+        hosts: Arc::new(hosts),
+        ..Options::default()
+    };
+    let token_path = state.with_extension("token");
+    let daemon = Arc::new(Daemon {
+        queue: Arc::new(Queue::open(Some(state.clone()))?),
+        dir: PathBuf::from(matches.value_of("dir").unwrap()),
+        token: write_token(&token_path)?,
+    });
+
+    if !config.schedule.is_empty() {
+        let daemon = Arc::clone(&daemon);
+        thread::spawn(move || daemon.run_schedules(&config.schedule));
+    }
+
+    let client = builder()?.build()?;
+    for _ in 0..workers {
+        let queue = Arc::clone(&daemon.queue);
+        let client = client.clone();
+        let options = options.clone();
+        thread::spawn(move || queue::run_worker(&queue, &client, &options));
+    }
+
+    let mut listeners = systemd::listeners()?;
+    if listeners.is_empty() {
+        #[cfg(unix)]
+        if let Some(socket) = matches.value_of("socket") {
+            listeners.push(Listener::Unix(bind_unix(Path::new(socket))?));
+            println!("Daemon listening on unix:{}", socket);
+        }
+        #[cfg(not(unix))]
+        if matches.is_present("socket") {
+            return Err("--socket is only supported on Unix".into());
+        }
+        if listeners.is_empty() || matches.occurrences_of("listen") > 0 {
+            let listen = matches.value_of("listen").unwrap();
+            listeners.push(Listener::Tcp(TcpListener::bind(listen)?));
+            println!(
+                "Daemon listening on http://{} (state: {}, token: {})",
+                listen,
+                state.display(),
+                token_path.display()
+            );
+        }
+    } else {
+        println!(
+            "Daemon listening on the sockets passed by systemd (state: {}, token: {})",
+            state.display(),
+            token_path.display()
+        );
+    }
+
+    let serving: Vec<_> = listeners
+        .into_iter()
+        .map(|listener| {
+            let daemon = Arc::clone(&daemon);
+            thread::spawn(move || accept(daemon, listener))
+        })
+        .collect();
+    if let Err(err) = systemd::notify("READY=1") {
+        eprintln!("Warning: cannot notify systemd: {}", err);
+    }
+    systemd::start_watchdog();
+    for thread in serving {
+        let _ = thread.join();
+    }
+    Ok(())
+}
+
+/// Serves the control API on every connection accepted by `listener`.
+fn accept(daemon: Arc<Daemon>, listener: Listener) {
+    match listener {
+        Listener::Tcp(listener) => {
+            for stream in listener.incoming() {
+                connect(&daemon, stream, false);
+            }
+        }
+        #[cfg(unix)]
+        Listener::Unix(listener) => {
+            for stream in listener.incoming() {
+                connect(&daemon, stream, true);
+            }
+        }
+    }
+}
+
+/// Serves the control API on an accepted connection, in its own thread.
+///
+/// Requests on a `local` connection, one to the Unix domain socket, need no
+/// token.
+fn connect<S: Read + Write + Send + 'static>(
+    daemon: &Arc<Daemon>,
+    stream: io::Result<S>,
+    local: bool,
+) {
+    let stream = match stream {
+        Ok(stream) => stream,
+        Err(err) => {
+            eprintln!("Warning: failed to accept connection: {}", err);
+            return;
+        }
+    };
+    let daemon = Arc::clone(daemon);
+    thread::spawn(move || {
+        let handler = |request: &Request| match daemon.refuse(request, local) {
+            Some(refusal) => refusal,
+            None => daemon.handle(request),
+        };
+        if let Err(err) = httpd::serve(stream, handler) {
+            eprintln!("Warning: control connection failed: {}", err);
+        }
+    });
+}
+
+/// Binds the Unix domain socket `path`, replacing a stale one, and lets only
+/// the daemon's user connect to it.
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> io::Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Generates a new bearer token for the control API and writes it to `path`,
+/// readable only by the daemon's user.
+///
+/// # Errors
+///
+/// Returns an error if no random token can be generated or the file cannot be
+/// written.
+fn write_token(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).map_err(|err| format!("cannot generate token: {}", err))?;
+    let token = ledger::hex(&bytes);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let _ = fs::remove_file(path);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(token.as_bytes()))
+        .map_err(|err| format!("cannot write token {}: {}", path.display(), err))?;
+    Ok(token)
+}
+
+/// Whether the `Host` or `Origin` value `host` names the loopback interface.
+fn is_loopback(host: &str) -> bool {
+    let url = match host.contains("://") {
+        true => Url::parse(host),
+        false => Url::parse(&format!("http://{}", host)),
+    };
+    match url.ok().as_ref().and_then(Url::host) {
+        Some(url::Host::Domain(domain)) => domain.eq_ignore_ascii_case("localhost"),
+        Some(url::Host::Ipv4(address)) => address.is_loopback(),
+        Some(url::Host::Ipv6(address)) => address.is_loopback(),
+        None => false,
+    }
+}
+
+impl Daemon {
+    /// Returns the answer to a request that is not allowed, or `None` if it is.
+    ///
+    /// A `Host` or `Origin` other than loopback marks a request from a web
+    /// page, possibly through DNS rebinding. Requests that are not `local`
+    /// must also carry the daemon's token.
+    fn refuse(&self, request: &Request, local: bool) -> Option<Response> {
+        if let Some(host) = request.header("host").filter(|host| !is_loopback(host)) {
+            return Some(error(403, &format!("host {} is not allowed", host)));
+        }
+        if let Some(origin) = request
+            .header("origin")
+            .filter(|origin| !is_loopback(origin))
+        {
+            return Some(error(403, &format!("origin {} is not allowed", origin)));
+        }
+        let bearer = request
+            .header("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !local && bearer != Some(self.token.as_str()) {
+            return Some(error(401, "missing or wrong token"));
+        }
+        None
+    }
+
+    /// Routes one API request.
+    fn handle(&self, request: &Request) -> Response {
+        let path = request.path.split('?').next().unwrap_or("");
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        let json_body = request
+            .header("content-type")
+            .and_then(|value| value.split(';').next())
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("application/json"));
+
+        match (request.method.as_str(), segments.as_slice()) {
+            ("POST", ["jobs"]) | ("POST", ["jobs", _, "priority"]) if !json_body => {
+                error(415, "the body must be application/json")
+            }
+            ("GET", ["status"]) => self.status(),
+            ("GET", ["jobs"]) => json(200, &self.queue.jobs()),
+            ("POST", ["jobs"]) => self.add(&request.body),
+            ("GET", ["jobs", id]) => match parse_id(id) {
+                Some(id) => match self.queue.job(id) {
+                    Some(job) => json(200, &job),
+                    None => queue_error(QueueError::NotFound(id)),
+                },
+                None => error(404, "not found"),
+            },
+            ("DELETE", ["jobs", id]) => self.act(id, "cancel"),
+            ("POST", ["jobs", id, "priority"]) => self.prioritize(id, &request.body),
+            ("POST", ["jobs", id, action]) => self.act(id, action),
+            (_, ["status"]) | (_, ["jobs"]) | (_, ["jobs", _]) | (_, ["jobs", _, _]) => {
+                error(405, "method not allowed")
+            }
+            _ => error(404, "not found"),
+        }
+    }
+
+    fn add(&self, body: &[u8]) -> Response {
+        let new: NewJob = match serde_json::from_slice(body) {
+            Ok(new) => new,
+            Err(err) => return error(400, &format!("invalid job: {}", err)),
+        };
+        let url = match Url::parse(&new.url) {
+            Ok(url) => url,
+            Err(err) => return error(400, &format!("invalid URL: {}", err)),
+        };
+        if new
+            .output
+            .as_deref()
+            .is_some_and(|output| !filename::is_contained(output))
+        {
+            return error(
+                400,
+                "output must be a relative path inside the download directory",
+            );
+        }
+        let job = self
+            .queue
+            .add(new.url, self.output_path(&url, new.output), new.priority);
+        json(201, &job)
+    }
+
+    /// Resolves where a job is written, relative to the daemon's directory.
+    ///
+    /// A name taken from the URL is sanitized so that it stays in that directory.
+    fn output_path(&self, url: &Url, output: Option<PathBuf>) -> PathBuf {
+        let output = output.unwrap_or_else(|| {
+            let name = download::default_filename(url);
+            PathBuf::from(
+                filename::sanitize(&name, Restriction::default())
+                    .unwrap_or_else(|| "index.html".to_string()),
+            )
+        });
+        self.dir.join(output)
+    }
+
+    /// Queues each schedule's download whenever its cron expression fires.
+    ///
+    /// A schedule is skipped for one occurrence if the job it queued last time
+    /// has not finished yet, so slow transfers do not pile up.
+    fn run_schedules(&self, schedules: &[Schedule]) {
+        let mut entries: Vec<ScheduleEntry> = Vec::new();
+        for schedule in schedules {
+            // The configuration was validated when it was loaded.
+            match (
+                schedule.cron(),
+                schedule.priority(),
+                Url::parse(&schedule.url),
+            ) {
+                (Ok(cron), Ok(priority), Ok(url)) => {
+                    let output = self.output_path(&url, schedule.output.clone());
+                    entries.push((schedule, cron, priority, output));
+                }
+                (_, _, Err(err)) => {
+                    eprintln!(
+                        "Warning: skipping schedule for {}: {}",
+                        redact::url(&schedule.url),
+                        err
+                    )
+                }
+                _ => {}
+            }
+        }
+
+        loop {
+            let now = Local::now();
+            let Some(due) = entries
+                .iter()
+                .filter_map(|(_, cron, _, _)| cron.next_after(now))
+                .min()
+            else {
+                return;
+            };
+            if let Ok(wait) = (due - now).to_std() {
+                thread::sleep(wait);
+            }
+
+            self.queue_due(&entries, due);
+        }
+    }
+
+    /// Queues every schedule entry whose cron expression matches `due`.
+    fn queue_due(&self, entries: &[ScheduleEntry], due: DateTime<Local>) {
+        let jobs = self.queue.jobs();
+        for (schedule, cron, priority, output) in entries {
+            if !cron.matches(&due.naive_local()) {
+                continue;
+            }
+            let pending = jobs
+                .iter()
+                .any(|job| !job.is_finished() && job.url == schedule.url && &job.output == output);
+            if pending {
+                eprintln!(
+                    "Warning: previous scheduled download of {} has not finished; skipping",
+                    redact::url(&schedule.url)
+                );
+                continue;
+            }
+            let job = self
+                .queue
+                .add(schedule.url.clone(), output.clone(), *priority);
+            println!("Scheduled job {}: {}", job.id, redact::url(&job.url));
+        }
+    }
+
+    fn prioritize(&self, id: &str, body: &[u8]) -> Response {
+        let Some(id) = parse_id(id) else {
+            return error(404, "not found");
+        };
+        match serde_json::from_slice::<NewPriority>(body) {
+            Ok(new) => match self.queue.set_priority(id, new.priority) {
+                Ok(job) => json(200, &job),
+                Err(err) => queue_error(err),
+            },
+            Err(err) => error(400, &format!("invalid priority: {}", err)),
+        }
+    }
+
+    fn act(&self, id: &str, action: &str) -> Response {
+        let Some(id) = parse_id(id) else {
+            return error(404, "not found");
+        };
+        let result = match action {
+            "pause" => self.queue.pause(id),
+            "resume" => self.queue.resume(id),
+            "cancel" => self.queue.cancel(id),
+            _ => return error(404, "not found"),
+        };
+        match result {
+            Ok(job) => json(200, &job),
+            Err(err) => queue_error(err),
+        }
+    }
+
+    fn status(&self) -> Response {
+        let mut counts = BTreeMap::new();
+        for job in self.queue.jobs() {
+            *counts.entry(job.status.to_string()).or_insert(0u64) += 1;
+        }
+        json(200, &counts)
+    }
+}
+
+fn parse_id(id: &str) -> Option<u64> {
+    id.parse().ok()
+}
+
+fn json<T: serde::Serialize>(status: u16, value: &T) -> Response {
+    match serde_json::to_string(value) {
+        Ok(body) => Response::json(status, body),
+        Err(err) => error(500, &err.to_string()),
+    }
+}
+
+fn error(status: u16, message: &str) -> Response {
+    json(status, &serde_json::json!({ "error": message }))
+}
+
+fn queue_error(err: QueueError) -> Response {
+    let status = match err {
+        QueueError::NotFound(_) => 404,
+        QueueError::InvalidState { .. } => 409,
+    };
+    error(status, &err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::{Job, Status};
+
+    fn daemon() -> Daemon {
+        Daemon {
+            queue: Arc::new(Queue::open(None).unwrap()),
+            dir: PathBuf::from("/srv/downloads"),
+            token: "secret".to_string(),
+        }
+    }
+
+    fn parse_job(response: &Response) -> Job {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: vec![
+                ("Host".to_string(), "127.0.0.1:8750".to_string()),
+                ("Content-Type".to_string(), "application/json".to_string()),
+            ],
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_add_and_show_job() {
+        let daemon = daemon();
+        let response = daemon.handle(&request(
+            "POST",
+            "/jobs",
+            r#"{"url": "https://example.com/files/big.iso"}"#,
+        ));
+        assert_eq!(response.status, 201);
+        let job = parse_job(&response);
+        assert_eq!(job.output, PathBuf::from("/srv/downloads/big.iso"));
+        assert_eq!(job.status, Status::Queued);
+
+        let response = daemon.handle(&request("GET", &format!("/jobs/{}", job.id), ""));
+        assert_eq!(response.status, 200);
+        assert_eq!(parse_job(&response), job);
+    }
+
+    #[test]
+    fn test_pause_and_cancel() {
+        let daemon = daemon();
+        let job = parse_job(&daemon.handle(&request(
+            "POST",
+            "/jobs",
+            r#"{"url": "https://example.com/a", "output": "renamed"}"#,
+        )));
+
+        let paused = daemon.handle(&request("POST", &format!("/jobs/{}/pause", job.id), ""));
+        assert_eq!(parse_job(&paused).status, Status::Paused);
+
+        let raised = daemon.handle(&request(
+            "POST",
+            &format!("/jobs/{}/priority", job.id),
+            r#"{"priority": 3}"#,
+        ));
+        assert_eq!(parse_job(&raised).priority, 3);
+
+        let cancelled = daemon.handle(&request("DELETE", &format!("/jobs/{}", job.id), ""));
+        assert_eq!(parse_job(&cancelled).status, Status::Cancelled);
+
+        let again = daemon.handle(&request("POST", &format!("/jobs/{}/pause", job.id), ""));
+        assert_eq!(again.status, 409);
+
+        let status = daemon.handle(&request("GET", "/status", ""));
+        assert_eq!(status.body, br#"{"cancelled":1}"#);
+    }
+
+    #[test]
+    fn test_schedules_skip_unfinished_jobs() {
+        let daemon = daemon();
+        let config = config::parse(
+            "[[schedule]]\nurl = \"https://example.com/nightly.tar.gz\"\ncron = \"0 2 * * *\"\n",
+        )
+        .unwrap();
+        let schedule = &config.schedule[0];
+        let entries = vec![(
+            schedule,
+            schedule.cron().unwrap(),
+            0,
+            PathBuf::from("/srv/downloads/nightly.tar.gz"),
+        )];
+        let due = Local::now()
+            .with_time(chrono::NaiveTime::from_hms_opt(2, 0, 0).unwrap())
+            .unwrap();
+
+        daemon.queue_due(&entries, due);
+        daemon.queue_due(&entries, due);
+        assert_eq!(daemon.queue.jobs().len(), 1);
+
+        daemon.queue_due(&entries, due + chrono::Duration::hours(1));
+        assert_eq!(daemon.queue.jobs().len(), 1);
+    }
+
+    #[test]
+    fn test_rejects_bad_requests() {
+        let daemon = daemon();
+        assert_eq!(daemon.handle(&request("POST", "/jobs", "{}")).status, 400);
+        assert_eq!(
+            daemon
+                .handle(&request("POST", "/jobs", r#"{"url": "not a url"}"#))
+                .status,
+            400
+        );
+        assert_eq!(
+            daemon
+                .handle(&request(
+                    "POST",
+                    "/jobs",
+                    r#"{"url": "https://example.com/a", "output": "../../.profile"}"#
+                ))
+                .status,
+            400
+        );
+        assert_eq!(daemon.handle(&request("GET", "/jobs/42", "")).status, 404);
+        assert_eq!(daemon.handle(&request("PUT", "/jobs", "")).status, 405);
+        assert_eq!(daemon.handle(&request("GET", "/nope", "")).status, 404);
+
+        let mut text = request("POST", "/jobs", r#"{"url": "https://example.com/a"}"#);
+        text.headers[1].1 = "text/plain".to_string();
+        assert_eq!(daemon.handle(&text).status, 415);
+    }
+
+    #[test]
+    fn test_refuses_foreign_requests() {
+        let daemon = daemon();
+        let mut allowed = request("POST", "/jobs/1/cancel", "");
+        assert_eq!(daemon.refuse(&allowed, true), None);
+        assert_eq!(daemon.refuse(&allowed, false).unwrap().status, 401);
+        allowed
+            .headers
+            .push(("Authorization".to_string(), "Bearer secret".to_string()));
+        assert_eq!(daemon.refuse(&allowed, false), None);
+
+        let mut rebound = allowed.clone();
+        rebound.headers[0].1 = "attacker.example:8750".to_string();
+        assert_eq!(daemon.refuse(&rebound, false).unwrap().status, 403);
+        let mut cross_site = allowed.clone();
+        cross_site
+            .headers
+            .push(("Origin".to_string(), "https://attacker.example".to_string()));
+        assert_eq!(daemon.refuse(&cross_site, true).unwrap().status, 403);
+
+        assert!(is_loopback("localhost"));
+        assert!(is_loopback("[::1]:8750"));
+        assert!(is_loopback("http://127.0.0.1:8750"));
+        assert!(!is_loopback("null"));
+    }
+}
@@ -0,0 +1,123 @@
+//! Decompressing single compressed files while they download.
+//!
+//! With `--decompress`, a URL ending in `.gz`, `.zst`, or `.xz` is decoded as
+//! it arrives and written without that extension, so `data.csv.gz` becomes
+//! `data.csv` and the compressed copy never touches the disk. Such downloads
+//! cannot be resumed: an interrupted one starts over.
+
+use flate2::read::MultiGzDecoder;
+use ruzstd::decoding::StreamingDecoder;
+use std::io::{self, BufReader, Read, Write};
+
+/// A compression format recognized by its file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Format {
+    /// Recognizes the format of a file name or URL path from its extension.
+    pub fn detect(name: &str) -> Option<Format> {
+        let name = name.split(['?', '#']).next().unwrap_or_default();
+        let (_, extension) = name.rsplit_once('.')?;
+        match extension.to_ascii_lowercase().as_str() {
+            "gz" | "gzip" => Some(Format::Gzip),
+            "zst" | "zstd" => Some(Format::Zstd),
+            "xz" => Some(Format::Xz),
+            _ => None,
+        }
+    }
+
+    /// Recognizes the format of a `Content-Encoding`; `None` for `identity`
+    /// and encodings not supported.
+    pub fn from_encoding(encoding: &str) -> Option<Format> {
+        match encoding.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Format::Gzip),
+            "zstd" => Some(Format::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Decodes everything `input` yields and writes the result to `output`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading or writing fails, or if the input is not
+    /// valid data of this format.
+    pub fn decode(self, input: impl Read, output: &mut impl Write) -> io::Result<()> {
+        let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidData, err);
+        match self {
+            Format::Gzip => io::copy(&mut MultiGzDecoder::new(input), output).map(drop),
+            Format::Zstd => {
+                let mut decoder =
+                    StreamingDecoder::new(input).map_err(|err| invalid(err.to_string()))?;
+                io::copy(&mut decoder, output).map(drop)
+            }
+            Format::Xz => lzma_rs::xz_decompress(&mut BufReader::new(input), output).map_err(
+                |err| match err {
+                    lzma_rs::error::Error::IoError(err) => err,
+                    err => invalid(err.to_string()),
+                },
+            ),
+        }
+    }
+}
+
+/// Returns the name of the decompressed file: `name` without its compression extension.
+pub fn strip_extension(name: &str) -> &str {
+    match Format::detect(name) {
+        Some(_) => name
+            .rsplit_once('.')
+            .map_or(name, |(stem, _)| stem)
+            .trim_end_matches('.'),
+        None => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    #[test]
+    fn test_detect_and_strip() {
+        assert_eq!(
+            Format::detect("https://example.com/data.csv.gz?sig=1"),
+            Some(Format::Gzip)
+        );
+        assert_eq!(Format::from_encoding("X-Gzip"), Some(Format::Gzip));
+        assert_eq!(Format::from_encoding("identity"), None);
+        assert_eq!(Format::detect("archive.tar.ZST"), Some(Format::Zstd));
+        assert_eq!(Format::detect("notes.xz"), Some(Format::Xz));
+        assert_eq!(Format::detect("data.csv"), None);
+        assert_eq!(strip_extension("data.csv.gz"), "data.csv");
+        assert_eq!(strip_extension("data.csv"), "data.csv");
+    }
+
+    #[test]
+    fn test_decode_every_format() {
+        let text = b"id,name\n1,alpha\n2,beta\n".repeat(100);
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&text).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = ruzstd::encoding::compress_to_vec(
+            &text[..],
+            ruzstd::encoding::CompressionLevel::Fastest,
+        );
+        let mut xz = Vec::new();
+        lzma_rs::xz_compress(&mut &text[..], &mut xz).unwrap();
+
+        for (format, compressed) in [(Format::Gzip, gzip), (Format::Zstd, zstd), (Format::Xz, xz)] {
+            let mut output = Vec::new();
+            format.decode(&compressed[..], &mut output).unwrap();
+            assert_eq!(output, text, "{:?}", format);
+            assert!(format
+                .decode(&compressed[..compressed.len() / 2], &mut Vec::new())
+                .is_err());
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Detecting downloads identical to earlier ones of the same run.
+//!
+//! URLs that differ only in tracking parameters, or mirrors of the same file
+//! under several names, often serve the same bytes. With `--dedupe-content
+//! link`, a file whose SHA-256 digest, computed as it streams in, equals that
+//! of a file saved earlier in the run is replaced with a hard link to it, so
+//! that the copies share their space on disk; where a hard link cannot be
+//! made, as across file systems, the copy is kept. With `--dedupe-content
+//! skip`, the copy is removed and counted as skipped.
+
+use crate::i18n;
+use crate::logfile;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Names accepted by `--dedupe-content`.
+pub const MODES: [&str; 2] = ["link", "skip"];
+
+/// What becomes of a download identical to an earlier one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// It becomes a hard link to the earlier file.
+    Link,
+    /// It is removed.
+    Skip,
+}
+
+impl Mode {
+    /// Parses one of the [`MODES`].
+    pub fn parse(name: &str) -> Result<Mode, String> {
+        match name {
+            "link" => Ok(Mode::Link),
+            "skip" => Ok(Mode::Skip),
+            _ => Err(format!(
+                "invalid --dedupe-content '{}': expected {}",
+                name,
+                MODES.join(" or ")
+            )),
+        }
+    }
+}
+
+/// The files saved so far in a run, by their SHA-256 digest.
+#[derive(Debug)]
+pub struct Registry {
+    mode: Mode,
+    saved: Mutex<HashMap<String, PathBuf>>,
+}
+
+impl Registry {
+    /// Creates a registry that treats duplicates as `mode` says.
+    pub fn new(mode: Mode) -> Registry {
+        Registry {
+            mode,
+            saved: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records the file at `path`, whose SHA-256 digest is `digest`, and
+    /// links or removes it if it duplicates a file saved earlier.
+    ///
+    /// Returns whether the file was removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the duplicate cannot be removed or replaced.
+    pub fn dedupe(&self, path: &Path, digest: &str) -> io::Result<bool> {
+        let original = {
+            let mut saved = self.saved.lock().unwrap_or_else(|e| e.into_inner());
+            match saved.get(digest) {
+                // The same file downloaded again is not a duplicate of itself.
+                Some(original) if original != path && original.exists() => original.clone(),
+                _ => {
+                    saved.insert(digest.to_string(), path.to_path_buf());
+                    return Ok(false);
+                }
+            }
+        };
+        match self.mode {
+            Mode::Skip => {
+                fs::remove_file(path)?;
+                logfile::info(&i18n::text(
+                    "skipped.duplicate",
+                    &[("file", &path.display()), ("original", &original.display())],
+                ));
+                Ok(true)
+            }
+            Mode::Link => {
+                let mut link = path.as_os_str().to_owned();
+                link.push(".link");
+                let link = PathBuf::from(link);
+                match fs::hard_link(&original, &link) {
+                    Ok(()) => {
+                        fs::rename(&link, path)?;
+                        logfile::info(&format!(
+                            "Linked {} to {}, which has the same content",
+                            path.display(),
+                            original.display()
+                        ));
+                    }
+                    Err(err) => logfile::detail(&format!(
+                        "Keeping {}, a copy of {}: {}",
+                        path.display(),
+                        original.display(),
+                        err
+                    )),
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedupe() {
+        let dir = tempfile::tempdir().unwrap();
+        let [first, second, third] =
+            ["a.html", "b.html", "c.html"].map(|name| dir.path().join(name));
+        for path in [&first, &second, &third] {
+            fs::write(path, "same").unwrap();
+        }
+
+        let registry = Registry::new(Mode::Link);
+        assert!(!registry.dedupe(&first, "d1").unwrap());
+        assert!(!registry.dedupe(&first, "d1").unwrap());
+        assert!(!registry.dedupe(&second, "d1").unwrap());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let inode = |path: &Path| fs::metadata(path).unwrap().ino();
+            assert_eq!(inode(&first), inode(&second));
+        }
+
+        let registry = Registry::new(Mode::Skip);
+        assert!(!registry.dedupe(&first, "d1").unwrap());
+        assert!(registry.dedupe(&third, "d1").unwrap());
+        assert!(!third.exists());
+    }
+}
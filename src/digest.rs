@@ -0,0 +1,230 @@
+//! Digests computed while a download is written.
+//!
+//! `--print-hash sha256,md5` prints the digests of every downloaded file as
+//! `SHA256 (file) = HEX` lines, and `--hash-file` also writes them next to the
+//! file as `file.sha256`, `file.md5`, ... in the format read by `sha256sum -c`.
+//! The data is hashed as it is written, so the file is not read a second time
+//! (except for the already present part of a resumed file).
+
+use crate::ledger::hex;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::digest::DynDigest;
+use sha2::{Digest, Sha256, Sha512};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// A supported digest algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Algorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// Names accepted by `--print-hash`.
+pub const ALGORITHMS: [&str; 4] = ["md5", "sha1", "sha256", "sha512"];
+
+/// Computed digests, as lowercase hex, in the order they were requested.
+pub type Digests = Vec<(Algorithm, String)>;
+
+impl Algorithm {
+    /// Parses one of the [`ALGORITHMS`].
+    pub fn parse(name: &str) -> Result<Algorithm, String> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "md5" => Ok(Algorithm::Md5),
+            "sha1" => Ok(Algorithm::Sha1),
+            "sha256" => Ok(Algorithm::Sha256),
+            "sha512" => Ok(Algorithm::Sha512),
+            other => Err(format!(
+                "unknown hash algorithm '{}': expected one of {}",
+                other,
+                ALGORITHMS.join(", ")
+            )),
+        }
+    }
+
+    /// Returns the lowercase name, which is also the extension of hash files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "md5",
+            Algorithm::Sha1 => "sha1",
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha512 => "sha512",
+        }
+    }
+
+    fn hasher(self) -> Box<dyn DynDigest> {
+        match self {
+            Algorithm::Md5 => Box::new(Md5::new()),
+            Algorithm::Sha1 => Box::new(Sha1::new()),
+            Algorithm::Sha256 => Box::new(Sha256::new()),
+            Algorithm::Sha512 => Box::new(Sha512::new()),
+        }
+    }
+}
+
+/// Computes several digests of the same data at once.
+pub struct Hasher {
+    states: Vec<(Algorithm, Box<dyn DynDigest>)>,
+}
+
+impl Hasher {
+    /// Creates a hasher for `algorithms`, each computed once.
+    pub fn new(algorithms: &[Algorithm]) -> Hasher {
+        let mut states: Vec<(Algorithm, Box<dyn DynDigest>)> = Vec::new();
+        for algorithm in algorithms {
+            if !states.iter().any(|(known, _)| known == algorithm) {
+                states.push((*algorithm, algorithm.hasher()));
+            }
+        }
+        Hasher { states }
+    }
+
+    /// Returns whether no digest is computed at all.
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /// Adds `data` to every digest.
+    pub fn update(&mut self, data: &[u8]) {
+        for (_, state) in &mut self.states {
+            state.update(data);
+        }
+    }
+
+    /// Adds everything `reader` yields to every digest.
+    pub fn update_from(&mut self, mut reader: impl Read) -> io::Result<()> {
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            match reader.read(&mut buffer)? {
+                0 => return Ok(()),
+                read => self.update(&buffer[..read]),
+            }
+        }
+    }
+
+    /// Returns the digests.
+    pub fn finish(self) -> Digests {
+        self.states
+            .into_iter()
+            .map(|(algorithm, state)| (algorithm, hex(&state.finalize())))
+            .collect()
+    }
+}
+
+/// Writes through to another writer, hashing everything that is written.
+pub struct HashingWriter<W> {
+    pub inner: W,
+    pub hasher: Hasher,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Computes digests of the file at `path` by reading it.
+pub fn file(path: &Path, algorithms: &[Algorithm]) -> io::Result<Digests> {
+    let mut hasher = Hasher::new(algorithms);
+    hasher.update_from(fs::File::open(path)?)?;
+    Ok(hasher.finish())
+}
+
+/// Returns the `SHA256 (file) = HEX` lines describing `digests`.
+pub fn format(path: &Path, digests: &Digests) -> String {
+    digests
+        .iter()
+        .map(|(algorithm, digest)| {
+            format!(
+                "{} ({}) = {}\n",
+                algorithm.name().to_ascii_uppercase(),
+                path.display(),
+                digest
+            )
+        })
+        .collect()
+}
+
+/// Writes one `path.<algorithm>` file per digest, as read by `sha256sum -c` and friends.
+///
+/// # Errors
+///
+/// Returns an error if a file cannot be written.
+pub fn write_files(path: &Path, digests: &Digests) -> io::Result<Vec<PathBuf>> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    digests
+        .iter()
+        .map(|(algorithm, digest)| {
+            let mut target = path.as_os_str().to_owned();
+            target.push(format!(".{}", algorithm.name()));
+            let target = PathBuf::from(target);
+            fs::write(&target, format!("{}  {}\n", digest, name))?;
+            Ok(target)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digests_of_known_input() {
+        let mut writer = HashingWriter {
+            inner: Vec::new(),
+            hasher: Hasher::new(&[Algorithm::Sha256, Algorithm::Md5, Algorithm::Sha256]),
+        };
+        writer.write_all(b"ab").unwrap();
+        writer.write_all(b"c").unwrap();
+
+        let digests = writer.hasher.finish();
+        assert_eq!(writer.inner, b"abc");
+        assert_eq!(
+            digests,
+            vec![
+                (
+                    Algorithm::Sha256,
+                    "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string()
+                ),
+                (
+                    Algorithm::Md5,
+                    "900150983cd24fb0d6963f7d28e17f72".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            format(Path::new("abc.txt"), &digests[1..].to_vec()),
+            "MD5 (abc.txt) = 900150983cd24fb0d6963f7d28e17f72\n"
+        );
+        assert!(Algorithm::parse("crc32").is_err());
+    }
+
+    #[test]
+    fn test_write_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("abc.txt");
+        fs::write(&path, "abc").unwrap();
+
+        let digests = file(&path, &[Algorithm::Sha1]).unwrap();
+        let written = write_files(&path, &digests).unwrap();
+
+        assert_eq!(written, vec![dir.path().join("abc.txt.sha1")]);
+        assert_eq!(
+            fs::read_to_string(&written[0]).unwrap(),
+            "a9993e364706816aba3e25717850c26c9cd0d89d  abc.txt\n"
+        );
+    }
+}
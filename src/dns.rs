@@ -0,0 +1,215 @@
+//! Caching host name lookups for the length of a run.
+//!
+//! Every new connection otherwise asks the system resolver again, which adds
+//! up when thousands of assets are fetched from a handful of hosts. A
+//! [`Resolver`] remembers each successful lookup for `--dns-cache-timeout`
+//! seconds (60 by default, `0` to ask every time). The system resolver does not
+//! report the TTL of its records, so the timeout stands in for it; keeping it
+//! short lets addresses that change move on during long runs. With
+//! `--dns-timeout`, a lookup that takes longer fails instead of holding up the
+//! connection.
+//!
+//! With `--dns-servers`, lookups skip the system resolver and ask the given
+//! [`nameserver`]s, whose TTLs then shorten how long answers are kept.
+
+use crate::nameserver;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a lookup is remembered by default.
+pub const DEFAULT_CACHE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The addresses of a host and until when they may be used.
+#[derive(Debug, Clone)]
+struct Entry {
+    addresses: Vec<SocketAddr>,
+    expires: Instant,
+}
+
+/// Remembered lookups, by host name.
+#[derive(Debug, Default)]
+struct Cache {
+    entries: HashMap<String, Entry>,
+}
+
+impl Cache {
+    /// Returns the addresses of `host` if they have not expired by `now`.
+    fn get(&self, host: &str, now: Instant) -> Option<Vec<SocketAddr>> {
+        self.entries
+            .get(host)
+            .filter(|entry| now < entry.expires)
+            .map(|entry| entry.addresses.clone())
+    }
+
+    fn insert(&mut self, host: String, addresses: Vec<SocketAddr>, expires: Instant) {
+        self.entries.insert(host, Entry { addresses, expires });
+    }
+}
+
+/// A resolver that caches the answers of the system resolver, or of chosen
+/// servers, for use with `reqwest::ClientBuilder::dns_resolver`.
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    cache: Arc<Mutex<Cache>>,
+    cache_timeout: Duration,
+    timeout: Option<Duration>,
+    servers: Arc<Vec<SocketAddr>>,
+}
+
+impl Resolver {
+    /// Creates a resolver.
+    ///
+    /// # Arguments
+    ///
+    /// * `cache_timeout`: How long a lookup is remembered; zero disables the cache.
+    /// * `timeout`: How long a lookup may take, if limited.
+    /// * `servers`: DNS servers to ask instead of the system resolver, if any.
+    pub fn new(
+        cache_timeout: Duration,
+        timeout: Option<Duration>,
+        servers: Vec<SocketAddr>,
+    ) -> Resolver {
+        Resolver {
+            cache: Arc::default(),
+            cache_timeout,
+            timeout,
+            servers: Arc::new(servers),
+        }
+    }
+
+    /// Looks up `host`, returning its addresses and how long they may be kept.
+    fn lookup(&self, host: &str) -> io::Result<(Vec<SocketAddr>, Duration)> {
+        if self.servers.is_empty() {
+            // The port is filled in by the connector.
+            let addresses = (host, 0).to_socket_addrs()?.collect();
+            return Ok((addresses, self.cache_timeout));
+        }
+        let timeout = self.timeout.unwrap_or(nameserver::DEFAULT_TIMEOUT);
+        let answer = nameserver::lookup(&self.servers, host, timeout)?;
+        let addresses = answer
+            .addresses
+            .into_iter()
+            .map(|address| SocketAddr::new(address, 0))
+            .collect();
+        Ok((addresses, answer.ttl.min(self.cache_timeout)))
+    }
+}
+
+/// Opens a TCP connection to `host` for a protocol the client does not speak,
+/// trying each of its addresses in turn. Connecting, and each read and
+/// write, may take up to `timeout`.
+///
+/// # Errors
+///
+/// Returns an error if the host cannot be resolved or none of its addresses
+/// accepts the connection.
+pub fn connect(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for address in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} has no addresses", host),
+        )
+    }))
+}
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let cached = resolver
+                .cache
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(&host, Instant::now());
+            let addresses = match cached {
+                Some(addresses) => addresses,
+                None => {
+                    let lookup = {
+                        let (resolver, host) = (resolver.clone(), host.clone());
+                        tokio::task::spawn_blocking(move || resolver.lookup(&host))
+                    };
+                    let result = match resolver.timeout {
+                        Some(timeout) => tokio::time::timeout(timeout, lookup)
+                            .await
+                            .map_err(|_| format!("looking up {} timed out", host))?,
+                        None => lookup.await,
+                    };
+                    let (addresses, ttl) = result??;
+                    resolver
+                        .cache
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .insert(host, addresses.clone(), Instant::now() + ttl);
+                    addresses
+                }
+            };
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+
+    #[test]
+    fn test_cache_expires() {
+        let mut cache = Cache::default();
+        let start = Instant::now();
+        let addresses = vec![SocketAddr::from(([192, 0, 2, 1], 0))];
+        let later = |seconds| start + Duration::from_secs(seconds);
+        cache.insert("example.com".to_string(), addresses.clone(), later(60));
+
+        assert_eq!(cache.get("example.com", later(59)), Some(addresses));
+        assert_eq!(cache.get("example.com", later(60)), None);
+        assert_eq!(cache.get("example.org", start), None);
+    }
+
+    #[test]
+    fn test_resolver_remembers_lookups() {
+        let mock = mock("GET", "/dns/cached.txt")
+            .with_body("cached")
+            .expect(2)
+            .create();
+        let resolver = Resolver::new(
+            DEFAULT_CACHE_TIMEOUT,
+            Some(Duration::from_secs(10)),
+            Vec::new(),
+        );
+        let client = reqwest::blocking::ClientBuilder::from(
+            reqwest::ClientBuilder::new().dns_resolver(Arc::new(resolver.clone())),
+        )
+        .pool_max_idle_per_host(0)
+        .build()
+        .unwrap();
+        let url = format!("{}/dns/cached.txt", server_url()).replace("127.0.0.1", "localhost");
+
+        for _ in 0..2 {
+            assert_eq!(client.get(&url).send().unwrap().text().unwrap(), "cached");
+        }
+        let cache = resolver.cache.lock().unwrap();
+        assert!(cache.entries["localhost"]
+            .addresses
+            .iter()
+            .any(|address| address.ip().is_loopback()));
+        mock.assert();
+    }
+}
@@ -0,0 +1,585 @@
+//! Files of research datasets named by their DOI (`doi:` URLs).
+//!
+//! `doi:10.5281/zenodo.1234567` stands for every file of the dataset the DOI
+//! names, as listed by the API of the repository that holds it: Zenodo,
+//! Figshare, or OSF. DOIs of these repositories are recognized by their
+//! prefix; others are resolved through `doi.org` and recognized by the page
+//! they lead to, so that institutional Figshare portals work too:
+//!
+//! ```text
+//! rustwget doi:10.5281/zenodo.1234567
+//! rustwget -O data doi:10.6084/m9.figshare.7890123.v2
+//! rustwget doi:10.17605/OSF.IO/ABCDE
+//! ```
+//!
+//! The files are saved at their path in the dataset below the directory given
+//! with `-O`, or below one named after the record, such as `zenodo-1234567`,
+//! and verified against the MD5 or SHA-256 digests the repository lists for
+//! them. Credentials for restricted records are set up per host as usual,
+//! e.g. with `token_file` in a `[host."zenodo.org"]` section of the
+//! configuration.
+
+use crate::digest::Algorithm;
+use crate::download::{Checksums, Options};
+use crate::input::Entry;
+use crate::logfile;
+use reqwest::blocking::Client;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// The scheme of DOI URLs.
+pub const SCHEME: &str = "doi:";
+
+/// Where DOIs and datasets are looked up.
+#[derive(Debug, Clone)]
+pub struct Apis {
+    pub resolver: String,
+    pub zenodo: String,
+    pub figshare: String,
+    pub osf: String,
+}
+
+impl Default for Apis {
+    fn default() -> Apis {
+        Apis {
+            resolver: "https://doi.org".to_string(),
+            zenodo: "https://zenodo.org/api".to_string(),
+            figshare: "https://api.figshare.com/v2".to_string(),
+            osf: "https://api.osf.io/v2".to_string(),
+        }
+    }
+}
+
+/// A dataset in one of the supported repositories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    Zenodo(String),
+    /// A Figshare article, and its version if the DOI names one.
+    Figshare(String, Option<String>),
+    Osf(String),
+}
+
+impl Record {
+    /// Recognizes the record of a DOI such as `10.5281/zenodo.1234567` by
+    /// the prefix of its repository.
+    pub fn from_doi(doi: &str) -> Option<Record> {
+        let (prefix, suffix) = doi.split_once('/')?;
+        let suffix = suffix.to_ascii_lowercase();
+        let numeric = |text: &str| !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit());
+        match prefix {
+            "10.5281" => suffix
+                .strip_prefix("zenodo.")
+                .filter(|id| numeric(id))
+                .map(|id| Record::Zenodo(id.to_string())),
+            "10.6084" => {
+                let article = suffix.strip_prefix("m9.figshare.")?;
+                let (id, version) = match article.split_once(".v") {
+                    Some((id, version)) => (id, Some(version.to_string())),
+                    None => (article, None),
+                };
+                (numeric(id) && version.as_deref().is_none_or(numeric))
+                    .then(|| Record::Figshare(id.to_string(), version))
+            }
+            "10.17605" => suffix
+                .strip_prefix("osf.io/")
+                .filter(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_alphanumeric()))
+                .map(|id| Record::Osf(id.to_string())),
+            _ => None,
+        }
+    }
+
+    /// Recognizes the record of the landing page a DOI resolved to.
+    pub fn from_landing(url: &Url) -> Option<Record> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let segments: Vec<&str> = url.path_segments()?.filter(|s| !s.is_empty()).collect();
+        let numeric = |text: &&str| text.bytes().all(|b| b.is_ascii_digit());
+        if host == "zenodo.org" || host.ends_with(".zenodo.org") {
+            return match segments.as_slice() {
+                ["records" | "record", id, ..] if numeric(id) => {
+                    Some(Record::Zenodo(id.to_string()))
+                }
+                _ => None,
+            };
+        }
+        if host == "figshare.com" || host.ends_with(".figshare.com") {
+            // `/articles/TYPE/TITLE/ID` with an optional `/VERSION`.
+            if segments.first() != Some(&"articles") {
+                return None;
+            }
+            let numbers: Vec<&str> = segments
+                .iter()
+                .rev()
+                .take_while(|segment| numeric(segment))
+                .copied()
+                .collect();
+            return match numbers.as_slice() {
+                [id] => Some(Record::Figshare(id.to_string(), None)),
+                [version, id, ..] => {
+                    Some(Record::Figshare(id.to_string(), Some(version.to_string())))
+                }
+                _ => None,
+            };
+        }
+        if host == "osf.io" {
+            return segments
+                .first()
+                .map(|id| Record::Osf(id.to_ascii_lowercase()));
+        }
+        None
+    }
+
+    /// The name of the directory the files are saved in by default.
+    pub fn directory(&self) -> String {
+        match self {
+            Record::Zenodo(id) => format!("zenodo-{}", id),
+            Record::Figshare(id, _) => format!("figshare-{}", id),
+            Record::Osf(id) => format!("osf-{}", id),
+        }
+    }
+}
+
+/// One file of a dataset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct File {
+    pub url: String,
+    /// The path of the file in the dataset.
+    pub path: String,
+    pub checksum: Option<(Algorithm, String)>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ZenodoRecord {
+    files: Vec<ZenodoFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ZenodoFile {
+    key: String,
+    /// `md5:HEX`.
+    checksum: Option<String>,
+    links: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FigshareArticle {
+    files: Vec<FigshareFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FigshareFile {
+    name: String,
+    download_url: String,
+    computed_md5: Option<String>,
+}
+
+/// A page of an OSF listing, in the JSON:API format.
+#[derive(Debug, Deserialize)]
+struct OsfPage {
+    data: Vec<OsfItem>,
+    links: OsfLinks,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsfLinks {
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsfItem {
+    attributes: OsfAttributes,
+    links: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    relationships: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsfAttributes {
+    kind: String,
+    materialized_path: String,
+    #[serde(default)]
+    extra: serde_json::Value,
+}
+
+/// Replaces the `doi:` URLs among `entries` with the files of the datasets
+/// they name, listed through `apis`, saved into `output` or a directory named
+/// after their record.
+///
+/// Also returns the digests the repositories list for the files, by URL.
+///
+/// # Errors
+///
+/// Returns an error if a DOI cannot be resolved, is not that of a dataset
+/// of a supported repository, or its files cannot be listed.
+pub fn expand(
+    client: &Client,
+    entries: Vec<Entry>,
+    apis: &Apis,
+    output: Option<&Path>,
+    options: &Options,
+) -> Result<(Vec<Entry>, Checksums), Box<dyn std::error::Error>> {
+    let mut expanded = Vec::new();
+    let mut checksums = Checksums::new();
+    for entry in entries {
+        let Some(doi) = entry.url.strip_prefix(SCHEME) else {
+            expanded.push(entry);
+            continue;
+        };
+        let doi = doi.trim_start_matches('/');
+        let record = match Record::from_doi(doi) {
+            Some(record) => record,
+            None => resolve(client, apis, doi, options)?,
+        };
+        logfile::info(&format!("Listing: {}", entry.url));
+        let files = match &record {
+            Record::Zenodo(id) => zenodo(client, apis, id, options)?,
+            Record::Figshare(id, version) => {
+                figshare(client, apis, id, version.as_deref(), options)?
+            }
+            Record::Osf(id) => osf(client, apis, id, options)?,
+        };
+        if files.is_empty() {
+            logfile::warning(&format!("No files to download in {}", entry.url));
+        }
+        let dir = match (&entry.output, output) {
+            (Some(dir), _) => dir.clone(),
+            (None, Some(dir)) => dir.to_path_buf(),
+            (None, None) => PathBuf::from(record.directory()),
+        };
+        for file in files {
+            let relative = file.path.trim_start_matches('/');
+            if relative
+                .split('/')
+                .any(|part| part.is_empty() || part == "." || part == "..")
+            {
+                logfile::warning(&format!(
+                    "Skipped: unsafe path '{}' in {}",
+                    file.path, entry.url
+                ));
+                continue;
+            }
+            if let Some(checksum) = file.checksum {
+                checksums.insert(file.url.clone(), checksum);
+            }
+            expanded.push(Entry {
+                url: file.url,
+                priority: entry.priority,
+                output: Some(dir.join(relative)),
+            });
+        }
+    }
+    Ok((expanded, checksums))
+}
+
+/// Finds the record of `doi` by the landing page it resolves to.
+fn resolve(
+    client: &Client,
+    apis: &Apis,
+    doi: &str,
+    options: &Options,
+) -> Result<Record, Box<dyn std::error::Error>> {
+    let url = format!("{}/{}", apis.resolver, doi);
+    let response = options
+        .send(client, &url, |client| client.head(&url))?
+        .error_for_status()
+        .map_err(|err| format!("cannot resolve doi:{}: {}", doi, err))?;
+    Record::from_landing(response.url()).ok_or_else(|| {
+        format!(
+            "doi:{} leads to {}, which is not a Zenodo, Figshare, or OSF dataset",
+            doi,
+            response.url()
+        )
+        .into()
+    })
+}
+
+/// Fetches `url` and parses its JSON body.
+fn get<T: DeserializeOwned>(
+    client: &Client,
+    url: &str,
+    options: &Options,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let response = options
+        .send(client, url, |client| client.get(url))?
+        .error_for_status()
+        .map_err(|err| format!("cannot list {}: {}", url, err))?;
+    Ok(serde_json::from_reader(response)
+        .map_err(|err| format!("invalid listing from {}: {}", url, err))?)
+}
+
+/// Parses a digest given as `md5:HEX` or as plain hex of `algorithm`.
+fn checksum(text: &str, algorithm: Algorithm) -> Option<(Algorithm, String)> {
+    let (algorithm, hex) = match text.split_once(':') {
+        Some((name, hex)) => (Algorithm::parse(name).ok()?, hex),
+        None => (algorithm, text),
+    };
+    (!hex.is_empty() && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+        .then(|| (algorithm, hex.to_ascii_lowercase()))
+}
+
+fn zenodo(
+    client: &Client,
+    apis: &Apis,
+    id: &str,
+    options: &Options,
+) -> Result<Vec<File>, Box<dyn std::error::Error>> {
+    let record: ZenodoRecord = get(client, &format!("{}/records/{}", apis.zenodo, id), options)?;
+    Ok(record
+        .files
+        .into_iter()
+        .filter_map(|file| {
+            let links = &file.links;
+            let url = ["download", "content", "self"]
+                .iter()
+                .find_map(|name| links.get(*name))?
+                .clone();
+            Some(File {
+                url,
+                checksum: file
+                    .checksum
+                    .as_deref()
+                    .and_then(|text| checksum(text, Algorithm::Md5)),
+                path: file.key,
+            })
+        })
+        .collect())
+}
+
+fn figshare(
+    client: &Client,
+    apis: &Apis,
+    id: &str,
+    version: Option<&str>,
+    options: &Options,
+) -> Result<Vec<File>, Box<dyn std::error::Error>> {
+    let url = match version {
+        Some(version) => format!("{}/articles/{}/versions/{}", apis.figshare, id, version),
+        None => format!("{}/articles/{}", apis.figshare, id),
+    };
+    let article: FigshareArticle = get(client, &url, options)?;
+    Ok(article
+        .files
+        .into_iter()
+        .map(|file| File {
+            url: file.download_url,
+            path: file.name,
+            checksum: file
+                .computed_md5
+                .as_deref()
+                .and_then(|text| checksum(text, Algorithm::Md5)),
+        })
+        .collect())
+}
+
+/// Lists the files of the OSF storage of project `id`, descending into its
+/// folders.
+fn osf(
+    client: &Client,
+    apis: &Apis,
+    id: &str,
+    options: &Options,
+) -> Result<Vec<File>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    let mut pending = vec![format!("{}/nodes/{}/files/osfstorage/", apis.osf, id)];
+    while let Some(url) = pending.pop() {
+        let page: OsfPage = get(client, &url, options)?;
+        pending.extend(page.links.next);
+        for item in page.data {
+            let attributes = item.attributes;
+            if attributes.kind == "folder" {
+                let related = &item.relationships["files"]["links"]["related"];
+                let folder = related["href"].as_str().or(related.as_str());
+                pending.extend(folder.map(str::to_string));
+                continue;
+            }
+            let Some(url) = item.links.get("download").and_then(|link| link.as_str()) else {
+                continue;
+            };
+            let hashes = &attributes.extra["hashes"];
+            let checksum = [("sha256", Algorithm::Sha256), ("md5", Algorithm::Md5)]
+                .iter()
+                .find_map(|(name, algorithm)| checksum(hashes[name].as_str()?, *algorithm));
+            files.push(File {
+                url: url.to_string(),
+                path: attributes.materialized_path,
+                checksum,
+            });
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+
+    #[test]
+    fn test_records() {
+        assert_eq!(
+            Record::from_doi("10.5281/zenodo.1234567"),
+            Some(Record::Zenodo("1234567".to_string()))
+        );
+        assert_eq!(
+            Record::from_doi("10.6084/m9.figshare.7890123.v2"),
+            Some(Record::Figshare(
+                "7890123".to_string(),
+                Some("2".to_string())
+            ))
+        );
+        assert_eq!(
+            Record::from_doi("10.17605/OSF.IO/ABCDE"),
+            Some(Record::Osf("abcde".to_string()))
+        );
+        assert_eq!(Record::from_doi("10.1000/xyz123"), None);
+
+        let landing = |url: &str| Record::from_landing(&Url::parse(url).unwrap());
+        assert_eq!(
+            landing("https://zenodo.org/records/42"),
+            Some(Record::Zenodo("42".to_string()))
+        );
+        assert_eq!(
+            landing("https://data.example.figshare.com/articles/dataset/Rainfall/12345/3"),
+            Some(Record::Figshare("12345".to_string(), Some("3".to_string())))
+        );
+        assert_eq!(
+            landing("https://osf.io/abcde/"),
+            Some(Record::Osf("abcde".to_string()))
+        );
+        assert_eq!(landing("https://example.com/paper"), None);
+    }
+
+    #[test]
+    fn test_expand_datasets() {
+        let zenodo = mock("GET", "/doi/zenodo/records/42")
+            .with_body(format!(
+                r#"{{"files": [{{"key": "data/a.csv", "size": 3, "checksum": "md5:900150983CD24FB0D6963F7D28E17F72",
+                    "links": {{"self": "{}/doi/files/a.csv"}}}}]}}"#,
+                server_url()
+            ))
+            .create();
+        let resolver = mock("HEAD", "/doi/resolver/10.9999/inst.figshare.77")
+            .with_status(302)
+            .with_header("location", "/doi/landing")
+            .create();
+        let entries = vec![
+            Entry {
+                url: "doi:10.5281/zenodo.42".to_string(),
+                priority: 0,
+                output: None,
+            },
+            Entry {
+                url: "https://example.com/b.txt".to_string(),
+                priority: 0,
+                output: None,
+            },
+        ];
+        let apis = Apis {
+            resolver: format!("{}/doi/resolver", server_url()),
+            zenodo: format!("{}/doi/zenodo", server_url()),
+            figshare: format!("{}/doi/figshare", server_url()),
+            osf: format!("{}/doi/osf", server_url()),
+        };
+        let (entries, checksums) =
+            expand(&Client::new(), entries, &apis, None, &Options::default()).unwrap();
+        let url = format!("{}/doi/files/a.csv", server_url());
+        assert_eq!(
+            entries,
+            vec![
+                Entry {
+                    url: url.clone(),
+                    priority: 0,
+                    output: Some(PathBuf::from("zenodo-42/data/a.csv")),
+                },
+                Entry {
+                    url: "https://example.com/b.txt".to_string(),
+                    priority: 0,
+                    output: None,
+                },
+            ]
+        );
+        assert_eq!(
+            checksums,
+            Checksums::from([(
+                url,
+                (
+                    Algorithm::Md5,
+                    "900150983cd24fb0d6963f7d28e17f72".to_string()
+                )
+            )])
+        );
+        zenodo.assert();
+
+        // A DOI of another prefix that leads to a page of an unknown site.
+        let entries = vec![Entry {
+            url: "doi:10.9999/inst.figshare.77".to_string(),
+            priority: 0,
+            output: None,
+        }];
+        let landing = mock("HEAD", "/doi/landing").create();
+        let err = expand(&Client::new(), entries, &apis, None, &Options::default()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("not a Zenodo, Figshare, or OSF dataset"));
+        resolver.assert();
+        landing.assert();
+    }
+
+    #[test]
+    fn test_list_osf() {
+        let next = format!(
+            "{}/doi/osf/nodes/abcde/files/osfstorage/?page=2",
+            server_url()
+        );
+        let folder = format!("{}/doi/osf/folder", server_url());
+        let first = mock("GET", "/doi/osf/nodes/abcde/files/osfstorage/")
+            .match_query(mockito::Matcher::Missing)
+            .with_body(format!(
+                r#"{{"data": [{{"attributes": {{"kind": "folder", "materialized_path": "/raw/"}}, "links": {{}},
+                    "relationships": {{"files": {{"links": {{"related": {{"href": "{}"}}}}}}}}}}],
+                    "links": {{"next": "{}"}}}}"#,
+                folder, next
+            ))
+            .create();
+        let second = mock("GET", "/doi/osf/nodes/abcde/files/osfstorage/")
+            .match_query(mockito::Matcher::UrlEncoded("page".to_string(), "2".to_string()))
+            .with_body(
+                r#"{"data": [{"attributes": {"kind": "file", "materialized_path": "/README.md",
+                    "extra": {"hashes": {"md5": "AB", "sha256": "CD"}}},
+                    "links": {"download": "https://osf.io/download/1/"}}], "links": {"next": null}}"#,
+            )
+            .create();
+        let inner = mock("GET", "/doi/osf/folder")
+            .with_body(
+                r#"{"data": [{"attributes": {"kind": "file", "materialized_path": "/raw/a.csv", "extra": {}},
+                    "links": {"download": "https://osf.io/download/2/"}}], "links": {"next": null}}"#,
+            )
+            .create();
+        let apis = Apis {
+            osf: format!("{}/doi/osf", server_url()),
+            ..Apis::default()
+        };
+        let mut files = osf(&Client::new(), &apis, "abcde", &Options::default()).unwrap();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(
+            files,
+            vec![
+                File {
+                    url: "https://osf.io/download/1/".to_string(),
+                    path: "/README.md".to_string(),
+                    checksum: Some((Algorithm::Sha256, "cd".to_string())),
+                },
+                File {
+                    url: "https://osf.io/download/2/".to_string(),
+                    path: "/raw/a.csv".to_string(),
+                    checksum: None,
+                },
+            ]
+        );
+        first.assert();
+        second.assert();
+        inner.assert();
+    }
+}
@@ -0,0 +1,2680 @@
+//! Streaming transfer engine shared by one-shot downloads and the daemon queue.
+//!
+//! Transfers are written chunk by chunk so that a running download can be
+//! observed (bytes received, expected size) and steered (paused, cancelled)
+//! from another thread through a shared [`Control`].
+
+use crate::allocate::{Allocation, Reservation};
+use crate::auth::{self, Credentials, Keyring};
+use crate::content;
+use crate::decompress;
+use crate::dedupe::Registry;
+use crate::digest::{self, Algorithm, Digests, Hasher, HashingWriter};
+use crate::direct::DirectWriter;
+use crate::extension;
+use crate::filename::{self, Restriction};
+use crate::gemini;
+use crate::hosts::Hosts;
+use crate::kubernetes::ServiceAccount;
+use crate::ledger::{self, Ledger, Record};
+use crate::lfs;
+use crate::lockfile::Lockfile;
+use crate::logfile;
+use crate::mirror::MirrorList;
+use crate::oversize;
+use crate::overwrite::{self, Decision};
+use crate::partial::{self, Recorder};
+use crate::permissions::{self, Owner};
+use crate::realm::Tokens;
+use crate::render::Browser;
+use crate::resume::{self, State};
+use crate::scan;
+use crate::secrets::Secrets;
+use crate::segment;
+use crate::share;
+use crate::sizes::Bounds;
+use crate::smb;
+use crate::split::{self, SplitWriter};
+use crate::staging;
+use crate::throttle::{MinSpeed, SpeedCheck, Throttle};
+use crate::transport::Transport;
+use crate::units::ByteRange;
+use crate::wayback::Wayback;
+use crate::webhook::Webhook;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{
+    CONTENT_DISPOSITION, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE,
+    LAST_MODIFIED, RANGE,
+};
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+pub use crate::filename::default_filename;
+
+/// Default size of the buffer used by the copy loop.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Settings that apply to every transfer of a run.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Number of attempts per download before giving up.
+    pub tries: u32,
+    /// Bandwidth limit shared by all transfers, if any.
+    pub throttle: Option<Arc<Throttle>>,
+    /// Where completed downloads are recorded, if anywhere.
+    pub ledger: Option<Arc<Ledger>>,
+    /// Whether intact files in the ledger are only downloaded again if the
+    /// server answers a request conditional on their ETag and `Last-Modified`
+    /// time with new content.
+    pub if_changed: bool,
+    /// Other servers to fall back to when a transfer fails, if any.
+    pub mirrors: Option<Arc<MirrorList>>,
+    /// Where snapshots are downloaded from instead of, or after failing, the
+    /// live URL (see [`wayback`](crate::wayback)).
+    pub wayback: Option<Arc<Wayback>>,
+    /// The certificates trusted for Gemini hosts (see [`gemini`]).
+    pub known_hosts: Arc<gemini::KnownHosts>,
+    /// How long a connection that the client does not make, such as one to a
+    /// Gemini host, may stay silent.
+    pub stall_timeout: Duration,
+    /// Expected SHA-256 digest (lowercase hex) of every downloaded file.
+    pub checksum: Option<String>,
+    /// Expected digests of particular URLs, such as those of files kept in
+    /// LFS on the Hugging Face Hub (see [`hub`](crate::hub)).
+    pub checksums: Arc<Checksums>,
+    /// Where downloads are pinned and checked against their pins (see
+    /// [`lockfile`](crate::lockfile)).
+    pub lockfile: Option<Arc<Lockfile>>,
+    /// Whether a Git LFS pointer whose object cannot be fetched fails its
+    /// download rather than being kept (see [`lfs`]).
+    pub lfs: bool,
+    /// Whether to fetch different parts of a file from different mirrors at once.
+    pub stripe: bool,
+    /// Credentials sent with every request, if any.
+    pub credentials: Option<Credentials>,
+    /// Where per-host credentials are looked up when no `credentials` are set.
+    pub keyring: Option<Arc<Keyring>>,
+    /// The Kubernetes service account whose token in-cluster services are
+    /// sent when no other credentials apply.
+    pub service_account: Option<Arc<ServiceAccount>>,
+    /// Credentials for an NTLM or Negotiate proxy, if any.
+    pub proxy_credentials: Option<Credentials>,
+    /// Which responses are accepted, by their content type.
+    pub content: content::Policy,
+    /// The part of every resource to download, if not all of it.
+    pub range: Option<ByteRange>,
+    /// Whether `.gz`, `.zst`, and `.xz` resources are decompressed as they arrive.
+    pub decompress: bool,
+    /// Whether partial files keep block digests with which they are checked before they are resumed.
+    pub verify_partial: bool,
+    /// Where the digests of the files saved in the run are kept to find
+    /// duplicates among them, if they are looked for.
+    pub dedupe: Option<Arc<Registry>>,
+    /// What becomes of an existing file that a completed download would be
+    /// renamed onto, as with `--content-disposition`.
+    pub overwrite: overwrite::Policy,
+    /// The browser that HTML pages are saved as rendered by, if any (see
+    /// [`render`](crate::render)).
+    pub render: Option<Arc<Browser>>,
+    /// Size of the part files each download is split into, if it is split.
+    pub split_output: Option<u64>,
+    /// Digests printed for every downloaded file.
+    pub hashes: Vec<Algorithm>,
+    /// Whether the printed digests are also written to `file.<algorithm>` files.
+    pub hash_file: bool,
+    /// Whether files saved without an extension get one after their content.
+    pub auto_extension: bool,
+    /// Whether files are renamed after the name in their `Content-Disposition` header.
+    pub content_disposition: bool,
+    /// Whether files are renamed after the URL they were redirected to.
+    pub trust_server_names: bool,
+    /// How the space of a file of known size is reserved before it is written.
+    pub file_allocation: Allocation,
+    /// Whether completed files and their directories are flushed to disk before success is reported.
+    pub sync: bool,
+    /// Size of the buffer each transfer reads into.
+    pub buffer_size: usize,
+    /// Whether single-connection downloads are written with direct I/O.
+    pub direct_io: bool,
+    /// Which characters are allowed in file names chosen by servers.
+    pub restrict_file_names: Restriction,
+    /// Mode given to completed files, if any.
+    pub mode: Option<u32>,
+    /// Owner and group given to completed files, if any.
+    pub owner: Option<Owner>,
+    /// Whether completed files get the modification time of their `Last-Modified` header.
+    pub server_timestamps: bool,
+    /// Directory in which files are written until they are complete, if not in place.
+    pub tmp_dir: Option<PathBuf>,
+    /// Command that must accept a file in `tmp_dir` before it is moved into place, if any.
+    pub scan_command: Option<String>,
+    /// Rate below which a single-connection transfer is abandoned, if any.
+    pub min_speed: Option<MinSpeed>,
+    /// Size over which a download needs confirmation, if any.
+    pub confirm_over: Option<oversize::Limit>,
+    /// The sizes of the files downloaded; downloads of other sizes are skipped.
+    pub size_bounds: Bounds,
+    /// Headers, credentials, limits, and clients of configured hosts.
+    pub hosts: Arc<Hosts>,
+    /// Header values and the password that come from commands.
+    pub secrets: Arc<Secrets>,
+    /// What sends requests instead of the client, if anything (see [`transport`](crate::transport)).
+    pub transport: Option<Arc<dyn Transport>>,
+    /// Where the end of every download is posted, if anywhere (see [`webhook`](crate::webhook)).
+    pub webhook: Option<Arc<Webhook>>,
+    /// Bearer tokens obtained from the realms of `401` challenges (see [`realm`](crate::realm)).
+    pub tokens: Arc<Tokens>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            tries: 1,
+            throttle: None,
+            ledger: None,
+            if_changed: false,
+            mirrors: None,
+            wayback: None,
+            known_hosts: Arc::default(),
+            stall_timeout: Duration::from_secs(30),
+            checksum: None,
+            checksums: Arc::default(),
+            lockfile: None,
+            lfs: false,
+            stripe: false,
+            credentials: None,
+            keyring: None,
+            service_account: None,
+            proxy_credentials: None,
+            content: content::Policy::default(),
+            range: None,
+            decompress: false,
+            verify_partial: false,
+            dedupe: None,
+            overwrite: overwrite::Policy::default(),
+            render: None,
+            split_output: None,
+            hashes: Vec::new(),
+            hash_file: false,
+            auto_extension: false,
+            content_disposition: false,
+            trust_server_names: false,
+            file_allocation: Allocation::None,
+            sync: false,
+            buffer_size: CHUNK_SIZE,
+            direct_io: false,
+            restrict_file_names: Restriction::default(),
+            mode: None,
+            owner: None,
+            server_timestamps: true,
+            tmp_dir: None,
+            scan_command: None,
+            min_speed: None,
+            confirm_over: None,
+            size_bounds: Bounds::default(),
+            hosts: Arc::default(),
+            secrets: Arc::default(),
+            transport: None,
+            webhook: None,
+            tokens: Arc::default(),
+        }
+    }
+}
+
+impl Options {
+    /// Sends the request made by `build` for `url` with the headers and
+    /// credentials that apply to it.
+    ///
+    /// The request is built on `client`, or on the client of the host's TLS
+    /// settings if it has one, once the host's [`Pace`](crate::hosts::Pace)
+    /// allows it. Credentials given on the command line come
+    /// first, then those of the host, then those in the keyring; headers from
+    /// commands replace those of the host. See [`auth::send`] for how
+    /// challenge-response authentication repeats the request. Without
+    /// credentials or with Basic ones, a `401` naming a `Bearer` realm is
+    /// answered with a token from it, which is then sent to the host up front
+    /// (see [`realm`](crate::realm)). With [`Options::transport`], the request
+    /// goes to the transport instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails, or if a command that prints a
+    /// secret for it fails.
+    pub fn send(
+        &self,
+        client: &Client,
+        url: &str,
+        build: impl Fn(&Client) -> RequestBuilder,
+    ) -> Result<Response, Box<dyn std::error::Error>> {
+        let host = self.hosts.get(url);
+        let client = host.and_then(|host| host.client.as_ref()).unwrap_or(client);
+        let headers = self.secrets.headers()?;
+        if let Some(pace) = host.and_then(|host| host.pace.as_ref()) {
+            pace.wait();
+        }
+        if let Some(transport) = &self.transport {
+            let request = match host {
+                Some(host) => host.apply(build(client)),
+                None => build(client),
+            };
+            return transport.execute(request.headers(headers).build()?);
+        }
+        let credentials = self.credentials(url)?;
+        let credentials = credentials.as_ref();
+        let send = |token: Option<&str>| {
+            auth::send(
+                || {
+                    let request = match host {
+                        Some(host) => host.apply(build(client)),
+                        None => build(client),
+                    };
+                    let request = request.headers(headers.clone());
+                    match token {
+                        Some(token) => request.bearer_auth(token),
+                        None => request,
+                    }
+                },
+                credentials.filter(|_| token.is_none()),
+                self.proxy_credentials.as_ref(),
+            )
+        };
+        if !matches!(credentials, None | Some(Credentials::Basic { .. })) {
+            return Ok(send(None)?);
+        }
+        let token = self.tokens.current(url);
+        let response = send(token.as_deref())?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+        match self
+            .tokens
+            .answer(client, url, &response, credentials, token.as_deref())?
+        {
+            Some(token) => {
+                response.bytes()?;
+                Ok(send(Some(&token))?)
+            }
+            None => Ok(response),
+        }
+    }
+
+    /// Returns the credentials for `url`: those given on the command line,
+    /// then those of its host, then those in the keyring, then the token of
+    /// the service account if `url` is an in-cluster service.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a command that prints a password fails.
+    pub fn credentials(&self, url: &str) -> Result<Option<Credentials>, String> {
+        let credentials = self
+            .credentials
+            .as_ref()
+            .or(self.secrets.credentials()?)
+            .or(self
+                .hosts
+                .get(url)
+                .and_then(|host| host.credentials.as_ref()));
+        let credentials = match (credentials, &self.keyring) {
+            (None, Some(keyring)) => keyring.lookup(url),
+            _ => credentials.cloned(),
+        };
+        Ok(credentials.or_else(|| self.service_account.as_ref()?.credentials(url)))
+    }
+
+    /// Accounts for `bytes` received from `url` against the bandwidth limits.
+    pub fn consume(&self, url: &str, bytes: usize) {
+        if let Some(throttle) = &self.throttle {
+            throttle.consume(bytes);
+        }
+        if let Some(throttle) = self.hosts.get(url).and_then(|host| host.throttle.as_ref()) {
+            throttle.consume(bytes);
+        }
+    }
+}
+
+/// Expected digests (lowercase hex) of files, by URL.
+pub type Checksums = HashMap<String, (Algorithm, String)>;
+
+/// Shared handle used to observe and steer a running transfer.
+///
+/// A `Control` is cheap to share behind an `Arc`; the transfer loop checks the
+/// pause and cancel flags between chunks and publishes its progress counters
+/// after every write.
+#[derive(Debug, Default)]
+pub struct Control {
+    paused: AtomicBool,
+    cancelled: AtomicBool,
+    downloaded: AtomicU64,
+    total: AtomicU64,
+}
+
+impl Control {
+    /// Asks the transfer to stop at the next chunk boundary, keeping the partial file.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Asks the transfer to stop at the next chunk boundary and give up on the job.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Number of bytes of the resource present on disk so far.
+    pub fn downloaded(&self) -> u64 {
+        self.downloaded.load(Ordering::SeqCst)
+    }
+
+    /// Full size of the resource, if the server announced it.
+    pub fn total(&self) -> Option<u64> {
+        match self.total.load(Ordering::SeqCst) {
+            0 => None,
+            total => Some(total),
+        }
+    }
+
+    /// Publishes the bytes already on disk and the expected size as a transfer starts.
+    pub(crate) fn start(&self, downloaded: u64, total: Option<u64>) {
+        self.downloaded.store(downloaded, Ordering::SeqCst);
+        self.total.store(total.unwrap_or(0), Ordering::SeqCst);
+    }
+
+    /// Adds freshly written bytes to the progress counter.
+    pub(crate) fn advance(&self, bytes: u64) {
+        self.downloaded.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    /// Returns how the transfer must stop if a pause or cancel was requested.
+    pub(crate) fn interruption(&self) -> Option<Outcome> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            Some(Outcome::Cancelled)
+        } else if self.paused.load(Ordering::SeqCst) {
+            Some(Outcome::Paused)
+        } else {
+            None
+        }
+    }
+}
+
+/// How a call to [`fetch`] ended when it did not fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The whole resource was written to disk.
+    Completed,
+    /// The transfer stopped because [`Control::pause`] was called.
+    Paused,
+    /// The transfer stopped because [`Control::cancel`] was called.
+    Cancelled,
+    /// The download was left out on purpose: for its size or content type,
+    /// as a duplicate of a file saved earlier, or when declined at
+    /// `--confirm-over`.
+    Skipped,
+}
+
+/// Streams a URL into a file on disk.
+///
+/// When [`Options::mirrors`] is set, a transfer that fails or whose content
+/// does not match [`Options::checksum`] or [`Options::checksums`] is retried from the next mirror,
+/// resuming the partial file where that is safe. With [`Options::stripe`],
+/// byte ranges are first fetched from all mirrors at once when they support
+/// range requests, skipping those the control file of an earlier run records
+/// as written (see [`resume`]). With [`Options::wayback`], a snapshot of `url`
+/// is downloaded instead of it, or after it and every mirror have failed.
+/// With [`Options::tmp_dir`], the file is written there and
+/// moved to `path` once it is complete and, with [`Options::scan_command`],
+/// scanned. A file with a checksum or a pin to verify is otherwise written
+/// beside `path`, so that one that fails verification is removed without
+/// ever replacing it. With [`Options::if_changed`], a file that the ledger records as
+/// downloaded from `url` is kept as it is when the server answers `304 Not
+/// Modified`.
+///
+/// # Arguments
+///
+/// * `client`: The HTTP client used for making requests.
+/// * `url`: The URL of the resource to download.
+/// * `path`: Where the resource is written.
+/// * `resume`: Whether to continue an existing partial file with a `Range` request.
+/// * `control`: Shared handle through which progress is reported and pause/cancel requests arrive.
+/// * `options`: Settings of the run, such as the bandwidth limit.
+///
+/// # Returns
+///
+/// * `Result<Outcome, Box<dyn std::error::Error>>`: How the transfer ended, or an error if it failed.
+///
+/// # Errors
+///
+/// This function can return errors in the following cases:
+/// * If the HTTP request fails
+/// * If the server returns a non-success status code
+/// * If there's an issue creating or writing to the output file
+/// * If the downloaded file does not match the expected checksum
+///
+/// With mirrors, the error of the last mirror tried is returned.
+pub fn fetch(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    resume: bool,
+    control: &Control,
+    options: &Options,
+) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let extended = filename::long_path(path);
+    let target = extended.as_path();
+    let recorded = match &options.ledger {
+        Some(ledger) if options.if_changed && !resume && options.range.is_none() => {
+            ledger.current(url, target)
+        }
+        _ => None,
+    };
+    let stageable = !is_stdout(target) && options.split_output.is_none();
+    let staged = match &options.tmp_dir {
+        Some(dir) if stageable => Some(staging::prepare(dir, target, resume)?),
+        // A file that may fail verification must not replace the target.
+        None if stageable && verified(url, options) => {
+            let dir = target.parent().unwrap_or(Path::new(""));
+            Some(staging::prepare(dir, target, resume)?)
+        }
+        _ => None,
+    };
+    let path = staged.as_deref().unwrap_or(target);
+    let candidates = match (&options.wayback, &options.mirrors) {
+        (Some(wayback), _) if wayback.timestamp.is_some() => {
+            vec![wayback.locate(client, url, options)?]
+        }
+        (_, Some(mirrors)) => mirrors.ordered(client, url, options),
+        _ => vec![url.to_string()],
+    };
+
+    let mut resume = resume;
+    let mut last_error = None;
+    let whole_file =
+        options.range.is_none() && !options.decompress && options.split_output.is_none();
+    if options.stripe && whole_file && candidates.len() > 1 {
+        if let Some(mut state) = segment::probe(client, &candidates[0], options) {
+            if let Some(limit) = options.confirm_over {
+                if !oversize::allow(url, state.size, limit)? {
+                    return Ok(Outcome::Skipped);
+                }
+            }
+            let skipped = options
+                .size_bounds
+                .reject(state.size)
+                .or_else(|| options.content.skips(state.content_type.as_deref()));
+            if let Some(reason) = skipped {
+                logfile::info(&format!("Skipping {}: {}", url, reason));
+                return Ok(Outcome::Skipped);
+            }
+            if resume {
+                match State::load(path) {
+                    Some(saved) if saved.same_resource(&state) => {
+                        saved.done.into_iter().for_each(|range| state.add(range))
+                    }
+                    Some(_) => logfile::warning(&format!(
+                        "{} changed on the server since the partial download; starting over",
+                        url
+                    )),
+                    None => {
+                        let len = fs::metadata(path).map_or(0, |meta| meta.len());
+                        state.add(0..len.min(state.size));
+                    }
+                }
+            }
+            match segment::fetch(client, &candidates, path, state, control, options) {
+                Ok(Outcome::Completed) => {
+                    // Pieces arrive out of order, so the file is hashed afterwards.
+                    let received = Received {
+                        digests: digest::file(path, &algorithms(options))?,
+                        ..Received::default()
+                    };
+                    match mismatch(path, url, "the mirrors", &received, options)? {
+                        None => return complete(url, path, target, &received, options),
+                        Some(err) => {
+                            discard_staged(path, target)?;
+                            resume = false;
+                            last_error = Some(err.into());
+                        }
+                    }
+                }
+                Ok(outcome) => return Ok(outcome),
+                Err(err) => {
+                    logfile::warning(&format!("Striped download failed: {}", err));
+                    resume = true;
+                    last_error = Some(err);
+                }
+            }
+        }
+    }
+
+    // A single connection can only continue the prefix of a segmented download.
+    if resume {
+        resume::keep_prefix(path)?;
+    }
+    // Looked up only once the live URL and every mirror have failed.
+    let live = candidates.len();
+    let archived = std::iter::once_with(|| {
+        let wayback = options
+            .wayback
+            .as_ref()
+            .filter(|wayback| wayback.timestamp.is_none())?;
+        wayback.fall_back(client, url, options)
+    })
+    .flatten();
+    for (index, candidate) in candidates.iter().cloned().chain(archived).enumerate() {
+        if index > 0 && index < live {
+            logfile::warning(&format!("Trying mirror: {}", candidate));
+        }
+        // Other servers have validators of their own, and a snapshot may
+        // differ from what was received live.
+        let existing = match (&recorded, index) {
+            (Some(record), 0) if !resume => Existing::Revalidate(record),
+            _ if resume && index < live => Existing::Resume,
+            _ => Existing::Replace,
+        };
+        let candidate = candidate.as_str();
+        let received = match transfer(client, candidate, path, existing, control, options, true) {
+            Ok((Outcome::Completed, received)) if received.unchanged => {
+                logfile::info(&format!("Not modified: {}", target.display()));
+                return Ok(Outcome::Completed);
+            }
+            Ok((Outcome::Completed, received)) => {
+                resolve_lfs(client, candidate, path, received, options)?
+            }
+            Ok((outcome, _)) => return Ok(outcome),
+            // Bytes already written to standard output cannot be taken back.
+            Err(err) if is_stdout(path) && control.downloaded() > 0 => return Err(err),
+            Err(err) => {
+                // Keep what was received: the next mirror serves the same bytes.
+                resume = true;
+                last_error = Some(err);
+                continue;
+            }
+        };
+        match mismatch(path, url, candidate, &received, options)? {
+            None => return complete(url, path, target, &received, options),
+            Some(err) => {
+                discard_staged(path, target)?;
+                resume = false;
+                last_error = Some(err.into());
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| "no URL to download".into()))
+}
+
+/// Replaces a Git LFS pointer downloaded from `url` to `path` with its object,
+/// computing the digests of the object instead (see [`lfs`]).
+///
+/// Without [`Options::lfs`], a pointer whose object cannot be fetched is kept
+/// with a warning.
+fn resolve_lfs(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    received: Received,
+    options: &Options,
+) -> Result<Received, Box<dyn std::error::Error>> {
+    if is_stdout(path) || options.range.is_some() || options.split_output.is_some() {
+        return Ok(received);
+    }
+    match lfs::resolve(client, url, path, options) {
+        Ok(true) => Ok(Received {
+            content_type: None,
+            digests: digest::file(path, &algorithms(options))?,
+            ..received
+        }),
+        Ok(false) => Ok(received),
+        Err(err) if options.lfs => Err(err),
+        Err(err) => {
+            logfile::warning(&format!("Warning: {}", err));
+            Ok(received)
+        }
+    }
+}
+
+/// Returns the digests computed during a transfer: those to print, those of
+/// [`Options::checksums`], and SHA-256 when a checksum is to be verified,
+/// duplicates are looked for, or downloads are pinned.
+fn algorithms(options: &Options) -> Vec<Algorithm> {
+    let mut algorithms = options.hashes.clone();
+    if options.checksum.is_some() || options.dedupe.is_some() || options.lockfile.is_some() {
+        algorithms.push(Algorithm::Sha256);
+    }
+    for (algorithm, _) in options.checksums.values() {
+        if !algorithms.contains(algorithm) {
+            algorithms.push(*algorithm);
+        }
+    }
+    algorithms
+}
+
+/// Whether a download of `url` is checked against a checksum or a pin once
+/// it is complete.
+fn verified(url: &str, options: &Options) -> bool {
+    options.checksum.is_some()
+        || options.checksums.contains_key(url)
+        || options
+            .lockfile
+            .as_ref()
+            .is_some_and(|lockfile| lockfile.get(url).is_some())
+}
+
+/// Removes a staged download at `path` that failed verification, so that
+/// neither it nor a partial file is left behind. A file written directly to
+/// `target` is left alone.
+fn discard_staged(path: &Path, target: &Path) -> io::Result<()> {
+    if path == target {
+        return Ok(());
+    }
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Checks a file downloaded for `url` from `source` against
+/// [`Options::checksum`], or its own digest in [`Options::checksums`], and
+/// against its pin in [`Options::lockfile`], describing any mismatch.
+///
+/// The digests of `received` are used when they include the one needed;
+/// otherwise the file is read.
+fn mismatch(
+    path: &Path,
+    url: &str,
+    source: &str,
+    received: &Received,
+    options: &Options,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let digest = |wanted: Algorithm| -> io::Result<String> {
+        Ok(
+            match received
+                .digests
+                .iter()
+                .find(|(algorithm, _)| *algorithm == wanted)
+            {
+                Some((_, digest)) => digest.clone(),
+                None => digest::file(path, &[wanted])?
+                    .pop()
+                    .map(|(_, digest)| digest)
+                    .unwrap_or_default(),
+            },
+        )
+    };
+    let expected = match (&options.checksum, options.checksums.get(url)) {
+        (Some(expected), _) => Some((Algorithm::Sha256, expected)),
+        (None, Some((algorithm, expected))) => Some((*algorithm, expected)),
+        (None, None) => None,
+    };
+    if let Some((wanted, expected)) = expected {
+        let actual = digest(wanted)?;
+        if actual != *expected {
+            return Ok(Some(format!(
+                "Checksum mismatch for {}: expected {}, got {}",
+                source, expected, actual
+            )));
+        }
+    }
+    let Some(lockfile) = options.lockfile.as_ref().filter(|_| !is_stdout(path)) else {
+        return Ok(None);
+    };
+    // Mirrors and snapshots are served from URLs of their own.
+    let final_url = (source == url).then(|| received.redirected.as_ref().map_or(url, Url::as_str));
+    let size = fs::metadata(path)?.len();
+    Ok(lockfile.drift(url, final_url, size, &digest(Algorithm::Sha256)?))
+}
+
+/// Scans a verified download and moves it from `path` to `target`, renames it as the server
+/// or its content suggest, replaces a page with its rendering, links or removes it if it duplicates a file saved
+/// earlier in the run, reports its requested digests, dates it by its
+/// `Last-Modified` header, gives its files the requested mode and owner, and
+/// records it in the ledger and pins it in the lockfile, if there are any.
+fn complete(
+    url: &str,
+    path: &Path,
+    target: &Path,
+    received: &Received,
+    options: &Options,
+) -> Result<Outcome, Box<dyn std::error::Error>> {
+    if path != target {
+        if let Some(command) = &options.scan_command {
+            scan::run(command, path)?;
+        }
+        staging::move_file(path, target)?;
+    }
+    let renamed = rename(target, received, options)?;
+    let path = renamed.as_deref().unwrap_or(target);
+    let browser = options.render.as_ref().filter(|_| {
+        !is_stdout(path)
+            && options.split_output.is_none()
+            && received.content_type.as_deref().map(content::essence) == Some("text/html".into())
+    });
+    if let Some(browser) = browser {
+        fs::write(path, browser.render(url)?)?;
+    }
+    let sha256 = received
+        .digests
+        .iter()
+        .find(|(algorithm, _)| *algorithm == Algorithm::Sha256);
+    // The shells of single-page applications are alike until they are rendered.
+    if let (Some(registry), Some((_, digest))) = (
+        options.dedupe.as_ref().filter(|_| browser.is_none()),
+        sha256,
+    ) {
+        if !is_stdout(path) && options.split_output.is_none() && registry.dedupe(path, digest)? {
+            return Ok(Outcome::Skipped);
+        }
+    }
+    let mut written = match options.split_output {
+        Some(_) => (0..)
+            .map(|index| split::part_path(path, index))
+            .take_while(|part| part.exists())
+            .collect(),
+        None => vec![path.to_path_buf()],
+    };
+
+    let requested: Digests = received
+        .digests
+        .iter()
+        .filter(|(algorithm, _)| options.hashes.contains(algorithm))
+        .cloned()
+        .collect();
+    if !requested.is_empty() {
+        let report = digest::format(path, &requested);
+        if is_stdout(path) {
+            logfile::notice(report.trim_end());
+        } else {
+            logfile::info(report.trim_end());
+            if options.hash_file {
+                written.extend(digest::write_files(path, &requested)?);
+            }
+        }
+    }
+
+    // The time is set first, as the requested mode may not allow writing.
+    if let Some(modified) = received.modified.filter(|_| options.server_timestamps) {
+        if !is_stdout(path) && options.split_output.is_none() {
+            File::options()
+                .write(true)
+                .open(path)?
+                .set_modified(modified)?;
+        }
+    }
+    if !is_stdout(path) {
+        for file in &written {
+            permissions::apply(file, options.mode, options.owner)?;
+        }
+    }
+
+    if options.sync && !is_stdout(path) {
+        sync(&written)?;
+    }
+
+    // Standard output and split files leave no single file to fingerprint.
+    let saved = !is_stdout(path) && options.split_output.is_none();
+    if let Some(ledger) = options.ledger.as_ref().filter(|_| saved) {
+        let last_modified = received.modified.map(|modified| {
+            chrono::DateTime::<chrono::Utc>::from(modified)
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string()
+        });
+        ledger.record(url, path, received.etag.clone(), last_modified)?;
+    }
+    if let Some(lockfile) = options.lockfile.as_ref().filter(|_| saved) {
+        let final_url = received.redirected.as_ref().map_or(url, Url::as_str);
+        let sha256 = match sha256 {
+            Some((_, digest)) => digest.clone(),
+            None => ledger::sha256_file(path)?,
+        };
+        lockfile.pin(url, final_url, fs::metadata(path)?.len(), &sha256)?;
+    }
+    Ok(Outcome::Completed)
+}
+
+/// Flushes `files` and the directories holding them to disk.
+fn sync(files: &[PathBuf]) -> io::Result<()> {
+    let mut directories = Vec::new();
+    for file in files {
+        File::open(file)?.sync_all()?;
+        let directory = match file.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        if !directories.contains(&directory) {
+            directories.push(directory);
+        }
+    }
+    // Only Unix-like systems can open a directory to flush its entries.
+    if cfg!(unix) {
+        for directory in directories {
+            File::open(directory)?.sync_all()?;
+        }
+    }
+    Ok(())
+}
+
+/// What a completed transfer learned about the resource.
+#[derive(Debug, Default)]
+struct Received {
+    /// The `ETag` of the response, if any.
+    etag: Option<String>,
+    /// The `Content-Type` of the response, if any.
+    content_type: Option<String>,
+    /// The file name in the `Content-Disposition` of the response, if any.
+    filename: Option<String>,
+    /// The URL the request was redirected to, if it was.
+    redirected: Option<Url>,
+    /// The `Last-Modified` time of the response, if any.
+    modified: Option<SystemTime>,
+    /// The digests of the written data that [`algorithms`] asked for.
+    digests: Digests,
+    /// Whether the server answered `304 Not Modified`, so nothing was written.
+    unchanged: bool,
+}
+
+/// What a transfer does with a file already at its path.
+#[derive(Debug, Clone, Copy)]
+enum Existing<'a> {
+    /// Overwrites it.
+    Replace,
+    /// Continues it with a `Range` request.
+    Resume,
+    /// Keeps it if the server answers a request conditional on the ETag and
+    /// `Last-Modified` time of its record with `304 Not Modified`, and
+    /// overwrites it otherwise.
+    Revalidate(&'a Record),
+}
+
+/// Renames a completed file after the URL it was finally redirected to, the
+/// sanitized name in its `Content-Disposition`, and, if it then has no
+/// extension, its content as detected by [`extension::detect`], each as far as
+/// the options ask for it.
+///
+/// Standard output and split downloads are left alone. The content type of a
+/// decompressed file is ignored, as it describes the compressed data. A file
+/// already at the new name is treated as [`Options::overwrite`] says: it is
+/// replaced, the download takes the next free numbered name, or the download
+/// keeps its own name.
+///
+/// # Returns
+///
+/// * `io::Result<Option<PathBuf>>`: The new path, or `None` if the file kept its name.
+fn rename(path: &Path, received: &Received, options: &Options) -> io::Result<Option<PathBuf>> {
+    if is_stdout(path) || options.split_output.is_some() {
+        return Ok(None);
+    }
+    let original = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut name = original.to_string();
+    if let Some(redirected) = received
+        .redirected
+        .as_ref()
+        .filter(|_| options.trust_server_names)
+    {
+        let derived = match options.auto_extension {
+            true => extension::base_name(redirected),
+            false => default_filename(redirected),
+        };
+        let derived = match options.decompress {
+            true => decompress::strip_extension(&derived),
+            false => &derived,
+        };
+        if let Some(derived) = filename::sanitize(derived, options.restrict_file_names) {
+            name = derived;
+        }
+    }
+    if let Some(suggested) = received
+        .filename
+        .as_deref()
+        .filter(|_| options.content_disposition)
+        .and_then(|suggested| filename::sanitize(suggested, options.restrict_file_names))
+    {
+        name = suggested;
+    }
+    if options.auto_extension && !extension::has_extension(&name) {
+        let mut head = Vec::new();
+        File::open(path)?.take(512).read_to_end(&mut head)?;
+        let content_type = received
+            .content_type
+            .as_deref()
+            .filter(|_| !options.decompress);
+        if let Some(extension) = extension::detect(content_type, &head) {
+            name = format!("{}.{}", name, extension);
+        }
+    }
+    if name == original {
+        return Ok(None);
+    }
+    let mut renamed = path.with_file_name(name);
+    match overwrite::decide(&renamed, options.overwrite)? {
+        Decision::Overwrite => {}
+        Decision::Rename(free) => renamed = free,
+        // A completed download has nothing to resume.
+        Decision::Resume | Decision::Skip => {
+            logfile::info(&format!(
+                "Kept {}, as {} exists",
+                path.display(),
+                renamed.display()
+            ));
+            return Ok(None);
+        }
+    }
+    fs::rename(path, &renamed)?;
+    logfile::info(&format!(
+        "Saved {} as {}",
+        path.display(),
+        renamed.display()
+    ));
+    Ok(Some(renamed))
+}
+
+/// A transfer that stopped receiving data after this many bytes, which is
+/// resumed from a new request.
+#[derive(Debug)]
+struct Stalled(u64);
+
+impl fmt::Display for Stalled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Transfer stalled after {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for Stalled {}
+
+/// Streams a single URL into `path`, returning how it ended and what was received.
+///
+/// `existing` says what becomes of a file already at `path`. With `confirm`, the download behind a Google Drive warning page is followed.
+/// When a response stops sending data for longer than the client's timeout
+/// after some of it arrived, the connection is dropped and the file resumed
+/// from a new request, up to `options.tries` times, unless it is decompressed
+/// or written to standard output.
+fn transfer(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    existing: Existing,
+    control: &Control,
+    options: &Options,
+    confirm: bool,
+) -> Result<(Outcome, Received), Box<dyn std::error::Error>> {
+    let mut existing = existing;
+    let mut reconnects = 0;
+    loop {
+        match transfer_once(client, url, path, existing, control, options, confirm) {
+            Err(err) if err.is::<Stalled>() && reconnects < options.tries.max(1) => {
+                logfile::warning(&format!("{}; reconnecting", err));
+                reconnects += 1;
+                existing = Existing::Resume;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Makes one request for [`transfer`], returning [`Stalled`] if the response
+/// stops sending data.
+fn transfer_once(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    existing: Existing,
+    control: &Control,
+    options: &Options,
+    confirm: bool,
+) -> Result<(Outcome, Received), Box<dyn std::error::Error>> {
+    if gemini::is_gemini(url) {
+        return transfer_gemini(url, path, control, options);
+    }
+    if smb::is_smb(url) {
+        return transfer_smb(url, path, existing, control, options);
+    }
+    let resume = matches!(existing, Existing::Resume);
+    let format = options
+        .decompress
+        .then(|| decompress::Format::detect(url))
+        .flatten();
+    // Decompressed output cannot be continued from the middle of the stream.
+    let offset = if resume && !is_stdout(path) && format.is_none() {
+        match options.split_output {
+            Some(size) => split::existing_len(path, size),
+            None => fs::metadata(path).map(|meta| meta.len()).unwrap_or(0),
+        }
+    } else {
+        0
+    };
+    // Only a whole file written in order can be checked block by block.
+    let verify = options.verify_partial
+        && !is_stdout(path)
+        && format.is_none()
+        && options.split_output.is_none()
+        && options.range.is_none();
+    let (offset, metadata) = match verify {
+        true => partial::verify(path, offset).map(|(offset, metadata)| (offset, Some(metadata)))?,
+        false => (offset, None),
+    };
+    let first = options.range.map_or(0, |range| range.start) + offset;
+    let last = options.range.and_then(|range| range.end);
+    if last.is_some_and(|last| first > last) {
+        // The partial file already holds the whole range.
+        control.start(offset, Some(offset));
+        return Ok((Outcome::Completed, Received::default()));
+    }
+
+    let mut response = options.send(client, url, |client| {
+        let mut request = client.get(url);
+        if let Existing::Revalidate(record) = existing {
+            if let Some(etag) = &record.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(modified) = &record.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, modified);
+            }
+        }
+        if let Some(etag) = metadata
+            .as_ref()
+            .and_then(|metadata| metadata.if_range(url))
+        {
+            if offset > 0 {
+                request = request.header(IF_RANGE, etag);
+            }
+        }
+        match (first, last) {
+            (0, None) => request,
+            (first, Some(last)) => request.header(RANGE, format!("bytes={}-{}", first, last)),
+            (first, None) => request.header(RANGE, format!("bytes={}-", first)),
+        }
+    })?;
+    logfile::detail(&format!(
+        "GET {}: HTTP {}, {} bytes",
+        response.url(),
+        response.status(),
+        response
+            .content_length()
+            .map_or_else(|| "unknown".to_string(), |len| len.to_string())
+    ));
+
+    if offset > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // The partial file already holds the whole resource.
+        control.start(offset, Some(offset));
+        if verify {
+            partial::remove(path);
+        }
+        return Ok((Outcome::Completed, Received::default()));
+    }
+    if response.status() == StatusCode::NOT_MODIFIED && matches!(existing, Existing::Revalidate(_))
+    {
+        control.start(0, None);
+        let received = Received {
+            unchanged: true,
+            ..Received::default()
+        };
+        return Ok((Outcome::Completed, received));
+    }
+    if !response.status().is_success() {
+        return Err(format!("Failed to download: HTTP {}", response.status()).into());
+    }
+    if share::is_drive(url) && is_html(&response) {
+        let page = response.text()?;
+        return match share::confirm_url(url, &page).filter(|_| confirm) {
+            Some(confirmed) => transfer(client, &confirmed, path, existing, control, options, false),
+            None => Err("Google Drive returned a web page instead of the file; check that it is shared with anyone who has the link".into()),
+        };
+    }
+
+    let partial = response.status() == StatusCode::PARTIAL_CONTENT;
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let redirected = Some(response.url().clone()).filter(|final_url| final_url.as_str() != url);
+    let filename = response
+        .headers()
+        .get(CONTENT_DISPOSITION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(filename::disposition_filename);
+    let etag = response
+        .headers()
+        .get(ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let modified = response
+        .headers()
+        .get(LAST_MODIFIED)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| chrono::DateTime::parse_from_rfc2822(value).ok())
+        .map(SystemTime::from);
+    let length = response.content_length();
+
+    // A server that ignores the requested range sends the resource from its
+    // first byte: the bytes before the range are skipped, and a partial file
+    // is only kept when it holds part of the range.
+    let (mut skip, start) = match (partial, options.range) {
+        (true, _) => (0, offset),
+        (false, Some(_)) => (first, offset),
+        (false, None) => (0, 0),
+    };
+    let mut remaining = last.map(|last| last + 1 - first);
+    let total = match options.range {
+        Some(range) => range.length().or_else(|| {
+            length.map(|len| match partial {
+                true => len + offset,
+                false => len.saturating_sub(range.start),
+            })
+        }),
+        None => length.map(|len| len + start),
+    };
+
+    if let (Some(limit), Some(total)) = (options.confirm_over, total) {
+        if !oversize::allow(url, total, limit)? {
+            return Ok((Outcome::Skipped, Received::default()));
+        }
+    }
+    let skipped = total
+        .and_then(|total| options.size_bounds.reject(total))
+        .or_else(|| options.content.skips(content_type.as_deref()));
+    if let Some(reason) = skipped {
+        logfile::info(&format!("Skipping {}: {}", url, reason));
+        return Ok((Outcome::Skipped, Received::default()));
+    }
+
+    // The first chunk is checked before an existing file is touched.
+    let mut buffer = vec![0; options.buffer_size];
+    let mut read = response.read(&mut buffer)?;
+    let head = if partial || skip > 0 {
+        &[][..]
+    } else {
+        &buffer[..read]
+    };
+    if let Some(warning) = options.content.check(url, content_type.as_deref(), head)? {
+        logfile::warning(&format!("Warning: {}", warning));
+    }
+
+    let hasher = seeded_hasher(path, start, options)?;
+
+    // Only a plain file of known size is preallocated; a decompressed one
+    // has a size of its own.
+    let allocate = total.filter(|_| {
+        options.file_allocation != Allocation::None
+            && format.is_none()
+            && options.split_output.is_none()
+            && !is_stdout(path)
+    });
+    let mut reserved = None;
+    let sink: Box<dyn Write> = if is_stdout(path) {
+        Box::new(io::stdout().lock())
+    } else if let Some(size) = options.split_output {
+        Box::new(SplitWriter::open(path, size, start)?)
+    } else {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(start == 0)
+            .write(true)
+            .open(path)?;
+        if let Some(total) = allocate {
+            options.file_allocation.apply(&file, total)?;
+            reserved = Some(file.try_clone()?);
+        }
+        file.seek(SeekFrom::Start(start))?;
+        let file: Box<dyn Write> = match options.direct_io {
+            true => Box::new(DirectWriter::open(path, file, start, options.buffer_size)?),
+            false => Box::new(file),
+        };
+        match metadata {
+            Some(mut metadata) => {
+                // A resource sent from its start replaces the blocks recorded.
+                if start == 0 {
+                    metadata.blocks.clear();
+                }
+                metadata.url = url.to_string();
+                metadata.etag = etag.clone();
+                Box::new(Recorder::new(file, path, &metadata)?)
+            }
+            None => file,
+        }
+    };
+    let mut sink = HashingWriter {
+        inner: sink,
+        hasher,
+    };
+    control.start(start, total);
+    let reservation = reserved.map(|file| Reservation::new(file, control));
+    let mut speed = options.min_speed.map(SpeedCheck::new);
+    let resumable = format.is_none() && !is_stdout(path);
+    // The bytes written, which the control counts only without decompression.
+    let mut written = None;
+
+    if let Some(format) = format {
+        let input = Tracked {
+            inner: io::Cursor::new(buffer[..read].to_vec()).chain(response),
+            control,
+            options,
+            url,
+            speed,
+        };
+        let mut output = Decompressed {
+            inner: &mut sink,
+            written: 0,
+            bounds: &options.size_bounds,
+            exceeded: None,
+        };
+        if let Err(err) = format.decode(input, &mut output) {
+            if let Some(reason) = output.exceeded {
+                drop(sink);
+                return discard(url, path, &reason, options);
+            }
+            return match control.interruption() {
+                Some(outcome) => Ok((outcome, Received::default())),
+                None => Err(format!("Cannot decompress {}: {}", url, err).into()),
+            };
+        }
+        written = Some(output.written);
+    } else {
+        while read > 0 && remaining != Some(0) {
+            if let Some(outcome) = control.interruption() {
+                sink.flush()?;
+                return Ok((outcome, Received::default()));
+            }
+            let skipped = skip.min(read as u64) as usize;
+            skip -= skipped as u64;
+            let mut chunk = &buffer[skipped..read];
+            if let Some(remaining) = &mut remaining {
+                chunk = &chunk[..chunk.len().min(*remaining as usize)];
+                *remaining -= chunk.len() as u64;
+            }
+            let grown = control.downloaded() + chunk.len() as u64;
+            if let Some(reason) = options.size_bounds.exceeded(grown) {
+                drop(sink);
+                return discard(url, path, &reason, options);
+            }
+            sink.write_all(chunk)?;
+            control.advance(chunk.len() as u64);
+            options.consume(url, read);
+            if let Some(speed) = &mut speed {
+                speed.record(read as u64)?;
+            }
+            if remaining == Some(0) {
+                break;
+            }
+            read = match response.read(&mut buffer) {
+                Err(err) if is_stall(&err) && resumable && control.downloaded() > start => {
+                    // The file must hold exactly what was written before it
+                    // is resumed, and the dead connection is dropped with it.
+                    sink.flush()?;
+                    return Err(Stalled(control.downloaded()).into());
+                }
+                result => result?,
+            };
+        }
+    }
+    sink.flush()?;
+
+    // Some servers close the connection early without reporting an error; the
+    // partial file is kept so that the next attempt resumes it.
+    if let Some(total) = total.filter(|total| control.downloaded() < *total) {
+        return Err(format!(
+            "Connection closed after {} of {} bytes",
+            control.downloaded(),
+            total
+        )
+        .into());
+    }
+
+    if let Some(reservation) = reservation {
+        reservation.complete();
+    }
+    // The metadata is closed before it is removed.
+    drop(sink.inner);
+    if verify {
+        partial::remove(path);
+    }
+    // The size of a file of unknown length, or of decompressed data, is only known now.
+    let size = written.unwrap_or_else(|| control.downloaded());
+    if let Some(reason) = options.size_bounds.reject(size) {
+        return discard(url, path, &reason, options);
+    }
+    Ok((
+        Outcome::Completed,
+        Received {
+            etag,
+            content_type,
+            filename,
+            redirected,
+            modified,
+            digests: sink.hasher.finish(),
+            unchanged: false,
+        },
+    ))
+}
+
+/// Returns a hasher for the digests that [`algorithms`] asks for, seeded with
+/// the first `start` bytes of a resumed file.
+fn seeded_hasher(path: &Path, start: u64, options: &Options) -> io::Result<Hasher> {
+    // The part of a resumed file already on disk is read once to seed the digests.
+    let mut hasher = Hasher::new(&algorithms(options));
+    if start > 0 && !hasher.is_empty() {
+        match options.split_output {
+            Some(size) => {
+                for index in 0..start.div_ceil(size) {
+                    let part = File::open(split::part_path(path, index))?;
+                    hasher.update_from(part.take((start - index * size).min(size)))?;
+                }
+            }
+            None => hasher.update_from(File::open(path)?.take(start))?,
+        }
+    }
+    Ok(hasher)
+}
+
+/// The body of a download over a protocol other than HTTP, and what is known
+/// about it.
+struct Stream {
+    body: Box<dyn Read>,
+    /// The offset in the file the body starts at.
+    start: u64,
+    /// The size of the whole file, if known.
+    total: Option<u64>,
+    content_type: Option<String>,
+    redirected: Option<Url>,
+    modified: Option<SystemTime>,
+}
+
+/// Writes the body of the Gemini response for `url` to `path`, always from
+/// its start (see [`gemini`]).
+fn transfer_gemini(
+    url: &str,
+    path: &Path,
+    control: &Control,
+    options: &Options,
+) -> Result<(Outcome, Received), Box<dyn std::error::Error>> {
+    let response = gemini::get(url, &options.known_hosts, options.stall_timeout)?;
+    let stream = Stream {
+        body: Box::new(response.body),
+        start: 0,
+        total: None,
+        content_type: Some(response.mime),
+        redirected: Some(response.url).filter(|final_url| final_url.as_str() != url),
+        modified: None,
+    };
+    transfer_stream(url, path, stream, control, options)
+}
+
+/// Writes the file on an SMB share that `url` names to `path`, continuing a
+/// partial file when resuming (see [`smb`]).
+fn transfer_smb(
+    url: &str,
+    path: &Path,
+    existing: Existing,
+    control: &Control,
+    options: &Options,
+) -> Result<(Outcome, Received), Box<dyn std::error::Error>> {
+    let offset = match existing {
+        Existing::Resume if !is_stdout(path) => match options.split_output {
+            Some(size) => split::existing_len(path, size),
+            None => fs::metadata(path).map(|meta| meta.len()).unwrap_or(0),
+        },
+        _ => 0,
+    };
+    let credentials = options.credentials(url)?;
+    let mut file = smb::open(url, credentials.as_ref(), options.stall_timeout)?;
+    // A partial file longer than the share's is no part of it.
+    let start = if offset > file.size { 0 } else { offset };
+    file.seek(SeekFrom::Start(start))?;
+    logfile::detail(&format!("SMB {}: {} bytes", url, file.size));
+    let stream = Stream {
+        start,
+        total: Some(file.size),
+        content_type: None,
+        redirected: None,
+        modified: file.modified,
+        body: Box::new(file),
+    };
+    transfer_stream(url, path, stream, control, options)
+}
+
+/// Writes `stream`, the body of `url`, to `path` from its start offset.
+fn transfer_stream(
+    url: &str,
+    path: &Path,
+    stream: Stream,
+    control: &Control,
+    options: &Options,
+) -> Result<(Outcome, Received), Box<dyn std::error::Error>> {
+    let skipped = stream
+        .total
+        .and_then(|total| options.size_bounds.reject(total))
+        .or_else(|| options.content.skips(stream.content_type.as_deref()));
+    if let Some(reason) = skipped {
+        logfile::info(&format!("Skipping {}: {}", url, reason));
+        return Ok((Outcome::Skipped, Received::default()));
+    }
+    let start = stream.start;
+    let hasher = seeded_hasher(path, start, options)?;
+    let sink: Box<dyn Write> = if is_stdout(path) {
+        Box::new(io::stdout().lock())
+    } else if let Some(size) = options.split_output {
+        Box::new(SplitWriter::open(path, size, start)?)
+    } else {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(start == 0)
+            .write(true)
+            .open(path)?;
+        file.seek(SeekFrom::Start(start))?;
+        Box::new(file)
+    };
+    let mut sink = HashingWriter {
+        inner: sink,
+        hasher,
+    };
+    control.start(start, stream.total);
+    let mut body = stream.body;
+    let mut buffer = vec![0; options.buffer_size];
+    loop {
+        if let Some(outcome) = control.interruption() {
+            sink.flush()?;
+            return Ok((outcome, Received::default()));
+        }
+        let read = body.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        if let Some(reason) = options
+            .size_bounds
+            .exceeded(control.downloaded() + read as u64)
+        {
+            drop(sink);
+            return discard(url, path, &reason, options);
+        }
+        sink.write_all(&buffer[..read])?;
+        control.advance(read as u64);
+        options.consume(url, read);
+    }
+    sink.flush()?;
+    drop(sink.inner);
+    if let Some(reason) = options.size_bounds.reject(control.downloaded()) {
+        return discard(url, path, &reason, options);
+    }
+    Ok((
+        Outcome::Completed,
+        Received {
+            content_type: stream.content_type,
+            redirected: stream.redirected,
+            modified: stream.modified,
+            digests: sink.hasher.finish(),
+            ..Received::default()
+        },
+    ))
+}
+
+/// Removes the file of a download skipped for its size after it started.
+///
+/// Data already written to standard output stays there.
+fn discard(
+    url: &str,
+    path: &Path,
+    reason: &str,
+    options: &Options,
+) -> Result<(Outcome, Received), Box<dyn std::error::Error>> {
+    logfile::info(&format!("Skipping {}: {}", url, reason));
+    if is_stdout(path) {
+        return Ok((Outcome::Skipped, Received::default()));
+    }
+    match options.split_output {
+        Some(_) => {
+            for part in (0..).map(|index| split::part_path(path, index)) {
+                if fs::remove_file(part).is_err() {
+                    break;
+                }
+            }
+        }
+        None => fs::remove_file(path)?,
+    }
+    partial::remove(path);
+    Ok((Outcome::Skipped, Received::default()))
+}
+
+/// Reads a response body for a decoder, reporting progress and honouring
+/// the bandwidth limits and pause/cancel requests.
+struct Tracked<'a, R> {
+    inner: R,
+    control: &'a Control,
+    options: &'a Options,
+    url: &'a str,
+    speed: Option<SpeedCheck>,
+}
+
+impl<R: Read> Read for Tracked<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.control.interruption().is_some() {
+            return Err(io::Error::other("transfer interrupted"));
+        }
+        let read = self.inner.read(buf)?;
+        self.control.advance(read as u64);
+        self.options.consume(self.url, read);
+        if let Some(speed) = &mut self.speed {
+            speed.record(read as u64)?;
+        }
+        Ok(read)
+    }
+}
+
+/// Counts the bytes a decoder writes, and stops it with an error once they
+/// exceed `--max-size`, so that the bounds apply to the decompressed data.
+struct Decompressed<'a, W> {
+    inner: W,
+    written: u64,
+    bounds: &'a Bounds,
+    /// Why the decoder was stopped, if it was.
+    exceeded: Option<String>,
+}
+
+impl<W: Write> Write for Decompressed<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(reason) = self.bounds.exceeded(self.written + buf.len() as u64) {
+            self.exceeded = Some(reason.clone());
+            return Err(io::Error::other(reason));
+        }
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Returns whether `err` is a read that timed out waiting for data.
+fn is_stall(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::TimedOut
+        || err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<reqwest::Error>())
+            .is_some_and(reqwest::Error::is_timeout)
+}
+
+/// Returns whether `path` is `-`, which stands for standard output.
+pub fn is_stdout(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Returns whether `response` is an HTML page.
+fn is_html(response: &Response) -> bool {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| content::essence(value) == "text/html")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+
+    #[test]
+    fn test_default_filename() {
+        let url = Url::parse("https://example.com/dir/file.tar.gz").unwrap();
+        assert_eq!(default_filename(&url), "file.tar.gz");
+
+        let url = Url::parse("https://example.com/dir/").unwrap();
+        assert_eq!(default_filename(&url), "index.html");
+
+        let url = Url::parse("https://bücher.example/r%C3%A9sum%C3%A9 2024.pdf").unwrap();
+        assert_eq!(url.host_str(), Some("xn--bcher-kva.example"));
+        assert_eq!(default_filename(&url), "résumé 2024.pdf");
+    }
+
+    #[test]
+    fn test_fetch_reports_progress() {
+        let mock = mock("GET", "/download/progress.bin")
+            .with_status(200)
+            .with_body("0123456789")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("progress.bin");
+        let control = Control::default();
+        let url = format!("{}/download/progress.bin", server_url());
+
+        let outcome = fetch(
+            &Client::new(),
+            &url,
+            &path,
+            false,
+            &control,
+            &Options::default(),
+        )
+        .unwrap();
+
+        assert_eq!(outcome, Outcome::Completed);
+        assert_eq!(control.downloaded(), 10);
+        assert_eq!(control.total(), Some(10));
+        assert_eq!(fs::read(&path).unwrap(), b"0123456789");
+        mock.assert();
+    }
+
+    #[test]
+    fn test_fetch_keeps_server_timestamp() {
+        let mock = mock("GET", "/download/dated.bin")
+            .with_status(200)
+            .with_header("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT")
+            .with_body("dated")
+            .expect(2)
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dated.bin");
+        let url = format!("{}/download/dated.bin", server_url());
+        let modified = |options: &Options| {
+            fetch(
+                &Client::new(),
+                &url,
+                &path,
+                false,
+                &Control::default(),
+                options,
+            )
+            .unwrap();
+            fs::metadata(&path).unwrap().modified().unwrap()
+        };
+
+        let dated = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_445_412_480);
+        assert_eq!(modified(&Options::default()), dated);
+        let options = Options {
+            server_timestamps: false,
+            ..Options::default()
+        };
+        assert!(modified(&options) > dated);
+        mock.assert();
+    }
+
+    #[test]
+    fn test_send_caches_realm_token() {
+        use mockito::Matcher;
+
+        let challenge = format!(
+            "Bearer realm=\"{}/realm/token\",service=\"files\",scope=\"repository:team/app:pull\"",
+            server_url()
+        );
+        let denied = mock("GET", Matcher::Regex("^/realm/blob/".to_string()))
+            .match_header("authorization", "Basic YWxpY2U6c2VjcmV0")
+            .with_status(401)
+            .with_header("www-authenticate", &challenge)
+            .expect(1)
+            .create();
+        let token = mock("GET", "/realm/token")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("service".to_string(), "files".to_string()),
+                Matcher::UrlEncoded("scope".to_string(), "repository:team/app:pull".to_string()),
+            ]))
+            .match_header("authorization", "Basic YWxpY2U6c2VjcmV0")
+            .with_body(r#"{"token": "t0k3n", "expires_in": 300}"#)
+            .expect(1)
+            .create();
+        let blobs = mock("GET", Matcher::Regex("^/realm/blob/".to_string()))
+            .match_header("authorization", "Bearer t0k3n")
+            .with_body("layer")
+            .expect(2)
+            .create();
+
+        let options = Options {
+            credentials: Some(Credentials::Basic {
+                user: "alice".to_string(),
+                password: "secret".to_string(),
+            }),
+            ..Options::default()
+        };
+        let client = Client::new();
+        for name in ["a", "b"] {
+            let url = format!("{}/realm/blob/{}", server_url(), name);
+            let response = options
+                .send(&client, &url, |client| client.get(&url))
+                .unwrap();
+            assert_eq!(response.text().unwrap(), "layer");
+        }
+        denied.assert();
+        token.assert();
+        blobs.assert();
+    }
+
+    #[test]
+    fn test_fetch_resolves_lfs_pointer() {
+        use mockito::Matcher;
+
+        let oid = "73034adcce3a5999a49cd807515b4cc621a9f02403ef8c14b262792d5067cced";
+        let pointer = mock("GET", "/lfs-owner/repo/raw/main/big.bin")
+            .with_body(format!(
+                "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 12\n",
+                oid
+            ))
+            .create();
+        let batch = mock("POST", "/lfs-owner/repo.git/info/lfs/objects/batch")
+            .match_header("content-type", "application/vnd.git-lfs+json")
+            .match_body(Matcher::PartialJsonString(format!(
+                r#"{{"operation": "download", "objects": [{{"oid": "{}", "size": 12}}]}}"#,
+                oid
+            )))
+            .with_body(format!(
+                r#"{{"objects": [{{"oid": "{}", "size": 12, "actions": {{"download":
+                    {{"href": "{}/lfs-objects/{}", "header": {{"X-Signature": "abc"}}}}}}}}]}}"#,
+                oid,
+                server_url(),
+                oid
+            ))
+            .create();
+        let object = mock("GET", format!("/lfs-objects/{}", oid).as_str())
+            .match_header("x-signature", "abc")
+            .with_body("large object")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.bin");
+        let url = format!("{}/lfs-owner/repo/raw/main/big.bin", server_url());
+        let options = Options {
+            checksum: Some(oid.to_string()),
+            ..Options::default()
+        };
+        fetch(
+            &Client::new(),
+            &url,
+            &path,
+            false,
+            &Control::default(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"large object");
+        pointer.assert();
+        batch.assert();
+        object.assert();
+    }
+
+    #[test]
+    fn test_fetch_falls_back_to_wayback() {
+        use mockito::Matcher;
+
+        let live = mock("GET", "/wayback-live/gone.txt")
+            .with_status(503)
+            .create();
+        let url = format!("{}/wayback-live/gone.txt", server_url());
+        let api = mock("GET", "/wayback/available")
+            .match_query(Matcher::UrlEncoded("url".to_string(), url.clone()))
+            .with_body(format!(
+                r#"{{"archived_snapshots": {{"closest": {{"available": true,
+                    "timestamp": "20200101000000", "url": "{}/web/20200101000000/{}"}}}}}}"#,
+                server_url(),
+                url
+            ))
+            .create();
+        let snapshot = mock("GET", format!("/web/20200101000000id_/{}", url).as_str())
+            .with_body("archived")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gone.txt");
+        let options = Options {
+            wayback: Some(Arc::new(Wayback {
+                api: format!("{}/wayback/available", server_url()),
+                ..Wayback::new(None).unwrap()
+            })),
+            ..Options::default()
+        };
+        fetch(
+            &Client::new(),
+            &url,
+            &path,
+            false,
+            &Control::default(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"archived");
+        live.assert();
+        api.assert();
+        snapshot.assert();
+    }
+
+    #[test]
+    fn test_fetch_through_tmp_dir() {
+        let mock = mock("GET", "/download/staged.bin")
+            .with_status(200)
+            .with_body("staged")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("staged.bin");
+        let options = Options {
+            tmp_dir: Some(dir.path().join("scratch")),
+            ..Options::default()
+        };
+        let url = format!("{}/download/staged.bin", server_url());
+
+        fetch(
+            &Client::new(),
+            &url,
+            &path,
+            false,
+            &Control::default(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"staged");
+        assert_eq!(fs::read_dir(dir.path().join("scratch")).unwrap().count(), 0);
+        mock.assert();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fetch_keeps_rejected_file_in_quarantine() {
+        let mock = mock("GET", "/download/infected.bin")
+            .with_status(200)
+            .with_body("EICAR")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("infected.bin");
+        let options = Options {
+            tmp_dir: Some(dir.path().join("quarantine")),
+            scan_command: Some("! grep -q EICAR".to_string()),
+            ..Options::default()
+        };
+        let url = format!("{}/download/infected.bin", server_url());
+
+        let err = fetch(
+            &Client::new(),
+            &url,
+            &path,
+            false,
+            &Control::default(),
+            &options,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("scan of"));
+        assert!(!path.exists());
+        assert_eq!(
+            fs::read_dir(dir.path().join("quarantine")).unwrap().count(),
+            1
+        );
+        mock.assert();
+    }
+
+    #[test]
+    fn test_fetch_records_in_ledger() {
+        let mock = mock("GET", "/download/ledger.bin")
+            .with_status(200)
+            .with_header("etag", "\"abc\"")
+            .with_body("ledgered")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger.bin");
+        let ledger = Arc::new(Ledger::open(dir.path().join("ledger.json")).unwrap());
+        let options = Options {
+            ledger: Some(Arc::clone(&ledger)),
+            ..Options::default()
+        };
+        let url = format!("{}/download/ledger.bin", server_url());
+
+        fetch(
+            &Client::new(),
+            &url,
+            &path,
+            false,
+            &Control::default(),
+            &options,
+        )
+        .unwrap();
+
+        assert!(ledger.is_current(&url, &path));
+        mock.assert();
+    }
+
+    #[test]
+    fn test_fetch_pins_in_lockfile() {
+        let pinned = mock("GET", "/download/pinned.bin")
+            .with_body("pinned")
+            .expect(2)
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pinned.bin");
+        let lockfile = Arc::new(Lockfile::open(dir.path().join("rustwget.lock")).unwrap());
+        let options = Options {
+            lockfile: Some(Arc::clone(&lockfile)),
+            ..Options::default()
+        };
+        let url = format!("{}/download/pinned.bin", server_url());
+        let run = || {
+            fetch(
+                &Client::new(),
+                &url,
+                &path,
+                false,
+                &Control::default(),
+                &options,
+            )
+        };
+
+        run().unwrap();
+        assert_eq!(lockfile.get(&url).unwrap().size, 6);
+        run().unwrap();
+        pinned.assert();
+
+        let _drifted = mock("GET", "/download/pinned.bin")
+            .with_body("changed")
+            .create();
+        let err = run().unwrap_err().to_string();
+        assert!(err.contains("expected size 6, got 7"), "{}", err);
+        // The drifted download never replaces the pinned file.
+        assert_eq!(fs::read(&path).unwrap(), b"pinned");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_fetch_skips_by_size() {
+        let known = mock("GET", "/download/sized.bin")
+            .with_body("0123456789")
+            .create();
+        let unknown = mock("GET", "/download/streamed.bin")
+            .with_body_from_fn(|body| body.write_all(b"0123456789"))
+            .expect(3)
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sized.bin");
+        let run = |name: &str, bounds: Bounds| {
+            let options = Options {
+                size_bounds: bounds,
+                ..Options::default()
+            };
+            let url = format!("{}/download/{}", server_url(), name);
+            fetch(
+                &Client::new(),
+                &url,
+                &path,
+                false,
+                &Control::default(),
+                &options,
+            )
+            .unwrap()
+        };
+        let max = Bounds {
+            max: Some(5),
+            ..Bounds::default()
+        };
+        let min = Bounds {
+            min: Some(20),
+            ..Bounds::default()
+        };
+
+        assert_eq!(run("sized.bin", max), Outcome::Skipped);
+        assert!(!path.exists());
+        assert_eq!(run("streamed.bin", max), Outcome::Skipped);
+        assert!(!path.exists());
+        assert_eq!(run("streamed.bin", min), Outcome::Skipped);
+        assert!(!path.exists());
+        assert_eq!(run("streamed.bin", Bounds::default()), Outcome::Completed);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "0123456789");
+        known.assert();
+        unknown.assert();
+    }
+
+    #[test]
+    fn test_fetch_verifies_partial_file() {
+        let data: Vec<u8> = (0..partial::BLOCK + 10).map(|i| (i % 251) as u8).collect();
+        let block = partial::BLOCK as usize;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("verified.bin");
+        let url = format!("{}/download/verified.bin", server_url());
+        let metadata = partial::Metadata {
+            url: url.clone(),
+            etag: Some("\"v1\"".to_string()),
+            blocks: Vec::new(),
+        };
+        let mut recorder = Recorder::new(File::create(&path).unwrap(), &path, &metadata).unwrap();
+        recorder.write_all(&data[..block + 3]).unwrap();
+        drop(recorder);
+
+        // The bytes after the last complete block are requested again.
+        let mock = mock("GET", "/download/verified.bin")
+            .match_header("range", format!("bytes={}-", block).as_str())
+            .match_header("if-range", "\"v1\"")
+            .with_status(206)
+            .with_header("etag", "\"v1\"")
+            .with_body(&data[block..])
+            .create();
+        let options = Options {
+            verify_partial: true,
+            ..Options::default()
+        };
+        fetch(
+            &Client::new(),
+            &url,
+            &path,
+            true,
+            &Control::default(),
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert!(!partial::path(&path).exists());
+        mock.assert();
+    }
+
+    #[test]
+    fn test_fetch_if_changed_keeps_unmodified_file() {
+        let first = mock("GET", "/download/conditional.bin")
+            .with_header("etag", "\"v1\"")
+            .with_header("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT")
+            .with_body("original")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("conditional.bin");
+        let options = Options {
+            ledger: Some(Arc::new(
+                Ledger::open(dir.path().join("ledger.json")).unwrap(),
+            )),
+            if_changed: true,
+            ..Options::default()
+        };
+        let url = format!("{}/download/conditional.bin", server_url());
+        let run = || {
+            fetch(
+                &Client::new(),
+                &url,
+                &path,
+                false,
+                &Control::default(),
+                &options,
+            )
+        };
+
+        run().unwrap();
+        first.assert();
+        let unchanged = mock("GET", "/download/conditional.bin")
+            .match_header("if-none-match", "\"v1\"")
+            .match_header("if-modified-since", "Wed, 21 Oct 2015 07:28:00 GMT")
+            .with_status(304)
+            .create();
+        assert_eq!(run().unwrap(), Outcome::Completed);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original");
+        unchanged.assert();
+
+        // A file changed on disk is downloaded unconditionally.
+        fs::write(&path, "edited").unwrap();
+        let again = mock("GET", "/download/conditional.bin")
+            .match_header("if-none-match", mockito::Matcher::Missing)
+            .with_body("original")
+            .create();
+        run().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original");
+        again.assert();
+    }
+
+    #[test]
+    fn test_fetch_fails_over_to_mirror() {
+        let broken = mock("GET", "/failover/primary/data.bin")
+            .with_status(500)
+            .create();
+        let corrupt = mock("GET", "/failover/corrupt/data.bin")
+            .with_body("garbage")
+            .create();
+        let good = mock("GET", "/failover/good/data.bin")
+            .with_body("abc")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        let base = |name: &str| Url::parse(&format!("{}/failover/{}/", server_url(), name));
+        let options = Options {
+            mirrors: Some(Arc::new(MirrorList::new(
+                vec![
+                    base("primary").unwrap(),
+                    base("corrupt").unwrap(),
+                    base("good").unwrap(),
+                ],
+                false,
+            ))),
+            checksum: Some(
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string(),
+            ),
+            ..Options::default()
+        };
+        let url = format!("{}/failover/primary/data.bin", server_url());
+
+        let outcome = fetch(
+            &Client::new(),
+            &url,
+            &path,
+            false,
+            &Control::default(),
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(outcome, Outcome::Completed);
+        assert_eq!(fs::read(&path).unwrap(), b"abc");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        broken.assert();
+        corrupt.assert();
+        good.assert();
+    }
+
+    #[test]
+    fn test_is_html() {
+        let _page = mock("GET", "/download/page")
+            .with_header("content-type", "text/html; charset=utf-8")
+            .create();
+        let _file = mock("GET", "/download/file")
+            .with_header("content-type", "application/zip")
+            .create();
+        let get = |path: &str| {
+            Client::new()
+                .get(format!("{}{}", server_url(), path))
+                .send()
+                .unwrap()
+        };
+        assert!(is_html(&get("/download/page")));
+        assert!(!is_html(&get("/download/file")));
+    }
+
+    #[test]
+    fn test_rejected_content_type_keeps_existing_file() {
+        let mock = mock("GET", "/download/rejected.iso")
+            .with_header("content-type", "text/html")
+            .with_body("<html>Please sign in</html>")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rejected.iso");
+        fs::write(&path, "old").unwrap();
+        let options = Options {
+            content: content::Policy {
+                strict: true,
+                ..content::Policy::default()
+            },
+            ..Options::default()
+        };
+        let url = format!("{}/download/rejected.iso", server_url());
+
+        let err = fetch(
+            &Client::new(),
+            &url,
+            &path,
+            false,
+            &Control::default(),
+            &options,
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("returned an HTML page"));
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        mock.assert();
+    }
+
+    #[test]
+    fn test_fetch_range() {
+        let ranged = mock("GET", "/download/range.bin")
+            .match_header("range", "bytes=2-5")
+            .with_status(206)
+            .with_body("2345")
+            .create();
+        let ignored = mock("GET", "/download/range-ignored.bin")
+            .with_body("0123456789")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            range: Some(ByteRange::parse("2-5").unwrap()),
+            ..Options::default()
+        };
+        for name in ["range.bin", "range-ignored.bin"] {
+            let path = dir.path().join(name);
+            let control = Control::default();
+            let url = format!("{}/download/{}", server_url(), name);
+
+            let outcome = fetch(&Client::new(), &url, &path, false, &control, &options).unwrap();
+
+            assert_eq!(outcome, Outcome::Completed);
+            assert_eq!(fs::read(&path).unwrap(), b"2345");
+            assert_eq!(control.total(), Some(4));
+        }
+        ranged.assert();
+        ignored.assert();
+    }
+
+    /// Serves `responses` to successive connections, each written verbatim and then closed.
+    fn serve_raw(responses: Vec<&'static str>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file.bin", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_preallocated_file_keeps_only_written_bytes() {
+        let url = serve_raw(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\n01234",
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 5-9/10\r\nContent-Length: 5\r\nConnection: close\r\n\r\n56789",
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.bin");
+        let client = Client::new();
+        let options = Options {
+            file_allocation: Allocation::Falloc,
+            ..Options::default()
+        };
+
+        assert!(fetch(&client, &url, &path, false, &Control::default(), &options).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"01234");
+
+        fetch(&client, &url, &path, true, &Control::default(), &options).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"0123456789");
+    }
+
+    #[test]
+    fn test_stalled_transfer_reconnects() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file.bin", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let mut request = [0; 1024];
+            // The first connection stays open without sending the rest.
+            let (mut stalled, _) = listener.accept().unwrap();
+            let _ = stalled.read(&mut request);
+            stalled
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n01234")
+                .unwrap();
+            let (mut resumed, _) = listener.accept().unwrap();
+            let len = resumed.read(&mut request).unwrap();
+            if String::from_utf8_lossy(&request[..len]).contains("range: bytes=5-") {
+                resumed.write_all(b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 5-9/10\r\nContent-Length: 5\r\n\r\n56789").unwrap();
+            }
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.bin");
+        let client = Client::builder()
+            .timeout(std::time::Duration::from_millis(500))
+            .build()
+            .unwrap();
+
+        let outcome = fetch(
+            &client,
+            &url,
+            &path,
+            false,
+            &Control::default(),
+            &Options::default(),
+        );
+        assert_eq!(outcome.unwrap(), Outcome::Completed);
+        assert_eq!(fs::read(&path).unwrap(), b"0123456789");
+    }
+
+    #[test]
+    fn test_stalled_transfer_reconnects_tries_times() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file.bin", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicU64::new(0));
+        let accepted = Arc::clone(&connections);
+        std::thread::spawn(move || {
+            // Every connection sends one more byte and then stalls.
+            let mut open = Vec::new();
+            for (index, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let _ = stream.read(&mut [0; 1024]);
+                let response = match index {
+                    0 => "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0".to_string(),
+                    _ => format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {0}-9/10\r\nContent-Length: {1}\r\n\r\n{0}",
+                        index,
+                        10 - index
+                    ),
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+                open.push(stream);
+            }
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.bin");
+        let client = Client::builder()
+            .timeout(std::time::Duration::from_millis(300))
+            .build()
+            .unwrap();
+        let options = Options {
+            tries: 2,
+            ..Options::default()
+        };
+
+        let err = fetch(&client, &url, &path, false, &Control::default(), &options).unwrap_err();
+        assert_eq!(err.to_string(), "Transfer stalled after 3 bytes");
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+        assert_eq!(fs::read(&path).unwrap(), b"012");
+    }
+
+    #[test]
+    fn test_truncated_transfer_fails_and_resumes() {
+        let url = serve_raw(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\n01234",
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 5-9/10\r\nContent-Length: 5\r\nConnection: close\r\n\r\n56789",
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.bin");
+        let client = Client::new();
+        let options = Options::default();
+
+        assert!(fetch(&client, &url, &path, false, &Control::default(), &options).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"01234");
+
+        let outcome = fetch(&client, &url, &path, true, &Control::default(), &options).unwrap();
+        assert_eq!(outcome, Outcome::Completed);
+        assert_eq!(fs::read(&path).unwrap(), b"0123456789");
+    }
+
+    #[test]
+    fn test_fetch_decompresses() {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(b"a,b\n1,2\n").unwrap();
+        let body = gzip.finish().unwrap();
+        let mock = mock("GET", "/download/table.csv.gz")
+            .with_body(&body)
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("table.csv");
+        fs::write(&path, "stale partial data").unwrap();
+        let control = Control::default();
+        let options = Options {
+            decompress: true,
+            ..Options::default()
+        };
+        let url = format!("{}/download/table.csv.gz", server_url());
+
+        let outcome = fetch(&Client::new(), &url, &path, true, &control, &options).unwrap();
+
+        assert_eq!(outcome, Outcome::Completed);
+        assert_eq!(fs::read(&path).unwrap(), b"a,b\n1,2\n");
+        assert_eq!(control.downloaded(), body.len() as u64);
+        mock.assert();
+
+        // --max-size bounds what the data decompresses to.
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&[0; 65536]).unwrap();
+        let bomb = mockito::mock("GET", "/download/zeros.gz")
+            .with_body(gzip.finish().unwrap())
+            .create();
+        let path = dir.path().join("zeros");
+        let options = Options {
+            size_bounds: Bounds {
+                max: Some(4096),
+                ..Bounds::default()
+            },
+            ..options
+        };
+        let url = format!("{}/download/zeros.gz", server_url());
+        let control = Control::default();
+        let outcome = fetch(&Client::new(), &url, &path, false, &control, &options).unwrap();
+        assert_eq!(outcome, Outcome::Skipped);
+        assert!(!path.exists());
+        bomb.assert();
+    }
+
+    #[test]
+    fn test_fetch_resumes_partial_file() {
+        let mock = mock("GET", "/download/resume.bin")
+            .match_header("range", "bytes=4-")
+            .with_status(206)
+            .with_body("456789")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("resume.bin");
+        fs::write(&path, "0123").unwrap();
+        let control = Control::default();
+        let url = format!("{}/download/resume.bin", server_url());
+
+        let outcome = fetch(
+            &Client::new(),
+            &url,
+            &path,
+            true,
+            &control,
+            &Options::default(),
+        )
+        .unwrap();
+
+        assert_eq!(outcome, Outcome::Completed);
+        assert_eq!(fs::read(&path).unwrap(), b"0123456789");
+        assert_eq!(control.total(), Some(10));
+        mock.assert();
+    }
+
+    #[test]
+    fn test_resumed_fetch_writes_hash_of_whole_file() {
+        let mock = mock("GET", "/download/hashed.txt")
+            .match_header("range", "bytes=1-")
+            .with_status(206)
+            .with_body("bc")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hashed.txt");
+        fs::write(&path, "a").unwrap();
+        let options = Options {
+            hashes: vec![Algorithm::Sha256],
+            hash_file: true,
+            ..Options::default()
+        };
+        let url = format!("{}/download/hashed.txt", server_url());
+
+        let outcome = fetch(
+            &Client::new(),
+            &url,
+            &path,
+            true,
+            &Control::default(),
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(outcome, Outcome::Completed);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("hashed.txt.sha256")).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  hashed.txt\n"
+        );
+        mock.assert();
+    }
+
+    #[test]
+    fn test_fetch_adds_detected_extension() {
+        let mock = mock("GET", "/download/sniffed?id=42")
+            .with_status(200)
+            .with_header("content-type", "application/octet-stream")
+            .with_body("%PDF-1.7\n")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sniffed_42");
+        let options = Options {
+            auto_extension: true,
+            ..Options::default()
+        };
+        let url = format!("{}/download/sniffed?id=42", server_url());
+
+        let outcome = fetch(
+            &Client::new(),
+            &url,
+            &path,
+            false,
+            &Control::default(),
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(outcome, Outcome::Completed);
+        assert!(!path.exists());
+        assert_eq!(
+            fs::read(dir.path().join("sniffed_42.pdf")).unwrap(),
+            b"%PDF-1.7\n"
+        );
+        mock.assert();
+    }
+
+    #[test]
+    fn test_fetch_renames_after_content_disposition() {
+        let mock = mock("GET", "/download/attachment")
+            .with_status(200)
+            .with_header(
+                "content-disposition",
+                r#"attachment; filename="../../evil/report.csv""#,
+            )
+            .with_body("a,b\n")
+            .expect(2)
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("attachment");
+        let options = Options {
+            content_disposition: true,
+            ..Options::default()
+        };
+        let url = format!("{}/download/attachment", server_url());
+
+        fetch(
+            &Client::new(),
+            &url,
+            &path,
+            false,
+            &Control::default(),
+            &options,
+        )
+        .unwrap();
+
+        assert!(!path.exists());
+        assert_eq!(fs::read(dir.path().join("report.csv")).unwrap(), b"a,b\n");
+
+        // A file at the suggested name is only replaced as the policy allows.
+        fs::write(dir.path().join("report.csv"), "mine").unwrap();
+        let options = Options {
+            overwrite: overwrite::Policy::Skip,
+            ..options
+        };
+        let control = Control::default();
+        fetch(&Client::new(), &url, &path, false, &control, &options).unwrap();
+        assert_eq!(fs::read(dir.path().join("report.csv")).unwrap(), b"mine");
+        assert_eq!(fs::read(&path).unwrap(), b"a,b\n");
+        mock.assert();
+    }
+
+    #[test]
+    fn test_fetch_trusts_redirected_name() {
+        let short = mock("GET", "/download/s/x1")
+            .with_status(302)
+            .with_header("location", "/download/files/release-1.2.tar.gz")
+            .create();
+        let target = mock("GET", "/download/files/release-1.2.tar.gz")
+            .with_status(200)
+            .with_body("tarball")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("x1");
+        let options = Options {
+            trust_server_names: true,
+            ..Options::default()
+        };
+        let url = format!("{}/download/s/x1", server_url());
+
+        fetch(
+            &Client::new(),
+            &url,
+            &path,
+            false,
+            &Control::default(),
+            &options,
+        )
+        .unwrap();
+
+        assert!(!path.exists());
+        assert_eq!(
+            fs::read(dir.path().join("release-1.2.tar.gz")).unwrap(),
+            b"tarball"
+        );
+        short.assert();
+        target.assert();
+    }
+
+    #[test]
+    fn test_fetch_syncs_split_parts() {
+        let mock = mock("GET", "/download/synced.bin")
+            .with_status(200)
+            .with_body("0123456789")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("synced.bin");
+        let options = Options {
+            split_output: Some(4),
+            sync: true,
+            ..Options::default()
+        };
+        let url = format!("{}/download/synced.bin", server_url());
+
+        let outcome = fetch(
+            &Client::new(),
+            &url,
+            &path,
+            false,
+            &Control::default(),
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(outcome, Outcome::Completed);
+        assert_eq!(fs::read(split::part_path(&path, 2)).unwrap(), b"89");
+        assert!(sync(&[split::part_path(&path, 3)]).is_err());
+        mock.assert();
+    }
+
+    #[test]
+    fn test_fetch_stops_when_cancelled() {
+        let mock = mock("GET", "/download/cancel.bin")
+            .with_status(200)
+            .with_body("data")
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cancel.bin");
+        let control = Control::default();
+        control.cancel();
+        let url = format!("{}/download/cancel.bin", server_url());
+
+        let outcome = fetch(
+            &Client::new(),
+            &url,
+            &path,
+            false,
+            &control,
+            &Options::default(),
+        )
+        .unwrap();
+
+        assert_eq!(outcome, Outcome::Cancelled);
+        assert_eq!(control.downloaded(), 0);
+        mock.assert();
+    }
+}
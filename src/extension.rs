@@ -0,0 +1,198 @@
+//! File extensions chosen from what a server sent.
+//!
+//! With `--auto-extension`, a URL whose last segment has no useful extension,
+//! such as `download?id=42` or `get.php?file=7`, is saved under a name built
+//! from its path and query values (`download_42`), and once the download
+//! completes an extension is added from the `Content-Type` or, when that is
+//! missing or generic, from the first bytes of the file: `download_42.pdf`.
+
+use crate::content;
+use url::Url;
+
+/// Extensions of server-side scripts, which say nothing about what they return.
+const SCRIPT_EXTENSIONS: [&str; 7] = ["php", "asp", "aspx", "jsp", "cgi", "pl", "py"];
+
+/// Media types that say nothing about the content.
+const GENERIC_TYPES: [&str; 5] = [
+    "application/octet-stream",
+    "binary/octet-stream",
+    "application/download",
+    "application/force-download",
+    "application/x-download",
+];
+
+/// Extensions of known media types.
+const TYPES: [(&str, &str); 36] = [
+    ("application/pdf", "pdf"),
+    ("application/zip", "zip"),
+    ("application/gzip", "gz"),
+    ("application/x-gzip", "gz"),
+    ("application/x-xz", "xz"),
+    ("application/zstd", "zst"),
+    ("application/x-bzip2", "bz2"),
+    ("application/x-tar", "tar"),
+    ("application/x-7z-compressed", "7z"),
+    ("application/vnd.rar", "rar"),
+    ("application/x-rar-compressed", "rar"),
+    ("application/json", "json"),
+    ("application/xml", "xml"),
+    ("application/javascript", "js"),
+    ("application/msword", "doc"),
+    ("application/vnd.ms-excel", "xls"),
+    (
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "docx",
+    ),
+    (
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "xlsx",
+    ),
+    (
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "pptx",
+    ),
+    ("application/x-iso9660-image", "iso"),
+    ("text/html", "html"),
+    ("application/xhtml+xml", "html"),
+    ("text/plain", "txt"),
+    ("text/csv", "csv"),
+    ("text/css", "css"),
+    ("text/javascript", "js"),
+    ("text/xml", "xml"),
+    ("text/markdown", "md"),
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("image/svg+xml", "svg"),
+    ("audio/mpeg", "mp3"),
+    ("audio/ogg", "ogg"),
+    ("video/mp4", "mp4"),
+];
+
+/// Returns whether `name` ends in an extension that describes its content.
+pub fn has_extension(name: &str) -> bool {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !extension.is_empty() => {
+            extension.chars().all(|c| c.is_ascii_alphanumeric())
+                && !SCRIPT_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+        }
+        _ => false,
+    }
+}
+
+/// Derives the local filename for a URL whose last segment may lack an extension.
+///
+/// Such a name loses its script extension and gains the URL's query values,
+/// so that `download?id=42` and `download?id=43` are saved apart; other URLs
+/// are named as by [`crate::filename::default_filename`].
+pub fn base_name(url: &Url) -> String {
+    let name = crate::filename::default_filename(url);
+    if has_extension(&name) {
+        return name;
+    }
+    let stem = match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => name.as_str(),
+    };
+    let mut parts = vec![stem.to_string()];
+    for (_, value) in url.query_pairs() {
+        let value: String = value
+            .chars()
+            .map(|c| match c {
+                c if c.is_ascii_alphanumeric() || c == '-' || c == '.' => c,
+                _ => '_',
+            })
+            .collect();
+        let value = value.trim_matches(['_', '.']);
+        if !value.is_empty() {
+            parts.push(value.to_string());
+        }
+    }
+    parts.join("_")
+}
+
+/// Chooses the extension of a downloaded file.
+///
+/// # Arguments
+///
+/// * `content_type`: The `Content-Type` header of the response, if it describes the file.
+/// * `head`: The first bytes of the file.
+///
+/// # Returns
+///
+/// * `Option<&'static str>`: The extension without a dot, or `None` if the content is not recognized.
+pub fn detect(content_type: Option<&str>, head: &[u8]) -> Option<&'static str> {
+    let mime = content_type.map(content::essence).unwrap_or_default();
+    let declared = TYPES
+        .iter()
+        .find(|(known, _)| *known == mime)
+        .map(|(_, extension)| *extension);
+    match declared {
+        Some(extension) if !GENERIC_TYPES.contains(&mime.as_str()) => Some(extension),
+        _ => sniff(head),
+    }
+}
+
+/// Recognizes common file formats by their magic bytes.
+fn sniff(head: &[u8]) -> Option<&'static str> {
+    const MAGIC: [(&[u8], &str); 15] = [
+        (b"%PDF-", "pdf"),
+        (b"PK\x03\x04", "zip"),
+        (b"\x1f\x8b", "gz"),
+        (b"\xfd7zXZ\x00", "xz"),
+        (b"\x28\xb5\x2f\xfd", "zst"),
+        (b"BZh", "bz2"),
+        (b"7z\xbc\xaf\x27\x1c", "7z"),
+        (b"Rar!\x1a\x07", "rar"),
+        (b"\x89PNG\r\n\x1a\n", "png"),
+        (b"\xff\xd8\xff", "jpg"),
+        (b"GIF87a", "gif"),
+        (b"GIF89a", "gif"),
+        (b"OggS", "ogg"),
+        (b"ID3", "mp3"),
+        (b"\x00\x00\x01\xba", "mpg"),
+    ];
+    if let Some((_, extension)) = MAGIC.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Some(extension);
+    }
+    if head.len() >= 12 && head.starts_with(b"RIFF") && &head[8..12] == b"WEBP" {
+        return Some("webp");
+    }
+    if head.len() >= 8 && &head[4..8] == b"ftyp" {
+        return Some("mp4");
+    }
+    if head.len() >= 262 && &head[257..262] == b"ustar" {
+        return Some("tar");
+    }
+    content::looks_like_html(head).then_some("html")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base_name() {
+        let name = |url| base_name(&Url::parse(url).unwrap());
+        assert_eq!(name("https://example.com/download?id=42"), "download_42");
+        assert_eq!(
+            name("https://example.com/get.php?file=a%20b/c&v=2"),
+            "get_a_b_c_2"
+        );
+        assert_eq!(name("https://example.com/report.pdf?token=x"), "report.pdf");
+        assert_eq!(name("https://example.com/"), "index.html");
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(Some("application/pdf; qs=0.9"), b""), Some("pdf"));
+        assert_eq!(
+            detect(Some("application/octet-stream"), b"%PDF-1.7\n"),
+            Some("pdf")
+        );
+        assert_eq!(detect(None, b"\x89PNG\r\n\x1a\n...."), Some("png"));
+        assert_eq!(detect(None, b"\n<!doctype html><p>"), Some("html"));
+        assert_eq!(detect(Some("application/x-unknown"), b"\x00\x01"), None);
+    }
+}
@@ -0,0 +1,376 @@
+//! Downloading through a host's `fetch`, the engine of WebAssembly builds.
+//!
+//! The transfer engine of [`download`](crate::download) sends requests with
+//! reqwest's blocking client, which needs threads and sockets that
+//! WebAssembly hosts do not offer. [`download`] instead sends its requests
+//! through a [`Fetch`] backend, and it is the engine the library keeps when
+//! built with `cargo build --lib --target wasm32-wasip1`, along with the
+//! modules it uses for names, digests, and staging.
+//!
+//! On WebAssembly, [`HostFetch`] calls the `fetch` of the host, which plugin
+//! hosts and edge runtimes provide as imports of the `rustwget` module:
+//!
+//! | Import                                          | Effect                                                        |
+//! |-------------------------------------------------|---------------------------------------------------------------|
+//! | `fetch(request, len) -> i64`                    | Sends the request in `len` bytes of JSON at `request`, `{"method", "url", "headers": [[name, value]]}`, and returns a handle to its response |
+//! | `fetch_head(handle, buffer, capacity) -> i64`   | Writes `{"status", "headers"}` of the response to `buffer` and returns its length; nothing is written if that exceeds `capacity` |
+//! | `fetch_read(handle, buffer, capacity) -> i64`   | Reads up to `capacity` bytes of the body into `buffer`, returning how many, or 0 at its end |
+//! | `fetch_close(handle)`                           | Releases the response                                         |
+//!
+//! A negative return value is an error. On native targets, [`ClientFetch`]
+//! sends the requests with a reqwest client instead.
+
+use crate::filename::{self, Restriction};
+use crate::ledger;
+use crate::redact;
+use crate::staging;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use url::Url;
+
+#[cfg(target_family = "wasm")]
+pub use host::HostFetch;
+
+/// A request handed to a [`Fetch`] backend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Request {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+}
+
+/// The head of a response.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Head {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+}
+
+impl Head {
+    /// Returns the value of the first header named `name` (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A response from a [`Fetch`] backend, with its body still to be read.
+pub struct Response {
+    pub head: Head,
+    pub body: Box<dyn Read>,
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("head", &self.head)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Sends requests for [`download`], following redirects.
+pub trait Fetch: fmt::Debug + Send + Sync {
+    /// Sends `request`, returning the response.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no response is received.
+    fn fetch(&self, request: &Request) -> io::Result<Response>;
+}
+
+/// Settings of a [`download`].
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Number of attempts, each continuing what the last one received.
+    pub tries: u32,
+    /// Expected SHA-256 digest (lowercase hex) of the file.
+    pub checksum: Option<String>,
+    /// How names taken from the URL are sanitized.
+    pub restriction: Restriction,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            tries: 1,
+            checksum: None,
+            restriction: Restriction::default(),
+        }
+    }
+}
+
+/// Downloads `url` into `output`, or into a file named after the URL when
+/// `output` is a directory, and returns the path of the file.
+///
+/// The body is written beside the file as its [`staging`] path and moved
+/// into place once it is complete and matches [`Options::checksum`]. A
+/// failed attempt leaves it there, and the next attempt, or a later call,
+/// continues it with a `Range` request.
+///
+/// # Errors
+///
+/// Returns an error if the URL is invalid, every attempt fails, the server
+/// refuses the request, or the file does not match its checksum.
+pub fn download(
+    backend: &dyn Fetch,
+    url: &str,
+    output: &Path,
+    options: &Options,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let target = if output.is_dir() {
+        let name = filename::derive_filename(&Url::parse(url)?, false, false);
+        output.join(
+            filename::sanitize(&name, options.restriction)
+                .unwrap_or_else(|| "index.html".to_string()),
+        )
+    } else {
+        output.to_path_buf()
+    };
+    let dir = target.parent().unwrap_or(Path::new(""));
+    let part = staging::path(dir, &target);
+
+    let mut last_error: Option<Box<dyn Error>> = None;
+    for attempt in 0..options.tries.max(1) {
+        if attempt > 0 {
+            if let Some(err) = &last_error {
+                eprintln!("Warning: retrying {}: {}", redact::url(url), err);
+            }
+        }
+        match transfer(backend, url, &part) {
+            Ok(()) => {
+                last_error = None;
+                break;
+            }
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => return Err(err.into()),
+            Err(err) => last_error = Some(err.into()),
+        }
+    }
+    if let Some(err) = last_error {
+        return Err(err);
+    }
+
+    if let Some(expected) = &options.checksum {
+        let actual = ledger::sha256_file(&part)?;
+        if actual != *expected {
+            fs::remove_file(&part)?;
+            return Err(format!(
+                "Checksum mismatch for {}: expected {}, got {}",
+                redact::url(url),
+                expected,
+                actual
+            )
+            .into());
+        }
+    }
+    staging::move_file(&part, &target)?;
+    Ok(target)
+}
+
+/// Receives the body of `url` into `part`, continuing what it holds.
+///
+/// A response the server refused is an `InvalidData` error, which another
+/// attempt would not change.
+fn transfer(backend: &dyn Fetch, url: &str, part: &Path) -> io::Result<()> {
+    let offset = fs::metadata(part).map_or(0, |meta| meta.len());
+    let mut headers = Vec::new();
+    if offset > 0 {
+        headers.push(("Range".to_string(), format!("bytes={}-", offset)));
+    }
+    let mut response = backend.fetch(&Request {
+        method: "GET".to_string(),
+        url: url.to_string(),
+        headers,
+    })?;
+    let status = response.head.status;
+    // A server that ignores the range sends the whole body again.
+    let append = match status {
+        206 if offset > 0 => true,
+        200..=299 => false,
+        416 if offset > 0 => {
+            fs::remove_file(part)?;
+            return Err(io::Error::other(
+                "the partial file is longer than the resource",
+            ));
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the server answered {}", status),
+            ))
+        }
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(part)?;
+    io::copy(&mut response.body, &mut file)?;
+    file.sync_all()
+}
+
+/// A backend that sends requests with a reqwest client.
+#[cfg(not(target_family = "wasm"))]
+#[derive(Debug, Clone, Default)]
+pub struct ClientFetch {
+    client: reqwest::blocking::Client,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl ClientFetch {
+    pub fn new(client: reqwest::blocking::Client) -> ClientFetch {
+        ClientFetch { client }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Fetch for ClientFetch {
+    fn fetch(&self, request: &Request) -> io::Result<Response> {
+        let method = reqwest::Method::from_bytes(request.method.as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let mut builder = self.client.request(method, &request.url);
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        let response = builder.send().map_err(io::Error::other)?;
+        let head = Head {
+            status: response.status().as_u16(),
+            headers: response
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_string()))
+                })
+                .collect(),
+        };
+        Ok(Response {
+            head,
+            body: Box::new(response),
+        })
+    }
+}
+
+#[cfg(target_family = "wasm")]
+mod host {
+    use super::{Fetch, Head, Request, Response};
+    use std::io::{self, Read};
+
+    #[link(wasm_import_module = "rustwget")]
+    extern "C" {
+        fn fetch(request: *const u8, len: usize) -> i64;
+        fn fetch_head(handle: u32, buffer: *mut u8, capacity: usize) -> i64;
+        fn fetch_read(handle: u32, buffer: *mut u8, capacity: usize) -> i64;
+        fn fetch_close(handle: u32);
+    }
+
+    /// A backend that calls the `fetch` imported from the host.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct HostFetch;
+
+    /// The body of a response, released when dropped.
+    struct Body {
+        handle: u32,
+    }
+
+    impl Read for Body {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            // SAFETY: the host writes at most `buffer.len()` bytes into `buffer`.
+            let read = unsafe { fetch_read(self.handle, buffer.as_mut_ptr(), buffer.len()) };
+            usize::try_from(read).map_err(|_| failed("reading the body"))
+        }
+    }
+
+    impl Drop for Body {
+        fn drop(&mut self) {
+            // SAFETY: the handle was returned by `fetch` and is released once.
+            unsafe { fetch_close(self.handle) }
+        }
+    }
+
+    impl Fetch for HostFetch {
+        fn fetch(&self, request: &Request) -> io::Result<Response> {
+            let request = serde_json::to_vec(request)?;
+            // SAFETY: the host only reads `request.len()` bytes of `request`.
+            let handle = unsafe { fetch(request.as_ptr(), request.len()) };
+            let body = Body {
+                handle: u32::try_from(handle).map_err(|_| failed("sending the request"))?,
+            };
+            let mut head = vec![0; 4096];
+            loop {
+                // SAFETY: the host writes at most `head.len()` bytes into `head`.
+                let len = unsafe { fetch_head(body.handle, head.as_mut_ptr(), head.len()) };
+                let len = usize::try_from(len).map_err(|_| failed("reading the head"))?;
+                if len <= head.len() {
+                    head.truncate(len);
+                    break;
+                }
+                head.resize(len, 0);
+            }
+            Ok(Response {
+                head: serde_json::from_slice::<Head>(&head)?,
+                body: Box::new(body),
+            })
+        }
+    }
+
+    fn failed(what: &str) -> io::Error {
+        io::Error::other(format!("the host's fetch failed {}", what))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn test_download_resumes_and_verifies() {
+        let full = mock("GET", "/fetch/tool.tar.gz")
+            .match_header("range", Matcher::Missing)
+            .with_body("abcdef")
+            .create();
+        let rest = mock("GET", "/fetch/tool.tar.gz")
+            .match_header("range", "bytes=3-")
+            .with_status(206)
+            .with_body("def")
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("{}/fetch/tool.tar.gz", server_url());
+        let backend = ClientFetch::default();
+        let target = dir.path().join("tool.tar.gz");
+
+        fs::write(staging::path(dir.path(), &target), "abc").unwrap();
+        let options = Options {
+            checksum: Some(ledger::hex(&Sha256::digest(b"abcdef"))),
+            ..Options::default()
+        };
+        assert_eq!(
+            download(&backend, &url, dir.path(), &options).unwrap(),
+            target
+        );
+        assert_eq!(fs::read(&target).unwrap(), b"abcdef");
+        rest.assert();
+
+        let options = Options {
+            checksum: Some("0".repeat(64)),
+            ..Options::default()
+        };
+        let err = download(&backend, &url, &target, &options).unwrap_err();
+        assert!(err.to_string().starts_with("Checksum mismatch"));
+        assert_eq!(fs::read(&target).unwrap(), b"abcdef");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        full.assert();
+
+        let missing = format!("{}/fetch/missing", server_url());
+        let err = download(&backend, &missing, dir.path(), &options).unwrap_err();
+        assert_eq!(err.to_string(), "the server answered 501");
+    }
+}
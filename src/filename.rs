@@ -0,0 +1,420 @@
+//! Safe local file names for names chosen by servers.
+//!
+//! A file name taken from a URL or a `Content-Disposition` header is under
+//! the control of whoever runs the server. [`sanitize`] reduces it to a
+//! single path component that stays inside the target directory, without
+//! control characters, and short enough for common filesystems. The
+//! `--restrict-file-names` mode adds the rules of other platforms:
+//!
+//! * `unix`: only `/`, `\`, and control characters are replaced.
+//! * `windows`: also `: * ? " < > |`, trailing dots and spaces, and reserved
+//!   device names such as `CON` or `LPT1`.
+//! * `ascii`: like `unix`, and non-ASCII characters are replaced too.
+//!
+//! On Windows, paths longer than the 260 characters its APIs accept by
+//! default are opened through their `\\?\` form; see [`long_path`].
+
+use crate::decompress;
+use crate::extension;
+use percent_encoding::percent_decode_str;
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use url::Url;
+
+/// Names accepted by `--restrict-file-names`.
+pub const RESTRICTIONS: [&str; 3] = ["unix", "windows", "ascii"];
+
+/// Longest file name, in bytes, that common filesystems accept.
+const MAX_LEN: usize = 255;
+
+/// Longest path that Windows accepts without the `\\?\` prefix.
+const MAX_PATH: usize = 260;
+
+/// Device names that Windows reserves regardless of their extension.
+const RESERVED: [&str; 32] = [
+    "CON", "PRN", "AUX", "NUL", "CONIN$", "CONOUT$", "COM0", "COM1", "COM2", "COM3", "COM4",
+    "COM5", "COM6", "COM7", "COM8", "COM9", "COM¹", "COM²", "COM³", "LPT0", "LPT1", "LPT2", "LPT3",
+    "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9", "LPT¹", "LPT²", "LPT³",
+];
+
+/// Which characters are allowed in file names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restriction {
+    Unix,
+    Windows,
+    Ascii,
+}
+
+impl Default for Restriction {
+    /// The rules of the platform rustwget runs on.
+    fn default() -> Restriction {
+        if cfg!(windows) {
+            Restriction::Windows
+        } else {
+            Restriction::Unix
+        }
+    }
+}
+
+impl Restriction {
+    /// Parses one of the [`RESTRICTIONS`].
+    pub fn parse(name: &str) -> Result<Restriction, String> {
+        match name {
+            "unix" => Ok(Restriction::Unix),
+            "windows" => Ok(Restriction::Windows),
+            "ascii" => Ok(Restriction::Ascii),
+            other => Err(format!(
+                "unknown file name restriction '{}': expected one of {}",
+                other,
+                RESTRICTIONS.join(", ")
+            )),
+        }
+    }
+
+    fn allows(self, c: char) -> bool {
+        match self {
+            _ if c.is_control() || c == '/' || c == '\\' => false,
+            Restriction::Windows => !matches!(c, ':' | '*' | '?' | '"' | '<' | '>' | '|'),
+            Restriction::Ascii => c.is_ascii(),
+            Restriction::Unix => true,
+        }
+    }
+}
+
+/// Reduces a name chosen by a server to a safe file name.
+///
+/// Only the last component of a path is kept, so `../../.bashrc` and
+/// `C:\Windows\x.dll` cannot point outside the target directory; disallowed
+/// characters become `_`, and overlong names are shortened, keeping their
+/// extension.
+///
+/// # Returns
+///
+/// * `Option<String>`: The file name, or `None` if nothing usable is left.
+pub fn sanitize(name: &str, restriction: Restriction) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let mut name = shorten(
+        name.chars()
+            .map(|c| if restriction.allows(c) { c } else { '_' })
+            .collect(),
+    );
+    if restriction == Restriction::Windows {
+        // Shortening may leave a name ending in a space, or one that is
+        // reserved once its trailing spaces are gone.
+        name.truncate(name.trim_end_matches(['.', ' ']).len());
+        let stem = name.split('.').next().unwrap_or_default();
+        if RESERVED
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(stem.trim_end()))
+        {
+            name = shorten(format!("_{}", name));
+            name.truncate(name.trim_end_matches(['.', ' ']).len());
+        }
+    }
+    if name.trim_matches('.').is_empty() {
+        return None;
+    }
+    Some(name)
+}
+
+/// Cuts `name` to [`MAX_LEN`] bytes, keeping a short extension.
+fn shorten(mut name: String) -> String {
+    if name.len() > MAX_LEN {
+        let extension = match name.rsplit_once('.') {
+            Some((_, extension)) if extension.len() <= 16 => format!(".{}", extension),
+            _ => String::new(),
+        };
+        let mut end = MAX_LEN - extension.len();
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name = format!("{}{}", &name[..end], extension);
+    }
+    name
+}
+
+/// Derives the local filename for a URL from its last path segment.
+///
+/// The segment is percent-decoded, so `r%C3%A9sum%C3%A9.pdf` becomes
+/// `résumé.pdf`; the result may therefore hold any character and must be
+/// passed through [`sanitize`] before use. URLs whose path
+/// ends in a slash (or that have no path at all) map to `index.html`,
+/// mirroring wget's behaviour.
+pub fn default_filename(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|segment| !segment.is_empty())
+        .map_or("index.html".to_string(), |segment| {
+            percent_decode_str(segment).decode_utf8_lossy().into_owned()
+        })
+}
+
+/// Returns the name a download of `url` is saved under when no output is
+/// given, before it is [`sanitize`]d: the last segment of its path, or with
+/// `auto_extension` its base name with its query values (see
+/// [`extension::base_name`]), without a compression suffix when `decompress`
+/// is set.
+pub fn derive_filename(url: &Url, auto_extension: bool, decompress: bool) -> String {
+    let filename = match auto_extension {
+        true => extension::base_name(url),
+        false => default_filename(url),
+    };
+    match decompress {
+        true => decompress::strip_extension(&filename).to_string(),
+        false => filename,
+    }
+}
+
+/// Applies [`sanitize`] to every named component of `path`, keeping its root
+/// and any `.` or `..` components.
+///
+/// This is for output paths filled in from URL patterns, whose directories
+/// must be valid names too; a component with nothing usable left becomes `_`.
+pub fn sanitize_path(path: &Path, restriction: Restriction) -> PathBuf {
+    path.components()
+        .map(|component| match component {
+            Component::Normal(name) => match name.to_str() {
+                Some(name) => {
+                    OsString::from(sanitize(name, restriction).unwrap_or_else(|| "_".to_string()))
+                }
+                None => name.to_owned(),
+            },
+            other => other.as_os_str().to_owned(),
+        })
+        .collect()
+}
+
+/// Returns a form of `path` that can be opened even when it is longer than
+/// Windows' 260-character limit.
+///
+/// On Windows, a long path is made absolute and given the `\\?\` prefix,
+/// which lifts the limit. Short paths, and all paths on other platforms, are
+/// returned as they are.
+pub fn long_path(path: &Path) -> PathBuf {
+    if !cfg!(windows) {
+        return path.to_path_buf();
+    }
+    match std::path::absolute(path) {
+        Ok(absolute) if absolute.as_os_str().len() >= MAX_PATH => match absolute.to_str() {
+            Some(absolute) => PathBuf::from(verbatim(absolute)),
+            None => absolute,
+        },
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Adds the `\\?\` prefix to an absolute Windows path, or `\\?\UNC\` to a network one.
+fn verbatim(absolute: &str) -> String {
+    if absolute.starts_with(r"\\?\") {
+        absolute.to_string()
+    } else if let Some(share) = absolute.strip_prefix(r"\\") {
+        format!(r"\\?\UNC\{}", share)
+    } else {
+        format!(r"\\?\{}", absolute.replace('/', "\\"))
+    }
+}
+
+/// Returns whether `path` is relative and never leaves the directory it is resolved in.
+pub fn is_contained(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Returns the file name suggested by a `Content-Disposition` header, if any.
+///
+/// The RFC 5987 form `filename*=UTF-8''%E2%82%AC.pdf`, including the RFC 2231
+/// continuations `filename*0*=...; filename*1*=...`, takes precedence over a
+/// plain `filename`, which older clients read instead.
+pub fn disposition_filename(header: &str) -> Option<String> {
+    let parameters = parameters(header);
+    extended_filename(&parameters)
+        .or_else(|| {
+            parameters
+                .into_iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("filename"))
+                .map(|(_, value)| value)
+        })
+        .filter(|value| !value.is_empty())
+}
+
+/// Decodes the `filename*` parameter, or its numbered continuations.
+fn extended_filename(parameters: &[(String, String)]) -> Option<String> {
+    // Each piece is (index, whether it is percent-encoded, value).
+    let mut pieces: Vec<(u32, bool, &str)> = parameters
+        .iter()
+        .filter_map(|(name, value)| {
+            let rest = name
+                .to_ascii_lowercase()
+                .strip_prefix("filename*")?
+                .to_string();
+            let (index, encoded) = match rest.strip_suffix('*') {
+                Some(index) => (index.to_string(), true),
+                None if rest.is_empty() => (rest, true),
+                None => (rest, false),
+            };
+            let index = if index.is_empty() {
+                0
+            } else {
+                index.parse().ok()?
+            };
+            Some((index, encoded, value.as_str()))
+        })
+        .collect();
+    pieces.sort_by_key(|(index, _, _)| *index);
+    let (_, first_encoded, first) = pieces.first()?;
+
+    // Only an encoded first piece declares a charset: `UTF-8'en'...`.
+    let (charset, first) = match first_encoded {
+        true => {
+            let mut fields = first.splitn(3, '\'');
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(charset), Some(_language), Some(value)) => {
+                    (charset.to_ascii_lowercase(), value)
+                }
+                _ => return None,
+            }
+        }
+        false => ("us-ascii".to_string(), *first),
+    };
+    let mut bytes = Vec::new();
+    for (position, (_, encoded, value)) in pieces.iter().enumerate() {
+        let value = if position == 0 { first } else { value };
+        match encoded {
+            true => bytes.extend(percent_decode_str(value)),
+            false => bytes.extend_from_slice(value.as_bytes()),
+        }
+    }
+    match charset.as_str() {
+        "utf-8" | "us-ascii" => String::from_utf8(bytes).ok(),
+        "iso-8859-1" | "latin1" => Some(bytes.into_iter().map(char::from).collect()),
+        _ => None,
+    }
+}
+
+/// Splits the `name=value` parameters of a header, unquoting quoted values.
+fn parameters(header: &str) -> Vec<(String, String)> {
+    let mut parameters = Vec::new();
+    let mut rest = header.split_once(';').map_or("", |(_, rest)| rest);
+    while let Some((name, after)) = rest.split_once('=') {
+        let name = name.trim().trim_start_matches(';').trim().to_string();
+        let after = after.trim_start();
+        let (value, remainder) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((index, c)) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                        '"' => {
+                            end = index + 1;
+                            break;
+                        }
+                        c => value.push(c),
+                    }
+                }
+                (value, &quoted[end..])
+            }
+            None => {
+                let end = after.find(';').unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            }
+        };
+        parameters.push((name, value));
+        rest = remainder.split_once(';').map_or("", |(_, rest)| rest);
+    }
+    parameters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        let unix = |name| sanitize(name, Restriction::Unix);
+        let windows = |name| sanitize(name, Restriction::Windows);
+        assert_eq!(unix("../../.bashrc").unwrap(), ".bashrc");
+        assert_eq!(unix("..\\..\\evil.exe").unwrap(), "evil.exe");
+        assert_eq!(unix("report\n2024.pdf").unwrap(), "report_2024.pdf");
+        assert_eq!(unix(".."), None);
+        assert_eq!(unix("dir/"), None);
+        assert_eq!(windows("a:b?.txt").unwrap(), "a_b_.txt");
+        assert_eq!(windows("con.txt").unwrap(), "_con.txt");
+        assert_eq!(windows("notes. . ").unwrap(), "notes");
+        assert_eq!(windows("CONOUT$.log").unwrap(), "_CONOUT$.log");
+        assert_eq!(windows("com¹ .txt").unwrap(), "_com¹ .txt");
+        assert_eq!(
+            sanitize("résumé.pdf", Restriction::Ascii).unwrap(),
+            "r_sum_.pdf"
+        );
+
+        let long = format!("{}.tar.gz", "é".repeat(200));
+        let short = unix(&long).unwrap();
+        assert!(short.len() <= MAX_LEN && short.ends_with("é.gz"));
+        // Shortening must not leave trailing spaces or a reserved name.
+        let spaced = format!("con{}x", " ".repeat(300));
+        assert_eq!(windows(&spaced).unwrap(), "_con");
+    }
+
+    #[test]
+    fn test_disposition_filename() {
+        assert_eq!(
+            disposition_filename(r#"attachment; filename="Q1 \"final\".pdf"; size=10"#).unwrap(),
+            r#"Q1 "final".pdf"#
+        );
+        assert_eq!(
+            disposition_filename("attachment;FILENAME=data.csv").unwrap(),
+            "data.csv"
+        );
+        assert_eq!(
+            disposition_filename(
+                "attachment; filename=\"EURO rates.pdf\"; filename*=UTF-8''%E2%82%AC%20rates.pdf"
+            )
+            .unwrap(),
+            "€ rates.pdf"
+        );
+        assert_eq!(
+            disposition_filename("attachment; filename*=iso-8859-1'de'Stra%DFe.txt").unwrap(),
+            "Straße.txt"
+        );
+        assert_eq!(
+            disposition_filename(
+                "attachment; filename*1=\" 2024.csv\"; filename*0*=UTF-8''R%C3%A9sum%C3%A9"
+            )
+            .unwrap(),
+            "Résumé 2024.csv"
+        );
+        assert_eq!(disposition_filename("inline"), None);
+    }
+
+    #[test]
+    fn test_sanitize_path() {
+        assert_eq!(
+            sanitize_path(Path::new("photos/aux/img:1.jpg."), Restriction::Windows),
+            Path::new("photos/_aux/img_1.jpg")
+        );
+        assert_eq!(
+            sanitize_path(Path::new("../2024/.../a.txt"), Restriction::Unix),
+            Path::new("../2024/_/a.txt")
+        );
+    }
+
+    #[test]
+    fn test_long_paths() {
+        assert_eq!(verbatim(r"C:\deep\file.bin"), r"\\?\C:\deep\file.bin");
+        assert_eq!(
+            verbatim(r"\\server\share\file.bin"),
+            r"\\?\UNC\server\share\file.bin"
+        );
+        assert_eq!(verbatim(r"\\?\C:\file.bin"), r"\\?\C:\file.bin");
+        assert_eq!(long_path(Path::new("short.bin")), Path::new("short.bin"));
+    }
+
+    #[test]
+    fn test_is_contained() {
+        assert!(is_contained(Path::new("a/b.txt")));
+        assert!(!is_contained(Path::new("../b.txt")));
+        assert!(!is_contained(Path::new("/etc/passwd")));
+    }
+}
@@ -0,0 +1,217 @@
+//! Property tests for names and URLs chosen by servers, built with the `fuzz`
+//! feature: `cargo test --features fuzz fuzz`.
+//!
+//! Each property is checked against many inputs drawn from a seeded
+//! generator that favours what attacks file names are made of: separators,
+//! dots, percent escapes, quotes, reserved device names, control and
+//! right-to-left characters, and long runs. A failure prints the input, which
+//! the same seed reproduces. `FUZZ_SEED` and `FUZZ_CASES` change the seed and
+//! the number of inputs per property.
+
+use crate::filename::{self, Restriction};
+use crate::glob;
+use std::path::{Component, Path};
+use url::Url;
+
+/// Fragments inputs are assembled from.
+const PIECES: [&str; 40] = [
+    "/",
+    "\\",
+    ".",
+    "..",
+    "%",
+    "%2e",
+    "%2F",
+    "%5c",
+    "%00",
+    "%ff",
+    "%e2%80%ae",
+    ":",
+    "*",
+    "?",
+    "\"",
+    "<",
+    ">",
+    "|",
+    "'",
+    ";",
+    "=",
+    " ",
+    "\t",
+    "\n",
+    "\0",
+    "\u{202e}",
+    "é",
+    "日本",
+    "CON",
+    "lpt1",
+    "a",
+    "file",
+    ".txt",
+    "filename=",
+    "filename*=UTF-8''",
+    "[1-3]",
+    "{a,b}",
+    "[",
+    "}",
+    "#",
+];
+
+/// Longest file name `sanitize` may return, in bytes.
+const MAX_LEN: usize = 255;
+
+/// A xorshift generator, so that every run draws the same inputs.
+struct Inputs(u64);
+
+impl Inputs {
+    fn new() -> Inputs {
+        let seed = std::env::var("FUZZ_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or(0x5eed_cafe_f00d_d00d_u64);
+        Inputs(seed.max(1))
+    }
+
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+
+    /// Returns a string of up to `pieces` fragments, some repeated at length.
+    fn string(&mut self, pieces: usize) -> String {
+        let mut text = String::new();
+        for _ in 0..self.next(pieces + 1) {
+            let piece = PIECES[self.next(PIECES.len())];
+            let repeat = match self.next(16) {
+                0 => 100 + self.next(200),
+                _ => 1,
+            };
+            text += &piece.repeat(repeat);
+        }
+        text
+    }
+}
+
+/// Checks `property` against `FUZZ_CASES` inputs made by `input`.
+fn check<T: std::fmt::Debug>(mut input: impl FnMut(&mut Inputs) -> T, property: impl Fn(&T)) {
+    let cases = std::env::var("FUZZ_CASES")
+        .ok()
+        .and_then(|cases| cases.parse().ok())
+        .unwrap_or(20_000);
+    let mut inputs = Inputs::new();
+    for _ in 0..cases {
+        let value = input(&mut inputs);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| property(&value)));
+        if let Err(panic) = result {
+            eprintln!("failing input: {:?}", value);
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+/// Asserts that `name` is a file name `restriction` allows.
+fn assert_safe(name: &str, restriction: Restriction) {
+    assert!(filename::is_contained(Path::new(name)), "{:?}", name);
+    assert_eq!(Path::new(name).components().count(), 1, "{:?}", name);
+    assert!(name.len() <= MAX_LEN, "{:?}", name);
+    assert!(!name
+        .chars()
+        .any(|c| c.is_control() || c == '/' || c == '\\'));
+    match restriction {
+        Restriction::Windows => {
+            assert!(!name.ends_with(['.', ' ']), "{:?}", name);
+            assert!(!name.contains([':', '*', '?', '"', '<', '>', '|']));
+        }
+        Restriction::Ascii => assert!(name.is_ascii()),
+        Restriction::Unix => {}
+    }
+}
+
+const ALL: [Restriction; 3] = [Restriction::Unix, Restriction::Windows, Restriction::Ascii];
+
+#[test]
+fn fuzz_sanitize() {
+    check(
+        |inputs| inputs.string(12),
+        |name| {
+            for restriction in ALL {
+                if let Some(safe) = filename::sanitize(name, restriction) {
+                    assert_safe(&safe, restriction);
+                    assert_eq!(filename::sanitize(&safe, restriction).as_ref(), Some(&safe));
+                }
+            }
+        },
+    );
+}
+
+#[test]
+fn fuzz_sanitize_path() {
+    check(
+        |inputs| inputs.string(12),
+        |path| {
+            for restriction in ALL {
+                for component in filename::sanitize_path(Path::new(path), restriction).components()
+                {
+                    if let Component::Normal(name) = component {
+                        let name = name.to_str().unwrap();
+                        assert_eq!(filename::sanitize(name, restriction).as_deref(), Some(name));
+                    }
+                }
+            }
+        },
+    );
+}
+
+#[test]
+fn fuzz_disposition_filename() {
+    check(
+        |inputs| format!("attachment; {}", inputs.string(12)),
+        |header| {
+            if let Some(name) = filename::disposition_filename(header) {
+                for restriction in ALL {
+                    if let Some(safe) = filename::sanitize(&name, restriction) {
+                        assert_safe(&safe, restriction);
+                    }
+                }
+            }
+        },
+    );
+}
+
+#[test]
+fn fuzz_derive_filename() {
+    check(
+        |inputs| format!("https://example.com/{}", inputs.string(12)),
+        |url| {
+            let Ok(url) = Url::parse(url) else {
+                return;
+            };
+            for (auto_extension, decompress) in [(false, false), (true, false), (false, true)] {
+                let name = filename::derive_filename(&url, auto_extension, decompress);
+                for restriction in ALL {
+                    if let Some(safe) = filename::sanitize(&name, restriction) {
+                        assert_safe(&safe, restriction);
+                    }
+                }
+            }
+        },
+    );
+}
+
+#[test]
+fn fuzz_expand_glob() {
+    check(
+        |inputs| format!("https://example.com/{}", inputs.string(8)),
+        |url| {
+            let Ok(expanded) = glob::expand_glob(url, Some("#1-#2")) else {
+                return;
+            };
+            assert!((1..=100_000).contains(&expanded.len()));
+            if !glob::is_pattern(url) {
+                assert_eq!(expanded, [(url.clone(), None)]);
+            }
+        },
+    );
+}
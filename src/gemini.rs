@@ -0,0 +1,457 @@
+//! The Gemini protocol, for `gemini://` URLs.
+//!
+//! A request is the URL on a line of its own, sent over TLS to port 1965
+//! unless the URL names another. The response starts with a header line of a
+//! two-digit status and a meta field; after a `2x` status, the body, whose
+//! media type is the meta field, runs to the end of the connection. Redirects
+//! (`3x`) are followed up to [`MAX_REDIRECTS`] times within Gemini, and any
+//! other status fails the download with the server's message. There are no
+//! ranges, so an interrupted download starts over.
+//!
+//! Capsules mostly serve self-signed certificates, which are trusted on first
+//! use: the SHA-256 fingerprint of the certificate a host first presents is
+//! recorded in [`known_hosts_path`], one `HOST:PORT FINGERPRINT EXPIRES` line
+//! each, and a different certificate is refused until the recorded one has
+//! expired. Removing a host's line trusts whatever it presents next.
+
+use crate::dns;
+use crate::logfile;
+use crate::paths;
+use openssl::asn1::Asn1Time;
+use openssl::hash::MessageDigest;
+use openssl::ssl::{SslConnector, SslMethod, SslOptions, SslStream, SslVerifyMode, SslVersion};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use url::{Host, Url};
+
+/// The prefix of Gemini URLs.
+pub const SCHEME: &str = "gemini://";
+
+/// The port of a URL that names none.
+const PORT: u16 = 1965;
+
+/// The most redirects followed for one request.
+pub const MAX_REDIRECTS: usize = 5;
+
+/// The longest request URL the protocol allows, in bytes.
+const MAX_URL: usize = 1024;
+
+/// The longest response header, without its line ending.
+const MAX_HEADER: usize = 1029;
+
+/// The media type of a successful response whose meta field is empty.
+const DEFAULT_MIME: &str = "text/gemini; charset=utf-8";
+
+/// `SSL_OP_IGNORE_UNEXPECTED_EOF` of OpenSSL 3, which the bindings do not
+/// name: a connection closed without `close_notify` ends the body.
+const IGNORE_UNEXPECTED_EOF: SslOptions = SslOptions::from_bits_retain(1 << 7);
+
+/// Whether `url` is a Gemini URL.
+pub fn is_gemini(url: &str) -> bool {
+    url.get(..SCHEME.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(SCHEME))
+}
+
+/// Returns the file where the certificates of Gemini hosts are recorded.
+pub fn known_hosts_path() -> PathBuf {
+    paths::data_dir().join("gemini_known_hosts")
+}
+
+/// A certificate trusted for a host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Known {
+    /// The SHA-256 digest of the certificate, in lowercase hex.
+    fingerprint: String,
+    /// When the certificate expires, in seconds since the Unix epoch.
+    expires: i64,
+}
+
+/// The certificates trusted for Gemini hosts.
+///
+/// The file, if any, is read on first use and rewritten whenever a host is
+/// trusted; without one, certificates are only remembered for the run.
+#[derive(Debug, Default)]
+pub struct KnownHosts {
+    path: Option<PathBuf>,
+    hosts: Mutex<Option<HashMap<String, Known>>>,
+}
+
+impl KnownHosts {
+    /// Creates the record kept in the file at `path`.
+    pub fn open(path: PathBuf) -> KnownHosts {
+        KnownHosts {
+            path: Some(path),
+            hosts: Mutex::default(),
+        }
+    }
+
+    /// Trusts `host` to present a certificate with `fingerprint` that
+    /// expires at `expires`, recording it if the host is new.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the host is known by another certificate that has
+    /// not expired, or the record cannot be written.
+    fn check(&self, host: &str, fingerprint: &str, expires: i64) -> Result<(), String> {
+        let mut hosts = self.hosts.lock().unwrap_or_else(|err| err.into_inner());
+        let hosts = hosts.get_or_insert_with(|| self.load());
+        match hosts.get(host) {
+            Some(known) if known.fingerprint == fingerprint => return Ok(()),
+            Some(known) if known.expires > chrono::Utc::now().timestamp() => {
+                return Err(format!(
+                "the certificate of {} is not the one trusted on first use (SHA-256 {}, now {}); \
+                     remove its line from {} if the change is expected",
+                host,
+                known.fingerprint,
+                fingerprint,
+                self.path
+                    .as_ref()
+                    .map_or("the known hosts".to_string(), |path| path
+                        .display()
+                        .to_string())
+            ))
+            }
+            Some(_) => logfile::warning(&format!(
+                "Trusting the new certificate of {} in place of its expired one: SHA-256 {}",
+                host, fingerprint
+            )),
+            None => logfile::info(&format!(
+                "Trusting the certificate of {} on first use: SHA-256 {}",
+                host, fingerprint
+            )),
+        }
+        hosts.insert(
+            host.to_string(),
+            Known {
+                fingerprint: fingerprint.to_string(),
+                expires,
+            },
+        );
+        self.save(hosts)
+            .map_err(|err| format!("cannot record the certificate of {}: {}", host, err))
+    }
+
+    fn load(&self) -> HashMap<String, Known> {
+        let Some(text) = self
+            .path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+        else {
+            return HashMap::new();
+        };
+        text.lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let host = fields.next()?;
+                let fingerprint = fields.next()?;
+                let expires = fields.next()?.parse().ok()?;
+                Some((
+                    host.to_string(),
+                    Known {
+                        fingerprint: fingerprint.to_ascii_lowercase(),
+                        expires,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    fn save(&self, hosts: &HashMap<String, Known>) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let sorted: BTreeMap<_, _> = hosts.iter().collect();
+        let text: String = sorted
+            .into_iter()
+            .map(|(host, known)| format!("{} {} {}\n", host, known.fingerprint, known.expires))
+            .collect();
+        fs::write(path, text)
+    }
+}
+
+/// A successful response.
+#[derive(Debug)]
+pub struct Response {
+    /// The URL that answered, after any redirects.
+    pub url: Url,
+    /// The media type of the body.
+    pub mime: String,
+    /// The body, read to the end of the connection.
+    pub body: SslStream<TcpStream>,
+}
+
+/// Requests `url`, following redirects, giving up on a connection that
+/// stays silent for `timeout`.
+///
+/// # Errors
+///
+/// Returns an error if the host cannot be reached, presents a certificate
+/// other than the one [`KnownHosts`] trusts for it, or answers with something
+/// other than success.
+pub fn get(
+    url: &str,
+    known_hosts: &KnownHosts,
+    timeout: Duration,
+) -> Result<Response, Box<dyn std::error::Error>> {
+    let mut url = Url::parse(url)?;
+    for _ in 0..=MAX_REDIRECTS {
+        let mut stream = connect(&url, known_hosts, timeout)?;
+        let (status, meta) = exchange(&mut stream, &url)?;
+        logfile::detail(&format!("Gemini {}: {} {}", url, status, meta));
+        match status / 10 {
+            2 => {
+                let mime = match meta.is_empty() {
+                    true => DEFAULT_MIME.to_string(),
+                    false => meta,
+                };
+                return Ok(Response {
+                    url,
+                    mime,
+                    body: stream,
+                });
+            }
+            3 => {
+                let next = url.join(&meta)?;
+                if next.scheme() != "gemini" {
+                    return Err(format!("{} redirects out of Gemini, to {}", url, next).into());
+                }
+                url = next;
+            }
+            1 => return Err(format!("{} asks for input: {}", url, meta).into()),
+            6 => return Err(format!("{} requires a client certificate: {}", url, meta).into()),
+            _ => return Err(format!("Failed to download: Gemini {} {}", status, meta).into()),
+        }
+    }
+    Err(format!("{} redirects more than {} times", url, MAX_REDIRECTS).into())
+}
+
+/// Opens a TLS connection to the host of `url`, checking its certificate
+/// against `known_hosts`.
+fn connect(
+    url: &Url,
+    known_hosts: &KnownHosts,
+    timeout: Duration,
+) -> Result<SslStream<TcpStream>, Box<dyn std::error::Error>> {
+    let host = match url.host() {
+        Some(Host::Domain(domain)) => domain.to_string(),
+        Some(Host::Ipv4(address)) => address.to_string(),
+        Some(Host::Ipv6(address)) => address.to_string(),
+        None => return Err(format!("{} names no host", url).into()),
+    };
+    let port = url.port().unwrap_or(PORT);
+    let tcp = dns::connect(&host, port, timeout)
+        .map_err(|err| format!("cannot connect to {}: {}", url, err))?;
+
+    // The certificate is checked against the known hosts instead of a CA.
+    let mut builder = SslConnector::builder(SslMethod::tls_client())?;
+    builder.set_verify(SslVerifyMode::NONE);
+    builder.set_min_proto_version(Some(SslVersion::TLS1_2))?;
+    // Servers may close the connection without a TLS close_notify.
+    builder.set_options(IGNORE_UNEXPECTED_EOF);
+    let stream = builder
+        .build()
+        .configure()?
+        .verify_hostname(false)
+        .connect(&host, tcp)
+        .map_err(|err| format!("TLS handshake with {} failed: {}", url, err))?;
+    let certificate = stream
+        .ssl()
+        .peer_certificate()
+        .ok_or_else(|| format!("{} presented no certificate", url))?;
+    let fingerprint: String = certificate
+        .digest(MessageDigest::sha256())?
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let expires = Asn1Time::from_unix(0)?.diff(certificate.not_after())?;
+    known_hosts.check(
+        &format!("{}:{}", host, port),
+        &fingerprint,
+        i64::from(expires.days) * 86400 + i64::from(expires.secs),
+    )?;
+    Ok(stream)
+}
+
+/// Sends the request for `url` on `stream` and reads the status and meta
+/// field of the response header.
+fn exchange(
+    stream: &mut SslStream<TcpStream>,
+    url: &Url,
+) -> Result<(u8, String), Box<dyn std::error::Error>> {
+    let request = url.as_str();
+    if request.len() > MAX_URL {
+        return Err(format!("{} is longer than Gemini allows", url).into());
+    }
+    stream.write_all(format!("{}\r\n", request).as_bytes())?;
+    stream.flush()?;
+
+    let mut header = Vec::new();
+    let mut byte = [0];
+    while !header.ends_with(b"\r\n") {
+        if stream.read(&mut byte)? == 0 || header.len() > MAX_HEADER + 1 {
+            return Err(format!("invalid Gemini response from {}", url).into());
+        }
+        header.push(byte[0]);
+    }
+    header.truncate(header.len() - 2);
+    parse_header(&header).ok_or_else(|| format!("invalid Gemini response from {}", url).into())
+}
+
+/// Splits a response header into its status and meta field.
+fn parse_header(header: &[u8]) -> Option<(u8, String)> {
+    let header = std::str::from_utf8(header).ok()?;
+    let (status, meta) = header.split_once(' ').unwrap_or((header, ""));
+    if status.len() != 2 || !status.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((status.parse().ok()?, meta.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Integer;
+    use openssl::bn::BigNum;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::ssl::{SslAcceptor, SslFiletype};
+    use openssl::x509::{X509NameBuilder, X509};
+    use std::io::BufRead;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Writes a self-signed certificate for localhost and its key to `dir`.
+    fn certificate(dir: &std::path::Path, name: &str) -> (PathBuf, PathBuf) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", "localhost").unwrap();
+        let subject = subject.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = BigNum::from_u32(1).unwrap();
+        builder
+            .set_serial_number(&Asn1Integer::from_bn(&serial).unwrap())
+            .unwrap();
+        builder.set_subject_name(&subject).unwrap();
+        builder.set_issuer_name(&subject).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(30).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        let (cert_path, key_path) = (
+            dir.join(format!("{}.pem", name)),
+            dir.join(format!("{}.key", name)),
+        );
+        fs::write(&cert_path, builder.build().to_pem().unwrap()).unwrap();
+        fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        (cert_path, key_path)
+    }
+
+    /// Serves one response per connection from `responses`, keyed by the
+    /// path of the request, returning the port.
+    fn serve(cert: &(PathBuf, PathBuf), responses: Vec<(&'static str, Vec<u8>)>) -> u16 {
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_certificate_chain_file(&cert.0).unwrap();
+        acceptor
+            .set_private_key_file(&cert.1, SslFiletype::PEM)
+            .unwrap();
+        let acceptor = acceptor.build();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let responses: HashMap<_, _> = responses.into_iter().collect();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = acceptor.accept(stream.unwrap()) else {
+                    continue;
+                };
+                let mut line = String::new();
+                io::BufReader::new(&mut stream)
+                    .read_line(&mut line)
+                    .unwrap();
+                let url = Url::parse(line.trim_end()).unwrap();
+                let response = responses.get(url.path()).cloned().unwrap_or_default();
+                stream.write_all(&response).unwrap();
+                let _ = stream.shutdown();
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            parse_header(b"20 text/gemini"),
+            Some((20, "text/gemini".to_string()))
+        );
+        assert_eq!(parse_header(b"51"), Some((51, String::new())));
+        assert_eq!(parse_header(b"2 text/plain"), None);
+        assert!(is_gemini("GEMINI://example.org/") && !is_gemini("https://example.org/"));
+    }
+
+    #[test]
+    fn test_get_follows_redirects_and_trusts_on_first_use() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = certificate(dir.path(), "first");
+        let port = serve(
+            &first,
+            vec![
+                ("/old", b"31 /new.gmi\r\n".to_vec()),
+                ("/new.gmi", b"20 text/gemini\r\n# Hello\n".to_vec()),
+                ("/missing", b"51 Not found\r\n".to_vec()),
+            ],
+        );
+        let known_hosts = KnownHosts::open(dir.path().join("known_hosts"));
+        let timeout = Duration::from_secs(5);
+        let mut response = get(
+            &format!("gemini://localhost:{}/old", port),
+            &known_hosts,
+            timeout,
+        )
+        .unwrap();
+        let mut body = String::new();
+        response.body.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "# Hello\n");
+        assert_eq!(response.mime, "text/gemini");
+        assert_eq!(response.url.path(), "/new.gmi");
+        let recorded = fs::read_to_string(dir.path().join("known_hosts")).unwrap();
+        assert!(recorded.starts_with(&format!("localhost:{} ", port)));
+
+        let err = get(
+            &format!("gemini://localhost:{}/missing", port),
+            &known_hosts,
+            timeout,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("51 Not found"));
+
+        // Another certificate on the same host and port is refused.
+        let second = certificate(dir.path(), "second");
+        let port = serve(&second, vec![("/", b"20 text/plain\r\n".to_vec())]);
+        let known = format!(
+            "localhost:{} {}",
+            port,
+            &recorded[recorded.find(' ').unwrap() + 1..]
+        );
+        fs::write(dir.path().join("known_hosts"), known).unwrap();
+        let known_hosts = KnownHosts::open(dir.path().join("known_hosts"));
+        let err = get(
+            &format!("gemini://localhost:{}/", port),
+            &known_hosts,
+            timeout,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("trusted on first use"));
+    }
+}
@@ -0,0 +1,302 @@
+//! curl-style URL globbing.
+//!
+//! A URL given on the command line may contain patterns that expand into
+//! several URLs:
+//!
+//! * `[001-100]` and `[a-z]` are numeric and alphabetic ranges; an optional
+//!   step follows a colon (`[0-100:10]`), and leading zeros in the start of a
+//!   numeric range set the width of every value.
+//! * `{one,two,three}` is an alternation.
+//!
+//! The value each pattern took is available to output names as `#1`, `#2`,
+//! and so on, counted from the left. A `[` that does not hold a valid range,
+//! such as the brackets of an IPv6 host, is kept as is.
+
+use std::path::PathBuf;
+
+/// Upper bound on the number of URLs a single pattern may expand to.
+const MAX_EXPANSIONS: usize = 100_000;
+
+/// One URL produced by expanding a pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    pub url: String,
+    /// The value taken by each pattern, in order of appearance.
+    pub values: Vec<String>,
+}
+
+impl Expansion {
+    /// Substitutes `#1`, `#2`, ... in `template` with the values of this expansion.
+    ///
+    /// References to patterns that do not exist are left untouched.
+    pub fn output(&self, template: &str) -> String {
+        let mut output = String::new();
+        let mut rest = template;
+        while let Some(index) = rest.find('#') {
+            output.push_str(&rest[..index]);
+            rest = &rest[index + 1..];
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            match rest[..digits]
+                .parse::<usize>()
+                .ok()
+                .and_then(|number| self.values.get(number.checked_sub(1)?))
+            {
+                Some(value) => output.push_str(value),
+                None => {
+                    output.push('#');
+                    output.push_str(&rest[..digits]);
+                }
+            }
+            rest = &rest[digits..];
+        }
+        output.push_str(rest);
+        output
+    }
+}
+
+/// A piece of a parsed pattern.
+#[derive(Debug)]
+enum Part {
+    Literal(String),
+    Set(Vec<String>),
+}
+
+/// Whether `url` contains anything that [`expand`] would expand.
+pub fn is_pattern(url: &str) -> bool {
+    parse(url).is_ok_and(|parts| parts.iter().any(|part| matches!(part, Part::Set(_))))
+}
+
+/// Expands the URL pattern `url` as [`expand`] does, pairing each URL with
+/// the output name `template` gives it when `url` holds patterns.
+///
+/// # Errors
+///
+/// Returns an error if the pattern cannot be expanded.
+pub fn expand_glob(
+    url: &str,
+    template: Option<&str>,
+) -> Result<Vec<(String, Option<PathBuf>)>, String> {
+    let template = template.filter(|_| is_pattern(url));
+    Ok(expand(url)?
+        .into_iter()
+        .map(|expansion| {
+            let output = template.map(|template| PathBuf::from(expansion.output(template)));
+            (expansion.url, output)
+        })
+        .collect())
+}
+
+/// Expands a URL pattern into every URL it describes.
+///
+/// A URL without patterns expands to itself. Expansions are ordered with the
+/// rightmost pattern varying fastest.
+///
+/// # Errors
+///
+/// Returns an error for an unterminated `{`, an empty alternation, a numeric
+/// range that ends before it starts or whose step is zero, or a pattern that expands to more than 100 000 URLs.
+pub fn expand(pattern: &str) -> Result<Vec<Expansion>, String> {
+    let parts = parse(pattern)?;
+    let count = parts.iter().try_fold(1usize, |count, part| match part {
+        Part::Set(values) => count
+            .checked_mul(values.len())
+            .filter(|count| *count <= MAX_EXPANSIONS),
+        Part::Literal(_) => Some(count),
+    });
+    if count.is_none() {
+        return Err(format!(
+            "'{}' expands to more than {} URLs",
+            pattern, MAX_EXPANSIONS
+        ));
+    }
+
+    let mut expansions = vec![Expansion {
+        url: String::new(),
+        values: Vec::new(),
+    }];
+    for part in &parts {
+        expansions = match part {
+            Part::Literal(text) => {
+                for expansion in &mut expansions {
+                    expansion.url.push_str(text);
+                }
+                expansions
+            }
+            Part::Set(values) => expansions
+                .iter()
+                .flat_map(|expansion| {
+                    values.iter().map(move |value| {
+                        let mut next = expansion.clone();
+                        next.url.push_str(value);
+                        next.values.push(value.clone());
+                        next
+                    })
+                })
+                .collect(),
+        };
+    }
+    Ok(expansions)
+}
+
+/// Splits a pattern into literal text and sets of alternatives.
+fn parse(pattern: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = pattern;
+    while let Some(index) = rest.find(['[', '{']) {
+        literal.push_str(&rest[..index]);
+        let (open, close) = if rest[index..].starts_with('[') {
+            ('[', ']')
+        } else {
+            ('{', '}')
+        };
+        let body = &rest[index + 1..];
+        let Some(end) = body.find(close) else {
+            if open == '{' {
+                return Err(format!("unterminated '{{' in '{}'", pattern));
+            }
+            literal.push_str(&rest[index..]);
+            rest = "";
+            break;
+        };
+        let inner = &body[..end];
+        let set = if open == '{' {
+            let values: Vec<String> = inner.split(',').map(str::to_string).collect();
+            if values.iter().all(String::is_empty) {
+                return Err(format!("empty alternation '{{}}' in '{}'", pattern));
+            }
+            Some(values)
+        } else {
+            range(inner)?
+        };
+        match set {
+            Some(values) => {
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Set(values));
+            }
+            None => {
+                literal.push(open);
+                literal.push_str(inner);
+                literal.push(close);
+            }
+        }
+        rest = &body[end + 1..];
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    Ok(parts)
+}
+
+/// Expands the inside of a `[...]` range, or returns `None` if it is not one.
+fn range(inner: &str) -> Result<Option<Vec<String>>, String> {
+    let (bounds, step) = match inner.split_once(':') {
+        Some((bounds, step)) => match step.parse::<u64>() {
+            Ok(0) => return Err(format!("invalid step 0 in range '[{}]'", inner)),
+            Ok(step) => (bounds, step),
+            Err(_) => return Ok(None),
+        },
+        None => (inner, 1),
+    };
+    let Some((start, end)) = bounds.split_once('-') else {
+        return Ok(None);
+    };
+
+    if let (Ok(first), Ok(last)) = (start.parse::<u64>(), end.parse::<u64>()) {
+        let width = if start.starts_with('0') {
+            start.len()
+        } else {
+            0
+        };
+        if first > last {
+            return Err(format!("empty range '[{}]'", inner));
+        }
+        return Ok(Some(
+            (first..=last)
+                .step_by(step as usize)
+                .map(|n| format!("{:0width$}", n, width = width))
+                .collect(),
+        ));
+    }
+
+    let letter = |text: &str| {
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() => Some(c),
+            _ => None,
+        }
+    };
+    match (letter(start), letter(end)) {
+        (Some(first), Some(last)) if first.is_ascii_lowercase() == last.is_ascii_lowercase() => {
+            Ok(Some(
+                (first..=last)
+                    .step_by(step as usize)
+                    .map(String::from)
+                    .collect(),
+            ))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(pattern: &str) -> Vec<String> {
+        expand(pattern)
+            .unwrap()
+            .into_iter()
+            .map(|expansion| expansion.url)
+            .collect()
+    }
+
+    #[test]
+    fn test_expand_ranges_and_alternations() {
+        assert_eq!(
+            urls("https://host/img[008-010].png"),
+            vec![
+                "https://host/img008.png",
+                "https://host/img009.png",
+                "https://host/img010.png",
+            ]
+        );
+        assert_eq!(
+            urls("https://host/[a-e:2]"),
+            vec!["https://host/a", "https://host/c", "https://host/e",]
+        );
+        assert_eq!(
+            urls("https://{www,cdn}.host/v[1-2]"),
+            vec![
+                "https://www.host/v1",
+                "https://www.host/v2",
+                "https://cdn.host/v1",
+                "https://cdn.host/v2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_non_patterns_are_kept() {
+        assert_eq!(
+            urls("http://[::1]:8080/file"),
+            vec!["http://[::1]:8080/file"]
+        );
+        assert!(!is_pattern("http://[::1]:8080/file"));
+        assert!(is_pattern("https://host/{a,b}"));
+        assert!(expand("https://host/{a,b").is_err());
+        assert!(expand("https://host/[1-9:0]").is_err());
+        assert!(expand("https://host/[9-1]").is_err());
+        assert!(expand("https://host/[0-999999][0-999999]").is_err());
+    }
+
+    #[test]
+    fn test_output_names() {
+        let expansions = expand("https://host/{jan,feb}/img[1-2].png").unwrap();
+        assert_eq!(expansions[3].output("#1_#2.png"), "feb_2.png");
+        assert_eq!(expansions[0].output("#3-#x.png"), "#3-#x.png");
+    }
+}
@@ -0,0 +1,378 @@
+//! Grouped `--help` output and the `examples` subcommand.
+//!
+//! clap 2 lists every option of the main command in one alphabetical block.
+//! [`sections`] splits the block it renders into the [`SECTIONS`] below, so
+//! that `--help` reads like a manual page, and adds an `OTHER OPTIONS`
+//! section for any option not assigned to one. `rustwget examples` prints
+//! [`EXAMPLES`], invocations for common tasks that can be pasted as they are.
+
+use clap::{App, SubCommand};
+
+/// The sections of `--help`, each with the long names of its options.
+pub const SECTIONS: [(&str, &[&str]); 10] = [
+    (
+        "INPUT",
+        &[
+            "input-file",
+            "template",
+            "vars",
+            "vars-product",
+            "default-scheme",
+            "strip-query-params",
+            "canonicalize",
+            "webseed",
+            "priority",
+            "record",
+        ],
+    ),
+    (
+        "OUTPUT",
+        &[
+            "output",
+            "tmp-dir",
+            "split-output",
+            "auto-extension",
+            "content-disposition",
+            "trust-server-names",
+            "restrict-file-names",
+            "decompress",
+            "force-overwrite",
+            "skip-existing",
+            "skip-existing-ledger",
+            "ledger",
+            "if-changed",
+            "no-use-server-timestamps",
+            "chmod",
+            "chown",
+            "umask",
+            "file-allocation",
+            "o-direct",
+            "sync",
+            "buffer-size",
+            "dedupe-content",
+            "convert-links",
+            "render",
+            "browser",
+            "write-manifest",
+        ],
+    ),
+    (
+        "RECURSION",
+        &["recursive", "level", "accept", "include", "exclude"],
+    ),
+    (
+        "HTTP",
+        &[
+            "header-from-cmd",
+            "range",
+            "max-redirect",
+            "accept-mime",
+            "reject-mime",
+            "require-content-type",
+            "reject-content-type",
+            "strict",
+            "lfs",
+            "min-size",
+            "max-size",
+            "confirm-over",
+        ],
+    ),
+    (
+        "AUTHENTICATION",
+        &[
+            "user",
+            "password",
+            "password-command",
+            "auth-type",
+            "bearer",
+            "token-file",
+            "aws-sigv4",
+            "no-keyring",
+            "service-account-dir",
+            "no-service-account",
+        ],
+    ),
+    (
+        "NETWORK",
+        &[
+            "proxy",
+            "proxy-user",
+            "proxy-password",
+            "proxy-auth-type",
+            "ca-certificate",
+            "dns-servers",
+            "dns-timeout",
+            "dns-cache-timeout",
+            "stall-timeout",
+            "limit-rate",
+            "speed-limit",
+            "speed-time",
+            "jobs",
+            "max-per-host",
+            "tries",
+            "mirror-list",
+            "probe-mirrors",
+            "stripe",
+            "wayback",
+        ],
+    ),
+    (
+        "VERIFICATION",
+        &[
+            "checksum",
+            "lock",
+            "lock-file",
+            "print-hash",
+            "hash-file",
+            "verify-partial",
+            "scan-cmd",
+        ],
+    ),
+    (
+        "RUNNING",
+        &[
+            "start-at",
+            "dry-run",
+            "notify",
+            "notify-url",
+            "benchmark",
+            "config",
+        ],
+    ),
+    (
+        "LOGGING",
+        &[
+            "output-file",
+            "append-output",
+            "log-max-size",
+            "verbose",
+            "color",
+            "si",
+            "bytes",
+            "no-redact",
+            "tui",
+            "report",
+        ],
+    ),
+    ("GENERAL", &["help", "version"]),
+];
+
+/// Invocations for common tasks, each under a comment saying what it does.
+pub const EXAMPLES: &str = "\
+# Download a file into the current directory
+rustwget https://example.com/file.txt
+
+# Save under another name, or write to standard output
+rustwget -O latest.tar.gz https://example.com/releases/v2.1.tar.gz
+rustwget -O - https://example.com/data.json | jq .
+
+# Download numbered files, naming each after the values of the patterns
+rustwget -O 'photo_#1_#2.jpg' 'https://example.com/{2023,2024}/img[001-100].jpg'
+
+# Download the URLs listed in a file, four at a time and at most two per host
+rustwget -i urls.txt -j 4 --max-per-host 2
+
+# Mirror a directory listing two levels deep, keeping only PDFs
+rustwget -r -l 2 -A .pdf https://example.com/papers/
+
+# Save a single-page app as its scripts render it, with its assets, for browsing offline
+rustwget --render -k -O app/index.html https://example.com/app/
+
+# Download the weights and configuration of a Hugging Face model
+rustwget --include '*.safetensors' --include '*.json' hf://Qwen/Qwen2.5-0.5B
+
+# Download every file of a Zenodo, Figshare, or OSF dataset by its DOI
+rustwget -O rainfall doi:10.5281/zenodo.1234567
+
+# Download the PDFs of papers by their arXiv, PubMed Central, or PubMed IDs
+rustwget -i reading-list.txt    # lines such as arxiv:2401.12345 or pubmed:23193287
+
+# Download a page as the Wayback Machine archived it at the start of 2015
+rustwget --wayback=20150101 https://example.com/old-page.html
+
+# Download a page of a Gemini capsule
+rustwget gemini://geminiprotocol.net/docs/faq.gmi
+
+# Download a public dataset from the web seeds of its torrent, verifying every piece
+rustwget --webseed https://example.com/datasets/census-2020.torrent
+
+# Download a file from a Windows share, with the password kept in the keyring
+rustwget auth add fileserver --user 'CORP\\alice'
+rustwget smb://fileserver/builds/nightly/setup.exe
+
+# Inside a pod, download from an in-cluster service with the pod's service account
+rustwget https://artifacts.ci.svc.cluster.local/builds/latest.tar.gz
+
+# Trust a private certificate authority
+rustwget --ca-certificate /etc/ssl/corp-root.pem https://intranet.corp/report.pdf
+
+# Verify a download against its published digest
+rustwget --checksum sha256:5f70bf18a086007016e948b04aed3b82103a36bea41755b6cddfaf10ace3c6ef https://example.com/tool.tar.gz
+
+# Pin the downloads of a build in rustwget.lock, and fail when one changes
+rustwget --lock -i third-party.txt
+
+# Download through a proxy, at most 500 KiB/s
+rustwget --proxy http://proxy.corp:3128 --limit-rate 500k https://example.com/dataset.zip
+
+# Share one download cache between the jobs of a build farm
+rustwget proxy --listen 0.0.0.0:3128 --allow '*.example.com:80' --allow '*.example.com:443' --cache-size 50G
+http_proxy=http://cache.ci:3128 rustwget http://example.com/tool.tar.gz
+
+# Fill the proxy's cache with the files of a manifest, e.g. before going offline
+rustwget prefetch toolchains.txt
+
+# Carry downloads across an air gap in one file
+rustwget bundle create docs.bundle -i urls.txt
+rustwget bundle extract docs.bundle -C /srv/mirror
+
+# Re-download only what changed since the last run
+rustwget -i urls.txt --skip-existing-ledger --if-changed
+
+# Retry the downloads that failed in the last run
+rustwget retry-failed
+
+# Find the broken links of a site
+rustwget check --level 3 https://example.com/
+
+# Show what a server says about a URL without downloading it
+rustwget info https://example.com/file.bin
+";
+
+/// Defines the `examples` subcommand.
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("examples").about("Print example invocations for common tasks")
+}
+
+/// Splits the `OPTIONS` block of the main command's `help`, as rendered by
+/// clap, into [`SECTIONS`].
+///
+/// The help of a subcommand, which starts with `rustwget-`, is returned as
+/// it is.
+pub fn sections(help: &str) -> String {
+    if !help.starts_with("rustwget ") {
+        return help.to_string();
+    }
+    let lines: Vec<&str> = help.lines().collect();
+    let Some(start) = lines.iter().position(|line| *line == "OPTIONS:") else {
+        return help.to_string();
+    };
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| !line.is_empty() && !line.starts_with(' '))
+        .map_or(lines.len(), |end| start + 1 + end);
+
+    // Each option starts a line indented by 4 (`-o, --output`) or 8
+    // (`--output`) spaces; its description and blank lines follow.
+    let mut options: Vec<(&str, Vec<&str>)> = Vec::new();
+    for line in &lines[start + 1..end] {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        match (option_name(trimmed), options.last_mut()) {
+            (Some(name), _) if indent == 4 || indent == 8 => options.push((name, vec![line])),
+            (_, Some((_, entry))) => entry.push(line),
+            (_, None) => {}
+        }
+    }
+
+    let mut grouped = String::new();
+    let mut other = Vec::new();
+    for (name, entry) in &options {
+        if !SECTIONS.iter().any(|(_, names)| names.contains(name)) {
+            other.push(entry);
+        }
+    }
+    let named = SECTIONS.iter().map(|(heading, names)| {
+        let entries: Vec<_> = options
+            .iter()
+            .filter(|(name, _)| names.contains(name))
+            .map(|(_, entry)| entry)
+            .collect();
+        (*heading, entries)
+    });
+    for (heading, entries) in named.chain(std::iter::once(("OTHER", other))) {
+        if entries.is_empty() {
+            continue;
+        }
+        grouped += &format!("{} OPTIONS:\n", heading);
+        for entry in entries {
+            let last = entry
+                .iter()
+                .rposition(|line| !line.is_empty())
+                .map_or(0, |last| last + 1);
+            for line in &entry[..last] {
+                grouped += line;
+                grouped.push('\n');
+            }
+            // Descriptions on their own lines are set apart by a blank line.
+            if entry.len() > 1 && entry[1].trim_start().len() + 12 == entry[1].len() {
+                grouped.push('\n');
+            }
+        }
+        if !grouped.ends_with("\n\n") {
+            grouped.push('\n');
+        }
+    }
+
+    let mut text = lines[..start].join("\n");
+    text.push('\n');
+    text += &grouped;
+    for line in &lines[end..] {
+        text += line;
+        text.push('\n');
+    }
+    text
+}
+
+/// Returns the long name of the option whose help starts with `line`,
+/// trimmed, such as `output` for `-O, --output <FILE>    Specify ...`.
+fn option_name(line: &str) -> Option<&str> {
+    let line = match line.strip_prefix('-')?.split_once(", ") {
+        Some((short, rest)) if short.len() == 1 => rest,
+        _ => line,
+    };
+    let name = line.strip_prefix("--")?;
+    Some(&name[..name.find([' ', '=']).unwrap_or(name.len())])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sections() {
+        let help = "rustwget 1.0\n\nOPTIONS:\n\
+                    \x20   -h, --help        Prints help information\n\
+                    \x20       --level <N>   Recurse N levels\n\
+                    \x20       --tmp-dir <DIR>\n\
+                    \x20           Write downloads in DIR\n\
+                    \n\
+                    \x20       --unknown     Not in a section\n\
+                    \nARGS:\n    <URL>...    The URL(s) to download\n";
+        assert_eq!(
+            sections(help),
+            "rustwget 1.0\n\n\
+             OUTPUT OPTIONS:\n\
+             \x20       --tmp-dir <DIR>\n\
+             \x20           Write downloads in DIR\n\
+             \n\
+             RECURSION OPTIONS:\n\
+             \x20       --level <N>   Recurse N levels\n\
+             \n\
+             GENERAL OPTIONS:\n\
+             \x20   -h, --help        Prints help information\n\
+             \n\
+             OTHER OPTIONS:\n\
+             \x20       --unknown     Not in a section\n\
+             \n\
+             ARGS:\n    <URL>...    The URL(s) to download\n"
+        );
+        assert_eq!(
+            sections("rustwget-check\n\nOPTIONS:\n"),
+            "rustwget-check\n\nOPTIONS:\n"
+        );
+        assert_eq!(option_name("-O, --output <FILE>    Save"), Some("output"));
+        assert_eq!(option_name("--tui    Show"), Some("tui"));
+        assert_eq!(option_name("<URL>..."), None);
+    }
+}
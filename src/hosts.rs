@@ -0,0 +1,266 @@
+//! Settings that apply to the URLs of one host.
+//!
+//! Each `[host."NAME"]` section of the configuration file (see
+//! [`config`](crate::config)) is keyed like the keyring, by the host name and
+//! any explicit port, e.g. `artifacts.corp.com` or `localhost:8443`. Requests
+//! to that host then carry its `headers`; its `user` (with the password in the
+//! `password_env` variable) or `token_file` authenticates them unless
+//! credentials were given on the command line; its transfers share its `rate`
+//! on top of `--limit-rate`; and with `ca_certificate` or `insecure` they go
+//! through a client of their own that trusts that certificate authority or
+//! any certificate at all. Hosts whose operators ask for it, such as arXiv
+//! (see [`papers`](crate::papers)), are also sent requests at a [`Pace`].
+
+use crate::auth::{self, Credentials};
+use crate::config::HostConfig;
+use crate::throttle::Throttle;
+use reqwest::blocking::{Client, ClientBuilder, RequestBuilder};
+use reqwest::header::HeaderMap;
+use reqwest::Certificate;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// The prepared settings of one host.
+#[derive(Debug, Default)]
+pub struct Host {
+    pub headers: HeaderMap,
+    pub credentials: Option<Credentials>,
+    pub throttle: Option<Throttle>,
+    /// Client for the host's TLS settings, if it has any.
+    pub client: Option<Client>,
+    /// The least time between requests to the host, if it asks for one.
+    pub pace: Option<Pace>,
+}
+
+/// A minimum interval between the requests to a host, shared by all the
+/// transfers of a run.
+#[derive(Debug)]
+pub struct Pace {
+    interval: Duration,
+    /// When the next request may be sent.
+    next: Mutex<Instant>,
+}
+
+impl Pace {
+    pub fn new(interval: Duration) -> Pace {
+        Pace {
+            interval,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits until a request may be sent, taking its turn.
+    pub fn wait(&self) {
+        let turn = {
+            let mut next = self.next.lock().unwrap();
+            let turn = (*next).max(Instant::now());
+            *next = turn + self.interval;
+            turn
+        };
+        thread::sleep(turn.saturating_duration_since(Instant::now()));
+    }
+}
+
+impl Host {
+    /// Prepares the settings of `name`.
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: The host, as written in the configuration.
+    /// * `config`: Its section of the configuration.
+    /// * `builder`: Makes a client builder with the settings of the run, to
+    ///   which the host's TLS settings are added.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the password variable is not set, or a token or
+    /// certificate file cannot be read.
+    fn new(
+        name: &str,
+        config: &HostConfig,
+        builder: &dyn Fn() -> Result<ClientBuilder, Box<dyn Error>>,
+    ) -> Result<Host, Box<dyn Error>> {
+        let credentials = match (&config.user, &config.password_env, &config.token_file) {
+            (_, _, Some(path)) => auth::bearer(None, Some(path))?,
+            (Some(user), Some(variable), None) => {
+                let password = env::var(variable).map_err(|_| {
+                    format!(
+                        "host {}: set {} to the password of {}",
+                        name, variable, user
+                    )
+                })?;
+                Some(Credentials::password(
+                    config.auth_type.as_deref().unwrap_or("basic"),
+                    user.clone(),
+                    password,
+                )?)
+            }
+            _ => None,
+        };
+        let client = if config.ca_certificate.is_some() || config.insecure {
+            let mut client = builder()?.danger_accept_invalid_certs(config.insecure);
+            if let Some(path) = &config.ca_certificate {
+                let pem = fs::read(path).map_err(|err| {
+                    format!("cannot read certificate {}: {}", path.display(), err)
+                })?;
+                client = client.add_root_certificate(Certificate::from_pem(&pem)?);
+            }
+            Some(client.build()?)
+        } else {
+            None
+        };
+        Ok(Host {
+            headers: config.headers()?,
+            credentials,
+            throttle: Throttle::new(config.rate()?, Vec::new()),
+            client,
+            pace: None,
+        })
+    }
+
+    /// Adds the host's headers to `request`.
+    pub fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+        request.headers(self.headers.clone())
+    }
+}
+
+/// The settings of every configured host.
+#[derive(Debug, Default)]
+pub struct Hosts {
+    hosts: HashMap<String, Host>,
+}
+
+impl Hosts {
+    /// Prepares the settings of every host in `config`.
+    ///
+    /// `builder` makes a client builder with the settings of the run, for the
+    /// hosts that need a client of their own.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the settings of a host cannot be prepared.
+    pub fn new(
+        config: &BTreeMap<String, HostConfig>,
+        builder: impl Fn() -> Result<ClientBuilder, Box<dyn Error>>,
+    ) -> Result<Hosts, Box<dyn Error>> {
+        let hosts = config
+            .iter()
+            .map(|(name, config)| {
+                Ok((
+                    name.to_ascii_lowercase(),
+                    Host::new(name, config, &builder)?,
+                ))
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+        Ok(Hosts { hosts })
+    }
+
+    /// Authenticates requests to the host of `url` with `credentials`,
+    /// unless the configuration gives it credentials of its own.
+    pub fn authorize(&mut self, url: &Url, credentials: Credentials) {
+        if let Some(key) = auth::host_key(url) {
+            let host = self.hosts.entry(key).or_default();
+            host.credentials.get_or_insert(credentials);
+        }
+    }
+
+    /// Authenticates requests to every configured host with `credentials`,
+    /// unless the configuration gives it credentials of its own. Other hosts
+    /// are never sent them.
+    pub fn authorize_configured(&mut self, credentials: &Credentials) {
+        for host in self.hosts.values_mut() {
+            host.credentials.get_or_insert_with(|| credentials.clone());
+        }
+    }
+
+    /// Sends requests to the host of `url` at most once every `interval`.
+    pub fn pace(&mut self, url: &Url, interval: Duration) {
+        if let Some(key) = auth::host_key(url) {
+            let host = self.hosts.entry(key).or_default();
+            host.pace.get_or_insert_with(|| Pace::new(interval));
+        }
+    }
+
+    /// Returns the settings that apply to `url`, if its host has any.
+    pub fn get(&self, url: &str) -> Option<&Host> {
+        if self.hosts.is_empty() {
+            return None;
+        }
+        let key = auth::host_key(&Url::parse(url).ok()?)?;
+        self.hosts.get(&key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    #[test]
+    fn test_hosts_match_name_and_port() {
+        let config = config::parse(
+            r#"
+            [host."Artifacts.example"]
+            headers = { "X-Team" = "build" }
+            user = "deploy"
+            password_env = "RUSTWGET_TEST_HOST_PASSWORD"
+            rate = "1M"
+
+            [host."localhost:8443"]
+            insecure = true
+            "#,
+        )
+        .unwrap();
+        let builder = || -> Result<ClientBuilder, Box<dyn Error>> { Ok(Client::builder()) };
+
+        let err = Hosts::new(&config.host, builder).unwrap_err();
+        assert!(err.to_string().contains("set RUSTWGET_TEST_HOST_PASSWORD"));
+
+        env::set_var("RUSTWGET_TEST_HOST_PASSWORD", "secret");
+        let hosts = Hosts::new(&config.host, builder).unwrap();
+        let artifacts = hosts.get("https://artifacts.example/a.zip").unwrap();
+        assert_eq!(artifacts.headers["x-team"], "build");
+        assert!(matches!(
+            &artifacts.credentials,
+            Some(Credentials::Basic { user, password }) if user == "deploy" && password == "secret"
+        ));
+        assert!(artifacts.throttle.is_some() && artifacts.client.is_none());
+
+        assert!(hosts
+            .get("https://localhost:8443/")
+            .unwrap()
+            .client
+            .is_some());
+        assert!(hosts.get("https://localhost/").is_none());
+        assert!(hosts.get("https://artifacts.example:8080/").is_none());
+
+        let mut hosts = hosts;
+        hosts.authorize_configured(&Credentials::Bearer("token".to_string()));
+        assert!(matches!(
+            &hosts.get("https://artifacts.example/").unwrap().credentials,
+            Some(Credentials::Basic { .. })
+        ));
+        assert!(matches!(
+            &hosts.get("https://localhost:8443/").unwrap().credentials,
+            Some(Credentials::Bearer(token)) if token == "token"
+        ));
+        assert!(hosts.get("https://elsewhere.example/").is_none());
+    }
+
+    #[test]
+    fn test_pace_spaces_requests() {
+        let pace = Pace::new(Duration::from_millis(50));
+        let start = Instant::now();
+        pace.wait();
+        assert!(start.elapsed() < Duration::from_millis(50));
+        pace.wait();
+        pace.wait();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,459 @@
+//! Files and snapshots of Hugging Face Hub repositories (`hf://` URLs).
+//!
+//! `hf://ORG/REPO/PATH@REVISION` names a file of a model repository, with
+//! `hf://datasets/ORG/REPO/...` and `hf://spaces/ORG/REPO/...` for datasets
+//! and spaces; the revision is a branch, tag, or commit and defaults to
+//! `main`:
+//!
+//! ```text
+//! rustwget hf://openai-community/gpt2/config.json
+//! rustwget hf://datasets/stanfordnlp/imdb/plain_text/@refs/convert/parquet
+//! rustwget --include '*.safetensors' --include '*.json' hf://Qwen/Qwen2.5-0.5B@v1.0
+//! ```
+//!
+//! A file is downloaded from its `resolve` URL, which redirects files kept in
+//! Git LFS to the Hub's storage. A URL without a path, or whose path ends in
+//! `/`, is a snapshot: the files below that directory are listed through the
+//! Hub API and saved at their path below the directory given with `-O`, or
+//! below one named after the repository. `--include` keeps only the files
+//! whose path or name matches one of its `*` and `?` patterns, and `--exclude`
+//! drops those matching one of its own. Files of a snapshot kept in LFS are
+//! verified against the SHA-256 digest the Hub lists for them.
+//!
+//! `HF_TOKEN` (or `HUGGING_FACE_HUB_TOKEN`) is sent as a bearer token to the
+//! Hub for private and gated repositories, and `HF_ENDPOINT` names a Hub
+//! other than `https://huggingface.co`, such as a mirror.
+
+use crate::crawl;
+use crate::digest::Algorithm;
+use crate::download::{Checksums, Options};
+use crate::input::Entry;
+use crate::logfile;
+use reqwest::header::LINK;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// The scheme of Hub URLs.
+pub const SCHEME: &str = "hf://";
+
+/// The Hub used unless `HF_ENDPOINT` names another.
+const DEFAULT_ENDPOINT: &str = "https://huggingface.co";
+
+/// The kinds of repository on the Hub.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Model,
+    Dataset,
+    Space,
+}
+
+impl Kind {
+    /// Prefix of the repository in `resolve` URLs.
+    fn prefix(self) -> &'static str {
+        match self {
+            Kind::Model => "",
+            Kind::Dataset => "datasets/",
+            Kind::Space => "spaces/",
+        }
+    }
+
+    /// Name of the repositories in API URLs.
+    fn api(self) -> &'static str {
+        match self {
+            Kind::Model => "models",
+            Kind::Dataset => "datasets",
+            Kind::Space => "spaces",
+        }
+    }
+}
+
+/// A file or directory of a repository, from an `hf://` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub kind: Kind,
+    /// The repository, as `ORG/REPO`.
+    pub repo: String,
+    /// The path in the repository, empty for its root.
+    pub path: String,
+    pub revision: String,
+}
+
+impl Location {
+    /// Parses an `hf://` URL.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the URL does not name a repository.
+    pub fn parse(url: &str) -> Result<Location, String> {
+        let rest = url
+            .strip_prefix(SCHEME)
+            .ok_or_else(|| format!("'{}' is not an hf:// URL", url))?;
+        let (rest, revision) = match rest.rsplit_once('@') {
+            Some((rest, revision)) if !revision.is_empty() => (rest, revision),
+            _ => (rest, "main"),
+        };
+        let (kind, rest) = match rest.split_once('/') {
+            Some(("datasets", rest)) => (Kind::Dataset, rest),
+            Some(("spaces", rest)) => (Kind::Space, rest),
+            _ => (Kind::Model, rest),
+        };
+        let mut parts = rest.splitn(3, '/');
+        let (Some(org), Some(name)) = (parts.next(), parts.next()) else {
+            return Err(format!(
+                "invalid hf:// URL '{}': expected hf://ORG/REPO/PATH",
+                url
+            ));
+        };
+        if org.is_empty() || name.is_empty() {
+            return Err(format!(
+                "invalid hf:// URL '{}': expected hf://ORG/REPO/PATH",
+                url
+            ));
+        }
+        Ok(Location {
+            kind,
+            repo: format!("{}/{}", org, name),
+            path: parts.next().unwrap_or_default().to_string(),
+            revision: revision.to_string(),
+        })
+    }
+
+    /// Whether the location is a directory, whose files are downloaded as a
+    /// snapshot.
+    pub fn is_directory(&self) -> bool {
+        self.path.is_empty() || self.path.ends_with('/')
+    }
+
+    /// Returns the URL that serves the file at `path` of the repository.
+    pub fn resolve(&self, endpoint: &Url, path: &str) -> Url {
+        self.url(
+            endpoint,
+            &format!("{}{}", self.kind.prefix(), self.repo),
+            "resolve",
+            path,
+        )
+    }
+
+    /// Returns the API URL listing the files below the location.
+    fn tree(&self, endpoint: &Url) -> Url {
+        let mut url = self.url(
+            endpoint,
+            &format!("api/{}/{}", self.kind.api(), self.repo),
+            "tree",
+            self.path.trim_end_matches('/'),
+        );
+        url.query_pairs_mut().append_pair("recursive", "true");
+        url
+    }
+
+    fn url(&self, endpoint: &Url, repository: &str, action: &str, path: &str) -> Url {
+        let mut url = endpoint.clone();
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments
+                .pop_if_empty()
+                .extend(repository.split('/'))
+                .push(action)
+                // A revision such as `refs/pr/1` is a single segment.
+                .push(&self.revision)
+                .extend(path.split('/').filter(|segment| !segment.is_empty()));
+        }
+        url
+    }
+}
+
+/// One entry of a repository listing.
+#[derive(Debug, Deserialize)]
+struct Item {
+    #[serde(rename = "type")]
+    kind: String,
+    path: String,
+    lfs: Option<Lfs>,
+}
+
+/// What the Hub lists of a file kept in Git LFS.
+#[derive(Debug, Deserialize)]
+struct Lfs {
+    /// The SHA-256 digest of the file.
+    oid: String,
+}
+
+/// The `--include` and `--exclude` patterns of snapshots.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl Filter {
+    /// Whether the file at `path` of a repository is downloaded.
+    pub fn accepts(&self, path: &str) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let matches = |pattern: &String| {
+            crawl::wildcard(pattern, path)
+                || (!pattern.contains('/') && crawl::wildcard(pattern, name))
+        };
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Returns the Hub the URLs are resolved on.
+///
+/// # Errors
+///
+/// Returns an error if `HF_ENDPOINT` is not a URL.
+pub fn endpoint() -> Result<Url, String> {
+    let endpoint = env::var("HF_ENDPOINT")
+        .ok()
+        .filter(|endpoint| !endpoint.is_empty())
+        .unwrap_or_else(|| DEFAULT_ENDPOINT.to_string());
+    Url::parse(&endpoint).map_err(|err| format!("invalid HF_ENDPOINT '{}': {}", endpoint, err))
+}
+
+/// Returns the token sent to the Hub, if one is set.
+pub fn token() -> Option<String> {
+    ["HF_TOKEN", "HUGGING_FACE_HUB_TOKEN"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|token| !token.is_empty())
+}
+
+/// Replaces the `hf://` URLs among `entries` with the URLs of the files they
+/// name on the Hub at `endpoint`, listing the files of snapshots into
+/// `output` or a directory named after their repository.
+///
+/// Also returns the SHA-256 digests of the files kept in LFS, by URL.
+///
+/// # Errors
+///
+/// Returns an error if a URL is invalid or a snapshot cannot be listed.
+pub fn expand(
+    client: &reqwest::blocking::Client,
+    entries: Vec<Entry>,
+    filter: &Filter,
+    endpoint: &Url,
+    output: Option<&Path>,
+    options: &Options,
+) -> Result<(Vec<Entry>, Checksums), Box<dyn std::error::Error>> {
+    let mut expanded = Vec::new();
+    let mut digests = HashMap::new();
+    for entry in entries {
+        if !entry.url.starts_with(SCHEME) {
+            expanded.push(entry);
+            continue;
+        }
+        let location = Location::parse(&entry.url)?;
+        if !location.is_directory() {
+            expanded.push(Entry {
+                url: location.resolve(endpoint, &location.path).into(),
+                ..entry
+            });
+            continue;
+        }
+        let dir = match (entry.output, output) {
+            (Some(dir), _) => dir,
+            (None, Some(dir)) => dir.to_path_buf(),
+            (None, None) => PathBuf::from(location.repo.rsplit('/').next().unwrap_or_default()),
+        };
+        logfile::info(&format!("Listing: {}", entry.url));
+        let mut listed = 0;
+        for item in list(client, &location.tree(endpoint), options)? {
+            let relative = item.path.strip_prefix(&location.path).unwrap_or(&item.path);
+            if item.kind != "file" || !filter.accepts(&item.path) {
+                continue;
+            }
+            if relative
+                .split('/')
+                .any(|part| part.is_empty() || part == "." || part == "..")
+            {
+                logfile::warning(&format!(
+                    "Skipped: unsafe path '{}' in {}",
+                    item.path, location.repo
+                ));
+                continue;
+            }
+            let url = String::from(location.resolve(endpoint, &item.path));
+            if let Some(lfs) = item.lfs {
+                digests.insert(
+                    url.clone(),
+                    (Algorithm::Sha256, lfs.oid.to_ascii_lowercase()),
+                );
+            }
+            expanded.push(Entry {
+                url,
+                priority: entry.priority,
+                output: Some(dir.join(relative)),
+            });
+            listed += 1;
+        }
+        if listed == 0 {
+            logfile::warning(&format!("No files to download in {}", entry.url));
+        }
+    }
+    Ok((expanded, digests))
+}
+
+/// Lists a repository from `url` and the pages that follow it.
+fn list(
+    client: &reqwest::blocking::Client,
+    url: &Url,
+    options: &Options,
+) -> Result<Vec<Item>, Box<dyn std::error::Error>> {
+    let mut items = Vec::new();
+    let mut next = Some(url.to_string());
+    while let Some(url) = next.take() {
+        let response = options
+            .send(client, &url, |client| client.get(&url))?
+            .error_for_status()
+            .map_err(|err| format!("cannot list {}: {}", url, err))?;
+        next = response
+            .headers()
+            .get(LINK)
+            .and_then(|link| link.to_str().ok())
+            .and_then(next_page);
+        let page: Vec<Item> = serde_json::from_reader(response)
+            .map_err(|err| format!("invalid listing from {}: {}", url, err))?;
+        items.extend(page);
+    }
+    Ok(items)
+}
+
+/// Returns the `rel="next"` URL of a `Link` header.
+fn next_page(link: &str) -> Option<String> {
+    link.split(',').find_map(|part| {
+        let (target, parameters) = part.split_once(';')?;
+        parameters
+            .split(';')
+            .any(|parameter| parameter.trim().replace(' ', "") == "rel=\"next\"")
+            .then(|| {
+                target
+                    .trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string()
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+
+    #[test]
+    fn test_parse_location() {
+        let endpoint = Url::parse(DEFAULT_ENDPOINT).unwrap();
+        let file = Location::parse("hf://openai-community/gpt2/onnx/model.onnx@v1.0").unwrap();
+        assert_eq!(
+            file.resolve(&endpoint, &file.path).as_str(),
+            "https://huggingface.co/openai-community/gpt2/resolve/v1.0/onnx/model.onnx"
+        );
+        assert!(!file.is_directory());
+
+        let snapshot = Location::parse("hf://datasets/org/data/train/@refs/pr/1").unwrap();
+        assert_eq!(snapshot.kind, Kind::Dataset);
+        assert!(snapshot.is_directory());
+        assert_eq!(
+            snapshot.tree(&endpoint).as_str(),
+            "https://huggingface.co/api/datasets/org/data/tree/refs%2Fpr%2F1/train?recursive=true"
+        );
+        assert_eq!(Location::parse("hf://org/model").unwrap().revision, "main");
+        assert!(Location::parse("hf://gpt2").is_err());
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter {
+            include: vec!["*.safetensors".to_string(), "onnx/*".to_string()],
+            exclude: vec!["*fp16*".to_string()],
+        };
+        assert!(filter.accepts("model.safetensors"));
+        assert!(filter.accepts("sub/model.safetensors"));
+        assert!(filter.accepts("onnx/model.onnx"));
+        assert!(!filter.accepts("model-fp16.safetensors"));
+        assert!(!filter.accepts("config.json"));
+        assert!(Filter::default().accepts("config.json"));
+    }
+
+    #[test]
+    fn test_expand_snapshot() {
+        let next = format!(
+            "{}/api/models/org/model/tree/main?recursive=true&cursor=2",
+            server_url()
+        );
+        let first = mock("GET", "/api/models/org/model/tree/main")
+            .match_query(Matcher::Exact("recursive=true".to_string()))
+            .with_header("link", &format!("<{}>; rel=\"next\"", next))
+            .with_body(
+                r#"[{"type": "file", "path": "config.json", "oid": "1a2b", "size": 10},
+                    {"type": "directory", "path": "onnx", "oid": "3c4d", "size": 0}]"#,
+            )
+            .create();
+        let second = mock("GET", "/api/models/org/model/tree/main")
+            .match_query(Matcher::UrlEncoded("cursor".to_string(), "2".to_string()))
+            .with_body(
+                r#"[{"type": "file", "path": "onnx/model.onnx", "oid": "5e6f", "size": 99,
+                     "lfs": {"oid": "ABC123", "size": 99, "pointerSize": 130}},
+                    {"type": "file", "path": "README.md", "oid": "7a8b", "size": 5}]"#,
+            )
+            .create();
+
+        let entries = vec![
+            Entry {
+                url: "hf://org/model".to_string(),
+                priority: 1,
+                output: None,
+            },
+            Entry {
+                url: "https://example.com/a.txt".to_string(),
+                priority: 0,
+                output: None,
+            },
+        ];
+        let filter = Filter {
+            include: Vec::new(),
+            exclude: vec!["*.md".to_string()],
+        };
+        let endpoint = Url::parse(&server_url()).unwrap();
+        let client = reqwest::blocking::Client::new();
+        let (entries, digests) = expand(
+            &client,
+            entries,
+            &filter,
+            &endpoint,
+            None,
+            &Options::default(),
+        )
+        .unwrap();
+        let onnx = format!("{}/org/model/resolve/main/onnx/model.onnx", server_url());
+        assert_eq!(
+            entries,
+            vec![
+                Entry {
+                    url: format!("{}/org/model/resolve/main/config.json", server_url()),
+                    priority: 1,
+                    output: Some(PathBuf::from("model/config.json")),
+                },
+                Entry {
+                    url: onnx.clone(),
+                    priority: 1,
+                    output: Some(PathBuf::from("model/onnx/model.onnx")),
+                },
+                Entry {
+                    url: "https://example.com/a.txt".to_string(),
+                    priority: 0,
+                    output: None,
+                },
+            ]
+        );
+        assert_eq!(
+            digests,
+            HashMap::from([(onnx, (Algorithm::Sha256, "abc123".to_string()))])
+        );
+        first.assert();
+        second.assert();
+    }
+}
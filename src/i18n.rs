@@ -0,0 +1,175 @@
+//! Translations of user-facing messages.
+//!
+//! Messages are looked up by key in [`ENGLISH`], whose templates name their
+//! values with `{name}` placeholders, as URL templates do (see
+//! [`template`](crate::template)). The language comes from the first of
+//! `RUSTWGET_LANG`, `LC_ALL`, `LC_MESSAGES`, and `LANG` that is set, and its
+//! translations from `<lang>.toml` in `RUSTWGET_LOCALE_DIR`, or in the
+//! `locale` directory of the configuration directory (see
+//! [`paths::config_dir`]):
+//!
+//! ```text
+//! downloaded = "Téléchargé : {file}"
+//! "skipped.exists" = "Ignoré (le fichier existe) : {file}"
+//! ```
+//!
+//! `fr_FR.UTF-8` reads `fr_FR.toml`, then `fr.toml`. A message missing from
+//! the translation, or whose translation names a value the message does not
+//! have, is shown in English, so that a catalog written for an older release
+//! keeps working.
+
+use crate::logfile;
+use crate::paths;
+use crate::template::{self, Vars};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Every message, by key, in English.
+pub const ENGLISH: [(&str, &str); 11] = [
+    ("error", "Error: {error}"),
+    ("downloaded", "Downloaded: {file}"),
+    ("failed", "Failed: {url}: {error}"),
+    ("skipped.exists", "Skipped (file exists): {file}"),
+    (
+        "skipped.unchanged",
+        "Skipped (unchanged since last download): {file}",
+    ),
+    ("skipped.listed", "Skipped (listed before): {url}"),
+    (
+        "skipped.duplicate",
+        "Skipped {file}: same content as {original}",
+    ),
+    ("schedule.would-start", "Would start at {time}"),
+    ("schedule.waiting", "Waiting until {time} to start"),
+    (
+        "summary",
+        "Summary: {succeeded} succeeded, {failed} failed, {skipped} skipped, {cancelled} cancelled\n\
+         Downloaded {bytes} in {seconds}s ({rate})",
+    ),
+    ("summary.failed-urls", "Failed URLs:"),
+];
+
+/// The translations of the user's language, once read.
+static CATALOG: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Returns the message `key` in the user's language, with `values` for its
+/// placeholders.
+///
+/// # Panics
+///
+/// Panics if `key` is not in [`ENGLISH`] or `values` lacks a placeholder of
+/// its English template.
+pub fn text(key: &str, values: &[(&str, &dyn fmt::Display)]) -> String {
+    let catalog = CATALOG.get_or_init(|| {
+        let Some(locale) = locale() else {
+            return HashMap::new();
+        };
+        let dir = env::var_os("RUSTWGET_LOCALE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| paths::config_dir().join("locale"));
+        translations(&dir, &locale)
+    });
+    render(catalog, key, values)
+}
+
+/// Renders `key` from `catalog`, or from [`ENGLISH`] when it cannot be.
+fn render(
+    catalog: &HashMap<String, String>,
+    key: &str,
+    values: &[(&str, &dyn fmt::Display)],
+) -> String {
+    let vars: Vars = values
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    if let Some(text) = catalog
+        .get(key)
+        .and_then(|translated| template::render(translated, &vars).ok())
+    {
+        return text;
+    }
+    let english = ENGLISH
+        .iter()
+        .find(|(name, _)| *name == key)
+        .unwrap_or_else(|| panic!("no message '{}'", key))
+        .1;
+    template::render(english, &vars).unwrap_or_else(|err| panic!("message '{}': {}", key, err))
+}
+
+/// Returns the user's language, such as `fr_FR` for `LANG=fr_FR.UTF-8`, or
+/// `None` for English and the `C` locale.
+fn locale() -> Option<String> {
+    let value = ["RUSTWGET_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())?;
+    let locale = value.split(['.', '@']).next().unwrap_or_default();
+    match locale {
+        "" | "C" | "POSIX" => None,
+        _ if locale == "en" || locale.starts_with("en_") => None,
+        _ => Some(locale.to_string()),
+    }
+}
+
+/// Reads the translations for `locale` from `dir`, falling back from a
+/// country (`fr_FR`) to its language (`fr`).
+///
+/// A catalog that cannot be parsed is reported and ignored.
+fn translations(dir: &Path, locale: &str) -> HashMap<String, String> {
+    let language = locale.split(['_', '-']).next().unwrap_or(locale);
+    for name in [locale, language] {
+        let path = dir.join(format!("{}.toml", name));
+        let Ok(text) = fs::read_to_string(&path) else {
+            continue;
+        };
+        match toml::from_str(&text) {
+            Ok(catalog) => return catalog,
+            Err(err) => {
+                logfile::warning(&format!(
+                    "ignoring translations {}: {}",
+                    path.display(),
+                    err
+                ));
+                return HashMap::new();
+            }
+        }
+    }
+    HashMap::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translations_fall_back_to_english() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("fr.toml"),
+            "downloaded = \"Téléchargé : {file}\"\n\"skipped.exists\" = \"Ignoré : {fichier}\"\n",
+        )
+        .unwrap();
+        let catalog = translations(dir.path(), "fr_FR");
+        assert_eq!(catalog.len(), 2);
+        assert!(translations(dir.path(), "de_DE").is_empty());
+
+        let file: &dyn fmt::Display = &"a.txt";
+        assert_eq!(
+            render(&catalog, "downloaded", &[("file", file)]),
+            "Téléchargé : a.txt"
+        );
+        // A translation naming an unknown value is not used.
+        assert_eq!(
+            render(&catalog, "skipped.exists", &[("file", file)]),
+            "Skipped (file exists): a.txt"
+        );
+        assert_eq!(
+            render(&catalog, "error", &[("error", &"timed out")]),
+            "Error: timed out"
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! Inspecting a resource without downloading it.
+//!
+//! `rustwget info URL` sends a `HEAD` request, following redirects, and prints
+//! what the response says about the resource: its status, final URL, size,
+//! content type, modification time, ETag, and whether the server accepts byte
+//! ranges and compresses it. `--json` prints the same as a JSON object for
+//! scripts. Servers that refuse `HEAD` are asked for the first byte with a
+//! ranged `GET` instead. Credentials come from the keyring and the `[host]`
+//! sections of the configuration file, as for downloads.
+
+use crate::auth::Keyring;
+use crate::config;
+use crate::download::Options;
+use crate::hosts::Hosts;
+use crate::redact;
+use crate::redirect;
+use crate::units;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use reqwest::blocking::{Client, ClientBuilder, Response};
+use reqwest::header::{
+    HeaderMap, ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE,
+    CONTENT_TYPE, ETAG, LAST_MODIFIED, RANGE,
+};
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Encodings the server is offered, to learn whether it compresses the resource.
+const ENCODINGS: &str = "gzip, br, zstd, deflate";
+
+/// Defines the `info` subcommand.
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("info")
+        .about("Show what a server says about a URL without downloading it")
+        .arg(
+            Arg::with_name("URL")
+                .help("The URL to inspect")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Print the information as JSON"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Read host settings from this TOML file")
+                .takes_value(true),
+        )
+}
+
+/// What a server says about a resource.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Info {
+    pub status: u16,
+    pub final_url: String,
+    /// Size in bytes, if the server gives one.
+    pub size: Option<u64>,
+    pub content_type: Option<String>,
+    pub last_modified: Option<String>,
+    pub etag: Option<String>,
+    pub accepts_ranges: bool,
+    /// The `Content-Encoding` the server chose among those offered, if any.
+    pub compression: Option<String>,
+}
+
+impl Info {
+    /// Reads the information from the response to a `HEAD` request, or to a
+    /// `GET` request for the first byte.
+    fn new(response: &Response) -> Info {
+        let headers = response.headers();
+        let partial = response.status() == StatusCode::PARTIAL_CONTENT;
+        let size = match partial {
+            // `bytes 0-0/1234`
+            true => header(headers, CONTENT_RANGE)
+                .and_then(|range| range.rsplit_once('/')?.1.parse().ok()),
+            false => header(headers, CONTENT_LENGTH).and_then(|len| len.parse().ok()),
+        };
+        Info {
+            status: response.status().as_u16(),
+            final_url: response.url().to_string(),
+            size,
+            content_type: header(headers, CONTENT_TYPE),
+            last_modified: header(headers, LAST_MODIFIED),
+            etag: header(headers, ETAG),
+            accepts_ranges: partial || header(headers, ACCEPT_RANGES).as_deref() == Some("bytes"),
+            compression: header(headers, CONTENT_ENCODING)
+                .filter(|encoding| encoding != "identity"),
+        }
+    }
+
+    /// Formats the information for the terminal.
+    pub fn summary(&self) -> String {
+        let status = StatusCode::from_u16(self.status)
+            .map(|status| status.to_string())
+            .unwrap_or_else(|_| self.status.to_string());
+        let unknown = || "unknown".to_string();
+        let yes_no = |yes| if yes { "yes" } else { "no" };
+        format!(
+            "Status:         {}\n\
+             Final URL:      {}\n\
+             Size:           {}\n\
+             Content type:   {}\n\
+             Last modified:  {}\n\
+             ETag:           {}\n\
+             Byte ranges:    {}\n\
+             Compression:    {}\n",
+            status,
+            redact::url(&self.final_url),
+            self.size.map_or_else(unknown, |size| format!(
+                "{} ({} bytes)",
+                units::format_bytes(size),
+                size
+            )),
+            self.content_type.clone().unwrap_or_else(unknown),
+            self.last_modified.clone().unwrap_or_else(unknown),
+            self.etag.clone().unwrap_or_else(unknown),
+            yes_no(self.accepts_ranges),
+            self.compression.as_deref().unwrap_or("none"),
+        )
+    }
+}
+
+fn header(headers: &HeaderMap, name: reqwest::header::HeaderName) -> Option<String> {
+    Some(headers.get(name)?.to_str().ok()?.to_string())
+}
+
+/// Asks the server about `url`.
+///
+/// # Errors
+///
+/// Returns an error if the request fails.
+pub fn fetch(
+    client: &Client,
+    url: &str,
+    options: &Options,
+) -> Result<Info, Box<dyn std::error::Error>> {
+    let response = options.send(client, url, |client| {
+        client.head(url).header(ACCEPT_ENCODING, ENCODINGS)
+    })?;
+    let response = match response.status() {
+        StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED => {
+            options.send(client, url, |client| {
+                client
+                    .get(url)
+                    .header(ACCEPT_ENCODING, ENCODINGS)
+                    .header(RANGE, "bytes=0-0")
+            })?
+        }
+        _ => response,
+    };
+    Ok(Info::new(&response))
+}
+
+/// Runs the `info` subcommand.
+///
+/// # Errors
+///
+/// Returns an error if the configuration cannot be read or the request fails.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let config = config::load(matches.value_of("config").map(Path::new))?;
+    let builder = || -> Result<ClientBuilder, Box<dyn std::error::Error>> {
+        Ok(Client::builder().redirect(redirect::policy(redirect::DEFAULT_MAX, false)))
+    };
+    let options = Options {
+        keyring: Some(Arc::new(Keyring::new(config.oauth.clone()))),
+        hosts: Arc::new(Hosts::new(&config.host, builder)?),
+        ..Options::default()
+    };
+    let info = fetch(
+        &builder()?.build()?,
+        matches.value_of("URL").unwrap(),
+        &options,
+    )?;
+    if matches.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+    } else {
+        print!("{}", info.summary());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+
+    #[test]
+    fn test_fetch_reads_headers() {
+        let head = mock("HEAD", "/info/file.tar")
+            .with_header("content-length", "2048")
+            .with_header("content-type", "application/x-tar")
+            .with_header("etag", "\"v1\"")
+            .with_header("accept-ranges", "bytes")
+            .with_header("content-encoding", "gzip")
+            .create();
+        let url = format!("{}/info/file.tar", server_url());
+
+        let info = fetch(&Client::new(), &url, &Options::default()).unwrap();
+        assert_eq!(info.status, 200);
+        assert_eq!(info.size, Some(2048));
+        assert_eq!(info.content_type.as_deref(), Some("application/x-tar"));
+        assert_eq!(info.etag.as_deref(), Some("\"v1\""));
+        assert!(info.accepts_ranges);
+        assert_eq!(info.compression.as_deref(), Some("gzip"));
+        assert!(info
+            .summary()
+            .contains("Size:           2.0 KiB (2048 bytes)"));
+        head.assert();
+    }
+
+    #[test]
+    fn test_fetch_falls_back_to_ranged_get() {
+        let head = mock("HEAD", "/info/nohead.bin").with_status(405).create();
+        let get = mock("GET", "/info/nohead.bin")
+            .match_header("range", "bytes=0-0")
+            .with_status(206)
+            .with_header("content-range", "bytes 0-0/5000")
+            .with_body("x")
+            .create();
+        let url = format!("{}/info/nohead.bin", server_url());
+
+        let info = fetch(&Client::new(), &url, &Options::default()).unwrap();
+        assert_eq!(info.status, 206);
+        assert_eq!(info.size, Some(5000));
+        assert!(info.accepts_ranges && info.compression.is_none());
+        head.assert();
+        get.assert();
+    }
+}
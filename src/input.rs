@@ -0,0 +1,165 @@
+//! Parsing of URL lists given with `-i/--input-file`.
+//!
+//! Each non-empty line holds a URL optionally followed by `key=value`
+//! settings for that entry. Lines starting with `#` are comments:
+//!
+//! ```text
+//! # release artifacts first, docs whenever there is time
+//! https://example.com/release.tar.gz  priority=critical
+//! https://example.com/docs.zip        priority=low
+//! https://example.com/checksums.txt
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// One URL to download together with its per-entry settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub url: String,
+    pub priority: i32,
+    /// Where to save the download, if not derived from the URL.
+    pub output: Option<PathBuf>,
+}
+
+/// Parses a priority given either as an integer or as a named level.
+///
+/// The named levels are `low` (-1), `normal` (0), `high` (1) and `critical` (2);
+/// larger values are scheduled first.
+pub fn parse_priority(value: &str) -> Result<i32, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "low" => Ok(-1),
+        "normal" => Ok(0),
+        "high" => Ok(1),
+        "critical" => Ok(2),
+        other => other.parse().map_err(|_| {
+            format!(
+                "invalid priority '{}': expected low, normal, high, critical, or an integer",
+                value
+            )
+        }),
+    }
+}
+
+/// Parses the contents of an input file.
+///
+/// # Arguments
+///
+/// * `text`: The file contents.
+/// * `default_priority`: Priority of entries that do not set one.
+///
+/// # Errors
+///
+/// Returns an error naming the offending line for unknown or malformed settings.
+pub fn parse(text: &str, default_priority: i32) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let mut entry = Entry {
+            url: fields.next().unwrap_or_default().to_string(),
+            priority: default_priority,
+            output: None,
+        };
+        for field in fields {
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            match field.split_once('=') {
+                Some(("priority", value)) => {
+                    entry.priority = parse_priority(value).map_err(error)?
+                }
+                _ => return Err(error(format!("unknown setting '{}'", field))),
+            }
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Reads and parses an input file; `-` reads standard input.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or contains invalid settings.
+pub fn read(path: &str, default_priority: i32) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+    let text = if path == "-" {
+        std::io::read_to_string(std::io::stdin())?
+    } else {
+        fs::read_to_string(Path::new(path))?
+    };
+    parse(&text, default_priority).map_err(|err| format!("{}: {}", path, err).into())
+}
+
+/// Adds `scheme` to a URL given without one, such as `example.com/file.txt`
+/// or `//example.com/file.txt`; other URLs are returned unchanged.
+pub fn with_default_scheme(url: &str, scheme: &str) -> String {
+    if url.contains("://") {
+        url.to_string()
+    } else if let Some(rest) = url.strip_prefix("//") {
+        format!("{}://{}", scheme, rest)
+    } else {
+        format!("{}://{}", scheme, url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_priority() {
+        assert_eq!(parse_priority("HIGH"), Ok(1));
+        assert_eq!(parse_priority("-5"), Ok(-5));
+        assert!(parse_priority("urgent").is_err());
+    }
+
+    #[test]
+    fn test_with_default_scheme() {
+        assert_eq!(
+            with_default_scheme("example.com/file.txt", "https"),
+            "https://example.com/file.txt"
+        );
+        assert_eq!(
+            with_default_scheme("localhost:8080/a", "http"),
+            "http://localhost:8080/a"
+        );
+        assert_eq!(
+            with_default_scheme("//cdn.example.com/a", "https"),
+            "https://cdn.example.com/a"
+        );
+        assert_eq!(
+            with_default_scheme("http://example.com/", "https"),
+            "http://example.com/"
+        );
+    }
+
+    #[test]
+    fn test_parse_entries() {
+        let text = "# comment\n\nhttps://a.example/x priority=critical\n  https://b.example/y  \n";
+        let entries = parse(text, 0).unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                Entry {
+                    url: "https://a.example/x".to_string(),
+                    priority: 2,
+                    output: None,
+                },
+                Entry {
+                    url: "https://b.example/y".to_string(),
+                    priority: 0,
+                    output: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_settings() {
+        let err = parse("https://a.example/x\nhttps://b.example/y colour=blue\n", 0).unwrap_err();
+        assert_eq!(err, "line 2: unknown setting 'colour=blue'");
+    }
+}
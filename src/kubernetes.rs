@@ -0,0 +1,204 @@
+//! Credentials and certificate authorities of Kubernetes pods.
+//!
+//! Kubernetes mounts a service-account token and the certificate authority of
+//! the cluster into every pod at [`DIRECTORY`], and OpenShift adds the one
+//! that signs the certificates of its services. When a token is mounted
+//! there, both authorities are trusted in addition to the system's, and the
+//! token is sent as a bearer token to in-cluster services that no other
+//! credentials apply to: the API server and hosts named `NAME.NAMESPACE.svc`
+//! or `NAME.NAMESPACE.svc.CLUSTER-DOMAIN`, the cluster domain being taken
+//! from the DNS search list of the pod. It is only ever sent over HTTPS, and
+//! read again every minute, as the kubelet rotates it.
+//!
+//! `--service-account-dir DIR` reads the token and authorities from `DIR`
+//! instead, and `--no-service-account` ignores them. `--ca-certificate FILE`
+//! trusts the authorities of another PEM bundle, in or out of a cluster.
+
+use crate::auth::{self, Credentials};
+use reqwest::Certificate;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Where Kubernetes mounts the service account of a pod.
+pub const DIRECTORY: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+/// The certificate authorities mounted with the token: the cluster's, and
+/// that of OpenShift's service certificates.
+const AUTHORITIES: [&str; 2] = ["ca.crt", "service-ca.crt"];
+
+/// The cluster domain of a pod whose DNS search list names none.
+const CLUSTER_DOMAIN: &str = "cluster.local";
+
+/// How long a token read from its file is used before it is read again.
+const REFRESH: Duration = Duration::from_secs(60);
+
+/// The service account a pod runs as.
+#[derive(Debug)]
+pub struct ServiceAccount {
+    directory: PathBuf,
+    /// The host of the API server, as Kubernetes tells pods.
+    api_server: Option<String>,
+    /// The domain the names of services end in, such as `cluster.local`.
+    cluster_domain: String,
+    /// The token last read, and when.
+    token: Mutex<(Instant, Credentials)>,
+}
+
+impl ServiceAccount {
+    /// Opens the service account mounted at `directory`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if its token cannot be read or is not a valid header
+    /// value.
+    pub fn open(directory: &Path) -> Result<ServiceAccount, String> {
+        let token = read_token(directory)?;
+        Ok(ServiceAccount {
+            directory: directory.to_path_buf(),
+            api_server: env::var("KUBERNETES_SERVICE_HOST")
+                .ok()
+                .map(|host| host.trim_matches(['[', ']']).to_ascii_lowercase()),
+            cluster_domain: fs::read_to_string("/etc/resolv.conf")
+                .ok()
+                .and_then(|resolv| cluster_domain(&resolv))
+                .unwrap_or_else(|| CLUSTER_DOMAIN.to_string()),
+            token: Mutex::new((Instant::now(), token)),
+        })
+    }
+
+    /// Returns the service account mounted at `directory`, or `None` outside
+    /// of a pod.
+    pub fn detect(directory: &Path) -> Option<ServiceAccount> {
+        directory
+            .join("token")
+            .is_file()
+            .then(|| ServiceAccount::open(directory).ok())
+            .flatten()
+    }
+
+    /// Returns the certificate authority bundles mounted with the token.
+    pub fn authorities(&self) -> Vec<PathBuf> {
+        AUTHORITIES
+            .iter()
+            .map(|name| self.directory.join(name))
+            .filter(|path| path.is_file())
+            .collect()
+    }
+
+    /// Returns the token for `url` if it is an in-cluster service reached
+    /// over HTTPS.
+    pub fn credentials(&self, url: &str) -> Option<Credentials> {
+        let url = Url::parse(url).ok()?;
+        if url.scheme() != "https" || !self.is_in_cluster(url.host_str()?) {
+            return None;
+        }
+        let mut token = self.token.lock().unwrap_or_else(|e| e.into_inner());
+        if token.0.elapsed() >= REFRESH {
+            // The last token read stays in use if the file is being replaced.
+            if let Ok(fresh) = read_token(&self.directory) {
+                token.1 = fresh;
+            }
+            token.0 = Instant::now();
+        }
+        Some(token.1.clone())
+    }
+
+    /// Whether `host` is the API server or a service of the cluster.
+    fn is_in_cluster(&self, host: &str) -> bool {
+        let host = host.trim_matches(['[', ']']).to_ascii_lowercase();
+        let host = host.trim_end_matches('.');
+        self.api_server.as_deref() == Some(host)
+            || host.ends_with(".svc")
+            || host
+                .strip_suffix(self.cluster_domain.as_str())
+                .is_some_and(|name| name.ends_with(".svc."))
+    }
+}
+
+/// Returns the cluster domain in the DNS search list of `resolv`, the
+/// `/etc/resolv.conf` of a pod, which lists `svc.CLUSTER-DOMAIN`.
+fn cluster_domain(resolv: &str) -> Option<String> {
+    resolv
+        .lines()
+        .filter_map(|line| line.strip_prefix("search"))
+        .flat_map(str::split_whitespace)
+        .find_map(|domain| domain.strip_prefix("svc."))
+        .map(|domain| domain.trim_end_matches('.').to_ascii_lowercase())
+}
+
+/// Reads the token of the service account mounted at `directory`.
+fn read_token(directory: &Path) -> Result<Credentials, String> {
+    let path = directory.join("token");
+    auth::bearer(None, Some(&path))
+        .map_err(|err| err.to_string())?
+        .ok_or_else(|| format!("no service-account token in {}", directory.display()))
+}
+
+/// Reads the certificates of the PEM bundles at `paths`.
+///
+/// # Errors
+///
+/// Returns an error if a bundle cannot be read or holds no valid certificate.
+pub fn certificates(paths: &[PathBuf]) -> Result<Vec<Certificate>, String> {
+    let mut certificates = Vec::new();
+    for path in paths {
+        let invalid =
+            |reason: String| format!("cannot read certificate {}: {}", path.display(), reason);
+        let pem = fs::read(path).map_err(|err| invalid(err.to_string()))?;
+        let bundle = Certificate::from_pem_bundle(&pem).map_err(|err| invalid(err.to_string()))?;
+        if bundle.is_empty() {
+            return Err(invalid("no certificate in it".to_string()));
+        }
+        certificates.extend(bundle);
+    }
+    Ok(certificates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_account() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(ServiceAccount::detect(dir.path()).is_none());
+        assert!(ServiceAccount::open(dir.path()).is_err());
+
+        fs::write(dir.path().join("token"), "eyJhbGciOi.token\n").unwrap();
+        fs::write(dir.path().join("ca.crt"), "").unwrap();
+        let account = ServiceAccount {
+            api_server: Some("10.96.0.1".to_string()),
+            cluster_domain: "cluster.local".to_string(),
+            ..ServiceAccount::detect(dir.path()).unwrap()
+        };
+        assert_eq!(account.authorities(), vec![dir.path().join("ca.crt")]);
+        let token = Some(Credentials::Bearer("eyJhbGciOi.token".to_string()));
+        for url in [
+            "https://10.96.0.1/api/v1/namespaces",
+            "https://kubernetes.default.svc/version",
+            "https://minio.storage.svc.cluster.local:9000/bucket/a.tar",
+        ] {
+            assert_eq!(account.credentials(url), token, "{}", url);
+        }
+        for url in [
+            "http://minio.storage.svc:9000/bucket/a.tar",
+            "https://example.com/a.tar",
+            "https://svc.example.com/a.tar",
+            "https://minio.storage.svc.example.com/a.tar",
+        ] {
+            assert_eq!(account.credentials(url), None, "{}", url);
+        }
+        assert!(certificates(&account.authorities()).is_err());
+    }
+
+    #[test]
+    fn test_cluster_domain() {
+        let resolv = "search ci.svc.k8s.corp svc.k8s.corp k8s.corp\nnameserver 10.96.0.10\noptions ndots:5\n";
+        assert_eq!(cluster_domain(resolv).as_deref(), Some("k8s.corp"));
+        assert_eq!(cluster_domain("nameserver 1.1.1.1\n"), None);
+    }
+}
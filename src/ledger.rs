@@ -0,0 +1,253 @@
+//! A record of completed downloads that lets repeated runs skip work.
+//!
+//! With `--skip-existing-ledger`, every completed download is recorded with
+//! its size, SHA-256 digest, and ETag. On later runs a URL is skipped without
+//! any network request when the ledger has an entry for the same URL and
+//! destination and the file on disk still has the recorded size and digest.
+//! URLs are recorded with their credentials [`redact`](crate::redact)ed,
+//! and matched by a digest of the exact URL, so that URLs differing only in
+//! their secrets are kept apart.
+//!
+//! With `--if-changed`, the recorded ETag and `Last-Modified` time of an
+//! intact file are sent instead as `If-None-Match` and `If-Modified-Since`, so
+//! that the file is only downloaded again when the server has changed it.
+
+use crate::paths;
+use crate::redact;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One completed download.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    pub url: String,
+    /// The [`url_key`] of the URL; empty in records of older versions,
+    /// which are matched by their URL instead.
+    #[serde(default)]
+    pub key: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    /// The `Last-Modified` time of the download, as an HTTP date.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub fetched_at: u64,
+}
+
+/// The on-disk ledger, shared by all transfers of a run.
+#[derive(Debug)]
+pub struct Ledger {
+    path: PathBuf,
+    records: Mutex<Vec<Record>>,
+}
+
+/// Returns the path of the ledger used when none is given.
+pub fn default_path() -> PathBuf {
+    paths::data_dir().join("ledger.json")
+}
+
+/// Computes the hex-encoded SHA-256 digest of a file.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Returns the hex-encoded SHA-256 digest of the exact `url`, by which
+/// records and pins are looked up without keeping its secrets.
+pub fn url_key(url: &str) -> String {
+    hex(&Sha256::digest(url.as_bytes()))
+}
+
+/// Parses an expected SHA-256 digest given as `sha256:HEX` or plain `HEX`.
+pub fn parse_checksum(value: &str) -> Result<String, String> {
+    let digest = value.strip_prefix("sha256:").unwrap_or(value);
+    if digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(digest.to_ascii_lowercase())
+    } else {
+        Err(format!(
+            "invalid checksum '{}': expected sha256:<64 hex digits>",
+            value
+        ))
+    }
+}
+
+/// Encodes bytes as lowercase hexadecimal.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl Record {
+    /// Whether this is the record of a download of `url`.
+    fn is_for(&self, url: &str) -> bool {
+        match self.key.is_empty() {
+            true => self.url == url,
+            false => self.key == url_key(url),
+        }
+    }
+}
+
+impl Ledger {
+    /// Opens the ledger at `path`, starting empty if it does not exist yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or parsed.
+    pub fn open(path: PathBuf) -> Result<Ledger, Box<dyn std::error::Error>> {
+        let records = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)
+                .map_err(|err| format!("invalid ledger {}: {}", path.display(), err))?
+        } else {
+            Vec::new()
+        };
+        Ok(Ledger {
+            path,
+            records: Mutex::new(records),
+        })
+    }
+
+    /// Returns the recorded downloads, oldest first.
+    pub fn records(&self) -> Vec<Record> {
+        self.records
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Whether `url` was already downloaded to `destination` and the file is unchanged.
+    ///
+    /// This never touches the network: the local file's size and digest are
+    /// compared with the recorded ones.
+    pub fn is_current(&self, url: &str, destination: &Path) -> bool {
+        self.current(url, destination).is_some()
+    }
+
+    /// Returns the record of the download of `url` to `destination` if the
+    /// file is unchanged, as checked by [`Ledger::is_current`].
+    pub fn current(&self, url: &str, destination: &Path) -> Option<Record> {
+        let destination = std::path::absolute(destination).ok()?;
+        let record = self
+            .records
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|record| record.is_for(url) && record.path == destination)
+            .cloned();
+        record.filter(|record| {
+            fs::metadata(&destination).is_ok_and(|meta| meta.len() == record.size)
+                && sha256_file(&destination).is_ok_and(|digest| digest == record.sha256)
+        })
+    }
+
+    /// Records a completed download of `url` into `destination` and saves the ledger.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be hashed or the ledger cannot be written.
+    pub fn record(
+        &self,
+        url: &str,
+        destination: &Path,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let destination = std::path::absolute(destination)?;
+        let record = Record {
+            url: redact::url(url).into_owned(),
+            key: url_key(url),
+            size: fs::metadata(&destination)?.len(),
+            sha256: sha256_file(&destination)?,
+            path: destination,
+            etag,
+            last_modified,
+            fetched_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+        };
+
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.retain(|existing| !(existing.is_for(url) && existing.path == record.path));
+        records.push(record);
+
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&*records)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_skip() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger_path = dir.path().join("ledger.json");
+        let file = dir.path().join("data.bin");
+        fs::write(&file, "payload").unwrap();
+
+        let ledger = Ledger::open(ledger_path.clone()).unwrap();
+        assert!(!ledger.is_current("https://example.com/data.bin", &file));
+        ledger
+            .record(
+                "https://example.com/data.bin",
+                &file,
+                Some("\"v1\"".to_string()),
+                None,
+            )
+            .unwrap();
+
+        let reopened = Ledger::open(ledger_path).unwrap();
+        assert!(reopened.is_current("https://example.com/data.bin", &file));
+        assert_eq!(
+            reopened
+                .current("https://example.com/data.bin", &file)
+                .and_then(|record| record.etag),
+            Some("\"v1\"".to_string())
+        );
+        assert!(!reopened.is_current("https://example.com/other.bin", &file));
+        // URLs that differ only in their secrets are recorded apart.
+        reopened
+            .record("https://example.com/data.bin?sig=a", &file, None, None)
+            .unwrap();
+        assert!(reopened.is_current("https://example.com/data.bin?sig=a", &file));
+        assert!(!reopened.is_current("https://example.com/data.bin?sig=b", &file));
+
+        fs::write(&file, "tampered").unwrap();
+        assert!(!reopened.is_current("https://example.com/data.bin", &file));
+    }
+
+    #[test]
+    fn test_parse_checksum() {
+        let digest = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+        assert_eq!(
+            parse_checksum(&format!("sha256:{}", digest)),
+            Ok(digest.to_ascii_lowercase())
+        );
+        assert!(parse_checksum("md5:900150983cd24fb0d6963f7d28e17f72").is_err());
+    }
+
+    #[test]
+    fn test_sha256_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("abc");
+        fs::write(&file, "abc").unwrap();
+
+        assert_eq!(
+            sha256_file(&file).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
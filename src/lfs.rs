@@ -0,0 +1,314 @@
+//! Git LFS objects behind the pointer files of raw Git URLs.
+//!
+//! A repository that keeps a file in Git LFS stores a small pointer in its
+//! place, and the raw URL of the file serves that pointer:
+//!
+//! ```text
+//! version https://git-lfs.github.com/spec/v1
+//! oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393
+//! size 12345
+//! ```
+//!
+//! A download that turns out to be a pointer is replaced with the object it
+//! names, which is looked up with the LFS batch API of the repository and
+//! verified against its OID and size. The repository is told by the layout
+//! of the raw URL: `raw.githubusercontent.com/OWNER/REPO/...`,
+//! `HOST/OWNER/REPO/raw/...` (GitHub, Gitea), and `HOST/GROUP/REPO/-/raw/...`
+//! (GitLab). The batch request carries the credentials of the download.
+//!
+//! A pointer whose object cannot be fetched is kept with a warning, unless
+//! `--lfs` is given, which fails its download instead.
+
+use crate::digest::{Algorithm, Hasher, HashingWriter};
+use crate::download::Options;
+use crate::logfile;
+use reqwest::blocking::Client;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use url::Url;
+
+/// The first line of a pointer file.
+const VERSION: &str = "version https://git-lfs.github.com/spec/v1";
+
+/// The media type of the batch API.
+const MEDIA_TYPE: &str = "application/vnd.git-lfs+json";
+
+/// Largest pointer file; the specification keeps them under 1024 bytes.
+const MAX_POINTER: u64 = 1024;
+
+/// The object a pointer file names.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Pointer {
+    /// The SHA-256 digest of the object, in lowercase hex.
+    pub oid: String,
+    pub size: u64,
+}
+
+impl Pointer {
+    /// Parses the text of a pointer file.
+    pub fn parse(text: &str) -> Option<Pointer> {
+        let mut lines = text.lines();
+        if lines.next()? != VERSION {
+            return None;
+        }
+        let (mut oid, mut size) = (None, None);
+        for line in lines.filter(|line| !line.is_empty()) {
+            match line.split_once(' ')? {
+                ("oid", value) => oid = value.strip_prefix("sha256:"),
+                ("size", value) => size = value.parse().ok(),
+                _ => {}
+            }
+        }
+        let oid =
+            oid.filter(|oid| oid.len() == 64 && oid.bytes().all(|b| b.is_ascii_hexdigit()))?;
+        Some(Pointer {
+            oid: oid.to_ascii_lowercase(),
+            size: size?,
+        })
+    }
+
+    /// Reads the pointer file at `path`, or `None` if it holds something else.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read.
+    pub fn read(path: &Path) -> io::Result<Option<Pointer>> {
+        if fs::metadata(path)?.len() > MAX_POINTER {
+            return Ok(None);
+        }
+        Ok(String::from_utf8(fs::read(path)?)
+            .ok()
+            .and_then(|text| Pointer::parse(&text)))
+    }
+}
+
+/// Returns the LFS server of the repository that serves the raw file at
+/// `url`, if its layout is a known one.
+pub fn server(url: &Url) -> Option<Url> {
+    let host = url.host_str()?;
+    let segments: Vec<&str> = url.path_segments()?.collect();
+    let gitlab = segments.windows(2).position(|pair| pair == ["-", "raw"]);
+    let repository = if host == "raw.githubusercontent.com" {
+        segments.get(..2)?
+    } else if let Some(index) = gitlab {
+        &segments[..index]
+    } else if segments.get(2) == Some(&"raw") {
+        &segments[..2]
+    } else {
+        return None;
+    };
+    if repository.len() < 2 || repository.iter().any(|segment| segment.is_empty()) {
+        return None;
+    }
+    let mut server = url.clone();
+    server.set_query(None);
+    server.set_fragment(None);
+    if host == "raw.githubusercontent.com" {
+        server.set_host(Some("github.com")).ok()?;
+    }
+    let (name, groups) = repository.split_last()?;
+    server
+        .path_segments_mut()
+        .ok()?
+        .clear()
+        .extend(groups)
+        .push(&format!("{}.git", name.trim_end_matches(".git")))
+        .extend(["info", "lfs"]);
+    Some(server)
+}
+
+#[derive(Debug, Serialize)]
+struct BatchRequest<'a> {
+    operation: &'static str,
+    transfers: [&'static str; 1],
+    objects: [&'a Pointer; 1],
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchResponse {
+    objects: Vec<Object>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Object {
+    oid: String,
+    actions: Option<Actions>,
+    error: Option<ObjectError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Actions {
+    download: Option<Action>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Action {
+    href: String,
+    #[serde(default)]
+    header: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ObjectError {
+    code: u16,
+    message: String,
+}
+
+/// Replaces the file downloaded from `url` to `path` with the LFS object it
+/// points to, if it is a pointer file.
+///
+/// Returns whether it was one.
+///
+/// # Errors
+///
+/// Returns an error if the repository of `url` is unknown, or the object
+/// cannot be fetched or does not match the pointer.
+pub fn resolve(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    options: &Options,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let Some(pointer) = Pointer::read(path)? else {
+        return Ok(false);
+    };
+    let server = Url::parse(url)
+        .ok()
+        .as_ref()
+        .and_then(server)
+        .ok_or_else(|| {
+            format!(
+                "{} is a Git LFS pointer, but its repository is unknown",
+                url
+            )
+        })?;
+    logfile::info(&format!("Fetching Git LFS object {}", pointer.oid));
+    let action = locate(client, &server, &pointer, options)?;
+    let mut request = client.get(&action.href);
+    for (name, value) in &action.header {
+        request = request.header(name, value);
+    }
+    let mut response = request
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| format!("cannot fetch Git LFS object {}: {}", pointer.oid, err))?;
+    let mut writer = HashingWriter {
+        inner: File::create(path)?,
+        hasher: Hasher::new(&[Algorithm::Sha256]),
+    };
+    let size = io::copy(&mut response, &mut writer)?;
+    writer.inner.sync_all()?;
+    let oid = writer
+        .hasher
+        .finish()
+        .into_iter()
+        .next()
+        .map(|(_, digest)| digest)
+        .unwrap_or_default();
+    if oid != pointer.oid || size != pointer.size {
+        return Err(format!(
+            "Git LFS object {} does not match its pointer: got {} bytes with OID {}",
+            pointer.oid, size, oid
+        )
+        .into());
+    }
+    Ok(true)
+}
+
+/// Asks the batch API at `server` where to download the object of `pointer`.
+fn locate(
+    client: &Client,
+    server: &Url,
+    pointer: &Pointer,
+    options: &Options,
+) -> Result<Action, Box<dyn std::error::Error>> {
+    let batch = format!("{}/objects/batch", server);
+    let body = serde_json::to_vec(&BatchRequest {
+        operation: "download",
+        transfers: ["basic"],
+        objects: [pointer],
+    })?;
+    let response = options
+        .send(client, &batch, |client| {
+            client
+                .post(&batch)
+                .header(ACCEPT, MEDIA_TYPE)
+                .header(CONTENT_TYPE, MEDIA_TYPE)
+                .body(body.clone())
+        })?
+        .error_for_status()
+        .map_err(|err| format!("Git LFS batch request to {} failed: {}", server, err))?;
+    let batch: BatchResponse = serde_json::from_reader(response)
+        .map_err(|err| format!("invalid Git LFS batch response from {}: {}", server, err))?;
+    let object = batch
+        .objects
+        .into_iter()
+        .find(|object| object.oid == pointer.oid)
+        .ok_or_else(|| {
+            format!(
+                "{} did not answer for Git LFS object {}",
+                server, pointer.oid
+            )
+        })?;
+    if let Some(error) = object.error {
+        return Err(format!(
+            "Git LFS object {}: {} ({})",
+            pointer.oid, error.message, error.code
+        )
+        .into());
+    }
+    object
+        .actions
+        .and_then(|actions| actions.download)
+        .ok_or_else(|| {
+            format!(
+                "{} offers no download of Git LFS object {}",
+                server, pointer.oid
+            )
+            .into()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pointer() {
+        let oid = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+        assert_eq!(
+            Pointer::parse(&format!("{}\noid sha256:{}\nsize 12345\n", VERSION, oid)),
+            Some(Pointer {
+                oid: oid.to_string(),
+                size: 12345,
+            })
+        );
+        assert_eq!(
+            Pointer::parse(&format!("{}\noid sha256:{}\n", VERSION, oid)),
+            None
+        );
+        assert_eq!(Pointer::parse("oid sha256:abc\nsize 1\n"), None);
+    }
+
+    #[test]
+    fn test_server() {
+        let lfs = |url: &str| server(&Url::parse(url).unwrap()).map(String::from);
+        assert_eq!(
+            lfs("https://raw.githubusercontent.com/owner/repo/main/data/model.bin").as_deref(),
+            Some("https://github.com/owner/repo.git/info/lfs")
+        );
+        assert_eq!(
+            lfs("https://github.com/owner/repo/raw/refs/heads/main/model.bin").as_deref(),
+            Some("https://github.com/owner/repo.git/info/lfs")
+        );
+        assert_eq!(
+            lfs("https://gitlab.com/group/sub/repo/-/raw/main/model.bin?inline=false").as_deref(),
+            Some("https://gitlab.com/group/sub/repo.git/info/lfs")
+        );
+        assert_eq!(lfs("https://example.com/files/model.bin"), None);
+    }
+}
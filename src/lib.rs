@@ -0,0 +1,182 @@
+//! The download engine of rustwget, for programs that embed it.
+//!
+//! [`download::fetch`] streams a URL into a file with the [`download::Options`]
+//! of a run, reporting progress through a [`download::Control`]. Requests can
+//! be handed to a [`transport::Transport`] instead of the network; the
+//! scripted [`transport::Script`] injects latency, failures, and responses cut
+//! short, to exercise retries and resumption without sockets.
+//!
+//! The names and paths chosen from what servers send are derived by the pure
+//! functions [`filename::derive_filename`] and [`filename::sanitize_path`],
+//! and URL patterns are expanded by [`glob::expand_glob`], so that fuzzers
+//! and property tests can call them directly; the crate's own run with
+//! `cargo test --features fuzz fuzz`.
+//!
+//! Built for WebAssembly, the library keeps [`fetch::download`], which sends
+//! its requests through the host's `fetch`, and the modules it needs; see
+//! [`fetch`].
+//!
+//! The other modules make up the `rustwget` command and are public for its
+//! binary; they follow its needs rather than those of a stable API.
+
+#[cfg(not(target_family = "wasm"))]
+pub mod allocate;
+#[cfg(not(target_family = "wasm"))]
+pub mod auth;
+#[cfg(not(target_family = "wasm"))]
+pub mod bars;
+#[cfg(not(target_family = "wasm"))]
+pub mod batch;
+#[cfg(not(target_family = "wasm"))]
+pub mod benchmark;
+#[cfg(not(target_family = "wasm"))]
+pub mod bundle;
+#[cfg(not(target_family = "wasm"))]
+pub mod cache;
+#[cfg(not(target_family = "wasm"))]
+pub mod canonical;
+#[cfg(not(target_family = "wasm"))]
+pub mod charset;
+#[cfg(not(target_family = "wasm"))]
+pub mod check;
+#[cfg(not(target_family = "wasm"))]
+pub mod color;
+#[cfg(not(target_family = "wasm"))]
+pub mod config;
+pub mod content;
+#[cfg(not(target_family = "wasm"))]
+pub mod convert;
+#[cfg(not(target_family = "wasm"))]
+pub mod crawl;
+#[cfg(not(target_family = "wasm"))]
+pub mod daemon;
+pub mod decompress;
+#[cfg(not(target_family = "wasm"))]
+pub mod dedupe;
+pub mod digest;
+#[cfg(not(target_family = "wasm"))]
+pub mod direct;
+#[cfg(not(target_family = "wasm"))]
+pub mod dns;
+#[cfg(not(target_family = "wasm"))]
+pub mod doi;
+#[cfg(not(target_family = "wasm"))]
+pub mod download;
+pub mod extension;
+pub mod fetch;
+pub mod filename;
+#[cfg(all(test, feature = "fuzz"))]
+pub mod fuzz;
+#[cfg(not(target_family = "wasm"))]
+pub mod gemini;
+pub mod glob;
+#[cfg(not(target_family = "wasm"))]
+pub mod hosts;
+#[cfg(not(target_family = "wasm"))]
+pub mod httpd;
+#[cfg(not(target_family = "wasm"))]
+pub mod hub;
+#[cfg(not(target_family = "wasm"))]
+pub mod i18n;
+#[cfg(not(target_family = "wasm"))]
+pub mod info;
+#[cfg(not(target_family = "wasm"))]
+pub mod input;
+#[cfg(not(target_family = "wasm"))]
+pub mod kubernetes;
+pub mod ledger;
+#[cfg(not(target_family = "wasm"))]
+pub mod lfs;
+#[cfg(not(target_family = "wasm"))]
+pub mod listing;
+#[cfg(not(target_family = "wasm"))]
+pub mod lockfile;
+#[cfg(not(target_family = "wasm"))]
+pub mod logfile;
+#[cfg(not(target_family = "wasm"))]
+pub mod manifest;
+#[cfg(not(target_family = "wasm"))]
+pub mod mirror;
+#[cfg(not(target_family = "wasm"))]
+pub mod nameserver;
+#[cfg(not(target_family = "wasm"))]
+pub mod notify;
+#[cfg(not(target_family = "wasm"))]
+pub mod ntlm;
+#[cfg(not(target_family = "wasm"))]
+pub mod oauth;
+#[cfg(not(target_family = "wasm"))]
+pub mod oversize;
+pub mod overwrite;
+#[cfg(not(target_family = "wasm"))]
+pub mod papers;
+#[cfg(not(target_family = "wasm"))]
+pub mod partial;
+pub mod paths;
+#[cfg(not(target_family = "wasm"))]
+pub mod permissions;
+#[cfg(not(target_family = "wasm"))]
+pub mod prefetch;
+#[cfg(not(target_family = "wasm"))]
+pub mod proxy;
+#[cfg(not(target_family = "wasm"))]
+pub mod queue;
+#[cfg(not(target_family = "wasm"))]
+pub mod realm;
+pub mod redact;
+#[cfg(not(target_family = "wasm"))]
+pub mod redirect;
+#[cfg(not(target_family = "wasm"))]
+pub mod render;
+#[cfg(not(target_family = "wasm"))]
+pub mod report;
+#[cfg(not(target_family = "wasm"))]
+pub mod resume;
+#[cfg(not(target_family = "wasm"))]
+pub mod robots;
+#[cfg(not(target_family = "wasm"))]
+pub mod scan;
+#[cfg(not(target_family = "wasm"))]
+pub mod schedule;
+#[cfg(not(target_family = "wasm"))]
+pub mod secrets;
+#[cfg(not(target_family = "wasm"))]
+pub mod segment;
+#[cfg(not(target_family = "wasm"))]
+pub mod service;
+#[cfg(not(target_family = "wasm"))]
+pub mod session;
+#[cfg(not(target_family = "wasm"))]
+pub mod share;
+#[cfg(not(target_family = "wasm"))]
+pub mod sigv4;
+#[cfg(not(target_family = "wasm"))]
+pub mod sizes;
+#[cfg(not(target_family = "wasm"))]
+pub mod smb;
+#[cfg(not(target_family = "wasm"))]
+pub mod split;
+pub mod staging;
+#[cfg(not(target_family = "wasm"))]
+pub mod systemd;
+#[cfg(not(target_family = "wasm"))]
+pub mod template;
+#[cfg(not(target_family = "wasm"))]
+pub mod throttle;
+#[cfg(not(target_family = "wasm"))]
+pub mod torrent;
+#[cfg(not(target_family = "wasm"))]
+pub mod transport;
+#[cfg(not(target_family = "wasm"))]
+pub mod tui;
+pub mod units;
+#[cfg(not(target_family = "wasm"))]
+pub mod update;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub mod uring;
+#[cfg(not(target_family = "wasm"))]
+pub mod verify;
+#[cfg(not(target_family = "wasm"))]
+pub mod wayback;
+#[cfg(not(target_family = "wasm"))]
+pub mod webhook;
@@ -0,0 +1,419 @@
+//! Downloading directories of plain file servers from their listings.
+//!
+//! With `--recursive`, a URL that ends in `/` is a directory whose listing
+//! the server generates: an autoindex page of Apache or nginx, or the `LIST`
+//! output of an FTP server passed on as text by a gateway. Its entries are
+//! read into [`Item`]s; its files are downloaded and its subdirectories
+//! listed in turn, at most `--level` directories deep (5 by default, `0` for
+//! no limit) and never above the directory given. With `--accept`, only files
+//! whose names end in one of its comma-separated suffixes, or match one of its
+//! `*` and `?` patterns, are downloaded.
+//!
+//! Files are saved at their path below the directory given, so the recursive
+//! download of `https://example.com/pub/` saves `https://example.com/pub/a/b.txt`
+//! as `a/b.txt`, or below the output of the entry in an `--input-file`.
+
+use crate::charset;
+use crate::crawl;
+use crate::download::Options;
+use crate::input::Entry;
+use crate::logfile;
+use crate::share;
+use crate::units;
+use percent_encoding::percent_decode_str;
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
+use std::collections::{HashSet, VecDeque};
+use std::io::Read;
+use url::Url;
+
+/// Largest listing that is read.
+const MAX_LISTING: u64 = 16 * 1024 * 1024;
+
+/// One entry of a directory listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    pub url: Url,
+    /// The name of the file or directory, which holds no `/`.
+    pub name: String,
+    pub directory: bool,
+    /// Size in bytes, if the listing gives one; autoindex pages round it.
+    pub size: Option<u64>,
+    /// Modification time as the listing shows it.
+    pub modified: Option<String>,
+}
+
+impl Item {
+    /// Creates the item for `name` in the directory at `base`, unless the
+    /// name is not one of a file in that directory.
+    fn new(base: &Url, name: &str, directory: bool) -> Option<Item> {
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return None;
+        }
+        let mut url = base.clone();
+        url.path_segments_mut().ok()?.pop_if_empty().push(name);
+        if directory {
+            url.path_segments_mut().ok()?.push("");
+        }
+        Some(Item {
+            url,
+            name: name.to_string(),
+            directory,
+            size: None,
+            modified: None,
+        })
+    }
+}
+
+/// Reads the autoindex `page` of the directory at `base`.
+///
+/// Its entries are the links to the files and directories right below
+/// `base`; links to sort the listing, to the parent directory, or elsewhere
+/// are left out. Sizes and times are read from the text after each link.
+pub fn parse_html(page: &str, base: &Url) -> Vec<Item> {
+    let lower = page.to_ascii_lowercase();
+    let mut items = Vec::new();
+    let mut seen = HashSet::new();
+    for reference in crawl::html_references(page) {
+        let attribute = lower[..reference.start].trim_end_matches(['"', '\'', ' ', '=']);
+        if !attribute.ends_with("href") {
+            continue;
+        }
+        let Some(url) = crawl::resolve(base, &share::unescape(reference.text)) else {
+            continue;
+        };
+        if url.query().is_some() || url.origin() != base.origin() {
+            continue;
+        }
+        let Some(rest) = url.path().strip_prefix(base.path()) else {
+            continue;
+        };
+        let directory = rest.ends_with('/');
+        let name = percent_decode_str(rest.trim_end_matches('/')).decode_utf8_lossy();
+        let Some(mut item) = Item::new(base, &name, directory) else {
+            continue;
+        };
+        if !seen.insert(item.url.clone()) {
+            continue;
+        }
+        // `name</a>   2024-01-31 12:00  1.2K` or `<td>2024-01-31 12:00</td><td>1.2K</td>`
+        let after = &page[reference.start..];
+        let after = after[after.find('>').map_or(after.len(), |i| i + 1)..]
+            .split_once("</a>")
+            .map_or("", |(_, after)| after);
+        let row = after
+            .split(['\n', '\r'])
+            .next()
+            .unwrap_or_default()
+            .split("</tr>")
+            .next()
+            .unwrap_or_default();
+        let text = strip_tags(row);
+        let columns: Vec<&str> = text.split_whitespace().collect();
+        if let [date, time, ..] = columns[..] {
+            if date.contains('-') && time.contains(':') {
+                item.modified = Some(format!("{} {}", date, time));
+            }
+        }
+        if !directory {
+            item.size = columns
+                .last()
+                .filter(|size| {
+                    size.starts_with(|c: char| c.is_ascii_digit()) && !size.contains(':')
+                })
+                .and_then(|size| units::parse_size(size).ok());
+        }
+        items.push(item);
+    }
+    items
+}
+
+/// Returns `html` with its tags replaced by spaces.
+fn strip_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Reads the FTP `LIST` output `text` of the directory at `base`, in the
+/// Unix `ls -l` format or the DOS format of IIS.
+///
+/// Symbolic links and lines in neither format are left out.
+pub fn parse_ftp(text: &str, base: &Url) -> Vec<Item> {
+    let mut items = Vec::new();
+    for line in text.lines() {
+        // `drwxr-xr-x 2 ftp ftp 4096 Jan 31 12:00 name with spaces`
+        if let Some((fields, name)) = split_fields(line, 8) {
+            let kind = fields[0].chars().next().unwrap_or_default();
+            if fields[0].len() == 10 && matches!(kind, 'd' | '-') {
+                if let Some(mut item) = Item::new(base, name, kind == 'd') {
+                    item.size = fields[4].parse().ok().filter(|_| kind == '-');
+                    item.modified = Some(fields[5..8].join(" "));
+                    items.push(item);
+                }
+                continue;
+            }
+        }
+        // `01-31-24  12:00PM       <DIR>          name` or `... 1234 name`
+        if let Some((fields, name)) = split_fields(line, 3) {
+            if fields[0].matches('-').count() == 2 && fields[1].contains(':') {
+                let directory = fields[2] == "<DIR>";
+                if let Some(mut item) = Item::new(base, name, directory) {
+                    item.size = fields[2].parse().ok();
+                    item.modified = Some(format!("{} {}", fields[0], fields[1]));
+                    items.push(item);
+                }
+            }
+        }
+    }
+    items
+}
+
+/// Splits the first `count` whitespace-separated fields off `line`, returning
+/// them with the rest of the line.
+fn split_fields(line: &str, count: usize) -> Option<(Vec<&str>, &str)> {
+    let mut fields = Vec::with_capacity(count);
+    let mut rest = line.trim_start();
+    while fields.len() < count {
+        let end = rest.find(char::is_whitespace)?;
+        fields.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    Some((fields, rest.trim_end())).filter(|(_, rest)| !rest.is_empty())
+}
+
+/// Fetches and reads the listing of the directory at `url`.
+///
+/// # Errors
+///
+/// Returns an error if the request fails or the response is neither an HTML
+/// page nor text.
+pub fn fetch(client: &Client, url: &Url, options: &Options) -> Result<Vec<Item>, String> {
+    let response = options
+        .send(client, url.as_str(), |client| client.get(url.as_str()))
+        .map_err(|err| err.to_string())?
+        .error_for_status()
+        .map_err(|err| err.to_string())?;
+    // A redirect adds the slash that `/pub` lacks.
+    let base = response.url().clone();
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_default();
+    let mut body = Vec::new();
+    response
+        .take(MAX_LISTING)
+        .read_to_end(&mut body)
+        .map_err(|err| err.to_string())?;
+    let text = charset::decode(&body, Some(&content_type));
+    match content_type.as_str() {
+        html if html.starts_with("text/html") => Ok(parse_html(&text, &base)),
+        plain if plain.starts_with("text/plain") => Ok(parse_ftp(&text, &base)),
+        other => Err(format!("{} is not a directory listing ({})", url, other)),
+    }
+}
+
+/// Returns whether a file called `name` is accepted by `accept`, a list of
+/// suffixes and patterns; an empty list accepts every file.
+pub fn accepts(accept: &[String], name: &str) -> bool {
+    accept.is_empty()
+        || accept
+            .iter()
+            .any(|pattern| match pattern.contains(['*', '?']) {
+                true => crawl::wildcard(pattern, name),
+                false => name.ends_with(pattern.as_str()),
+            })
+}
+
+/// Replaces the entries whose URL ends in `/` with the files of the directory
+/// they name and its subdirectories, as far as `level` directories deep.
+///
+/// # Errors
+///
+/// Returns an error if the listing of a directory given cannot be read. A
+/// subdirectory whose listing cannot be read is skipped with a warning.
+pub fn expand(
+    client: &Client,
+    entries: Vec<Entry>,
+    accept: &[String],
+    level: usize,
+    options: &Options,
+) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+    let mut expanded = Vec::new();
+    for entry in entries {
+        if !entry.url.ends_with('/') {
+            expanded.push(entry);
+            continue;
+        }
+        let start = Url::parse(&entry.url)?;
+        let mut pending = VecDeque::from([(start.clone(), entry.output.unwrap_or_default(), 0)]);
+        let mut seen = HashSet::from([start]);
+        while let Some((url, dir, depth)) = pending.pop_front() {
+            logfile::info(&format!("Listing: {}", url));
+            let items = match fetch(client, &url, options) {
+                Ok(items) => items,
+                Err(err) if depth == 0 => return Err(err.into()),
+                Err(err) => {
+                    logfile::warning(&format!("Skipped: {}", err));
+                    continue;
+                }
+            };
+            for item in items {
+                let path = dir.join(&item.name);
+                if item.directory {
+                    if (level == 0 || depth + 1 < level) && seen.insert(item.url.clone()) {
+                        pending.push_back((item.url, path, depth + 1));
+                    }
+                } else if accepts(accept, &item.name) {
+                    expanded.push(Entry {
+                        url: item.url.into(),
+                        priority: entry.priority,
+                        output: Some(path),
+                    });
+                }
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_html() {
+        let base = Url::parse("https://example.com/pub/").unwrap();
+        // nginx
+        let nginx = "<html><head><title>Index of /pub/</title></head><body><pre>\
+            <a href=\"../\">../</a>\n\
+            <a href=\"docs/\">docs/</a>                                  31-Jan-2024 12:00       -\n\
+            <a href=\"release%201.tar.gz\">release 1.tar.gz</a>          31-Jan-2024 12:01    2048\n\
+            </pre></body></html>";
+        let items = parse_html(nginx, &base);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].url.as_str(), "https://example.com/pub/docs/");
+        assert!(items[0].directory && items[0].size.is_none());
+        assert_eq!(items[1].name, "release 1.tar.gz");
+        assert_eq!(items[1].size, Some(2048));
+        assert_eq!(items[1].modified.as_deref(), Some("31-Jan-2024 12:01"));
+
+        // Apache, with sorting links, icons, and a table
+        let apache = "<table><tr><th><a href=\"?C=N;O=D\">Name</a></th></tr>\
+            <tr><td><img src=\"/icons/back.gif\"></td><td><a href=\"/\">Parent Directory</a></td></tr>\n\
+            <tr><td><img src=\"/icons/text.gif\"></td><td><a href=\"notes.txt\">notes.txt</a></td>\
+            <td align=\"right\">2024-01-31 12:00  </td><td align=\"right\">1.5K</td></tr>\n";
+        let items = parse_html(apache, &base);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].name, "notes.txt");
+        assert_eq!(items[0].size, Some(1536));
+        assert_eq!(items[0].modified.as_deref(), Some("2024-01-31 12:00"));
+    }
+
+    #[test]
+    fn test_parse_ftp() {
+        let base = Url::parse("https://gateway.example/ftp/").unwrap();
+        let list = "total 12\n\
+            drwxr-xr-x    2 ftp      ftp          4096 Jan 31 12:00 docs\n\
+            -rw-r--r--    1 ftp      ftp          2048 Jan 31  2023 read me.txt\n\
+            lrwxrwxrwx    1 ftp      ftp             7 Jan 31 12:00 latest -> docs\n\
+            01-31-24  12:00PM       <DIR>          iis\n\
+            01-31-24  12:01PM                 1234 data.csv\n";
+        let items = parse_ftp(list, &base);
+        let names: Vec<_> = items.iter().map(|item| item.name.as_str()).collect();
+        assert_eq!(names, ["docs", "read me.txt", "iis", "data.csv"]);
+        assert!(items[0].directory && items[2].directory);
+        assert_eq!(
+            items[1].url.as_str(),
+            "https://gateway.example/ftp/read%20me.txt"
+        );
+        assert_eq!((items[1].size, items[3].size), (Some(2048), Some(1234)));
+        assert_eq!(items[1].modified.as_deref(), Some("Jan 31 2023"));
+    }
+
+    #[test]
+    fn test_accepts() {
+        let accept = [".iso".to_string(), "SHA*SUMS".to_string()];
+        assert!(accepts(&accept, "debian.iso"));
+        assert!(accepts(&accept, "SHA256SUMS"));
+        assert!(!accepts(&accept, "README"));
+        assert!(accepts(&[], "README"));
+    }
+
+    #[test]
+    fn test_expand_lists_subdirectories() {
+        let html = |path, body| {
+            mock("GET", path)
+                .with_header("content-type", "text/html")
+                .with_body(body)
+                .create()
+        };
+        let root = html(
+            "/listing/",
+            r#"<a href="a.iso">a.iso</a> <a href="sub/">sub/</a> <a href="skip.txt">skip.txt</a>"#,
+        );
+        let sub = html(
+            "/listing/sub/",
+            r#"<a href="b.iso">b.iso</a> <a href="deeper/">deeper/</a>"#,
+        );
+        let entries = vec![
+            Entry {
+                url: format!("{}/listing/", server_url()),
+                priority: 1,
+                output: None,
+            },
+            Entry {
+                url: format!("{}/file.txt", server_url()),
+                priority: 0,
+                output: None,
+            },
+        ];
+
+        let expanded = expand(
+            &Client::new(),
+            entries,
+            &[".iso".to_string()],
+            2,
+            &Options::default(),
+        )
+        .unwrap();
+        let got: Vec<_> = expanded
+            .iter()
+            .map(|entry| {
+                (
+                    entry.url.trim_start_matches(&server_url()),
+                    entry.output.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            got,
+            [
+                ("/listing/a.iso", Some(PathBuf::from("a.iso"))),
+                (
+                    "/listing/sub/b.iso",
+                    Some(PathBuf::from("sub").join("b.iso"))
+                ),
+                ("/file.txt", None),
+            ]
+        );
+        assert_eq!(expanded[0].priority, 1);
+        root.assert();
+        sub.assert();
+    }
+}
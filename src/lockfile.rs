@@ -0,0 +1,228 @@
+//! Pinning downloads in a lockfile.
+//!
+//! With `--lock`, the first download of a URL records the URL it was finally
+//! served from, its size, and its SHA-256 digest in `rustwget.lock` (or the
+//! file given with `--lock-file`). Later downloads of the URL are checked
+//! against that pin and fail if any of them drifted, as a checksum mismatch
+//! does, so that a build committing the lockfile gets the same bytes every
+//! time. A pin is never replaced; remove its entry from the lockfile to pin
+//! the URL again.
+//!
+//! Pins are looked up by the SHA-256 digest of the exact URL, so that URLs
+//! differing only in their secrets are pinned apart while the lockfile shows
+//! them [`redact`](crate::redact)ed. The final URL is compared with its
+//! secrets hidden, as the signatures of redirects to a CDN change each time.
+//!
+//! The lockfile is TOML, sorted by URL so that it diffs well:
+//!
+//! ```toml
+//! version = 1
+//!
+//! [[download]]
+//! url = "https://example.com/tool.tar.gz"
+//! key = "22aff12a4ac8c78e26af8ca593ff754fda066d8931a5eac58cf1680c2cad34af"
+//! final_url = "https://cdn.example.com/tool-1.2.tar.gz"
+//! size = 1048576
+//! sha256 = "5f70bf18a086007016e948b04aed3b82103a36bea41755b6cddfaf10ace3c6ef"
+//! ```
+
+use crate::ledger;
+use crate::redact;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// The lockfile used when none is given.
+pub const DEFAULT_PATH: &str = "rustwget.lock";
+
+/// The version of the lockfile format.
+const VERSION: u32 = 1;
+
+/// What a URL is pinned to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pin {
+    pub url: String,
+    /// The [`ledger::url_key`] of the URL.
+    pub key: String,
+    /// The URL the download was served from, after redirects.
+    pub final_url: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// The contents of a lockfile.
+#[derive(Debug, Serialize, Deserialize)]
+struct Contents {
+    version: u32,
+    #[serde(default, rename = "download")]
+    pins: Vec<Pin>,
+}
+
+/// A lockfile, shared by all transfers of a run.
+#[derive(Debug)]
+pub struct Lockfile {
+    path: PathBuf,
+    pins: Mutex<Vec<Pin>>,
+}
+
+impl Lockfile {
+    /// Opens the lockfile at `path`, starting empty if it does not exist yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or parsed.
+    pub fn open(path: PathBuf) -> Result<Lockfile, Box<dyn std::error::Error>> {
+        let pins = if path.exists() {
+            let contents: Contents = toml::from_str(&fs::read_to_string(&path)?)
+                .map_err(|err| format!("invalid lockfile {}: {}", path.display(), err))?;
+            if contents.version != VERSION {
+                return Err(format!(
+                    "unsupported lockfile version {} in {}",
+                    contents.version,
+                    path.display()
+                )
+                .into());
+            }
+            contents.pins
+        } else {
+            Vec::new()
+        };
+        Ok(Lockfile {
+            path,
+            pins: Mutex::new(pins),
+        })
+    }
+
+    /// Returns the pin of `url`, if it has one.
+    pub fn get(&self, url: &str) -> Option<Pin> {
+        let key = ledger::url_key(url);
+        self.pins
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|pin| pin.key == key)
+            .cloned()
+    }
+
+    /// Describes how a download of `url`, served from `final_url`, differs from
+    /// its pin, or returns `None` if it matches or `url` is not pinned yet.
+    ///
+    /// The final URL is only compared when `final_url` is known, which it is
+    /// not for downloads from mirrors.
+    pub fn drift(
+        &self,
+        url: &str,
+        final_url: Option<&str>,
+        size: u64,
+        sha256: &str,
+    ) -> Option<String> {
+        let pin = self.get(url)?;
+        let (what, expected, got) = match final_url.map(redact::hidden) {
+            Some(final_url) if final_url != redact::hidden(&pin.final_url) => {
+                ("final URL", pin.final_url, final_url.into_owned())
+            }
+            _ if size != pin.size => ("size", pin.size.to_string(), size.to_string()),
+            _ if sha256 != pin.sha256 => ("SHA-256", pin.sha256, sha256.to_string()),
+            _ => return None,
+        };
+        Some(format!(
+            "{} drifted from {}: expected {} {}, got {}",
+            redact::url(url),
+            self.path.display(),
+            what,
+            expected,
+            got
+        ))
+    }
+
+    /// Pins `url` to the `size` bytes with digest `sha256` downloaded from
+    /// `final_url`, unless it is pinned already, and saves the lockfile.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the lockfile cannot be written.
+    pub fn pin(
+        &self,
+        url: &str,
+        final_url: &str,
+        size: u64,
+        sha256: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut pins = self.pins.lock().unwrap_or_else(|e| e.into_inner());
+        let key = ledger::url_key(url);
+        if pins.iter().any(|pin| pin.key == key) {
+            return Ok(());
+        }
+        pins.push(Pin {
+            url: redact::url(url).into_owned(),
+            key,
+            final_url: redact::url(final_url).into_owned(),
+            size,
+            sha256: sha256.to_string(),
+        });
+        pins.sort_by(|a, b| (&a.url, &a.key).cmp(&(&b.url, &b.key)));
+
+        let contents = Contents {
+            version: VERSION,
+            pins: pins.clone(),
+        };
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, toml::to_string(&contents)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pin_and_drift() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rustwget.lock");
+        let digest = "5f70bf18a086007016e948b04aed3b82103a36bea41755b6cddfaf10ace3c6ef";
+        let cdn = "https://cdn.example.com/tool-1.2.tar.gz?sig=1";
+
+        let lockfile = Lockfile::open(path.clone()).unwrap();
+        let url = "https://example.com/tool.tar.gz";
+        assert_eq!(lockfile.drift(url, Some(url), 1, "other"), None);
+        lockfile.pin(url, cdn, 4, digest).unwrap();
+        // A pin is kept once made.
+        lockfile.pin(url, url, 5, "other").unwrap();
+
+        let reopened = Lockfile::open(path.clone()).unwrap();
+        let pin = reopened.get(url).unwrap();
+        assert_eq!(
+            pin.final_url,
+            "https://cdn.example.com/tool-1.2.tar.gz?sig=REDACTED"
+        );
+        assert_eq!((pin.size, pin.sha256.as_str()), (4, digest));
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("version = 1\n\n[[download]]\n"));
+
+        // Redirects are compared without their changing signatures.
+        let resigned = "https://cdn.example.com/tool-1.2.tar.gz?sig=2";
+        assert_eq!(reopened.drift(url, Some(resigned), 4, digest), None);
+        assert_eq!(reopened.drift(url, None, 4, digest), None);
+        let drift = reopened.drift(url, Some(url), 4, digest).unwrap();
+        assert!(drift.contains("expected final URL https://cdn.example.com/tool-1.2.tar.gz"));
+        let drift = reopened.drift(url, None, 4, "0000").unwrap();
+        assert!(drift.ends_with(&format!("expected SHA-256 {}, got 0000", digest)));
+
+        // URLs that differ only in their secrets are pinned apart.
+        let signed = "https://example.com/tool.tar.gz?key=a";
+        reopened.pin(signed, signed, 5, "other").unwrap();
+        assert_eq!(reopened.get(url).unwrap().size, 4);
+        assert_eq!(reopened.get(signed).unwrap().size, 5);
+        assert_eq!(reopened.get("https://example.com/tool.tar.gz?key=b"), None);
+
+        fs::write(&path, "version = 2\n").unwrap();
+        assert!(Lockfile::open(path).is_err());
+    }
+}
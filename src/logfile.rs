@@ -0,0 +1,176 @@
+//! Writing a log of the run to a file.
+//!
+//! With `-o FILE`, or `-a FILE` to append to an existing log, every message
+//! shown on the terminal is also written to `FILE` with a timestamp, together
+//! with details the terminal leaves out, such as the status of each response
+//! and every redirect. With `--log-max-size`, a log about to grow past that
+//! size is renamed to `FILE.1`, older ones move up to `FILE.5`, and a new log
+//! is started, so that long runs under cron do not fill the disk. Only the
+//! terminal sees [`color`]s. Credentials in the URLs of messages are
+//! [`redact`]ed from both. While progress [`bars`] are shown, messages are
+//! printed above them.
+
+use crate::bars;
+use crate::color::{self, Color, Stream};
+use crate::redact;
+use chrono::Local;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Number of rotated logs kept next to the current one.
+const KEEP: usize = 5;
+
+/// The log of the run, if one was requested.
+static LOG: Mutex<Option<LogFile>> = Mutex::new(None);
+
+/// Starts logging to `path` for the rest of the run.
+///
+/// # Arguments
+///
+/// * `path`: The log file.
+/// * `append`: Whether to add to an existing log instead of replacing it.
+/// * `max_size`: Size in bytes at which the log is rotated, if any.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened.
+pub fn open(path: &Path, append: bool, max_size: Option<u64>) -> io::Result<()> {
+    let log = LogFile::open(path, append, max_size)?;
+    *LOG.lock().unwrap_or_else(|e| e.into_inner()) = Some(log);
+    Ok(())
+}
+
+/// Prints `message` to standard output and writes it to the log.
+pub fn info(message: &str) {
+    let message = &redact::text(message);
+    bars::above(|| println!("{}", message));
+    detail(message);
+}
+
+/// Prints `message` to standard output in green and writes it to the log.
+pub fn success(message: &str) {
+    let message = &redact::text(message);
+    bars::above(|| println!("{}", color::paint(message, Color::Green, Stream::Stdout)));
+    detail(message);
+}
+
+/// Prints `message` to standard error and writes it to the log.
+pub fn notice(message: &str) {
+    let message = &redact::text(message);
+    bars::above(|| eprintln!("{}", message));
+    detail(message);
+}
+
+/// Prints `message` to standard error in yellow and writes it to the log.
+pub fn warning(message: &str) {
+    let message = &redact::text(message);
+    bars::above(|| eprintln!("{}", color::paint(message, Color::Yellow, Stream::Stderr)));
+    detail(message);
+}
+
+/// Prints `message` to standard error in red and writes it to the log.
+pub fn error(message: &str) {
+    let message = &redact::text(message);
+    bars::above(|| eprintln!("{}", color::paint(message, Color::Red, Stream::Stderr)));
+    detail(message);
+}
+
+/// Writes `message` to the log only.
+pub fn detail(message: &str) {
+    if let Some(log) = &mut *LOG.lock().unwrap_or_else(|e| e.into_inner()) {
+        // A log that cannot be written must not stop the downloads.
+        let _ = log.write(&redact::text(message));
+    }
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: Option<u64>,
+}
+
+impl LogFile {
+    fn open(path: &Path, append: bool, max_size: Option<u64>) -> io::Result<LogFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        Ok(LogFile {
+            path: path.to_path_buf(),
+            size: file.metadata()?.len(),
+            file,
+            max_size,
+        })
+    }
+
+    /// Writes each line of `message` with a timestamp, rotating the log first if it would grow too large.
+    fn write(&mut self, message: &str) -> io::Result<()> {
+        let time = Local::now().format("%Y-%m-%d %H:%M:%S");
+        let text: String = message
+            .lines()
+            .map(|line| format!("{} {}\n", time, line))
+            .collect();
+        if self
+            .max_size
+            .is_some_and(|max| self.size > 0 && self.size + text.len() as u64 > max)
+        {
+            self.rotate()?;
+        }
+        self.file.write_all(text.as_bytes())?;
+        self.size += text.len() as u64;
+        Ok(())
+    }
+
+    /// Moves `FILE.1`, `FILE.2`, ... up by one, dropping the oldest, and the log to `FILE.1`.
+    fn rotate(&mut self) -> io::Result<()> {
+        for number in (1..KEEP).rev() {
+            let _ = fs::rename(self.numbered(number), self.numbered(number + 1));
+        }
+        fs::rename(&self.path, self.numbered(1))?;
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn numbered(&self, number: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", number));
+        PathBuf::from(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_appends_and_rotates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rustwget.log");
+        fs::write(&path, "earlier run\n").unwrap();
+
+        let mut log = LogFile::open(&path, true, Some(100)).unwrap();
+        log.write("Downloading: https://example.com/a\nsecond line")
+            .unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("earlier run\n"));
+        assert!(text.ends_with(" second line\n"));
+        assert_eq!(text.lines().count(), 3);
+
+        for _ in 0..KEEP + 2 {
+            log.write("Downloaded: a").unwrap();
+            log.write("Downloaded: b").unwrap();
+        }
+        assert!(fs::metadata(&path).unwrap().len() <= 100);
+        assert!(log.numbered(KEEP).exists());
+        assert!(!log.numbered(KEEP + 1).exists());
+
+        let log = LogFile::open(&path, false, None).unwrap();
+        assert_eq!(log.size, 0);
+    }
+}
@@ -1,49 +1,1027 @@
-use clap::{App, Arg};
-use reqwest::blocking::Client;
-use std::fs::File;
-use std::io::Write;
-use url::Url;
+//! A simple wget-like CLI tool for downloading files from URLs.
+//!
+//! This program allows users to download files from specified URLs and optionally
+//! save them with custom filenames.
+//!
+//! # Usage
+//!
+//! ```
+//! rustwget [OPTIONS] <URL>...
+//! rustwget daemon [OPTIONS] [install|uninstall]
+//! rustwget proxy [--listen ADDR] [--allow HOST:PORT]... [--cache-dir DIR] [--max-age SECONDS]
+//!                [--cache-size SIZE] [-o LOGFILE]
+//! rustwget prefetch [--cache-dir DIR] [--cache-size SIZE] [--jobs N] [-o LOGFILE] <MANIFEST>
+//! rustwget bundle create <BUNDLE> [-i FILE] [-o LOGFILE] [URL]...
+//! rustwget bundle extract <BUNDLE> [-C DIR]
+//! rustwget retry-failed [REPORT]
+//! rustwget replay <SESSION>
+//! rustwget info [--json] <URL>
+//! rustwget check [--level N] [--no-parent] [-I LIST] [-X LIST] [--render] [--wait SECONDS] [--from EMAIL]
+//!               [--max-urls N] [--max-bytes SIZE] [--max-time SECONDS] [--state FILE] [--json] <URL>
+//! rustwget verify [--manifest FILE] [--revalidate] [--json]
+//! rustwget examples
+//! rustwget self-update [--check] [--endpoint URL]
+//! rustwget auth add|remove <HOST>
+//! rustwget auth login|logout <PROVIDER>
+//! ```
+//!
+//! # Arguments
+//!
+//! * `<URL>...`: The URL(s) of the file(s) to download (required); `[001-100]`
+//!   ranges and `{a,b}` alternations expand into several URLs; Google Drive, Dropbox, and
+//!   OneDrive share links are resolved to the shared file; `hf://ORG/REPO/PATH@REVISION` names a
+//!   file of a Hugging Face Hub repository, or with a path ending in `/` or none, a snapshot of
+//!   its files (see the `hub` module); `doi:10.5281/zenodo.1234567` stands for the files of a Zenodo,
+//!   Figshare, or OSF dataset, verified against their published digests (see the `doi` module);
+//!   `arxiv:ID`, `pmc:PMCID`, and `pubmed:PMID` stand for the PDF of a paper, saved as its ID and
+//!   fetched at the pace arXiv and NCBI ask for (see the `papers` module); `gemini://` URLs are
+//!   fetched over the Gemini protocol, trusting each capsule's certificate on first use (see the
+//!   `gemini` module); `magnet:` links, and with `--webseed` `.torrent` files, stand for the content
+//!   of a torrent, fetched from its HTTP web seeds and verified piece by piece (see the `torrent`
+//!   module); `smb://HOST/SHARE/PATH` names a file on a Windows or Samba share, read with the
+//!   credentials in the URL, of `--user`, or in the keyring (see the `smb` module)
+//!
+//! # Options
+//!
+//! * `--default-scheme <SCHEME>`: Scheme of URLs given without one, such as `example.com/file.txt`
+//!   (default `https`)
+//! * `-O, --output <FILE>`: Specify a custom filename for the downloaded file, or `-` for standard
+//!   output; `#1`, `#2`, ... stand for the values of the URL's patterns
+//! * `--template <URL> --vars <FILE>`: Download `URL` once per row of a CSV file, filling in its
+//!   `{column}` placeholders; `--vars-product` combines the columns instead
+//! * `-i, --input-file <FILE>`: Read URLs (and per-URL priorities) from a file
+//! * `--priority <LEVEL>`: Priority of the given URLs when several are queued
+//! * `-t, --tries <N>`: Number of attempts per download
+//! * `-j, --jobs <N>`: Number of downloads to run at the same time
+//! * `--max-per-host <N>`: Number of those downloads that may fetch from the same host
+//! * `--limit-rate <RATE>`: Limit the combined download rate
+//! * `--dns-timeout <SECONDS>`: Give up on host name lookups that take longer than this
+//! * `--dns-cache-timeout <SECONDS>`: Reuse each host's addresses for this long (default 60, `0`
+//!   to look hosts up for every connection)
+//! * `--dns-servers <ADDRESSES>`: Look host names up at these DNS servers, e.g. `1.1.1.1,9.9.9.9`,
+//!   instead of those the system is configured with
+//! * `--stall-timeout <SECONDS>`: Reconnect and resume a transfer that receives nothing for this
+//!   long (default 30)
+//! * `--verify-partial`: Keep the digest of every mebibyte written in `FILE.resume`, and before a
+//!   partial file is resumed, cut it back to the last part that still matches
+//! * `--speed-limit <RATE>`, `--speed-time <SECONDS>`: Abandon and retry a transfer that stays
+//!   slower than `RATE` for `SECONDS` (default 30)
+//! * `--bearer <TOKEN>`, `--token-file <PATH>`: Authenticate with a bearer token; the
+//!   `RUSTWGET_TOKEN` environment variable is used when neither is given
+//! * `--user <USER>`, `--password <PASS>`: Authenticate to the server; `--auth-type` selects
+//!   `basic`, `ntlm`, or `negotiate`
+//! * `--password-command <COMMAND>`: Take the password for `--user` from the output of a command
+//! * `--header-from-cmd <'NAME: COMMAND'>`: Send a header whose value a command prints
+//! * `--aws-sigv4 <PROVIDER1[:PROVIDER2[:REGION[:SERVICE]]]>`: Sign requests with AWS
+//!   Signature Version 4, using keys from the environment or `~/.aws/credentials`
+//! * `--proxy <URL>`: Send requests through a proxy, with `--proxy-user`, `--proxy-password`,
+//!   and `--proxy-auth-type` for its credentials
+//! * `--no-keyring`: Do not use credentials stored with `rustwget auth add` or `rustwget auth login`
+//! * `--service-account-dir <DIR>`: Read the Kubernetes service-account token, sent to in-cluster
+//!   services over HTTPS, and the cluster's certificate authorities from `DIR` instead of the one
+//!   mounted into pods; `--no-service-account` ignores them (see the `kubernetes` module)
+//! * `--ca-certificate <FILE>`: Also trust the certificate authorities in a PEM bundle
+//! * `--no-redact`: Show the credentials in URLs in messages and the log instead of `REDACTED`
+//! * `--mirror-list <FILE>`: Fall back to other base URLs serving the same content
+//! * `--probe-mirrors`: Try the fastest mirror first
+//! * `--wayback[=TIMESTAMP]`: Download the Wayback Machine's snapshot of each URL closest to
+//!   `TIMESTAMP` (`YYYY[MM[DD[hh[mm[ss]]]]]`), or without one, the latest snapshot of a URL that fails
+//! * `--stripe`: Fetch different byte ranges of a file from the mirrors concurrently, recording
+//!   those written in `FILE.rustwget` so that a later run fetches only the rest
+//! * `--checksum <sha256:HEX>`: Verify the downloaded file, moving on to the next mirror on mismatch
+//! * `--lock`: Pin the final URL, size, and SHA-256 digest of each URL in `rustwget.lock` when it is
+//!   first downloaded, and fail later downloads that drift from their pin; `--lock-file <FILE>`
+//!   uses another lockfile
+//! * `--decompress`: Decompress `.gz`, `.zst`, and `.xz` files on the fly, dropping the extension
+//! * `--split-output <SIZE>`: Write each download as numbered part files of at most `SIZE` bytes
+//! * `--print-hash <ALGOS>`: Print the `md5`, `sha1`, `sha256`, or `sha512` digests of each file;
+//!   `--hash-file` also writes them to `FILE.sha256`, ... for `sha256sum -c`
+//! * `--strip-query-params <LIST>`: Drop query parameters such as `utm_*,fbclid` from URLs before
+//!   they are fetched and named
+//! * `--canonicalize`: Sort the query parameters of URLs and drop their fragments, so that URLs
+//!   differing only in those are downloaded once
+//! * `--webseed`: Download the content of `.torrent` files and URLs from the torrents' HTTP web
+//!   seeds (BEP 19), verifying each piece against its hash, instead of the `.torrent` files
+//! * `--auto-extension`: Name files like `download?id=42` after their query (`download_42`) and
+//!   add an extension from their `Content-Type` or first bytes, e.g. `download_42.pdf`
+//! * `--content-disposition`: Rename files after the name in the server's `Content-Disposition`
+//! * `--trust-server-names`: Name files after the last URL of a redirect chain instead of the first
+//! * `--restrict-file-names <MODE>`: Which characters names chosen by servers may hold: `unix`,
+//!   `windows`, or `ascii`; such names never leave the target directory
+//! * `--file-allocation <METHOD>`: Reserve each file's size up front with `falloc` or `trunc`
+//! * `--force-overwrite`, `--skip-existing`: Replace or keep existing files without asking; on a
+//!   terminal, rustwget otherwise asks whether to overwrite, resume, rename, or skip each one
+//! * `--sync`: Flush each completed file and its directory to disk before reporting success
+//! * `--buffer-size <SIZE>`: Read and write in blocks of this size (default 64K)
+//! * `--o-direct`: Write files with direct I/O, bypassing the page cache (Linux only)
+//! * `--chmod <MODE>`, `--chown <USER[:GROUP]>`: Give each completed file an octal mode such as
+//!   `644` and, with the privileges to do so, another owner
+//! * `--no-use-server-timestamps`: Date files by when they were downloaded instead of by the
+//!   server's `Last-Modified` header
+//! * `--tmp-dir <DIR>`: Write downloads in `DIR` and move them into place once complete
+//! * `--scan-cmd <COMMAND>`: Move downloads into place only if a scanner such as
+//!   `clamscan {file}` accepts them in quarantine
+//! * `--umask <MASK>`: Mask removed from the modes of created files and directories, e.g. `002`
+//! * `--range <START-END>`: Download only part of each resource, e.g. `0-1023` or `1M-`
+//! * `--require-content-type <TYPE>`, `--reject-content-type <TYPE>`: Accept or refuse responses by
+//!   their `Content-Type`; `--strict` also refuses HTML pages served for URLs naming other files
+//! * `--lfs`: Fail the downloads of Git LFS pointer files whose object cannot be fetched; by
+//!   default, pointers served by the raw URLs of GitHub, GitLab, and Gitea repositories are replaced
+//!   with their object, and other pointers are kept with a warning (see the `lfs` module)
+//! * `--accept-mime <TYPE>`, `--reject-mime <TYPE>`: Skip rather than fail responses by their
+//!   `Content-Type`, e.g. the pages among the extensionless files of a recursive download
+//! * `--max-redirect <N>`: Follow at most `N` redirects per request; loops are reported as errors
+//! * `-v, --verbose`: Print each redirect as it is followed
+//! * `--config <FILE>`: Read settings such as bandwidth schedules from a TOML file
+//! * `--skip-existing-ledger`: Skip URLs whose earlier download is still intact on disk
+//! * `--if-changed`: Send the recorded ETag and `Last-Modified` time of intact files in the ledger,
+//!   and keep them when the server answers `304 Not Modified`
+//! * `--start-at <TIME>`: Defer the download until the given time
+//! * `--dry-run`: Print what would be downloaded and where, without transferring anything
+//! * `--notify`: Show a desktop notification (`notify-send`, Notification Center, or a Windows
+//!   toast) when the download or batch finishes or fails
+//! * `--notify-url <URL>`: POST the URL, file, size, SHA-256 digest, status, and duration of every
+//!   download, and the summary of the batch, to `URL` as JSON when they end
+//! * `--color <WHEN>`: Color status lines `auto` (on a terminal, unless `NO_COLOR` is set),
+//!   `always`, or `never`
+//! * `--si`, `--bytes`: Show sizes and rates in progress, summaries, and messages in powers of
+//!   1000, or as exact byte counts, instead of in powers of 1024
+//! * `-o, --output-file <LOGFILE>`: Also write all messages, timestamped and with details such as
+//!   response statuses and redirects, to `LOGFILE`; `-a, --append-output` appends to it instead
+//! * `--log-max-size <SIZE>`: Rotate the log file at `SIZE`, keeping five older logs
+//! * `--confirm-over <SIZE>`: Ask before downloading files larger than `SIZE`, or fail them
+//!   without a terminal
+//! * `--min-size <SIZE>`, `--max-size <SIZE>`: Skip files smaller or larger than `SIZE`, by their
+//!   `Content-Length` or, when it is missing, as they are downloaded
+//! * `-r, --recursive`: Download the files of URLs ending in `/` from the directory listings of the
+//!   server (Apache and nginx autoindex pages, FTP `LIST` text), and those of their subdirectories
+//! * `-l, --level <N>`: With `-r`, list subdirectories at most `N` deep (default 5, `0` for no limit)
+//! * `-A, --accept <LIST>`: With `-r`, only download files ending in one of these comma-separated
+//!   suffixes or matching one of these `*`/`?` patterns
+//! * `--include <PATTERN>`, `--exclude <PATTERN>`: Only download the files of `hf://` snapshots
+//!   whose path or name matches one of the `--include` patterns and none of the `--exclude` ones
+//! * `-k, --convert-links`: Once the downloads end, rewrite the links of the pages and stylesheets
+//!   saved to lead to the other files downloaded, and make their other relative links absolute
+//! * `--render`: Save HTML pages as a headless Chromium renders them once their scripts have run,
+//!   and then download the scripts, stylesheets, and images they load from their site
+//! * `--browser <PATH>`: With `--render`, the Chromium to use instead of the first found on the
+//!   `PATH`
+//! * `--dedupe-content <MODE>`: Replace files identical to one saved earlier in the run, as
+//!   pages reached through URLs with different tracking parameters, with a hard link to it
+//!   (`link`), or remove them (`skip`)
+//! * `--write-manifest <FILE>`: Once the downloads end, list every file saved with its SHA-256
+//!   digest and URL in `FILE`, as JSON if it ends in `.json` and for `sha256sum -c` otherwise
+//! * `--report <FILE>`: Write the summary printed after several downloads to `FILE` as JSON,
+//!   including the URLs that failed
+//! * `--record <FILE>`: Save the arguments, configuration, and resolved URLs of the run to `FILE`,
+//!   which `rustwget replay FILE` runs again as it was
+//! * `--benchmark <N>`: Download each URL `N` times without saving it and report the throughput
+//!   and the time spent resolving, connecting, waiting for the first byte, and transferring
+//! * `--tui`: Show an interactive dashboard of the transfers; without it, several downloads show
+//!   stacked progress bars that fit the terminal when standard error is one
+//!
+//! Status messages are shown in the language of `RUSTWGET_LANG` or the locale (`LANG`) when a
+//! translation for it is installed; see the `i18n` module.
+//!
+//! # Examples
+//!
+//! ```
+//! rustwget https://example.com/file.txt
+//! rustwget -O custom_name.txt https://example.com/file.txt
+//! rustwget -O 'photo_#1_#2.jpg' 'https://example.com/{2023,2024}/img[001-100].jpg'
+//! rustwget --template 'https://example.com/{ver}/{arch}.tar.gz' --vars vars.csv --vars-product
+//! rustwget --tui -j 2 https://example.com/a.iso https://example.com/b.iso
+//! rustwget --include '*.safetensors' --include '*.json' hf://Qwen/Qwen2.5-0.5B@main
+//! rustwget -O rainfall doi:10.5281/zenodo.1234567
+//! rustwget arxiv:2401.12345 pubmed:23193287
+//! rustwget --wayback=20150101 https://example.com/old-page.html
+//! rustwget gemini://geminiprotocol.net/docs/faq.gmi
+//! rustwget --webseed https://example.com/datasets/census-2020.torrent
+//! rustwget 'smb://CORP;alice@fileserver/builds/nightly/setup.exe'
+//! rustwget --lock -i third-party.txt
+//! rustwget daemon --listen 127.0.0.1:8750 --socket /tmp/rustwget.sock
+//! rustwget daemon --jobs 2 --dir ~/mirror install
+//! rustwget proxy --listen 0.0.0.0:3128 --allow '*.example.com:80' --allow '*.example.com:443' --cache-size 50G
+//! rustwget prefetch --jobs 8 toolchains.txt
+//! rustwget bundle create docs.bundle -i urls.txt
+//! rustwget bundle extract docs.bundle -C /srv/mirror
+//! ```
 
-/// A simple wget-like CLI tool for downloading files from URLs.
-///
-/// This program allows users to download files from specified URLs and optionally
-/// save them with custom filenames.
-///
-/// # Usage
-///
-/// ```
-/// rustwget [OPTIONS] <URL>
-/// ```
-///
-/// # Arguments
-///
-/// * `<URL>`: The URL of the file to download (required)
-///
-/// # Options
-///
-/// * `-O, --output <FILE>`: Specify a custom filename for the downloaded file
-///
-/// # Examples
-///
-/// ```
-/// rustwget https://example.com/file.txt
-/// rustwget -O custom_name.txt https://example.com/file.txt
-/// ```
+// The command needs threads, sockets, and a terminal. A WebAssembly build is
+// of the library alone, which downloads through the host's fetch instead.
+#![cfg_attr(target_family = "wasm", no_main)]
+#![cfg(not(target_family = "wasm"))]
+
+mod help;
+
+use chrono::Local;
+use clap::{App, AppSettings, Arg, ArgMatches, ErrorKind};
+use reqwest::blocking::{Client, ClientBuilder};
+use reqwest::Proxy;
+use rustwget::allocate::Allocation;
+use rustwget::auth::{Credentials, Keyring};
+use rustwget::digest::Algorithm;
+use rustwget::download::{Control, Options, Outcome};
+use rustwget::filename::Restriction;
+use rustwget::hosts::Hosts;
+use rustwget::kubernetes::ServiceAccount;
+use rustwget::ledger::Ledger;
+use rustwget::lockfile::Lockfile;
+use rustwget::overwrite::Decision;
+use rustwget::render::Browser;
+use rustwget::report::Report;
+use rustwget::secrets::Secrets;
+use rustwget::session::Session;
+use rustwget::throttle::MinSpeed;
+use rustwget::units::ByteRange;
+use rustwget::{
+    allocate, auth, batch, benchmark, bundle, canonical, check, color, config, content, convert,
+    crawl, daemon, dedupe, digest, dns, doi, download, filename, gemini, glob, hub, i18n, info,
+    input, kubernetes, ledger, listing, lockfile, logfile, manifest, mirror, nameserver, notify,
+    oversize, overwrite, papers, permissions, prefetch, proxy, redact, redirect, render, report,
+    scan, schedule, secrets, session, share, sigv4, sizes, template, torrent, units, update,
+    verify, wayback, webhook,
+};
+use std::collections::HashSet;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
 
 /// The main function that sets up the CLI and initiates the download process.
 ///
-/// # Returns
-///
-/// * `Result<(), Box<dyn std::error::Error>>`: Ok(()) if successful, or an error if something goes wrong.
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let matches = App::new("rustwget")
+/// Errors are printed to standard error, and the process then exits with status 1.
+fn main() {
+    if let Err(err) = run() {
+        logfile::error(&i18n::text("error", &[("error", &err)]));
+        notify::send("Download failed", &err.to_string());
+        process::exit(1);
+    }
+}
+
+/// Parses the command line and runs what it asks for.
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = match app().get_matches_safe() {
+        Ok(matches) => matches,
+        Err(err) if err.kind == ErrorKind::HelpDisplayed => {
+            print!("{}", help::sections(&err.message));
+            return Ok(());
+        }
+        Err(err) => err.exit(),
+    };
+    if matches.is_present("no-redact") {
+        redact::disable();
+    }
+
+    if matches.subcommand_matches("examples").is_some() {
+        print!("{}", help::EXAMPLES);
+        return Ok(());
+    }
+    if let Some(matches) = matches.subcommand_matches("auth") {
+        return auth::run(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("daemon") {
+        return daemon::run(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("proxy") {
+        return proxy::run(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("prefetch") {
+        return prefetch::run(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("bundle") {
+        return bundle::run(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("info") {
+        return info::run(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("check") {
+        return check::run(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("verify") {
+        return verify::run(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("self-update") {
+        return update::run(matches);
+    }
+    // A replay runs again with the arguments, configuration, and URLs of a recorded run.
+    let (matches, replay) = match matches.subcommand_matches("replay") {
+        Some(replay) => {
+            let path = std::path::absolute(replay.value_of("SESSION").unwrap())?;
+            let mut session = Session::read(&path)?;
+            session.ask_secrets()?;
+            std::env::set_current_dir(&session.directory).map_err(|err| {
+                format!(
+                    "cannot replay {} in {}: {}",
+                    path.display(),
+                    session.directory.display(),
+                    err
+                )
+            })?;
+            let arguments =
+                std::iter::once("rustwget".to_string()).chain(session.arguments.clone());
+            (app().get_matches_from_safe(arguments)?, Some(session))
+        }
+        None => (matches, None),
+    };
+    // A retry runs again with the arguments of the earlier run, but only for its failed URLs.
+    let (matches, retry) = match matches.subcommand_matches("retry-failed") {
+        Some(retry) => {
+            let path = retry
+                .value_of("REPORT")
+                .map_or_else(report::default_path, PathBuf::from);
+            let mut report = Report::read(&path)?;
+            if report.failures.is_empty() {
+                logfile::info(&format!(
+                    "No failed downloads to retry in {}",
+                    path.display()
+                ));
+                return Ok(());
+            }
+            session::ask_secrets(&mut report.arguments, "retry")?;
+            let arguments = std::iter::once("rustwget".to_string()).chain(report.arguments.clone());
+            (app().get_matches_from_safe(arguments)?, Some(report))
+        }
+        None => (matches, None),
+    };
+    let arguments: Vec<String> = match (&retry, &replay) {
+        (Some(report), _) => report.arguments.clone(),
+        (None, Some(session)) => session.arguments.clone(),
+        (None, None) => std::env::args_os()
+            .skip(1)
+            .map(|argument| argument.to_string_lossy().into_owned())
+            .collect(),
+    };
+    color::init(color::Choice::parse(matches.value_of("color").unwrap())?);
+    if matches.is_present("notify") {
+        notify::enable();
+    }
+    units::init(if matches.is_present("si") {
+        units::Style::Si
+    } else if matches.is_present("bytes") {
+        units::Style::Bytes
+    } else {
+        units::Style::Binary
+    });
+    if let Some(mask) = matches.value_of("umask") {
+        permissions::set_umask(permissions::parse_mode(mask, permissions::MAX_UMASK)?)?;
+    }
+    let log = match matches.value_of("append-output") {
+        Some(path) => Some((path, true)),
+        None => matches.value_of("output-file").map(|path| (path, false)),
+    };
+    if let Some((path, append)) = log {
+        let max_size = matches
+            .value_of("log-max-size")
+            .map(units::parse_size)
+            .transpose()?;
+        logfile::open(Path::new(path), append, max_size)?;
+    }
+
+    let priority = input::parse_priority(matches.value_of("priority").unwrap())?;
+    let output = matches.value_of("output");
+    let retrying = retry.is_some();
+    let mut entries = match retry {
+        Some(report) => report.entries(),
+        None => match &replay {
+            Some(session) => session.entries(),
+            None => entries(&matches, priority, output)?,
+        },
+    };
+    let scheme = matches.value_of("default-scheme").unwrap();
+    let webseed = matches.is_present("webseed");
+    for entry in entries
+        .iter_mut()
+        .filter(|entry| !entry.url.starts_with(doi::SCHEME) && !papers::is_shorthand(&entry.url))
+        .filter(|entry| !torrent::is_local(&entry.url, webseed))
+    {
+        entry.url = input::with_default_scheme(&entry.url, scheme);
+    }
+    let config = match &replay {
+        Some(session) => config::parse(session.config.as_deref().unwrap_or_default())
+            .map_err(|err| format!("invalid configuration in session: {}", err))?,
+        None => config::load(matches.value_of("config").map(Path::new))?,
+    };
+    let limit_rate = matches
+        .value_of("limit-rate")
+        .map(units::parse_rate)
+        .transpose()?
+        .flatten();
+    let speed_time = Duration::from_secs(matches.value_of("speed-time").unwrap().parse()?);
+    let min_speed = matches
+        .value_of("speed-limit")
+        .map(units::parse_rate)
+        .transpose()?
+        .flatten()
+        .map(|rate| MinSpeed {
+            rate,
+            window: speed_time,
+        });
+    if let (Some(min_speed), Some(limit)) = (min_speed, limit_rate) {
+        if limit < min_speed.rate {
+            return Err("--speed-limit must not exceed --limit-rate".into());
+        }
+    }
+    let ledger = if matches.is_present("skip-existing-ledger") || matches.is_present("if-changed") {
+        let path = matches
+            .value_of("ledger")
+            .map_or_else(ledger::default_path, PathBuf::from);
+        Some(Arc::new(Ledger::open(path)?))
+    } else {
+        None
+    };
+    let values = |name| {
+        matches
+            .values_of(name)
+            .map(|values| values.map(str::to_string).collect())
+            .unwrap_or_default()
+    };
+    let mut dashboard = matches.is_present("tui");
+    if dashboard && !io::stdout().is_terminal() {
+        logfile::warning("Warning: --tui needs a terminal; showing plain status lines instead");
+        dashboard = false;
+    }
+    let buffer_size = units::parse_size(matches.value_of("buffer-size").unwrap())?;
+    if buffer_size < 4096 {
+        return Err("--buffer-size must be at least 4K".into());
+    }
+    let stall_timeout: u64 = matches.value_of("stall-timeout").unwrap().parse()?;
+    if stall_timeout == 0 {
+        return Err("--stall-timeout must be at least 1 second".into());
+    }
+    let service_account = match matches.value_of("service-account-dir") {
+        Some(dir) => Some(ServiceAccount::open(Path::new(dir))?),
+        None if matches.is_present("no-service-account") => None,
+        None => ServiceAccount::detect(Path::new(kubernetes::DIRECTORY)),
+    };
+    let lockfile = match matches.value_of("lock-file") {
+        Some(path) => Some(Arc::new(Lockfile::open(PathBuf::from(path))?)),
+        None if matches.is_present("lock") => Some(Arc::new(Lockfile::open(PathBuf::from(
+            lockfile::DEFAULT_PATH,
+        ))?)),
+        None => None,
+    };
+    let mut options = Options {
+        tries: matches.value_of("tries").unwrap().parse()?,
+        throttle: config.throttle(limit_rate)?,
+        ledger,
+        if_changed: matches.is_present("if-changed"),
+        mirrors: matches
+            .value_of("mirror-list")
+            .map(|path| mirror::read(Path::new(path), matches.is_present("probe-mirrors")))
+            .transpose()?
+            .map(Arc::new),
+        wayback: matches
+            .is_present("wayback")
+            .then(|| wayback::Wayback::new(matches.value_of("wayback")))
+            .transpose()?
+            .map(Arc::new),
+        known_hosts: Arc::new(gemini::KnownHosts::open(gemini::known_hosts_path())),
+        stall_timeout: Duration::from_secs(stall_timeout),
+        checksum: matches
+            .value_of("checksum")
+            .map(ledger::parse_checksum)
+            .transpose()?,
+        lockfile,
+        stripe: matches.is_present("stripe"),
+        credentials: match (matches.value_of("aws-sigv4"), matches.value_of("user")) {
+            (Some(spec), _) => Some(Credentials::AwsSigV4(sigv4::Signer::new(
+                spec,
+                sigv4::keys()?,
+            )?)),
+            // The password is asked of the command when the first request is sent.
+            (None, Some(_)) if matches.is_present("password-command") => None,
+            (None, Some(user)) => Some(auth::user_credentials(
+                matches.value_of("auth-type").unwrap(),
+                user,
+                matches.value_of("password"),
+                "RUSTWGET_PASSWORD",
+            )?),
+            (None, None) => auth::bearer(
+                matches.value_of("bearer"),
+                matches.value_of("token-file").map(Path::new),
+            )?,
+        },
+        keyring: (!matches.is_present("no-keyring"))
+            .then(|| Arc::new(Keyring::new(config.oauth.clone()))),
+        service_account: service_account.map(Arc::new),
+        proxy_credentials: matches
+            .value_of("proxy-user")
+            .map(|user| {
+                auth::user_credentials(
+                    matches.value_of("proxy-auth-type").unwrap(),
+                    user,
+                    matches.value_of("proxy-password"),
+                    "RUSTWGET_PROXY_PASSWORD",
+                )
+            })
+            .transpose()?,
+        content: content::Policy {
+            require: values("require-content-type"),
+            reject: values("reject-content-type"),
+            strict: matches.is_present("strict"),
+            accept: values("accept-mime"),
+            skip: values("reject-mime"),
+        },
+        range: matches
+            .value_of("range")
+            .map(ByteRange::parse)
+            .transpose()?,
+        decompress: matches.is_present("decompress"),
+        verify_partial: matches.is_present("verify-partial"),
+        dedupe: matches
+            .value_of("dedupe-content")
+            .map(dedupe::Mode::parse)
+            .transpose()?
+            .map(|mode| Arc::new(dedupe::Registry::new(mode))),
+        overwrite: if matches.is_present("force-overwrite") {
+            overwrite::Policy::Overwrite
+        } else if matches.is_present("skip-existing") {
+            overwrite::Policy::Skip
+        } else {
+            overwrite::Policy::Ask
+        },
+        render: match matches.is_present("render") {
+            true => Some(Arc::new(Browser::find(
+                matches.value_of("browser").map(Path::new),
+            )?)),
+            false => None,
+        },
+        split_output: matches
+            .value_of("split-output")
+            .map(units::parse_size)
+            .transpose()?
+            .filter(|size| *size > 0),
+        hashes: values("print-hash")
+            .iter()
+            .map(|name| Algorithm::parse(name))
+            .collect::<Result<_, _>>()?,
+        hash_file: matches.is_present("hash-file"),
+        auto_extension: matches.is_present("auto-extension"),
+        content_disposition: matches.is_present("content-disposition"),
+        trust_server_names: matches.is_present("trust-server-names"),
+        file_allocation: Allocation::parse(matches.value_of("file-allocation").unwrap())?,
+        sync: matches.is_present("sync"),
+        buffer_size: usize::try_from(buffer_size)?,
+        direct_io: matches.is_present("o-direct"),
+        restrict_file_names: matches
+            .value_of("restrict-file-names")
+            .map(Restriction::parse)
+            .transpose()?
+            .unwrap_or_default(),
+        mode: matches
+            .value_of("chmod")
+            .map(|mode| permissions::parse_mode(mode, permissions::MAX_MODE))
+            .transpose()?,
+        owner: matches
+            .value_of("chown")
+            .map(permissions::parse_owner)
+            .transpose()?,
+        server_timestamps: !matches.is_present("no-use-server-timestamps"),
+        tmp_dir: matches
+            .value_of("tmp-dir")
+            .map(PathBuf::from)
+            .or_else(|| matches.is_present("scan-cmd").then(scan::default_dir)),
+        scan_command: matches.value_of("scan-cmd").map(str::to_string),
+        min_speed,
+        confirm_over: matches
+            .value_of("confirm-over")
+            .map(units::parse_size)
+            .transpose()?
+            .map(|size| oversize::Limit::new(size, dashboard)),
+        size_bounds: sizes::Bounds {
+            min: matches
+                .value_of("min-size")
+                .map(units::parse_size)
+                .transpose()?,
+            max: matches
+                .value_of("max-size")
+                .map(units::parse_size)
+                .transpose()?,
+        },
+        hosts: Arc::default(),
+        secrets: Arc::new(Secrets::new(
+            matches
+                .values_of("header-from-cmd")
+                .into_iter()
+                .flatten()
+                .map(secrets::parse_header)
+                .collect::<Result<_, _>>()?,
+            matches.value_of("password-command").map(|command| {
+                (
+                    matches.value_of("auth-type").unwrap(),
+                    matches.value_of("user").unwrap(),
+                    secrets::Command::new(command),
+                )
+            }),
+        )),
+        checksums: Arc::default(),
+        lfs: matches.is_present("lfs"),
+        transport: None,
+        webhook: matches
+            .value_of("notify-url")
+            .map(webhook::Webhook::new)
+            .transpose()?
+            .map(Arc::new),
+        tokens: Arc::default(),
+    };
+    if output == Some("-")
+        && (options.checksum.is_some() || options.lockfile.is_some() || dashboard)
+    {
+        return Err("-O - cannot be combined with --checksum, --lock, or --tui".into());
+    }
+    if options.split_output.is_some() && (options.checksum.is_some() || options.lockfile.is_some())
+    {
+        return Err("--split-output cannot be combined with --checksum or --lock".into());
+    }
+    if options.scan_command.is_some() && (output == Some("-") || options.split_output.is_some()) {
+        return Err("--scan-cmd cannot be combined with -O - or --split-output".into());
+    }
+    let jobs: usize = matches.value_of("jobs").unwrap().parse()?;
+    let per_host: usize = matches.value_of("max-per-host").unwrap().parse()?;
+
+    let max_redirect = matches
+        .value_of("max-redirect")
+        .map(str::parse)
+        .transpose()?
+        .unwrap_or(redirect::DEFAULT_MAX);
+    let seconds = |name| -> Result<Option<Duration>, Box<dyn std::error::Error>> {
+        Ok(matches
+            .value_of(name)
+            .map(str::parse)
+            .transpose()?
+            .map(Duration::from_secs))
+    };
+    let resolver = Arc::new(dns::Resolver::new(
+        seconds("dns-cache-timeout")?.unwrap_or(dns::DEFAULT_CACHE_TIMEOUT),
+        seconds("dns-timeout")?,
+        matches
+            .value_of("dns-servers")
+            .map(nameserver::parse_servers)
+            .transpose()?
+            .unwrap_or_default(),
+    ));
+    let mut authorities: Vec<PathBuf> = matches
+        .values_of("ca-certificate")
+        .into_iter()
+        .flatten()
+        .map(PathBuf::from)
+        .collect();
+    if let Some(account) = &options.service_account {
+        authorities.extend(account.authorities());
+    }
+    let certificates = kubernetes::certificates(&authorities)?;
+    let builder = || -> Result<ClientBuilder, Box<dyn std::error::Error>> {
+        let mut client =
+            ClientBuilder::from(reqwest::ClientBuilder::new().dns_resolver(Arc::clone(&resolver)))
+                .redirect(redirect::policy(
+                    max_redirect,
+                    matches.is_present("verbose"),
+                ))
+                .timeout(Duration::from_secs(stall_timeout));
+        for certificate in &certificates {
+            client = client.add_root_certificate(certificate.clone());
+        }
+        if let Some(proxy) = matches.value_of("proxy") {
+            let mut proxy = Proxy::all(proxy)?;
+            if let Some(Credentials::Basic { user, password }) = &options.proxy_credentials {
+                proxy = proxy.basic_auth(user, password);
+            }
+            client = client.proxy(proxy);
+        }
+        Ok(client)
+    };
+    let mut hosts = Hosts::new(&config.host, builder)?;
+    if let Some(token) = hub::token() {
+        hosts.authorize(&hub::endpoint()?, Credentials::Bearer(token));
+    }
+    for (url, interval) in papers::PACES {
+        hosts.pace(&Url::parse(url)?, interval);
+    }
+    options.hosts = Arc::new(hosts);
+    let mut client = builder()?;
+    let benchmark: Option<usize> = matches.value_of("benchmark").map(str::parse).transpose()?;
+    if benchmark.is_some() {
+        // Every run opens its own connection, as a first-time visitor would.
+        client = client.pool_max_idle_per_host(0);
+    }
+    let client = client.build()?;
+
+    // Snapshots and datasets are saved in directories of their own.
+    let nested = entries.iter().any(|entry| {
+        entry.url.starts_with(doi::SCHEME)
+            || hub::Location::parse(&entry.url).is_ok_and(|location| location.is_directory())
+    });
+    if entries
+        .iter()
+        .any(|entry| entry.url.starts_with(hub::SCHEME))
+    {
+        let filter = hub::Filter {
+            include: values("include"),
+            exclude: values("exclude"),
+        };
+        let (expanded, digests) = hub::expand(
+            &client,
+            entries,
+            &filter,
+            &hub::endpoint()?,
+            output.map(Path::new),
+            &options,
+        )?;
+        entries = expanded;
+        Arc::make_mut(&mut options.checksums).extend(digests);
+    }
+    if entries
+        .iter()
+        .any(|entry| entry.url.starts_with(doi::SCHEME))
+    {
+        let (expanded, checksums) = doi::expand(
+            &client,
+            entries,
+            &doi::Apis::default(),
+            output.map(Path::new),
+            &options,
+        )?;
+        entries = expanded;
+        Arc::make_mut(&mut options.checksums).extend(checksums);
+    }
+    if entries.iter().any(|entry| papers::is_shorthand(&entry.url)) {
+        entries = papers::expand(&client, entries, &papers::Apis::default(), output, &options)?;
+    }
+    if entries
+        .iter()
+        .any(|entry| torrent::is_torrent(&entry.url, webseed))
+    {
+        entries = torrent::download(
+            &client,
+            entries,
+            webseed,
+            output.map(Path::new),
+            matches.is_present("dry-run"),
+            &options,
+        )?;
+        if entries.is_empty() {
+            return Ok(());
+        }
+    }
+    if matches.is_present("recursive") {
+        let accept: Vec<String> = matches
+            .value_of("accept")
+            .map(|list| {
+                list.split(',')
+                    .map(|suffix| suffix.trim().to_string())
+                    .filter(|suffix| !suffix.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let level = matches
+            .value_of("level")
+            .map_or(Ok(crawl::DEFAULT_LEVEL), str::parse)?;
+        entries = listing::expand(&client, entries, &accept, level, &options)?;
+    }
+    let canonical = canonical::Rules {
+        strip: matches
+            .value_of("strip-query-params")
+            .map_or_else(Vec::new, canonical::Rules::patterns),
+        sort: matches.is_present("canonicalize"),
+    };
+    if canonical.is_active() {
+        for entry in &mut entries {
+            entry.url = canonical.apply(&Url::parse(&entry.url)?).into();
+        }
+        let mut seen = HashSet::new();
+        entries.retain(|entry| {
+            let first = seen.insert(entry.url.clone());
+            if !first {
+                logfile::info(&i18n::text("skipped.listed", &[("url", &entry.url)]));
+            }
+            first
+        });
+    }
+
+    // Replaying a session does not record it again.
+    if let Some(path) = matches.value_of("record").filter(|_| replay.is_none()) {
+        let config = config::read(matches.value_of("config").map(Path::new))?.map(|(_, text)| text);
+        Session::new(arguments.clone(), config, &entries)?.write(Path::new(path))?;
+    }
+
+    if output.is_some() && entries.len() > 1 {
+        let mut outputs: Vec<_> = entries.iter().map(|entry| entry.output.as_ref()).collect();
+        outputs.sort();
+        outputs.dedup();
+        if outputs.len() < entries.len() || outputs.contains(&None) {
+            return Err("--output cannot be used with more than one URL unless it names each one with #1, #2, ... or {name}".into());
+        }
+    }
+    let mut downloads = Vec::new();
+    let mut skipped = 0;
+    for entry in entries {
+        if let Some(Credentials::AwsSigV4(signer)) = &options.credentials {
+            signer.scope(Url::parse(&entry.url)?.host_str().unwrap_or_default())?;
+        }
+        let (url, filename) = match share::resolve(&entry.url) {
+            Some(link) => (link.url, link.filename),
+            None => {
+                let parsed = Url::parse(&entry.url)?;
+                let filename =
+                    filename::derive_filename(&parsed, options.auto_extension, options.decompress);
+                // The parsed form has internationalized host names in punycode.
+                (String::from(parsed), filename)
+            }
+        };
+        let output = match (entry.output, output) {
+            (Some(output), _) => filename::sanitize_path(&output, options.restrict_file_names),
+            (None, Some(output)) => PathBuf::from(output),
+            (None, None) => PathBuf::from(
+                filename::sanitize(&filename, options.restrict_file_names)
+                    .unwrap_or_else(|| "index.html".to_string()),
+            ),
+        };
+        if matches.is_present("skip-existing-ledger")
+            && options
+                .ledger
+                .as_ref()
+                .is_some_and(|ledger| ledger.is_current(&url, &output))
+        {
+            logfile::info(&i18n::text(
+                "skipped.unchanged",
+                &[("file", &output.display())],
+            ));
+            skipped += 1;
+            continue;
+        }
+        downloads.push(batch::Download {
+            url,
+            output,
+            priority: entry.priority,
+            resume: false,
+        });
+    }
+
+    let start = matches
+        .value_of("start-at")
+        .map(|start_at| schedule::parse_start_at(start_at, Local::now()))
+        .transpose()?;
+    if matches.is_present("dry-run") {
+        if let Some(start) = start {
+            logfile::info(&i18n::text(
+                "schedule.would-start",
+                &[("time", &start.format("%Y-%m-%d %H:%M"))],
+            ));
+        }
+        logfile::info(batch::plan(&downloads).trim_end());
+        return Ok(());
+    }
+    if !matches.is_present("benchmark") {
+        let mut kept = Vec::new();
+        for mut download in downloads {
+            if !download::is_stdout(&download.output) {
+                match overwrite::decide(&download.output, options.overwrite)? {
+                    Decision::Overwrite => {}
+                    Decision::Resume => download.resume = true,
+                    Decision::Rename(output) => download.output = output,
+                    Decision::Skip => {
+                        logfile::info(&i18n::text(
+                            "skipped.exists",
+                            &[("file", &download.output.display())],
+                        ));
+                        skipped += 1;
+                        continue;
+                    }
+                }
+            }
+            kept.push(download);
+        }
+        downloads = kept;
+    }
+    if matches.is_present("recursive") || nested {
+        for download in &downloads {
+            if let Some(dir) = download.output.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+        }
+    }
+    if let Some(start) = start {
+        logfile::info(&i18n::text(
+            "schedule.waiting",
+            &[("time", &start.format("%Y-%m-%d %H:%M"))],
+        ));
+        if let Ok(wait) = (start - Local::now()).to_std() {
+            thread::sleep(wait);
+        }
+    }
+
+    if let Some(runs) = benchmark {
+        for download in &downloads {
+            logfile::info(
+                benchmark::run(&client, &download.url, runs.max(1), &options)?.trim_end(),
+            );
+        }
+        return Ok(());
+    }
+
+    // A retry always saves a new report, so that it is not repeated once it succeeds.
+    if downloads.len() == 1 && !dashboard && !retrying {
+        let download = &downloads[0];
+        let output = download.output.to_string_lossy();
+        let started = Instant::now();
+        let mut attempt = 1;
+        loop {
+            let result = download_file(
+                &client,
+                &download.url,
+                Some(&output),
+                download.resume || attempt > 1,
+                &options,
+            );
+            match result {
+                // A failed transfer to standard output cannot be restarted.
+                Err(err) if attempt < options.tries && !download::is_stdout(&download.output) => {
+                    logfile::warning(&format!("Attempt {} failed: {}", attempt, err));
+                    attempt += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some(webhook) = &options.webhook {
+                let ended = result.as_ref().copied().map_err(|err| err.to_string());
+                webhook.download(&download.url, &download.output, &ended, started.elapsed());
+            }
+            match result {
+                Ok(Outcome::Completed) => {
+                    let mut saved = match download::is_stdout(&download.output) {
+                        true => Vec::new(),
+                        false => vec![(download.url.clone(), download.output.clone())],
+                    };
+                    if options.render.is_some() {
+                        download_requisites(&client, &mut saved, jobs, per_host, &options)?;
+                    }
+                    if matches.is_present("convert-links") {
+                        convert::convert(&saved);
+                    }
+                    if let Some(path) = matches.value_of("write-manifest") {
+                        manifest::write(Path::new(path), &saved)?;
+                    }
+                    notify::send("Download finished", &output);
+                    return Ok(());
+                }
+                Ok(_) => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    let mut report = batch::run(&client, downloads, jobs, per_host, &options, dashboard)?;
+    report.skipped += skipped;
+    report.arguments = arguments;
+    if options.render.is_some() {
+        download_requisites(&client, &mut report.completed, jobs, per_host, &options)?;
+    }
+    if matches.is_present("convert-links") {
+        convert::convert(&report.completed);
+    }
+    if let Some(path) = matches.value_of("write-manifest") {
+        let listed = manifest::write(Path::new(path), &report.completed)?;
+        logfile::detail(&format!("Listed {} files in {}", listed, path));
+    }
+    let summary = report.summary();
+    logfile::info(summary.trim_end());
+    if let Some(webhook) = &options.webhook {
+        webhook.post(&webhook::BatchEvent::new(&report));
+    }
+    notify::send(
+        match report.failed {
+            0 => "Downloads finished",
+            _ => "Downloads failed",
+        },
+        summary.lines().next().unwrap_or_default(),
+    );
+    if let Err(err) = report.write(&report::default_path()) {
+        logfile::warning(&format!("Cannot save the report for retry-failed: {}", err));
+    }
+    if let Some(path) = matches.value_of("report") {
+        report.write(Path::new(path))?;
+    }
+    Ok(report.check()?)
+}
+
+/// Builds the command-line interface.
+fn app() -> App<'static, 'static> {
+    App::new("rustwget")
         .version("1.0")
         .author("AskCodi")
         .about("A simple wget-like CLI tool")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::UnifiedHelpMessage)
+        .after_help("Run 'rustwget examples' for example invocations of common tasks.")
         .arg(
             Arg::with_name("URL")
-                .help("The URL to download")
-                .required(true)
+                .help("The URL(s) to download; [001-100] ranges and {a,b} alternations expand into several URLs")
+                .required_unless_one(&["input-file", "template"])
+                .multiple(true)
                 .index(1),
         )
         .arg(
@@ -51,17 +1029,855 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .short("O")
                 .long("output")
                 .value_name("FILE")
-                .help("Write documents to FILE")
+                .help("Write documents to FILE, or to standard output if FILE is -; #1, #2, ... are replaced by the values of the URL's patterns, {name} by --vars values")
                 .takes_value(true),
         )
-        .get_matches();
-
-    let url = matches.value_of("URL").unwrap();
-    let output = matches.value_of("output");
+        .arg(
+            Arg::with_name("default-scheme")
+                .long("default-scheme")
+                .value_name("SCHEME")
+                .help("Scheme of URLs given without one, such as example.com/file.txt")
+                .possible_values(&["https", "http"])
+                .default_value("https")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("template")
+                .long("template")
+                .value_name("URL")
+                .help("Download URL once per set of --vars values, replacing its {name} placeholders")
+                .requires("vars")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("vars")
+                .long("vars")
+                .value_name("FILE")
+                .help("CSV file whose header names the --template variables, one download per row")
+                .requires("template")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("vars-product")
+                .long("vars-product")
+                .help("Download every combination of the --vars columns instead of one URL per row")
+                .requires("template"),
+        )
+        .arg(
+            Arg::with_name("input-file")
+                .short("i")
+                .long("input-file")
+                .value_name("FILE")
+                .help("Read URLs from FILE ('-' for stdin), one per line, optionally followed by priority=LEVEL")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("priority")
+                .long("priority")
+                .value_name("LEVEL")
+                .help("Priority of the given URLs: low, normal, high, critical, or an integer")
+                .default_value("normal")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tries")
+                .short("t")
+                .long("tries")
+                .value_name("N")
+                .help("Number of attempts per download")
+                .default_value("1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .value_name("N")
+                .help("Number of downloads to run at the same time")
+                .default_value("4")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-per-host")
+                .long("max-per-host")
+                .value_name("N")
+                .help("Number of those downloads that may fetch from the same host at once")
+                .default_value("4")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("limit-rate")
+                .long("limit-rate")
+                .value_name("RATE")
+                .help("Limit the combined download rate, e.g. 500k or 2M")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dns-timeout")
+                .long("dns-timeout")
+                .value_name("SECONDS")
+                .help("Give up on host name lookups that take longer than this")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dns-cache-timeout")
+                .long("dns-cache-timeout")
+                .value_name("SECONDS")
+                .help("How long the addresses of a host are reused, 0 to look it up for every connection")
+                .default_value("60")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dns-servers")
+                .long("dns-servers")
+                .value_name("ADDRESSES")
+                .help("Look host names up at these DNS servers instead of the system's, e.g. 1.1.1.1,[2606:4700::1111]:53")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("stall-timeout")
+                .long("stall-timeout")
+                .value_name("SECONDS")
+                .help("Reconnect and resume a transfer that receives no data for this long")
+                .default_value("30")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("verify-partial")
+                .long("verify-partial")
+                .help("Record digests of partial files in FILE.resume and check the part already downloaded against them before resuming"),
+        )
+        .arg(
+            Arg::with_name("speed-limit")
+                .long("speed-limit")
+                .value_name("RATE")
+                .help("Abandon and retry a transfer that stays slower than RATE for --speed-time, e.g. 10k")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("speed-time")
+                .long("speed-time")
+                .value_name("SECONDS")
+                .help("How long a transfer may stay below --speed-limit")
+                .default_value("30")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("bearer")
+                .long("bearer")
+                .value_name("TOKEN")
+                .help("Send 'Authorization: Bearer TOKEN'; prefer --token-file or $RUSTWGET_TOKEN, which stay out of process listings")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("token-file")
+                .long("token-file")
+                .value_name("PATH")
+                .help("Read the bearer token from PATH")
+                .conflicts_with("bearer")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("user")
+                .long("user")
+                .value_name("USER")
+                .help("User name for the server, as DOMAIN\\USER for NTLM")
+                .conflicts_with_all(&["bearer", "token-file"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("password")
+                .long("password")
+                .value_name("PASS")
+                .help("Password for --user [default: $RUSTWGET_PASSWORD, or prompt]")
+                .requires("user")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("password-command")
+                .long("password-command")
+                .value_name("COMMAND")
+                .help("Take the password for --user from what COMMAND prints, e.g. 'pass show example.com'")
+                .requires("user")
+                .conflicts_with("password")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("header-from-cmd")
+                .long("header-from-cmd")
+                .value_name("NAME: COMMAND")
+                .help("Send the header NAME with what COMMAND prints as its value; may be repeated")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("auth-type")
+                .long("auth-type")
+                .value_name("TYPE")
+                .help("How --user and --password are sent")
+                .possible_values(&auth::AUTH_TYPES)
+                .default_value("basic")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("aws-sigv4")
+                .long("aws-sigv4")
+                .value_name("PROVIDER1[:PROVIDER2[:REGION[:SERVICE]]]")
+                .help("Sign requests with AWS Signature Version 4, e.g. aws:amz:eu-west-1:s3; keys come from $AWS_ACCESS_KEY_ID and $AWS_SECRET_ACCESS_KEY or ~/.aws/credentials")
+                .conflicts_with_all(&["bearer", "token-file", "user"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("proxy")
+                .long("proxy")
+                .value_name("URL")
+                .help("Send all requests through the proxy at URL")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("proxy-user")
+                .long("proxy-user")
+                .value_name("USER")
+                .help("User name for the proxy")
+                .requires("proxy")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("proxy-password")
+                .long("proxy-password")
+                .value_name("PASS")
+                .help("Password for --proxy-user [default: $RUSTWGET_PROXY_PASSWORD, or prompt]")
+                .requires("proxy-user")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("proxy-auth-type")
+                .long("proxy-auth-type")
+                .value_name("TYPE")
+                .help("How --proxy-user is sent; ntlm and negotiate only work for http:// URLs")
+                .possible_values(&auth::AUTH_TYPES)
+                .default_value("basic")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mirror-list")
+                .long("mirror-list")
+                .value_name("FILE")
+                .help("Fall back to the base URLs listed in FILE when a download fails")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("probe-mirrors")
+                .long("probe-mirrors")
+                .help("Try the mirrors in order of their measured latency")
+                .requires("mirror-list"),
+        )
+        .arg(
+            Arg::with_name("stripe")
+                .long("stripe")
+                .help("Download different parts of each file from different mirrors at the same time")
+                .requires("mirror-list"),
+        )
+        .arg(
+            Arg::with_name("wayback")
+                .long("wayback")
+                .value_name("TIMESTAMP")
+                .help("Download the Wayback Machine's snapshot closest to TIMESTAMP (YYYY[MM[DD[hh[mm[ss]]]]]) instead of each URL; without one, fall back to the latest snapshot of a URL that fails")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true),
+        )
+        .arg(
+            Arg::with_name("checksum")
+                .long("checksum")
+                .value_name("sha256:HEX")
+                .help("Expected SHA-256 digest; a mismatch fails the download or moves on to the next mirror")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("lock")
+                .long("lock")
+                .help("Pin the final URL, size, and SHA-256 digest of each URL in rustwget.lock on its first download, and fail later downloads that drift from it"),
+        )
+        .arg(
+            Arg::with_name("lock-file")
+                .long("lock-file")
+                .value_name("FILE")
+                .help("Lockfile used by --lock, which it implies [default: rustwget.lock]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("print-hash")
+                .long("print-hash")
+                .value_name("ALGOS")
+                .help("Print these digests of each downloaded file, computed while it is written, e.g. sha256,md5")
+                .possible_values(&digest::ALGORITHMS)
+                .multiple(true)
+                .number_of_values(1)
+                .use_delimiter(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("hash-file")
+                .long("hash-file")
+                .help("Also write the --print-hash digests to FILE.sha256, FILE.md5, ...")
+                .requires("print-hash"),
+        )
+        .arg(
+            Arg::with_name("strip-query-params")
+                .long("strip-query-params")
+                .value_name("LIST")
+                .help("Drop the query parameters with these comma-separated names, where * and ? are wildcards, e.g. utm_*,fbclid")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("canonicalize")
+                .long("canonicalize")
+                .help("Sort the query parameters of URLs and drop their fragments, downloading variants of one URL once"),
+        )
+        .arg(
+            Arg::with_name("webseed")
+                .long("webseed")
+                .help("Download the content of .torrent files and URLs from their HTTP web seeds, verifying every piece, instead of the files themselves"),
+        )
+        .arg(
+            Arg::with_name("auto-extension")
+                .long("auto-extension")
+                .help("Give files saved from URLs without an extension, such as download?id=42, one from their Content-Type or first bytes: download_42.pdf")
+                .conflicts_with("output"),
+        )
+        .arg(
+            Arg::with_name("content-disposition")
+                .long("content-disposition")
+                .help("Rename each file after the name the server suggests in its Content-Disposition header")
+                .conflicts_with("output"),
+        )
+        .arg(
+            Arg::with_name("trust-server-names")
+                .long("trust-server-names")
+                .help("Name each file after the URL it was finally redirected to, such as the target of a short link")
+                .conflicts_with("output"),
+        )
+        .arg(
+            Arg::with_name("restrict-file-names")
+                .long("restrict-file-names")
+                .value_name("MODE")
+                .help("Characters allowed in names taken from URLs and servers: unix, windows (reserved names and \\:*?\"<>| too), or ascii [default: the platform's]")
+                .possible_values(&filename::RESTRICTIONS)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("range")
+                .long("range")
+                .value_name("START-END")
+                .help("Download only bytes START to END (inclusive) of each resource, e.g. 0-1023 or 1M-2M; END may be omitted")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("decompress")
+                .long("decompress")
+                .help("Decompress .gz, .zst, and .xz files while downloading, saving data.csv.gz as data.csv")
+                .conflicts_with_all(&["range", "stripe"]),
+        )
+        .arg(
+            Arg::with_name("split-output")
+                .long("split-output")
+                .value_name("SIZE")
+                .help("Write each download as FILE.part000, FILE.part001, ... of at most SIZE bytes, e.g. 4G")
+                .conflicts_with("stripe")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("file-allocation")
+                .long("file-allocation")
+                .value_name("METHOD")
+                .help("Reserve the full size of each file before writing it: falloc reserves disk blocks and fails early when space runs out, trunc only sets the length")
+                .possible_values(&allocate::ALLOCATIONS)
+                .default_value("none")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("force-overwrite")
+                .long("force-overwrite")
+                .help("Replace existing files without asking"),
+        )
+        .arg(
+            Arg::with_name("skip-existing")
+                .long("skip-existing")
+                .help("Skip URLs whose file already exists instead of asking")
+                .conflicts_with("force-overwrite"),
+        )
+        .arg(
+            Arg::with_name("sync")
+                .long("sync")
+                .help("Flush each file and its directory to disk before reporting it downloaded, so that a crash cannot leave a corrupt file behind"),
+        )
+        .arg(
+            Arg::with_name("buffer-size")
+                .long("buffer-size")
+                .value_name("SIZE")
+                .help("Size of the blocks data is read and written in, at least 4K, e.g. 1M")
+                .default_value("64K")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("o-direct")
+                .long("o-direct")
+                .help("Write files with direct I/O, bypassing the page cache, for very fast storage (Linux only)"),
+        )
+        .arg(
+            Arg::with_name("chmod")
+                .long("chmod")
+                .value_name("MODE")
+                .help("Give each downloaded file this octal mode, e.g. 644")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("chown")
+                .long("chown")
+                .value_name("USER[:GROUP]")
+                .help("Give each downloaded file this owner and group, which usually needs root")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-use-server-timestamps")
+                .long("no-use-server-timestamps")
+                .help("Give files the time they were downloaded instead of the server's Last-Modified time"),
+        )
+        .arg(
+            Arg::with_name("tmp-dir")
+                .long("tmp-dir")
+                .value_name("DIR")
+                .help("Write downloads in DIR, which may be on another file system, and move each into place once complete")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("scan-cmd")
+                .long("scan-cmd")
+                .value_name("COMMAND")
+                .help("Scan each download in quarantine with COMMAND, e.g. 'clamscan {file}', and only move it into place if COMMAND exits with status 0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("umask")
+                .long("umask")
+                .value_name("MASK")
+                .help("Octal mask removed from the modes of created files and directories, e.g. 002")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("require-content-type")
+                .long("require-content-type")
+                .value_name("TYPE")
+                .help("Only save responses of these types, e.g. application/zip,image/*")
+                .multiple(true)
+                .number_of_values(1)
+                .use_delimiter(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("reject-content-type")
+                .long("reject-content-type")
+                .value_name("TYPE")
+                .help("Refuse responses of these types, e.g. text/html")
+                .multiple(true)
+                .number_of_values(1)
+                .use_delimiter(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("strict")
+                .long("strict")
+                .help("Fail instead of warning when a URL naming a file returns an HTML page"),
+        )
+        .arg(
+            Arg::with_name("lfs")
+                .long("lfs")
+                .help("Fail downloads of Git LFS pointers whose object cannot be fetched, instead of keeping the pointer"),
+        )
+        .arg(
+            Arg::with_name("accept-mime")
+                .long("accept-mime")
+                .value_name("TYPE")
+                .help("Skip responses that are not of these types, e.g. image/*,application/pdf, before reading their body")
+                .multiple(true)
+                .number_of_values(1)
+                .use_delimiter(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("reject-mime")
+                .long("reject-mime")
+                .value_name("TYPE")
+                .help("Skip responses of these types, e.g. text/html, before reading their body")
+                .multiple(true)
+                .number_of_values(1)
+                .use_delimiter(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-redirect")
+                .long("max-redirect")
+                .value_name("N")
+                .help("Follow at most N redirects per request, or none with 0 [default: 20]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .help("Print each redirect as it is followed"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Read settings such as [[bandwidth]] schedules from FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("skip-existing-ledger")
+                .long("skip-existing-ledger")
+                .help("Skip URLs already downloaded to the same, unchanged file according to the ledger"),
+        )
+        .arg(
+            Arg::with_name("if-changed")
+                .long("if-changed")
+                .help("Download files recorded in the ledger again only if the server answers a request conditional on their ETag and Last-Modified time with new content")
+                .conflicts_with("skip-existing-ledger"),
+        )
+        .arg(
+            Arg::with_name("ledger")
+                .long("ledger")
+                .value_name("FILE")
+                .help("Ledger used by --skip-existing-ledger and --if-changed [default: <data dir>/ledger.json]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("start-at")
+                .long("start-at")
+                .value_name("TIME")
+                .help("Wait until TIME (HH:MM, YYYY-MM-DD HH:MM, or RFC 3339) before downloading")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("notify")
+                .long("notify")
+                .help("Show a desktop notification when the download or batch finishes or fails"),
+        )
+        .arg(
+            Arg::with_name("notify-url")
+                .long("notify-url")
+                .value_name("URL")
+                .help("POST a JSON description of every download, and of the batch, to URL when it ends")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Print what would be downloaded and where, without transferring anything"),
+        )
+        .arg(
+            Arg::with_name("color")
+                .long("color")
+                .value_name("WHEN")
+                .help("Color status lines: auto colors them on a terminal unless NO_COLOR is set")
+                .possible_values(&color::CHOICES)
+                .default_value("auto")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("si")
+                .long("si")
+                .help("Show sizes and rates in powers of 1000 (kB, MB) instead of 1024 (KiB, MiB)"),
+        )
+        .arg(
+            Arg::with_name("bytes")
+                .long("bytes")
+                .help("Show sizes and rates as exact byte counts, for scripts that parse them")
+                .conflicts_with("si"),
+        )
+        .arg(
+            Arg::with_name("output-file")
+                .short("o")
+                .long("output-file")
+                .value_name("LOGFILE")
+                .help("Also write all messages, with details the terminal leaves out, to LOGFILE")
+                .conflicts_with("append-output")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("append-output")
+                .short("a")
+                .long("append-output")
+                .value_name("LOGFILE")
+                .help("Like --output-file, but append to LOGFILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-max-size")
+                .long("log-max-size")
+                .value_name("SIZE")
+                .help("Rotate the log file when it would grow past SIZE, keeping LOGFILE.1 to LOGFILE.5, e.g. 10M")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("confirm-over")
+                .long("confirm-over")
+                .value_name("SIZE")
+                .help("Ask before downloading a file whose Content-Length is over SIZE, e.g. 2G; without a terminal, fail it")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("min-size")
+                .long("min-size")
+                .value_name("SIZE")
+                .help("Skip files smaller than SIZE, e.g. 10k, by their Content-Length or once they are downloaded")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-size")
+                .long("max-size")
+                .value_name("SIZE")
+                .help("Skip files larger than SIZE, e.g. 500M, by their Content-Length or as soon as they grow past it")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("recursive")
+                .long("recursive")
+                .short("r")
+                .help("Download the files of URLs ending in / from the directory listings of the server, and those of their subdirectories")
+                .conflicts_with_all(&["output", "benchmark"]),
+        )
+        .arg(
+            Arg::with_name("level")
+                .long("level")
+                .short("l")
+                .value_name("N")
+                .help("List subdirectories at most N deep, 0 for no limit [default: 5]")
+                .requires("recursive")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("accept")
+                .long("accept")
+                .short("A")
+                .value_name("LIST")
+                .help("Only download the files of listings that end in one of these comma-separated suffixes or match one of these patterns, such as .iso,SHA*SUMS")
+                .requires("recursive")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("include")
+                .long("include")
+                .value_name("PATTERN")
+                .help("Only download the files of hf:// snapshots whose path or name matches PATTERN, such as *.safetensors; may be repeated")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .value_name("PATTERN")
+                .help("Skip the files of hf:// snapshots whose path or name matches PATTERN; may be repeated")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("convert-links")
+                .long("convert-links")
+                .short("k")
+                .help("Once the downloads end, point the links of the pages saved to the files downloaded, and make their other links absolute")
+                .conflicts_with_all(&["auto-extension", "content-disposition", "trust-server-names", "benchmark"]),
+        )
+        .arg(
+            Arg::with_name("render")
+                .long("render")
+                .help("Save HTML pages as rendered by a headless Chromium once their scripts have run, then download their scripts, styles, and images")
+                .conflicts_with("benchmark"),
+        )
+        .arg(
+            Arg::with_name("browser")
+                .long("browser")
+                .value_name("PATH")
+                .help("Chromium to render pages with [default: chromium, google-chrome, ... on the PATH]")
+                .requires("render")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("write-manifest")
+                .long("write-manifest")
+                .value_name("FILE")
+                .help("Once the downloads end, list every file saved with its SHA-256 digest and URL in FILE (JSON if it ends in .json, else for sha256sum -c)")
+                .conflicts_with("benchmark")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dedupe-content")
+                .long("dedupe-content")
+                .value_name("MODE")
+                .help("Hard-link (link) or remove (skip) files whose content equals that of a file saved earlier in the run")
+                .possible_values(&dedupe::MODES)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .value_name("FILE")
+                .help("Save the options, configuration, and resolved URLs of the run to FILE, for rustwget replay")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("report")
+                .long("report")
+                .value_name("FILE")
+                .help("Write a JSON summary of the run to FILE, listing the URLs that failed")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("benchmark")
+                .long("benchmark")
+                .value_name("N")
+                .help("Download each URL N times without saving it, then report min/avg/p95 throughput and where the time went")
+                .conflicts_with_all(&["output", "dry-run", "tui"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tui")
+                .long("tui")
+                .help("Show an interactive dashboard of the transfers"),
+        )
+        .arg(
+            Arg::with_name("no-keyring")
+                .long("no-keyring")
+                .help("Do not look up credentials stored with 'rustwget auth add' or 'rustwget auth login'"),
+        )
+        .arg(
+            Arg::with_name("service-account-dir")
+                .long("service-account-dir")
+                .value_name("DIR")
+                .help("Read the Kubernetes service-account token and certificate authorities from DIR [default: /var/run/secrets/kubernetes.io/serviceaccount, if mounted]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-service-account")
+                .long("no-service-account")
+                .help("Do not send the Kubernetes service-account token to in-cluster services or trust its certificate authorities")
+                .conflicts_with("service-account-dir"),
+        )
+        .arg(
+            Arg::with_name("ca-certificate")
+                .long("ca-certificate")
+                .value_name("FILE")
+                .help("Also trust the certificate authorities in the PEM bundle FILE; may be repeated")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-redact")
+                .long("no-redact")
+                .help("Show user names, passwords, and tokens in URLs instead of hiding them in messages and the log"),
+        )
+        .subcommand(auth::subcommand())
+        .subcommand(daemon::subcommand())
+        .subcommand(proxy::subcommand())
+        .subcommand(prefetch::subcommand())
+        .subcommand(bundle::subcommand())
+        .subcommand(report::subcommand())
+        .subcommand(session::subcommand())
+        .subcommand(info::subcommand())
+        .subcommand(check::subcommand())
+        .subcommand(verify::subcommand())
+        .subcommand(update::subcommand())
+        .subcommand(help::subcommand())
+}
 
-    let client = Client::new();
+/// Collects the URLs given on the command line, by `--template`, and in `--input-file`.
+fn entries(
+    matches: &ArgMatches,
+    priority: i32,
+    output: Option<&str>,
+) -> Result<Vec<input::Entry>, Box<dyn std::error::Error>> {
+    let mut entries = Vec::new();
+    for url in matches.values_of("URL").into_iter().flatten() {
+        for (url, output) in glob::expand_glob(url, output)? {
+            entries.push(input::Entry {
+                url,
+                priority,
+                output,
+            });
+        }
+    }
+    if let Some(url) = matches.value_of("template") {
+        let rows = template::read_vars(
+            Path::new(matches.value_of("vars").unwrap()),
+            matches.is_present("vars-product"),
+        )?;
+        for vars in rows {
+            entries.push(input::Entry {
+                url: template::render(url, &vars)?,
+                priority,
+                output: output
+                    .map(|output| template::render(output, &vars).map(PathBuf::from))
+                    .transpose()?,
+            });
+        }
+    }
+    if let Some(path) = matches.value_of("input-file") {
+        entries.extend(input::read(path, priority)?);
+    }
+    Ok(entries)
+}
 
-    download_file(&client, url, output)
+/// Downloads the requisites of the rendered pages among `saved`, the URLs
+/// downloaded with the files they were saved to (see [`render::downloads`]),
+/// and adds those saved to it. Requisites that fail are reported, and do not
+/// fail the run.
+fn download_requisites(
+    client: &Client,
+    saved: &mut Vec<(String, PathBuf)>,
+    jobs: usize,
+    per_host: usize,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let requisites = render::downloads(saved, options.restrict_file_names);
+    if requisites.is_empty() {
+        return Ok(());
+    }
+    logfile::info(&format!(
+        "Downloading {} requisites of the rendered pages",
+        requisites.len()
+    ));
+    let mut downloads = Vec::new();
+    for (url, output) in requisites {
+        if let Some(dir) = output.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        downloads.push(batch::Download {
+            url,
+            output,
+            priority: 0,
+            resume: false,
+        });
+    }
+    // Requisites are saved as served, even those that are pages.
+    let options = Options {
+        render: None,
+        ..options.clone()
+    };
+    let report = batch::run(client, downloads, jobs, per_host, &options, false)?;
+    if report.failed > 0 {
+        logfile::warning(&format!(
+            "{} requisites of the rendered pages could not be downloaded",
+            report.failed
+        ));
+    }
+    saved.extend(report.completed);
+    Ok(())
 }
 
 /// Downloads a file from the specified URL and saves it to the local filesystem.
@@ -71,10 +1887,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 /// * `client`: A reference to the HTTP client used for making requests.
 /// * `url`: The URL of the file to download.
 /// * `output`: An optional custom filename for the downloaded file.
+/// * `resume`: Whether to continue a partial file left by an earlier attempt.
+/// * `options`: Settings of the run, such as the bandwidth limit.
 ///
 /// # Returns
 ///
-/// * `Result<(), Box<dyn std::error::Error>>`: Ok(()) if the download is successful, or an error if something goes wrong.
+/// * `Result<Outcome, Box<dyn std::error::Error>>`: How the download ended, such as skipped for its size, or an error if something goes wrong.
 ///
 /// # Errors
 ///
@@ -83,30 +1901,42 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 /// * If the server returns a non-success status code
 /// * If there's an issue creating or writing to the output file
 /// * If the URL parsing fails
-fn download_file(client: &Client, url: &str, output: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Downloading: {}", url);
-
-    let response = client.get(url).send()?;
-
-    if !response.status().is_success() {
-        return Err(format!("Failed to download: HTTP {}", response.status()).into());
+fn download_file(
+    client: &Client,
+    url: &str,
+    output: Option<&str>,
+    resume: bool,
+    options: &Options,
+) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let filename = match output {
+        Some(output) => output.to_string(),
+        None => download::default_filename(&Url::parse(url)?),
+    };
+    // With `-O -` the document itself goes to standard output.
+    let stdout = download::is_stdout(Path::new(&filename));
+    if stdout {
+        logfile::notice(&format!("Downloading: {}", url));
+    } else {
+        logfile::info(&format!("Downloading: {}", url));
+    }
+    let outcome = download::fetch(
+        client,
+        url,
+        Path::new(&filename),
+        resume,
+        &Control::default(),
+        options,
+    )?;
+    if outcome != Outcome::Completed {
+        return Ok(outcome);
+    }
+    if stdout {
+        logfile::notice(&i18n::text("downloaded", &[("file", &filename)]));
+    } else {
+        logfile::success(&i18n::text("downloaded", &[("file", &filename)]));
     }
 
-    let url = Url::parse(url)?;
-    
-    let filename = output.unwrap_or_else(|| {
-        url.path_segments()
-            .and_then(|segments| segments.last())
-            .unwrap_or("index.html")
-    });
-
-    let mut file = File::create(filename)?;
-    let content = response.bytes()?;
-    file.write_all(&content)?;
-
-    println!("Downloaded: {}", filename);
-
-    Ok(())
+    Ok(outcome)
 }
 
 /// Test module for the download functionality.
@@ -118,6 +1948,7 @@ fn download_file(client: &Client, url: &str, output: Option<&str>) -> Result<(),
 mod tests {
     use super::*;
     use mockito::{mock, server_url};
+    use std::fs::File;
     use std::io::Read;
     use tempfile::NamedTempFile;
 
@@ -134,7 +1965,7 @@ mod tests {
         let output_path = temp_file.path().to_str().unwrap();
 
         let client = Client::new();
-        let result = download_file(&client, &url, Some(output_path));
+        let result = download_file(&client, &url, Some(output_path), false, &Options::default());
 
         assert!(result.is_ok());
 
@@ -146,30 +1977,44 @@ mod tests {
         mock.assert();
     }
 
+    #[test]
+    fn test_help_sections_cover_every_option() {
+        let err = app()
+            .get_matches_from_safe(["rustwget", "--help"])
+            .unwrap_err();
+        let help = help::sections(&err.message);
+        assert!(help.contains("OUTPUT OPTIONS:"));
+        assert!(!help.contains("OTHER OPTIONS:"), "{}", help);
+    }
+
     #[test]
     fn test_invalid_url() {
         let client = Client::new();
         let invalid_url = "not_a_valid_url";
 
-        let result = download_file(&client, invalid_url, None);
+        let result = download_file(&client, invalid_url, None, false, &Options::default());
 
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("relative URL without a base"));
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("relative URL without a base"));
     }
 
     #[test]
     fn test_http_error() {
-        let mock = mock("GET", "/not_found")
-            .with_status(404)
-            .create();
+        let mock = mock("GET", "/not_found").with_status(404).create();
 
         let url = format!("{}/not_found", server_url());
         let client = Client::new();
 
-        let result = download_file(&client, &url, None);
+        let result = download_file(&client, &url, None, false, &Options::default());
 
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("Failed to download: HTTP 404"));
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Failed to download: HTTP 404"));
         mock.assert();
     }
 
@@ -186,7 +2031,13 @@ mod tests {
         let custom_filename = temp_file.path().to_str().unwrap();
 
         let client = Client::new();
-        let result = download_file(&client, &url, Some(custom_filename));
+        let result = download_file(
+            &client,
+            &url,
+            Some(custom_filename),
+            false,
+            &Options::default(),
+        );
 
         assert!(result.is_ok());
 
@@ -211,7 +2062,7 @@ mod tests {
         std::env::set_current_dir(&temp_dir).unwrap();
 
         let client = Client::new();
-        let result = download_file(&client, &url, None);
+        let result = download_file(&client, &url, None, false, &Options::default());
 
         assert!(result.is_ok());
 
@@ -0,0 +1,140 @@
+//! A checksum manifest of the files downloaded in a run.
+//!
+//! `--write-manifest FILE` lists, once the downloads (and any
+//! `--convert-links`) end, every file the run saved with its SHA-256 digest
+//! and the URL it came from. A `FILE` ending in `.json` gets a JSON array of
+//! entries; any other gets the format of `sha256sum`, each line preceded by a
+//! `# URL` comment, so that `sha256sum -c FILE` verifies the set. Paths are
+//! relative to the directory of `FILE` when the files are below it.
+
+use crate::ledger;
+use crate::redact;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A file listed in a manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Entry {
+    /// Relative to the directory of the manifest when below it.
+    pub path: PathBuf,
+    pub url: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Describes `downloaded`, the URLs a run saved with the files it saved them
+/// to, for a manifest in the directory `dir`.
+///
+/// # Errors
+///
+/// Returns an error if a file cannot be read.
+pub fn entries(downloaded: &[(String, PathBuf)], dir: &Path) -> io::Result<Vec<Entry>> {
+    let dir = std::path::absolute(dir)?;
+    downloaded
+        .iter()
+        .map(|(url, path)| {
+            let absolute = std::path::absolute(path)?;
+            Ok(Entry {
+                path: absolute
+                    .strip_prefix(&dir)
+                    .map_or_else(|_| absolute.clone(), Path::to_path_buf),
+                url: redact::url(url).into_owned(),
+                size: fs::metadata(&absolute)?.len(),
+                sha256: ledger::sha256_file(&absolute)?,
+            })
+        })
+        .collect()
+}
+
+/// Formats `entries` as `sha256sum` lines, each after a `# URL` comment.
+pub fn format(entries: &[Entry]) -> String {
+    entries
+        .iter()
+        .map(|entry| {
+            format!(
+                "# {}\n{}  {}\n",
+                entry.url,
+                entry.sha256,
+                entry.path.display()
+            )
+        })
+        .collect()
+}
+
+/// Writes the manifest of `downloaded` to `path`, returning how many files it lists.
+///
+/// # Errors
+///
+/// Returns an error if a file cannot be read or the manifest cannot be written.
+pub fn write(
+    path: &Path,
+    downloaded: &[(String, PathBuf)],
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let entries = entries(downloaded, dir)
+        .map_err(|err| format!("cannot write manifest {}: {}", path.display(), err))?;
+    let json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let text = match json {
+        true => serde_json::to_string_pretty(&entries)? + "\n",
+        false => format(&entries),
+    };
+    fs::write(path, text)
+        .map_err(|err| format!("cannot write manifest {}: {}", path.display(), err))?;
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub").join("a.txt"), "abc").unwrap();
+        fs::write(outside.path().join("b.txt"), "").unwrap();
+        let downloaded = [
+            (
+                "https://example.com/a.txt".to_string(),
+                dir.path().join("sub").join("a.txt"),
+            ),
+            (
+                "https://example.com/b.txt".to_string(),
+                outside.path().join("b.txt"),
+            ),
+        ];
+
+        let text = dir.path().join("manifest.sha256");
+        assert_eq!(write(&text, &downloaded).unwrap(), 2);
+        assert_eq!(
+            fs::read_to_string(&text).unwrap(),
+            format!(
+                "# https://example.com/a.txt\n\
+                 ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  {}\n\
+                 # https://example.com/b.txt\n\
+                 e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  {}\n",
+                Path::new("sub").join("a.txt").display(),
+                outside.path().join("b.txt").display()
+            )
+        );
+
+        let json = dir.path().join("manifest.json");
+        write(&json, &downloaded[..1]).unwrap();
+        let parsed: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&json).unwrap()).unwrap();
+        assert_eq!(parsed[0]["url"], "https://example.com/a.txt");
+        assert_eq!(parsed[0]["size"], 3);
+        assert_eq!(
+            parsed[0]["path"],
+            Path::new("sub").join("a.txt").to_str().unwrap()
+        );
+    }
+}
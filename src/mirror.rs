@@ -0,0 +1,209 @@
+//! Failover between mirrors that serve the same content.
+//!
+//! `--mirror-list FILE` names a file of base URLs, one per line, with `#`
+//! comments:
+//!
+//! ```text
+//! https://mirror-a.example.org/debian/
+//! https://mirror-b.example.net/pub/debian/
+//! ```
+//!
+//! A URL that starts with one of the bases is available under the same
+//! relative path from every other base; any other URL is looked up under its
+//! own path. The original URL is tried first, then each mirror in turn, so a
+//! failed transfer or a checksum mismatch moves on to the next mirror. With
+//! `--probe-mirrors` the candidates are ordered by the latency of a `HEAD`
+//! request instead, and mirrors that do not answer are tried last.
+
+use crate::download::Options;
+use reqwest::blocking::Client;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// How long a latency probe may take before the mirror counts as unreachable.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Base URLs that serve the same content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorList {
+    bases: Vec<Url>,
+    probe: bool,
+}
+
+impl MirrorList {
+    /// Creates a mirror list.
+    ///
+    /// # Arguments
+    ///
+    /// * `bases`: The base URLs; a trailing slash is added where missing.
+    /// * `probe`: Whether to order candidates by measured latency.
+    pub fn new(bases: Vec<Url>, probe: bool) -> MirrorList {
+        let bases = bases
+            .into_iter()
+            .map(|mut base| {
+                if !base.path().ends_with('/') {
+                    base.set_path(&format!("{}/", base.path()));
+                }
+                base
+            })
+            .collect();
+        MirrorList { bases, probe }
+    }
+
+    /// Returns every URL under which `url` can be fetched, original first.
+    pub fn candidates(&self, url: &str) -> Vec<String> {
+        let relative = match self
+            .bases
+            .iter()
+            .find_map(|base| url.strip_prefix(base.as_str()))
+        {
+            Some(relative) => relative.to_string(),
+            None => match Url::parse(url) {
+                Ok(parsed) => {
+                    let path = parsed.path().trim_start_matches('/').to_string();
+                    match parsed.query() {
+                        Some(query) => format!("{}?{}", path, query),
+                        None => path,
+                    }
+                }
+                Err(_) => return vec![url.to_string()],
+            },
+        };
+
+        let mut candidates = vec![url.to_string()];
+        for base in &self.bases {
+            if let Ok(candidate) = base.join(&relative) {
+                if !candidates.contains(&candidate.to_string()) {
+                    candidates.push(candidate.to_string());
+                }
+            }
+        }
+        candidates
+    }
+
+    /// Returns the candidates for `url` in the order they should be tried.
+    ///
+    /// Without probing this is [`MirrorList::candidates`]; with probing each
+    /// candidate is sent a `HEAD` request and the fastest answers come first.
+    pub fn ordered(&self, client: &Client, url: &str, options: &Options) -> Vec<String> {
+        let candidates = self.candidates(url);
+        if !self.probe {
+            return candidates;
+        }
+        let mut timed: Vec<(Option<Duration>, String)> = candidates
+            .into_iter()
+            .map(|candidate| (latency(client, &candidate, options), candidate))
+            .collect();
+        timed.sort_by_key(|(latency, _)| latency.unwrap_or(Duration::MAX));
+        timed.into_iter().map(|(_, candidate)| candidate).collect()
+    }
+}
+
+/// Measures how long a `HEAD` request takes, or `None` if it fails.
+fn latency(client: &Client, url: &str, options: &Options) -> Option<Duration> {
+    let start = Instant::now();
+    options
+        .send(client, url, |client| {
+            client.head(url).timeout(PROBE_TIMEOUT)
+        })
+        .ok()
+        .filter(|response| response.status().is_success())
+        .map(|_| start.elapsed())
+}
+
+/// Parses a mirror list.
+///
+/// # Errors
+///
+/// Returns an error naming the offending line for anything that is not an absolute URL.
+pub fn parse(text: &str, probe: bool) -> Result<MirrorList, String> {
+    let mut bases = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        bases.push(
+            Url::parse(line).map_err(|err| {
+                format!("line {}: invalid mirror '{}': {}", number + 1, line, err)
+            })?,
+        );
+    }
+    Ok(MirrorList::new(bases, probe))
+}
+
+/// Reads and parses a mirror list file.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or contains an invalid URL.
+pub fn read(path: &Path, probe: bool) -> Result<MirrorList, Box<dyn std::error::Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| format!("cannot read {}: {}", path.display(), err))?;
+    parse(&text, probe).map_err(|err| format!("{}: {}", path.display(), err).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+
+    #[test]
+    fn test_candidates() {
+        let mirrors = parse(
+            "# mirrors\nhttps://a.example/debian\n\nhttps://b.example/pub/debian/\n",
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            mirrors.candidates("https://a.example/debian/pool/x.deb"),
+            vec![
+                "https://a.example/debian/pool/x.deb",
+                "https://b.example/pub/debian/pool/x.deb",
+            ]
+        );
+        assert_eq!(
+            mirrors.candidates("https://origin.example/pool/x.deb?v=2"),
+            vec![
+                "https://origin.example/pool/x.deb?v=2",
+                "https://a.example/debian/pool/x.deb?v=2",
+                "https://b.example/pub/debian/pool/x.deb?v=2",
+            ]
+        );
+        assert_eq!(
+            parse("ftp-mirror\n", false).unwrap_err(),
+            "line 1: invalid mirror 'ftp-mirror': relative URL without a base"
+        );
+    }
+
+    #[test]
+    fn test_probing_puts_unreachable_mirrors_last() {
+        let alive = mock("HEAD", "/mirror-probe/alive/file.bin")
+            .with_status(200)
+            .create();
+        let dead = mock("HEAD", "/mirror-probe/dead/file.bin")
+            .with_status(503)
+            .create();
+
+        let base = |name: &str| Url::parse(&format!("{}/mirror-probe/{}/", server_url(), name));
+        let mirrors = MirrorList::new(vec![base("dead").unwrap(), base("alive").unwrap()], true);
+        let ordered = mirrors.ordered(
+            &Client::new(),
+            &format!("{}/mirror-probe/dead/file.bin", server_url()),
+            &Options::default(),
+        );
+
+        assert_eq!(
+            ordered,
+            vec![
+                format!("{}/mirror-probe/alive/file.bin", server_url()),
+                format!("{}/mirror-probe/dead/file.bin", server_url()),
+            ]
+        );
+        alive.assert();
+        dead.assert();
+    }
+}
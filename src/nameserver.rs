@@ -0,0 +1,331 @@
+//! Asking chosen DNS servers directly.
+//!
+//! With `--dns-servers`, host names are looked up by asking those servers for
+//! their IPv4 and IPv6 addresses, in turn until one answers, instead of going
+//! through the system's resolver configuration. Queries are sent over UDP and
+//! repeated over TCP when the answer does not fit in a datagram. Unlike the
+//! system resolver, the answers come with TTLs, which bound how long they are
+//! cached.
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+/// Port DNS servers listen on unless another is given.
+pub const PORT: u16 = 53;
+
+/// How long a server may take to answer when `--dns-timeout` is not given.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Record type of an IPv4 address.
+const A: u16 = 1;
+/// Record type of an IPv6 address.
+const AAAA: u16 = 28;
+/// Class of Internet records.
+const IN: u16 = 1;
+
+/// The addresses of a host and how long they may be used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answer {
+    pub addresses: Vec<IpAddr>,
+    /// The shortest TTL of the address records.
+    pub ttl: Duration,
+}
+
+/// Parses a comma-separated list of servers, such as `1.1.1.1,[2606:4700::1111]:53`.
+pub fn parse_servers(text: &str) -> Result<Vec<SocketAddr>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|server| !server.is_empty())
+        .map(|server| {
+            server
+                .parse::<SocketAddr>()
+                .or_else(|_| server.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, PORT)))
+                .map_err(|_| format!("invalid DNS server '{}': expected an IP address", server))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|servers| match servers.is_empty() {
+            true => Err("no DNS servers given".to_string()),
+            false => Ok(servers),
+        })
+}
+
+/// Looks up the IPv4 and IPv6 addresses of `host`.
+///
+/// The servers are asked in order; the first that answers decides, and the
+/// next is only tried when one fails or does not answer within `timeout`.
+///
+/// # Errors
+///
+/// Returns an error if no server answers, or if the host has no addresses.
+pub fn lookup(servers: &[SocketAddr], host: &str, timeout: Duration) -> io::Result<Answer> {
+    let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "no DNS servers given");
+    for server in servers {
+        let mut answered = false;
+        let mut addresses = Vec::new();
+        let mut ttl = Duration::MAX;
+        for record_type in [A, AAAA] {
+            match query(*server, host, record_type, timeout) {
+                Ok(answer) => {
+                    answered = true;
+                    if !answer.addresses.is_empty() {
+                        ttl = ttl.min(answer.ttl);
+                    }
+                    addresses.extend(answer.addresses);
+                }
+                Err(err) => last_error = err,
+            }
+        }
+        if !answered {
+            continue;
+        }
+        if addresses.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no addresses at {}", host, server),
+            ));
+        }
+        return Ok(Answer { addresses, ttl });
+    }
+    Err(last_error)
+}
+
+/// Asks `server` for the records of `record_type` of `host`.
+fn query(
+    server: SocketAddr,
+    host: &str,
+    record_type: u16,
+    timeout: Duration,
+) -> io::Result<Answer> {
+    let mut id = [0; 2];
+    // A predictable id only makes forged replies easier to match.
+    let _ = getrandom::getrandom(&mut id);
+    let id = u16::from_be_bytes(id);
+    let message = encode(id, host, record_type)?;
+
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.connect(server)?;
+    socket.send(&message)?;
+    let mut buffer = [0; 1232];
+    let len = socket.recv(&mut buffer)?;
+    match decode(id, &buffer[..len])? {
+        Response::Truncated => {
+            let mut stream = TcpStream::connect_timeout(&server, timeout)?;
+            stream.set_read_timeout(Some(timeout))?;
+            stream.write_all(&(message.len() as u16).to_be_bytes())?;
+            stream.write_all(&message)?;
+            let mut len = [0; 2];
+            stream.read_exact(&mut len)?;
+            let mut reply = vec![0; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut reply)?;
+            match decode(id, &reply)? {
+                Response::Answer(answer) => Ok(answer),
+                Response::Truncated => Err(invalid("truncated answer over TCP")),
+            }
+        }
+        Response::Answer(answer) => Ok(answer),
+    }
+}
+
+/// A decoded reply.
+#[derive(Debug, PartialEq, Eq)]
+enum Response {
+    Answer(Answer),
+    /// The answer did not fit and must be asked for over TCP.
+    Truncated,
+}
+
+/// Encodes a recursive query for the `record_type` records of `host`.
+fn encode(id: u16, host: &str, record_type: u16) -> io::Result<Vec<u8>> {
+    let mut message = Vec::with_capacity(host.len() + 18);
+    message.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question.
+    message.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid host name '{}'", host),
+            ));
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&record_type.to_be_bytes());
+    message.extend_from_slice(&IN.to_be_bytes());
+    Ok(message)
+}
+
+/// Decodes the reply to the query `id`, keeping its address records.
+///
+/// A name that does not exist gives an answer without addresses.
+fn decode(id: u16, message: &[u8]) -> io::Result<Response> {
+    let mut reader = Reader {
+        message,
+        position: 0,
+    };
+    let header = reader.take(12)?;
+    if u16::from_be_bytes([header[0], header[1]]) != id || header[2] & 0x80 == 0 {
+        return Err(invalid("reply does not match the query"));
+    }
+    if header[2] & 0x02 != 0 {
+        return Ok(Response::Truncated);
+    }
+    match header[3] & 0x0f {
+        // No error, or no such name.
+        0 | 3 => {}
+        code => return Err(invalid(&format!("server failed with code {}", code))),
+    }
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let answers = u16::from_be_bytes([header[6], header[7]]);
+
+    for _ in 0..questions {
+        reader.skip_name()?;
+        reader.take(4)?;
+    }
+    let mut addresses = Vec::new();
+    let mut ttl = u32::MAX;
+    for _ in 0..answers {
+        reader.skip_name()?;
+        let fields = reader.take(10)?;
+        let record_type = u16::from_be_bytes([fields[0], fields[1]]);
+        let record_ttl = u32::from_be_bytes([fields[4], fields[5], fields[6], fields[7]]);
+        let data = reader.take(u16::from_be_bytes([fields[8], fields[9]]) as usize)?;
+        let address = match (record_type, data.len()) {
+            (A, 4) => IpAddr::from(<[u8; 4]>::try_from(data).unwrap()),
+            (AAAA, 16) => IpAddr::from(<[u8; 16]>::try_from(data).unwrap()),
+            // Aliases lead to the address records that follow them.
+            _ => continue,
+        };
+        addresses.push(address);
+        ttl = ttl.min(record_ttl);
+    }
+    let ttl = match addresses.is_empty() {
+        true => Duration::ZERO,
+        false => Duration::from_secs(ttl.into()),
+    };
+    Ok(Response::Answer(Answer { addresses, ttl }))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid DNS reply: {}", message),
+    )
+}
+
+/// Reads the fields of a DNS message in order.
+struct Reader<'a> {
+    message: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let field = self
+            .message
+            .get(self.position..self.position + len)
+            .ok_or_else(|| invalid("message too short"))?;
+        self.position += len;
+        Ok(field)
+    }
+
+    /// Skips a name, which ends in an empty label or in a pointer to a name elsewhere.
+    fn skip_name(&mut self) -> io::Result<()> {
+        loop {
+            match self.take(1)?[0] {
+                0 => return Ok(()),
+                len if len & 0xc0 == 0xc0 => {
+                    self.take(1)?;
+                    return Ok(());
+                }
+                len => {
+                    self.take(len as usize)?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reply to `query` with an alias and one address of each kind.
+    fn reply(query: &[u8]) -> Vec<u8> {
+        let mut reply = query.to_vec();
+        reply[2] |= 0x80;
+        reply[7] = 3;
+        // The alias `cdn.example.com`, pointing back at `example.com` in the question.
+        reply.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 1, 0, 0, 6, 3]);
+        reply.extend_from_slice(b"cdn\xc0\x0c");
+        reply.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 7]);
+        reply.extend_from_slice(&[0xc0, 12, 0, 28, 0, 1, 0, 0, 0, 30, 0, 16]);
+        reply.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        reply
+    }
+
+    #[test]
+    fn test_encode_and_decode() {
+        let query = encode(0x1234, "example.com.", A).unwrap();
+        assert_eq!(&query[..4], &[0x12, 0x34, 0x01, 0x00]);
+        assert_eq!(&query[12..], b"\x07example\x03com\x00\x00\x01\x00\x01");
+        assert!(encode(1, "bad..name", A).is_err());
+
+        let answer = Answer {
+            addresses: vec!["192.0.2.7".parse().unwrap(), "2001:db8::1".parse().unwrap()],
+            ttl: Duration::from_secs(30),
+        };
+        assert_eq!(
+            decode(0x1234, &reply(&query)).unwrap(),
+            Response::Answer(answer)
+        );
+        assert!(decode(0x4321, &reply(&query)).is_err());
+        assert!(decode(0x1234, &reply(&query)[..40]).is_err());
+
+        let mut truncated = reply(&query);
+        truncated[2] |= 0x02;
+        assert_eq!(decode(0x1234, &truncated).unwrap(), Response::Truncated);
+    }
+
+    #[test]
+    fn test_lookup_asks_servers_in_turn() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buffer = [0; 512];
+            while let Ok((len, from)) = socket.recv_from(&mut buffer) {
+                socket.send_to(&reply(&buffer[..len]), from).unwrap();
+            }
+        });
+        // Nothing listens on the first server, which must be skipped.
+        let silent = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let answer = lookup(&[silent, server], "example.com", Duration::from_millis(200)).unwrap();
+        assert_eq!(answer.addresses.len(), 4);
+        assert_eq!(answer.ttl, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_parse_servers() {
+        assert_eq!(
+            parse_servers("1.1.1.1, [2606:4700::1111]:5353").unwrap(),
+            [
+                SocketAddr::from(([1, 1, 1, 1], PORT)),
+                "[2606:4700::1111]:5353".parse().unwrap()
+            ]
+        );
+        assert_eq!(parse_servers("::1").unwrap(), ["[::1]:53".parse().unwrap()]);
+        assert!(parse_servers("dns.example").is_err());
+        assert!(parse_servers(",").is_err());
+    }
+}
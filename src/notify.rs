@@ -0,0 +1,107 @@
+//! Desktop notifications at the end of a run (`--notify`).
+//!
+//! With `--notify`, rustwget shows one notification when the run ends: that
+//! the download or the batch finished, with its summary, or that it failed,
+//! with the error. The notification is shown by the desktop's own tool:
+//! `notify-send` on Linux and the BSDs, `osascript` (Notification Center) on
+//! macOS, and a PowerShell toast on Windows. A desktop without one is warned
+//! about and does not fail the run.
+
+use crate::logfile;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether a notification is still to be shown for this run.
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Shows a notification at the end of this run.
+pub fn enable() {
+    PENDING.store(true, Ordering::Relaxed);
+}
+
+/// Shows the notification of this run, titled `summary`, if one was asked
+/// for and none was shown yet.
+pub fn send(summary: &str, body: &str) {
+    if !PENDING.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    let mut command = command(summary, body);
+    let program = command.get_program().to_string_lossy().into_owned();
+    let status = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => logfile::warning(&format!(
+            "Cannot show a notification: {} exited with {}",
+            program, status
+        )),
+        Err(err) => logfile::warning(&format!(
+            "Cannot show a notification: cannot run {}: {}",
+            program, err
+        )),
+    }
+}
+
+/// Returns the command that shows the notification on this platform.
+fn command(summary: &str, body: &str) -> Command {
+    if cfg!(target_os = "macos") {
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(applescript(summary, body));
+        command
+    } else if cfg!(windows) {
+        let mut command = Command::new("powershell");
+        command
+            .args(["-NoProfile", "-NonInteractive", "-Command"])
+            .arg(powershell(summary, body));
+        command
+    } else {
+        let mut command = Command::new("notify-send");
+        command.arg("--app-name=rustwget").arg(summary).arg(body);
+        command
+    }
+}
+
+/// Returns the AppleScript that shows the notification in Notification Center.
+fn applescript(summary: &str, body: &str) -> String {
+    let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+    format!(
+        "display notification {} with title \"rustwget\" subtitle {}",
+        quote(body),
+        quote(summary)
+    )
+}
+
+/// Returns the PowerShell script that shows the notification as a toast.
+fn powershell(summary: &str, body: &str) -> String {
+    let quote = |text: &str| format!("'{}'", text.replace('\'', "''"));
+    format!(
+        "$m = [Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime]; \
+         $t = $m::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02); \
+         $x = $t.GetElementsByTagName('text'); \
+         $x.Item(0).AppendChild($t.CreateTextNode({})) > $null; \
+         $x.Item(1).AppendChild($t.CreateTextNode({})) > $null; \
+         $m::CreateToastNotifier('rustwget').Show([Windows.UI.Notifications.ToastNotification]::new($t))",
+        quote(summary),
+        quote(body)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripts_quote_messages() {
+        assert_eq!(
+            applescript("Download failed", "bad \"name\" \\ here"),
+            "display notification \"bad \\\"name\\\" \\\\ here\" with title \"rustwget\" \
+             subtitle \"Download failed\""
+        );
+        let script = powershell("Downloads finished", "it's done");
+        assert!(script.contains("CreateTextNode('Downloads finished')"));
+        assert!(script.contains("CreateTextNode('it''s done')"));
+    }
+}
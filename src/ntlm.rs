@@ -0,0 +1,357 @@
+//! NTLMv2 messages for Windows-integrated authentication.
+//!
+//! NTLM is a connection-oriented challenge-response scheme: the client sends
+//! a negotiate message, the server answers `401` (or `407` from a proxy) with
+//! a challenge, and the client repeats the request with an authenticate
+//! message computed from the challenge and the user's password. The same
+//! tokens are accepted by most servers under the `Negotiate` scheme. Kerberos
+//! is not supported.
+//!
+//! The raw messages, and the session key agreed with them, also authenticate
+//! SMB sessions (see [`smb`](crate::smb)).
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use md4::{Digest, Md4};
+use md5::Md5;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SIGNATURE: &[u8; 8] = b"NTLMSSP\0";
+
+const NEGOTIATE_UNICODE: u32 = 0x0000_0001;
+const REQUEST_TARGET: u32 = 0x0000_0004;
+const NEGOTIATE_NTLM: u32 = 0x0000_0200;
+const NEGOTIATE_ANONYMOUS: u32 = 0x0000_0800;
+const NEGOTIATE_ALWAYS_SIGN: u32 = 0x0000_8000;
+const NEGOTIATE_EXTENDED_SESSIONSECURITY: u32 = 0x0008_0000;
+const NEGOTIATE_TARGET_INFO: u32 = 0x0080_0000;
+const NEGOTIATE_128: u32 = 0x2000_0000;
+const NEGOTIATE_56: u32 = 0x8000_0000;
+
+/// Flags requested in the negotiate message.
+const CLIENT_FLAGS: u32 = NEGOTIATE_UNICODE
+    | REQUEST_TARGET
+    | NEGOTIATE_NTLM
+    | NEGOTIATE_ALWAYS_SIGN
+    | NEGOTIATE_EXTENDED_SESSIONSECURITY
+    | NEGOTIATE_128
+    | NEGOTIATE_56;
+
+/// Identifier of the timestamp in the challenge's target information.
+const AV_TIMESTAMP: u16 = 7;
+
+/// Seconds between 1601-01-01 (the Windows epoch) and 1970-01-01.
+const WINDOWS_EPOCH_OFFSET: u64 = 11_644_473_600;
+
+/// The parts of a server challenge needed to answer it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Challenge {
+    pub flags: u32,
+    pub server_challenge: [u8; 8],
+    pub target_info: Vec<u8>,
+}
+
+/// An authenticate message and the session key it agrees on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Authentication {
+    pub message: Vec<u8>,
+    pub session_key: Vec<u8>,
+}
+
+/// Returns the base64 negotiate message that starts a handshake.
+pub fn negotiate() -> String {
+    STANDARD.encode(negotiate_message())
+}
+
+/// Returns the negotiate message that starts a handshake.
+pub fn negotiate_message() -> Vec<u8> {
+    let mut message = Vec::with_capacity(32);
+    message.extend_from_slice(SIGNATURE);
+    message.extend_from_slice(&1u32.to_le_bytes());
+    message.extend_from_slice(&CLIENT_FLAGS.to_le_bytes());
+    // Empty domain and workstation fields.
+    message.extend_from_slice(&[0; 16]);
+    message
+}
+
+/// Decodes the base64 challenge message sent by the server.
+///
+/// # Errors
+///
+/// Returns an error if the token is not a well-formed challenge message.
+pub fn parse_challenge(token: &str) -> Result<Challenge, String> {
+    let message = STANDARD
+        .decode(token.trim())
+        .map_err(|_| "invalid NTLM challenge".to_string())?;
+    parse_challenge_message(&message)
+}
+
+/// Parses the challenge message sent by the server.
+///
+/// # Errors
+///
+/// Returns an error if `message` is not a well-formed challenge message.
+pub fn parse_challenge_message(message: &[u8]) -> Result<Challenge, String> {
+    let invalid = || "invalid NTLM challenge".to_string();
+    if message.len() < 32 || &message[..8] != SIGNATURE || u32_at(message, 8) != 2 {
+        return Err(invalid());
+    }
+    let flags = u32_at(message, 20);
+    let mut server_challenge = [0; 8];
+    server_challenge.copy_from_slice(&message[24..32]);
+    let target_info = if flags & NEGOTIATE_TARGET_INFO != 0 && message.len() >= 48 {
+        let len = u16::from_le_bytes([message[40], message[41]]) as usize;
+        let offset = u32_at(message, 44) as usize;
+        message
+            .get(offset..offset + len)
+            .ok_or_else(invalid)?
+            .to_vec()
+    } else {
+        Vec::new()
+    };
+    Ok(Challenge {
+        flags,
+        server_challenge,
+        target_info,
+    })
+}
+
+/// Returns the base64 authenticate message that answers `challenge`.
+///
+/// # Arguments
+///
+/// * `user`: The user name, optionally as `DOMAIN\user`.
+/// * `password`: The user's password.
+/// * `challenge`: The server challenge.
+pub fn authenticate(user: &str, password: &str, challenge: &Challenge) -> String {
+    STANDARD.encode(authentication(user, password, challenge).message)
+}
+
+/// Returns the authenticate message that answers `challenge`, with the
+/// session key it agrees on.
+///
+/// # Arguments
+///
+/// * `user`: The user name, optionally as `DOMAIN\user`.
+/// * `password`: The user's password.
+/// * `challenge`: The server challenge.
+pub fn authentication(user: &str, password: &str, challenge: &Challenge) -> Authentication {
+    let (domain, user) = user.split_once('\\').unwrap_or(("", user));
+    let mut client_challenge = [0; 8];
+    // Without randomness the response is still valid, merely predictable.
+    let _ = getrandom::getrandom(&mut client_challenge);
+    let timestamp = timestamp(&challenge.target_info).unwrap_or_else(now);
+    let (lm_response, nt_response) = responses(
+        domain,
+        user,
+        password,
+        challenge,
+        client_challenge,
+        timestamp,
+    );
+
+    // Without key exchange, the session key is the NTLMv2 session base key.
+    let session_key = hmac_md5(&ntowf_v2(domain, user, password), &[&nt_response[..16]]);
+
+    let domain = utf16(domain);
+    let user = utf16(user);
+    let flags = challenge.flags & CLIENT_FLAGS | NEGOTIATE_UNICODE;
+    Authentication {
+        message: message(
+            flags,
+            [&lm_response, &nt_response, &domain, &user, &[], &[]],
+        ),
+        session_key,
+    }
+}
+
+/// Returns the authenticate message of an anonymous user, which agrees on
+/// no session key.
+pub fn anonymous(challenge: &Challenge) -> Vec<u8> {
+    let flags = challenge.flags & CLIENT_FLAGS | NEGOTIATE_UNICODE | NEGOTIATE_ANONYMOUS;
+    message(flags, [&[0], &[], &[], &[], &[], &[]])
+}
+
+/// Lays out an authenticate message: the LM and NT responses, the domain,
+/// user, and workstation names, and the encrypted session key.
+fn message(flags: u32, fields: [&[u8]; 6]) -> Vec<u8> {
+    let header_len = 64;
+    let mut message = Vec::new();
+    message.extend_from_slice(SIGNATURE);
+    message.extend_from_slice(&3u32.to_le_bytes());
+    let mut offset = header_len;
+    for field in fields {
+        message.extend_from_slice(&(field.len() as u16).to_le_bytes());
+        message.extend_from_slice(&(field.len() as u16).to_le_bytes());
+        message.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += field.len();
+    }
+    message.extend_from_slice(&flags.to_le_bytes());
+    for field in fields {
+        message.extend_from_slice(field);
+    }
+    message
+}
+
+/// Computes the LMv2 and NTLMv2 responses.
+fn responses(
+    domain: &str,
+    user: &str,
+    password: &str,
+    challenge: &Challenge,
+    client_challenge: [u8; 8],
+    timestamp: u64,
+) -> (Vec<u8>, Vec<u8>) {
+    let key = ntowf_v2(domain, user, password);
+
+    let mut temp = vec![1, 1, 0, 0, 0, 0, 0, 0];
+    temp.extend_from_slice(&timestamp.to_le_bytes());
+    temp.extend_from_slice(&client_challenge);
+    temp.extend_from_slice(&[0; 4]);
+    temp.extend_from_slice(&challenge.target_info);
+    temp.extend_from_slice(&[0; 4]);
+
+    let mut nt_response = hmac_md5(&key, &[&challenge.server_challenge, &temp]);
+    nt_response.extend_from_slice(&temp);
+
+    // With a server-provided timestamp the LMv2 response must be zeroed.
+    let lm_response = if self::timestamp(&challenge.target_info).is_some() {
+        vec![0; 24]
+    } else {
+        let mut lm = hmac_md5(&key, &[&challenge.server_challenge, &client_challenge]);
+        lm.extend_from_slice(&client_challenge);
+        lm
+    };
+    (lm_response, nt_response)
+}
+
+/// Derives the NTLMv2 key from the password: `HMAC-MD5(MD4(password), UPPER(user) + domain)`.
+fn ntowf_v2(domain: &str, user: &str, password: &str) -> Vec<u8> {
+    let nt_hash = Md4::digest(utf16(password));
+    hmac_md5(&nt_hash, &[&utf16(&user.to_uppercase()), &utf16(domain)])
+}
+
+fn hmac_md5(key: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+    let mut mac = Hmac::<Md5>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Returns the server's timestamp from the target information, if present.
+fn timestamp(target_info: &[u8]) -> Option<u64> {
+    let mut rest = target_info;
+    while rest.len() >= 4 {
+        let id = u16::from_le_bytes([rest[0], rest[1]]);
+        let len = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        let value = rest.get(4..4 + len)?;
+        if id == AV_TIMESTAMP && len == 8 {
+            return Some(u64::from_le_bytes(value.try_into().ok()?));
+        }
+        if id == 0 {
+            break;
+        }
+        rest = &rest[4 + len..];
+    }
+    None
+}
+
+/// The current time in 100-nanosecond intervals since 1601-01-01.
+fn now() -> u64 {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (elapsed.as_secs() + WINDOWS_EPOCH_OFFSET) * 10_000_000
+        + u64::from(elapsed.subsec_nanos() / 100)
+}
+
+fn utf16(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::hex;
+
+    /// The NTLMv2 example of MS-NLMP section 4.2.4.
+    fn example() -> Challenge {
+        let mut target_info = Vec::new();
+        for (id, value) in [(2u16, "Domain"), (1, "Server")] {
+            let value = utf16(value);
+            target_info.extend_from_slice(&id.to_le_bytes());
+            target_info.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            target_info.extend_from_slice(&value);
+        }
+        target_info.extend_from_slice(&[0; 4]);
+        Challenge {
+            flags: 0xe28a_8233,
+            server_challenge: [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef],
+            target_info,
+        }
+    }
+
+    #[test]
+    fn test_ntlmv2_responses() {
+        assert_eq!(
+            hex(&ntowf_v2("Domain", "User", "Password")),
+            "0c868a403bfd7a93a3001ef22ef02e3f"
+        );
+
+        let (lm, nt) = responses("Domain", "User", "Password", &example(), [0xaa; 8], 0);
+        assert_eq!(hex(&lm), "86c35097ac9cec102554764a57cccc19aaaaaaaaaaaaaaaa");
+        assert_eq!(hex(&nt[..16]), "68cd0ab851e51c96aabc927bebef6a1c");
+    }
+
+    #[test]
+    fn test_parse_challenge() {
+        let challenge = example();
+        let mut message = Vec::new();
+        message.extend_from_slice(SIGNATURE);
+        message.extend_from_slice(&2u32.to_le_bytes());
+        message.extend_from_slice(&[0; 8]);
+        message.extend_from_slice(&(challenge.flags | NEGOTIATE_TARGET_INFO).to_le_bytes());
+        message.extend_from_slice(&challenge.server_challenge);
+        message.extend_from_slice(&[0; 8]);
+        let len = challenge.target_info.len() as u16;
+        message.extend_from_slice(&len.to_le_bytes());
+        message.extend_from_slice(&len.to_le_bytes());
+        message.extend_from_slice(&48u32.to_le_bytes());
+        message.extend_from_slice(&challenge.target_info);
+
+        let parsed = parse_challenge(&STANDARD.encode(&message)).unwrap();
+        assert_eq!(parsed.server_challenge, challenge.server_challenge);
+        assert_eq!(parsed.target_info, challenge.target_info);
+        assert!(parse_challenge("bm90IG50bG0=").is_err());
+    }
+
+    #[test]
+    fn test_authenticate_message_layout() {
+        let message = STANDARD
+            .decode(authenticate("DOMAIN\\user", "pw", &example()))
+            .unwrap();
+        assert_eq!(&message[..8], SIGNATURE);
+        assert_eq!(u32_at(&message, 8), 3);
+        // The user name field points at "user" in UTF-16.
+        let len = u16::from_le_bytes([message[36], message[37]]) as usize;
+        let offset = u32_at(&message, 40) as usize;
+        assert_eq!(&message[offset..offset + len], utf16("user").as_slice());
+
+        let message = anonymous(&example());
+        assert_ne!(u32_at(&message, 60) & NEGOTIATE_ANONYMOUS, 0);
+        // Only the one-byte LM response is set.
+        assert_eq!(u16::from_le_bytes([message[12], message[13]]), 1);
+        assert_eq!(u16::from_le_bytes([message[20], message[21]]), 0);
+    }
+}
@@ -0,0 +1,347 @@
+//! OAuth 2.0 tokens obtained with the device authorization grant (RFC 8628).
+//!
+//! Providers are declared in the configuration file, together with the hosts
+//! their tokens are sent to:
+//!
+//! ```toml
+//! [[oauth]]
+//! name = "google"
+//! device_authorization_url = "https://oauth2.googleapis.com/device/code"
+//! token_url = "https://oauth2.googleapis.com/token"
+//! client_id = "1234.apps.googleusercontent.com"
+//! client_secret = "..."        # optional; Google requires one
+//! scope = "https://www.googleapis.com/auth/drive.readonly"
+//! hosts = ["www.googleapis.com", "*.googleusercontent.com"]
+//! ```
+//!
+//! `rustwget auth login google` prints a code to enter on the provider's web
+//! page and waits until it is approved. The token is kept in the system
+//! keyring, sent as a bearer token to the provider's hosts, and refreshed
+//! with its refresh token once it expires.
+
+use reqwest::blocking::{Client, Response};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Grant type of device code token requests.
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Polling interval used when the provider does not name one.
+const DEFAULT_INTERVAL: u64 = 5;
+
+/// How long before its expiry a token is already refreshed.
+const EXPIRY_MARGIN: i64 = 60;
+
+/// An OAuth provider from the configuration file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Provider {
+    pub name: String,
+    pub device_authorization_url: String,
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub scope: Option<String>,
+    /// Hosts the token is sent to; `*.example.com` matches every subdomain.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+}
+
+impl Provider {
+    /// Returns whether the token of this provider is sent to `host`.
+    pub fn matches(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        self.hosts.iter().any(|pattern| {
+            let pattern = pattern.to_ascii_lowercase();
+            match pattern.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|sub| sub.ends_with('.')),
+                None => host == pattern,
+            }
+        })
+    }
+
+    /// Adds the client credentials to the parameters of a request.
+    fn params<'a>(&'a self, mut params: Vec<(&'a str, &'a str)>) -> Vec<(&'a str, &'a str)> {
+        params.push(("client_id", &self.client_id));
+        if let Some(secret) = &self.client_secret {
+            params.push(("client_secret", secret));
+        }
+        params
+    }
+}
+
+/// An access token with what is needed to renew it.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Token {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// Expiry as seconds since the Unix epoch, if the provider gave one.
+    pub expires_at: Option<i64>,
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Token(<redacted>, expires_at: {:?})", self.expires_at)
+    }
+}
+
+impl Token {
+    /// Returns whether the token has expired or is about to.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| chrono::Utc::now().timestamp() >= expires_at - EXPIRY_MARGIN)
+    }
+}
+
+/// The answer to a device authorization request.
+#[derive(Debug, Deserialize)]
+struct DeviceAuthorization {
+    device_code: String,
+    user_code: String,
+    /// Google calls this `verification_url`.
+    #[serde(alias = "verification_url")]
+    verification_uri: String,
+    verification_uri_complete: Option<String>,
+    expires_in: u64,
+    interval: Option<u64>,
+}
+
+/// A successful token response.
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: Option<i64>,
+}
+
+/// An error response of the token endpoint.
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+/// Runs the device flow for `provider` and waits for the user to approve it.
+///
+/// # Arguments
+///
+/// * `client`: The HTTP client used for making requests.
+/// * `provider`: The provider to log in to.
+/// * `prompt`: Called once with the instructions to show to the user.
+///
+/// # Errors
+///
+/// Returns an error if a request fails, or if the user denies access or does
+/// not approve it before the code expires.
+pub fn authorize(
+    client: &Client,
+    provider: &Provider,
+    prompt: impl FnOnce(&str),
+) -> Result<Token, String> {
+    let mut params = vec![];
+    if let Some(scope) = &provider.scope {
+        params.push(("scope", scope.as_str()));
+    }
+    let response = client
+        .post(&provider.device_authorization_url)
+        .form(&provider.params(params))
+        .send()
+        .map_err(|err| err.to_string())?;
+    let device: DeviceAuthorization = parse(response)?;
+
+    prompt(&match &device.verification_uri_complete {
+        Some(uri) => format!("Open {} and confirm the code {}", uri, device.user_code),
+        None => format!(
+            "Open {} and enter the code {}",
+            device.verification_uri, device.user_code
+        ),
+    });
+
+    let deadline = Instant::now() + Duration::from_secs(device.expires_in);
+    let mut interval = device.interval.unwrap_or(DEFAULT_INTERVAL);
+    loop {
+        thread::sleep(Duration::from_secs(interval));
+        if Instant::now() >= deadline {
+            return Err("the device code expired before it was approved".to_string());
+        }
+        let response = client
+            .post(&provider.token_url)
+            .form(&provider.params(vec![
+                ("grant_type", DEVICE_CODE_GRANT),
+                ("device_code", &device.device_code),
+            ]))
+            .send()
+            .map_err(|err| err.to_string())?;
+        match read_token(response, None) {
+            Ok(token) => return Ok(token),
+            Err(err) if err == "authorization_pending" => {}
+            Err(err) if err == "slow_down" => interval += 5,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Exchanges the refresh token of `token` for a new access token.
+///
+/// # Errors
+///
+/// Returns an error if the token cannot be refreshed.
+pub fn refresh(client: &Client, provider: &Provider, token: &Token) -> Result<Token, String> {
+    let refresh_token = token
+        .refresh_token
+        .as_deref()
+        .ok_or("the token expired and cannot be refreshed; run 'rustwget auth login' again")?;
+    let response = client
+        .post(&provider.token_url)
+        .form(&provider.params(vec![
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ]))
+        .send()
+        .map_err(|err| err.to_string())?;
+    read_token(response, Some(refresh_token))
+        .map_err(|err| format!("cannot refresh the token: {}", err))
+}
+
+/// Reads a token response, keeping `refresh_token` when the provider sends no new one.
+///
+/// Errors are the provider's error code, with its description if it gave one.
+fn read_token(response: Response, refresh_token: Option<&str>) -> Result<Token, String> {
+    let status = response.status();
+    let text = response.text().map_err(|err| err.to_string())?;
+    if !status.is_success() {
+        return Err(match serde_json::from_str::<ErrorResponse>(&text) {
+            Ok(ErrorResponse {
+                error,
+                error_description: Some(description),
+            }) if error != "authorization_pending" && error != "slow_down" => {
+                format!("{}: {}", error, description)
+            }
+            Ok(ErrorResponse { error, .. }) => error,
+            Err(_) => format!("HTTP {}", status),
+        });
+    }
+    let response: TokenResponse = serde_json::from_str(&text).map_err(|err| err.to_string())?;
+    Ok(Token {
+        access_token: response.access_token,
+        refresh_token: response
+            .refresh_token
+            .or_else(|| refresh_token.map(str::to_string)),
+        expires_at: response
+            .expires_in
+            .map(|seconds| chrono::Utc::now().timestamp() + seconds),
+    })
+}
+
+/// Parses a successful JSON response.
+fn parse<T: serde::de::DeserializeOwned>(response: Response) -> Result<T, String> {
+    let status = response.status();
+    let text = response.text().map_err(|err| err.to_string())?;
+    if !status.is_success() {
+        return Err(format!("HTTP {}: {}", status, text.trim()));
+    }
+    serde_json::from_str(&text).map_err(|err| format!("unexpected response: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+
+    fn provider(path: &str) -> Provider {
+        Provider {
+            name: "test".to_string(),
+            device_authorization_url: format!("{}/oauth/{}/device", server_url(), path),
+            token_url: format!("{}/oauth/{}/token", server_url(), path),
+            client_id: "client".to_string(),
+            client_secret: None,
+            scope: Some("files.read".to_string()),
+            hosts: vec![
+                "api.example.com".to_string(),
+                "*.files.example.com".to_string(),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_provider_matches_hosts() {
+        let provider = provider("hosts");
+        assert!(provider.matches("API.example.com"));
+        assert!(provider.matches("eu.files.example.com"));
+        assert!(!provider.matches("files.example.com"));
+        assert!(!provider.matches("example.com"));
+    }
+
+    #[test]
+    fn test_authorize_returns_approved_token() {
+        let device = mock("POST", "/oauth/flow/device")
+            .match_body(Matcher::UrlEncoded("scope".into(), "files.read".into()))
+            .with_body(
+                r#"{"device_code":"dev","user_code":"ABCD-EFGH","verification_uri":"https://example.com/device","expires_in":600,"interval":0}"#,
+            )
+            .create();
+        let token = mock("POST", "/oauth/flow/token")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("grant_type".into(), DEVICE_CODE_GRANT.into()),
+                Matcher::UrlEncoded("device_code".into(), "dev".into()),
+            ]))
+            .with_body(r#"{"access_token":"at","refresh_token":"rt","expires_in":3600}"#)
+            .create();
+
+        let mut shown = String::new();
+        let approved = authorize(&Client::new(), &provider("flow"), |text| {
+            shown = text.to_string()
+        })
+        .unwrap();
+
+        assert_eq!(approved.access_token, "at");
+        assert_eq!(approved.refresh_token.as_deref(), Some("rt"));
+        assert!(!approved.is_expired());
+        assert_eq!(
+            shown,
+            "Open https://example.com/device and enter the code ABCD-EFGH"
+        );
+        device.assert();
+        token.assert();
+    }
+
+    #[test]
+    fn test_authorize_reports_denial() {
+        let _device = mock("POST", "/oauth/denied/device")
+            .with_body(
+                r#"{"device_code":"dev","user_code":"X","verification_url":"https://example.com/device","expires_in":600,"interval":0}"#,
+            )
+            .create();
+        let _token = mock("POST", "/oauth/denied/token")
+            .with_status(400)
+            .with_body(r#"{"error":"access_denied","error_description":"The user declined"}"#)
+            .create();
+
+        let err = authorize(&Client::new(), &provider("denied"), |_| {}).unwrap_err();
+        assert_eq!(err, "access_denied: The user declined");
+    }
+
+    #[test]
+    fn test_refresh_keeps_refresh_token() {
+        let _token = mock("POST", "/oauth/refresh/token")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("grant_type".into(), "refresh_token".into()),
+                Matcher::UrlEncoded("refresh_token".into(), "rt".into()),
+            ]))
+            .with_body(r#"{"access_token":"new","expires_in":3600}"#)
+            .create();
+        let expired = Token {
+            access_token: "old".to_string(),
+            refresh_token: Some("rt".to_string()),
+            expires_at: Some(0),
+        };
+        assert!(expired.is_expired());
+
+        let token = refresh(&Client::new(), &provider("refresh"), &expired).unwrap();
+        assert_eq!(token.access_token, "new");
+        assert_eq!(token.refresh_token.as_deref(), Some("rt"));
+    }
+}
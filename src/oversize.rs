@@ -0,0 +1,126 @@
+//! Asking before downloading unexpectedly large files.
+//!
+//! With `--confirm-over SIZE`, a download whose `Content-Length` says it is
+//! larger than `SIZE` is asked about before its first byte is written, when
+//! rustwget runs on a terminal; declining cancels it. Without a terminal to
+//! ask on, or with `--tui`, such downloads fail instead. A URL is only asked
+//! about once per run, however often it is retried.
+
+use crate::units;
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::sync::Mutex;
+
+/// The size over which downloads need confirmation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub size: u64,
+    /// Whether to ask on the terminal rather than fail.
+    pub ask: bool,
+}
+
+impl Limit {
+    /// Creates a limit that asks on the terminal if there is one.
+    pub fn new(size: u64, dashboard: bool) -> Limit {
+        Limit {
+            size,
+            ask: !dashboard && io::stdin().is_terminal() && io::stderr().is_terminal(),
+        }
+    }
+}
+
+/// URLs confirmed so far; holding the lock also keeps prompts from overlapping.
+static CONFIRMED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Whether the download of `url`, `size` bytes long, may go ahead.
+///
+/// # Errors
+///
+/// Returns an error if the download is over the limit and cannot be asked
+/// about, or if the terminal cannot be read from or written to.
+pub fn allow(url: &str, size: u64, limit: Limit) -> Result<bool, Box<dyn std::error::Error>> {
+    if size <= limit.size {
+        return Ok(true);
+    }
+    let mut confirmed = CONFIRMED.lock().unwrap_or_else(|e| e.into_inner());
+    if confirmed.contains(url) {
+        return Ok(true);
+    }
+    if !limit.ask {
+        return Err(format!(
+            "{} is {}, more than --confirm-over {}",
+            url,
+            units::format_bytes(size),
+            units::format_bytes(limit.size)
+        )
+        .into());
+    }
+    let allowed = ask(url, size, &mut io::stdin().lock(), &mut io::stderr())?;
+    if allowed {
+        confirmed.insert(url.to_string());
+    }
+    Ok(allowed)
+}
+
+/// Asks on `prompt` whether to download `url` until `input` gives a valid answer.
+///
+/// The end of the input declines.
+fn ask(
+    url: &str,
+    size: u64,
+    input: &mut impl BufRead,
+    prompt: &mut impl Write,
+) -> io::Result<bool> {
+    loop {
+        write!(
+            prompt,
+            "{} is {}. Download it? [y/N] ",
+            url,
+            units::format_bytes(size)
+        )?;
+        prompt.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Ok(false);
+        }
+        match answer.trim().to_ascii_lowercase().as_str() {
+            "y" | "yes" => return Ok(true),
+            "" | "n" | "no" => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ask() {
+        let mut prompt = Vec::new();
+        let mut input = io::Cursor::new("maybe\nyes\n");
+        assert!(ask(
+            "https://example.com/big.iso",
+            5 << 30,
+            &mut input,
+            &mut prompt
+        )
+        .unwrap());
+        let prompt = String::from_utf8(prompt).unwrap();
+        assert_eq!(prompt.matches("Download it? [y/N]").count(), 2);
+
+        assert!(!ask("u", 1, &mut io::Cursor::new("\n"), &mut Vec::new()).unwrap());
+        assert!(!ask("u", 1, &mut io::Cursor::new(""), &mut Vec::new()).unwrap());
+    }
+
+    #[test]
+    fn test_allow_without_terminal() {
+        let limit = Limit {
+            size: 1000,
+            ask: false,
+        };
+        assert!(allow("https://example.com/small", 1000, limit).unwrap());
+        let err = allow("https://example.com/large", 1001, limit).unwrap_err();
+        assert!(err.to_string().contains("more than --confirm-over"));
+    }
+}
@@ -0,0 +1,132 @@
+//! What to do with a file that a download would replace.
+//!
+//! Unless `--force-overwrite` or `--skip-existing` decides up front, each
+//! existing file is asked about when rustwget runs on a terminal: overwrite
+//! it, resume it as a partial download, save the download under a new name
+//! such as `file.zip.1`, or skip the URL. Without a terminal to ask on,
+//! existing files are overwritten. The same is decided for a file that a
+//! completed download would be renamed onto, as with `--content-disposition`.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Held while asking, so that downloads ending at once ask one at a time.
+static ASKING: Mutex<()> = Mutex::new(());
+
+/// How existing files are treated, as chosen on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Policy {
+    #[default]
+    Ask,
+    Overwrite,
+    Skip,
+}
+
+/// What to do with one download whose file exists.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Overwrite,
+    Resume,
+    Rename(PathBuf),
+    Skip,
+}
+
+/// Decides what to do with the download into `path`.
+///
+/// # Errors
+///
+/// Returns an error if the terminal cannot be read from or written to.
+pub fn decide(path: &Path, policy: Policy) -> io::Result<Decision> {
+    match policy {
+        _ if !path.exists() => Ok(Decision::Overwrite),
+        Policy::Overwrite => Ok(Decision::Overwrite),
+        Policy::Skip => Ok(Decision::Skip),
+        Policy::Ask if io::stdin().is_terminal() && io::stderr().is_terminal() => {
+            let _asking = ASKING.lock().unwrap_or_else(|e| e.into_inner());
+            ask(path, &mut io::stdin().lock(), &mut io::stderr())
+        }
+        Policy::Ask => Ok(Decision::Overwrite),
+    }
+}
+
+/// Asks on `prompt` what to do with `path` until `input` gives a valid answer.
+///
+/// The end of the input skips the file.
+fn ask(path: &Path, input: &mut impl BufRead, prompt: &mut impl Write) -> io::Result<Decision> {
+    loop {
+        write!(
+            prompt,
+            "{} already exists. [o]verwrite, [r]esume, re[n]ame, or [s]kip? ",
+            path.display()
+        )?;
+        prompt.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Ok(Decision::Skip);
+        }
+        match answer.trim().to_ascii_lowercase().as_str() {
+            "o" | "overwrite" => return Ok(Decision::Overwrite),
+            "r" | "resume" => return Ok(Decision::Resume),
+            "n" | "rename" => return Ok(Decision::Rename(free_name(path))),
+            "s" | "skip" => return Ok(Decision::Skip),
+            _ => {}
+        }
+    }
+}
+
+/// Returns the first of `path.1`, `path.2`, ... that does not exist.
+pub fn free_name(path: &Path) -> PathBuf {
+    (1..)
+        .map(|number| {
+            let mut name = path.as_os_str().to_owned();
+            name.push(format!(".{}", number));
+            PathBuf::from(name)
+        })
+        .find(|candidate| !candidate.exists())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_ask_until_answered() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.zip");
+        fs::write(&path, "old").unwrap();
+        fs::write(dir.path().join("file.zip.1"), "older").unwrap();
+
+        let mut prompt = Vec::new();
+        let decision = ask(&path, &mut &b"maybe\nN\n"[..], &mut prompt).unwrap();
+        assert_eq!(decision, Decision::Rename(dir.path().join("file.zip.2")));
+        assert_eq!(
+            String::from_utf8(prompt)
+                .unwrap()
+                .matches("[s]kip?")
+                .count(),
+            2
+        );
+
+        let decision = ask(&path, &mut &b"resume\n"[..], &mut Vec::new()).unwrap();
+        assert_eq!(decision, Decision::Resume);
+        let decision = ask(&path, &mut &b""[..], &mut Vec::new()).unwrap();
+        assert_eq!(decision, Decision::Skip);
+    }
+
+    #[test]
+    fn test_decide_by_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.zip");
+        assert_eq!(decide(&path, Policy::Skip).unwrap(), Decision::Overwrite);
+
+        fs::write(&path, "old").unwrap();
+        assert_eq!(decide(&path, Policy::Skip).unwrap(), Decision::Skip);
+        assert_eq!(
+            decide(&path, Policy::Overwrite).unwrap(),
+            Decision::Overwrite
+        );
+    }
+}
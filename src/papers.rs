@@ -0,0 +1,336 @@
+//! Shorthands for papers on arXiv and in PubMed Central.
+//!
+//! Instead of the URL of a paper's PDF, it can be named by its identifier:
+//!
+//! ```text
+//! rustwget arxiv:2401.12345 arxiv:2401.12345v2 arxiv:hep-th/9901001
+//! rustwget pmc:PMC1234567
+//! rustwget pubmed:23193287
+//! ```
+//!
+//! `arxiv:ID` is the PDF on arXiv, `pmc:PMCID` that of an open-access
+//! article of PubMed Central (served by Europe PMC), and `pubmed:PMID` that
+//! of the PubMed Central article of a PubMed record, looked up with the NCBI
+//! ID converter for up to 200 records at a time; records without one are
+//! skipped with a warning. Unless `-O` names it, each PDF is saved as its
+//! identifier, such as `2401.12345v2.pdf`, `hep-th_9901001.pdf`,
+//! `PMC1234567.pdf`, or `23193287.pdf`.
+//!
+//! Requests to these services go out no faster than their operators ask of
+//! automated clients (see [`PACES`]), however many `--jobs` run, so that a
+//! reading list can be fetched in bulk without being blocked.
+
+use crate::download::Options;
+use crate::input::Entry;
+use crate::logfile;
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The prefixes of the shorthands.
+pub const SCHEMES: [&str; 3] = ["arxiv:", "pmc:", "pubmed:"];
+
+/// The least time between requests to each service.
+pub const PACES: [(&str, Duration); 3] = [
+    ("https://arxiv.org", Duration::from_secs(3)),
+    ("https://europepmc.org", Duration::from_millis(500)),
+    // Three requests a second without an API key.
+    ("https://www.ncbi.nlm.nih.gov", Duration::from_millis(340)),
+];
+
+/// The most PubMed IDs converted by one request.
+const BATCH: usize = 200;
+
+/// Where papers and IDs are looked up.
+#[derive(Debug, Clone)]
+pub struct Apis {
+    pub arxiv: String,
+    pub europepmc: String,
+    pub idconv: String,
+}
+
+impl Default for Apis {
+    fn default() -> Apis {
+        Apis {
+            arxiv: "https://arxiv.org".to_string(),
+            europepmc: "https://europepmc.org".to_string(),
+            idconv: "https://www.ncbi.nlm.nih.gov/pmc/utils/idconv/v1.0/".to_string(),
+        }
+    }
+}
+
+/// A paper named by a shorthand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Paper {
+    Arxiv(String),
+    /// A PubMed Central ID, with its `PMC` prefix.
+    Pmc(String),
+    Pubmed(String),
+}
+
+impl Paper {
+    /// Parses a shorthand, or returns `None` for other URLs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the identifier is malformed.
+    pub fn parse(url: &str) -> Result<Option<Paper>, String> {
+        let Some((scheme, id)) = url.split_once(':') else {
+            return Ok(None);
+        };
+        let digits = |text: &str| !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit());
+        let paper = match scheme.to_ascii_lowercase().as_str() {
+            "arxiv" if is_arxiv(id) => Paper::Arxiv(id.to_string()),
+            "pmc" => {
+                let number = id
+                    .strip_prefix("PMC")
+                    .or(id.strip_prefix("pmc"))
+                    .unwrap_or(id);
+                if !digits(number) {
+                    return Err(format!("invalid PubMed Central ID '{}'", id));
+                }
+                Paper::Pmc(format!("PMC{}", number))
+            }
+            "pubmed" if digits(id) => Paper::Pubmed(id.to_string()),
+            "arxiv" => return Err(format!("invalid arXiv ID '{}'", id)),
+            "pubmed" => return Err(format!("invalid PubMed ID '{}'", id)),
+            _ => return Ok(None),
+        };
+        Ok(Some(paper))
+    }
+
+    /// The name the PDF is saved as.
+    pub fn filename(&self) -> String {
+        match self {
+            Paper::Arxiv(id) => format!("{}.pdf", id.replace('/', "_")),
+            Paper::Pmc(id) | Paper::Pubmed(id) => format!("{}.pdf", id),
+        }
+    }
+}
+
+/// Whether `url` is one of the shorthands.
+pub fn is_shorthand(url: &str) -> bool {
+    SCHEMES.iter().any(|scheme| {
+        url.get(..scheme.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme))
+    })
+}
+
+/// Whether `id` is an arXiv identifier: `YYMM.NNNNN` since 2007, or
+/// `archive/YYMMNNN` before, either with an optional `vN` version.
+fn is_arxiv(id: &str) -> bool {
+    let id = match id.rsplit_once('v') {
+        Some((id, version))
+            if !version.is_empty() && version.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            id
+        }
+        _ => id,
+    };
+    let digits = |text: &str, lengths: &[usize]| {
+        lengths.contains(&text.len()) && text.bytes().all(|b| b.is_ascii_digit())
+    };
+    match id.split_once('/') {
+        Some((archive, number)) => {
+            !archive.is_empty()
+                && archive
+                    .bytes()
+                    .all(|b| b.is_ascii_alphabetic() || b == b'-' || b == b'.')
+                && digits(number, &[7])
+        }
+        None => id
+            .split_once('.')
+            .is_some_and(|(month, number)| digits(month, &[4]) && digits(number, &[4, 5])),
+    }
+}
+
+/// The answer of the NCBI ID converter.
+#[derive(Debug, Deserialize)]
+struct Conversion {
+    records: Vec<HashMap<String, serde_json::Value>>,
+}
+
+/// Replaces the shorthands among `entries` with the URLs of the PDFs they
+/// name, found through `apis`, each saved as its identifier unless `output`
+/// names it.
+///
+/// # Errors
+///
+/// Returns an error if an identifier is malformed or the PubMed IDs cannot
+/// be converted.
+pub fn expand(
+    client: &Client,
+    entries: Vec<Entry>,
+    apis: &Apis,
+    output: Option<&str>,
+    options: &Options,
+) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+    let mut papers = Vec::new();
+    for entry in entries {
+        let paper = Paper::parse(&entry.url)?;
+        papers.push((entry, paper));
+    }
+    let pmids: Vec<&str> = papers
+        .iter()
+        .filter_map(|(_, paper)| match paper {
+            Some(Paper::Pubmed(id)) => Some(id.as_str()),
+            _ => None,
+        })
+        .collect();
+    let mut pmcids = HashMap::new();
+    for batch in pmids.chunks(BATCH) {
+        pmcids.extend(convert(client, apis, batch, options)?);
+    }
+
+    let mut expanded = Vec::new();
+    for (entry, paper) in papers {
+        let Some(paper) = paper else {
+            expanded.push(entry);
+            continue;
+        };
+        let url = match &paper {
+            Paper::Arxiv(id) => format!("{}/pdf/{}", apis.arxiv, id),
+            Paper::Pmc(id) => pdf(apis, id),
+            Paper::Pubmed(id) => match pmcids.get(id) {
+                Some(pmcid) => pdf(apis, pmcid),
+                None => {
+                    logfile::warning(&format!(
+                        "Skipped: {} has no open-access article in PubMed Central",
+                        entry.url
+                    ));
+                    continue;
+                }
+            },
+        };
+        let output = entry
+            .output
+            .or_else(|| output.is_none().then(|| PathBuf::from(paper.filename())));
+        expanded.push(Entry {
+            url,
+            output,
+            ..entry
+        });
+    }
+    Ok(expanded)
+}
+
+/// Returns the URL of the PDF of the PubMed Central article `pmcid`.
+fn pdf(apis: &Apis, pmcid: &str) -> String {
+    format!(
+        "{}/backend/ptpmcrender.fcgi?accid={}&blobtype=pdf",
+        apis.europepmc, pmcid
+    )
+}
+
+/// Looks up the PubMed Central IDs of the PubMed records `pmids`.
+fn convert(
+    client: &Client,
+    apis: &Apis,
+    pmids: &[&str],
+    options: &Options,
+) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let mut url = url::Url::parse(&apis.idconv)?;
+    url.query_pairs_mut()
+        .append_pair("ids", &pmids.join(","))
+        .append_pair("idtype", "pmid")
+        .append_pair("format", "json")
+        .append_pair("tool", "rustwget");
+    let url = String::from(url);
+    let response = options
+        .send(client, &url, |client| client.get(&url))?
+        .error_for_status()
+        .map_err(|err| format!("cannot look up PubMed IDs: {}", err))?;
+    let conversion: Conversion = serde_json::from_reader(response)
+        .map_err(|err| format!("invalid answer of the NCBI ID converter: {}", err))?;
+    let text = |value: &serde_json::Value| match value {
+        serde_json::Value::String(text) => Some(text.clone()),
+        serde_json::Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    };
+    Ok(conversion
+        .records
+        .iter()
+        .filter_map(|record| Some((text(record.get("pmid")?)?, text(record.get("pmcid")?)?)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+
+    #[test]
+    fn test_parse_papers() {
+        assert_eq!(
+            Paper::parse("arxiv:2401.12345v2"),
+            Ok(Some(Paper::Arxiv("2401.12345v2".to_string())))
+        );
+        assert_eq!(
+            Paper::parse("arXiv:hep-th/9901001")
+                .unwrap()
+                .unwrap()
+                .filename(),
+            "hep-th_9901001.pdf"
+        );
+        assert_eq!(
+            Paper::parse("pmc:1234567"),
+            Ok(Some(Paper::Pmc("PMC1234567".to_string())))
+        );
+        assert!(Paper::parse("arxiv:2401.123").is_err());
+        assert!(Paper::parse("pubmed:PMC1").is_err());
+        assert_eq!(Paper::parse("https://example.com/a.pdf"), Ok(None));
+        assert!(is_shorthand("PubMed:1") && !is_shorthand("example.com:8080/a"));
+    }
+
+    #[test]
+    fn test_expand_papers() {
+        let idconv = mock("GET", "/papers/idconv/")
+            .match_query(Matcher::UrlEncoded(
+                "ids".to_string(),
+                "111,222".to_string(),
+            ))
+            .with_body(
+                r#"{"status": "ok", "records": [
+                    {"pmid": "111", "pmcid": "PMC900"},
+                    {"pmid": 222, "status": "error", "errmsg": "invalid article id"}]}"#,
+            )
+            .expect(1)
+            .create();
+        let apis = Apis {
+            arxiv: "https://arxiv.example".to_string(),
+            europepmc: "https://pmc.example".to_string(),
+            idconv: format!("{}/papers/idconv/", server_url()),
+        };
+        let entry = |url: &str| Entry {
+            url: url.to_string(),
+            priority: 0,
+            output: None,
+        };
+        let entries = vec![
+            entry("arxiv:2401.12345"),
+            entry("pubmed:111"),
+            entry("pubmed:222"),
+            entry("https://example.com/a.pdf"),
+        ];
+        let entries = expand(&Client::new(), entries, &apis, None, &Options::default()).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                Entry {
+                    output: Some(PathBuf::from("2401.12345.pdf")),
+                    ..entry("https://arxiv.example/pdf/2401.12345")
+                },
+                Entry {
+                    output: Some(PathBuf::from("111.pdf")),
+                    ..entry(
+                        "https://pmc.example/backend/ptpmcrender.fcgi?accid=PMC900&blobtype=pdf"
+                    )
+                },
+                entry("https://example.com/a.pdf"),
+            ]
+        );
+        idconv.assert();
+    }
+}
@@ -0,0 +1,213 @@
+//! Verifying partial files before they are resumed.
+//!
+//! With `--verify-partial`, a transfer into `FILE` keeps `FILE.resume` next to
+//! it: the URL, the ETag of the response, and the SHA-256 digest of every
+//! complete [`BLOCK`] written so far, one per line. Before the file is resumed,
+//! its blocks are hashed again and it is cut back to the end of the last one
+//! that matches, so that a corrupted partial file is not extended; the bytes
+//! after the last complete block are discarded, as nothing vouches for them. A
+//! partial file without metadata cannot be verified and is downloaded again
+//! from the start. The rest is requested with `If-Range` on the recorded ETag
+//! when it is strong, so that a server whose resource changed sends all of it
+//! instead. The metadata is removed once the download completes.
+
+use crate::ledger;
+use crate::logfile;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Number of bytes covered by each digest.
+pub const BLOCK: u64 = 1 << 20;
+
+/// Returns the path of the metadata of the partial file `file`.
+pub fn path(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".resume");
+    PathBuf::from(name)
+}
+
+/// What is known about the data of a partial file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// The URL the data came from.
+    pub url: String,
+    /// The `ETag` of the response the data came from, if any.
+    pub etag: Option<String>,
+    /// The SHA-256 digest of each complete block, as lowercase hex.
+    pub blocks: Vec<String>,
+}
+
+impl Metadata {
+    /// Reads the metadata of `file`, if it has any that can be read.
+    pub fn load(file: &Path) -> Option<Metadata> {
+        let mut lines = BufReader::new(File::open(path(file)).ok()?).lines();
+        let url = lines.next()?.ok()?;
+        let etag = Some(lines.next()?.ok()?).filter(|etag| !etag.is_empty());
+        let blocks = lines
+            .map_while(Result::ok)
+            .take_while(|digest| digest.len() == 64)
+            .collect();
+        Some(Metadata { url, etag, blocks })
+    }
+
+    /// Returns the validator to send as `If-Range` when the data is continued
+    /// from `url`: the recorded ETag, if it is strong and from the same URL.
+    pub fn if_range(&self, url: &str) -> Option<&str> {
+        self.etag
+            .as_deref()
+            .filter(|etag| self.url == url && !etag.starts_with("W/"))
+    }
+}
+
+/// Checks the first `len` bytes of the partial file `file` against its
+/// metadata and cuts it back to the part that matches.
+///
+/// Returns the length kept, a whole number of blocks, and the metadata of the
+/// part kept.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or truncated.
+pub fn verify(file: &Path, len: u64) -> io::Result<(u64, Metadata)> {
+    if len == 0 {
+        return Ok((0, Metadata::default()));
+    }
+    let Some(mut metadata) = Metadata::load(file) else {
+        logfile::warning(&format!(
+            "No resume metadata for {}; downloading it again from the start",
+            file.display()
+        ));
+        return Ok((0, Metadata::default()));
+    };
+    let mut reader = File::open(file)?;
+    let mut buffer = vec![0; BLOCK as usize];
+    let mut kept = 0;
+    while kept < metadata.blocks.len() && (kept as u64 + 1) * BLOCK <= len {
+        reader.read_exact(&mut buffer)?;
+        if ledger::hex(&Sha256::digest(&buffer)) != metadata.blocks[kept] {
+            logfile::warning(&format!(
+                "Block {} of {} does not match its resume metadata; resuming from byte {}",
+                kept,
+                file.display(),
+                kept as u64 * BLOCK
+            ));
+            break;
+        }
+        kept += 1;
+    }
+    metadata.blocks.truncate(kept);
+    let kept = kept as u64 * BLOCK;
+    OpenOptions::new().write(true).open(file)?.set_len(kept)?;
+    Ok((kept, metadata))
+}
+
+/// Removes the metadata of `file`, if it has any.
+pub fn remove(file: &Path) {
+    let _ = fs::remove_file(path(file));
+}
+
+/// Records the digest of every block written through it in the metadata of a
+/// partial file.
+#[derive(Debug)]
+pub struct Recorder<W> {
+    inner: W,
+    metadata: File,
+    hasher: Sha256,
+    /// Bytes of the current block written so far.
+    filled: u64,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Writes to `inner`, the partial file `file` after the blocks of
+    /// `metadata`, saving `metadata` as the start of its new metadata.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metadata cannot be written.
+    pub fn new(inner: W, file: &Path, metadata: &Metadata) -> io::Result<Recorder<W>> {
+        let mut text = format!(
+            "{}\n{}\n",
+            metadata.url,
+            metadata.etag.as_deref().unwrap_or_default()
+        );
+        for digest in &metadata.blocks {
+            text += digest;
+            text.push('\n');
+        }
+        fs::write(path(file), text)?;
+        Ok(Recorder {
+            inner,
+            metadata: OpenOptions::new().append(true).open(path(file))?,
+            hasher: Sha256::new(),
+            filled: 0,
+        })
+    }
+}
+
+impl<W: Write> Write for Recorder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        let mut data = &buf[..written];
+        while !data.is_empty() {
+            let take = data.len().min((BLOCK - self.filled) as usize);
+            self.hasher.update(&data[..take]);
+            self.filled += take as u64;
+            data = &data[take..];
+            if self.filled == BLOCK {
+                let digest = ledger::hex(&self.hasher.finalize_reset());
+                writeln!(self.metadata, "{}", digest)?;
+                self.filled = 0;
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_cuts_back_to_matching_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("data.bin");
+        let data: Vec<u8> = (0..BLOCK * 3 + 10).map(|i| (i % 251) as u8).collect();
+        let metadata = Metadata {
+            url: "https://example.com/data.bin".to_string(),
+            etag: Some("\"v1\"".to_string()),
+            blocks: Vec::new(),
+        };
+        let mut recorder = Recorder::new(File::create(&file).unwrap(), &file, &metadata).unwrap();
+        recorder.write_all(&data).unwrap();
+        drop(recorder);
+
+        let loaded = Metadata::load(&file).unwrap();
+        assert_eq!(loaded.blocks.len(), 3);
+        assert_eq!(
+            loaded.if_range("https://example.com/data.bin"),
+            Some("\"v1\"")
+        );
+        assert_eq!(loaded.if_range("https://mirror.example/data.bin"), None);
+
+        let len = data.len() as u64;
+        assert_eq!(verify(&file, len).unwrap(), (BLOCK * 3, loaded.clone()));
+        assert_eq!(fs::metadata(&file).unwrap().len(), BLOCK * 3);
+
+        // A corrupted second block leaves only the first.
+        let mut corrupted = data[..(BLOCK * 3) as usize].to_vec();
+        corrupted[BLOCK as usize + 7] ^= 0xff;
+        fs::write(&file, &corrupted).unwrap();
+        let (kept, metadata) = verify(&file, BLOCK * 3).unwrap();
+        assert_eq!((kept, metadata.blocks.len()), (BLOCK, 1));
+        assert_eq!(fs::read(&file).unwrap(), &data[..BLOCK as usize]);
+
+        remove(&file);
+        assert_eq!(verify(&file, BLOCK).unwrap(), (0, Metadata::default()));
+    }
+}
@@ -0,0 +1,51 @@
+//! Locations of per-user state and cache files.
+
+use std::env;
+use std::path::PathBuf;
+
+/// Returns the directory holding rustwget's persistent state.
+///
+/// This is `$XDG_DATA_HOME/rustwget` (falling back to
+/// `~/.local/share/rustwget`) on Unix-like systems and `%APPDATA%\rustwget`
+/// on Windows. The directory is not created.
+pub fn data_dir() -> PathBuf {
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+    };
+    base.unwrap_or_else(|| PathBuf::from(".")).join("rustwget")
+}
+
+/// Returns the directory holding rustwget's configuration.
+///
+/// This is `$XDG_CONFIG_HOME/rustwget` (falling back to `~/.config/rustwget`)
+/// on Unix-like systems and `%APPDATA%\rustwget` on Windows.
+pub fn config_dir() -> PathBuf {
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    base.unwrap_or_else(|| PathBuf::from(".")).join("rustwget")
+}
+
+/// Returns the directory holding rustwget's caches.
+///
+/// This is `$XDG_CACHE_HOME/rustwget` (falling back to `~/.cache/rustwget`)
+/// on Unix-like systems and `%LOCALAPPDATA%\rustwget` on Windows. The
+/// directory is not created.
+pub fn cache_dir() -> PathBuf {
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    };
+    base.unwrap_or_else(|| PathBuf::from(".")).join("rustwget")
+}
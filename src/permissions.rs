@@ -0,0 +1,227 @@
+//! Modes and owners of downloaded files.
+//!
+//! `--umask MASK` replaces the process umask, so that the files and
+//! directories rustwget creates get modes such as `664` and `775` for a shared
+//! group. `--chmod MODE` then sets the exact mode of each completed file, and
+//! `--chown USER[:GROUP]` hands it to another owner, which usually needs root.
+//! Modes are octal, as with chmod(1). These options only work on Unix-like
+//! systems.
+
+use std::io;
+use std::path::Path;
+
+/// Largest mode accepted by `--chmod`, including the setuid, setgid, and sticky bits.
+pub const MAX_MODE: u32 = 0o7777;
+
+/// Largest mask accepted by `--umask`.
+pub const MAX_UMASK: u32 = 0o777;
+
+/// The owner and group to give files, each left unchanged when `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+/// Parses an octal mode such as `644` or `0755` of at most `max`.
+pub fn parse_mode(text: &str, max: u32) -> Result<u32, String> {
+    u32::from_str_radix(text, 8)
+        .ok()
+        .filter(|mode| *mode <= max)
+        .ok_or_else(|| {
+            format!(
+                "invalid mode '{}': expected an octal number up to {:o}",
+                text, max
+            )
+        })
+}
+
+/// Parses `USER`, `USER:GROUP`, or `:GROUP`, each a name or a numeric id.
+///
+/// `USER:` stands for the login group of `USER`, as with chown(1).
+///
+/// # Errors
+///
+/// Returns an error if a user or group does not exist.
+pub fn parse_owner(text: &str) -> Result<Owner, String> {
+    let (user, group) = match text.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (text, None),
+    };
+    let mut owner = Owner::default();
+    if !user.is_empty() {
+        let (uid, login_group) = lookup_user(user)?;
+        owner.uid = Some(uid);
+        if group == Some("") {
+            owner.gid =
+                Some(login_group.ok_or_else(|| format!("user '{}' has no login group", user))?);
+        }
+    }
+    if let Some(group) = group.filter(|group| !group.is_empty()) {
+        owner.gid = Some(lookup_group(group)?);
+    }
+    match owner {
+        Owner {
+            uid: None,
+            gid: None,
+        } => Err(format!("invalid owner '{}': expected USER[:GROUP]", text)),
+        owner => Ok(owner),
+    }
+}
+
+/// Sets the mask of permissions removed from every file and directory created from now on.
+///
+/// # Errors
+///
+/// Returns an error on systems without a umask.
+#[cfg(unix)]
+pub fn set_umask(mask: u32) -> io::Result<()> {
+    // SAFETY: umask only replaces the process's mask and cannot fail.
+    unsafe { libc::umask(mask as libc::mode_t) };
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_umask(_mask: u32) -> io::Result<()> {
+    Err(unsupported())
+}
+
+/// Gives the completed file at `path` the `mode` and `owner` asked for, if any.
+///
+/// The owner is changed first, as that clears the setuid and setgid bits.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be changed, such as when changing its
+/// owner needs privileges rustwget does not have.
+#[cfg(unix)]
+pub fn apply(path: &Path, mode: Option<u32>, owner: Option<Owner>) -> io::Result<()> {
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::{chown, PermissionsExt};
+
+    if let Some(owner) = owner {
+        chown(path, owner.uid, owner.gid)?;
+    }
+    if let Some(mode) = mode {
+        fs::set_permissions(path, Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn apply(_path: &Path, mode: Option<u32>, owner: Option<Owner>) -> io::Result<()> {
+    match (mode, owner) {
+        (None, None) => Ok(()),
+        _ => Err(unsupported()),
+    }
+}
+
+#[cfg(not(unix))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "file modes and owners are only supported on Unix-like systems",
+    )
+}
+
+/// Returns the id and login group of the user named or numbered `name`.
+#[cfg(unix)]
+fn lookup_user(name: &str) -> Result<(u32, Option<u32>), String> {
+    let number = name.parse::<u32>().ok();
+    // SAFETY: the entry is copied out before anything else can look up users;
+    // this runs while the command line is read, before any thread is started.
+    let entry = unsafe {
+        match number {
+            Some(uid) => libc::getpwuid(uid),
+            None => libc::getpwnam(c_name(name)?.as_ptr()),
+        }
+        .as_ref()
+        .map(|entry| (entry.pw_uid, entry.pw_gid))
+    };
+    match (entry, number) {
+        (Some((uid, gid)), _) => Ok((uid, Some(gid))),
+        (None, Some(uid)) => Ok((uid, None)),
+        (None, None) => Err(format!("unknown user '{}'", name)),
+    }
+}
+
+/// Returns the id of the group named or numbered `name`.
+#[cfg(unix)]
+fn lookup_group(name: &str) -> Result<u32, String> {
+    if let Ok(gid) = name.parse() {
+        return Ok(gid);
+    }
+    // SAFETY: as in `lookup_user`.
+    unsafe { libc::getgrnam(c_name(name)?.as_ptr()).as_ref() }
+        .map(|entry| entry.gr_gid)
+        .ok_or_else(|| format!("unknown group '{}'", name))
+}
+
+#[cfg(unix)]
+fn c_name(name: &str) -> Result<std::ffi::CString, String> {
+    std::ffi::CString::new(name).map_err(|_| format!("invalid name '{}'", name))
+}
+
+#[cfg(not(unix))]
+fn lookup_user(_name: &str) -> Result<(u32, Option<u32>), String> {
+    Err(unsupported().to_string())
+}
+
+#[cfg(not(unix))]
+fn lookup_group(_name: &str) -> Result<u32, String> {
+    Err(unsupported().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("644", MAX_MODE), Ok(0o644));
+        assert_eq!(parse_mode("02775", MAX_MODE), Ok(0o2775));
+        assert!(parse_mode("2775", MAX_UMASK).is_err());
+        assert!(parse_mode("rw-r--r--", MAX_MODE).is_err());
+        assert!(parse_mode("", MAX_MODE).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_parse_owner() {
+        let root = Owner {
+            uid: Some(0),
+            gid: Some(0),
+        };
+        assert_eq!(parse_owner("root:"), Ok(root));
+        assert_eq!(parse_owner("0:0"), Ok(root));
+        assert_eq!(
+            parse_owner(":0"),
+            Ok(Owner {
+                uid: None,
+                gid: Some(0)
+            })
+        );
+        assert!(parse_owner("no-such-user-here").is_err());
+        assert!(parse_owner("root:no-such-group-here").is_err());
+        assert!(parse_owner(":").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_apply() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        std::fs::write(&path, "data").unwrap();
+        let meta = std::fs::metadata(&path).unwrap();
+        // Giving a file to its own owner needs no privileges.
+        let owner = Owner {
+            uid: Some(meta.uid()),
+            gid: Some(meta.gid()),
+        };
+
+        apply(&path, Some(0o640), Some(owner)).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().mode() & MAX_MODE, 0o640);
+    }
+}
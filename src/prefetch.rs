@@ -0,0 +1,233 @@
+//! `rustwget prefetch`: fills the download cache from a manifest.
+//!
+//! `rustwget prefetch MANIFEST` downloads every URL listed in `MANIFEST`, in
+//! the format of `--input-file`, into the cache that `rustwget proxy` serves,
+//! without saving anything in the working directory. URLs already cached are
+//! revalidated with their server and only downloaded again if they changed.
+//!
+//! This primes a shared proxy before a build farm starts its jobs, or a
+//! laptop's own proxy before it goes offline: the proxy serves what it has
+//! cached when the servers cannot be reached.
+
+use crate::cache::{self, Cache, Entry};
+use crate::input;
+use crate::logfile;
+use crate::redact;
+use crate::units;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use reqwest::blocking::Client;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// What prefetching a URL did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    /// The body was downloaded, this many bytes.
+    Fetched(u64),
+    /// The cached body is still current.
+    Current,
+}
+
+/// Builds the `prefetch` subcommand definition.
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("prefetch")
+        .about("Download the URLs of a manifest into the cache of rustwget proxy")
+        .arg(
+            Arg::with_name("manifest")
+                .value_name("MANIFEST")
+                .help("File listing the URLs to cache, one per line ('-' for stdin)")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("cache-dir")
+                .long("cache-dir")
+                .value_name("DIR")
+                .help("Where responses are cached [default: <cache dir>/proxy]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cache-size")
+                .long("cache-size")
+                .value_name("SIZE")
+                .help(
+                    "Remove the files used least recently once the cache grows past SIZE, e.g. 50G",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .value_name("N")
+                .help("Number of downloads to run at the same time")
+                .default_value("4")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output-file")
+                .short("o")
+                .long("output-file")
+                .value_name("LOGFILE")
+                .help("Also append all messages to LOGFILE")
+                .takes_value(true),
+        )
+}
+
+/// Prefetches the URLs of the manifest.
+///
+/// # Errors
+///
+/// Returns an error if the arguments are invalid, the manifest or cache
+/// cannot be opened, or a URL cannot be cached.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(path) = matches.value_of("output-file") {
+        logfile::open(Path::new(path), true, None)?;
+    }
+    let manifest = matches.value_of("manifest").unwrap();
+    let urls: Vec<String> = input::read(manifest, 0)?
+        .into_iter()
+        .map(|entry| input::with_default_scheme(&entry.url, "https"))
+        .collect();
+    let dir = matches
+        .value_of("cache-dir")
+        .map_or_else(cache::default_dir, PathBuf::from);
+    let max_size = matches
+        .value_of("cache-size")
+        .map(units::parse_size)
+        .transpose()?;
+    let jobs: usize = match matches.value_of("jobs").unwrap().parse() {
+        Ok(jobs) if jobs > 0 => jobs,
+        _ => return Err("--jobs must be a positive number".into()),
+    };
+    let cache = Cache::open(&dir, max_size)
+        .map_err(|err| format!("cannot create cache {}: {}", dir.display(), err))?;
+    let client = Client::builder().build()?;
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(urls.len()) {
+            scope.spawn(|| {
+                while let Some(url) = urls.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    match prefetch(&cache, &client, url) {
+                        Ok(Outcome::Fetched(size)) => logfile::info(&format!(
+                            "Cached {} ({})",
+                            redact::url(url),
+                            units::format_bytes(size)
+                        )),
+                        Ok(Outcome::Current) => {
+                            logfile::info(&format!("Current {}", redact::url(url)))
+                        }
+                        Err(err) => {
+                            logfile::warning(&format!(
+                                "Warning: cannot prefetch {}: {}",
+                                redact::url(url),
+                                err
+                            ));
+                            failed.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let failed = failed.into_inner();
+    logfile::info(&format!(
+        "Prefetched {} of {} URLs into {}",
+        urls.len() - failed,
+        urls.len(),
+        dir.display()
+    ));
+    if failed > 0 {
+        return Err(format!("{} of {} URLs could not be prefetched", failed, urls.len()).into());
+    }
+    Ok(())
+}
+
+/// Downloads `url` into `cache`, or revalidates the body cached for it. A
+/// body reached through redirects is also cached under the URL it came from.
+fn prefetch(cache: &Cache, client: &Client, url: &str) -> Result<Outcome, String> {
+    let _turn = cache.lock(url).lock().unwrap_or_else(|e| e.into_inner());
+    let cached = cache.lookup(url);
+    let mut headers = HeaderMap::new();
+    if let Some(entry) = &cached {
+        entry.revalidate(&mut headers);
+    }
+    let response = client
+        .get(url)
+        .headers(headers)
+        .send()
+        .map_err(|err| err.to_string())?;
+    if let (Some(mut entry), StatusCode::NOT_MODIFIED) = (cached, response.status()) {
+        entry.fetched = cache::now();
+        cache.record(&entry).map_err(|err| err.to_string())?;
+        return Ok(Outcome::Current);
+    }
+    if !response.status().is_success() {
+        return Err(format!("the server answered {}", response.status()));
+    }
+    if !cache::storable(&response) {
+        return Err("the server does not allow it to be cached".to_string());
+    }
+    let location = response.url().to_string();
+    let entry = Entry::new(url, &response);
+    let expected = response.content_length();
+    let entry = cache
+        .store(entry, response, expected, io::sink())
+        .map_err(|err| err.to_string())?;
+    if location != url {
+        cache
+            .record(&Entry {
+                url: location,
+                ..entry.clone()
+            })
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(Outcome::Fetched(entry.size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+
+    #[test]
+    fn test_prefetch_fills_and_revalidates_cache() {
+        let fetch = mock("GET", "/prefetch/tool.tar.gz")
+            .match_header("if-none-match", Matcher::Missing)
+            .with_header("etag", "\"v1\"")
+            .with_body("tool")
+            .expect(1)
+            .create();
+        let revalidate = mock("GET", "/prefetch/tool.tar.gz")
+            .match_header("if-none-match", "\"v1\"")
+            .with_status(304)
+            .expect(1)
+            .create();
+        let private = mock("GET", "/prefetch/private.txt")
+            .with_header("cache-control", "private")
+            .with_body("mine")
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::open(dir.path(), None).unwrap();
+        let client = Client::new();
+        let url = format!("{}/prefetch/tool.tar.gz", server_url());
+
+        assert_eq!(prefetch(&cache, &client, &url), Ok(Outcome::Fetched(4)));
+        let entry = cache.lookup(&url).unwrap();
+        assert_eq!(entry.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(prefetch(&cache, &client, &url), Ok(Outcome::Current));
+        fetch.assert();
+        revalidate.assert();
+
+        let url = format!("{}/prefetch/private.txt", server_url());
+        assert!(prefetch(&cache, &client, &url).is_err());
+        assert_eq!(cache.lookup(&url), None);
+        private.assert();
+    }
+}
@@ -0,0 +1,657 @@
+//! `rustwget proxy`: a caching forward proxy shared by many clients.
+//!
+//! Build farms run the same downloads in job after job. Pointed at the proxy
+//! (`http_proxy=http://cache.ci:3128`), their jobs fetch each file from its
+//! server once; later requests for it are answered from a cache on the
+//! proxy's disk, which `rustwget prefetch` can fill ahead of time.
+//!
+//! `GET` and `HEAD` requests for `http://` URLs are cached, and other methods
+//! are refused. `https://` URLs, which clients tunnel through the proxy with
+//! `CONNECT` where they cannot be read, are cached when requested through the
+//! proxy's own URL instead, as in
+//! `http://cache.ci:3128/https://example.com/tool.tar.gz`. Requests with
+//! credentials, cookies, or a `Range`, and responses marked `no-store` or
+//! `private`, pass through uncached. A cached file is served without asking
+//! its server for `--max-age` seconds, and then revalidated with its `ETag`
+//! and `Last-Modified` time; when the server cannot be reached, it is
+//! served as it is. With `--cache-size`, the files used least recently are
+//! removed once the cache grows past it.
+//!
+//! With `--allow`, the proxy only fetches from and tunnels to the
+//! `HOST:PORT`s it lists, where `*` stands for any host and `*.example.com`
+//! for the hosts below `example.com`; without it, `CONNECT` tunnels are only
+//! opened to port 443. Every client that can reach the proxy can use it,
+//! including to reach the services of the proxy's own network, so it only
+//! listens on an address other machines reach when `--allow` is given, and
+//! that address should be one only trusted machines reach. Each request is logged on its own
+//! line, which `--output-file` also appends to a log.
+
+use crate::cache::{self, Cache, Entry};
+use crate::httpd::{self, Request, Response};
+use crate::logfile;
+use crate::redact;
+use crate::units;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use reqwest::blocking::Client;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::{Method, StatusCode};
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use url::Url;
+
+/// Default address of the proxy.
+const DEFAULT_LISTEN: &str = "127.0.0.1:3128";
+
+/// Headers that only apply to one connection, which are not forwarded.
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// The port `CONNECT` tunnels are opened to without `--allow`.
+const HTTPS_PORT: &str = "443";
+
+/// How long a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The proxy's cache and the client it fetches with.
+#[derive(Debug)]
+struct Proxy {
+    cache: Cache,
+    client: Client,
+    max_age: Duration,
+    /// The `HOST:PORT` patterns requests may reach, if given.
+    allow: Vec<String>,
+}
+
+/// Builds the `proxy` subcommand definition.
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("proxy")
+        .about("Run a caching HTTP proxy that many clients share a download cache through")
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .value_name("ADDR")
+                .help("Address to accept proxy requests on")
+                .default_value(DEFAULT_LISTEN)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("allow")
+                .long("allow")
+                .value_name("HOST:PORT")
+                .help(
+                    "Only fetch from and tunnel to HOST:PORT, e.g. '*.example.com:443' \
+                     (repeatable) [default: any host, tunnels to port 443]",
+                )
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cache-dir")
+                .long("cache-dir")
+                .value_name("DIR")
+                .help("Where responses are cached [default: <cache dir>/proxy]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-age")
+                .long("max-age")
+                .value_name("SECONDS")
+                .help("Serve cached files this long before revalidating them with their server")
+                .default_value("86400")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cache-size")
+                .long("cache-size")
+                .value_name("SIZE")
+                .help(
+                    "Remove the files used least recently once the cache grows past SIZE, e.g. 50G",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output-file")
+                .short("o")
+                .long("output-file")
+                .value_name("LOGFILE")
+                .help("Also append the requests served, and all other messages, to LOGFILE")
+                .takes_value(true),
+        )
+}
+
+/// Runs the proxy until the process is terminated.
+///
+/// # Errors
+///
+/// Returns an error if the arguments are invalid, the cache cannot be
+/// created, or the address cannot be bound or is reachable from other
+/// machines without `--allow`.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(path) = matches.value_of("output-file") {
+        logfile::open(Path::new(path), true, None)?;
+    }
+    let allow: Vec<String> = matches
+        .values_of("allow")
+        .map(|values| values.map(str::to_ascii_lowercase).collect())
+        .unwrap_or_default();
+    for pattern in &allow {
+        match pattern.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {}
+            _ => return Err(format!("invalid --allow {}: expected HOST:PORT", pattern).into()),
+        }
+    }
+    let dir = matches
+        .value_of("cache-dir")
+        .map_or_else(cache::default_dir, PathBuf::from);
+    let max_size = matches
+        .value_of("cache-size")
+        .map(units::parse_size)
+        .transpose()?;
+    let proxy = Arc::new(Proxy {
+        cache: Cache::open(&dir, max_size)
+            .map_err(|err| format!("cannot create cache {}: {}", dir.display(), err))?,
+        client: Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .no_proxy()
+            .build()?,
+        max_age: Duration::from_secs(matches.value_of("max-age").unwrap().parse()?),
+        allow,
+    });
+    let listen = matches.value_of("listen").unwrap();
+    let listener = TcpListener::bind(listen)?;
+    if !listener.local_addr()?.ip().is_loopback() && proxy.allow.is_empty() {
+        return Err(format!(
+            "{} is reachable from other machines, which could reach any host through \
+             the proxy; list the hosts they may reach with --allow",
+            listen
+        )
+        .into());
+    }
+    logfile::info(&format!(
+        "Proxy listening on http://{} (cache: {})",
+        listen,
+        dir.display()
+    ));
+    serve(proxy, listener);
+    Ok(())
+}
+
+/// Serves every connection accepted by `listener`, each in its own thread.
+fn serve(proxy: Arc<Proxy>, listener: TcpListener) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                logfile::warning(&format!("Warning: failed to accept connection: {}", err));
+                continue;
+            }
+        };
+        let proxy = Arc::clone(&proxy);
+        thread::spawn(move || {
+            if let Err(err) = proxy.handle(stream) {
+                logfile::warning(&format!("Warning: proxy connection failed: {}", err));
+            }
+        });
+    }
+}
+
+impl Proxy {
+    /// Answers the one request of a connection.
+    fn handle(&self, stream: TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut out = stream;
+        let request = match httpd::read_request(&mut reader) {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                return Ok(refuse(&mut out, 400, &err.to_string())?);
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if request.method == "CONNECT" {
+            if !may_connect(&request.path, &self.allow) {
+                logfile::info(&format!("DENIED CONNECT {}", request.path));
+                let message = format!("tunnels to {} are not allowed", request.path);
+                return Ok(refuse(&mut out, 403, &message)?);
+            }
+            return tunnel(&request.path, reader, out);
+        }
+        let Some(url) = target(&request.path) else {
+            return Ok(refuse(
+                &mut out,
+                400,
+                "expected an http:// or https:// URL to fetch",
+            )?);
+        };
+        if !may_fetch(url, &self.allow) {
+            logfile::info(&format!("DENIED {} {}", request.method, redact::url(url)));
+            let message = format!("fetching {} is not allowed", redact::url(url));
+            return Ok(refuse(&mut out, 403, &message)?);
+        }
+        let method = match request.method.as_str() {
+            "GET" => Method::GET,
+            "HEAD" => Method::HEAD,
+            _ => {
+                return Ok(refuse(
+                    &mut out,
+                    501,
+                    "only GET, HEAD, and CONNECT are supported",
+                )?)
+            }
+        };
+        let shared = ["authorization", "cookie", "range"]
+            .iter()
+            .all(|name| request.header(name).is_none());
+        if !shared {
+            logfile::info(&format!("BYPASS {} {}", method, redact::url(url)));
+            return self.forward(&request, url, method, &mut out, false, None);
+        }
+
+        let _turn = self
+            .cache
+            .lock(url)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let cached = self.cache.lookup(url);
+        match cached {
+            Some(entry) if cache::now().saturating_sub(entry.fetched) < self.max_age.as_secs() => {
+                logfile::info(&format!("HIT {} {}", method, redact::url(url)));
+                self.cache.touch(url);
+                self.replay(&entry, method, "HIT", &mut out)
+            }
+            cached => self.forward(&request, url, method, &mut out, true, cached),
+        }
+    }
+
+    /// Sends `request` on to its server, revalidating the `cached` entry if
+    /// there is one, and answers from the cache or with the server's response,
+    /// storing it if the request is `shared` and the response may be.
+    fn forward(
+        &self,
+        request: &Request,
+        url: &str,
+        method: Method,
+        out: &mut TcpStream,
+        shared: bool,
+        cached: Option<Entry>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &request.headers {
+            let name = name.to_ascii_lowercase();
+            // A shared response is the same for every client, uncompressed
+            // and unconditional.
+            let client_specific = name == "accept-encoding" || name.starts_with("if-");
+            if HOP_BY_HOP.contains(&name.as_str()) || name == "host" || (shared && client_specific)
+            {
+                continue;
+            }
+            if let (Ok(name), Ok(value)) = (
+                reqwest::header::HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                headers.append(name, value);
+            }
+        }
+        if let Some(entry) = &cached {
+            entry.revalidate(&mut headers);
+        }
+        let response = match self
+            .client
+            .request(method.clone(), url)
+            .headers(headers)
+            .send()
+        {
+            Ok(response) => response,
+            Err(_) if cached.is_some() => {
+                // Serving the last copy keeps clients working offline.
+                logfile::info(&format!("STALE {} {}", method, redact::url(url)));
+                return self.replay(cached.as_ref().unwrap(), method, "STALE", out);
+            }
+            Err(err) => {
+                return Ok(refuse(out, 502, &format!("cannot fetch {}: {}", url, err))?);
+            }
+        };
+
+        if let (Some(mut entry), StatusCode::NOT_MODIFIED) = (cached, response.status()) {
+            logfile::info(&format!("REVALIDATED {} {}", method, redact::url(url)));
+            entry.fetched = cache::now();
+            self.cache.record(&entry)?;
+            return self.replay(&entry, method, "REVALIDATED", out);
+        }
+        let storable = shared && method == Method::GET && cache::storable(&response);
+        logfile::info(&format!(
+            "{} {} {}",
+            if storable { "MISS" } else { "PASS" },
+            method,
+            redact::url(url)
+        ));
+        let mut head: Vec<(String, String)> = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| !HOP_BY_HOP.contains(&name.as_str()))
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        head.push((
+            "X-Cache".to_string(),
+            if storable { "MISS" } else { "PASS" }.to_string(),
+        ));
+        write_head(out, response.status(), &head)?;
+        if !storable {
+            let mut response = response;
+            io::copy(&mut response, out)?;
+            return Ok(());
+        }
+        let entry = Entry::new(url, &response);
+        let expected = response.content_length();
+        self.cache.store(entry, response, expected, out)?;
+        Ok(())
+    }
+
+    /// Answers with the cached `entry`.
+    fn replay(
+        &self,
+        entry: &Entry,
+        method: Method,
+        status: &str,
+        out: &mut TcpStream,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut head = vec![("Content-Length".to_string(), entry.size.to_string())];
+        let optional = [
+            ("Content-Type", &entry.content_type),
+            ("ETag", &entry.etag),
+            ("Last-Modified", &entry.last_modified),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                head.push((name.to_string(), value.clone()));
+            }
+        }
+        head.push(("X-Cache".to_string(), status.to_string()));
+        write_head(out, StatusCode::OK, &head)?;
+        if method == Method::GET {
+            io::copy(&mut File::open(self.cache.object_path(&entry.digest))?, out)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the URL a request asks the proxy for: its absolute target, or one
+/// appended to the proxy's own URL.
+fn target(path: &str) -> Option<&str> {
+    let url = path.strip_prefix('/').unwrap_or(path);
+    let scheme = url.get(..8)?.to_ascii_lowercase();
+    (scheme.starts_with("http://") || scheme == "https://").then_some(url)
+}
+
+/// Returns whether a tunnel may be opened to `authority`, `HOST:PORT`: to port
+/// 443 if `allow` is empty, and otherwise if one of its patterns matches.
+fn may_connect(authority: &str, allow: &[String]) -> bool {
+    let authority = authority.to_ascii_lowercase();
+    let Some((host, port)) = authority.rsplit_once(':') else {
+        return false;
+    };
+    match allow.is_empty() {
+        true => port == HTTPS_PORT,
+        false => allowed(host, port, allow),
+    }
+}
+
+/// Returns whether `url` may be fetched: any URL if `allow` is empty, and
+/// otherwise one whose host and port one of its patterns matches.
+fn may_fetch(url: &str, allow: &[String]) -> bool {
+    if allow.is_empty() {
+        return true;
+    }
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+    match (url.host_str(), url.port_or_known_default()) {
+        (Some(host), Some(port)) => allowed(host, &port.to_string(), allow),
+        _ => false,
+    }
+}
+
+/// Returns whether one of the `allow` patterns matches `host` and `port`.
+fn allowed(host: &str, port: &str, allow: &[String]) -> bool {
+    allow.iter().any(|pattern| match pattern.rsplit_once(':') {
+        Some((pattern, allowed)) if allowed == port => {
+            pattern == "*"
+                || pattern == host
+                || pattern
+                    .strip_prefix('*')
+                    .is_some_and(|suffix| suffix.starts_with('.') && host.ends_with(suffix))
+        }
+        _ => false,
+    })
+}
+
+/// Connects the client to `authority`, `HOST:PORT`, and relays the bytes of
+/// the connection both ways until either side closes it.
+fn tunnel(
+    authority: &str,
+    reader: BufReader<TcpStream>,
+    mut client: TcpStream,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut upstream = match TcpStream::connect(authority) {
+        Ok(upstream) => upstream,
+        Err(err) => {
+            return Ok(refuse(
+                &mut client,
+                502,
+                &format!("cannot connect to {}: {}", authority, err),
+            )?)
+        }
+    };
+    logfile::info(&format!("TUNNEL {}", authority));
+    client.set_read_timeout(None)?;
+    client.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")?;
+    upstream.write_all(reader.buffer())?;
+    let mut from_client = reader.into_inner();
+    let mut to_upstream = upstream.try_clone()?;
+    let forward = thread::spawn(move || {
+        let _ = io::copy(&mut from_client, &mut to_upstream);
+        let _ = to_upstream.shutdown(std::net::Shutdown::Write);
+    });
+    let _ = io::copy(&mut upstream, &mut client);
+    let _ = client.shutdown(std::net::Shutdown::Write);
+    let _ = forward.join();
+    Ok(())
+}
+
+/// Writes the status line and headers of a response whose body ends when
+/// the connection closes.
+fn write_head<W: Write>(
+    writer: &mut W,
+    status: StatusCode,
+    headers: &[(String, String)],
+) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or("")
+    )?;
+    for (name, value) in headers {
+        write!(writer, "{}: {}\r\n", name, value)?;
+    }
+    writer.write_all(b"Connection: close\r\n\r\n")
+}
+
+/// Answers with an error `message`.
+fn refuse<W: Write>(writer: &mut W, status: u16, message: &str) -> io::Result<()> {
+    httpd::write_response(
+        writer,
+        &Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{}\n", message).into_bytes(),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+
+    #[test]
+    fn test_target() {
+        assert_eq!(
+            target("http://example.com/a.txt"),
+            Some("http://example.com/a.txt")
+        );
+        assert_eq!(
+            target("/https://example.com/a.txt"),
+            Some("https://example.com/a.txt")
+        );
+        assert_eq!(target("/status"), None);
+    }
+
+    #[test]
+    fn test_may_connect() {
+        assert!(may_connect("example.com:443", &[]));
+        assert!(!may_connect("example.com:22", &[]));
+        assert!(!may_connect("example.com", &[]));
+
+        let allow = ["*.example.com:443".to_string(), "git.corp:22".to_string()];
+        assert!(may_connect("cdn.example.com:443", &allow));
+        assert!(may_connect("GIT.corp:22", &allow));
+        assert!(!may_connect("example.com:443", &allow));
+        assert!(!may_connect("badexample.com:443", &allow));
+        assert!(!may_connect("cdn.example.com:8443", &allow));
+        assert!(!may_connect("other.com:443", &allow));
+        assert!(may_connect("10.0.0.1:8080", &["*:8080".to_string()]));
+    }
+
+    #[test]
+    fn test_proxy_refuses_tunnels_not_allowed() {
+        let dir = tempfile::tempdir().unwrap();
+        let proxy = Arc::new(Proxy {
+            cache: Cache::open(dir.path(), None).unwrap(),
+            client: Client::builder().no_proxy().build().unwrap(),
+            max_age: Duration::from_secs(60),
+            allow: Vec::new(),
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve(proxy, listener));
+
+        // An internal service on a port other than 443 is not reachable.
+        let internal = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n",
+            internal.local_addr().unwrap()
+        )
+        .unwrap();
+        let mut response = String::new();
+        io::Read::read_to_string(&mut stream, &mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 403 "), "{}", response);
+    }
+
+    #[test]
+    fn test_may_fetch() {
+        assert!(may_fetch("http://169.254.169.254/latest/meta-data/", &[]));
+
+        let allow = [
+            "*.example.com:80".to_string(),
+            "*.example.com:443".to_string(),
+        ];
+        assert!(may_fetch("http://cdn.example.com/a.txt", &allow));
+        assert!(may_fetch("https://CDN.example.com/a.txt", &allow));
+        assert!(!may_fetch("http://cdn.example.com:8080/a.txt", &allow));
+        assert!(!may_fetch(
+            "http://169.254.169.254/latest/meta-data/",
+            &allow
+        ));
+        assert!(!may_fetch("http://localhost:3000/", &allow));
+    }
+
+    #[test]
+    fn test_proxy_refuses_fetches_not_allowed() {
+        let upstream = mock("GET", "/proxy/internal.txt")
+            .with_body("internal")
+            .expect(0)
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let proxy = Arc::new(Proxy {
+            cache: Cache::open(dir.path(), None).unwrap(),
+            client: Client::builder().no_proxy().build().unwrap(),
+            max_age: Duration::from_secs(60),
+            allow: vec!["*.example.com:443".to_string()],
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve(proxy, listener));
+
+        let client = Client::builder()
+            .proxy(reqwest::Proxy::http(format!("http://{}", address)).unwrap())
+            .build()
+            .unwrap();
+        let url = format!("{}/proxy/internal.txt", server_url());
+        let response = client.get(&url).send().unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = Client::new()
+            .get(format!("http://{}/{}", address, url))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        upstream.assert();
+    }
+
+    #[test]
+    fn test_proxy_serves_from_cache() {
+        let upstream = mock("GET", "/proxy/artifact.tar")
+            .with_header("content-type", "application/x-tar")
+            .with_header("etag", "\"v1\"")
+            .with_body("artifact")
+            .expect(1)
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let proxy = Arc::new(Proxy {
+            cache: Cache::open(dir.path(), None).unwrap(),
+            client: Client::builder().no_proxy().build().unwrap(),
+            max_age: Duration::from_secs(60),
+            allow: Vec::new(),
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve(proxy, listener));
+
+        let client = Client::builder()
+            .proxy(reqwest::Proxy::http(format!("http://{}", address)).unwrap())
+            .build()
+            .unwrap();
+        let url = format!("{}/proxy/artifact.tar", server_url());
+        for status in ["MISS", "HIT"] {
+            let response = client.get(&url).send().unwrap();
+            assert_eq!(response.headers()["x-cache"], status);
+            assert_eq!(response.headers()["etag"], "\"v1\"");
+            assert_eq!(response.text().unwrap(), "artifact");
+        }
+        // The proxy's own URL names the target directly.
+        let response = Client::new()
+            .get(format!("http://{}/{}", address, url))
+            .send()
+            .unwrap();
+        assert_eq!(response.headers()["x-cache"], "HIT");
+        upstream.assert();
+    }
+}
@@ -0,0 +1,561 @@
+//! Persistent download queue shared between worker threads and the daemon API.
+//!
+//! The queue owns the list of jobs and their lifecycle. Every state change is
+//! written to an optional JSON state file so that a restarted daemon picks up
+//! where it left off; jobs that were running at the time are re-queued and
+//! resumed from their partial files.
+
+use crate::download::{self, Control, Options, Outcome};
+use crate::logfile;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Pause between two attempts of a failed job.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Lifecycle state of a queued download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Queued,
+    Running,
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
+    /// Left out on purpose, such as for its size (see [`Outcome::Skipped`]).
+    Skipped,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Status::Queued => "queued",
+            Status::Running => "running",
+            Status::Paused => "paused",
+            Status::Completed => "completed",
+            Status::Failed => "failed",
+            Status::Cancelled => "cancelled",
+            Status::Skipped => "skipped",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A single download managed by the queue.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub url: String,
+    pub output: PathBuf,
+    #[serde(default)]
+    pub priority: i32,
+    pub status: Status,
+    pub downloaded: u64,
+    pub total: Option<u64>,
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Job {
+    /// Whether the job has reached a state it will not leave on its own.
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.status,
+            Status::Completed | Status::Failed | Status::Cancelled | Status::Skipped
+        )
+    }
+}
+
+/// Errors returned when a job cannot be changed as requested.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueueError {
+    /// No job with this id exists.
+    NotFound(u64),
+    /// The job exists but its current status does not allow the change.
+    InvalidState { id: u64, status: Status },
+}
+
+impl fmt::Display for QueueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueueError::NotFound(id) => write!(f, "no job with id {}", id),
+            QueueError::InvalidState { id, status } => write!(f, "job {} is {}", id, status),
+        }
+    }
+}
+
+impl std::error::Error for QueueError {}
+
+/// The serialized form of the queue.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    next_id: u64,
+    jobs: Vec<Job>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    state: State,
+    controls: HashMap<u64, Arc<Control>>,
+    /// Whether [`Queue::close`] was called.
+    closed: bool,
+}
+
+/// A thread-safe, optionally persistent queue of download jobs.
+#[derive(Debug)]
+pub struct Queue {
+    inner: Mutex<Inner>,
+    wakeup: Condvar,
+    path: Option<PathBuf>,
+    /// Most jobs of one host that may run at the same time, if limited.
+    per_host: Option<usize>,
+}
+
+impl Queue {
+    /// Opens a queue, loading previously persisted jobs from `path` if it exists.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: The JSON state file, or `None` for an in-memory queue.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state file exists but cannot be read or parsed.
+    pub fn open(path: Option<PathBuf>) -> Result<Queue, Box<dyn std::error::Error>> {
+        let mut state = State::default();
+        if let Some(path) = path.as_ref().filter(|path| path.exists()) {
+            state = serde_json::from_str(&fs::read_to_string(path)?)?;
+            for job in &mut state.jobs {
+                if job.status == Status::Running {
+                    job.status = Status::Queued;
+                    job.downloaded = fs::metadata(&job.output).map_or(0, |meta| meta.len());
+                }
+            }
+        }
+
+        Ok(Queue {
+            inner: Mutex::new(Inner {
+                state,
+                controls: HashMap::new(),
+                closed: false,
+            }),
+            wakeup: Condvar::new(),
+            path,
+            per_host: None,
+        })
+    }
+
+    /// Limits how many jobs of the same host [`Queue::next`] lets run at once.
+    ///
+    /// Jobs of a host at its limit wait, while jobs of other hosts are
+    /// started in their place.
+    pub fn with_host_limit(mut self, per_host: usize) -> Queue {
+        self.per_host = Some(per_host.max(1));
+        self
+    }
+
+    /// Appends a new job and wakes a worker.
+    ///
+    /// Jobs with a higher `priority` are started before lower ones; jobs of
+    /// equal priority start in the order they were added.
+    pub fn add(&self, url: String, output: PathBuf, priority: i32) -> Job {
+        self.add_partial(url, output, priority, 0)
+    }
+
+    /// Appends a job whose file already holds its first `downloaded` bytes,
+    /// which the job resumes instead of starting over.
+    pub fn add_partial(&self, url: String, output: PathBuf, priority: i32, downloaded: u64) -> Job {
+        let mut inner = self.lock();
+        inner.state.next_id += 1;
+        let job = Job {
+            id: inner.state.next_id,
+            url,
+            output,
+            priority,
+            status: Status::Queued,
+            downloaded,
+            total: None,
+            attempts: 0,
+            error: None,
+        };
+        inner.state.jobs.push(job.clone());
+        self.persist(&inner);
+        self.wakeup.notify_one();
+        job
+    }
+
+    /// Returns a snapshot of every job, with live progress for running ones.
+    pub fn jobs(&self) -> Vec<Job> {
+        let mut inner = self.lock();
+        refresh_progress(&mut inner);
+        inner.state.jobs.clone()
+    }
+
+    /// Returns a snapshot of one job.
+    pub fn job(&self, id: u64) -> Option<Job> {
+        let mut inner = self.lock();
+        refresh_progress(&mut inner);
+        inner.state.jobs.iter().find(|job| job.id == id).cloned()
+    }
+
+    /// Pauses a queued or running job, keeping any partial data.
+    ///
+    /// Running jobs stop at the next chunk boundary; the returned snapshot may
+    /// still show them as running until the worker acknowledges.
+    pub fn pause(&self, id: u64) -> Result<Job, QueueError> {
+        self.change(id, |job, control| match (job.status, control) {
+            (Status::Queued, _) => {
+                job.status = Status::Paused;
+                true
+            }
+            (Status::Running, Some(control)) => {
+                control.pause();
+                true
+            }
+            _ => false,
+        })
+    }
+
+    /// Puts a paused or failed job back into the queue.
+    pub fn resume(&self, id: u64) -> Result<Job, QueueError> {
+        let job = self.change(id, |job, _| match job.status {
+            Status::Paused | Status::Failed => {
+                job.status = Status::Queued;
+                job.error = None;
+                true
+            }
+            _ => false,
+        })?;
+        self.wakeup.notify_one();
+        Ok(job)
+    }
+
+    /// Cancels a job that has not finished yet.
+    pub fn cancel(&self, id: u64) -> Result<Job, QueueError> {
+        self.change(id, |job, control| match (job.status, control) {
+            (Status::Queued, _) | (Status::Paused, _) | (Status::Failed, _) => {
+                job.status = Status::Cancelled;
+                true
+            }
+            (Status::Running, Some(control)) => {
+                control.cancel();
+                true
+            }
+            _ => false,
+        })
+    }
+
+    /// Changes the priority of a job.
+    ///
+    /// Only affects which queued job starts next; running jobs are not preempted.
+    pub fn set_priority(&self, id: u64, priority: i32) -> Result<Job, QueueError> {
+        self.change(id, |job, _| {
+            job.priority = priority;
+            true
+        })
+    }
+
+    /// Whether no job is waiting or running.
+    pub fn is_idle(&self) -> bool {
+        self.lock()
+            .state
+            .jobs
+            .iter()
+            .all(|job| !matches!(job.status, Status::Queued | Status::Running))
+    }
+
+    /// Stops handing out jobs: [`Queue::next`] returns `None` from now on, so
+    /// that workers return once their running jobs end.
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.wakeup.notify_all();
+    }
+
+    /// Blocks until a job is queued whose host is below its limit, marks the
+    /// most urgent one running, and hands it to the caller, or returns `None`
+    /// once the queue is [closed](Queue::close).
+    pub fn next(&self) -> Option<(Job, Arc<Control>)> {
+        let mut inner = self.lock();
+        loop {
+            if inner.closed {
+                return None;
+            }
+            let mut running: HashMap<Option<String>, usize> = HashMap::new();
+            for job in &inner.state.jobs {
+                if job.status == Status::Running {
+                    *running.entry(host(&job.url)).or_default() += 1;
+                }
+            }
+            let per_host = self.per_host.unwrap_or(usize::MAX);
+            if let Some(job) = inner
+                .state
+                .jobs
+                .iter_mut()
+                .filter(|job| job.status == Status::Queued)
+                .filter(|job| {
+                    running
+                        .get(&host(&job.url))
+                        .is_none_or(|count| *count < per_host)
+                })
+                .min_by_key(|job| Reverse(job.priority))
+            {
+                job.status = Status::Running;
+                let job = job.clone();
+                let control = Arc::new(Control::default());
+                inner.controls.insert(job.id, Arc::clone(&control));
+                self.persist(&inner);
+                return Some((job, control));
+            }
+            inner = self.wakeup.wait(inner).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Records that a running job failed and is being attempted again.
+    pub fn retry(&self, id: u64, err: String) {
+        let mut inner = self.lock();
+        if let Some(job) = inner.state.jobs.iter_mut().find(|job| job.id == id) {
+            job.attempts += 1;
+            job.error = Some(err);
+        }
+        self.persist(&inner);
+    }
+
+    /// Records how a running job ended.
+    pub fn finish(&self, id: u64, result: Result<Outcome, String>) {
+        let mut inner = self.lock();
+        let control = inner.controls.remove(&id);
+        if let Some(job) = inner.state.jobs.iter_mut().find(|job| job.id == id) {
+            if let Some(control) = control {
+                job.downloaded = control.downloaded();
+                job.total = control.total();
+            }
+            match result {
+                Ok(Outcome::Completed) => {
+                    job.status = Status::Completed;
+                    job.error = None;
+                }
+                Ok(Outcome::Paused) => job.status = Status::Paused,
+                Ok(Outcome::Cancelled) => job.status = Status::Cancelled,
+                Ok(Outcome::Skipped) => job.status = Status::Skipped,
+                Err(err) => {
+                    job.status = Status::Failed;
+                    job.error = Some(err);
+                }
+            }
+        }
+        self.persist(&inner);
+        // A job of the same host may have been waiting for this one.
+        self.wakeup.notify_all();
+    }
+
+    fn change<F>(&self, id: u64, apply: F) -> Result<Job, QueueError>
+    where
+        F: FnOnce(&mut Job, Option<&Arc<Control>>) -> bool,
+    {
+        let mut inner = self.lock();
+        let Inner {
+            state, controls, ..
+        } = &mut *inner;
+        let job = state
+            .jobs
+            .iter_mut()
+            .find(|job| job.id == id)
+            .ok_or(QueueError::NotFound(id))?;
+        if !apply(job, controls.get(&id)) {
+            return Err(QueueError::InvalidState {
+                id,
+                status: job.status,
+            });
+        }
+        let job = job.clone();
+        self.persist(&inner);
+        Ok(job)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Writes the queue to its state file, if any.
+    ///
+    /// Failures are reported but not fatal: the in-memory queue stays authoritative.
+    fn persist(&self, inner: &Inner) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = serde_json::to_string_pretty(&inner.state)
+            .map_err(|e| e.to_string())
+            .and_then(|json| {
+                let tmp = path.with_extension("tmp");
+                fs::write(&tmp, json)
+                    .and_then(|_| fs::rename(&tmp, path))
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(err) = result {
+            logfile::warning(&format!(
+                "Warning: could not save queue state to {}: {}",
+                path.display(),
+                err
+            ));
+        }
+    }
+}
+
+/// Returns the host of a job's URL, by which concurrent jobs are limited.
+fn host(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()?
+        .host_str()
+        .map(str::to_ascii_lowercase)
+}
+
+fn refresh_progress(inner: &mut Inner) {
+    let Inner {
+        state, controls, ..
+    } = inner;
+    for job in &mut state.jobs {
+        if let Some(control) = controls.get(&job.id) {
+            job.downloaded = control.downloaded();
+            job.total = control.total();
+        }
+    }
+}
+
+/// Processes jobs from `queue` until it is closed.
+///
+/// Jobs that already have data on disk (because they were paused or
+/// interrupted) are resumed with a `Range` request. A failed transfer is
+/// retried, resuming from its partial file, until `options.tries` attempts
+/// were made.
+pub fn run_worker(queue: &Queue, client: &Client, options: &Options) {
+    while let Some((job, control)) = queue.next() {
+        let started = Instant::now();
+        let mut attempt = 1;
+        let result = loop {
+            let resume = job.downloaded > 0 || attempt > 1;
+            match download::fetch(client, &job.url, &job.output, resume, &control, options) {
+                Err(err) if attempt < options.tries => {
+                    queue.retry(job.id, err.to_string());
+                    attempt += 1;
+                    thread::sleep(RETRY_DELAY);
+                }
+                result => break result.map_err(|e| e.to_string()),
+            }
+        };
+        // Posted first, so that the end of the batch is posted after it.
+        if let Some(webhook) = &options.webhook {
+            webhook.download(&job.url, &job.output, &result, started.elapsed());
+        }
+        queue.finish(job.id, result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lifecycle() {
+        let queue = Queue::open(None).unwrap();
+        let job = queue.add("http://example.com/a".to_string(), PathBuf::from("a"), 0);
+        assert_eq!(job.status, Status::Queued);
+
+        assert_eq!(queue.pause(job.id).unwrap().status, Status::Paused);
+        assert_eq!(queue.resume(job.id).unwrap().status, Status::Queued);
+
+        let (running, _control) = queue.next().unwrap();
+        assert_eq!(running.id, job.id);
+        assert_eq!(queue.job(job.id).unwrap().status, Status::Running);
+
+        // A running job is only flagged; the worker reports the outcome.
+        assert_eq!(queue.cancel(job.id).unwrap().status, Status::Running);
+        queue.finish(job.id, Ok(Outcome::Cancelled));
+        assert_eq!(queue.job(job.id).unwrap().status, Status::Cancelled);
+
+        // A closed queue starts no more jobs.
+        queue.add("http://example.com/c".to_string(), PathBuf::from("c"), 0);
+        queue.close();
+        assert!(queue.next().is_none());
+    }
+
+    #[test]
+    fn test_invalid_transitions() {
+        let queue = Queue::open(None).unwrap();
+        let job = queue.add("http://example.com/b".to_string(), PathBuf::from("b"), 0);
+
+        assert_eq!(queue.pause(99), Err(QueueError::NotFound(99)));
+        assert_eq!(
+            queue.resume(job.id),
+            Err(QueueError::InvalidState {
+                id: job.id,
+                status: Status::Queued
+            })
+        );
+    }
+
+    #[test]
+    fn test_priority_changes_pick_order() {
+        let queue = Queue::open(None).unwrap();
+        let low = queue.add("http://example.com/1".to_string(), PathBuf::from("1"), -1);
+        let first = queue.add("http://example.com/2".to_string(), PathBuf::from("2"), 0);
+        let second = queue.add("http://example.com/3".to_string(), PathBuf::from("3"), 0);
+
+        queue.set_priority(second.id, 5).unwrap();
+        assert_eq!(queue.next().unwrap().0.id, second.id);
+        assert_eq!(queue.next().unwrap().0.id, first.id);
+        assert_eq!(queue.next().unwrap().0.id, low.id);
+        assert!(!queue.is_idle());
+
+        queue.finish(first.id, Ok(Outcome::Completed));
+        queue.finish(second.id, Ok(Outcome::Completed));
+        queue.finish(low.id, Err("boom".to_string()));
+        assert!(queue.is_idle());
+        assert!(queue.jobs().iter().all(Job::is_finished));
+    }
+
+    #[test]
+    fn test_host_limit_starts_other_hosts_first() {
+        let queue = Queue::open(None).unwrap().with_host_limit(1);
+        let first = queue.add("http://slow.example/1".to_string(), PathBuf::from("1"), 1);
+        let second = queue.add("http://SLOW.example/2".to_string(), PathBuf::from("2"), 1);
+        let other = queue.add("http://fast.example/3".to_string(), PathBuf::from("3"), 0);
+
+        assert_eq!(queue.next().unwrap().0.id, first.id);
+        assert_eq!(queue.next().unwrap().0.id, other.id);
+        queue.finish(first.id, Ok(Outcome::Completed));
+        assert_eq!(queue.next().unwrap().0.id, second.id);
+    }
+
+    #[test]
+    fn test_state_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("queue.json");
+
+        let queue = Queue::open(Some(state.clone())).unwrap();
+        let first = queue.add("http://example.com/c".to_string(), PathBuf::from("c"), 0);
+        queue.add("http://example.com/d".to_string(), PathBuf::from("d"), 0);
+        let (running, _) = queue.next().unwrap();
+        assert_eq!(running.id, first.id);
+        drop(queue);
+
+        let reopened = Queue::open(Some(state)).unwrap();
+        let jobs = reopened.jobs();
+        assert_eq!(jobs.len(), 2);
+        assert!(jobs.iter().all(|job| job.status == Status::Queued));
+
+        let third = reopened.add("http://example.com/e".to_string(), PathBuf::from("e"), 0);
+        assert_eq!(third.id, 3);
+    }
+}
@@ -0,0 +1,222 @@
+//! Bearer tokens issued by the realm named in a challenge.
+//!
+//! Container registries and many artifact APIs answer a request without a
+//! token with `401 Unauthorized` and a challenge naming where to get one:
+//!
+//! ```text
+//! WWW-Authenticate: Bearer realm="https://auth.example.com/token",service="registry.example.com",scope="repository:team/app:pull"
+//! ```
+//!
+//! The token is requested from the realm with the service and scope as query
+//! parameters, and Basic credentials if the user gave some, and the request
+//! is repeated with it. Tokens are kept for the run by realm, service, and
+//! scope until they expire, and the last one a host accepted is sent to it
+//! up front, so that a batch of URLs on one service fetches its token once.
+//! A token the server rejects is requested again.
+
+use crate::auth::{self, Credentials};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::WWW_AUTHENTICATE;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Lifetime of a token whose response does not give one, as in the Docker
+/// registry token specification.
+const DEFAULT_LIFETIME: u64 = 60;
+
+/// How long before its expiry a token is already requested again.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(5);
+
+/// Where to get a token, from a `Bearer` challenge.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Challenge {
+    pub realm: String,
+    pub service: Option<String>,
+    pub scope: Option<String>,
+}
+
+impl Challenge {
+    /// Parses a `WWW-Authenticate` value such as
+    /// `Bearer realm="https://auth.example.com/token",scope="x"`, which must
+    /// name a realm.
+    pub fn parse(header: &str) -> Option<Challenge> {
+        let (scheme, parameters) = header.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return None;
+        }
+        let mut values = HashMap::new();
+        let mut rest = parameters.trim();
+        while let Some((name, after)) = rest.split_once('=') {
+            let name = name
+                .trim()
+                .trim_start_matches(',')
+                .trim()
+                .to_ascii_lowercase();
+            let (value, after) = match after.strip_prefix('"') {
+                Some(quoted) => {
+                    let end = quoted.find('"')?;
+                    (&quoted[..end], &quoted[end + 1..])
+                }
+                None => after.split_once(',').unwrap_or((after, "")),
+            };
+            values.insert(name, value.trim().to_string());
+            rest = after.trim_start_matches([',', ' ']);
+        }
+        Some(Challenge {
+            realm: values.remove("realm")?,
+            service: values.remove("service"),
+            scope: values.remove("scope"),
+        })
+    }
+}
+
+/// A token as a realm returns it; Docker registries name it `token`, OAuth
+/// servers `access_token`.
+#[derive(Debug, Deserialize)]
+struct Issued {
+    token: Option<String>,
+    access_token: Option<String>,
+    expires_in: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    value: String,
+    expires: Instant,
+}
+
+/// The tokens obtained during a run.
+#[derive(Debug, Default)]
+pub struct Tokens {
+    tokens: Mutex<HashMap<Challenge, Token>>,
+    /// The challenge each host was last answered with, by [`auth::host_key`].
+    hosts: Mutex<HashMap<String, Challenge>>,
+}
+
+impl Tokens {
+    /// Returns the token to send to the host of `url` up front, if it
+    /// accepted one that has not expired.
+    pub fn current(&self, url: &str) -> Option<String> {
+        let host = auth::host_key(&Url::parse(url).ok()?)?;
+        let challenge = self.hosts.lock().unwrap().get(&host)?.clone();
+        let tokens = self.tokens.lock().unwrap();
+        let token = tokens.get(&challenge)?;
+        (Instant::now() < token.expires).then(|| token.value.clone())
+    }
+
+    /// Returns a token answering the `Bearer` challenge of `response` to a
+    /// request for `url`, or `None` if it has none.
+    ///
+    /// A cached token is used unless it is `rejected`, the one the request
+    /// was sent with. Basic `credentials` are sent to the realm if it is on
+    /// the host of `url` or uses HTTPS.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the realm does not issue a token.
+    pub fn answer(
+        &self,
+        client: &Client,
+        url: &str,
+        response: &Response,
+        credentials: Option<&Credentials>,
+        rejected: Option<&str>,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let Some(challenge) = response
+            .headers()
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(Challenge::parse)
+        else {
+            return Ok(None);
+        };
+        let target = Url::parse(url)?;
+        if let Some(host) = auth::host_key(&target) {
+            self.hosts.lock().unwrap().insert(host, challenge.clone());
+        }
+        let cached = self.tokens.lock().unwrap().get(&challenge).cloned();
+        if let Some(token) = cached.filter(|token| {
+            Instant::now() < token.expires && Some(token.value.as_str()) != rejected
+        }) {
+            return Ok(Some(token.value));
+        }
+        let token = self.request(client, &target, &challenge, credentials)?;
+        let value = token.value.clone();
+        self.tokens.lock().unwrap().insert(challenge, token);
+        Ok(Some(value))
+    }
+
+    /// Requests a token for `challenge` from its realm.
+    fn request(
+        &self,
+        client: &Client,
+        target: &Url,
+        challenge: &Challenge,
+        credentials: Option<&Credentials>,
+    ) -> Result<Token, Box<dyn std::error::Error>> {
+        let mut realm = Url::parse(&challenge.realm)
+            .map_err(|err| format!("invalid token realm '{}': {}", challenge.realm, err))?;
+        {
+            let mut query = realm.query_pairs_mut();
+            if let Some(service) = &challenge.service {
+                query.append_pair("service", service);
+            }
+            if let Some(scope) = &challenge.scope {
+                query.append_pair("scope", scope);
+            }
+        }
+        let mut request = client.get(realm.clone());
+        let trusted = realm.scheme() == "https" || realm.origin() == target.origin();
+        if let Some(Credentials::Basic { user, password }) = credentials.filter(|_| trusted) {
+            request = request.basic_auth(user, Some(password));
+        }
+        let response = request
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| format!("cannot get a token from {}: {}", challenge.realm, err))?;
+        let issued: Issued = serde_json::from_reader(response)
+            .map_err(|err| format!("invalid token from {}: {}", challenge.realm, err))?;
+        let value = issued
+            .token
+            .or(issued.access_token)
+            .ok_or_else(|| format!("{} issued no token", challenge.realm))?;
+        let lifetime = Duration::from_secs(issued.expires_in.unwrap_or(DEFAULT_LIFETIME));
+        Ok(Token {
+            value,
+            expires: Instant::now() + lifetime.saturating_sub(EXPIRY_MARGIN),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_challenge() {
+        assert_eq!(
+            Challenge::parse(
+                r#"Bearer realm="https://auth.example.com/token",service="registry.example.com",scope="repository:team/app:pull,push""#
+            ),
+            Some(Challenge {
+                realm: "https://auth.example.com/token".to_string(),
+                service: Some("registry.example.com".to_string()),
+                scope: Some("repository:team/app:pull,push".to_string()),
+            })
+        );
+        assert_eq!(
+            Challenge::parse("bearer realm=https://auth.example.com/token, error=invalid_token"),
+            Some(Challenge {
+                realm: "https://auth.example.com/token".to_string(),
+                service: None,
+                scope: None,
+            })
+        );
+        assert_eq!(Challenge::parse(r#"Bearer error="invalid_token""#), None);
+        assert_eq!(Challenge::parse(r#"Basic realm="files""#), None);
+    }
+}
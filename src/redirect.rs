@@ -0,0 +1,126 @@
+//! How HTTP redirects are followed.
+//!
+//! Relative `Location` headers are resolved against the URL that returned
+//! them. A chain that comes back to a URL it already visited is stopped at
+//! once with an error showing the loop, instead of running into the limit,
+//! and `--max-redirect` sets that limit (0 does not follow redirects at all).
+//! Every hop is written to the log file, and with `--verbose` also printed
+//! as it is followed.
+
+use crate::logfile;
+use reqwest::redirect::{Attempt, Policy};
+
+/// Redirects followed per request unless `--max-redirect` says otherwise.
+pub const DEFAULT_MAX: usize = 20;
+
+/// Builds the redirect policy of the HTTP client.
+///
+/// # Arguments
+///
+/// * `max`: The number of redirects to follow per request.
+/// * `verbose`: Whether to print each redirect to standard error.
+pub fn policy(max: usize, verbose: bool) -> Policy {
+    Policy::custom(move |attempt| follow(attempt, max, verbose))
+}
+
+fn follow(attempt: Attempt, max: usize, verbose: bool) -> reqwest::redirect::Action {
+    if max == 0 {
+        return attempt.stop();
+    }
+    let previous = attempt.previous();
+    if previous.len() > max {
+        let hops = chain(previous, attempt.url().as_str());
+        return attempt.error(format!("more than {} redirects: {}", max, hops));
+    }
+    if let Some(start) = previous.iter().position(|url| url == attempt.url()) {
+        let cycle = chain(&previous[start..], attempt.url().as_str());
+        return attempt.error(format!("redirect loop: {}", cycle));
+    }
+    if let Some(from) = previous.last() {
+        let hop = format!(
+            "Redirect {}: {} -> {} ({})",
+            previous.len(),
+            from,
+            attempt.url(),
+            attempt.status()
+        );
+        match verbose {
+            true => logfile::notice(&hop),
+            false => logfile::detail(&hop),
+        }
+    }
+    attempt.follow()
+}
+
+/// Formats visited URLs and the next one as `a -> b -> c`.
+fn chain(visited: &[url::Url], next: &str) -> String {
+    visited
+        .iter()
+        .map(|url| url.as_str())
+        .chain([next])
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+    use reqwest::blocking::Client;
+
+    fn client(max: usize) -> Client {
+        Client::builder()
+            .redirect(policy(max, false))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_follows_relative_redirects() {
+        let first = mock("GET", "/redirect/start")
+            .with_status(302)
+            .with_header("location", "next/../file.txt?x=1")
+            .create();
+        let second = mock("GET", "/redirect/file.txt?x=1")
+            .with_status(200)
+            .with_body("moved")
+            .create();
+
+        let response = client(DEFAULT_MAX)
+            .get(format!("{}/redirect/start", server_url()))
+            .send()
+            .unwrap();
+
+        assert_eq!(response.url().path(), "/redirect/file.txt");
+        assert_eq!(response.text().unwrap(), "moved");
+        first.assert();
+        second.assert();
+    }
+
+    #[test]
+    fn test_stops_redirect_loops_and_long_chains() {
+        let _ping = mock("GET", "/redirect/ping")
+            .with_status(301)
+            .with_header("location", "/redirect/pong")
+            .create();
+        let _pong = mock("GET", "/redirect/pong")
+            .with_status(307)
+            .with_header("location", "ping")
+            .create();
+        let url = format!("{}/redirect/ping", server_url());
+
+        let err = client(DEFAULT_MAX).get(&url).send().unwrap_err();
+        assert!(err.to_string().contains("redirect loop"), "{}", err);
+        assert!(format!("{:?}", err).contains("pong -> "), "{:?}", err);
+
+        let err = client(1).get(&url).send().unwrap_err();
+        assert!(
+            format!("{:?}", err).contains("more than 1 redirects"),
+            "{:?}",
+            err
+        );
+
+        let response = client(0).get(&url).send().unwrap();
+        assert_eq!(response.status(), 301);
+    }
+}
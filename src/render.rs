@@ -0,0 +1,365 @@
+//! Rendering pages that build their content with JavaScript.
+//!
+//! Single-page applications often serve an almost empty HTML page and add
+//! their links with JavaScript, so that reading the page finds nothing to
+//! follow. With `--render`, pages are instead loaded in a headless Chromium,
+//! and the document it ends up with once the scripts have run is read. The
+//! browser is the one given with `--browser`, or the first of [`BROWSERS`]
+//! found on the `PATH`. Pages get [`DEFAULT_BUDGET`] of virtual time to settle,
+//! and a browser still running after [`DEFAULT_TIMEOUT`] is killed. The
+//! browser makes its own requests, without the credentials and headers of the
+//! run.
+//!
+//! `rustwget check --render` reads the links of pages from their rendering.
+//! Downloads with `--render` save the rendering of every HTML page instead of
+//! the page served, and once they end, download the scripts, stylesheets,
+//! images, and other [`requisites`] of the pages saved from the same site,
+//! below the directory of their page, so that `--convert-links` can point
+//! the pages at them. The rendering is read from the DOM the browser dumps,
+//! rather than driven over the DevTools protocol, so requests the scripts only
+//! make later, as the user scrolls or clicks, are not seen.
+
+use crate::content;
+use crate::crawl;
+use crate::filename::{self, Restriction};
+use crate::share;
+use percent_encoding::percent_decode_str;
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Names of Chromium builds looked for on the `PATH`, in order.
+pub const BROWSERS: [&str; 5] = [
+    "chromium",
+    "chromium-browser",
+    "google-chrome",
+    "google-chrome-stable",
+    "chrome",
+];
+
+/// How long scripts may run, in the browser's virtual time, before the page is read.
+pub const DEFAULT_BUDGET: Duration = Duration::from_secs(5);
+
+/// How long the browser may take to render a page before it is killed.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often a running browser is checked for having exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A headless browser that renders pages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Browser {
+    path: PathBuf,
+    budget: Duration,
+    timeout: Duration,
+}
+
+impl Browser {
+    /// Finds the browser at `path`, or the first of [`BROWSERS`] on the `PATH`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no browser is found.
+    pub fn find(path: Option<&Path>) -> Result<Browser, String> {
+        let path = match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => BROWSERS.iter().find_map(|name| search_path(name)),
+        };
+        match path {
+            Some(path) => Ok(Browser {
+                path,
+                budget: DEFAULT_BUDGET,
+                timeout: DEFAULT_TIMEOUT,
+            }),
+            None => Err(format!(
+                "--render needs Chromium: install one of {} or give its path with --browser",
+                BROWSERS.join(", ")
+            )),
+        }
+    }
+
+    /// Returns the document of the page at `url` once its scripts have run.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the browser cannot be started, fails, or is still
+    /// running after its timeout, when it is killed.
+    pub fn render(&self, url: &str) -> Result<String, String> {
+        let mut command = Command::new(&self.path);
+        command
+            .args([
+                "--headless=new",
+                "--disable-gpu",
+                "--no-first-run",
+                "--dump-dom",
+            ])
+            .arg(format!("--virtual-time-budget={}", self.budget.as_millis()))
+            .arg(url)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+        // Chromium starts helper processes, which are killed with it as a group.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = command
+            .spawn()
+            .map_err(|err| format!("cannot start {}: {}", self.path.display(), err))?;
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let reader = thread::spawn(move || {
+            let mut dom = Vec::new();
+            stdout.read_to_end(&mut dom).map(|_| dom)
+        });
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+                Ok(None) => {
+                    kill(&mut child);
+                    return Err(format!(
+                        "{} did not render {} within {} seconds",
+                        self.path.display(),
+                        url,
+                        self.timeout.as_secs()
+                    ));
+                }
+                Err(err) => {
+                    kill(&mut child);
+                    return Err(format!("cannot wait for {}: {}", self.path.display(), err));
+                }
+            }
+        };
+        // Helpers left behind would keep the output open.
+        kill(&mut child);
+        if !status.success() {
+            return Err(format!(
+                "{} failed to render {} with {}",
+                self.path.display(),
+                url,
+                status
+            ));
+        }
+        let dom = reader
+            .join()
+            .map_err(|_| "the browser output could not be read".to_string())?
+            .map_err(|err| format!("cannot read the rendering of {}: {}", url, err))?;
+        Ok(String::from_utf8_lossy(&dom).into_owned())
+    }
+}
+
+/// Kills the browser `child` and the processes it started, and reaps it.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    if let Ok(group) = libc::pid_t::try_from(child.id()) {
+        // The browser leads a process group of its own.
+        unsafe { libc::kill(-group, libc::SIGKILL) };
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Returns the resources from the site of `base` that the HTML `page` at
+/// `base` loads to be shown: the `src` and `srcset` of its elements, the
+/// `href` of its `<link>` elements, such as stylesheets and icons, and the
+/// `url(...)`s of its styles. Links to other pages are left out.
+pub fn requisites(page: &str, base: &Url) -> Vec<Url> {
+    let lower = page.to_ascii_lowercase();
+    let mut seen = HashSet::new();
+    let mut found = Vec::new();
+    for reference in crawl::html_references(page) {
+        let before = lower[..reference.start].trim_end_matches(['"', '\'', ' ', '=']);
+        if before.ends_with("href") {
+            let tag = before.rfind('<').map_or("", |start| &lower[start + 1..]);
+            if !tag.starts_with("link") || !tag[4..].starts_with(char::is_whitespace) {
+                continue;
+            }
+        }
+        let Some(mut url) = crawl::resolve(base, &share::unescape(reference.text)) else {
+            continue;
+        };
+        url.set_fragment(None);
+        if url.origin() == base.origin() && url != *base && seen.insert(url.clone()) {
+            found.push(url);
+        }
+    }
+    found
+}
+
+/// Returns where the requisite at `url` of the page at `page`, saved as
+/// `saved`, is saved: at its path below the directory of the page, so that
+/// `https://example.com/app/js/main.js` of `https://example.com/app/` goes to
+/// `js/main.js` beside the page. Requisites outside that directory, or whose
+/// path leaves no file name, have no place.
+pub fn requisite_path(
+    page: &Url,
+    saved: &Path,
+    url: &Url,
+    restriction: Restriction,
+) -> Option<PathBuf> {
+    let dir = &page.path()[..page.path().rfind('/')? + 1];
+    let rest = url.path().strip_prefix(dir)?;
+    let mut path = saved.parent().unwrap_or(Path::new("")).to_path_buf();
+    for segment in rest.split('/') {
+        let segment = percent_decode_str(segment).decode_utf8_lossy();
+        if segment == "." || segment == ".." {
+            return None;
+        }
+        path.push(filename::sanitize(&segment, restriction)?);
+    }
+    Some(path)
+}
+
+/// Returns the requisites of the HTML pages among `saved`, the URLs a run
+/// saved with the files it saved them to, paired with the files they are
+/// saved to, leaving out those saved already.
+pub fn downloads(saved: &[(String, PathBuf)], restriction: Restriction) -> Vec<(String, PathBuf)> {
+    let mut taken: HashSet<PathBuf> = saved.iter().map(|(_, path)| path.clone()).collect();
+    let mut urls: HashSet<String> = saved.iter().map(|(url, _)| url.clone()).collect();
+    let mut found = Vec::new();
+    for (url, path) in saved {
+        let Ok(page) = Url::parse(url) else {
+            continue;
+        };
+        let Ok(bytes) = fs::read(path) else {
+            continue;
+        };
+        if !content::looks_like_html(&bytes) {
+            continue;
+        }
+        for requisite in requisites(&String::from_utf8_lossy(&bytes), &page) {
+            let Some(target) = requisite_path(&page, path, &requisite, restriction) else {
+                continue;
+            };
+            if !urls.contains(requisite.as_str()) && taken.insert(target.clone()) {
+                urls.insert(requisite.to_string());
+                found.push((requisite.to_string(), target));
+            }
+        }
+    }
+    found
+}
+
+/// Returns the path of the program `name` in a directory of the `PATH`, if any.
+fn search_path(name: &str) -> Option<PathBuf> {
+    let file = match cfg!(windows) {
+        true => format!("{}.exe", name),
+        false => name.to_string(),
+    };
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(&file))
+        .find(|path| path.is_file())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_reads_dumped_dom() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fake-chromium");
+        // Prints the URL it was given, which comes last, inside a link.
+        std::fs::write(
+            &path,
+            "#!/bin/sh\nfor arg; do url=$arg; done\necho \"<a href=\\\"$url/app\\\">app</a>\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let browser = Browser::find(Some(&path)).unwrap();
+        assert_eq!(
+            browser.render("https://example.com").unwrap(),
+            "<a href=\"https://example.com/app\">app</a>\n"
+        );
+        let missing = Browser::find(Some(&dir.path().join("none"))).unwrap();
+        assert!(missing.render("https://example.com").is_err());
+    }
+
+    #[test]
+    fn test_render_kills_hung_browser() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fake-chromium");
+        std::fs::write(
+            &path,
+            "#!/bin/sh
+sleep 30
+",
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let browser = Browser {
+            timeout: Duration::from_millis(200),
+            ..Browser::find(Some(&path)).unwrap()
+        };
+        let started = Instant::now();
+        let err = browser.render("https://example.com").unwrap_err();
+        assert!(err.contains("did not render https://example.com"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_requisites_and_their_paths() {
+        let base = Url::parse("https://example.com/app/").unwrap();
+        let page = "<html><head><link rel=\"stylesheet\" href=\"css/app.css\">\
+            <script src=\"/app/js/main.js?v=2\"></script>\
+            <script src=\"https://cdn.example.net/lib.js\"></script></head>\
+            <body style=\"background: url(img/bg.png)\"><a href=\"about\">About</a>\
+            <img srcset=\"img/a.png 1x, img/a@2x.png 2x\"><img src=\"/logo.png\"></body></html>";
+        let found: Vec<String> = requisites(page, &base).iter().map(Url::to_string).collect();
+        assert_eq!(
+            found,
+            [
+                "https://example.com/app/css/app.css",
+                "https://example.com/app/js/main.js?v=2",
+                "https://example.com/app/img/bg.png",
+                "https://example.com/app/img/a.png",
+                "https://example.com/app/img/a@2x.png",
+                "https://example.com/logo.png",
+            ]
+        );
+
+        let saved = Path::new("site/index.html");
+        let path =
+            |url: &str| requisite_path(&base, saved, &Url::parse(url).unwrap(), Restriction::Unix);
+        assert_eq!(
+            path("https://example.com/app/js/main.js?v=2"),
+            Some(PathBuf::from("site/js/main.js"))
+        );
+        assert_eq!(path("https://example.com/logo.png"), None);
+        assert_eq!(path("https://example.com/app/js/"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let index = dir.path().join("index.html");
+        std::fs::write(&index, page).unwrap();
+        let css = dir.path().join("css/app.css");
+        let saved = [
+            (base.to_string(), index),
+            ("https://example.com/app/css/app.css".to_string(), css),
+        ];
+        let urls: Vec<String> = downloads(&saved, Restriction::Unix)
+            .into_iter()
+            .map(|(url, _)| url)
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/app/js/main.js?v=2",
+                "https://example.com/app/img/bg.png",
+                "https://example.com/app/img/a.png",
+                "https://example.com/app/img/a@2x.png",
+            ]
+        );
+    }
+}
@@ -0,0 +1,310 @@
+//! The summary of a batch of downloads.
+//!
+//! When several URLs are downloaded, a summary is printed at the end: how
+//! many files succeeded, failed, or were skipped, how much was transferred in
+//! how long, and which URLs failed. The same summary is saved as JSON in the
+//! data directory, and with `--report FILE` also to `FILE`.
+//!
+//! `rustwget retry-failed [REPORT]` reads such a report, the last one saved
+//! if none is given, and downloads its failed URLs again to the same files
+//! with the options of the original run. As in a recorded
+//! [`session`](crate::session), the values of secret options are not saved but
+//! asked for by the retry, and the file is readable by its owner only.
+
+use crate::i18n;
+use crate::input::Entry;
+use crate::paths;
+use crate::queue::{Job, Status};
+use crate::redact;
+use crate::session;
+use crate::units;
+use clap::{App, Arg, SubCommand};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Returns the path where the report of the last run is saved.
+pub fn default_path() -> PathBuf {
+    paths::data_dir().join("last-report.json")
+}
+
+/// Defines the `retry-failed` subcommand.
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("retry-failed")
+        .about("Download the URLs that failed in an earlier run again, with the same options")
+        .arg(
+            Arg::with_name("REPORT")
+                .help("Report written by the run, e.g. with --report [default: the last run's]")
+                .index(1),
+        )
+}
+
+/// A download that did not succeed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Failure {
+    pub url: String,
+    /// Absolute path of the file, so that a retry writes the same one.
+    pub output: PathBuf,
+    #[serde(default)]
+    pub priority: i32,
+    pub error: String,
+}
+
+/// What happened during one run.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Downloads skipped on purpose, before they started or for what they held.
+    pub skipped: usize,
+    /// Downloads cancelled or paused before they ended.
+    #[serde(default)]
+    pub cancelled: usize,
+    /// Bytes received over all downloads.
+    pub bytes: u64,
+    pub elapsed_seconds: f64,
+    /// Bytes per second over the whole run.
+    pub average_speed: f64,
+    pub failures: Vec<Failure>,
+    /// Command-line arguments of the run, which a retry repeats; secrets are
+    /// hidden when it is written.
+    #[serde(default)]
+    pub arguments: Vec<String>,
+    /// The URLs downloaded, with the files they were saved to; not saved.
+    #[serde(skip)]
+    pub completed: Vec<(String, PathBuf)>,
+}
+
+impl Report {
+    /// Summarizes the final state of `jobs`, which ran for `elapsed`.
+    pub fn new(jobs: &[Job], elapsed: Duration) -> Report {
+        let mut report = Report::default();
+        for job in jobs {
+            report.bytes += job.downloaded;
+            match job.status {
+                Status::Completed => {
+                    report.succeeded += 1;
+                    report.completed.push((job.url.clone(), job.output.clone()));
+                }
+                Status::Failed => {
+                    report.failed += 1;
+                    report.failures.push(Failure {
+                        url: job.url.clone(),
+                        output: std::path::absolute(&job.output)
+                            .unwrap_or_else(|_| job.output.clone()),
+                        priority: job.priority,
+                        error: redact::text(job.error.as_deref().unwrap_or_default()).into_owned(),
+                    });
+                }
+                Status::Skipped => report.skipped += 1,
+                _ => report.cancelled += 1,
+            }
+        }
+        report.elapsed_seconds = elapsed.as_secs_f64();
+        if report.elapsed_seconds > 0.0 {
+            report.average_speed = report.bytes as f64 / report.elapsed_seconds;
+        }
+        report
+    }
+
+    /// Formats the report for the terminal.
+    pub fn summary(&self) -> String {
+        let mut summary = i18n::text(
+            "summary",
+            &[
+                ("succeeded", &self.succeeded),
+                ("failed", &self.failed),
+                ("skipped", &self.skipped),
+                ("cancelled", &self.cancelled),
+                ("bytes", &units::format_bytes(self.bytes)),
+                ("seconds", &format!("{:.1}", self.elapsed_seconds)),
+                ("rate", &units::format_rate(self.average_speed)),
+            ],
+        ) + "\n";
+        if !self.failures.is_empty() {
+            summary += &i18n::text("summary.failed-urls", &[]);
+            summary.push('\n');
+            for failure in &self.failures {
+                summary += &format!("  {}\n", failure.url);
+            }
+        }
+        summary
+    }
+
+    /// Reads a report written by [`Report::write`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not a report.
+    pub fn read(path: &Path) -> Result<Report, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("cannot read report {}: {}", path.display(), err))?;
+        serde_json::from_str(&text)
+            .map_err(|err| format!("invalid report {}: {}", path.display(), err).into())
+    }
+
+    /// Writes the report to `path` as JSON, with the values of secret options
+    /// hidden, readable by its owner only.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let report = Report {
+            arguments: session::hide_secrets(self.arguments.clone()),
+            failures: self.failures.clone(),
+            completed: Vec::new(),
+            ..*self
+        };
+        let text = serde_json::to_string_pretty(&report)?;
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        // An earlier report may have been created with looser permissions.
+        let _ = fs::remove_file(path);
+        options
+            .open(path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .map_err(|err| format!("cannot write report {}: {}", path.display(), err).into())
+    }
+
+    /// Returns the failed downloads as entries to download again.
+    pub fn entries(&self) -> Vec<Entry> {
+        self.failures
+            .iter()
+            .map(|failure| Entry {
+                url: failure.url.clone(),
+                priority: failure.priority,
+                output: Some(failure.output.clone()),
+            })
+            .collect()
+    }
+
+    /// Returns an error naming how many downloads failed, if any did.
+    pub fn check(&self) -> Result<(), String> {
+        match self.failed {
+            0 => Ok(()),
+            failed => Err(format!(
+                "{} of {} downloads failed",
+                failed,
+                self.succeeded + self.failed + self.skipped
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_counts_jobs() {
+        let job = |id, status, downloaded| Job {
+            id,
+            url: format!("https://example.com/{}", id),
+            output: PathBuf::from(id.to_string()),
+            priority: 0,
+            status,
+            downloaded,
+            total: None,
+            attempts: 1,
+            error: (status == Status::Failed).then(|| "HTTP 404".to_string()),
+        };
+        let jobs = [
+            job(1, Status::Completed, 3000),
+            job(2, Status::Failed, 0),
+            job(3, Status::Cancelled, 1000),
+            job(4, Status::Skipped, 0),
+        ];
+
+        let report = Report::new(&jobs, Duration::from_secs(2));
+        assert_eq!(
+            (
+                report.succeeded,
+                report.failed,
+                report.skipped,
+                report.cancelled
+            ),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(report.bytes, 4000);
+        assert_eq!(report.average_speed, 2000.0);
+        assert_eq!(report.failures[0].url, "https://example.com/2");
+        assert!(report.failures[0].output.is_absolute());
+        assert_eq!(
+            report.completed,
+            [("https://example.com/1".to_string(), PathBuf::from("1"))]
+        );
+        assert!(report
+            .summary()
+            .contains("Failed URLs:\n  https://example.com/2\n"));
+        assert_eq!(report.check().unwrap_err(), "1 of 3 downloads failed");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        report.write(&path).unwrap();
+        let saved = Report {
+            completed: Vec::new(),
+            ..report.clone()
+        };
+        assert_eq!(Report::read(&path).unwrap(), saved);
+        assert_eq!(
+            report.entries(),
+            [Entry {
+                url: "https://example.com/2".to_string(),
+                priority: 0,
+                output: Some(report.failures[0].output.clone()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_report_hides_secrets() {
+        let url = "https://example.com/a.txt?token=t0ps3cret";
+        let job = Job {
+            id: 1,
+            url: url.to_string(),
+            output: PathBuf::from("a.txt"),
+            priority: 0,
+            status: Status::Failed,
+            downloaded: 0,
+            total: None,
+            attempts: 1,
+            error: Some(format!("error sending request for url ({})", url)),
+        };
+        let mut report = Report::new(&[job], Duration::from_secs(1));
+        report.arguments = ["--user", "me", "--password", "hunter2", "--bearer=abc123"]
+            .iter()
+            .map(|argument| argument.to_string())
+            .collect();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        fs::write(&path, "").unwrap();
+        report.write(&path).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        for secret in ["hunter2", "abc123"] {
+            assert!(!text.contains(secret), "{} in {}", secret, text);
+        }
+        // The URL is kept to retry it, but the error does not repeat its token.
+        let read = Report::read(&path).unwrap();
+        assert_eq!(read.failures[0].url, url);
+        assert!(!read.failures[0].error.contains("t0ps3cret"));
+        assert_eq!(read.arguments[..3], ["--user", "me", "--password"]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! The control file of a segmented download.
+//!
+//! While a file is downloaded over several connections (see
+//! [`segment`](crate::segment)), `FILE.rustwget` records the URL, the size and
+//! validators of the resource, and the byte ranges already written, as JSON:
+//!
+//! ```text
+//! {"url":"https://example.com/big.iso","size":4700000000,"etag":"\"5e1-3f\"",
+//!  "last_modified":"Wed, 21 Oct 2015 07:28:00 GMT","done":[{"start":0,"end":1048576}]}
+//! ```
+//!
+//! It is saved as each piece completes and when the download stops, so that a
+//! later run, on this machine or on another one the file and its control file
+//! were copied to, fetches only the missing ranges. The ranges are kept only
+//! while the server still reports the same size and validators. A download
+//! that goes on over a single connection keeps just the ranges that form a
+//! prefix of the file. The control file is removed once the download completes.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Returns the path of the control file of `file`.
+pub fn path(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".rustwget");
+    PathBuf::from(name)
+}
+
+/// What a segmented download has written so far.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    pub url: String,
+    /// The full size of the resource.
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    /// The byte ranges written, sorted and not touching each other.
+    pub done: Vec<Range<u64>>,
+    /// The `Content-Type` of the resource, if known; not saved.
+    #[serde(skip)]
+    pub content_type: Option<String>,
+}
+
+impl State {
+    /// Reads the control file of `file`, if it has one that can be read.
+    pub fn load(file: &Path) -> Option<State> {
+        serde_json::from_str(&fs::read_to_string(path(file)).ok()?).ok()
+    }
+
+    /// Saves the state as the control file of `file`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the control file cannot be written.
+    pub fn save(&self, file: &Path) -> io::Result<()> {
+        let target = path(file);
+        let mut tmp = target.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(&tmp, &target)
+    }
+
+    /// Whether `other` describes the same version of the resource.
+    ///
+    /// Validators that only one side knows are not compared.
+    pub fn same_resource(&self, other: &State) -> bool {
+        let same = |a: &Option<String>, b: &Option<String>| match (a, b) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+        self.size == other.size
+            && same(&self.etag, &other.etag)
+            && same(&self.last_modified, &other.last_modified)
+    }
+
+    /// Adds `range` to the ranges written.
+    pub fn add(&mut self, range: Range<u64>) {
+        self.done.push(range);
+        self.done.retain(|range| range.start < range.end);
+        self.done.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<u64>> = Vec::with_capacity(self.done.len());
+        for range in self.done.drain(..) {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        self.done = merged;
+    }
+
+    /// Returns the byte ranges not written yet.
+    pub fn missing(&self) -> Vec<Range<u64>> {
+        let mut missing = Vec::new();
+        let mut start = 0;
+        for range in &self.done {
+            if range.start > start {
+                missing.push(start..range.start);
+            }
+            start = start.max(range.end);
+        }
+        if start < self.size {
+            missing.push(start..self.size);
+        }
+        missing
+    }
+
+    /// Returns how many leading bytes of the file are written.
+    pub fn prefix(&self) -> u64 {
+        match self.done.first() {
+            Some(range) if range.start == 0 => range.end,
+            _ => 0,
+        }
+    }
+}
+
+/// Removes the control file of `file`, if it has one.
+pub fn remove(file: &Path) {
+    let _ = fs::remove_file(path(file));
+}
+
+/// Cuts `file` back to the prefix its control file records as written and
+/// removes the control file, so that the download can go on over a single
+/// connection.
+///
+/// A file without a control file is left alone.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be truncated.
+pub fn keep_prefix(file: &Path) -> io::Result<()> {
+    let Some(state) = State::load(file) else {
+        return Ok(());
+    };
+    if file.exists() {
+        OpenOptions::new()
+            .write(true)
+            .open(file)?
+            .set_len(state.prefix())?;
+    }
+    remove(file);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("big.iso");
+        fs::write(&file, vec![7; 100]).unwrap();
+        let mut state = State {
+            url: "https://example.com/big.iso".to_string(),
+            size: 100,
+            etag: Some("\"v1\"".to_string()),
+            ..State::default()
+        };
+        for range in [40..60, 0..10, 10..20, 50..70, 90..90] {
+            state.add(range);
+        }
+        assert_eq!(state.done, vec![0..20, 40..70]);
+        assert_eq!(state.missing(), vec![20..40, 70..100]);
+        assert_eq!(state.prefix(), 20);
+
+        state.save(&file).unwrap();
+        let loaded = State::load(&file).unwrap();
+        assert_eq!(loaded, state);
+        let fresh = State {
+            done: Vec::new(),
+            last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+            ..state.clone()
+        };
+        assert!(loaded.same_resource(&fresh));
+        let changed = State {
+            etag: Some("\"v2\"".to_string()),
+            ..fresh
+        };
+        assert!(!loaded.same_resource(&changed));
+
+        keep_prefix(&file).unwrap();
+        assert_eq!(fs::metadata(&file).unwrap().len(), 20);
+        assert!(State::load(&file).is_none());
+    }
+}
@@ -0,0 +1,99 @@
+//! Reading the `Crawl-delay` a site asks crawlers to keep.
+//!
+//! Before a crawl, the site's `/robots.txt` is read, and the `Crawl-delay` of
+//! the group that names [`AGENT`], or else of the `*` group, becomes the time
+//! waited between requests. `--wait` overrides it. Other rules of the file
+//! are not applied.
+
+use crate::download::Options;
+use reqwest::blocking::Client;
+use std::time::Duration;
+use url::Url;
+
+/// The product token crawls identify as in `robots.txt`.
+pub const AGENT: &str = "rustwget";
+
+/// Largest delay taken from a `robots.txt`, so that a typo there cannot stall a crawl.
+pub const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Returns the `Crawl-delay` that `robots` asks of `agent`, if any.
+///
+/// A group that names `agent` takes precedence over the `*` group.
+pub fn crawl_delay(robots: &str, agent: &str) -> Option<Duration> {
+    let agent = agent.to_ascii_lowercase();
+    let (mut named, mut any) = (None, None);
+    // The agents of the group being read, and whether its rules have started.
+    let mut agents: Vec<String> = Vec::new();
+    let mut in_rules = false;
+    for line in robots.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let Some((field, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match field.trim().to_ascii_lowercase().as_str() {
+            "user-agent" => {
+                if in_rules {
+                    agents.clear();
+                    in_rules = false;
+                }
+                agents.push(value.to_ascii_lowercase());
+            }
+            "crawl-delay" => {
+                in_rules = true;
+                let Some(delay) = value
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                else {
+                    continue;
+                };
+                if agents
+                    .iter()
+                    .any(|name| agent.contains(name.as_str()) && name != "*")
+                {
+                    named.get_or_insert(delay);
+                } else if agents.iter().any(|name| name == "*") {
+                    any.get_or_insert(delay);
+                }
+            }
+            _ => in_rules = true,
+        }
+    }
+    named.or(any).map(|delay| delay.min(MAX_DELAY))
+}
+
+/// Reads the `Crawl-delay` of the site of `start` for [`AGENT`].
+///
+/// A missing or unreadable `robots.txt` asks for no delay.
+pub fn fetch(client: &Client, start: &Url, options: &Options) -> Option<Duration> {
+    let url = start.join("/robots.txt").ok()?;
+    let response = options
+        .send(client, url.as_str(), |client| client.get(url.as_str()))
+        .ok()?
+        .error_for_status()
+        .ok()?;
+    crawl_delay(&response.text().ok()?, AGENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crawl_delay() {
+        let robots = "User-agent: *\nDisallow: /private\nCrawl-delay: 2\n\n\
+                      User-agent: otherbot\nUser-agent: RustWget # us\nCrawl-delay: 0.5\n";
+        assert_eq!(crawl_delay(robots, AGENT), Some(Duration::from_millis(500)));
+        assert_eq!(crawl_delay(robots, "somebot"), Some(Duration::from_secs(2)));
+        assert_eq!(
+            crawl_delay("User-agent: *\nCrawl-delay: 86400\n", AGENT),
+            Some(MAX_DELAY)
+        );
+        assert_eq!(
+            crawl_delay("User-agent: *\nCrawl-delay: soon\n", AGENT),
+            None
+        );
+        assert_eq!(crawl_delay("", AGENT), None);
+    }
+}
@@ -0,0 +1,77 @@
+//! Scanning downloads before they reach their destination.
+//!
+//! With `--scan-cmd COMMAND`, every download is written to a quarantine
+//! directory, `--tmp-dir` if one is given and `<data dir>/quarantine`
+//! otherwise (see [`paths::data_dir`]). Once it is complete, `COMMAND` runs
+//! through the shell with `{file}` standing for the quarantined file, e.g.
+//! `--scan-cmd 'clamscan --no-summary {file}'`; without `{file}`, the file is
+//! its last argument. The download is moved to its destination only if the
+//! command exits with status 0. Otherwise it fails, and the file stays in
+//! quarantine for inspection.
+
+use crate::paths;
+use crate::secrets;
+use std::path::{Path, PathBuf};
+
+/// What stands for the scanned file in a scan command.
+pub const PLACEHOLDER: &str = "{file}";
+
+/// Returns the quarantine directory used when `--tmp-dir` is not given.
+pub fn default_dir() -> PathBuf {
+    paths::data_dir().join("quarantine")
+}
+
+/// Runs the scan `command` on the file at `path`.
+///
+/// # Errors
+///
+/// Returns an error if the command cannot be run or does not exit with status 0.
+pub fn run(command: &str, path: &Path) -> Result<(), String> {
+    #[cfg(unix)]
+    let mut shell = {
+        // The path is passed as a positional parameter, so that no quoting is needed.
+        let command = match command.contains(PLACEHOLDER) {
+            true => command.replace(PLACEHOLDER, "\"$1\""),
+            false => format!("{} \"$1\"", command),
+        };
+        let mut shell = secrets::shell(&command);
+        shell.arg("sh").arg(path);
+        shell
+    };
+    #[cfg(not(unix))]
+    let mut shell = {
+        let file = format!("\"{}\"", path.display());
+        secrets::shell(&match command.contains(PLACEHOLDER) {
+            true => command.replace(PLACEHOLDER, &file),
+            false => format!("{} {}", command, file),
+        })
+    };
+    let status = shell
+        .status()
+        .map_err(|err| format!("cannot run scan command '{}': {}", command, err))?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(format!(
+            "scan of {} failed with {}; the file is kept there",
+            path.display(),
+            status
+        )),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_passes_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("it's a file.bin");
+        std::fs::write(&path, "clean").unwrap();
+
+        run("grep -q clean {file}", &path).unwrap();
+        run("test -f", &path).unwrap();
+        let err = run("grep -q EICAR {file}", &path).unwrap_err();
+        assert!(err.contains("failed with exit status: 1"));
+    }
+}
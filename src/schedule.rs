@@ -0,0 +1,235 @@
+//! Deferred and recurring download times.
+//!
+//! `--start-at` accepts a wall-clock time (`02:00`, the next occurrence of that
+//! time), a local date and time (`2024-06-01 02:00`), or an RFC 3339
+//! timestamp. Recurring daemon jobs use the classic five-field cron syntax
+//! (`minute hour day-of-month month day-of-week`) with `*`, lists, ranges, and
+//! `/step` suffixes.
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDateTime, NaiveTime, TimeZone, Timelike};
+
+/// How far ahead [`Cron::next_after`] searches before giving up, in minutes.
+///
+/// Four years covers every satisfiable expression, including `29 2`-style
+/// leap-day schedules.
+const SEARCH_LIMIT: i64 = 4 * 366 * 24 * 60;
+
+/// Resolves a `--start-at` value to an absolute local time.
+///
+/// # Arguments
+///
+/// * `value`: The user-supplied time.
+/// * `now`: The current time; bare wall-clock times resolve to the next occurrence after it.
+///
+/// # Errors
+///
+/// Returns an error if the value matches none of the accepted formats.
+pub fn parse_start_at(value: &str, now: DateTime<Local>) -> Result<DateTime<Local>, String> {
+    let value = value.trim();
+    if let Ok(time) = NaiveTime::parse_from_str(value, "%H:%M") {
+        let today = local(now.date_naive().and_time(time))?;
+        return Ok(if today > now {
+            today
+        } else {
+            local((now.date_naive() + Duration::days(1)).and_time(time))?
+        });
+    }
+    for format in ["%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S"] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(value, format) {
+            return local(datetime);
+        }
+    }
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Ok(datetime.with_timezone(&Local));
+    }
+    Err(format!(
+        "invalid start time '{}': expected HH:MM, YYYY-MM-DD HH:MM, or an RFC 3339 timestamp",
+        value
+    ))
+}
+
+fn local(datetime: NaiveDateTime) -> Result<DateTime<Local>, String> {
+    Local
+        .from_local_datetime(&datetime)
+        .earliest()
+        .ok_or_else(|| format!("{} does not exist in the local time zone", datetime))
+}
+
+/// A parsed five-field cron expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    /// Whether day-of-month and day-of-week were both restricted, in which
+    /// case cron fires when *either* matches.
+    either_day: bool,
+}
+
+impl Cron {
+    /// Parses an expression such as `*/15 8-18 * * 1-5`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for a wrong number of fields or out-of-range values.
+    pub fn parse(expression: &str) -> Result<Cron, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "invalid cron expression '{}': expected 5 fields, found {}",
+                expression,
+                fields.len()
+            ));
+        };
+        let error = |err: String| format!("invalid cron expression '{}': {}", expression, err);
+
+        let mut weekdays = parse_field(weekday, 0, 7).map_err(error)?;
+        // Both 0 and 7 mean Sunday.
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+        weekdays.truncate(7);
+
+        Ok(Cron {
+            minutes: parse_field(minute, 0, 59).map_err(error)?,
+            hours: parse_field(hour, 0, 23).map_err(error)?,
+            days: parse_field(day, 1, 31).map_err(error)?,
+            months: parse_field(month, 1, 12).map_err(error)?,
+            weekdays,
+            either_day: !day.starts_with('*') && !weekday.starts_with('*'),
+        })
+    }
+
+    /// Whether the expression fires at the minute containing `time`.
+    pub fn matches(&self, time: &NaiveDateTime) -> bool {
+        let day = self.days[time.day() as usize];
+        let weekday = self.weekdays[time.weekday().num_days_from_sunday() as usize];
+        let day_matches = if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        };
+        self.minutes[time.minute() as usize]
+            && self.hours[time.hour() as usize]
+            && self.months[time.month() as usize]
+            && day_matches
+    }
+
+    /// Returns the first matching minute strictly after `after`, if any.
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = after.naive_local().with_second(0)?.with_nanosecond(0)?;
+        (1..=SEARCH_LIMIT)
+            .map(|minutes| start + Duration::minutes(minutes))
+            .filter(|candidate| self.matches(candidate))
+            .find_map(|candidate| Local.from_local_datetime(&candidate).earliest())
+    }
+}
+
+/// Expands one cron field into a membership table indexed by value.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>, String> {
+    let mut allowed = vec![false; max as usize + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|&step| step > 0)
+                    .ok_or_else(|| format!("invalid step in '{}'", part))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (number(start, min, max)?, number(end, min, max)?)
+        } else {
+            let value = number(range, min, max)?;
+            // `5/10` means "from 5 to the end, every 10".
+            (value, if part.contains('/') { max } else { value })
+        };
+        if start > end {
+            return Err(format!("empty range '{}'", part));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            allowed[value as usize] = true;
+        }
+    }
+    Ok(allowed)
+}
+
+fn number(value: &str, min: u32, max: u32) -> Result<u32, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|number| (min..=max).contains(number))
+        .ok_or_else(|| format!("'{}' is not between {} and {}", value, min, max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(date: NaiveDate, hour: u32, minute: u32) -> Option<DateTime<Local>> {
+        local(date.and_hms_opt(hour, minute, 0)?).ok()
+    }
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn test_parse_start_at() {
+        let now = at(date(2024, 3, 10), 12, 0).unwrap();
+
+        assert_eq!(
+            parse_start_at("13:30", now),
+            Ok(at(date(2024, 3, 10), 13, 30).unwrap())
+        );
+        assert_eq!(
+            parse_start_at("02:00", now),
+            Ok(at(date(2024, 3, 11), 2, 0).unwrap())
+        );
+        assert_eq!(
+            parse_start_at("2024-04-01 06:15", now),
+            Ok(at(date(2024, 4, 1), 6, 15).unwrap())
+        );
+        assert!(parse_start_at("tomorrow", now).is_err());
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        let weekdays = Cron::parse("*/15 8-9 * * 1-5").unwrap();
+        // 2024-03-08 is a Friday; the next slot after 09:50 is Monday 08:00.
+        let friday = at(date(2024, 3, 8), 9, 50).unwrap();
+        assert_eq!(weekdays.next_after(friday), at(date(2024, 3, 11), 8, 0));
+
+        let nightly = Cron::parse("0 2 * * *").unwrap();
+        let evening = at(date(2024, 3, 8), 22, 0).unwrap();
+        assert_eq!(nightly.next_after(evening), at(date(2024, 3, 9), 2, 0));
+    }
+
+    #[test]
+    fn test_cron_day_fields_combine_with_or() {
+        // The 1st of the month or any Sunday (0 and 7 both mean Sunday).
+        let cron = Cron::parse("0 0 1 * 7").unwrap();
+        let sunday = date(2024, 3, 10).and_hms_opt(0, 0, 0).unwrap();
+        let first = date(2024, 4, 1).and_hms_opt(0, 0, 0).unwrap();
+        let tuesday = date(2024, 3, 12).and_hms_opt(0, 0, 0).unwrap();
+
+        assert!(cron.matches(&sunday));
+        assert!(cron.matches(&first));
+        assert!(!cron.matches(&tuesday));
+    }
+
+    #[test]
+    fn test_cron_rejects_invalid_expressions() {
+        assert!(Cron::parse("* * * *").is_err());
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("*/0 * * * *").is_err());
+        assert!(Cron::parse("5-1 * * * *").is_err());
+    }
+}
@@ -0,0 +1,228 @@
+//! Secrets printed by commands, such as those of a password manager.
+//!
+//! `--header-from-cmd 'NAME: COMMAND'` sends the header `NAME` with the
+//! output of `COMMAND` as its value, and `--password-command COMMAND` takes the
+//! password for `--user` from the output of `COMMAND`, e.g.
+//! `--password-command 'pass show files.example.com'`. Commands run through
+//! the shell, and only when the first request that needs their secret is
+//! sent; a run that never gets that far never asks for it. Their output is
+//! then kept for the rest of the run, without its final line break. They
+//! share the terminal, so that they can prompt for a passphrase.
+
+use crate::auth::Credentials;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::fmt;
+use std::process::{self, Stdio};
+use std::sync::OnceLock;
+
+/// A command whose output is a secret, run at most once.
+pub struct Command {
+    command: String,
+    output: OnceLock<Result<String, String>>,
+}
+
+impl fmt::Debug for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Command({:?})", self.command)
+    }
+}
+
+impl Command {
+    pub fn new(command: &str) -> Command {
+        Command {
+            command: command.to_string(),
+            output: OnceLock::new(),
+        }
+    }
+
+    /// Returns the output of the command, running it the first time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command cannot be run, fails, or prints
+    /// nothing or something that is not UTF-8.
+    pub fn output(&self) -> Result<&str, String> {
+        self.output
+            .get_or_init(|| run(&self.command))
+            .as_deref()
+            .map_err(Clone::clone)
+    }
+}
+
+/// Returns a process that runs `command` through the shell.
+pub fn shell(command: &str) -> process::Command {
+    #[cfg(unix)]
+    let mut shell = process::Command::new("sh");
+    #[cfg(unix)]
+    shell.arg("-c");
+    #[cfg(not(unix))]
+    let mut shell = process::Command::new("cmd");
+    #[cfg(not(unix))]
+    shell.arg("/C");
+    shell.arg(command);
+    shell
+}
+
+/// Runs `command` through the shell and returns what it printed.
+fn run(command: &str) -> Result<String, String> {
+    let output = shell(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|err| format!("cannot run '{}': {}", command, err))?;
+    if !output.status.success() {
+        return Err(format!("'{}' failed with {}", command, output.status));
+    }
+    let text = String::from_utf8(output.stdout)
+        .map_err(|_| format!("'{}' printed something that is not UTF-8", command))?;
+    let secret = text.strip_suffix('\n').unwrap_or(&text);
+    let secret = secret.strip_suffix('\r').unwrap_or(secret);
+    match secret.is_empty() {
+        true => Err(format!("'{}' printed nothing", command)),
+        false => Ok(secret.to_string()),
+    }
+}
+
+/// Parses a `--header-from-cmd` value, `NAME: COMMAND`.
+pub fn parse_header(text: &str) -> Result<(HeaderName, Command), String> {
+    let (name, command) = text
+        .split_once(':')
+        .filter(|(_, command)| !command.trim().is_empty())
+        .ok_or_else(|| format!("invalid header command '{}': expected NAME: COMMAND", text))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("invalid header name '{}'", name.trim()))?;
+    Ok((name, Command::new(command.trim())))
+}
+
+/// The password command of a user, and the credentials it led to.
+#[derive(Debug)]
+struct Password {
+    auth_type: String,
+    user: String,
+    command: Command,
+    credentials: OnceLock<Result<Credentials, String>>,
+}
+
+/// The secrets of a run that come from commands.
+#[derive(Debug, Default)]
+pub struct Secrets {
+    headers: Vec<(HeaderName, Command)>,
+    password: Option<Password>,
+}
+
+impl Secrets {
+    /// Collects the commands given on the command line.
+    ///
+    /// # Arguments
+    ///
+    /// * `headers`: The headers and the commands that print their values.
+    /// * `password`: One of the [`AUTH_TYPES`](crate::auth::AUTH_TYPES), the
+    ///   user, and the command that prints the user's password, if any.
+    pub fn new(
+        headers: Vec<(HeaderName, Command)>,
+        password: Option<(&str, &str, Command)>,
+    ) -> Secrets {
+        Secrets {
+            headers,
+            password: password.map(|(auth_type, user, command)| Password {
+                auth_type: auth_type.to_string(),
+                user: user.to_string(),
+                command,
+                credentials: OnceLock::new(),
+            }),
+        }
+    }
+
+    /// Returns the headers whose values come from commands, running them the
+    /// first time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a command fails or prints an invalid header value.
+    pub fn headers(&self) -> Result<HeaderMap, String> {
+        let mut headers = HeaderMap::new();
+        for (name, command) in &self.headers {
+            let mut value = HeaderValue::from_str(command.output()?).map_err(|_| {
+                format!(
+                    "invalid value for header {} from '{}'",
+                    name, command.command
+                )
+            })?;
+            value.set_sensitive(true);
+            headers.insert(name.clone(), value);
+        }
+        Ok(headers)
+    }
+
+    /// Returns the credentials of the user whose password comes from a
+    /// command, if there is one, running it the first time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command fails.
+    pub fn credentials(&self) -> Result<Option<&Credentials>, String> {
+        let Some(password) = &self.password else {
+            return Ok(None);
+        };
+        password
+            .credentials
+            .get_or_init(|| {
+                Credentials::password(
+                    &password.auth_type,
+                    password.user.clone(),
+                    password.command.output()?.to_string(),
+                )
+            })
+            .as_ref()
+            .map(Some)
+            .map_err(Clone::clone)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_run_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let count = dir.path().join("count");
+        let command = Command::new(&format!(
+            "echo run >> {}; printf 'token\\n'",
+            count.display()
+        ));
+        assert_eq!(command.output(), Ok("token"));
+        assert_eq!(command.output(), Ok("token"));
+        assert_eq!(std::fs::read_to_string(&count).unwrap(), "run\n");
+
+        assert!(Command::new("exit 3")
+            .output()
+            .unwrap_err()
+            .contains("failed"));
+        assert!(Command::new("true")
+            .output()
+            .unwrap_err()
+            .contains("printed nothing"));
+    }
+
+    #[test]
+    fn test_secrets() {
+        let (name, command) = parse_header("X-Api-Key: echo abc").unwrap();
+        assert_eq!(name, "x-api-key");
+        assert!(parse_header("X-Api-Key:").is_err());
+        assert!(parse_header("Bad Name: echo abc").is_err());
+
+        let secrets = Secrets::new(
+            vec![(name, command)],
+            Some(("basic", "alice", Command::new("echo hunter2"))),
+        );
+        let headers = secrets.headers().unwrap();
+        assert_eq!(headers["x-api-key"], "abc");
+        assert!(headers["x-api-key"].is_sensitive());
+        assert!(matches!(
+            secrets.credentials(),
+            Ok(Some(Credentials::Basic { user, password })) if user == "alice" && password == "hunter2"
+        ));
+        assert_eq!(Secrets::default().credentials(), Ok(None));
+    }
+}
@@ -0,0 +1,575 @@
+//! Downloading one file as byte ranges over several connections at once.
+//!
+//! Workers claim pieces of the file from a shared pool, each sized after the
+//! rate its connection reached on the previous piece, so a fast connection
+//! fetches more and larger pieces than a slow one. Once nothing is left to
+//! claim, an idle worker takes over the second half of the running piece with
+//! the most bytes left, so one slow connection does not hold up the end of the
+//! download. A worker whose server fails hands its unfinished piece back to
+//! the others and stops. Every worker writes through its own file handle at
+//! the piece's offset, so pieces can complete in any order; with the
+//! `io-uring` feature, that handle queues its writes on an io_uring. The
+//! ranges written are kept in the control file of the download (see
+//! [`resume`](crate::resume)), so that a later run fetches only the others.
+
+use crate::allocate::Allocation;
+use crate::download::{Control, Options, Outcome};
+use crate::resume::{self, State};
+use reqwest::blocking::Client;
+use reqwest::header::{
+    ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE,
+};
+use reqwest::StatusCode;
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Smallest piece handed to a worker.
+const MIN_PIECE: u64 = 256 * 1024;
+/// Largest piece handed to a worker.
+const MAX_PIECE: u64 = 16 * 1024 * 1024;
+/// How long a piece should take at the rate its worker reached on the last one.
+const PIECE_DURATION: Duration = Duration::from_secs(2);
+/// Smallest part of a running piece that an idle worker takes over.
+const MIN_STEAL: u64 = 64 * 1024;
+
+/// Returns the size and validators of the resource at `url`, with nothing
+/// written yet, if its server accepts byte ranges.
+pub fn probe(client: &Client, url: &str, options: &Options) -> Option<State> {
+    let response = options
+        .send(client, url, |client| client.head(url))
+        .ok()
+        .filter(|response| response.status().is_success())?;
+    let header = |name| response.headers().get(name)?.to_str().ok();
+    if header(ACCEPT_RANGES) != Some("bytes") {
+        return None;
+    }
+    Some(State {
+        url: url.to_string(),
+        size: header(CONTENT_LENGTH)?.parse().ok()?,
+        etag: header(ETAG).map(str::to_string),
+        last_modified: header(LAST_MODIFIED).map(str::to_string),
+        done: Vec::new(),
+        content_type: header(CONTENT_TYPE).map(str::to_string),
+    })
+}
+
+/// Work shared by the workers of one download.
+#[derive(Debug)]
+struct Pieces {
+    /// Byte ranges that no worker has claimed yet.
+    pending: VecDeque<Range<u64>>,
+    /// What each worker is downloading: its next byte to write and the end of
+    /// its piece, which moves back when an idle worker takes over the rest.
+    claims: Vec<Option<Range<u64>>>,
+    /// The ranges written, as saved in the control file.
+    progress: State,
+    error: Option<String>,
+}
+
+impl Pieces {
+    /// Claims up to `size` unclaimed bytes for `worker`, or else the second
+    /// half of the running piece with the most bytes left.
+    fn take(&mut self, worker: usize, size: u64) -> Option<Range<u64>> {
+        let piece = match self.pending.pop_front() {
+            Some(span) => {
+                let end = (span.start + size).min(span.end);
+                if end < span.end {
+                    self.pending.push_front(end..span.end);
+                }
+                span.start..end
+            }
+            None => {
+                let claim = self
+                    .claims
+                    .iter_mut()
+                    .flatten()
+                    .filter(|claim| claim.end - claim.start >= 2 * MIN_STEAL)
+                    .max_by_key(|claim| claim.end - claim.start)?;
+                let middle = claim.start + (claim.end - claim.start) / 2;
+                let piece = middle..claim.end;
+                claim.end = middle;
+                piece
+            }
+        };
+        self.claims[worker] = Some(piece.clone());
+        Some(piece)
+    }
+}
+
+/// Downloads the bytes of a resource that `state` does not record as written
+/// into `path` over several connections.
+///
+/// The ranges already written are kept as they are. When the download does
+/// not complete, the ranges written are saved in the control file of `path`;
+/// if that fails, the file is truncated to the longest fully downloaded
+/// prefix, so that it can be resumed like any partial file. The control file
+/// is removed once the download completes.
+///
+/// # Arguments
+///
+/// * `client`: The HTTP client used for making requests.
+/// * `urls`: One URL per connection; a URL may appear several times to open
+///   more than one connection to its server.
+/// * `path`: Where the resource is written.
+/// * `state`: The size and validators of the resource, and the ranges already written.
+/// * `control`: Shared handle through which progress is reported and pause/cancel requests arrive.
+/// * `options`: Settings of the run, such as the bandwidth limit.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be prepared, or if every connection
+/// failed before the whole resource was downloaded.
+pub fn fetch(
+    client: &Client,
+    urls: &[String],
+    path: &Path,
+    state: State,
+    control: &Control,
+    options: &Options,
+) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let size = state.size;
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    // Pieces are written at their offsets, so the file needs its full length anyway.
+    match options.file_allocation {
+        Allocation::None => Allocation::Trunc,
+        allocation => allocation,
+    }
+    .apply(&file, size)?;
+    let pending: VecDeque<Range<u64>> = state.missing().into();
+    let missing: u64 = pending.iter().map(|range| range.end - range.start).sum();
+    control.start(size - missing, Some(size));
+
+    // Until a worker has measured its rate, pieces split the file evenly.
+    let first_piece = (missing / (urls.len() as u64 * 4)).clamp(MIN_PIECE, MAX_PIECE);
+    // A resource changed since it was probed is sent whole instead of in a
+    // range; other mirrors have ETags of their own.
+    let origin = state.url.clone();
+    let etag = state.etag.clone().filter(|etag| !etag.starts_with("W/"));
+    let pieces = Mutex::new(Pieces {
+        pending,
+        claims: vec![None; urls.len()],
+        progress: state,
+        error: None,
+    });
+    let changed = Condvar::new();
+
+    thread::scope(|scope| {
+        for (worker, url) in urls.iter().enumerate() {
+            let shared = Shared {
+                pieces: &pieces,
+                changed: &changed,
+                control,
+                options,
+                if_range: etag.as_deref().map(|etag| (origin.as_str(), etag)),
+            };
+            scope.spawn(move || work(client, url, path, worker, first_piece, shared));
+        }
+    });
+
+    let pieces = pieces.into_inner().unwrap_or_else(|e| e.into_inner());
+    if pieces.pending.is_empty() {
+        resume::remove(path);
+        return Ok(Outcome::Completed);
+    }
+
+    let progress = pieces.progress;
+    let written: u64 = progress
+        .done
+        .iter()
+        .map(|range| range.end - range.start)
+        .sum();
+    if progress.save(path).is_err() {
+        file.set_len(progress.prefix())?;
+        control.start(progress.prefix(), Some(size));
+    } else {
+        control.start(written, Some(size));
+    }
+    match control.interruption() {
+        Some(outcome) => Ok(outcome),
+        None => Err(pieces
+            .error
+            .unwrap_or_else(|| "segmented download did not complete".to_string())
+            .into()),
+    }
+}
+
+/// Where a worker writes its pieces.
+trait Output: Write + Seek {}
+
+impl<T: Write + Seek> Output for T {}
+
+/// Opens the handle a worker writes through.
+fn output(path: &Path, options: &Options) -> std::io::Result<Box<dyn Output>> {
+    let file = OpenOptions::new().write(true).open(path)?;
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    if let Ok(writer) = crate::uring::Writer::new(&file, options.buffer_size) {
+        return Ok(Box::new(writer));
+    }
+    let _ = options;
+    Ok(Box::new(file))
+}
+
+/// What the workers of one download share.
+#[derive(Clone, Copy)]
+struct Shared<'a> {
+    pieces: &'a Mutex<Pieces>,
+    changed: &'a Condvar,
+    control: &'a Control,
+    options: &'a Options,
+    /// The URL the download was probed at and its strong ETag, on which
+    /// range requests to that URL are conditional, if it has one.
+    if_range: Option<(&'a str, &'a str)>,
+}
+
+impl Shared<'_> {
+    fn lock(&self) -> MutexGuard<'_, Pieces> {
+        self.pieces.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Moves the claim of `worker` back to `start` after its writes failed.
+    ///
+    /// Writes may still have been queued when the error was reported, so
+    /// none of the piece can be trusted to be in the file.
+    fn rewind(&self, worker: usize, start: u64) {
+        if let Some(claim) = &mut self.lock().claims[worker] {
+            claim.start = start;
+        }
+    }
+}
+
+/// Claims pieces and downloads them from `url` until none are left.
+///
+/// Each piece is sized to take about [`PIECE_DURATION`] at the rate the
+/// worker reached on its previous one, so fast connections take large pieces
+/// and slow ones small pieces.
+fn work(client: &Client, url: &str, path: &Path, worker: usize, first_piece: u64, shared: Shared) {
+    let mut file = match output(path, shared.options) {
+        Ok(file) => file,
+        Err(err) => {
+            shared.lock().error = Some(err.to_string());
+            return;
+        }
+    };
+
+    let mut size = first_piece;
+    loop {
+        let piece = {
+            let mut state = shared.lock();
+            loop {
+                if shared.control.interruption().is_some() {
+                    return;
+                }
+                if let Some(piece) = state.take(worker, size) {
+                    break piece;
+                }
+                // Another worker may still hand back an unfinished piece.
+                if state.claims.iter().all(Option::is_none) {
+                    return;
+                }
+                state = shared
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(|e| e.into_inner());
+            }
+        };
+
+        let started = Instant::now();
+        let mut result = download_piece(client, url, &mut *file, worker, &piece, shared);
+        // A piece only counts once its queued writes are in the file.
+        if let Err(err) = file.flush() {
+            shared.rewind(worker, piece.start);
+            result = Err(err.to_string());
+        }
+        let mut state = shared.lock();
+        let rest = state.claims[worker].take().unwrap_or(piece.end..piece.end);
+        if rest.start > piece.start {
+            state.progress.add(piece.start..rest.start);
+            // The file holds the piece once its writes are flushed.
+            let _ = state.progress.save(path);
+        }
+        if rest.start < rest.end {
+            state.pending.push_front(rest.clone());
+        }
+        shared.changed.notify_all();
+        if let Err(err) = result {
+            state.error = Some(format!("{}: {}", url, err));
+            return;
+        }
+        let rate = (rest.start - piece.start) as f64 / started.elapsed().as_secs_f64();
+        if rate.is_finite() && rate > 0.0 {
+            size = ((rate * PIECE_DURATION.as_secs_f64()) as u64).clamp(MIN_PIECE, MAX_PIECE);
+        }
+    }
+}
+
+/// Downloads the piece claimed by `worker`, advancing its claim as bytes are written.
+///
+/// The claim may shrink meanwhile when an idle worker takes over its end; the
+/// download then stops there. A piece that stops early without an error was
+/// interrupted by a pause or cancel request.
+fn download_piece(
+    client: &Client,
+    url: &str,
+    file: &mut dyn Output,
+    worker: usize,
+    piece: &Range<u64>,
+    shared: Shared,
+) -> Result<(), String> {
+    // Advances the claim by `written` bytes and returns how many are left.
+    let advance = |written: u64| {
+        let mut state = shared.lock();
+        match &mut state.claims[worker] {
+            Some(claim) => {
+                claim.start += written;
+                claim.end.saturating_sub(claim.start)
+            }
+            None => 0,
+        }
+    };
+
+    let mut response = shared
+        .options
+        .send(client, url, |client| {
+            let request = client
+                .get(url)
+                .header(RANGE, format!("bytes={}-{}", piece.start, piece.end - 1));
+            match shared.if_range {
+                Some((origin, etag)) if origin == url => request.header(IF_RANGE, etag),
+                _ => request,
+            }
+        })
+        .map_err(|err| err.to_string())?;
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(format!(
+            "expected HTTP 206 for a range request, got HTTP {}",
+            response.status()
+        ));
+    }
+    file.seek(SeekFrom::Start(piece.start))
+        .map_err(|err| err.to_string())?;
+
+    let mut buffer = vec![0; shared.options.buffer_size];
+    let mut left = advance(0);
+    while left > 0 {
+        if shared.control.interruption().is_some() {
+            return Ok(());
+        }
+        let want = buffer.len().min(left as usize);
+        let read = response
+            .read(&mut buffer[..want])
+            .map_err(|err| err.to_string())?;
+        if read == 0 {
+            return Err("connection closed before the end of the range".to_string());
+        }
+        // The end may have been taken over while reading.
+        let keep = (read as u64).min(advance(0)) as usize;
+        file.write_all(&buffer[..keep]).map_err(|err| {
+            shared.rewind(worker, piece.start);
+            err.to_string()
+        })?;
+        left = advance(keep as u64);
+        shared.control.advance(keep as u64);
+        shared.options.consume(url, read);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    /// Serves byte ranges of `body`, writing `chunk` bytes every `pause`, and
+    /// returns its URL and the ranges requested from it.
+    fn serve_ranges(
+        body: Arc<Vec<u8>>,
+        chunk: usize,
+        pause: Duration,
+    ) -> (String, Arc<Mutex<Vec<Range<u64>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file.bin", listener.local_addr().unwrap());
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&requested);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (body, log) = (Arc::clone(&body), Arc::clone(&log));
+                thread::spawn(move || {
+                    let mut stream = stream.unwrap();
+                    let mut request = String::new();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    while reader.read_line(&mut request).unwrap() > 2 {}
+                    let range = request
+                        .lines()
+                        .find_map(|line| {
+                            line.to_ascii_lowercase()
+                                .strip_prefix("range: bytes=")
+                                .map(str::to_string)
+                        })
+                        .unwrap();
+                    let (first, last) = range.trim().split_once('-').unwrap();
+                    let range = first.parse().unwrap()..last.parse::<u64>().unwrap() + 1;
+                    log.lock().unwrap().push(range.clone());
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        range.end - range.start
+                    );
+                    for part in body[range.start as usize..range.end as usize].chunks(chunk) {
+                        if stream.write_all(part).is_err() {
+                            return;
+                        }
+                        thread::sleep(pause);
+                    }
+                });
+            }
+        });
+        (url, requested)
+    }
+
+    #[test]
+    fn test_fetch_assembles_pieces_from_several_servers() {
+        let body: Arc<Vec<u8>> = Arc::new((0..MIN_PIECE * 3).map(|i| (i % 251) as u8).collect());
+        let chunk = Options::default().buffer_size;
+        let (first, _) = serve_ranges(Arc::clone(&body), chunk, Duration::ZERO);
+        let (second, _) = serve_ranges(Arc::clone(&body), chunk, Duration::ZERO);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.bin");
+        let control = Control::default();
+
+        let outcome = fetch(
+            &Client::new(),
+            &[first, second],
+            &path,
+            state(body.len() as u64),
+            &control,
+            &Options::default(),
+        )
+        .unwrap();
+
+        assert_eq!(outcome, Outcome::Completed);
+        assert_eq!(fs::read(&path).unwrap(), *body);
+        assert_eq!(control.downloaded(), body.len() as u64);
+    }
+
+    #[test]
+    fn test_idle_worker_takes_over_slow_piece() {
+        let body: Arc<Vec<u8>> = Arc::new((0..MIN_PIECE * 2).map(|i| (i % 241) as u8).collect());
+        let chunk = Options::default().buffer_size;
+        let (slow, slow_ranges) =
+            serve_ranges(Arc::clone(&body), 8 * 1024, Duration::from_millis(20));
+        let (fast, fast_ranges) = serve_ranges(Arc::clone(&body), chunk, Duration::ZERO);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.bin");
+
+        let outcome = fetch(
+            &Client::new(),
+            &[slow, fast],
+            &path,
+            state(body.len() as u64),
+            &Control::default(),
+            &Options::default(),
+        )
+        .unwrap();
+
+        assert_eq!(outcome, Outcome::Completed);
+        assert_eq!(fs::read(&path).unwrap(), *body);
+        // The fast server also served the end of the slow server's first piece.
+        let slow_piece = slow_ranges.lock().unwrap()[0].clone();
+        assert!(fast_ranges
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|range| range.start > slow_piece.start && range.end == slow_piece.end));
+    }
+
+    #[test]
+    fn test_failed_server_hands_pieces_to_the_others() {
+        let body = vec![7u8; MIN_PIECE as usize];
+        let broken = mock("GET", "/segment/broken/file.bin")
+            .with_status(503)
+            .expect_at_most(1)
+            .create();
+        // Serves every range, as all bytes of the body are equal.
+        let working = mock("GET", "/segment/working/file.bin")
+            .with_status(206)
+            .with_body(&body)
+            .expect_at_least(1)
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.bin");
+        let urls = vec![
+            format!("{}/segment/broken/file.bin", server_url()),
+            format!("{}/segment/working/file.bin", server_url()),
+        ];
+
+        let outcome = fetch(
+            &Client::new(),
+            &urls,
+            &path,
+            state(body.len() as u64),
+            &Control::default(),
+            &Options::default(),
+        )
+        .unwrap();
+
+        assert_eq!(outcome, Outcome::Completed);
+        assert_eq!(fs::read(&path).unwrap(), body);
+        broken.assert();
+        working.assert();
+    }
+
+    /// Returns the state of a download of `size` bytes with nothing written.
+    fn state(size: u64) -> State {
+        State {
+            size,
+            ..State::default()
+        }
+    }
+
+    #[test]
+    fn test_incomplete_download_saves_its_ranges() {
+        let failing = mock("GET", "/segment/failing/file.bin")
+            .with_status(500)
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.bin");
+        fs::write(&path, "0123").unwrap();
+        let urls = vec![format!("{}/segment/failing/file.bin", server_url())];
+        let mut partial = state(MIN_PIECE);
+        partial.add(0..4);
+
+        let err = fetch(
+            &Client::new(),
+            &urls,
+            &path,
+            partial,
+            &Control::default(),
+            &Options::default(),
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("got HTTP 500"));
+        assert_eq!(State::load(&path).unwrap().prefix(), 4);
+        resume::keep_prefix(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"0123");
+        failing.assert();
+    }
+}
@@ -0,0 +1,341 @@
+//! Starting `rustwget daemon` with the user's session.
+//!
+//! `rustwget daemon [OPTIONS] install` registers the daemon, with the options
+//! given before `install`, to start whenever the user logs in and to be
+//! restarted if it stops:
+//!
+//! * on macOS, as a launchd agent in
+//!   `~/Library/LaunchAgents/io.github.lordshrey.rustwget.plist`, logging to
+//!   `~/Library/Logs/rustwget`;
+//! * on Linux, as a systemd user service in
+//!   `~/.config/systemd/user/rustwget.service`, which reports readiness and
+//!   feeds a watchdog (see [`systemd`](crate::systemd));
+//! * on Windows, as a Task Scheduler task named `rustwget`, run at logon
+//!   and restarted on failure. A task is used rather than a service, as
+//!   rustwget does not answer the service control manager.
+//!
+//! Relative paths among the options are made absolute, and the daemon's
+//! `--dir` defaults to the directory `install` ran in, so that the daemon
+//! finds the same files wherever it is started from. `rustwget daemon
+//! uninstall` stops and removes the registration.
+
+use clap::ArgMatches;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The name of the launchd agent.
+const LABEL: &str = "io.github.lordshrey.rustwget";
+
+/// Daemon options that take a path, which is made absolute.
+const PATH_OPTIONS: [&str; 5] = ["socket", "state", "config", "dir", "token-file"];
+
+/// Daemon options passed on as they are.
+const OPTIONS: [&str; 4] = ["listen", "tries", "limit-rate", "jobs"];
+
+/// Returns the arguments the registered daemon runs with, from the options
+/// given to `rustwget daemon` before `install`.
+///
+/// # Errors
+///
+/// Returns an error if the current directory cannot be determined.
+pub fn arguments(matches: &ArgMatches) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut arguments = vec!["daemon".to_string()];
+    let current = env::current_dir()?;
+    for name in PATH_OPTIONS {
+        let value = match matches
+            .value_of(name)
+            .filter(|_| matches.occurrences_of(name) > 0)
+        {
+            Some(value) => current.join(value),
+            None if name == "dir" => current.clone(),
+            None => continue,
+        };
+        arguments.push(format!("--{}", name));
+        arguments.push(value.display().to_string());
+    }
+    for name in OPTIONS {
+        if let Some(value) = matches
+            .value_of(name)
+            .filter(|_| matches.occurrences_of(name) > 0)
+        {
+            arguments.push(format!("--{}", name));
+            arguments.push(value.to_string());
+        }
+    }
+    Ok(arguments)
+}
+
+/// Registers the daemon to run `exe` with `arguments` at logon, and starts it.
+///
+/// # Errors
+///
+/// Returns an error if the registration cannot be written or loaded.
+pub fn install(exe: &Path, arguments: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if cfg!(target_os = "macos") {
+        let logs = home()?.join("Library/Logs/rustwget");
+        fs::create_dir_all(&logs)?;
+        let path = plist_path()?;
+        fs::create_dir_all(path.parent().unwrap())?;
+        // A loaded agent keeps its old definition until it is unloaded.
+        if path.exists() {
+            let _ = Command::new("launchctl").arg("unload").arg(&path).status();
+        }
+        fs::write(&path, plist(exe, arguments, &logs))?;
+        run(Command::new("launchctl").args(["load", "-w"]).arg(&path))?;
+        println!("Installed the launchd agent {}", path.display());
+    } else if cfg!(windows) {
+        let xml = task(exe, arguments);
+        let path = env::temp_dir().join("rustwget-task.xml");
+        // Task Scheduler reads task definitions in UTF-16.
+        let mut encoded = vec![0xff, 0xfe];
+        encoded.extend(xml.encode_utf16().flat_map(u16::to_le_bytes));
+        fs::write(&path, encoded)?;
+        let created = run(Command::new("schtasks")
+            .args(["/Create", "/F", "/TN", "rustwget", "/XML"])
+            .arg(&path));
+        let _ = fs::remove_file(&path);
+        created?;
+        run(Command::new("schtasks").args(["/Run", "/TN", "rustwget"]))?;
+        println!("Installed the scheduled task rustwget");
+    } else {
+        let path = unit_path()?;
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, unit(exe, arguments))?;
+        run(Command::new("systemctl").args(["--user", "daemon-reload"]))?;
+        run(Command::new("systemctl").args(["--user", "enable", "--now", "rustwget.service"]))?;
+        println!("Installed the systemd user service {}", path.display());
+    }
+    Ok(())
+}
+
+/// Stops the registered daemon and removes its registration.
+///
+/// # Errors
+///
+/// Returns an error if the daemon is not registered or cannot be removed.
+pub fn uninstall() -> Result<(), Box<dyn std::error::Error>> {
+    if cfg!(target_os = "macos") {
+        let path = plist_path()?;
+        if !path.exists() {
+            return Err(format!("no launchd agent at {}", path.display()).into());
+        }
+        run(Command::new("launchctl").args(["unload", "-w"]).arg(&path))?;
+        fs::remove_file(&path)?;
+        println!("Removed the launchd agent {}", path.display());
+    } else if cfg!(windows) {
+        let _ = Command::new("schtasks")
+            .args(["/End", "/TN", "rustwget"])
+            .status();
+        run(Command::new("schtasks").args(["/Delete", "/F", "/TN", "rustwget"]))?;
+        println!("Removed the scheduled task rustwget");
+    } else {
+        let path = unit_path()?;
+        if !path.exists() {
+            return Err(format!("no systemd user service at {}", path.display()).into());
+        }
+        run(Command::new("systemctl").args(["--user", "disable", "--now", "rustwget.service"]))?;
+        fs::remove_file(&path)?;
+        run(Command::new("systemctl").args(["--user", "daemon-reload"]))?;
+        println!("Removed the systemd user service {}", path.display());
+    }
+    Ok(())
+}
+
+/// Runs `command`, failing unless it succeeds.
+fn run(command: &mut Command) -> Result<(), Box<dyn std::error::Error>> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let status = command
+        .status()
+        .map_err(|err| format!("cannot run {}: {}", program, err))?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(format!("{} exited with {}", program, status).into()),
+    }
+}
+
+fn home() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .ok_or_else(|| "HOME is not set".into())
+}
+
+fn plist_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    Ok(home()?.join(format!("Library/LaunchAgents/{}.plist", LABEL)))
+}
+
+fn unit_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let config = match env::var_os("XDG_CONFIG_HOME") {
+        Some(config) if !config.is_empty() => PathBuf::from(config),
+        _ => home()?.join(".config"),
+    };
+    Ok(config.join("systemd/user/rustwget.service"))
+}
+
+/// Escapes `text` for XML.
+fn xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Returns the launchd agent running `exe` with `arguments`, logging to `logs`.
+fn plist(exe: &Path, arguments: &[String], logs: &Path) -> String {
+    let program: String = std::iter::once(exe.display().to_string())
+        .chain(arguments.iter().cloned())
+        .map(|argument| format!("        <string>{}</string>\n", xml(&argument)))
+        .collect();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+         \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n\
+         <dict>\n\
+         \x20   <key>Label</key>\n\
+         \x20   <string>{}</string>\n\
+         \x20   <key>ProgramArguments</key>\n\
+         \x20   <array>\n\
+         {}\
+         \x20   </array>\n\
+         \x20   <key>RunAtLoad</key>\n\
+         \x20   <true/>\n\
+         \x20   <key>KeepAlive</key>\n\
+         \x20   <true/>\n\
+         \x20   <key>ProcessType</key>\n\
+         \x20   <string>Background</string>\n\
+         \x20   <key>StandardOutPath</key>\n\
+         \x20   <string>{}</string>\n\
+         \x20   <key>StandardErrorPath</key>\n\
+         \x20   <string>{}</string>\n\
+         </dict>\n\
+         </plist>\n",
+        LABEL,
+        program,
+        xml(&logs.join("daemon.log").display().to_string()),
+        xml(&logs.join("daemon.err.log").display().to_string())
+    )
+}
+
+/// Returns the systemd user service running `exe` with `arguments`.
+fn unit(exe: &Path, arguments: &[String]) -> String {
+    let quote = |argument: &str| {
+        format!(
+            "\"{}\"",
+            argument
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('%', "%%")
+        )
+    };
+    let command: Vec<String> = std::iter::once(exe.display().to_string())
+        .chain(arguments.iter().cloned())
+        .map(|argument| quote(&argument))
+        .collect();
+    format!(
+        "[Unit]\n\
+         Description=rustwget download daemon\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         ExecStart={}\n\
+         Restart=on-failure\n\
+         WatchdogSec=60\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        command.join(" ")
+    )
+}
+
+/// Returns the Task Scheduler task running `exe` with `arguments` at logon.
+fn task(exe: &Path, arguments: &[String]) -> String {
+    // Arguments are split by the C runtime's rules: quotes group words, and
+    // backslashes escape quotes only.
+    let quote = |argument: &str| {
+        if !argument.is_empty() && !argument.contains([' ', '\t', '"']) {
+            return argument.to_string();
+        }
+        let mut quoted = String::from("\"");
+        let mut backslashes = 0;
+        for c in argument.chars() {
+            match c {
+                '\\' => backslashes += 1,
+                '"' => {
+                    quoted += &"\\".repeat(2 * backslashes + 1);
+                    backslashes = 0;
+                }
+                _ => backslashes = 0,
+            }
+            quoted.push(c);
+        }
+        quoted += &"\\".repeat(backslashes);
+        quoted + "\""
+    };
+    let arguments: Vec<String> = arguments.iter().map(|argument| quote(argument)).collect();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-16\"?>\n\
+         <Task version=\"1.2\" xmlns=\"http://schemas.microsoft.com/windows/2004/02/mit/task\">\n\
+         \x20 <RegistrationInfo><Description>rustwget download daemon</Description></RegistrationInfo>\n\
+         \x20 <Triggers><LogonTrigger><Enabled>true</Enabled></LogonTrigger></Triggers>\n\
+         \x20 <Settings>\n\
+         \x20   <MultipleInstancesPolicy>IgnoreNew</MultipleInstancesPolicy>\n\
+         \x20   <DisallowStartIfOnBatteries>false</DisallowStartIfOnBatteries>\n\
+         \x20   <StopIfGoingOnBatteries>false</StopIfGoingOnBatteries>\n\
+         \x20   <ExecutionTimeLimit>PT0S</ExecutionTimeLimit>\n\
+         \x20   <RestartOnFailure><Interval>PT1M</Interval><Count>999</Count></RestartOnFailure>\n\
+         \x20 </Settings>\n\
+         \x20 <Actions>\n\
+         \x20   <Exec>\n\
+         \x20     <Command>{}</Command>\n\
+         \x20     <Arguments>{}</Arguments>\n\
+         \x20   </Exec>\n\
+         \x20 </Actions>\n\
+         </Task>\n",
+        xml(&exe.display().to_string()),
+        xml(&arguments.join(" "))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_definitions() {
+        let exe = Path::new("/opt/rust wget/rustwget");
+        let arguments = [
+            "daemon".to_string(),
+            "--dir".to_string(),
+            "/srv/mirror & co".to_string(),
+            "--limit-rate".to_string(),
+            "50%".to_string(),
+        ];
+
+        let plist = plist(
+            exe,
+            &arguments,
+            Path::new("/Users/me/Library/Logs/rustwget"),
+        );
+        assert!(plist.contains("<string>/opt/rust wget/rustwget</string>"));
+        assert!(plist.contains("<string>/srv/mirror &amp; co</string>"));
+        assert!(plist.contains("/Users/me/Library/Logs/rustwget/daemon.log"));
+
+        let unit = unit(exe, &arguments);
+        assert!(unit.contains(
+            "ExecStart=\"/opt/rust wget/rustwget\" \"daemon\" \"--dir\" \"/srv/mirror & co\" \
+             \"--limit-rate\" \"50%%\"\n"
+        ));
+        assert!(unit.contains("Type=notify\n"));
+
+        let task = task(Path::new(r"C:\Tools\rustwget.exe"), &arguments);
+        assert!(task.contains(r"<Command>C:\Tools\rustwget.exe</Command>"));
+        assert!(task.contains(
+            "<Arguments>daemon --dir &quot;/srv/mirror &amp; co&quot; --limit-rate 50%</Arguments>"
+        ));
+        let quoted = super::task(exe, &[r#"say "hi"\"#.to_string()]);
+        assert!(quoted.contains(r#"<Arguments>&quot;say \&quot;hi\&quot;\\&quot;</Arguments>"#));
+    }
+}
@@ -0,0 +1,269 @@
+//! Recording a run to repeat it.
+//!
+//! `--record FILE` saves to `FILE`, as JSON, what a run resolved before its
+//! downloads started: its command-line arguments, the directory it ran in, the
+//! text of the configuration file it read, and the URLs it was to download,
+//! once `-i` lists were read, directory listings expanded (`--recursive`), and
+//! URLs canonicalized (see [`canonical`](crate::canonical)), with the files
+//! they were to be saved to and their priorities.
+//!
+//! `rustwget replay FILE` runs it again in the same directory, with the same
+//! arguments and configuration, on exactly those URLs, without reading input
+//! files or listings again, so that a bug report or a data pull can be
+//! repeated as it was.
+//!
+//! The values of `--bearer`, `--password`, and `--proxy-password` are not
+//! recorded; the replay asks for them instead. The file can still hold
+//! credentials in URLs, so it is created readable by its owner only.
+
+use crate::auth;
+use crate::input::Entry;
+use crate::logfile;
+use clap::{App, Arg, SubCommand};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Options whose values are secrets, which are not recorded.
+const SECRET_OPTIONS: [&str; 3] = ["--bearer", "--password", "--proxy-password"];
+
+/// What a secret value is recorded as.
+const ASKED: &str = "<asked on replay>";
+
+/// Defines the `replay` subcommand.
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("replay")
+        .about("Run a run recorded with --record again, with the same options and URLs")
+        .arg(
+            Arg::with_name("SESSION")
+                .help("File written by the run with --record")
+                .required(true)
+                .index(1),
+        )
+}
+
+/// A URL a recorded run was to download.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recorded {
+    pub url: String,
+    /// Where to save the download, if not derived from the URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    #[serde(default)]
+    pub priority: i32,
+}
+
+/// What a run resolved before its downloads started.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    /// The version of rustwget that recorded the run.
+    pub version: String,
+    /// Command-line arguments of the run, without the program name, with
+    /// the values of [`SECRET_OPTIONS`] replaced.
+    pub arguments: Vec<String>,
+    /// The working directory of the run.
+    pub directory: PathBuf,
+    /// The text of the configuration file the run read, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<String>,
+    pub entries: Vec<Recorded>,
+}
+
+impl Session {
+    /// Records a run with `arguments` and `config` about to download `entries`
+    /// from the current directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the current directory cannot be determined.
+    pub fn new(
+        arguments: Vec<String>,
+        config: Option<String>,
+        entries: &[Entry],
+    ) -> Result<Session, Box<dyn std::error::Error>> {
+        Ok(Session {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            arguments: hide_secrets(arguments),
+            directory: std::env::current_dir()?,
+            config,
+            entries: entries
+                .iter()
+                .map(|entry| Recorded {
+                    url: entry.url.clone(),
+                    output: entry.output.clone(),
+                    priority: entry.priority,
+                })
+                .collect(),
+        })
+    }
+
+    /// Reads a session written by [`Session::write`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not a session.
+    pub fn read(path: &Path) -> Result<Session, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("cannot read session {}: {}", path.display(), err))?;
+        let session: Session = serde_json::from_str(&text)
+            .map_err(|err| format!("invalid session {}: {}", path.display(), err))?;
+        if session.version != env!("CARGO_PKG_VERSION") {
+            logfile::warning(&format!(
+                "{} was recorded by rustwget {}; replaying it with {}",
+                path.display(),
+                session.version,
+                env!("CARGO_PKG_VERSION")
+            ));
+        }
+        Ok(session)
+    }
+
+    /// Writes the session to `path` as JSON, readable by its owner only.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let text = serde_json::to_string_pretty(self)? + "\n";
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        // The mode only applies to a new file, so an earlier one is replaced.
+        let _ = fs::remove_file(path);
+        options
+            .open(path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .map_err(|err| format!("cannot write session {}: {}", path.display(), err).into())
+    }
+
+    /// Asks for the values of the secret options that were not recorded, and
+    /// puts them back into the arguments.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a secret cannot be read.
+    pub fn ask_secrets(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        ask_secrets(&mut self.arguments, "replay")
+    }
+
+    /// Returns the URLs to download again as entries.
+    pub fn entries(&self) -> Vec<Entry> {
+        self.entries
+            .iter()
+            .map(|recorded| Entry {
+                url: recorded.url.clone(),
+                priority: recorded.priority,
+                output: recorded.output.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Replaces the values of [`SECRET_OPTIONS`] in `arguments` with [`ASKED`].
+pub fn hide_secrets(mut arguments: Vec<String>) -> Vec<String> {
+    let mut secret_follows = false;
+    for argument in &mut arguments {
+        if std::mem::take(&mut secret_follows) {
+            *argument = ASKED.to_string();
+        } else if SECRET_OPTIONS.contains(&argument.as_str()) {
+            secret_follows = true;
+        } else if let Some((option, _)) = argument
+            .split_once('=')
+            .filter(|(option, _)| SECRET_OPTIONS.contains(option))
+        {
+            *argument = format!("{}={}", option, ASKED);
+        }
+    }
+    arguments
+}
+
+/// Asks for the values that [`hide_secrets`] replaced in `arguments`, for the
+/// `run` about to use them, and puts them back.
+///
+/// # Errors
+///
+/// Returns an error if a secret cannot be read.
+pub fn ask_secrets(arguments: &mut [String], run: &str) -> Result<(), Box<dyn std::error::Error>> {
+    for index in 0..arguments.len() {
+        let argument = &arguments[index];
+        let (option, prefix) = match argument.split_once('=') {
+            Some((option, ASKED)) => (option.to_string(), format!("{}=", option)),
+            None if argument == ASKED && index > 0 => (arguments[index - 1].clone(), String::new()),
+            _ => continue,
+        };
+        let secret = auth::read_secret(&format!("Value of {} for the {}: ", option, run))?;
+        arguments[index] = prefix + &secret;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.json");
+        // A session written before is readable by anyone.
+        fs::write(&path, "{}").unwrap();
+        let entries = [
+            Entry {
+                url: "https://example.com/a.txt".to_string(),
+                priority: 1,
+                output: None,
+            },
+            Entry {
+                url: "https://example.com/b.txt".to_string(),
+                priority: 0,
+                output: Some(PathBuf::from("docs/b.txt")),
+            },
+        ];
+        let session = Session::new(
+            vec!["-i".to_string(), "urls.txt".to_string()],
+            Some("[[bandwidth]]\nfrom = \"09:00\"\n".to_string()),
+            &entries,
+        )
+        .unwrap();
+        session.write(&path).unwrap();
+        let read = Session::read(&path).unwrap();
+        assert_eq!(read, session);
+        assert_eq!(read.entries(), entries);
+        assert!(Session::read(&dir.path().join("missing.json")).is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_secrets_are_not_recorded() {
+        let arguments = [
+            "--user",
+            "me",
+            "--password",
+            "hunter2",
+            "--bearer=t0ken",
+            "-i",
+        ];
+        let hidden = hide_secrets(arguments.iter().map(|a| a.to_string()).collect());
+        assert_eq!(
+            hidden,
+            [
+                "--user",
+                "me",
+                "--password",
+                ASKED,
+                &format!("--bearer={}", ASKED),
+                "-i"
+            ]
+        );
+    }
+}
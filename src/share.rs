@@ -0,0 +1,236 @@
+//! Public share links of cloud drives.
+//!
+//! The links people paste from Google Drive, Dropbox, and OneDrive open a
+//! preview page rather than the file. [`resolve`] rewrites them to the
+//! provider's direct download URL:
+//!
+//! * `drive.google.com/file/d/ID/view`, `open?id=ID`, and `uc?id=ID` become a
+//!   `drive.usercontent.google.com` download that skips the virus-scan warning.
+//! * `dropbox.com/s/...` and `dropbox.com/scl/fi/...` get `dl=1`.
+//! * `1drv.ms` and `onedrive.live.com` links go through the OneDrive shares API.
+//!
+//! Google Drive may still answer with a warning page for large files; its
+//! confirmation form is followed with [`confirm_url`].
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use url::Url;
+
+/// A share link resolved to a direct download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// The direct download URL.
+    pub url: String,
+    /// The local file name to use when none is given.
+    pub filename: String,
+}
+
+/// Resolves a share link to its direct download, or returns `None` for other URLs.
+pub fn resolve(url: &str) -> Option<Link> {
+    let parsed = Url::parse(url).ok()?;
+    let host = parsed.host_str()?.to_ascii_lowercase();
+    match host.as_str() {
+        "drive.google.com" | "docs.google.com" => drive(&parsed),
+        "www.dropbox.com" | "dropbox.com" => dropbox(parsed),
+        "1drv.ms" | "onedrive.live.com" => Some(Link {
+            url: format!(
+                "https://api.onedrive.com/v1.0/shares/u!{}/root/content",
+                URL_SAFE_NO_PAD.encode(url)
+            ),
+            filename: last_segment(&parsed)
+                .unwrap_or("onedrive-download")
+                .to_string(),
+        }),
+        _ => None,
+    }
+}
+
+/// Returns whether `url` is served by Google Drive, which may answer with a warning page.
+pub fn is_drive(url: &str) -> bool {
+    Url::parse(url).ok().is_some_and(|url| {
+        matches!(
+            url.host_str(),
+            Some("drive.google.com" | "drive.usercontent.google.com" | "docs.google.com")
+        )
+    })
+}
+
+/// Resolves a Google Drive link from the file ID in its path or query.
+fn drive(url: &Url) -> Option<Link> {
+    let segments: Vec<&str> = url.path_segments()?.collect();
+    let id = match segments.as_slice() {
+        ["file", "d", id, ..] => id.to_string(),
+        ["open"] | ["uc"] => url
+            .query_pairs()
+            .find(|(name, _)| name == "id")
+            .map(|(_, id)| id.into_owned())?,
+        _ => return None,
+    };
+    let mut direct = Url::parse("https://drive.usercontent.google.com/download").ok()?;
+    direct
+        .query_pairs_mut()
+        .append_pair("id", &id)
+        .append_pair("export", "download")
+        .append_pair("confirm", "t");
+    Some(Link {
+        url: direct.to_string(),
+        filename: id,
+    })
+}
+
+/// Resolves a Dropbox link by asking for the download instead of the preview.
+fn dropbox(mut url: Url) -> Option<Link> {
+    let first = url.path_segments()?.next()?;
+    if !matches!(first, "s" | "scl" | "sh") {
+        return None;
+    }
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| name != "dl" && name != "raw")
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair("dl", "1");
+    let filename = last_segment(&url).unwrap_or("dropbox-download").to_string();
+    Some(Link {
+        url: url.to_string(),
+        filename,
+    })
+}
+
+fn last_segment(url: &Url) -> Option<&str> {
+    url.path_segments()?
+        .next_back()
+        .filter(|segment| !segment.is_empty())
+}
+
+/// Finds the download behind a Google Drive warning page.
+///
+/// # Arguments
+///
+/// * `url`: The URL that returned the page.
+/// * `page`: The HTML of the page.
+///
+/// # Returns
+///
+/// * `Option<String>`: The URL that confirms the download, or `None` if the
+///   page holds no confirmation form or link.
+pub fn confirm_url(url: &str, page: &str) -> Option<String> {
+    let base = Url::parse(url).ok()?;
+    if let Some(start) = page.find("id=\"download-form\"") {
+        let form_start = page[..start].rfind("<form")?;
+        let form_end = form_start + page[form_start..].find("</form>")?;
+        let form = &page[form_start..form_end];
+        let tag_end = form.find('>')?;
+        let mut target = base
+            .join(&unescape(attribute(&form[..tag_end], "action")?))
+            .ok()?;
+        {
+            let mut query = target.query_pairs_mut();
+            for input in form.split("<input").skip(1) {
+                let input = &input[..input.find('>')?];
+                if attribute(input, "type") == Some("hidden") {
+                    if let (Some(name), Some(value)) =
+                        (attribute(input, "name"), attribute(input, "value"))
+                    {
+                        query.append_pair(&unescape(name), &unescape(value));
+                    }
+                }
+            }
+        }
+        return Some(target.to_string());
+    }
+    // Older pages link to the download with a one-time confirmation token.
+    page.split("href=\"")
+        .skip(1)
+        .filter_map(|rest| rest.split('"').next())
+        .map(unescape)
+        .find(|href| href.contains("confirm=") && href.contains("export=download"))
+        .and_then(|href| base.join(&href).ok())
+        .map(String::from)
+}
+
+/// Returns the value of attribute `name` in the text of an HTML tag.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!("{}=\"", name);
+    let start = tag
+        .match_indices(&pattern)
+        .find(|(index, _)| *index == 0 || tag[..*index].ends_with(char::is_whitespace))?
+        .0
+        + pattern.len();
+    tag[start..].split('"').next()
+}
+
+/// Decodes the HTML entities that appear in URLs and form values.
+pub fn unescape(text: &str) -> String {
+    text.replace("&amp;", "&")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_share_links() {
+        let expected =
+            "https://drive.usercontent.google.com/download?id=1AbC-d_E&export=download&confirm=t";
+        for link in [
+            "https://drive.google.com/file/d/1AbC-d_E/view?usp=sharing",
+            "https://drive.google.com/open?id=1AbC-d_E",
+            "https://drive.google.com/uc?export=download&id=1AbC-d_E",
+        ] {
+            let resolved = resolve(link).unwrap();
+            assert_eq!(resolved.url, expected);
+            assert_eq!(resolved.filename, "1AbC-d_E");
+        }
+
+        assert_eq!(
+            resolve("https://www.dropbox.com/scl/fi/abc123/report.pdf?rlkey=xyz&dl=0").unwrap(),
+            Link {
+                url: "https://www.dropbox.com/scl/fi/abc123/report.pdf?rlkey=xyz&dl=1".to_string(),
+                filename: "report.pdf".to_string(),
+            }
+        );
+
+        assert_eq!(
+            resolve("https://1drv.ms/u/s!AbCdEf").unwrap().url,
+            "https://api.onedrive.com/v1.0/shares/u!aHR0cHM6Ly8xZHJ2Lm1zL3UvcyFBYkNkRWY/root/content"
+        );
+
+        assert_eq!(resolve("https://example.com/file/d/x/view"), None);
+        assert_eq!(resolve("https://www.dropbox.com/home"), None);
+    }
+
+    #[test]
+    fn test_confirm_url_follows_warning_form() {
+        let page = r#"<html><body><p>Google Drive can't scan this file for viruses.</p>
+            <form id="download-form" action="https://drive.usercontent.google.com/download" method="get">
+            <input type="submit" id="uc-download-link" value="Download anyway"/>
+            <input type="hidden" name="id" value="1AbC">
+            <input type="hidden" name="export" value="download">
+            <input type="hidden" name="confirm" value="t">
+            <input type="hidden" name="uuid" value="5f0e&amp;9">
+            </form></body></html>"#;
+        assert_eq!(
+            confirm_url("https://drive.usercontent.google.com/download?id=1AbC", page).unwrap(),
+            "https://drive.usercontent.google.com/download?id=1AbC&export=download&confirm=t&uuid=5f0e%269"
+        );
+
+        let legacy = r#"<a id="uc-download-link" href="/uc?export=download&amp;confirm=Xy1z&amp;id=1AbC">Download anyway</a>"#;
+        assert_eq!(
+            confirm_url("https://drive.google.com/uc?id=1AbC", legacy).unwrap(),
+            "https://drive.google.com/uc?export=download&confirm=Xy1z&id=1AbC"
+        );
+
+        assert_eq!(
+            confirm_url("https://drive.google.com/uc?id=1AbC", "<p>Sign in</p>"),
+            None
+        );
+    }
+}
@@ -0,0 +1,418 @@
+//! AWS Signature Version 4 request signing, as done by curl's `--aws-sigv4`.
+//!
+//! `--aws-sigv4 PROVIDER1[:PROVIDER2[:REGION[:SERVICE]]]` signs every request,
+//! for example `aws:amz:eu-west-1:s3`. The providers name the algorithm
+//! (`AWS4-HMAC-SHA256`) and the date header (`X-Amz-Date`); a missing region
+//! or service is taken from an `amazonaws.com` host name.
+//!
+//! Keys come from the standard credential chain: the `AWS_ACCESS_KEY_ID`,
+//! `AWS_SECRET_ACCESS_KEY`, and `AWS_SESSION_TOKEN` environment variables,
+//! then the `AWS_PROFILE` (or `default`) profile of the shared credentials
+//! file (`AWS_SHARED_CREDENTIALS_FILE`, or `~/.aws/credentials`).
+
+use crate::ledger::hex;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use url::Url;
+
+/// Characters left unencoded in canonical URIs and query strings.
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// An access key pair, with the session token of temporary credentials.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Keys {
+    pub access_key: String,
+    pub secret_key: String,
+    pub session_token: Option<String>,
+}
+
+impl fmt::Debug for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Keys({}, <redacted>)", self.access_key)
+    }
+}
+
+/// Signs requests for one provider, region, and service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signer {
+    /// Names the algorithm, e.g. `aws` for `AWS4-HMAC-SHA256`.
+    provider: String,
+    /// Names the headers, e.g. `amz` for `x-amz-date`.
+    header_provider: String,
+    region: Option<String>,
+    service: Option<String>,
+    keys: Keys,
+}
+
+impl Signer {
+    /// Creates a signer from an `--aws-sigv4` value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value has no provider or more than four parts.
+    pub fn new(spec: &str, keys: Keys) -> Result<Signer, String> {
+        let parts: Vec<&str> = spec.split(':').collect();
+        if parts[0].is_empty() || parts.len() > 4 || parts.iter().any(|part| part.is_empty()) {
+            return Err(format!(
+                "invalid --aws-sigv4 '{}': expected PROVIDER1[:PROVIDER2[:REGION[:SERVICE]]]",
+                spec
+            ));
+        }
+        let part = |index: usize| parts.get(index).map(|part| part.to_ascii_lowercase());
+        Ok(Signer {
+            provider: parts[0].to_ascii_lowercase(),
+            header_provider: part(1).unwrap_or_else(|| parts[0].to_ascii_lowercase()),
+            region: part(2),
+            service: part(3),
+            keys,
+        })
+    }
+
+    /// Returns the region and service that requests to `host` are signed for.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if they were not given and cannot be derived from the host.
+    pub fn scope(&self, host: &str) -> Result<(String, String), String> {
+        if let (Some(region), Some(service)) = (&self.region, &self.service) {
+            return Ok((region.clone(), service.clone()));
+        }
+        let (service, region) = from_host(host).ok_or_else(|| {
+            format!(
+                "cannot derive the region and service from '{}'; give them as --aws-sigv4 aws:amz:REGION:SERVICE",
+                host
+            )
+        })?;
+        Ok((
+            self.region.clone().unwrap_or(region),
+            self.service.clone().unwrap_or(service),
+        ))
+    }
+
+    /// Returns the headers that sign a request, including the date header.
+    ///
+    /// # Arguments
+    ///
+    /// * `method`: The request method.
+    /// * `url`: The request URL.
+    /// * `headers`: Headers already on the request; `content-type` and provider headers are signed.
+    /// * `now`: The signing time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the region or service is neither given nor derivable from the host.
+    pub fn sign(
+        &self,
+        method: &str,
+        url: &Url,
+        headers: &HeaderMap,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<(HeaderName, HeaderValue)>, String> {
+        let host = url.host_str().ok_or("cannot sign a URL without a host")?;
+        let (region, service) = self.scope(host)?;
+        let prefix = format!("x-{}-", self.header_provider);
+        let date_time = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex(&Sha256::digest(b""));
+
+        let mut added = vec![(format!("{}date", prefix), date_time.clone())];
+        if service == "s3" {
+            added.push((format!("{}content-sha256", prefix), payload_hash.clone()));
+        }
+        if let Some(token) = &self.keys.session_token {
+            added.push((format!("{}security-token", prefix), token.clone()));
+        }
+
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        let mut canonical_headers = vec![("host".to_string(), host)];
+        canonical_headers.extend(added.iter().cloned());
+        for (name, value) in headers {
+            if (name == CONTENT_TYPE || name.as_str().starts_with(&prefix))
+                && !canonical_headers
+                    .iter()
+                    .any(|(added, _)| added == name.as_str())
+            {
+                let value = value.to_str().map_err(|_| "header is not valid text")?;
+                canonical_headers.push((name.as_str().to_string(), value.trim().to_string()));
+            }
+        }
+        canonical_headers.sort();
+        let signed_headers = canonical_headers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method,
+            canonical_path(url, service != "s3"),
+            canonical_query(url),
+            canonical_headers
+                .iter()
+                .map(|(name, value)| format!("{}:{}\n", name, value))
+                .collect::<String>(),
+            signed_headers,
+            payload_hash
+        );
+        let algorithm = format!("{}4-HMAC-SHA256", self.provider.to_ascii_uppercase());
+        let scope = format!("{}/{}/{}/{}4_request", date, region, service, self.provider);
+        let string_to_sign = format!(
+            "{}\n{}\n{}\n{}",
+            algorithm,
+            date_time,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let mut key = format!(
+            "{}4{}",
+            self.provider.to_ascii_uppercase(),
+            self.keys.secret_key
+        )
+        .into_bytes();
+        for part in [
+            date.as_str(),
+            &region,
+            &service,
+            &format!("{}4_request", self.provider),
+        ] {
+            key = hmac_sha256(&key, part.as_bytes());
+        }
+        let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        added.push((
+            "authorization".to_string(),
+            format!(
+                "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+                algorithm, self.keys.access_key, scope, signed_headers, signature
+            ),
+        ));
+        added
+            .into_iter()
+            .map(|(name, value)| {
+                let name =
+                    HeaderName::from_bytes(name.as_bytes()).map_err(|err| err.to_string())?;
+                let mut value = HeaderValue::from_str(&value).map_err(|err| err.to_string())?;
+                value.set_sensitive(true);
+                Ok((name, value))
+            })
+            .collect()
+    }
+}
+
+/// Derives the service and region from an `amazonaws.com` host name.
+fn from_host(host: &str) -> Option<(String, String)> {
+    let labels: Vec<&str> = host.strip_suffix(".amazonaws.com")?.split('.').collect();
+    match labels.as_slice() {
+        // bucket.s3.region, service.region, or s3-region style hosts.
+        [.., service, region] if service.starts_with("s3") => {
+            Some(("s3".to_string(), region.to_string()))
+        }
+        [.., service, region] => Some((service.to_string(), region.to_string())),
+        [service] => Some((service.to_string(), "us-east-1".to_string())),
+        [] => None,
+    }
+}
+
+/// Encodes the path of `url`, twice over for services other than S3.
+fn canonical_path(url: &Url, double: bool) -> String {
+    let path = url.path();
+    if path.is_empty() {
+        return "/".to_string();
+    }
+    path.split('/')
+        .map(|segment| {
+            let decoded = percent_decode_str(segment).decode_utf8_lossy();
+            let encoded = utf8_percent_encode(&decoded, UNRESERVED).to_string();
+            if double {
+                utf8_percent_encode(&encoded, UNRESERVED).to_string()
+            } else {
+                encoded
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Encodes and sorts the query parameters of `url`.
+fn canonical_query(url: &Url) -> String {
+    let mut pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(name, value)| {
+            (
+                utf8_percent_encode(&name, UNRESERVED).to_string(),
+                utf8_percent_encode(&value, UNRESERVED).to_string(),
+            )
+        })
+        .collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Finds keys through the environment and the shared credentials file.
+///
+/// # Errors
+///
+/// Returns an error if neither holds a complete key pair.
+pub fn keys() -> Result<Keys, String> {
+    let var = |name| {
+        std::env::var(name)
+            .ok()
+            .filter(|value: &String| !value.is_empty())
+    };
+    if let (Some(access_key), Some(secret_key)) =
+        (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY"))
+    {
+        return Ok(Keys {
+            access_key,
+            secret_key,
+            session_token: var("AWS_SESSION_TOKEN"),
+        });
+    }
+
+    let path = var("AWS_SHARED_CREDENTIALS_FILE")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".aws/credentials"))
+        });
+    let profile = var("AWS_PROFILE").unwrap_or_else(|| "default".to_string());
+    path.and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|text| profile_keys(&text, &profile))
+        .ok_or_else(|| {
+            "no AWS credentials found: set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, or add them to ~/.aws/credentials".to_string()
+        })
+}
+
+/// Reads the keys of `profile` from the text of a shared credentials file.
+fn profile_keys(text: &str, profile: &str) -> Option<Keys> {
+    let mut section = None;
+    let (mut access_key, mut secret_key, mut session_token) = (None, None, None);
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            section = Some(name.trim().to_string());
+        } else if section.as_deref() == Some(profile) {
+            if let Some((key, value)) = line.split_once('=') {
+                let value = Some(value.trim().to_string());
+                match key.trim() {
+                    "aws_access_key_id" => access_key = value,
+                    "aws_secret_access_key" => secret_key = value,
+                    "aws_session_token" => session_token = value,
+                    _ => {}
+                }
+            }
+        }
+    }
+    Some(Keys {
+        access_key: access_key?,
+        secret_key: secret_key?,
+        session_token,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn example_keys() -> Keys {
+        Keys {
+            access_key: "AKIDEXAMPLE".to_string(),
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        }
+    }
+
+    fn authorization(signer: &Signer, url: &str, headers: &HeaderMap) -> String {
+        let now = Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap();
+        let signed = signer
+            .sign("GET", &Url::parse(url).unwrap(), headers, now)
+            .unwrap();
+        signed
+            .iter()
+            .find(|(name, _)| name == "authorization")
+            .map(|(_, value)| value.to_str().unwrap().to_string())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_signs_aws_test_suite_requests() {
+        let signer = Signer::new("aws:amz:us-east-1:service", example_keys()).unwrap();
+        assert_eq!(
+            authorization(&signer, "https://example.amazonaws.com/", &HeaderMap::new()),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+
+        let signer = Signer::new("aws:amz", example_keys()).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded; charset=utf-8"),
+        );
+        assert_eq!(
+            authorization(
+                &signer,
+                "https://iam.amazonaws.com/?Action=ListUsers&Version=2010-05-08",
+                &headers
+            ),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        );
+    }
+
+    #[test]
+    fn test_region_and_service_from_host() {
+        assert_eq!(
+            from_host("bucket.s3.eu-west-1.amazonaws.com"),
+            Some(("s3".to_string(), "eu-west-1".to_string()))
+        );
+        assert_eq!(
+            from_host("sqs.us-west-2.amazonaws.com"),
+            Some(("sqs".to_string(), "us-west-2".to_string()))
+        );
+        assert_eq!(from_host("example.com"), None);
+        assert!(Signer::new("aws::x", example_keys()).is_err());
+    }
+
+    #[test]
+    fn test_profile_keys() {
+        let text = "[default]\naws_access_key_id = A\naws_secret_access_key = B\n\n[ci]\naws_access_key_id=C\naws_secret_access_key=D\naws_session_token=E\n";
+        assert_eq!(profile_keys(text, "default").unwrap().access_key, "A");
+        assert_eq!(
+            profile_keys(text, "ci").unwrap().session_token.as_deref(),
+            Some("E")
+        );
+        assert!(profile_keys(text, "missing").is_none());
+    }
+}
@@ -0,0 +1,67 @@
+//! Skipping files by their size.
+//!
+//! `--min-size SIZE` and `--max-size SIZE` skip downloads whose size is
+//! outside the bounds, such as thumbnails in a recursive download or a file
+//! that turns out to be a disk image. The size is taken from `Content-Length`
+//! before the first byte is written. A download of unknown size is stopped as
+//! soon as it grows past `--max-size`, and one that ends smaller than
+//! `--min-size` is removed; either counts as skipped in the summary.
+
+use crate::units;
+
+/// The sizes a downloaded file may have.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Bounds {
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+}
+
+impl Bounds {
+    /// Returns why a file of `size` bytes is skipped, if it is.
+    pub fn reject(&self, size: u64) -> Option<String> {
+        match (self.min, self.max) {
+            (Some(min), _) if size < min => Some(format!(
+                "{} is below --min-size {}",
+                units::format_bytes(size),
+                units::format_bytes(min)
+            )),
+            (_, Some(max)) if size > max => Some(format!(
+                "{} is over --max-size {}",
+                units::format_bytes(size),
+                units::format_bytes(max)
+            )),
+            _ => None,
+        }
+    }
+
+    /// Returns why a file that has grown to `size` bytes is skipped before
+    /// it ends, if it is.
+    pub fn exceeded(&self, size: u64) -> Option<String> {
+        self.max
+            .filter(|max| size > *max)
+            .and_then(|_| self.reject(size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reject() {
+        let bounds = Bounds {
+            min: Some(1024),
+            max: Some(4096),
+        };
+        assert_eq!(
+            bounds.reject(10).as_deref(),
+            Some("10 B is below --min-size 1.0 KiB")
+        );
+        assert_eq!(bounds.reject(1024), None);
+        assert_eq!(bounds.reject(4096), None);
+        assert!(bounds.reject(4097).unwrap().contains("--max-size"));
+        assert_eq!(bounds.exceeded(10), None);
+        assert!(bounds.exceeded(5000).is_some());
+        assert_eq!(Bounds::default().reject(u64::MAX), None);
+    }
+}
@@ -0,0 +1,150 @@
+//! Writing one download as a series of fixed-size part files.
+//!
+//! With `--split-output SIZE`, `name` is written as `name.part000`,
+//! `name.part001`, ... of at most `SIZE` bytes each, for filesystems and media
+//! with a per-file size limit. `cat name.part* > name` joins them again.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Returns the path of part `index` of `base`.
+pub fn part_path(base: &Path, index: u64) -> PathBuf {
+    let mut name = base.as_os_str().to_owned();
+    name.push(format!(".part{:03}", index));
+    PathBuf::from(name)
+}
+
+/// Returns how many bytes of `base` the existing part files already hold.
+///
+/// Only full parts followed by at most one shorter part count, so that an
+/// unrelated stray part file is not mistaken for downloaded data.
+pub fn existing_len(base: &Path, size: u64) -> u64 {
+    let mut total = 0;
+    for index in 0.. {
+        match fs::metadata(part_path(base, index)) {
+            Ok(meta) => {
+                total += meta.len().min(size);
+                if meta.len() < size {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    total
+}
+
+/// Writes a stream across part files of at most `size` bytes.
+#[derive(Debug)]
+pub struct SplitWriter {
+    base: PathBuf,
+    size: u64,
+    index: u64,
+    /// Bytes in the current part.
+    filled: u64,
+    file: Option<File>,
+}
+
+impl SplitWriter {
+    /// Starts writing `base` at byte `offset`, keeping the parts before it.
+    ///
+    /// The parts following the one that holds `offset` are deleted, as they
+    /// belong to an earlier download.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `size` is zero or the partial part cannot be opened.
+    pub fn open(base: &Path, size: u64, offset: u64) -> io::Result<SplitWriter> {
+        if size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the part size must be positive",
+            ));
+        }
+        let (index, filled) = (offset / size, offset % size);
+        let file = if filled > 0 {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .open(part_path(base, index))?;
+            file.set_len(filled)?;
+            file.seek(SeekFrom::End(0))?;
+            Some(file)
+        } else {
+            None
+        };
+        let first_stale = if filled > 0 { index + 1 } else { index };
+        for stale in first_stale.. {
+            if fs::remove_file(part_path(base, stale)).is_err() {
+                break;
+            }
+        }
+        Ok(SplitWriter {
+            base: base.to_path_buf(),
+            size,
+            index,
+            filled,
+            file,
+        })
+    }
+}
+
+impl Write for SplitWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.filled == self.size {
+            self.index += 1;
+            self.filled = 0;
+            self.file = None;
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self
+                .file
+                .insert(File::create(part_path(&self.base, self.index))?),
+        };
+        let len = buf.len().min((self.size - self.filled) as usize);
+        let written = file.write(&buf[..len])?;
+        self.filled += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writes_and_resumes_parts() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("big.iso");
+        for index in 0..3 {
+            fs::write(part_path(&base, index), "old!").unwrap();
+        }
+
+        let mut writer = SplitWriter::open(&base, 4, 0).unwrap();
+        writer.write_all(b"0123456").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(fs::read(part_path(&base, 0)).unwrap(), b"0123");
+        assert_eq!(fs::read(part_path(&base, 1)).unwrap(), b"456");
+        assert!(!part_path(&base, 2).exists());
+        assert_eq!(existing_len(&base, 4), 7);
+
+        let mut writer = SplitWriter::open(&base, 4, 6).unwrap();
+        writer.write_all(b"6789").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(fs::read(part_path(&base, 1)).unwrap(), b"4567");
+        assert_eq!(fs::read(part_path(&base, 2)).unwrap(), b"89");
+        assert_eq!(existing_len(&base, 4), 10);
+        assert_eq!(part_path(&base, 12).file_name().unwrap(), "big.iso.part012");
+    }
+}
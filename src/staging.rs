@@ -0,0 +1,83 @@
+//! Writing downloads in a scratch directory.
+//!
+//! With `--tmp-dir DIR`, each download is written to `DIR/NAME.HASH.part`
+//! and only moved to its destination once it is complete and verified, so
+//! that watched or synced directories never see partial files. `HASH` stands
+//! for the absolute destination, which keeps downloads of the same name apart
+//! and lets a later attempt resume the same partial file. `DIR` may be on
+//! another file system, in which case the file is copied into place.
+//!
+//! Without `--tmp-dir`, a download with a checksum or a lockfile pin to
+//! verify is staged the same way in its destination's own directory.
+
+use crate::ledger;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Returns where the download into `target` is written within `dir`.
+pub fn path(dir: &Path, target: &Path) -> PathBuf {
+    let absolute = std::path::absolute(target).unwrap_or_else(|_| target.to_path_buf());
+    let hash = Sha256::digest(absolute.as_os_str().as_encoded_bytes());
+    let mut name = target.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".{}.part", &ledger::hex(&hash)[..8]));
+    dir.join(name)
+}
+
+/// Creates `dir` and returns where the download into `target` is written.
+///
+/// When `resume` is set and `target` holds a partial file that is not yet in
+/// `dir`, it is moved there to be continued.
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be created or the file not moved.
+pub fn prepare(dir: &Path, target: &Path, resume: bool) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let staged = path(dir, target);
+    if resume && !staged.exists() && target.exists() {
+        move_file(target, &staged)?;
+    }
+    Ok(staged)
+}
+
+/// Moves `from` to `to`, copying it when they are on different file systems.
+///
+/// # Errors
+///
+/// Returns an error if the file can neither be renamed nor copied.
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepare_resumes_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let scratch = dir.path().join("scratch");
+        let target = dir.path().join("file.iso");
+
+        let staged = prepare(&scratch, &target, false).unwrap();
+        assert_eq!(staged.parent(), Some(scratch.as_path()));
+        let name = staged.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("file.iso.") && name.ends_with(".part"));
+        assert_ne!(staged, path(&scratch, &dir.path().join("other/file.iso")));
+
+        fs::write(&target, "partial").unwrap();
+        assert_eq!(prepare(&scratch, &target, true).unwrap(), staged);
+        assert!(!target.exists());
+        assert_eq!(fs::read(&staged).unwrap(), b"partial");
+
+        move_file(&staged, &target).unwrap();
+        assert!(!staged.exists());
+        assert_eq!(fs::read(&target).unwrap(), b"partial");
+    }
+}
@@ -0,0 +1,209 @@
+//! Supervision of `rustwget daemon` by systemd.
+//!
+//! Under a `Type=notify` service the daemon reports `READY=1` once its control
+//! API accepts connections, and under `WatchdogSec=` it sends `WATCHDOG=1` at
+//! half the interval systemd expects, so that a hung daemon is restarted.
+//! With socket activation (a `.socket` unit with `ListenStream=` lines) the
+//! daemon serves the control API on the sockets systemd passes it instead of
+//! binding `--listen` and `--socket` itself:
+//!
+//! ```text
+//! # rustwget.socket                # rustwget.service
+//! [Socket]                         [Service]
+//! ListenStream=127.0.0.1:8750      Type=notify
+//! ListenStream=/run/rustwget.sock  WatchdogSec=30
+//!                                  ExecStart=/usr/bin/rustwget daemon
+//! ```
+//!
+//! All of this follows the `sd_notify` and `sd_listen_fds` protocols through
+//! the `NOTIFY_SOCKET`, `WATCHDOG_USEC`, and `LISTEN_FDS` environment
+//! variables, without linking libsystemd; outside systemd it does nothing.
+
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+/// A socket the control API is served on.
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+}
+
+/// Tells systemd about the state of the service, such as `READY=1`.
+///
+/// Returns whether the service is supervised, that is whether
+/// `NOTIFY_SOCKET` is set.
+///
+/// # Errors
+///
+/// Returns an error if the message cannot be sent.
+pub fn notify(state: &str) -> io::Result<bool> {
+    match env::var("NOTIFY_SOCKET") {
+        Ok(socket) if !socket.is_empty() => send(&socket, state).map(|_| true),
+        _ => Ok(false),
+    }
+}
+
+#[cfg(unix)]
+fn send(socket: &str, state: &str) -> io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let datagram = UnixDatagram::unbound()?;
+    // A socket starting with `@` is in the abstract namespace of Linux.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if let Some(name) = socket.strip_prefix('@') {
+        #[cfg(target_os = "android")]
+        use std::os::android::net::SocketAddrExt;
+        #[cfg(target_os = "linux")]
+        use std::os::linux::net::SocketAddrExt;
+        let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        return datagram
+            .send_to_addr(state.as_bytes(), &address)
+            .map(|_| ());
+    }
+    datagram.send_to(state.as_bytes(), socket).map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn send(_socket: &str, _state: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "NOTIFY_SOCKET is only supported on Unix",
+    ))
+}
+
+/// Sends `WATCHDOG=1` for the rest of the run, if systemd expects it.
+pub fn start_watchdog() {
+    let Some(interval) = watchdog_interval(
+        env::var("WATCHDOG_USEC").ok().as_deref(),
+        env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    ) else {
+        return;
+    };
+    thread::spawn(move || loop {
+        if let Err(err) = notify("WATCHDOG=1") {
+            eprintln!("Warning: cannot notify the watchdog: {}", err);
+        }
+        thread::sleep(interval);
+    });
+}
+
+/// Returns how often to send `WATCHDOG=1` to meet the timeout `usec`, if it
+/// is set for the process `pid`.
+fn watchdog_interval(usec: Option<&str>, watchdog_pid: Option<&str>, pid: u32) -> Option<Duration> {
+    if watchdog_pid.is_some_and(|watchdog_pid| watchdog_pid.parse() != Ok(pid)) {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Returns the number of sockets passed to the process `pid` by `LISTEN_PID`
+/// and `LISTEN_FDS`.
+fn listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> usize {
+    if listen_pid.and_then(|listen_pid| listen_pid.parse().ok()) != Some(pid) {
+        return 0;
+    }
+    listen_fds.and_then(|fds| fds.parse().ok()).unwrap_or(0)
+}
+
+/// Takes the listening sockets systemd passed to the process, if it was
+/// socket-activated.
+///
+/// The variables describing them are removed, so that processes started by
+/// the daemon do not take them too.
+///
+/// # Errors
+///
+/// Returns an error if a socket is neither a TCP nor a Unix socket.
+pub fn listeners() -> io::Result<Vec<Listener>> {
+    let count = listen_fds(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    );
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+    (0..count).map(take).collect()
+}
+
+/// Passed sockets start at descriptor 3, after standard input, output, and error.
+#[cfg(unix)]
+fn take(index: usize) -> io::Result<Listener> {
+    use std::os::unix::io::FromRawFd;
+    use std::os::unix::net::UnixListener;
+
+    let fd = 3 + index as i32;
+    // SAFETY: `fd` was passed to this process to own, and is taken only once,
+    // as the variables naming it are removed; a zeroed `sockaddr_storage` is
+    // valid and large enough for any address.
+    unsafe {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        let mut address: libc::sockaddr_storage = std::mem::zeroed();
+        let mut length = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        if libc::getsockname(
+            fd,
+            &mut address as *mut _ as *mut libc::sockaddr,
+            &mut length,
+        ) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        match address.ss_family as i32 {
+            libc::AF_INET | libc::AF_INET6 => Ok(Listener::Tcp(TcpListener::from_raw_fd(fd))),
+            libc::AF_UNIX => Ok(Listener::Unix(UnixListener::from_raw_fd(fd))),
+            family => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "socket {} passed by systemd has unsupported family {}",
+                    fd, family
+                ),
+            )),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn take(_index: usize) -> io::Result<Listener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "socket activation is only supported on Unix",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol() {
+        assert_eq!(
+            watchdog_interval(Some("30000000"), None, 7),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(watchdog_interval(Some("30000000"), Some("8"), 7), None);
+        assert_eq!(watchdog_interval(Some("0"), Some("7"), 7), None);
+        assert_eq!(watchdog_interval(None, None, 7), None);
+
+        assert_eq!(listen_fds(Some("7"), Some("2"), 7), 2);
+        assert_eq!(listen_fds(Some("8"), Some("2"), 7), 0);
+        assert_eq!(listen_fds(None, Some("2"), 7), 0);
+
+        #[cfg(unix)]
+        {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("notify");
+            let socket = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+            send(path.to_str().unwrap(), "READY=1").unwrap();
+            let mut buffer = [0; 16];
+            let length = socket.recv(&mut buffer).unwrap();
+            assert_eq!(&buffer[..length], b"READY=1");
+        }
+    }
+}
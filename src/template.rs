@@ -0,0 +1,200 @@
+//! URL templates filled in from a CSV file of values.
+//!
+//! `--template 'https://host/{ver}/{arch}.tar.gz' --vars vars.csv` downloads
+//! one URL per row of `vars.csv`, whose first line names the columns:
+//!
+//! ```text
+//! ver,arch
+//! 1.0,x86_64
+//! 1.1,aarch64
+//! ```
+//!
+//! With `--vars-product` the distinct values of every column are combined
+//! instead, giving the full release matrix (here four URLs). The same
+//! `{name}` placeholders may be used in `-O` to name the files.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Values for the variables of a template, by column name.
+pub type Vars = HashMap<String, String>;
+
+/// Replaces every `{name}` in `template` with its value.
+///
+/// # Errors
+///
+/// Returns an error for an unterminated `{` or a variable without a value.
+pub fn render(template: &str, vars: &Vars) -> Result<String, String> {
+    let mut output = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unterminated '{{' in template '{}'", template))?;
+        let name = &rest[start + 1..start + end];
+        let value = vars
+            .get(name)
+            .ok_or_else(|| format!("template variable '{}' has no value", name))?;
+        output.push_str(value);
+        rest = &rest[start + end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Parses CSV text into one set of variables per row.
+///
+/// The first line holds the column names. Fields may be quoted with `"`, and a
+/// doubled `""` inside quotes stands for a literal quote. Blank lines are skipped.
+///
+/// # Errors
+///
+/// Returns an error naming the offending line when a row has a different
+/// number of fields than the header.
+pub fn parse_csv(text: &str) -> Result<Vec<Vars>, String> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let Some((_, header)) = lines.next() else {
+        return Ok(Vec::new());
+    };
+    let names = fields(header);
+    lines
+        .map(|(number, line)| {
+            let values = fields(line);
+            if values.len() != names.len() {
+                return Err(format!(
+                    "line {}: expected {} fields, found {}",
+                    number + 1,
+                    names.len(),
+                    values.len()
+                ));
+            }
+            Ok(names.iter().cloned().zip(values).collect())
+        })
+        .collect()
+}
+
+/// Splits one CSV line into trimmed fields.
+fn fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+/// Combines the distinct values of every column into all possible rows.
+///
+/// Columns are combined in order of their names, the last varying fastest.
+/// Empty values are ignored so that columns of different lengths can share
+/// one file.
+pub fn product(rows: &[Vars]) -> Vec<Vars> {
+    let mut columns: BTreeMap<&String, Vec<&String>> = BTreeMap::new();
+    for (name, value) in rows.iter().flatten().filter(|(_, value)| !value.is_empty()) {
+        let values = columns.entry(name).or_default();
+        if !values.contains(&value) {
+            values.push(value);
+        }
+    }
+
+    let mut combinations = vec![Vars::new()];
+    for (name, values) in &columns {
+        combinations = combinations
+            .iter()
+            .flat_map(|vars| {
+                values.iter().map(move |value| {
+                    let mut vars = vars.clone();
+                    vars.insert(name.to_string(), value.to_string());
+                    vars
+                })
+            })
+            .collect();
+    }
+    combinations
+}
+
+/// Reads the values file and returns the variables of every download.
+///
+/// # Arguments
+///
+/// * `path`: The CSV file.
+/// * `cartesian`: Whether to combine the columns (`--vars-product`) rather than use each row.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or is not valid CSV.
+pub fn read_vars(path: &Path, cartesian: bool) -> Result<Vec<Vars>, Box<dyn std::error::Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| format!("cannot read {}: {}", path.display(), err))?;
+    let rows = parse_csv(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(if cartesian { product(&rows) } else { rows })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vars {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_render() {
+        let vars = vars(&[("ver", "1.2"), ("arch", "x86_64")]);
+        assert_eq!(
+            render("https://host/{ver}/{arch}.tar.gz", &vars).unwrap(),
+            "https://host/1.2/x86_64.tar.gz"
+        );
+        assert_eq!(
+            render("https://host/{os}", &vars).unwrap_err(),
+            "template variable 'os' has no value"
+        );
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let rows = parse_csv("ver, arch\n1.0,x86_64\n\n\"1,1\",\"a\"\"b\"\n").unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                vars(&[("ver", "1.0"), ("arch", "x86_64")]),
+                vars(&[("ver", "1,1"), ("arch", "a\"b")]),
+            ]
+        );
+        assert_eq!(
+            parse_csv("ver,arch\n1.0\n").unwrap_err(),
+            "line 2: expected 2 fields, found 1"
+        );
+    }
+
+    #[test]
+    fn test_product() {
+        let rows = parse_csv("ver,arch\n1.0,x86_64\n1.1,aarch64\n1.2,\n").unwrap();
+        let combinations = product(&rows);
+        assert_eq!(combinations.len(), 6);
+        assert_eq!(combinations[0], vars(&[("arch", "x86_64"), ("ver", "1.0")]));
+        assert_eq!(
+            combinations[5],
+            vars(&[("arch", "aarch64"), ("ver", "1.2")])
+        );
+    }
+}
@@ -0,0 +1,258 @@
+//! Bandwidth limiting shared by all transfers of a process.
+//!
+//! A [`Throttle`] caps the combined rate of every transfer that uses it. The
+//! cap may vary with the time of day through [`Window`]s from the
+//! configuration file; the active rate is looked up on every chunk, so a
+//! window that opens or closes takes effect on transfers already in flight.
+//!
+//! The other way round, a [`SpeedCheck`] gives up on a single transfer that
+//! stays slower than a [`MinSpeed`] for a whole window, so that a nearly dead
+//! connection fails and is retried instead of stalling for hours.
+
+use crate::units;
+use chrono::{Local, NaiveTime};
+use std::io;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How far a transfer may fall behind its allowance before the accounting
+/// restarts. Without this, an idle period would be "saved up" and spent as a
+/// burst at full speed.
+const MAX_DEBT: Duration = Duration::from_secs(1);
+
+/// A daily time range with its own rate limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+    pub from: NaiveTime,
+    pub to: NaiveTime,
+    /// Bytes per second, or `None` for unlimited.
+    pub rate: Option<u64>,
+}
+
+impl Window {
+    /// Whether `time` falls inside the window; windows may wrap past midnight.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.from <= self.to {
+            self.from <= time && time < self.to
+        } else {
+            time >= self.from || time < self.to
+        }
+    }
+}
+
+/// Bytes accounted against the current rate since `start`.
+#[derive(Debug)]
+struct Bucket {
+    rate: Option<u64>,
+    start: Instant,
+    bytes: u64,
+}
+
+/// A process-wide, schedule-aware rate limiter.
+#[derive(Debug)]
+pub struct Throttle {
+    default: Option<u64>,
+    windows: Vec<Window>,
+    bucket: Mutex<Bucket>,
+}
+
+impl Throttle {
+    /// Creates a throttle, or `None` if it would never limit anything.
+    ///
+    /// # Arguments
+    ///
+    /// * `default`: The rate outside every window (`--limit-rate`), `None` for unlimited.
+    /// * `windows`: Time-of-day overrides; the first window containing the current time wins.
+    pub fn new(default: Option<u64>, windows: Vec<Window>) -> Option<Throttle> {
+        if default.is_none() && windows.iter().all(|window| window.rate.is_none()) {
+            return None;
+        }
+        Some(Throttle {
+            default,
+            windows,
+            bucket: Mutex::new(Bucket {
+                rate: None,
+                start: Instant::now(),
+                bytes: 0,
+            }),
+        })
+    }
+
+    /// Returns the rate in effect at `time`.
+    pub fn rate_at(&self, time: NaiveTime) -> Option<u64> {
+        self.windows
+            .iter()
+            .find(|window| window.contains(time))
+            .map_or(self.default, |window| window.rate)
+    }
+
+    /// Accounts for `bytes` just transferred and sleeps as long as needed to
+    /// keep the combined rate under the current limit.
+    pub fn consume(&self, bytes: usize) {
+        if let Some(delay) = self.reserve(bytes, Local::now().time(), Instant::now()) {
+            thread::sleep(delay);
+        }
+    }
+
+    /// Records `bytes` and returns how long the caller must wait.
+    fn reserve(&self, bytes: usize, time: NaiveTime, now: Instant) -> Option<Duration> {
+        let rate = self.rate_at(time);
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        if bucket.rate != rate {
+            *bucket = Bucket {
+                rate,
+                start: now,
+                bytes: 0,
+            };
+        }
+        let rate = rate?;
+
+        bucket.bytes += bytes as u64;
+        let due = bucket.start + Duration::from_secs_f64(bucket.bytes as f64 / rate as f64);
+        if due + MAX_DEBT < now {
+            bucket.start = now;
+            bucket.bytes = bytes as u64;
+            return None;
+        }
+        due.checked_duration_since(now)
+    }
+}
+
+/// The lowest rate a transfer may keep up for a whole window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinSpeed {
+    /// Bytes per second.
+    pub rate: u64,
+    pub window: Duration,
+}
+
+/// Watches the rate of one transfer against a [`MinSpeed`].
+#[derive(Debug)]
+pub struct SpeedCheck {
+    min: MinSpeed,
+    start: Instant,
+    bytes: u64,
+}
+
+impl SpeedCheck {
+    /// Starts watching a transfer that begins now.
+    pub fn new(min: MinSpeed) -> SpeedCheck {
+        SpeedCheck {
+            min,
+            start: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Accounts for `bytes` just received.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error once a whole window has passed below the minimum rate.
+    pub fn record(&mut self, bytes: u64) -> io::Result<()> {
+        self.record_at(bytes, Instant::now())
+    }
+
+    fn record_at(&mut self, bytes: u64, now: Instant) -> io::Result<()> {
+        self.bytes += bytes;
+        let elapsed = now.duration_since(self.start);
+        if elapsed < self.min.window {
+            return Ok(());
+        }
+        let rate = self.bytes as f64 / elapsed.as_secs_f64();
+        if rate < self.min.rate as f64 {
+            return Err(io::Error::other(format!(
+                "Transfer too slow: {} for {}s, below {}",
+                units::format_rate(rate),
+                elapsed.as_secs(),
+                units::format_rate(self.min.rate as f64)
+            )));
+        }
+        self.start = now;
+        self.bytes = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn office_hours() -> Throttle {
+        Throttle::new(
+            None,
+            vec![Window {
+                from: time(9, 0),
+                to: time(18, 0),
+                rate: Some(1000),
+            }],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_windows_select_rate() {
+        let throttle = office_hours();
+        assert_eq!(throttle.rate_at(time(10, 30)), Some(1000));
+        assert_eq!(throttle.rate_at(time(18, 0)), None);
+        assert_eq!(throttle.rate_at(time(3, 0)), None);
+
+        let overnight = Window {
+            from: time(22, 0),
+            to: time(6, 0),
+            rate: None,
+        };
+        assert!(overnight.contains(time(23, 0)));
+        assert!(overnight.contains(time(1, 0)));
+        assert!(!overnight.contains(time(12, 0)));
+    }
+
+    #[test]
+    fn test_reserve_paces_transfers() {
+        let throttle = office_hours();
+        let start = Instant::now();
+
+        assert_eq!(
+            throttle.reserve(500, time(10, 0), start),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            throttle.reserve(500, time(10, 0), start),
+            Some(Duration::from_secs(1))
+        );
+        // Outside the window nothing is limited, and the accounting restarts.
+        assert_eq!(throttle.reserve(10_000, time(20, 0), start), None);
+        assert_eq!(
+            throttle.reserve(250, time(10, 0), start),
+            Some(Duration::from_millis(250))
+        );
+    }
+
+    #[test]
+    fn test_speed_check_fails_slow_windows() {
+        let mut check = SpeedCheck::new(MinSpeed {
+            rate: 1000,
+            window: Duration::from_secs(10),
+        });
+        let start = check.start;
+        let at = |seconds| start + Duration::from_secs(seconds);
+
+        // A slow start is fine while the first window lasts.
+        assert!(check.record_at(100, at(5)).is_ok());
+        assert!(check.record_at(20_000, at(10)).is_ok());
+        // Each window is judged on its own.
+        assert!(check.record_at(5000, at(15)).is_ok());
+        assert!(check.record_at(4000, at(20)).is_err());
+    }
+
+    #[test]
+    fn test_unlimited_throttle_is_not_created() {
+        assert!(Throttle::new(None, Vec::new()).is_none());
+        assert!(Throttle::new(Some(1), Vec::new()).is_some());
+    }
+}
@@ -0,0 +1,861 @@
+//! Torrents downloaded through their HTTP web seeds (BEP 19).
+//!
+//! With `--webseed`, a URL or local file ending in `.torrent` stands for the
+//! content of the torrent rather than for the file itself. A `magnet:` link
+//! always does; as its metadata is otherwise only to be had from peers, it
+//! must name the `.torrent` file with `xs=`, which is checked against the
+//! link's info hash, and may add web seeds with `ws=`.
+//!
+//! The content is fetched piece by piece with range requests to the web
+//! seeds of the torrent's `url-list`, moving on to the next seed when one
+//! fails or serves a piece that does not match its SHA-1 hash, so that every
+//! byte written is verified. Pieces already on disk that match are kept, so
+//! that an interrupted download continues where it stopped. Padding files
+//! (BEP 47) are not fetched or written.
+//!
+//! A single-file torrent is saved as its name, and a multi-file one in a
+//! directory of that name; `-O` names either instead. Torrents are
+//! downloaded one after the other before the other URLs.
+
+use crate::download::Options;
+use crate::input::Entry;
+use crate::logfile;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use reqwest::blocking::Client;
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// The prefix of magnet links.
+pub const MAGNET: &str = "magnet:";
+
+/// Characters escaped in the names of files appended to web seed URLs.
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// The deepest nesting of bencoded values accepted.
+const MAX_DEPTH: usize = 32;
+
+/// A decoded bencoded value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value<'a> {
+    Integer(i64),
+    Bytes(&'a [u8]),
+    List(Vec<Value<'a>>),
+    /// A dictionary, with the bytes it was decoded from.
+    Dictionary(BTreeMap<&'a [u8], Value<'a>>, &'a [u8]),
+}
+
+impl<'a> Value<'a> {
+    fn get(&self, key: &str) -> Option<&Value<'a>> {
+        match self {
+            Value::Dictionary(entries, _) => entries.get(key.as_bytes()),
+            _ => None,
+        }
+    }
+
+    fn integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn text(&self) -> Option<&'a str> {
+        match self {
+            Value::Bytes(bytes) => std::str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes the bencoded value at the start of `data`, nested in `depth`
+/// lists and dictionaries, returning the rest.
+fn decode(data: &[u8], depth: usize) -> Result<(Value<'_>, &[u8]), String> {
+    let invalid = || "invalid bencoding".to_string();
+    if depth > MAX_DEPTH {
+        return Err(invalid());
+    }
+    match data.first().ok_or_else(invalid)? {
+        b'i' => {
+            let end = data.iter().position(|&b| b == b'e').ok_or_else(invalid)?;
+            let number = std::str::from_utf8(&data[1..end]).map_err(|_| invalid())?;
+            Ok((
+                Value::Integer(number.parse().map_err(|_| invalid())?),
+                &data[end + 1..],
+            ))
+        }
+        b'l' => {
+            let mut rest = &data[1..];
+            let mut items = Vec::new();
+            while rest.first() != Some(&b'e') {
+                let (item, next) = decode(rest, depth + 1)?;
+                items.push(item);
+                rest = next;
+            }
+            Ok((Value::List(items), &rest[1..]))
+        }
+        b'd' => {
+            let mut rest = &data[1..];
+            let mut entries = BTreeMap::new();
+            while rest.first() != Some(&b'e') {
+                let (Value::Bytes(key), next) = decode(rest, depth + 1)? else {
+                    return Err(invalid());
+                };
+                let (value, next) = decode(next, depth + 1)?;
+                entries.insert(key, value);
+                rest = next;
+            }
+            let raw = &data[..data.len() - rest.len() + 1];
+            Ok((Value::Dictionary(entries, raw), &rest[1..]))
+        }
+        b'0'..=b'9' => {
+            let colon = data.iter().position(|&b| b == b':').ok_or_else(invalid)?;
+            let length: usize = std::str::from_utf8(&data[..colon])
+                .map_err(|_| invalid())?
+                .parse()
+                .map_err(|_| invalid())?;
+            let end = (colon + 1)
+                .checked_add(length)
+                .filter(|&end| end <= data.len())
+                .ok_or_else(invalid)?;
+            Ok((Value::Bytes(&data[colon + 1..end]), &data[end..]))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// A file of a torrent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TorrentFile {
+    /// Its path within the directory of a multi-file torrent, or empty for a
+    /// single-file one.
+    pub path: Vec<String>,
+    pub length: u64,
+    /// Whether it only pads the next file to the start of a piece.
+    pub padding: bool,
+}
+
+/// The metadata of a torrent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Torrent {
+    pub name: String,
+    /// The SHA-1 digest of the bencoded `info` dictionary.
+    pub info_hash: [u8; 20],
+    pub piece_length: u64,
+    /// The SHA-1 digest of each piece.
+    pub pieces: Vec<[u8; 20]>,
+    pub files: Vec<TorrentFile>,
+    /// Whether the files are kept in a directory named [`Torrent::name`].
+    pub multi_file: bool,
+    pub web_seeds: Vec<String>,
+}
+
+impl Torrent {
+    /// Parses the contents of a `.torrent` file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file is not a valid torrent, or names a file
+    /// with an unsafe path.
+    pub fn parse(data: &[u8]) -> Result<Torrent, String> {
+        let (metainfo, _) = decode(data, 0)?;
+        let invalid = |what: &str| format!("invalid torrent: {}", what);
+        let info = metainfo.get("info").ok_or_else(|| invalid("no info"))?;
+        let Value::Dictionary(_, raw) = info else {
+            return Err(invalid("no info"));
+        };
+        let name = info
+            .get("name.utf-8")
+            .or(info.get("name"))
+            .and_then(Value::text)
+            .filter(|name| is_safe(name))
+            .ok_or_else(|| invalid("unsafe or missing name"))?
+            .to_string();
+        let piece_length = info
+            .get("piece length")
+            .and_then(Value::integer)
+            .filter(|&length| length > 0)
+            .ok_or_else(|| invalid("no piece length"))? as u64;
+        let pieces = match info.get("pieces") {
+            Some(Value::Bytes(bytes)) if bytes.len() % 20 == 0 => bytes
+                .chunks(20)
+                .map(|chunk| chunk.try_into().unwrap_or_default())
+                .collect::<Vec<[u8; 20]>>(),
+            _ => return Err(invalid("no piece hashes")),
+        };
+
+        let length = |value: &Value| value.get("length").and_then(Value::integer);
+        let (files, multi_file) = match (info.get("files"), length(info)) {
+            (Some(Value::List(list)), _) => {
+                let mut files = Vec::new();
+                for file in list {
+                    let path = match file.get("path.utf-8").or(file.get("path")) {
+                        Some(Value::List(parts)) => parts
+                            .iter()
+                            .map(|part| part.text().filter(|part| is_safe(part)))
+                            .collect::<Option<Vec<_>>>()
+                            .filter(|parts| !parts.is_empty())
+                            .ok_or_else(|| invalid("unsafe file path"))?,
+                        _ => return Err(invalid("file without a path")),
+                    };
+                    files.push(TorrentFile {
+                        path: path.into_iter().map(str::to_string).collect(),
+                        length: length(file)
+                            .filter(|&length| length >= 0)
+                            .ok_or_else(|| invalid("file without a length"))?
+                            as u64,
+                        padding: file
+                            .get("attr")
+                            .and_then(Value::text)
+                            .is_some_and(|attr| attr.contains('p')),
+                    });
+                }
+                (files, true)
+            }
+            (_, Some(length)) if length >= 0 => (
+                vec![TorrentFile {
+                    path: Vec::new(),
+                    length: length as u64,
+                    padding: false,
+                }],
+                false,
+            ),
+            _ => return Err(invalid("no files")),
+        };
+        let total: u64 = files.iter().map(|file| file.length).sum();
+        if pieces.len() as u64 != total.div_ceil(piece_length) {
+            return Err(invalid("the piece hashes do not cover the files"));
+        }
+
+        let web_seeds = match metainfo.get("url-list") {
+            Some(Value::List(urls)) => urls.iter().filter_map(Value::text).collect(),
+            Some(url) => url.text().into_iter().collect(),
+            None => Vec::new(),
+        };
+        Ok(Torrent {
+            name,
+            info_hash: Sha1::digest(raw).into(),
+            piece_length,
+            pieces,
+            files,
+            multi_file,
+            web_seeds: web_seeds
+                .into_iter()
+                .filter(|url| !url.is_empty())
+                .map(str::to_string)
+                .collect(),
+        })
+    }
+
+    /// The length of the content.
+    pub fn length(&self) -> u64 {
+        self.files.iter().map(|file| file.length).sum()
+    }
+
+    /// Returns the URL at which the web seed `seed` serves `file`.
+    fn url(&self, seed: &str, file: &TorrentFile) -> String {
+        let encode = |part: &str| utf8_percent_encode(part, SEGMENT).to_string();
+        if !self.multi_file {
+            return match seed.ends_with('/') {
+                true => format!("{}{}", seed, encode(&self.name)),
+                false => seed.to_string(),
+            };
+        }
+        let mut url = format!("{}/{}", seed.trim_end_matches('/'), encode(&self.name));
+        for part in &file.path {
+            url.push('/');
+            url.push_str(&encode(part));
+        }
+        url
+    }
+
+    /// Returns where each file is saved when the content is saved at `root`.
+    fn paths(&self, root: &Path) -> Vec<PathBuf> {
+        self.files
+            .iter()
+            .map(|file| {
+                file.path
+                    .iter()
+                    .fold(root.to_path_buf(), |path, part| path.join(part))
+            })
+            .collect()
+    }
+}
+
+/// Whether `part` can be used as a file name as it is.
+fn is_safe(part: &str) -> bool {
+    !part.is_empty()
+        && part != "."
+        && part != ".."
+        && !part.contains(['/', '\\'])
+        && !part.contains(|c: char| c.is_control())
+}
+
+/// Whether `url` is a magnet link.
+pub fn is_magnet(url: &str) -> bool {
+    url.get(..MAGNET.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(MAGNET))
+}
+
+/// Whether `url` stands for the content of a torrent, which a `.torrent`
+/// file only does with `--webseed`.
+pub fn is_torrent(url: &str, webseed: bool) -> bool {
+    is_magnet(url)
+        || (webseed
+            && url
+                .split(['?', '#'])
+                .next()
+                .is_some_and(|path| path.to_ascii_lowercase().ends_with(".torrent")))
+}
+
+/// Whether `url` is a magnet link or, with `--webseed`, a file on disk,
+/// neither of which takes the default scheme.
+pub fn is_local(url: &str, webseed: bool) -> bool {
+    is_magnet(url) || (webseed && Path::new(url).is_file())
+}
+
+/// The parts of a magnet link that matter without peers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Magnet {
+    info_hash: [u8; 20],
+    /// Where the `.torrent` file is (`xs=`).
+    sources: Vec<String>,
+    /// Web seeds (`ws=`).
+    web_seeds: Vec<String>,
+}
+
+impl Magnet {
+    fn parse(link: &str) -> Result<Magnet, String> {
+        let query = link[MAGNET.len()..].trim_start_matches('?');
+        let (mut info_hash, mut sources, mut web_seeds) = (None, Vec::new(), Vec::new());
+        for pair in query.split('&') {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode_str(&value.replace('+', " "))
+                .decode_utf8_lossy()
+                .into_owned();
+            match key {
+                "xt" => {
+                    if let Some(hash) = value.strip_prefix("urn:btih:") {
+                        info_hash = Some(parse_info_hash(hash).ok_or_else(|| {
+                            format!("invalid info hash '{}' in magnet link", hash)
+                        })?);
+                    }
+                }
+                "xs" | "as" => sources.push(value),
+                "ws" => web_seeds.push(value),
+                _ => {}
+            }
+        }
+        Ok(Magnet {
+            info_hash: info_hash.ok_or("magnet link without a BitTorrent info hash")?,
+            sources,
+            web_seeds,
+        })
+    }
+}
+
+/// Parses a version 1 info hash, in hex or base32.
+fn parse_info_hash(text: &str) -> Option<[u8; 20]> {
+    let mut hash = [0; 20];
+    match text.len() {
+        40 => {
+            for (byte, pair) in hash.iter_mut().zip(text.as_bytes().chunks(2)) {
+                *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+            }
+        }
+        32 => {
+            let (mut bits, mut buffer, mut index) = (0, 0u64, 0);
+            for c in text.bytes() {
+                let value = match c.to_ascii_uppercase() {
+                    c @ b'A'..=b'Z' => c - b'A',
+                    c @ b'2'..=b'7' => c - b'2' + 26,
+                    _ => return None,
+                };
+                buffer = (buffer << 5) | u64::from(value);
+                bits += 5;
+                if bits >= 8 {
+                    bits -= 8;
+                    hash[index] = (buffer >> bits) as u8;
+                    index += 1;
+                }
+            }
+        }
+        _ => return None,
+    }
+    Some(hash)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Downloads the torrents among `entries` through their web seeds, each saved
+/// as `output` or its entry's output when given, and returns the other
+/// entries. With `dry_run`, the files are only listed.
+///
+/// # Errors
+///
+/// Returns an error if a torrent cannot be read, has no web seeds, or has a
+/// piece that no web seed serves intact.
+pub fn download(
+    client: &Client,
+    entries: Vec<Entry>,
+    webseed: bool,
+    output: Option<&Path>,
+    dry_run: bool,
+    options: &Options,
+) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+    let mut others = Vec::new();
+    for entry in entries {
+        if !is_torrent(&entry.url, webseed) {
+            others.push(entry);
+            continue;
+        }
+        let torrent = load(client, &entry.url, options)?;
+        let root = match (&entry.output, output) {
+            (Some(root), _) => root.clone(),
+            (None, Some(root)) => root.to_path_buf(),
+            (None, None) => PathBuf::from(&torrent.name),
+        };
+        if dry_run {
+            for (file, path) in torrent.files.iter().zip(torrent.paths(&root)) {
+                if !file.padding {
+                    logfile::info(&format!("{} -> {}", entry.url, path.display()));
+                }
+            }
+            continue;
+        }
+        fetch(client, &torrent, &root, options)?;
+    }
+    Ok(others)
+}
+
+/// Reads the torrent that `url` names: a `.torrent` file on disk or at a
+/// URL, or a magnet link.
+fn load(
+    client: &Client,
+    url: &str,
+    options: &Options,
+) -> Result<Torrent, Box<dyn std::error::Error>> {
+    if !is_magnet(url) {
+        let data = match Path::new(url).is_file() {
+            true => fs::read(url)?,
+            false => get(client, url, options)?,
+        };
+        return Ok(Torrent::parse(&data).map_err(|err| format!("{}: {}", url, err))?);
+    }
+    let magnet = Magnet::parse(url)?;
+    let Some(source) = magnet.sources.first() else {
+        return Err(format!(
+            "magnet link {} names no .torrent file with xs=; its metadata is only available from peers",
+            hex(&magnet.info_hash)
+        )
+        .into());
+    };
+    let mut torrent = Torrent::parse(&get(client, source, options)?)
+        .map_err(|err| format!("{}: {}", source, err))?;
+    if torrent.info_hash != magnet.info_hash {
+        return Err(format!(
+            "{} is not the torrent of magnet link {} (its info hash is {})",
+            source,
+            hex(&magnet.info_hash),
+            hex(&torrent.info_hash)
+        )
+        .into());
+    }
+    torrent.web_seeds.extend(magnet.web_seeds);
+    Ok(torrent)
+}
+
+fn get(
+    client: &Client,
+    url: &str,
+    options: &Options,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut response = options
+        .send(client, url, |client| client.get(url))?
+        .error_for_status()
+        .map_err(|err| format!("cannot fetch {}: {}", url, err))?;
+    let mut data = Vec::new();
+    response.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// The part of a file that a piece covers.
+struct Span {
+    file: usize,
+    /// Where it starts in the file.
+    offset: u64,
+    length: u64,
+}
+
+/// Returns the parts of the files that piece `index` covers.
+fn spans(torrent: &Torrent, index: usize) -> Vec<Span> {
+    let start = index as u64 * torrent.piece_length;
+    let end = (start + torrent.piece_length).min(torrent.length());
+    let mut spans = Vec::new();
+    let mut position = 0;
+    for (file, entry) in torrent.files.iter().enumerate() {
+        let (first, last) = (position.max(start), (position + entry.length).min(end));
+        if first < last {
+            spans.push(Span {
+                file,
+                offset: first - position,
+                length: last - first,
+            });
+        }
+        position += entry.length;
+    }
+    spans
+}
+
+/// Downloads the content of `torrent` to `root`, keeping the pieces already
+/// there that match their hash.
+fn fetch(
+    client: &Client,
+    torrent: &Torrent,
+    root: &Path,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if torrent.web_seeds.is_empty() {
+        return Err(format!("the torrent {} lists no web seeds", torrent.name).into());
+    }
+    let paths = torrent.paths(root);
+    let mut files = Vec::new();
+    for (file, path) in torrent.files.iter().zip(&paths) {
+        if file.padding {
+            files.push(None);
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let handle = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        handle.set_len(file.length)?;
+        files.push(Some(handle));
+    }
+    logfile::info(&format!(
+        "Downloading {} ({} pieces) from {} web seed(s)",
+        torrent.name,
+        torrent.pieces.len(),
+        torrent.web_seeds.len()
+    ));
+
+    let (mut kept, mut preferred) = (0, 0);
+    for (index, hash) in torrent.pieces.iter().enumerate() {
+        let spans = spans(torrent, index);
+        if Sha1::digest(read_piece(&mut files, &spans)?).as_slice() == hash {
+            kept += 1;
+            continue;
+        }
+        let mut fetched = None;
+        for attempt in 0..torrent.web_seeds.len() {
+            let seed = &torrent.web_seeds[(preferred + attempt) % torrent.web_seeds.len()];
+            match fetch_piece(client, torrent, seed, &spans, options) {
+                Ok(data) if Sha1::digest(&data).as_slice() == hash => {
+                    preferred = (preferred + attempt) % torrent.web_seeds.len();
+                    fetched = Some(data);
+                    break;
+                }
+                Ok(_) => logfile::warning(&format!(
+                    "Piece {} from {} does not match its hash",
+                    index, seed
+                )),
+                Err(err) => logfile::warning(&format!("Piece {} from {}: {}", index, seed, err)),
+            }
+        }
+        let data = fetched.ok_or_else(|| {
+            format!(
+                "no web seed serves piece {} of {} intact",
+                index, torrent.name
+            )
+        })?;
+        let mut data = &data[..];
+        for span in &spans {
+            let (part, rest) = data.split_at(span.length as usize);
+            if let Some(file) = &mut files[span.file] {
+                file.seek(SeekFrom::Start(span.offset))?;
+                file.write_all(part)?;
+            }
+            data = rest;
+        }
+    }
+    for file in files.iter().flatten() {
+        file.sync_all()?;
+    }
+    if kept > 0 {
+        logfile::info(&format!("Kept {} verified pieces already on disk", kept));
+    }
+    for (file, path) in torrent.files.iter().zip(&paths) {
+        if !file.padding {
+            logfile::success(&format!("Downloaded: {}", path.display()));
+        }
+    }
+    Ok(())
+}
+
+/// Reads what the files hold of a piece.
+fn read_piece(files: &mut [Option<File>], spans: &[Span]) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    for span in spans {
+        match &mut files[span.file] {
+            Some(file) => {
+                file.seek(SeekFrom::Start(span.offset))?;
+                file.take(span.length).read_to_end(&mut data)?;
+            }
+            None => data.resize(data.len() + span.length as usize, 0),
+        }
+    }
+    Ok(data)
+}
+
+/// Fetches a piece from the web seed `seed`.
+fn fetch_piece(
+    client: &Client,
+    torrent: &Torrent,
+    seed: &str,
+    spans: &[Span],
+    options: &Options,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut data = Vec::new();
+    for span in spans {
+        let file = &torrent.files[span.file];
+        if file.padding {
+            data.resize(data.len() + span.length as usize, 0);
+            continue;
+        }
+        let url = torrent.url(seed, file);
+        let last = span.offset + span.length - 1;
+        let response = options.send(client, &url, |client| {
+            client
+                .get(&url)
+                .header(RANGE, format!("bytes={}-{}", span.offset, last))
+        })?;
+        // A server that ignores the range sends the file from its start.
+        let skip = match response.status() {
+            StatusCode::PARTIAL_CONTENT => 0,
+            StatusCode::OK => span.offset,
+            status => return Err(format!("HTTP {}", status).into()),
+        };
+        let mut body = response;
+        io::copy(&mut (&mut body).take(skip), &mut io::sink())?;
+        let before = data.len();
+        (&mut body).take(span.length).read_to_end(&mut data)?;
+        let read = data.len() - before;
+        options.consume(&url, read);
+        if read as u64 != span.length {
+            return Err(format!("{} ended after {} of {} bytes", url, read, span.length).into());
+        }
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+
+    /// Bencodes a torrent of `files` (name and content) cut into pieces of
+    /// `piece_length`, a single-file one when there is one file.
+    fn torrent(
+        name: &str,
+        files: &[(&str, &[u8])],
+        piece_length: usize,
+        seeds: &[String],
+    ) -> Vec<u8> {
+        let bytes = |text: &[u8]| [format!("{}:", text.len()).as_bytes(), text].concat();
+        let content: Vec<u8> = files.iter().flat_map(|(_, data)| data.to_vec()).collect();
+        let pieces: Vec<u8> = content
+            .chunks(piece_length)
+            .flat_map(|piece| Sha1::digest(piece).to_vec())
+            .collect();
+        let mut info = b"d".to_vec();
+        if let [(_, data)] = files {
+            info.extend(format!("6:lengthi{}e", data.len()).as_bytes());
+        } else {
+            info.extend(b"5:filesl");
+            for (path, data) in files {
+                info.extend(format!("d6:lengthi{}e4:pathl", data.len()).as_bytes());
+                for part in path.split('/') {
+                    info.extend(bytes(part.as_bytes()));
+                }
+                info.extend(b"ee");
+            }
+            info.extend(b"e");
+        }
+        info.extend(b"4:name");
+        info.extend(bytes(name.as_bytes()));
+        info.extend(format!("12:piece lengthi{}e6:pieces", piece_length).as_bytes());
+        info.extend(bytes(&pieces));
+        info.extend(b"e");
+        let mut metainfo = b"d4:info".to_vec();
+        metainfo.extend(info);
+        metainfo.extend(b"8:url-listl");
+        for seed in seeds {
+            metainfo.extend(bytes(seed.as_bytes()));
+        }
+        metainfo.extend(b"ee");
+        metainfo
+    }
+
+    #[test]
+    fn test_parse_torrent() {
+        let data = torrent(
+            "set",
+            &[("a.txt", b"hello"), ("sub dir/b.txt", b"world!")],
+            4,
+            &["https://seed.example/files/".to_string()],
+        );
+        let parsed = Torrent::parse(&data).unwrap();
+        assert_eq!(parsed.name, "set");
+        assert_eq!(parsed.pieces.len(), 3);
+        assert_eq!(parsed.length(), 11);
+        assert_eq!(
+            parsed.url(&parsed.web_seeds[0], &parsed.files[1]),
+            "https://seed.example/files/set/sub%20dir/b.txt"
+        );
+        let position = |key: &[u8]| data.windows(key.len()).position(|window| window == key);
+        let start = position(b"4:info").unwrap() + 6;
+        let end = position(b"8:url-list").unwrap();
+        assert_eq!(
+            parsed.info_hash,
+            <[u8; 20]>::from(Sha1::digest(&data[start..end]))
+        );
+
+        let single = Torrent::parse(&torrent("a.iso", &[("a.iso", b"iso")], 4, &[])).unwrap();
+        assert!(!single.multi_file);
+        assert_eq!(
+            single.url("https://seed.example/", &single.files[0]),
+            "https://seed.example/a.iso"
+        );
+        assert_eq!(
+            single.url("https://seed.example/x.iso", &single.files[0]),
+            "https://seed.example/x.iso"
+        );
+
+        assert!(Torrent::parse(&torrent("..", &[("a", b"a")], 4, &[])).is_err());
+        assert!(Torrent::parse(&torrent("set", &[("a", b"a"), ("../b", b"b")], 4, &[])).is_err());
+    }
+
+    #[test]
+    fn test_parse_magnet() {
+        let magnet = Magnet::parse(
+            "magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a&dn=set\
+             &xs=https%3A%2F%2Fexample.com%2Fset.torrent&ws=https://seed.example/",
+        )
+        .unwrap();
+        assert_eq!(
+            hex(&magnet.info_hash),
+            "c12fe1c06bba254a9dc9f519b335aa7c1367a88a"
+        );
+        assert_eq!(magnet.sources, vec!["https://example.com/set.torrent"]);
+        assert_eq!(magnet.web_seeds, vec!["https://seed.example/"]);
+        assert_eq!(
+            parse_info_hash("YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK"),
+            parse_info_hash("c12fe1c06bba254a9dc9f519b335aa7c1367a88a")
+        );
+        assert!(Magnet::parse("magnet:?dn=set").is_err());
+        assert!(is_torrent("MAGNET:?xt=urn:btih:x", false));
+        assert!(is_torrent("https://example.com/set.torrent?x=1", true));
+        assert!(!is_torrent("https://example.com/set.torrent", false));
+    }
+
+    #[test]
+    fn test_download_verifies_pieces() {
+        let seeds = [
+            format!("{}/torrent-bad/", server_url()),
+            format!("{}/torrent-good", server_url()),
+        ];
+        let data = torrent(
+            "set",
+            &[("a.txt", b"hello"), ("b.txt", b"world!")],
+            4,
+            &seeds,
+        );
+        let metainfo = mock("GET", "/torrents/set.torrent")
+            .with_body(data)
+            .create();
+        // The first seed serves a corrupt second file, so the pieces that
+        // cover it come from the second seed.
+        let bad_a = mock("GET", "/torrent-bad/set/a.txt")
+            .with_body("hello")
+            .expect(2)
+            .create();
+        let bad_b = mock("GET", "/torrent-bad/set/b.txt")
+            .with_body("WORLD!")
+            .expect_at_least(1)
+            .create();
+        let good_a = mock("GET", "/torrent-good/set/a.txt")
+            .with_body("hello")
+            .create();
+        let good_b = mock("GET", "/torrent-good/set/b.txt")
+            .with_body("world!")
+            .expect_at_least(1)
+            .create();
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("out");
+        let entries = vec![
+            Entry {
+                url: format!("{}/torrents/set.torrent", server_url()),
+                priority: 0,
+                output: Some(root.clone()),
+            },
+            Entry {
+                url: "https://example.com/other.bin".to_string(),
+                priority: 0,
+                output: None,
+            },
+        ];
+        let others = download(
+            &Client::new(),
+            entries,
+            true,
+            None,
+            false,
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(others.len(), 1);
+        assert_eq!(fs::read(root.join("a.txt")).unwrap(), b"hello");
+        assert_eq!(fs::read(root.join("b.txt")).unwrap(), b"world!");
+        metainfo.assert();
+        bad_a.assert();
+        bad_b.assert();
+        good_a.assert();
+        good_b.assert();
+
+        // Everything on disk is verified and kept.
+        let unused = mock(
+            "GET",
+            mockito::Matcher::Regex("^/torrent-unused/".to_string()),
+        )
+        .expect(0)
+        .create();
+        let seeds = [format!("{}/torrent-unused/", server_url())];
+        let files: [(&str, &[u8]); 2] = [("a.txt", b"hello"), ("b.txt", b"world!")];
+        let torrent = Torrent::parse(&torrent("set", &files, 4, &seeds)).unwrap();
+        fetch(&Client::new(), &torrent, &root, &Options::default()).unwrap();
+        unused.assert();
+    }
+}
@@ -0,0 +1,218 @@
+//! Sending requests without the network.
+//!
+//! Every request made for a download goes through [`Options::send`]. When
+//! [`Options::transport`] is set, the request, with the headers of its host
+//! and those from commands, is handed to that [`Transport`] instead of being
+//! sent by the client, so that retries, resumption, and mirrors can be
+//! exercised without sockets. Credentials are not applied and authentication
+//! challenges are not answered through a transport.
+//!
+//! [`Script`] answers requests with [`Step`]s in order: canned responses,
+//! each after an optional delay and possibly cut short as a dropped
+//! connection would leave them, and failures to connect. It records the
+//! requests it receives, so that tests, ours and those of programs using the
+//! library, can check the ranges and validators asked for.
+//!
+//! [`Options::send`]: crate::download::Options::send
+//! [`Options::transport`]: crate::download::Options::transport
+
+use reqwest::blocking::{Request, Response};
+use std::fmt;
+
+/// Sends requests on behalf of the client of a run.
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Sends `request`, returning the response.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no response is received.
+    fn execute(&self, request: Request) -> Result<Response, Box<dyn std::error::Error>>;
+}
+
+pub use script::{Reply, Script, Step};
+
+mod script {
+    use super::Transport;
+    use reqwest::blocking::{Request, Response};
+    use reqwest::ResponseBuilderExt;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    /// What a `Script` does with a request.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Step {
+        /// Answers with a response.
+        Reply(Reply),
+        /// Fails the request with a message, as an unreachable server would.
+        Fail(String),
+    }
+
+    /// A canned response.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Reply {
+        pub status: u16,
+        pub headers: Vec<(String, String)>,
+        pub body: Vec<u8>,
+        /// Time to wait before answering.
+        pub delay: Duration,
+        /// Whether the connection drops after `body`, failing the read of
+        /// whatever was to follow it.
+        pub dropped: bool,
+    }
+
+    impl Reply {
+        /// Answers with `status`, `headers`, and `body` at once.
+        pub fn new(status: u16, headers: &[(&str, &str)], body: impl Into<Vec<u8>>) -> Reply {
+            Reply {
+                status,
+                headers: headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+                body: body.into(),
+                delay: Duration::ZERO,
+                dropped: false,
+            }
+        }
+    }
+
+    /// A request received by a [`Script`].
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Sent {
+        pub method: String,
+        pub url: String,
+        pub headers: Vec<(String, String)>,
+    }
+
+    impl Sent {
+        /// Returns the value of the header `name`, if it was sent.
+        pub fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(sent, _)| sent.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+    }
+
+    /// A transport that takes its [`Step`]s in order.
+    #[derive(Debug, Default)]
+    pub struct Script {
+        steps: Mutex<VecDeque<Step>>,
+        sent: Mutex<Vec<Sent>>,
+    }
+
+    impl Script {
+        pub fn new(steps: impl IntoIterator<Item = Step>) -> Script {
+            Script {
+                steps: Mutex::new(steps.into_iter().collect()),
+                sent: Mutex::default(),
+            }
+        }
+
+        /// Returns the requests received so far.
+        pub fn sent(&self) -> Vec<Sent> {
+            self.sent.lock().unwrap().clone()
+        }
+    }
+
+    impl Transport for Script {
+        fn execute(&self, request: Request) -> Result<Response, Box<dyn std::error::Error>> {
+            self.sent.lock().unwrap().push(Sent {
+                method: request.method().to_string(),
+                url: request.url().to_string(),
+                headers: request
+                    .headers()
+                    .iter()
+                    .map(|(name, value)| {
+                        (
+                            name.to_string(),
+                            String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                        )
+                    })
+                    .collect(),
+            });
+            let step = self.steps.lock().unwrap().pop_front();
+            let Some(Step::Reply(reply)) = step else {
+                return Err(match step {
+                    Some(Step::Fail(message)) => message,
+                    _ => format!("no response scripted for {}", request.url()),
+                }
+                .into());
+            };
+            thread::sleep(reply.delay);
+            let mut builder = hyper::http::Response::builder()
+                .status(reply.status)
+                .url(request.url().clone());
+            for (name, value) in &reply.headers {
+                builder = builder.header(name, value);
+            }
+            if !reply.dropped {
+                return Ok(builder.body(reply.body)?.into());
+            }
+            let (mut sender, streamed) = hyper::Body::channel();
+            if !reply.body.is_empty() {
+                sender
+                    .try_send_data(reply.body.into())
+                    .map_err(|_| "cannot queue the body")?;
+            }
+            sender.abort();
+            Ok(builder.body(streamed)?.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::{self, Control, Options};
+    use reqwest::blocking::Client;
+    use std::fs;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_script_drives_retries_and_resumption() {
+        let script = Arc::new(Script::new([
+            Step::Fail("connection refused".to_string()),
+            Step::Reply(Reply {
+                dropped: true,
+                ..Reply::new(200, &[("content-length", "10")], "01234")
+            }),
+            Step::Reply(Reply {
+                delay: Duration::from_millis(20),
+                ..Reply::new(206, &[("content-range", "bytes 5-9/10")], "56789")
+            }),
+        ]));
+        let options = Options {
+            transport: Some(script.clone()),
+            ..Options::default()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.bin");
+        let url = "https://example.com/file.bin";
+        let client = Client::new();
+
+        let fetch =
+            |resume| download::fetch(&client, url, &path, resume, &Control::default(), &options);
+        assert!(fetch(false)
+            .unwrap_err()
+            .to_string()
+            .contains("connection refused"));
+        assert!(fetch(false).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"01234");
+        fetch(true).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"0123456789");
+
+        let sent = script.sent();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0].url, url);
+        assert_eq!(sent[1].header("range"), None);
+        assert_eq!(sent[2].header("range"), Some("bytes=5-"));
+        assert!(fetch(true)
+            .unwrap_err()
+            .to_string()
+            .contains("no response scripted"));
+    }
+}
@@ -0,0 +1,316 @@
+//! Interactive terminal dashboard for concurrent downloads (`--tui`).
+//!
+//! The dashboard polls the shared [`Queue`] a few times per second and renders
+//! one table row per transfer. Keybindings act on the selected row:
+//!
+//! * `↑`/`↓` (or `k`/`j`): select a transfer
+//! * `p`: pause or resume
+//! * `c`: cancel
+//! * `+`/`-`: raise or lower the priority, changing which transfer starts next
+//! * `q`: pause the transfers that have not ended and leave the dashboard
+//!
+//! The dashboard is left by itself once no transfer is queued or running.
+//! Transfers paused by then keep their partial files, which `-c` continues.
+
+use crate::queue::{Job, Queue, Status};
+use crate::redact;
+use crate::units::{format_bytes, format_rate};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::Frame;
+use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// How long to wait for a key press before redrawing.
+const TICK: Duration = Duration::from_millis(250);
+
+/// Width of the textual progress bar, in cells.
+const BAR_WIDTH: usize = 12;
+
+/// Last observed byte count of a transfer, used to derive its speed.
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    downloaded: u64,
+    at: Instant,
+    speed: f64,
+}
+
+/// Smoothed speeds of the running transfers of a queue.
+#[derive(Debug, Default)]
+pub struct Speeds(HashMap<u64, Sample>);
+
+impl Speeds {
+    /// Refreshes the smoothed speed of every running transfer.
+    pub fn update(&mut self, jobs: &[Job]) {
+        let now = Instant::now();
+        for job in jobs {
+            if job.status != Status::Running {
+                self.0.remove(&job.id);
+                continue;
+            }
+            let sample = self.0.entry(job.id).or_insert(Sample {
+                downloaded: job.downloaded,
+                at: now,
+                speed: 0.0,
+            });
+            let elapsed = now.duration_since(sample.at).as_secs_f64();
+            if elapsed >= 0.5 {
+                let instant = job.downloaded.saturating_sub(sample.downloaded) as f64 / elapsed;
+                sample.speed = if sample.speed == 0.0 {
+                    instant
+                } else {
+                    0.5 * sample.speed + 0.5 * instant
+                };
+                sample.downloaded = job.downloaded;
+                sample.at = now;
+            }
+        }
+    }
+
+    /// Returns the speed of the transfer `id` in bytes per second, if it is running.
+    pub fn get(&self, id: u64) -> Option<f64> {
+        self.0.get(&id).map(|sample| sample.speed)
+    }
+}
+
+/// Mutable state of the dashboard between frames.
+#[derive(Debug, Default)]
+struct Dashboard {
+    table: TableState,
+    speeds: Speeds,
+    message: Option<String>,
+}
+
+/// Runs the dashboard until the queue is idle, or the user presses `q`,
+/// which pauses every job not ended yet; running ones stop at their next chunk.
+///
+/// # Errors
+///
+/// Returns an error if the terminal cannot be drawn to or read from.
+pub fn run(queue: &Queue) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let mut dashboard = Dashboard::default();
+    dashboard.table.select(Some(0));
+
+    let result = loop {
+        let jobs = queue.jobs();
+        dashboard.speeds.update(&jobs);
+        if let Err(err) = terminal.draw(|frame| dashboard.draw(frame, &jobs)) {
+            break Err(err);
+        }
+        if queue.is_idle() {
+            break Ok(());
+        }
+
+        match event::poll(TICK).and_then(|ready| {
+            if ready {
+                event::read().map(Some)
+            } else {
+                Ok(None)
+            }
+        }) {
+            Ok(Some(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                if key.code == KeyCode::Char('q') {
+                    queue.close();
+                    for job in &jobs {
+                        if matches!(job.status, Status::Queued | Status::Running) {
+                            let _ = queue.pause(job.id);
+                        }
+                    }
+                    break Ok(());
+                }
+                dashboard.handle_key(key.code, queue, &jobs);
+            }
+            Ok(_) => {}
+            Err(err) => break Err(err),
+        }
+    };
+
+    ratatui::restore();
+    result
+}
+
+impl Dashboard {
+    fn selected<'a>(&self, jobs: &'a [Job]) -> Option<&'a Job> {
+        self.table.selected().and_then(|index| jobs.get(index))
+    }
+
+    fn handle_key(&mut self, code: KeyCode, queue: &Queue, jobs: &[Job]) {
+        let result = match code {
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.table.select_previous();
+                return;
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                if self
+                    .table
+                    .selected()
+                    .is_none_or(|index| index + 1 < jobs.len())
+                {
+                    self.table.select_next();
+                }
+                return;
+            }
+            KeyCode::Char('p') => self.selected(jobs).map(|job| match job.status {
+                Status::Paused => queue.resume(job.id),
+                _ => queue.pause(job.id),
+            }),
+            KeyCode::Char('c') => self.selected(jobs).map(|job| queue.cancel(job.id)),
+            KeyCode::Char('+') => self
+                .selected(jobs)
+                .map(|job| queue.set_priority(job.id, job.priority + 1)),
+            KeyCode::Char('-') => self
+                .selected(jobs)
+                .map(|job| queue.set_priority(job.id, job.priority - 1)),
+            _ => return,
+        };
+        self.message = match result {
+            Some(Err(err)) => Some(err.to_string()),
+            _ => None,
+        };
+    }
+
+    fn draw(&mut self, frame: &mut Frame, jobs: &[Job]) {
+        let [table_area, footer_area] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+
+        let header = Row::new([
+            "#", "File", "Priority", "Status", "Progress", "Speed", "Retries",
+        ])
+        .style(Style::default().add_modifier(Modifier::BOLD));
+        let rows = jobs.iter().map(|job| {
+            let speed = self.speeds.get(job.id).map(format_rate).unwrap_or_default();
+            let name = job
+                .output
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| redact::url(&job.url).into_owned());
+            Row::new([
+                job.id.to_string(),
+                name,
+                job.priority.to_string(),
+                job.status.to_string(),
+                progress(job),
+                speed,
+                job.attempts.to_string(),
+            ])
+            .style(Style::default().fg(status_color(job.status)))
+        });
+        let widths = [
+            Constraint::Length(4),
+            Constraint::Fill(1),
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Length(36),
+            Constraint::Length(12),
+            Constraint::Length(7),
+        ];
+        let done = jobs.iter().filter(|job| job.is_finished()).count();
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::bordered().title(format!(
+                " rustwget — {}/{} finished ",
+                done,
+                jobs.len()
+            )))
+            .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, table_area, &mut self.table);
+
+        let footer = match &self.message {
+            Some(message) => {
+                Paragraph::new(message.as_str()).style(Style::default().fg(Color::Red))
+            }
+            None => {
+                Paragraph::new("↑/↓ select  p pause/resume  c cancel  +/- reprioritize  q quit")
+            }
+        };
+        frame.render_widget(footer, footer_area);
+    }
+}
+
+/// Renders a job's progress as a bar with percentage and byte counts.
+fn progress(job: &Job) -> String {
+    match job.total {
+        Some(total) if total > 0 => {
+            let ratio = (job.downloaded as f64 / total as f64).min(1.0);
+            let filled = (ratio * BAR_WIDTH as f64).round() as usize;
+            format!(
+                "{}{} {:>5.1}% {}",
+                "█".repeat(filled),
+                "░".repeat(BAR_WIDTH - filled),
+                ratio * 100.0,
+                format_bytes(total)
+            )
+        }
+        _ if job.downloaded > 0 => format_bytes(job.downloaded),
+        _ => String::new(),
+    }
+}
+
+fn status_color(status: Status) -> Color {
+    match status {
+        Status::Running => Color::Cyan,
+        Status::Completed => Color::Green,
+        Status::Failed => Color::Red,
+        Status::Paused => Color::Yellow,
+        Status::Skipped => Color::DarkGray,
+        Status::Queued | Status::Cancelled => Color::Reset,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn job(downloaded: u64, total: Option<u64>) -> Job {
+        Job {
+            id: 1,
+            url: "https://example.com/file".to_string(),
+            output: PathBuf::from("file"),
+            priority: 0,
+            status: Status::Running,
+            downloaded,
+            total,
+            attempts: 0,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_progress_cell() {
+        assert_eq!(progress(&job(0, None)), "");
+        assert_eq!(progress(&job(2048, None)), "2.0 KiB");
+        assert_eq!(
+            progress(&job(512, Some(1024))),
+            "██████░░░░░░  50.0% 1.0 KiB"
+        );
+    }
+
+    #[test]
+    fn test_keys_act_on_selected_job() {
+        let queue = Queue::open(None).unwrap();
+        let first = queue.add("http://example.com/1".to_string(), PathBuf::from("1"), 0);
+        let second = queue.add("http://example.com/2".to_string(), PathBuf::from("2"), 0);
+        let mut dashboard = Dashboard::default();
+        dashboard.table.select(Some(0));
+
+        dashboard.handle_key(KeyCode::Char('p'), &queue, &queue.jobs());
+        assert_eq!(queue.job(first.id).unwrap().status, Status::Paused);
+
+        dashboard.handle_key(KeyCode::Down, &queue, &queue.jobs());
+        dashboard.handle_key(KeyCode::Char('+'), &queue, &queue.jobs());
+        assert_eq!(queue.job(second.id).unwrap().priority, 1);
+        assert_eq!(dashboard.table.selected(), Some(1));
+
+        dashboard.handle_key(KeyCode::Char('c'), &queue, &queue.jobs());
+        assert_eq!(queue.job(second.id).unwrap().status, Status::Cancelled);
+        assert_eq!(dashboard.message, None);
+
+        dashboard.handle_key(KeyCode::Char('c'), &queue, &queue.jobs());
+        assert_eq!(dashboard.message.as_deref(), Some("job 2 is cancelled"));
+    }
+}
@@ -0,0 +1,187 @@
+//! Parsing and human-readable formatting of sizes, byte ranges, and transfer rates.
+//!
+//! Sizes and rates are shown with binary units by default; `--si` shows them
+//! with decimal units and `--bytes` as exact byte counts, everywhere they are
+//! printed: progress, summaries, and messages.
+
+use std::sync::OnceLock;
+
+/// Parses a size such as `512`, `100k`, `1.5M`, or `2GiB` into bytes.
+///
+/// Suffixes are binary multiples (`k` = 1024) and case-insensitive; an
+/// optional trailing `B`/`iB` is accepted.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let trimmed = value.trim();
+    let lower = trimmed.to_ascii_lowercase();
+    let number = lower
+        .strip_suffix("ib")
+        .or_else(|| lower.strip_suffix('b'))
+        .unwrap_or(&lower);
+    let (digits, multiplier) = match number.chars().last() {
+        Some('k') => (&number[..number.len() - 1], 1u64 << 10),
+        Some('m') => (&number[..number.len() - 1], 1 << 20),
+        Some('g') => (&number[..number.len() - 1], 1 << 30),
+        Some('t') => (&number[..number.len() - 1], 1 << 40),
+        _ => (number, 1),
+    };
+    digits
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite() && *number >= 0.0)
+        .map(|number| (number * multiplier as f64).round() as u64)
+        .ok_or_else(|| format!("invalid size '{}'", trimmed))
+}
+
+/// A part of a resource, as selected with `--range`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    /// The first byte.
+    pub start: u64,
+    /// The last byte, inclusive as in HTTP; `None` reads to the end.
+    pub end: Option<u64>,
+}
+
+impl ByteRange {
+    /// Parses `START-END` or `START-`, where both sides are sizes such as `1M`.
+    ///
+    /// As in an HTTP `Range` header the end is inclusive: `0-1023` is the first KiB.
+    pub fn parse(value: &str) -> Result<ByteRange, String> {
+        let invalid = || format!("invalid range '{}': expected START-END or START-", value);
+        let (start, end) = value.trim().split_once('-').ok_or_else(invalid)?;
+        let start = parse_size(start).map_err(|_| invalid())?;
+        let end = match end.trim() {
+            "" => None,
+            end => Some(parse_size(end).map_err(|_| invalid())?),
+        };
+        if end.is_some_and(|end| end < start) {
+            return Err(format!(
+                "invalid range '{}': the end is before the start",
+                value
+            ));
+        }
+        Ok(ByteRange { start, end })
+    }
+
+    /// Returns the number of bytes in the range, if it has an end.
+    pub fn length(&self) -> Option<u64> {
+        self.end.map(|end| end - self.start + 1)
+    }
+}
+
+/// Parses a transfer rate such as `100k`, `2MB/s`, or `unlimited`.
+///
+/// Returns `None` for an unlimited rate (`unlimited`, `none`, or `0`).
+pub fn parse_rate(value: &str) -> Result<Option<u64>, String> {
+    let trimmed = value.trim();
+    if ["unlimited", "none"].contains(&trimmed.to_ascii_lowercase().as_str()) {
+        return Ok(None);
+    }
+    let size = trimmed.strip_suffix("/s").unwrap_or(trimmed);
+    match parse_size(size) {
+        Ok(0) => Ok(None),
+        Ok(rate) => Ok(Some(rate)),
+        Err(_) => Err(format!("invalid rate '{}'", trimmed)),
+    }
+}
+
+/// How sizes and rates are shown, chosen with `--si` or `--bytes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// Multiples of 1024, e.g. `1.5 MiB` (the default).
+    Binary,
+    /// Multiples of 1000, e.g. `1.6 MB`.
+    Si,
+    /// Exact byte counts, e.g. `1572864 B`, for scripts that parse them.
+    Bytes,
+}
+
+/// How sizes and rates are shown, once decided.
+static STYLE: OnceLock<Style> = OnceLock::new();
+
+/// Decides for the rest of the run how sizes and rates are shown.
+pub fn init(style: Style) {
+    let _ = STYLE.set(style);
+}
+
+/// Formats a byte count in the style of the run, e.g. `1.5 MiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    format_bytes_as(bytes, STYLE.get().copied().unwrap_or(Style::Binary))
+}
+
+/// Formats a byte count in `style`.
+pub fn format_bytes_as(bytes: u64, style: Style) -> String {
+    let (base, units) = match style {
+        Style::Binary => (1024.0, ["KiB", "MiB", "GiB", "TiB", "PiB"]),
+        Style::Si => (1000.0, ["kB", "MB", "GB", "TB", "PB"]),
+        Style::Bytes => return format!("{} B", bytes),
+    };
+    if (bytes as f64) < base {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / base;
+    let mut unit = 0;
+    while value >= base && unit < units.len() - 1 {
+        value /= base;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, units[unit])
+}
+
+/// Formats a transfer rate in bytes per second in the style of the run,
+/// e.g. `2.0 MiB/s`.
+pub fn format_rate(bytes_per_second: f64) -> String {
+    format!("{}/s", format_bytes(bytes_per_second.max(0.0) as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("100k"), Ok(102_400));
+        assert_eq!(parse_size("1.5M"), Ok(1_572_864));
+        assert_eq!(parse_size("2GiB"), Ok(2 << 30));
+        assert_eq!(parse_size("3 KB"), Ok(3072));
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("-1k").is_err());
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("100KB/s"), Ok(Some(102_400)));
+        assert_eq!(parse_rate("unlimited"), Ok(None));
+        assert_eq!(parse_rate("0"), Ok(None));
+        assert!(parse_rate("fast").is_err());
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+        assert_eq!(format_rate(2.0 * 1024.0 * 1024.0), "2.0 MiB/s");
+        assert_eq!(format_bytes_as(1536, Style::Si), "1.5 kB");
+        assert_eq!(format_bytes_as(999, Style::Si), "999 B");
+        assert_eq!(format_bytes_as(2_500_000_000, Style::Si), "2.5 GB");
+        assert_eq!(format_bytes_as(1_572_864, Style::Bytes), "1572864 B");
+    }
+
+    #[test]
+    fn test_parse_byte_range() {
+        assert_eq!(
+            ByteRange::parse("0-1023"),
+            Ok(ByteRange {
+                start: 0,
+                end: Some(1023)
+            })
+        );
+        assert_eq!(ByteRange::parse("1M-2M").unwrap().length(), Some(1_048_577));
+        assert_eq!(ByteRange::parse("512k-").unwrap().end, None);
+        assert!(ByteRange::parse("2M-1M").is_err());
+        assert!(ByteRange::parse("100").is_err());
+    }
+}
@@ -0,0 +1,257 @@
+//! Replacing the running binary with a newer release.
+//!
+//! `rustwget self-update` reads `latest.json` from the release endpoint
+//! (`--endpoint URL`, by default the project's latest GitHub release):
+//!
+//! ```text
+//! {"version": "0.2.0",
+//!  "targets": {"x86_64-linux-musl": {"url": "https://.../rustwget-x86_64-linux-musl",
+//!                                    "signature": "<base64 Ed25519 signature>"}}}
+//! ```
+//!
+//! When it names a newer version with a binary for this platform (see
+//! [`target`]), the binary is downloaded next to the running one, its
+//! signature checked against the release key the running binary was built
+//! with, and it is renamed over the running one in one step, so that an
+//! interrupted update leaves the old binary in place. `--check` only reports
+//! whether an update is available.
+//!
+//! The signature covers the version and platform along with the binary (see
+//! [`signed_message`]), so that an older release, signed when it was current,
+//! cannot be served again as a newer one.
+//!
+//! The release key is the base64 of a raw Ed25519 public key, taken from
+//! `RUSTWGET_RELEASE_KEY` when the binary is built; a binary built without one
+//! cannot update itself. Release builds are static, using rustls for TLS:
+//!
+//! ```text
+//! RUSTWGET_RELEASE_KEY=... cargo build --release --target x86_64-unknown-linux-musl
+//! ```
+
+use crate::logfile;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use ed25519_dalek::{Signature, VerifyingKey};
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Where releases are published by default.
+pub const DEFAULT_ENDPOINT: &str =
+    "https://github.com/lordshrey/rust_cli_tool/releases/latest/download";
+
+/// The key releases are signed with, if this binary was built with one.
+const RELEASE_KEY: Option<&str> = option_env!("RUSTWGET_RELEASE_KEY");
+
+/// Defines the `self-update` subcommand.
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("self-update")
+        .about("Replace this binary with the latest signed release")
+        .arg(
+            Arg::with_name("check")
+                .long("check")
+                .help("Only report whether a newer release is available"),
+        )
+        .arg(
+            Arg::with_name("endpoint")
+                .long("endpoint")
+                .value_name("URL")
+                .help("Read latest.json from this URL instead of the project's releases")
+                .takes_value(true),
+        )
+}
+
+/// The description of the latest release.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Release {
+    pub version: String,
+    /// The binaries of the release, by [`target`].
+    pub targets: HashMap<String, Asset>,
+}
+
+/// The binary of a release for one platform.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Asset {
+    pub url: String,
+    /// Base64 Ed25519 signature of the [`signed_message`] of the binary.
+    pub signature: String,
+}
+
+/// Returns the name of this platform in release manifests, such as
+/// `x86_64-linux-musl` or `aarch64-macos`.
+pub fn target() -> String {
+    let env = if cfg!(target_env = "musl") {
+        "-musl"
+    } else if cfg!(target_env = "gnu") {
+        "-gnu"
+    } else if cfg!(target_env = "msvc") {
+        "-msvc"
+    } else {
+        ""
+    };
+    format!("{}-{}{}", std::env::consts::ARCH, std::env::consts::OS, env)
+}
+
+/// Whether the version `candidate` comes after `current`, comparing their
+/// dot-separated numbers, with missing ones as 0; anything after a `-` or `+`
+/// is ignored.
+pub fn is_newer(candidate: &str, current: &str) -> bool {
+    let numbers = |version: &str| -> Vec<u64> {
+        version
+            .trim_start_matches('v')
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|number| number.parse().unwrap_or(0))
+            .collect()
+    };
+    let (mut candidate, mut current) = (numbers(candidate), numbers(current));
+    let len = candidate.len().max(current.len());
+    candidate.resize(len, 0);
+    current.resize(len, 0);
+    candidate > current
+}
+
+/// Returns what the release of `binary` as `version` for `target` is signed
+/// over: the line `rustwget <version> <target>`, then the binary.
+pub fn signed_message(version: &str, target: &str, binary: &[u8]) -> Vec<u8> {
+    let mut message = format!("rustwget {} {}\n", version, target).into_bytes();
+    message.extend_from_slice(binary);
+    message
+}
+
+/// Checks that `signature`, in base64, is the Ed25519 signature of `message`
+/// by the base64 public `key`.
+///
+/// # Errors
+///
+/// Returns an error if the key or signature is malformed or does not match.
+pub fn verify(message: &[u8], signature: &str, key: &str) -> Result<(), String> {
+    let key: [u8; 32] = STANDARD
+        .decode(key.trim())
+        .map_err(|err| format!("invalid release key: {}", err))?
+        .try_into()
+        .map_err(|_| "invalid release key: not 32 bytes".to_string())?;
+    let key =
+        VerifyingKey::from_bytes(&key).map_err(|err| format!("invalid release key: {}", err))?;
+    let signature = STANDARD
+        .decode(signature.trim())
+        .map_err(|err| format!("invalid signature: {}", err))?;
+    let signature =
+        Signature::from_slice(&signature).map_err(|err| format!("invalid signature: {}", err))?;
+    key.verify_strict(message, &signature)
+        .map_err(|_| "the signature does not match the release key".to_string())
+}
+
+/// Replaces the executable `exe` with `binary`, keeping its permissions.
+///
+/// The binary is written next to `exe` and renamed over it, so that `exe` is
+/// either the old binary or the new one.
+///
+/// # Errors
+///
+/// Returns an error if the binary cannot be written or renamed.
+pub fn replace(exe: &Path, binary: &[u8]) -> io::Result<()> {
+    let mut staged = exe.as_os_str().to_owned();
+    staged.push(".new");
+    fs::write(&staged, binary)?;
+    fs::set_permissions(&staged, fs::metadata(exe)?.permissions())?;
+    // Windows does not replace a running executable, but lets it be moved.
+    if cfg!(windows) {
+        let mut old = exe.as_os_str().to_owned();
+        old.push(".old");
+        let _ = fs::remove_file(&old);
+        fs::rename(exe, &old)?;
+    }
+    fs::rename(&staged, exe)
+}
+
+/// Runs the `self-update` subcommand.
+///
+/// # Errors
+///
+/// Returns an error if the release cannot be read, has no binary for this
+/// platform, or its binary cannot be verified or installed.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let current = env!("CARGO_PKG_VERSION");
+    let endpoint = matches.value_of("endpoint").unwrap_or(DEFAULT_ENDPOINT);
+    let client = Client::builder()
+        .user_agent(concat!("rustwget/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let url = format!("{}/latest.json", endpoint.trim_end_matches('/'));
+    let response = client.get(&url).send()?.error_for_status()?;
+    let release: Release = serde_json::from_reader(response)
+        .map_err(|err| format!("invalid release manifest {}: {}", url, err))?;
+    if !is_newer(&release.version, current) {
+        logfile::info(&format!("rustwget {} is the latest release", current));
+        return Ok(());
+    }
+    let asset = release
+        .targets
+        .get(&target())
+        .ok_or_else(|| format!("release {} has no binary for {}", release.version, target()))?;
+    if matches.is_present("check") {
+        logfile::info(&format!(
+            "rustwget {} is available (this is {})",
+            release.version, current
+        ));
+        return Ok(());
+    }
+    let key = RELEASE_KEY
+        .ok_or("this binary was built without a release key and cannot update itself")?;
+    logfile::info(&format!("Downloading rustwget {}", release.version));
+    let binary = client.get(&asset.url).send()?.error_for_status()?.bytes()?;
+    let message = signed_message(&release.version, &target(), &binary);
+    verify(&message, &asset.signature, key)?;
+    let exe = std::env::current_exe()?;
+    replace(&exe, &binary).map_err(|err| format!("cannot replace {}: {}", exe.display(), err))?;
+    logfile::info(&format!(
+        "Updated {} from {} to {}",
+        exe.display(),
+        current,
+        release.version
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn test_verify_and_replace() {
+        let pair = SigningKey::from_bytes(&[7; 32]);
+        let key = STANDARD.encode(pair.verifying_key().as_bytes());
+        let binary = b"\x7fELF new release";
+        let message = signed_message("0.2.0", "x86_64-linux-musl", binary);
+        let signature = STANDARD.encode(pair.sign(&message).to_bytes());
+        assert_eq!(verify(&message, &signature, &key), Ok(()));
+        let tampered = signed_message("0.2.0", "x86_64-linux-musl", b"\x7fELF tampered");
+        assert!(verify(&tampered, &signature, &key).is_err());
+        // An old release cannot pass for a newer one.
+        let replayed = signed_message("0.3.0", "x86_64-linux-musl", binary);
+        assert!(verify(&replayed, &signature, &key).is_err());
+        assert!(verify(&message, "not base64!", &key).is_err());
+
+        assert!(is_newer("0.2.0", "0.1.9"));
+        assert!(is_newer("v0.10.0", "0.9.1"));
+        assert!(is_newer("1.0.1", "1.0"));
+        assert!(!is_newer("0.1.0", "0.1.0"));
+        assert!(!is_newer("1.0.0", "1.0"));
+        assert!(!is_newer("1.0", "1.0.0"));
+        assert!(!is_newer("0.1.0-rc1", "0.1.0"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("rustwget");
+        fs::write(&exe, "old").unwrap();
+        replace(&exe, binary).unwrap();
+        assert_eq!(fs::read(&exe).unwrap(), binary);
+        assert!(!dir.path().join("rustwget.new").exists());
+    }
+}
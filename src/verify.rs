@@ -0,0 +1,244 @@
+//! Auditing downloaded files against the record of their download.
+//!
+//! `rustwget verify` reads a manifest in the format of the ledger kept by
+//! `--skip-existing-ledger` (see [`ledger`](crate::ledger)), by default the
+//! ledger itself, and checks that every file it lists still exists with the
+//! recorded size and SHA-256 digest. With `--revalidate`, the server is also
+//! asked about each URL with a `HEAD` request (see [`info`]), and a changed
+//! ETag or size is reported as drift on the server's side. The files that
+//! drifted are listed at the end, and the command fails if there are any.
+//! `--json` prints the same as JSON.
+
+use crate::auth::Keyring;
+use crate::config;
+use crate::download::Options;
+use crate::hosts::Hosts;
+use crate::info;
+use crate::ledger::{self, Ledger, Record};
+use crate::logfile;
+use crate::redirect;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use reqwest::blocking::{Client, ClientBuilder};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Defines the `verify` subcommand.
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("verify")
+        .about("Check downloaded files against the sizes and digests recorded in a manifest")
+        .arg(
+            Arg::with_name("manifest")
+                .long("manifest")
+                .value_name("FILE")
+                .help("Manifest in the format of the ledger [default: the ledger of --skip-existing-ledger]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("revalidate")
+                .long("revalidate")
+                .help("Also ask the server whether the ETag and size of each URL are still the recorded ones"),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Print the report as JSON"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Read host settings from this TOML file")
+                .takes_value(true),
+        )
+}
+
+/// A file that no longer matches its record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Drift {
+    pub path: PathBuf,
+    pub url: String,
+    /// What differs.
+    pub problem: String,
+}
+
+/// What an audit found.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Audit {
+    /// Number of files checked.
+    pub verified: usize,
+    pub drifted: Vec<Drift>,
+}
+
+impl Audit {
+    /// Formats the audit for the terminal.
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Verified {} files: {} drifted\n",
+            self.verified,
+            self.drifted.len()
+        );
+        for drift in &self.drifted {
+            summary += &format!("  {} ({})\n", drift.path.display(), drift.problem);
+        }
+        summary
+    }
+}
+
+/// Compares the file of `record` with its recorded size and digest.
+///
+/// Returns what differs, if anything.
+pub fn check_file(record: &Record) -> Option<String> {
+    let size = match fs::metadata(&record.path) {
+        Ok(meta) => meta.len(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Some("missing".to_string()),
+        Err(err) => return Some(format!("cannot read: {}", err)),
+    };
+    if size != record.size {
+        return Some(format!("{} bytes, recorded {}", size, record.size));
+    }
+    match ledger::sha256_file(&record.path) {
+        Ok(digest) if digest == record.sha256 => None,
+        Ok(digest) => Some(format!(
+            "SHA-256 {}, recorded {}",
+            &digest[..12],
+            &record.sha256[..record.sha256.len().min(12)]
+        )),
+        Err(err) => Some(format!("cannot read: {}", err)),
+    }
+}
+
+/// Asks the server whether the URL of `record` still serves what was recorded.
+///
+/// Returns what differs, if anything.
+pub fn check_server(client: &Client, record: &Record, options: &Options) -> Option<String> {
+    let info = match info::fetch(client, &record.url, options) {
+        Ok(info) => info,
+        Err(err) => return Some(format!("server: {}", err)),
+    };
+    if !(200..300).contains(&info.status) {
+        return Some(format!("server answers HTTP {}", info.status));
+    }
+    if let (Some(etag), Some(recorded)) = (&info.etag, &record.etag) {
+        if etag != recorded {
+            return Some(format!("server has ETag {}, recorded {}", etag, recorded));
+        }
+    }
+    match info.size {
+        Some(size) if size != record.size => Some(format!(
+            "server has {} bytes, recorded {}",
+            size, record.size
+        )),
+        _ => None,
+    }
+}
+
+/// Checks every file of `records`, and with `revalidate` every URL as well.
+pub fn audit(records: &[Record], revalidate: Option<(&Client, &Options)>) -> Audit {
+    let mut audit = Audit::default();
+    for record in records {
+        logfile::detail(&format!("Verifying: {}", record.path.display()));
+        audit.verified += 1;
+        let problem = check_file(record).or_else(|| {
+            revalidate.and_then(|(client, options)| check_server(client, record, options))
+        });
+        if let Some(problem) = problem {
+            audit.drifted.push(Drift {
+                path: record.path.clone(),
+                url: record.url.clone(),
+                problem,
+            });
+        }
+    }
+    audit
+}
+
+/// Runs the `verify` subcommand.
+///
+/// # Errors
+///
+/// Returns an error if the manifest or configuration cannot be read, or if
+/// any file drifted.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let path = matches
+        .value_of("manifest")
+        .map_or_else(ledger::default_path, PathBuf::from);
+    if !path.exists() {
+        return Err(format!("no manifest at {}", path.display()).into());
+    }
+    let records = Ledger::open(path)?.records();
+
+    let audit = if matches.is_present("revalidate") {
+        let config = config::load(matches.value_of("config").map(Path::new))?;
+        let builder = || -> Result<ClientBuilder, Box<dyn std::error::Error>> {
+            Ok(Client::builder().redirect(redirect::policy(redirect::DEFAULT_MAX, false)))
+        };
+        let options = Options {
+            keyring: Some(Arc::new(Keyring::new(config.oauth.clone()))),
+            hosts: Arc::new(Hosts::new(&config.host, builder)?),
+            ..Options::default()
+        };
+        audit(&records, Some((&builder()?.build()?, &options)))
+    } else {
+        audit(&records, None)
+    };
+    if matches.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&audit)?);
+    } else {
+        logfile::info(audit.summary().trim_end());
+    }
+    match audit.drifted.len() {
+        0 => Ok(()),
+        drifted => Err(format!("{} files drifted", drifted).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url};
+
+    #[test]
+    fn test_audit() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = Ledger::open(dir.path().join("ledger.json")).unwrap();
+        let url = |name| format!("{}/verify/{}", server_url(), name);
+        for name in ["same.bin", "changed.bin", "gone.bin", "stale.bin"] {
+            let path = dir.path().join(name);
+            fs::write(&path, "payload").unwrap();
+            ledger
+                .record(&url(name), &path, Some("\"v1\"".to_string()), None)
+                .unwrap();
+        }
+        fs::write(dir.path().join("changed.bin"), "PAYLOAD").unwrap();
+        fs::remove_file(dir.path().join("gone.bin")).unwrap();
+        let records = ledger.records();
+
+        let local = audit(&records, None);
+        assert_eq!(local.verified, 4);
+        let problems: Vec<_> = local.drifted.iter().map(|d| d.problem.as_str()).collect();
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("SHA-256 "));
+        assert_eq!(problems[1], "missing");
+
+        let same = mock("HEAD", "/verify/same.bin")
+            .with_header("etag", "\"v1\"")
+            .create();
+        let stale = mock("HEAD", "/verify/stale.bin")
+            .with_header("etag", "\"v2\"")
+            .create();
+        let remote = audit(&records, Some((&Client::new(), &Options::default())));
+        assert_eq!(remote.drifted.len(), 3);
+        assert_eq!(
+            remote.drifted[2].problem,
+            "server has ETag \"v2\", recorded \"v1\""
+        );
+        assert!(remote
+            .summary()
+            .starts_with("Verified 4 files: 3 drifted\n"));
+        same.assert();
+        stale.assert();
+    }
+}
@@ -0,0 +1,232 @@
+//! Snapshots of the Internet Archive's Wayback Machine.
+//!
+//! `--wayback=TIMESTAMP` downloads each URL as the Wayback Machine archived it
+//! closest to `TIMESTAMP`, given as `YYYY[MM[DD[hh[mm[ss]]]]]`, instead of
+//! the live URL. A bare `--wayback` downloads the live URL, and falls back to
+//! its latest snapshot only once it and any mirrors have failed.
+//!
+//! Snapshots are found with the availability API and fetched in their raw
+//! `id_` form, which serves the archived bytes without the links rewritten or
+//! the Wayback Machine's banner inserted.
+
+use crate::download::Options;
+use crate::logfile;
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use url::Url;
+
+/// The availability API of the Wayback Machine.
+pub const API: &str = "https://archive.org/wayback/available";
+
+/// Where and when snapshots are looked up.
+#[derive(Debug, Clone)]
+pub struct Wayback {
+    /// The time to download snapshots of, or `None` to fall back to the
+    /// latest one when the live URL fails.
+    pub timestamp: Option<String>,
+    /// The availability API.
+    pub api: String,
+}
+
+/// An archived copy of a URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The URL of its raw form.
+    pub url: String,
+    /// When it was archived, as `YYYYMMDDhhmmss`.
+    pub timestamp: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Availability {
+    archived_snapshots: Archived,
+}
+
+#[derive(Debug, Deserialize)]
+struct Archived {
+    closest: Option<Closest>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Closest {
+    available: bool,
+    url: String,
+    timestamp: String,
+}
+
+impl Wayback {
+    /// Creates a lookup of the snapshots closest to `timestamp`, or of the
+    /// latest ones for falling back to.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `timestamp` is not a prefix of `YYYYMMDDhhmmss`.
+    pub fn new(timestamp: Option<&str>) -> Result<Wayback, String> {
+        if let Some(timestamp) = timestamp {
+            if timestamp.is_empty()
+                || timestamp.len() > 14
+                || !timestamp.bytes().all(|b| b.is_ascii_digit())
+            {
+                return Err(format!(
+                    "invalid --wayback timestamp '{}': expected YYYY[MM[DD[hh[mm[ss]]]]]",
+                    timestamp
+                ));
+            }
+        }
+        Ok(Wayback {
+            timestamp: timestamp.map(str::to_string),
+            api: API.to_string(),
+        })
+    }
+
+    /// Looks up the snapshot of `url` closest to [`Wayback::timestamp`], or
+    /// `None` if it was never archived.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the availability API cannot be reached or answers
+    /// with something else.
+    pub fn snapshot(
+        &self,
+        client: &Client,
+        url: &str,
+        options: &Options,
+    ) -> Result<Option<Snapshot>, Box<dyn std::error::Error>> {
+        let mut api = Url::parse(&self.api)?;
+        api.query_pairs_mut().append_pair("url", url);
+        if let Some(timestamp) = &self.timestamp {
+            api.query_pairs_mut().append_pair("timestamp", timestamp);
+        }
+        let api = String::from(api);
+        let response = options
+            .send(client, &api, |client| client.get(&api))?
+            .error_for_status()
+            .map_err(|err| format!("cannot look up {} in the Wayback Machine: {}", url, err))?;
+        let availability: Availability = serde_json::from_reader(response)
+            .map_err(|err| format!("invalid answer of the Wayback Machine: {}", err))?;
+        Ok(availability
+            .archived_snapshots
+            .closest
+            .filter(|closest| closest.available)
+            .map(|closest| Snapshot {
+                url: raw(&closest.url, &closest.timestamp),
+                timestamp: closest.timestamp,
+            }))
+    }
+
+    /// Returns the URL of the snapshot of `url` to download instead of it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `url` was never archived or the lookup fails.
+    pub fn locate(
+        &self,
+        client: &Client,
+        url: &str,
+        options: &Options,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let snapshot = self
+            .snapshot(client, url, options)?
+            .ok_or_else(|| format!("{} has no snapshot in the Wayback Machine", url))?;
+        logfile::info(&format!(
+            "Snapshot of {} archived at {}: {}",
+            url, snapshot.timestamp, snapshot.url
+        ));
+        Ok(snapshot.url)
+    }
+
+    /// Returns the URL of the latest snapshot of `url`, which failed live,
+    /// or `None` with a warning if there is none.
+    pub fn fall_back(&self, client: &Client, url: &str, options: &Options) -> Option<String> {
+        match self.snapshot(client, url, options) {
+            Ok(Some(snapshot)) => {
+                logfile::warning(&format!(
+                    "Falling back to the snapshot archived at {}: {}",
+                    snapshot.timestamp, snapshot.url
+                ));
+                Some(snapshot.url)
+            }
+            Ok(None) => {
+                logfile::warning(&format!("{} has no snapshot in the Wayback Machine", url));
+                None
+            }
+            Err(err) => {
+                logfile::warning(&err.to_string());
+                None
+            }
+        }
+    }
+}
+
+/// Turns the URL of a snapshot page into that of its raw form, marking its
+/// timestamp with `id_`.
+fn raw(url: &str, timestamp: &str) -> String {
+    let page = format!("/{}/", timestamp);
+    match url.find(&page) {
+        Some(index) => format!(
+            "{}/{}id_/{}",
+            &url[..index],
+            timestamp,
+            &url[index + page.len()..]
+        ),
+        None => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+
+    #[test]
+    fn test_new_wayback() {
+        assert_eq!(
+            Wayback::new(Some("20200101")).unwrap().timestamp.as_deref(),
+            Some("20200101")
+        );
+        assert!(Wayback::new(None).unwrap().timestamp.is_none());
+        assert!(Wayback::new(Some("2020-01-01")).is_err());
+        assert!(Wayback::new(Some("202001010000000")).is_err());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let api = mock("GET", "/wayback/available")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("url".to_string(), "example.com/a.txt".to_string()),
+                Matcher::UrlEncoded("timestamp".to_string(), "2019".to_string()),
+            ]))
+            .with_body(
+                r#"{"url": "example.com/a.txt", "archived_snapshots": {"closest": {
+                    "status": "200", "available": true, "timestamp": "20190102030405",
+                    "url": "http://web.archive.org/web/20190102030405/http://example.com/a.txt"}}}"#,
+            )
+            .create();
+        let wayback = Wayback {
+            api: format!("{}/wayback/available", server_url()),
+            ..Wayback::new(Some("2019")).unwrap()
+        };
+        assert_eq!(
+            wayback
+                .snapshot(&Client::new(), "example.com/a.txt", &Options::default())
+                .unwrap(),
+            Some(Snapshot {
+                url: "http://web.archive.org/web/20190102030405id_/http://example.com/a.txt"
+                    .to_string(),
+                timestamp: "20190102030405".to_string(),
+            })
+        );
+        api.assert();
+
+        let _none = mock("GET", "/wayback/available")
+            .match_query(Matcher::UrlEncoded(
+                "url".to_string(),
+                "example.com/new".to_string(),
+            ))
+            .with_body(r#"{"url": "example.com/new", "archived_snapshots": {}}"#)
+            .create();
+        assert!(wayback
+            .locate(&Client::new(), "example.com/new", &Options::default())
+            .is_err());
+    }
+}
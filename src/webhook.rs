@@ -0,0 +1,234 @@
+//! Posting the outcome of downloads to a URL (`--notify-url`).
+//!
+//! With `--notify-url URL`, every download that ends, and then the run as a
+//! whole when it downloads several URLs, is described to `URL` in a JSON
+//! `POST`, so that chat bots and pipelines can act on it:
+//!
+//! ```text
+//! {"event": "download", "url": "https://example.com/a.iso", "path": "a.iso",
+//!  "status": "completed", "size": 1048576, "sha256": "5f70bf...", "duration_seconds": 2.5}
+//! {"event": "batch", "status": "failed", "succeeded": 3, "failed": 1, "skipped": 0,
+//!  "cancelled": 0, "bytes": 4194304, "duration_seconds": 9.1, "failures": [...]}
+//! ```
+//!
+//! A download's `status` is `completed`, `failed` (with an `error`),
+//! `cancelled`, or `skipped` (for its size or content type, or as a
+//! duplicate); `size` and `sha256` describe the file saved. Credentials are
+//! [`redact`](crate::redact)ed from the URLs. A post that fails is warned
+//! about and does not fail the run.
+
+use crate::digest::{self, Algorithm};
+use crate::download::{self, Outcome};
+use crate::logfile;
+use crate::redact;
+use crate::report::{Failure, Report};
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// How long a post may take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The end of one download.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DownloadEvent {
+    pub event: &'static str,
+    pub url: String,
+    pub path: String,
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    pub duration_seconds: f64,
+}
+
+impl DownloadEvent {
+    /// Describes the download of `url` to `path`, which ended with `result`
+    /// after `elapsed`, or `None` if it was only paused.
+    pub fn new(
+        url: &str,
+        path: &Path,
+        result: &Result<Outcome, String>,
+        elapsed: Duration,
+    ) -> Option<DownloadEvent> {
+        let status = match result {
+            Ok(Outcome::Completed) => "completed",
+            Ok(Outcome::Paused) => return None,
+            Ok(Outcome::Cancelled) => "cancelled",
+            Ok(Outcome::Skipped) => "skipped",
+            Err(_) => "failed",
+        };
+        let saved = *result == Ok(Outcome::Completed) && !download::is_stdout(path);
+        let sha256 = saved
+            .then(|| digest::file(path, &[Algorithm::Sha256]).ok())
+            .flatten()
+            .and_then(|digests| digests.into_iter().next())
+            .map(|(_, digest)| digest);
+        Some(DownloadEvent {
+            event: "download",
+            url: redact::url(url).into_owned(),
+            path: path.display().to_string(),
+            status,
+            error: result.as_ref().err().cloned(),
+            size: saved
+                .then(|| fs::metadata(path).ok().map(|meta| meta.len()))
+                .flatten(),
+            sha256,
+            duration_seconds: elapsed.as_secs_f64(),
+        })
+    }
+}
+
+/// The end of a run that downloaded several URLs.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BatchEvent {
+    pub event: &'static str,
+    pub status: &'static str,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub cancelled: usize,
+    pub bytes: u64,
+    pub duration_seconds: f64,
+    pub failures: Vec<Failure>,
+}
+
+impl BatchEvent {
+    /// Describes the run summed up by `report`.
+    pub fn new(report: &Report) -> BatchEvent {
+        BatchEvent {
+            event: "batch",
+            status: match report.failed {
+                0 => "completed",
+                _ => "failed",
+            },
+            succeeded: report.succeeded,
+            failed: report.failed,
+            skipped: report.skipped,
+            cancelled: report.cancelled,
+            bytes: report.bytes,
+            duration_seconds: report.elapsed_seconds,
+            failures: report
+                .failures
+                .iter()
+                .map(|failure| Failure {
+                    url: redact::url(&failure.url).into_owned(),
+                    ..failure.clone()
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Where events are posted.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: String,
+    client: Client,
+}
+
+impl Webhook {
+    /// Posts events to `url`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `url` is not an HTTP URL.
+    pub fn new(url: &str) -> Result<Webhook, Box<dyn std::error::Error>> {
+        let parsed =
+            url::Url::parse(url).map_err(|err| format!("invalid --notify-url: {}", err))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(format!("invalid --notify-url '{}': not an HTTP URL", url).into());
+        }
+        let client = Client::builder()
+            .user_agent(concat!("rustwget/", env!("CARGO_PKG_VERSION")))
+            .timeout(TIMEOUT)
+            .build()?;
+        Ok(Webhook {
+            url: url.to_string(),
+            client,
+        })
+    }
+
+    /// Posts the end of the download of `url` to `path`, unless it was only
+    /// paused (see [`DownloadEvent::new`]).
+    pub fn download(
+        &self,
+        url: &str,
+        path: &Path,
+        result: &Result<Outcome, String>,
+        elapsed: Duration,
+    ) {
+        if let Some(event) = DownloadEvent::new(url, path, result, elapsed) {
+            self.post(&event);
+        }
+    }
+
+    /// Posts `event` as JSON, warning if the endpoint cannot take it.
+    pub fn post(&self, event: &impl Serialize) {
+        let result = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(event).unwrap_or_default())
+            .send()
+            .and_then(|response| response.error_for_status());
+        if let Err(err) = result {
+            logfile::warning(&format!(
+                "Cannot post to {}: {}",
+                redact::url(&self.url),
+                err
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, server_url, Matcher};
+    use serde_json::json;
+
+    #[test]
+    fn test_post_download_event() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, "hello").unwrap();
+        let hook = mock("POST", "/webhook/done")
+            .match_header("content-type", "application/json")
+            .match_body(Matcher::PartialJson(json!({
+                "event": "download",
+                "url": "https://example.com/a.txt",
+                "status": "completed",
+                "size": 5,
+                "sha256": "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+            })))
+            .expect(1)
+            .create();
+        let webhook = Webhook::new(&format!("{}/webhook/done", server_url())).unwrap();
+        webhook.download(
+            "https://example.com/a.txt",
+            &path,
+            &Ok(Outcome::Completed),
+            Duration::from_secs(1),
+        );
+        hook.assert();
+
+        let failed = DownloadEvent::new(
+            "https://example.com/b.txt",
+            &dir.path().join("b.txt"),
+            &Err("HTTP 404".to_string()),
+            Duration::ZERO,
+        )
+        .unwrap();
+        assert_eq!((failed.status, failed.size), ("failed", None));
+        assert_eq!(failed.error.as_deref(), Some("HTTP 404"));
+        assert!(DownloadEvent::new("u", &path, &Ok(Outcome::Paused), Duration::ZERO).is_none());
+        assert!(Webhook::new("ftp://example.com/hook").is_err());
+    }
+}